@@ -31,6 +31,46 @@ fn benchmark_put(c: &mut Criterion) {
     });
 }
 
+fn benchmark_put_preallocate(c: &mut Criterion) {
+    // 打开存储引擎, 预分配活跃文件的空间
+    let mut options = lucasdb::options::EngineOptions::default();
+    options.dir_path = PathBuf::from("./tmp/benches-preallocate");
+    options.preallocate_data_file = true;
+    let engine = Engine::open(options).expect("failed to open engine");
+
+    let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
+
+    c.bench_function("lucasdb-put-preallocate-bench", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..std::usize::MAX);
+
+            let (k, v) = get_test_kv(i);
+            let res = engine.put(k, v);
+            assert!(res.is_ok());
+        });
+    });
+}
+
+fn benchmark_put_mmap(c: &mut Criterion) {
+    // 打开存储引擎, 使用mmap写入活跃文件
+    let mut options = lucasdb::options::EngineOptions::default();
+    options.dir_path = PathBuf::from("./tmp/benches-mmap");
+    options.write_io_type = lucasdb::options::IOType::MemoryMap;
+    let engine = Engine::open(options).expect("failed to open engine");
+
+    let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
+
+    c.bench_function("lucasdb-put-mmap-bench", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..std::usize::MAX);
+
+            let (k, v) = get_test_kv(i);
+            let res = engine.put(k, v);
+            assert!(res.is_ok());
+        });
+    });
+}
+
 fn benchmark_get(c: &mut Criterion) {
     // 打开存储引擎
     let mut options = lucasdb::options::EngineOptions::default();
@@ -58,6 +98,55 @@ fn benchmark_get(c: &mut Criterion) {
     });
 }
 
+fn benchmark_get_mmap(c: &mut Criterion) {
+    // 打开存储引擎, 活跃文件使用mmap, 读取时直接引用映射的内存
+    let mut options = lucasdb::options::EngineOptions::default();
+    options.dir_path = PathBuf::from("./tmp/benches-get-mmap");
+    options.write_io_type = lucasdb::options::IOType::MemoryMap;
+    let engine = Engine::open(options).expect("failed to open engine");
+
+    let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
+
+    // 写入数据, 都在活跃文件中
+    for i in 0..10000 {
+        let (k, v) = get_test_kv(i);
+        let res = engine.put(k, v);
+        assert!(res.is_ok());
+    }
+
+    c.bench_function("lucasdb-get-mmap-bench", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..10000);
+
+            let (k, _) = get_test_kv(i);
+            let res = engine.get(k);
+            assert!(res.is_ok());
+            black_box(res.unwrap());
+        });
+    });
+}
+
+fn benchmark_open(c: &mut Criterion) {
+    // 不使用mmap, 启动时按顺序读取数据文件加载索引, 每条数据只需要一次读取
+    let mut options = lucasdb::options::EngineOptions::default();
+    options.dir_path = PathBuf::from("./tmp/benches-open");
+    options.use_mmap_when_startup = false;
+    let engine = Engine::open(options.clone()).expect("failed to open engine");
+    for i in 0..10000 {
+        let (k, v) = get_test_kv(i);
+        let res = engine.put(k, v);
+        assert!(res.is_ok());
+    }
+    drop(engine);
+
+    c.bench_function("lucasdb-open-bench", |b| {
+        b.iter(|| {
+            let engine = Engine::open(options.clone()).expect("failed to open engine");
+            black_box(engine);
+        });
+    });
+}
+
 fn benchmark_delete(c: &mut Criterion) {
     // 打开存储引擎
     let mut options = lucasdb::options::EngineOptions::default();
@@ -81,5 +170,14 @@ fn benchmark_delete(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, benchmark_put, benchmark_get, benchmark_delete);
+criterion_group!(
+    benches,
+    benchmark_put,
+    benchmark_put_preallocate,
+    benchmark_put_mmap,
+    benchmark_get,
+    benchmark_get_mmap,
+    benchmark_open,
+    benchmark_delete
+);
 criterion_main!(benches);
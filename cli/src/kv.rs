@@ -0,0 +1,131 @@
+use std::io::{self, Write};
+
+use bytes::Bytes;
+use lucasdb::options::{EngineOptions, IteratorOptions};
+
+use crate::{open_existing, open_read_only, Args, CliResult};
+
+/// 独占方式打开数据库, 写入之后持久化
+fn open_exclusive(args: &Args) -> CliResult<lucasdb::Engine> {
+    let mut opts = EngineOptions::default();
+    opts.dir_path = args.dir.clone();
+    open_existing(opts)
+}
+
+/// 输出原始的`value`, 不做任何转换
+pub fn get(args: Args) -> CliResult<()> {
+    let key = &args.positional(&["key"])?[0];
+    let db = open_read_only(args.dir.clone())?;
+    let value = db.get(Bytes::from(key.clone()))?;
+
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&value)?;
+    stdout.write_all(b"\n")?;
+    Ok(())
+}
+
+pub fn put(args: Args) -> CliResult<()> {
+    let kv = args.positional(&["key", "value"])?;
+    let (key, value) = (&kv[0], &kv[1]);
+    let db = open_exclusive(&args)?;
+    db.put_sync(Bytes::from(key.clone()), Bytes::from(value.clone()))?;
+    Ok(())
+}
+
+pub fn delete(args: Args) -> CliResult<()> {
+    let key = &args.positional(&["key"])?[0];
+    let db = open_exclusive(&args)?;
+    db.delete(Bytes::from(key.clone()))?;
+    db.sync()?;
+    Ok(())
+}
+
+/// 按顺序输出`key`, 每行一个, 只遍历索引
+pub fn list_keys(args: Args) -> CliResult<()> {
+    args.positional(&[])?;
+    let limit = match args.get("limit") {
+        Some(limit) => Some(limit.parse()?),
+        None => None,
+    };
+    let db = open_read_only(args.dir.clone())?;
+    let iter = db.iter(IteratorOptions {
+        prefix: args.get("prefix").unwrap_or_default().as_bytes().to_vec(),
+        limit,
+        keys_only: true,
+        ..Default::default()
+    });
+
+    let mut stdout = io::stdout().lock();
+    while let Some((key, _)) = iter.next()? {
+        stdout.write_all(&key)?;
+        stdout.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+pub fn stat(args: Args) -> CliResult<()> {
+    args.positional(&[])?;
+    let db = open_read_only(args.dir.clone())?;
+    let stat = db.stat()?;
+    println!("key_num: {}", stat.key_num);
+    println!("index_type: {:?}", stat.index.index_type);
+    println!("index_in_memory_keys: {}", stat.index.in_memory_keys);
+    println!("index_spilled_keys: {}", stat.index.spilled_keys);
+    println!("data_file_num: {}", stat.data_file_num);
+    println!("reclaim_size: {}", stat.reclaim_size);
+    println!("disk_size: {}", stat.disk_size);
+    println!("discarded_bytes: {}", stat.discarded_bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::tests::strings;
+
+    fn basepath() -> PathBuf {
+        "../tmp/cli/kv".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn args(name: &str, rest: &[&str]) -> Args {
+        let mut args = vec![basepath().join(name).to_string_lossy().into_owned()];
+        args.extend(strings(rest));
+        Args::parse(&args, &[]).unwrap()
+    }
+
+    #[test]
+    fn test_kv_put_get_delete() {
+        let name = "put_get_delete";
+        clean(name);
+        // 目录不存在时不会创建数据库
+        assert!(put(args(name, &["key", "value"])).is_err());
+
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        std::mem::drop(lucasdb::Engine::open(opts.clone()).unwrap());
+
+        put(args(name, &["key-1", "value-1"])).unwrap();
+        put(args(name, &["key-2", "value-2"])).unwrap();
+        assert!(put(args(name, &["key-3"])).is_err());
+        get(args(name, &["key-1"])).unwrap();
+        list_keys(args(name, &["--prefix", "key", "--limit", "1"])).unwrap();
+        assert!(list_keys(args(name, &["--limit", "x"])).is_err());
+        stat(args(name, &[])).unwrap();
+
+        delete(args(name, &["key-1"])).unwrap();
+        assert!(get(args(name, &["key-1"])).is_err());
+
+        let db = lucasdb::Engine::open(opts).unwrap();
+        assert!(db.get(Bytes::from("key-1")).is_err());
+        assert_eq!(db.get(Bytes::from("key-2")).unwrap(), "value-2");
+        std::mem::drop(db);
+
+        clean(name);
+    }
+}
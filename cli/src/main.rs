@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap, error::Error, fs::File, io::BufWriter, path::PathBuf, process::ExitCode,
+};
+
+use lucasdb::{db::Engine, options::EngineOptions};
+
+mod kv;
+mod transfer;
+
+const USAGE: &str = "usage: lucasdb-cli <command> <dir> [options]
+
+只读的命令以只读方式打开数据库, 可以在数据库运行时执行, 其他命令需要独占数据库
+
+commands:
+    get <dir> <key>                       读取一个key, 输出原始的value (只读)
+    put <dir> <key> <value>               写入一个key
+    delete <dir> <key>                    删除一个key
+    list-keys <dir>                       按顺序列出key (只读)
+        --prefix <prefix>                 只列出带有这个前缀的key
+        --limit <n>                       最多列出多少个
+    stat <dir>                            输出数据库的统计信息 (只读)
+    merge <dir> [--ratio <ratio>]         无效数据占比达到 ratio (默认 0.5) 时做一次完整的merge
+    compact <dir>                         不管有多少无效数据都做一次完整的merge
+    verify <dir>                          校验所有数据文件和内存索引 (只读)
+    backup <dir> --out <path> [--tar]     备份到目录, --tar 时打包成一个 tar 文件 (只读)
+    export|dump <dir> --out <file>        导出所有数据 (只读)
+        --format <ndjson|csv>             导出的格式, 默认 ndjson
+        --prefix <prefix>                 只导出带有这个前缀的key
+        --after-ts <unix seconds>         只导出这个时间之后修改过的数据文件中的key
+    import|load <dir> --in <file>         导入 export 生成的文件
+        --format <ndjson|csv>             文件的格式, 默认 ndjson
+        --dry-run                         只统计不写入";
+
+pub type CliResult<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let res = match args.first().map(|cmd| cmd.as_str()) {
+        Some("get") => Args::parse(&args[1..], &[]).and_then(kv::get),
+        Some("put") => Args::parse(&args[1..], &[]).and_then(kv::put),
+        Some("delete") => Args::parse(&args[1..], &[]).and_then(kv::delete),
+        Some("list-keys") => Args::parse(&args[1..], &[]).and_then(kv::list_keys),
+        Some("stat") => Args::parse(&args[1..], &[]).and_then(kv::stat),
+        Some("merge") => Args::parse(&args[1..], &[]).and_then(|args| {
+            args.positional(&[])?;
+            let ratio = match args.get("ratio") {
+                Some(ratio) => ratio.parse()?,
+                None => EngineOptions::default().data_file_merge_ratio,
+            };
+            merge(args.dir, ratio)
+        }),
+        Some("compact") => Args::parse(&args[1..], &[]).and_then(|args| {
+            args.positional(&[])?;
+            merge(args.dir, 0f32)
+        }),
+        Some("verify") => Args::parse(&args[1..], &[]).and_then(|args| {
+            args.positional(&[])?;
+            verify(args.dir)
+        }),
+        Some("backup") => Args::parse(&args[1..], &["tar"]).and_then(backup),
+        Some("export" | "dump") => Args::parse(&args[1..], &[]).and_then(transfer::export),
+        Some("import" | "load") => Args::parse(&args[1..], &["dry-run"]).and_then(transfer::import),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match res {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// 命令行参数: `<dir> [args...] --flag value --switch`
+pub struct Args {
+    pub dir: PathBuf,
+    /// `<dir>`之后的位置参数
+    positional: Vec<String>,
+    flags: HashMap<String, String>,
+}
+
+impl Args {
+    /// `switches`是不带值的参数
+    fn parse(args: &[String], switches: &[&str]) -> CliResult<Self> {
+        let mut dir = None;
+        let mut positional = vec![];
+        let mut flags = HashMap::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if switches.contains(&name) => {
+                    flags.insert(name.to_string(), String::new());
+                }
+                Some(name) => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("missing value for --{}", name))?;
+                    flags.insert(name.to_string(), value.clone());
+                }
+                None if dir.is_none() => dir = Some(PathBuf::from(arg)),
+                None => positional.push(arg.clone()),
+            }
+        }
+
+        let dir = dir.ok_or("missing database directory")?;
+        Ok(Self {
+            dir,
+            positional,
+            flags,
+        })
+    }
+
+    /// 位置参数, 数量和`names`不一样时返回错误, `names`用于错误信息
+    pub fn positional(&self, names: &[&str]) -> CliResult<&[String]> {
+        if self.positional.len() != names.len() {
+            let expected: Vec<String> = names.iter().map(|name| format!("<{}>", name)).collect();
+            return Err(format!("expected arguments: {}", expected.join(" ")).into());
+        }
+        Ok(&self.positional)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).map(|v| v.as_str())
+    }
+
+    pub fn required(&self, name: &str) -> CliResult<&str> {
+        self.get(name)
+            .ok_or_else(|| format!("missing --{}", name).into())
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.flags.contains_key(name)
+    }
+}
+
+/// 打开一个已经存在的数据库, 会获取文件锁, 数据库正在被其他进程使用时返回错误
+pub fn open_existing(opts: EngineOptions) -> CliResult<Engine> {
+    // 不存在的目录会被引擎当成新数据库创建
+    if !opts.dir_path.is_dir() {
+        return Err(format!("{} is not a directory", opts.dir_path.display()).into());
+    }
+    Ok(Engine::open(opts)?)
+}
+
+/// 以只读方式打开一个已经存在的数据库, 只获取共享的文件锁, 不会修改数据目录
+pub fn open_read_only(dir_path: PathBuf) -> CliResult<Engine> {
+    let mut opts = EngineOptions::default();
+    opts.dir_path = dir_path;
+    opts.read_only = true;
+    open_existing(opts)
+}
+
+/// 离线merge, 无效数据的占比小于`ratio`时返回错误, 为0时总是执行
+fn merge(dir_path: PathBuf, ratio: f32) -> CliResult<()> {
+    let mut opts = EngineOptions::default();
+    opts.dir_path = dir_path;
+    opts.data_file_merge_ratio = ratio;
+
+    let db = open_existing(opts.clone())?;
+    let before = db.stat()?;
+    db.merge()?;
+    std::mem::drop(db);
+
+    // merge之后的数据在下次打开时才会替换原来的数据文件
+    let db = Engine::open(opts)?;
+    let after = db.stat()?;
+    println!(
+        "merged {} keys, disk size: {} -> {} bytes",
+        after.key_num, before.disk_size, after.disk_size
+    );
+
+    Ok(())
+}
+
+/// 校验数据库, 发现问题时返回错误
+fn verify(dir_path: PathBuf) -> CliResult<()> {
+    let db = open_read_only(dir_path)?;
+    let report = db.verify()?;
+    for record in report.corrupt_records.iter() {
+        println!(
+            "corrupt record: file {} offset {}: {}",
+            record.file_id, record.offset, record.reason
+        );
+    }
+    for mismatch in report.index_mismatches.iter() {
+        println!(
+            "index mismatch: key {:?} -> file {} offset {}: {}",
+            String::from_utf8_lossy(&mismatch.key),
+            mismatch.file_id,
+            mismatch.offset,
+            mismatch.reason
+        );
+    }
+    println!(
+        "checked {} records in {} data files, {} corrupt records, {} index mismatches",
+        report.records_checked,
+        report.files_checked,
+        report.corrupt_records.len(),
+        report.index_mismatches.len()
+    );
+
+    if !report.is_ok() {
+        return Err("verification failed".into());
+    }
+    Ok(())
+}
+
+/// 备份到`--out`, 默认复制成一个目录, `--tar`时打包成一个文件
+fn backup(args: Args) -> CliResult<()> {
+    args.positional(&[])?;
+    let out = PathBuf::from(args.required("out")?);
+    if out.exists() {
+        return Err(format!("{} already exists", out.display()).into());
+    }
+
+    let db = open_read_only(args.dir.clone())?;
+    if args.has("tar") {
+        let writer = db.backup_to_writer(BufWriter::new(File::create(&out)?))?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+    } else {
+        db.backup(out.clone())?;
+    }
+    println!("backed up {} keys to {}", db.len()?, out.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_args_parse() {
+        let args = Args::parse(
+            &strings(&["/data", "key", "--limit", "10", "--tar", "value"]),
+            &["tar"],
+        )
+        .unwrap();
+        assert_eq!(args.dir, PathBuf::from("/data"));
+        assert_eq!(
+            args.positional(&["key", "value"]).unwrap(),
+            ["key", "value"]
+        );
+        assert!(args.positional(&["key"]).is_err());
+        assert_eq!(args.get("limit"), Some("10"));
+        assert_eq!(args.required("limit").unwrap(), "10");
+        assert!(args.has("tar"));
+        assert_eq!(args.get("tar"), Some(""));
+        assert!(!args.has("prefix"));
+        assert!(args.required("prefix").is_err());
+
+        // 不在`switches`中的参数需要值
+        assert!(Args::parse(&strings(&["/data", "--tar"]), &[]).is_err());
+        assert!(Args::parse(&strings(&["--out", "backup"]), &[]).is_err());
+        assert!(Args::parse(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn test_open_existing_missing_dir() {
+        let dir = PathBuf::from("../tmp/cli/not-exist");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(open_read_only(dir.clone()).is_err());
+        // 不会创建新的数据库
+        assert!(!dir.exists());
+    }
+}
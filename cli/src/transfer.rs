@@ -0,0 +1,357 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Lines},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use indicatif::{ProgressBar, ProgressStyle};
+use lucasdb::{
+    db::Engine,
+    export::{ExportFilter, Format},
+    options::EngineOptions,
+};
+use serde_json::Value;
+
+use crate::{open_read_only, Args, CliResult};
+
+fn progress_bar(len: u64, template: &str) -> ProgressBar {
+    let bar = ProgressBar::new(len);
+    bar.set_style(ProgressStyle::with_template(template).unwrap());
+    bar
+}
+
+/// `--format`, 默认是 NDJSON
+fn parse_format(args: &Args) -> CliResult<Format> {
+    match args.get("format") {
+        None | Some("ndjson") | Some("json") => Ok(Format::Ndjson),
+        Some("csv") => Ok(Format::Csv),
+        Some(format) => Err(format!("unknown format: {}", format).into()),
+    }
+}
+
+/// 以只读方式导出, 格式和`Engine::export`一样
+pub fn export(args: Args) -> CliResult<()> {
+    args.positional(&[])?;
+    let out = args.required("out")?;
+    let format = parse_format(&args)?;
+    let modified_after = match args.get("after-ts") {
+        Some(ts) => Some(UNIX_EPOCH + Duration::from_secs(ts.parse()?)),
+        None => None,
+    };
+    let filter = ExportFilter {
+        prefix: args.get("prefix").unwrap_or_default().as_bytes().to_vec(),
+        modified_after,
+    };
+
+    let db = open_read_only(args.dir.clone())?;
+
+    // 过滤之后的数量未知, 以总数作为上限
+    let start = Instant::now();
+    let bar = progress_bar(db.len()? as u64, "{bar:40} {pos}/{len} keys {elapsed}");
+    let progress = |count: usize| bar.set_position(count as u64);
+    let count = db.export_filtered(out, format, None, &filter, Some(&progress))?;
+    bar.finish_and_clear();
+
+    println!(
+        "exported {} of {} keys to {} ({} bytes) in {:?}",
+        count,
+        db.len()?,
+        out,
+        std::fs::metadata(out)?.len(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// 导入的统计信息
+#[derive(Debug, Default)]
+struct ImportSummary {
+    /// 原来不存在的key
+    inserted: usize,
+    /// 覆盖了原来的值
+    overwritten: usize,
+    bytes: usize,
+}
+
+/// 导入`export`生成的文件
+/// NDJSON 中字符串的`value`按原样写入, 其他 JSON 值写入它的文本, CSV 的`value`都是字符串
+/// `--dry-run` 时只读取文件并统计会写入/覆盖多少数据
+pub fn import(args: Args) -> CliResult<()> {
+    args.positional(&[])?;
+    let input = args.required("in")?;
+    let format = parse_format(&args)?;
+    let dry_run = args.has("dry-run");
+
+    // 导入的目标可以是一个新的数据库, dry-run 时不创建
+    let db = if dry_run && !args.dir.is_dir() {
+        None
+    } else {
+        let mut opts = EngineOptions::default();
+        opts.dir_path = args.dir.clone();
+        Some(Engine::open(opts)?)
+    };
+
+    let file = File::open(input)?;
+    let start = Instant::now();
+    let bar = progress_bar(
+        file.metadata()?.len(),
+        "{bar:40} {bytes}/{total_bytes} {elapsed}",
+    );
+
+    let mut summary = ImportSummary::default();
+    let mut lines = BufReader::new(file).lines();
+    let mut line_num = 0;
+    // CSV 的第一行是表头
+    if format == Format::Csv {
+        if let Some(header) = lines.next() {
+            let header = header?;
+            bar.inc(header.len() as u64 + 1);
+            line_num += 1;
+        }
+    }
+    loop {
+        let record = match format {
+            Format::Ndjson => read_ndjson_record(&mut lines, &mut line_num),
+            Format::Csv => read_csv_record(&mut lines, &mut line_num),
+        };
+        let (key, value, len) = match record {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) => return Err(format!("line {}: {}", line_num, e).into()),
+        };
+        bar.inc(len);
+
+        let exists = match &db {
+            Some(db) => db.contains_key(key.clone())?,
+            None => false,
+        };
+        if exists {
+            summary.overwritten += 1;
+        } else {
+            summary.inserted += 1;
+        }
+        summary.bytes += key.len() + value.len();
+
+        match &db {
+            Some(db) if !dry_run => db.put(key, value)?,
+            _ => {}
+        }
+    }
+    match &db {
+        Some(db) if !dry_run => db.sync()?,
+        _ => {}
+    }
+    bar.finish_and_clear();
+
+    println!(
+        "{} {} keys ({} new, {} overwritten, {} bytes) from {} in {:?}",
+        if dry_run { "would import" } else { "imported" },
+        summary.inserted + summary.overwritten,
+        summary.inserted,
+        summary.overwritten,
+        summary.bytes,
+        input,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// 读取下一条 NDJSON 数据, 跳过空行, 返回`key`/`value`和读取的字节数
+fn read_ndjson_record(
+    lines: &mut Lines<BufReader<File>>,
+    line_num: &mut usize,
+) -> CliResult<Option<(Bytes, Bytes, u64)>> {
+    let mut len = 0;
+    for line in lines {
+        let line = line?;
+        *line_num += 1;
+        len += line.len() as u64 + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (key, value) = parse_line(&line)?;
+        return Ok(Some((key, value, len)));
+    }
+    Ok(None)
+}
+
+/// 读取下一条 CSV 数据`key,value`, 引号中的字段可以包含换行, 返回`key`/`value`和读取的字节数
+fn read_csv_record(
+    lines: &mut Lines<BufReader<File>>,
+    line_num: &mut usize,
+) -> CliResult<Option<(Bytes, Bytes, u64)>> {
+    let mut record = String::new();
+    let mut len = 0;
+    for line in lines {
+        let line = line?;
+        *line_num += 1;
+        len += line.len() as u64 + 1;
+        if record.is_empty() && line.is_empty() {
+            continue;
+        }
+        if !record.is_empty() {
+            record.push('\n');
+        }
+        record.push_str(&line);
+        // 引号成对出现时这条数据才结束
+        if record.matches('"').count() % 2 == 0 {
+            let mut fields = parse_csv_fields(&record)?.into_iter();
+            return match (fields.next(), fields.next(), fields.next()) {
+                (Some(key), Some(value), None) if !key.is_empty() => {
+                    Ok(Some((Bytes::from(key), Bytes::from(value), len)))
+                }
+                _ => Err("expected a non-empty key and a value".into()),
+            };
+        }
+    }
+    match record.is_empty() {
+        true => Ok(None),
+        false => Err("unterminated quoted field".into()),
+    }
+}
+
+/// 按逗号拆分字段, 引号中的`""`是一个引号
+fn parse_csv_fields(record: &str) -> CliResult<Vec<String>> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut chars = record.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => fields.push(std::mem::take(&mut field)),
+            (false, '"') => return Err("unexpected quote".into()),
+            (false, c) => field.push(c),
+        }
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// 解析一行 `{"key": "...", "value": ...}`
+fn parse_line(line: &str) -> CliResult<(Bytes, Bytes)> {
+    let record: Value = serde_json::from_str(line)?;
+    let key = match record.get("key") {
+        Some(Value::String(key)) if !key.is_empty() => Bytes::from(key.clone()),
+        _ => return Err("\"key\" must be a non-empty string".into()),
+    };
+    let value = match record.get("value") {
+        Some(Value::String(value)) => Bytes::from(value.clone()),
+        Some(value) => Bytes::from(value.to_string()),
+        None => return Err("missing \"value\"".into()),
+    };
+    Ok((key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::tests::strings;
+
+    fn basepath() -> PathBuf {
+        "../tmp/cli/transfer".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_transfer_parse_record() {
+        let (key, value) = parse_line(r#"{"key": "k", "value": "v"}"#).unwrap();
+        assert_eq!((key.as_ref(), value.as_ref()), (&b"k"[..], &b"v"[..]));
+        let (_, value) = parse_line(r#"{"key": "k", "value": {"a": 1}}"#).unwrap();
+        assert_eq!(value, r#"{"a":1}"#);
+        assert!(parse_line(r#"{"key": "", "value": "v"}"#).is_err());
+        assert!(parse_line(r#"{"key": "k"}"#).is_err());
+        assert!(parse_line("not json").is_err());
+
+        assert_eq!(
+            parse_csv_fields(r#"k,"a ""quoted"", value""#).unwrap(),
+            vec!["k", r#"a "quoted", value"#]
+        );
+        assert!(parse_csv_fields(r#"k,a"b"#).is_err());
+    }
+
+    #[test]
+    fn test_transfer_export_import() {
+        let name = "export_import";
+        clean(name);
+        let dir = basepath().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut opts = EngineOptions::default();
+        opts.dir_path = dir.join("src");
+        let db = Engine::open(opts).unwrap();
+        db.put(Bytes::from("key-1"), Bytes::from("value-1"))
+            .unwrap();
+        db.put(Bytes::from("key-2"), Bytes::from("multi\nline, \"quoted\""))
+            .unwrap();
+        db.put(Bytes::from("other"), Bytes::from("value")).unwrap();
+        std::mem::drop(db);
+
+        for format in ["ndjson", "csv"] {
+            let out = dir.join(format!("export.{}", format));
+            let src = dir.join("src").to_string_lossy().into_owned();
+            let dst = dir.join(format!("dst-{}", format));
+            let args = Args::parse(
+                &strings(&[
+                    &src,
+                    "--out",
+                    &out.to_string_lossy(),
+                    "--format",
+                    format,
+                    "--prefix",
+                    "key",
+                ]),
+                &[],
+            )
+            .unwrap();
+            export(args).unwrap();
+
+            // dry-run 不会创建数据库
+            let import_args = |dry_run: bool| {
+                let mut args = strings(&[
+                    &dst.to_string_lossy(),
+                    "--in",
+                    &out.to_string_lossy(),
+                    "--format",
+                    format,
+                ]);
+                if dry_run {
+                    args.push("--dry-run".to_string());
+                }
+                Args::parse(&args, &["dry-run"]).unwrap()
+            };
+            import(import_args(true)).unwrap();
+            assert!(!dst.exists());
+            import(import_args(false)).unwrap();
+
+            let mut opts = EngineOptions::default();
+            opts.dir_path = dst;
+            let db = Engine::open(opts).unwrap();
+            assert_eq!(db.len().unwrap(), 2);
+            assert_eq!(db.get(Bytes::from("key-1")).unwrap(), "value-1");
+            assert_eq!(
+                db.get(Bytes::from("key-2")).unwrap(),
+                "multi\nline, \"quoted\""
+            );
+        }
+
+        assert!(
+            parse_format(&Args::parse(&strings(&["dir", "--format", "xml"]), &[]).unwrap())
+                .is_err()
+        );
+
+        clean(name);
+    }
+}
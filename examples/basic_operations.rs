@@ -1,22 +1,17 @@
 use bytes::Bytes;
-use lucasdb::{
-    db,
-    errors::Errors,
-    options::{EngineOptions, IndexType},
-};
+use lucasdb::{Engine, EngineOptions, Errors, IndexType, SyncPolicy};
 
 fn main() {
     // let opts = EngineOptions::default();
     let opts = EngineOptions::builder()
         .dir_path("./tmp/examples".into())
         .data_file_size(256 * 1024 * 1024)
-        .sync_writes(false)
+        .sync_policy(SyncPolicy::Never)
         .index_type(IndexType::BTree)
-        .bytes_per_sync(0)
         .use_mmap_when_startup(true)
         .build();
 
-    let engine = db::Engine::open(opts).expect("failed to open bitcask engine");
+    let engine = Engine::open(opts).expect("failed to open bitcask engine");
 
     // put
     let res = engine.put(Bytes::from("hello"), Bytes::from("lucasdb"));
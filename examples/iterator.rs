@@ -1,7 +1,7 @@
 use bytes::Bytes;
 use lucasdb::{
     db::Engine,
-    options::{EngineOptions, IndexType, IteratorOptions},
+    options::{EngineOptions, IndexType, IteratorOptions, SyncPolicy},
 };
 fn get_test_kv(i: usize) -> (Bytes, Bytes) {
     let key = Bytes::copy_from_slice(format!("test_lucas_db_key_{:09}", i).as_bytes());
@@ -13,9 +13,8 @@ fn main() {
     let opts = EngineOptions::builder()
         .dir_path("./tmp/examples".into())
         .data_file_size(256 * 1024 * 1024)
-        .sync_writes(false)
+        .sync_policy(SyncPolicy::Never)
         .index_type(IndexType::BTree)
-        .bytes_per_sync(0)
         .use_mmap_when_startup(true)
         .data_file_merge_ratio(0f32)
         .build();
@@ -35,7 +34,7 @@ fn main() {
 
     // 迭代器遍历
     let it = db.iter(IteratorOptions::default());
-    while let Some((key, value)) = it.next() {
+    while let Some((key, value)) = it.next().unwrap() {
         let key = String::from_utf8(key.to_vec()).unwrap();
         let value = String::from_utf8(value.to_vec()).unwrap();
         println!("key: {:?},  value:{:?}", key, value);
@@ -1,7 +1,7 @@
 use bytes::Bytes;
 use lucasdb::{
     db::Engine,
-    options::{EngineOptions, IndexType, WriteBatchOptions},
+    options::{EngineOptions, IndexType, SyncPolicy, WriteBatchOptions},
 };
 fn get_test_kv(i: usize) -> (Bytes, Bytes) {
     let key = Bytes::copy_from_slice(format!("test_lucas_db_key_{:09}", i).as_bytes());
@@ -13,9 +13,8 @@ fn main() {
     let opts = EngineOptions::builder()
         .dir_path("./tmp/examples".into())
         .data_file_size(256 * 1024 * 1024)
-        .sync_writes(false)
+        .sync_policy(SyncPolicy::Never)
         .index_type(IndexType::BTree)
-        .bytes_per_sync(0)
         .use_mmap_when_startup(true)
         .data_file_merge_ratio(0f32)
         .build();
@@ -1,16 +1,15 @@
 use bytes::Bytes;
 use lucasdb::{
     db::Engine,
-    options::{EngineOptions, IndexType, WriteBatchOptions},
+    options::{EngineOptions, IndexType, SyncPolicy, WriteBatchOptions},
 };
 
 fn main() {
     let db_opts = EngineOptions::builder()
         .dir_path("./tmp/examples".into())
         .data_file_size(256 * 1024 * 1024)
-        .sync_writes(false)
+        .sync_policy(SyncPolicy::Never)
         .index_type(IndexType::BTree)
-        .bytes_per_sync(0)
         .use_mmap_when_startup(true)
         .build();
 
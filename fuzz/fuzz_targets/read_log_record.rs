@@ -0,0 +1,19 @@
+//! 把任意字节当作数据文件读取, 损坏的文件只能返回错误, 不能让进程 panic
+//! 第一个字节选择校验方式和是否加密, 不校验时损坏的头部、压缩和加密的数据都会被解析
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lucasdb::{options::ChecksumType, test_util::read_data_file};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+    let checksum = match flags % 3 {
+        0 => ChecksumType::Crc32,
+        1 => ChecksumType::XxHash64,
+        _ => ChecksumType::None,
+    };
+    let encryption_key = (flags & 0x80 != 0).then_some([7; 32]);
+    read_data_file(data, checksum, encryption_key);
+});
@@ -0,0 +1,380 @@
+use std::{path::PathBuf, pin::Pin, sync::Arc};
+
+use bytes::Bytes;
+use futures_util::{stream, Stream};
+use lucasdb::{
+    db::Engine,
+    errors::Errors,
+    options::{EngineOptions, IteratorOptions, WriteBatchOptions},
+};
+use tokio::sync::mpsc;
+use tonic::{transport::Server, Request, Response, Status};
+
+pub mod pb {
+    tonic::include_proto!("lucasdb");
+}
+
+use pb::{
+    lucasdb_server::{Lucasdb, LucasdbServer},
+    write_op::Op,
+    BatchWriteRequest, BatchWriteResponse, DeleteRequest, DeleteResponse, GetRequest, GetResponse,
+    KeyValue, MergeRequest, MergeResponse, PutRequest, PutResponse, ScanRequest, StatRequest,
+    StatResponse,
+};
+
+/// 把引擎的错误转换成 gRPC 的状态码
+fn to_status(e: Errors) -> Status {
+    let message = e.to_string();
+    match e {
+        Errors::KeyNotFound => Status::not_found(message),
+        Errors::KeyIsEmpty
+        | Errors::ExceedMaxBatchNum { .. }
+        | Errors::ExceedMaxBatchBytes { .. }
+        | Errors::KeyTooLarge { .. }
+        | Errors::ValueTooLarge { .. }
+        | Errors::RecordTooLarge { .. } => Status::invalid_argument(message),
+        Errors::MergeInProgress => Status::aborted(message),
+        Errors::MergeRatioUnreached { .. } => Status::failed_precondition(message),
+        Errors::ReadOnly => Status::permission_denied(message),
+        Errors::EngineClosed => Status::unavailable(message),
+        Errors::DatabaseFull | Errors::MergeSpaceNotEnough { .. } => {
+            Status::resource_exhausted(message)
+        }
+        _ => Status::internal(message),
+    }
+}
+
+/// 在阻塞线程中执行引擎的操作, 避免阻塞异步运行时
+async fn run_blocking<T, F>(engine: Arc<Engine>, f: F) -> Result<T, Status>
+where
+    T: Send + 'static,
+    F: FnOnce(&Engine) -> lucasdb::errors::Result<T> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(move || f(&engine)).await {
+        Ok(res) => res.map_err(to_status),
+        Err(e) => Err(Status::internal(e.to_string())),
+    }
+}
+
+struct LucasdbService {
+    engine: Arc<Engine>,
+}
+
+type ScanStream = Pin<Box<dyn Stream<Item = Result<KeyValue, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Lucasdb for LucasdbService {
+    async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+        let req = request.into_inner();
+        run_blocking(self.engine.clone(), move |engine| {
+            engine.put(Bytes::from(req.key), Bytes::from(req.value))
+        })
+        .await?;
+        Ok(Response::new(PutResponse {}))
+    }
+
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let req = request.into_inner();
+        let value = run_blocking(self.engine.clone(), move |engine| {
+            engine.get(Bytes::from(req.key))
+        })
+        .await?;
+        Ok(Response::new(GetResponse {
+            value: value.to_vec(),
+        }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let req = request.into_inner();
+        run_blocking(self.engine.clone(), move |engine| {
+            engine.delete(Bytes::from(req.key))
+        })
+        .await?;
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    type ScanStream = ScanStream;
+
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> Result<Response<Self::ScanStream>, Status> {
+        let req = request.into_inner();
+        let engine = self.engine.clone();
+
+        // 有界的 channel, 客户端读取慢的时候遍历也会暂停
+        let (tx, rx) = mpsc::channel::<Result<KeyValue, Status>>(64);
+        tokio::task::spawn_blocking(move || {
+            let iter = engine.iter(IteratorOptions {
+                prefix: req.prefix,
+                reverse: req.reverse,
+                // 0 表示不限制
+                limit: (req.limit > 0).then_some(req.limit as usize),
+                ..Default::default()
+            });
+            loop {
+                let item = match iter.next() {
+                    Ok(Some((key, value))) => Ok(KeyValue {
+                        key: key.to_vec(),
+                        value: value.to_vec(),
+                    }),
+                    Ok(None) => break,
+                    Err(e) => Err(to_status(e)),
+                };
+                let failed = item.is_err();
+                // 客户端断开连接
+                if tx.blocking_send(item).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let output = stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        });
+        Ok(Response::new(Box::pin(output) as Self::ScanStream))
+    }
+
+    async fn batch_write(
+        &self,
+        request: Request<BatchWriteRequest>,
+    ) -> Result<Response<BatchWriteResponse>, Status> {
+        let req = request.into_inner();
+        run_blocking(self.engine.clone(), move |engine| {
+            let wb = engine.new_write_batch(WriteBatchOptions::default())?;
+            for op in req.ops {
+                match op.op {
+                    Some(Op::Put(kv)) => wb.put(Bytes::from(kv.key), Bytes::from(kv.value))?,
+                    Some(Op::Delete(key)) => wb.delete(Bytes::from(key))?,
+                    None => {}
+                }
+            }
+            wb.commit()
+        })
+        .await?;
+        Ok(Response::new(BatchWriteResponse {}))
+    }
+
+    async fn stat(&self, _request: Request<StatRequest>) -> Result<Response<StatResponse>, Status> {
+        let stat = run_blocking(self.engine.clone(), |engine| engine.stat()).await?;
+        Ok(Response::new(StatResponse {
+            key_num: stat.key_num as u64,
+            data_file_num: stat.data_file_num as u64,
+            reclaim_size: stat.reclaim_size as u64,
+            disk_size: stat.disk_size as u64,
+            db_full_count: stat.db_full_count as u64,
+            discarded_bytes: stat.discarded_bytes as u64,
+            index_in_memory_keys: stat.index.in_memory_keys as u64,
+            index_spilled_keys: stat.index.spilled_keys as u64,
+        }))
+    }
+
+    async fn merge(
+        &self,
+        _request: Request<MergeRequest>,
+    ) -> Result<Response<MergeResponse>, Status> {
+        run_blocking(self.engine.clone(), |engine| engine.merge()).await?;
+        Ok(Response::new(MergeResponse {}))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // 启动 engine 实例
+    let mut opts = EngineOptions::default();
+    opts.dir_path = PathBuf::from("../tmp/lucasdb-grpc");
+    let engine = Arc::new(Engine::open(opts).unwrap());
+
+    // 启动grpc服务
+    let service = LucasdbService { engine };
+    Server::builder()
+        .add_service(LucasdbServer::new(service))
+        .serve("0.0.0.0:53310".parse()?)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use prost::Message;
+    use tonic::Code;
+
+    use super::*;
+    use pb::WriteOp;
+
+    fn basepath() -> PathBuf {
+        "../tmp/grpc".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn setup(name: &str) -> LucasdbService {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        LucasdbService {
+            engine: Arc::new(Engine::open(opts).unwrap()),
+        }
+    }
+
+    fn put_op(key: &str, value: &str) -> WriteOp {
+        WriteOp {
+            op: Some(Op::Put(KeyValue {
+                key: key.into(),
+                value: value.into(),
+            })),
+        }
+    }
+
+    async fn scan(service: &LucasdbService, req: ScanRequest) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut stream = service.scan(Request::new(req)).await.unwrap().into_inner();
+        let mut items = vec![];
+        while let Some(item) = stream.next().await {
+            let kv = item.unwrap();
+            items.push((kv.key, kv.value));
+        }
+        items
+    }
+
+    #[test]
+    fn test_grpc_message_round_trip() {
+        let req = BatchWriteRequest {
+            ops: vec![
+                put_op("key", "value"),
+                WriteOp {
+                    op: Some(Op::Delete(b"\x00\xff".to_vec())),
+                },
+            ],
+        };
+        let decoded = BatchWriteRequest::decode(req.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, req);
+
+        let req = ScanRequest {
+            prefix: b"key".to_vec(),
+            reverse: true,
+            limit: 10,
+        };
+        assert_eq!(
+            ScanRequest::decode(req.encode_to_vec().as_slice()).unwrap(),
+            req
+        );
+    }
+
+    #[test]
+    fn test_grpc_error_status() {
+        assert_eq!(to_status(Errors::KeyNotFound).code(), Code::NotFound);
+        assert_eq!(to_status(Errors::KeyIsEmpty).code(), Code::InvalidArgument);
+        assert_eq!(to_status(Errors::MergeInProgress).code(), Code::Aborted);
+        assert_eq!(to_status(Errors::ReadOnly).code(), Code::PermissionDenied);
+        assert_eq!(
+            to_status(Errors::DatabaseFull).code(),
+            Code::ResourceExhausted
+        );
+        assert_eq!(
+            to_status(Errors::FailedToBackupDatabase).code(),
+            Code::Internal
+        );
+    }
+
+    #[tokio::test]
+    async fn test_grpc_put_get_delete() {
+        let name = "put_get_delete";
+        let service = setup(name);
+
+        service
+            .put(Request::new(PutRequest {
+                key: b"key".to_vec(),
+                value: b"value".to_vec(),
+            }))
+            .await
+            .unwrap();
+        let res = service
+            .get(Request::new(GetRequest {
+                key: b"key".to_vec(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(res.into_inner().value, b"value");
+
+        let err = service
+            .put(Request::new(PutRequest {
+                key: vec![],
+                value: b"value".to_vec(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        service
+            .delete(Request::new(DeleteRequest {
+                key: b"key".to_vec(),
+            }))
+            .await
+            .unwrap();
+        let err = service
+            .get(Request::new(GetRequest {
+                key: b"key".to_vec(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        let stat = service.stat(Request::new(StatRequest {})).await.unwrap();
+        assert_eq!(stat.into_inner().key_num, 0);
+
+        clean(name);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_batch_write_scan_merge() {
+        let name = "batch_write_scan_merge";
+        let service = setup(name);
+
+        let ops = vec![
+            put_op("a", "1"),
+            put_op("b", "2"),
+            put_op("c", "3"),
+            WriteOp {
+                op: Some(Op::Delete(b"b".to_vec())),
+            },
+        ];
+        service
+            .batch_write(Request::new(BatchWriteRequest { ops }))
+            .await
+            .unwrap();
+
+        let all = scan(&service, ScanRequest::default()).await;
+        assert_eq!(
+            all,
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"c".to_vec(), b"3".to_vec())
+            ]
+        );
+        let reversed = scan(
+            &service,
+            ScanRequest {
+                prefix: vec![],
+                reverse: true,
+                limit: 1,
+            },
+        )
+        .await;
+        assert_eq!(reversed, vec![(b"c".to_vec(), b"3".to_vec())]);
+
+        // 没有无效数据时不需要 merge
+        let err = service
+            .merge(Request::new(MergeRequest {}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::FailedPrecondition);
+
+        clean(name);
+    }
+}
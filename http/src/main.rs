@@ -1,142 +1,330 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::Arc,
+};
 
 use axum::{
     body::{Body, Bytes},
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     routing::{delete, get, post},
     Json, Router,
 };
-use lucasdb::{db::Engine, options::EngineOptions};
+use futures_util::stream;
+use lucasdb::{
+    db::Engine,
+    errors::Errors,
+    options::{EngineOptions, IteratorOptions, WriteBatchOptions},
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
 
 async fn ping() -> &'static str {
     return "ping";
 }
 
-struct A {}
-impl IntoResponse for A {
-    fn into_response(self) -> axum::response::Response {
-        todo!()
+/// 出错时返回的状态码和错误信息
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl From<Errors> for ApiError {
+    fn from(e: Errors) -> Self {
+        let status = match e {
+            Errors::KeyNotFound => StatusCode::NOT_FOUND,
+            Errors::KeyIsEmpty
+            | Errors::ExceedMaxBatchNum { .. }
+            | Errors::ExceedMaxBatchBytes { .. } => StatusCode::BAD_REQUEST,
+            Errors::KeyTooLarge { .. }
+            | Errors::ValueTooLarge { .. }
+            | Errors::RecordTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Errors::MergeInProgress | Errors::MergeRatioUnreached { .. } => StatusCode::CONFLICT,
+            Errors::ReadOnly => StatusCode::FORBIDDEN,
+            Errors::EngineClosed => StatusCode::SERVICE_UNAVAILABLE,
+            Errors::DatabaseFull | Errors::MergeSpaceNotEnough { .. } => {
+                StatusCode::INSUFFICIENT_STORAGE
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError {
+            status,
+            message: e.to_string(),
+        }
     }
 }
 
-// get: /put
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, self.message).into_response()
+    }
+}
+
+/// 在阻塞线程中执行引擎的操作, 避免阻塞异步运行时
+async fn run_blocking<T, F>(engine: Arc<Engine>, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Engine) -> lucasdb::errors::Result<T> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(move || f(&engine)).await {
+        Ok(res) => res.map_err(ApiError::from),
+        Err(e) => Err(ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: e.to_string(),
+        }),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PutParams {
+    /// 为 true 时数据持久化之后才返回
+    #[serde(default)]
+    sync: bool,
+}
+
+// post: /put?sync=, 返回之后读取可以看到写入的数据
 async fn handler_put(
     State(engine): State<Arc<Engine>>,
+    Query(params): Query<PutParams>,
     Json(data): Json<HashMap<String, String>>,
-) -> impl IntoResponse {
-    println!("received: {:?}", data);
-    for (key, value) in data.iter() {
-        if let Err(_) = engine.put(Bytes::from(key.to_string()), Bytes::from(value.to_string())) {
-            let resp = Response::builder()
-                .status(StatusCode::OK)
-                .body(Body::from("failed to put value in engine"))
-                .unwrap();
-            return resp;
+) -> Result<&'static str, ApiError> {
+    run_blocking(engine, move |engine| {
+        let num = data.len();
+        for (i, (key, value)) in data.into_iter().enumerate() {
+            // 最后一次写入持久化时, 前面写入的数据也一起持久化
+            match params.sync && i + 1 == num {
+                true => engine.put_sync(Bytes::from(key), Bytes::from(value))?,
+                false => engine.put(Bytes::from(key), Bytes::from(value))?,
+            }
         }
-    }
-    let resp = Response::builder()
-        .status(StatusCode::OK)
-        .body(Body::from("OK"))
-        .unwrap();
-    return resp;
+        Ok(())
+    })
+    .await?;
+    Ok("OK")
 }
 
 async fn handler_get(
     State(engine): State<Arc<Engine>>,
     Path(key): Path<String>,
-) -> impl IntoResponse {
-    let key = Bytes::from(key);
-    let value_res = engine.get(key);
-    let value = match value_res {
-        Ok(value) => value,
-        Err(e) => match e {
-            lucasdb::errors::Errors::KeyNotFound => {
-                let resp = Response::builder()
-                    .status(StatusCode::OK)
-                    .body(Body::from("key not found"))
-                    .unwrap();
-                return resp;
-            }
-            _ => {
-                let resp = Response::builder()
-                    .status(StatusCode::OK)
-                    .body(Body::from("failed to get value in engine"))
-                    .unwrap();
-                return resp;
-            }
-        },
-    };
-
-    let resp = Response::builder()
-        .status(StatusCode::OK)
-        .body(Body::from(value))
-        .unwrap();
-    resp
+) -> Result<Bytes, ApiError> {
+    Ok(engine.get(Bytes::from(key))?)
 }
 
 async fn handler_delete(
     State(engine): State<Arc<Engine>>,
     Path(key): Path<String>,
-) -> impl IntoResponse {
-    let key = Bytes::from(key);
-    let value_res = engine.delete(key);
-    match value_res {
-        Ok(value) => value,
-        Err(e) => match e {
-            lucasdb::errors::Errors::KeyNotFound => {
-                let resp = Response::builder()
-                    .status(StatusCode::OK)
-                    .body(Body::from("key not found"))
-                    .unwrap();
-                return resp;
+) -> Result<&'static str, ApiError> {
+    engine.delete(Bytes::from(key))?;
+    Ok("OK")
+}
+
+#[derive(Debug, Deserialize)]
+struct ListKeysParams {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+// get: /listkeys?prefix=&offset=&limit=, 只遍历索引, 不读取 value
+async fn handler_listkeys(
+    State(engine): State<Arc<Engine>>,
+    Query(params): Query<ListKeysParams>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let keys = run_blocking(engine, move |engine| {
+        let iter = engine.iter(IteratorOptions {
+            prefix: params.prefix.into_bytes(),
+            offset: params.offset,
+            limit: params.limit,
+            keys_only: true,
+            ..Default::default()
+        });
+        let mut keys = vec![];
+        while let Some((key, _)) = iter.next()? {
+            keys.push(String::from_utf8_lossy(&key).to_string());
+        }
+        Ok(keys)
+    })
+    .await?;
+    Ok(Json(keys))
+}
+
+async fn handler_stat(
+    State(engine): State<Arc<Engine>>,
+) -> Result<Json<HashMap<&'static str, usize>>, ApiError> {
+    let stat = engine.stat()?;
+
+    let mut status_map = HashMap::new();
+    status_map.insert("key_num", stat.key_num);
+    status_map.insert("index_in_memory_keys", stat.index.in_memory_keys);
+    status_map.insert("index_spilled_keys", stat.index.spilled_keys);
+    status_map.insert("data_file_num", stat.data_file_num);
+    status_map.insert("reclaim_size", stat.reclaim_size);
+    status_map.insert("disk_size", stat.disk_size);
+    status_map.insert("db_full_count", stat.db_full_count);
+    status_map.insert("discarded_bytes", stat.discarded_bytes);
+    Ok(Json(status_map))
+}
+
+/// `/batch` 中的一个操作: `{"op": "put", "key": "k", "value": "v"}` 或 `{"op": "delete", "key": "k"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BatchOp {
+    Put { key: String, value: String },
+    Delete { key: String },
+}
+
+// post: /batch, 所有操作通过一个 WriteBatch 原子提交
+async fn handler_batch(
+    State(engine): State<Arc<Engine>>,
+    Json(ops): Json<Vec<BatchOp>>,
+) -> Result<&'static str, ApiError> {
+    run_blocking(engine, move |engine| {
+        let wb = engine.new_write_batch(WriteBatchOptions::default())?;
+        for op in ops {
+            match op {
+                BatchOp::Put { key, value } => wb.put(Bytes::from(key), Bytes::from(value))?,
+                BatchOp::Delete { key } => wb.delete(Bytes::from(key))?,
             }
-            _ => {
-                let resp = Response::builder()
-                    .status(StatusCode::OK)
-                    .body(Body::from("failed to delete value in engine"))
-                    .unwrap();
-                return resp;
+        }
+        wb.commit()
+    })
+    .await?;
+    Ok("OK")
+}
+
+#[derive(Debug, Deserialize)]
+struct ScanParams {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+// get: /scan?prefix=&offset=&limit=
+// 按 key 的顺序返回 NDJSON, 每行一个 `{"key": ..., "value": ...}`, 边遍历边发送
+// 读取出错时最后一行是 `{"error": ...}`
+async fn handler_scan(
+    State(engine): State<Arc<Engine>>,
+    Query(params): Query<ScanParams>,
+) -> Response {
+    // 有界的 channel, 客户端读取慢的时候遍历也会暂停
+    let (tx, rx) = mpsc::channel::<Bytes>(64);
+    tokio::task::spawn_blocking(move || {
+        let iter = engine.iter(IteratorOptions {
+            prefix: params.prefix.into_bytes(),
+            offset: params.offset,
+            limit: params.limit,
+            ..Default::default()
+        });
+        let mut failed = false;
+        while !failed {
+            let line = match iter.next() {
+                Ok(Some((key, value))) => serde_json::json!({
+                    "key": String::from_utf8_lossy(&key),
+                    "value": String::from_utf8_lossy(&value),
+                }),
+                Ok(None) => break,
+                // 状态码已经发送出去了, 最后一行返回错误信息
+                Err(e) => {
+                    failed = true;
+                    serde_json::json!({ "error": e.to_string() })
+                }
+            };
+            let mut line = line.to_string();
+            line.push('\n');
+            // 客户端断开连接
+            if tx.blocking_send(Bytes::from(line)).is_err() {
+                break;
             }
-        },
-    };
+        }
+    });
 
-    let resp = Response::builder()
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (Ok::<_, Infallible>(line), rx))
+    });
+    Response::builder()
         .status(StatusCode::OK)
-        .body(Body::from("OK"))
-        .unwrap();
-    resp
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(body))
+        .unwrap()
 }
 
-async fn handler_listkeys(State(engine): State<Arc<Engine>>) -> impl IntoResponse {
-    let keys = match engine.list_keys() {
-        Ok(keys) => keys,
-        Err(_) => todo!(),
-    };
+// post: /merge
+async fn handler_merge(State(engine): State<Arc<Engine>>) -> Result<&'static str, ApiError> {
+    run_blocking(engine, |engine| engine.merge()).await?;
+    Ok("OK")
+}
 
-    let keys = keys
-        .into_iter()
-        .map(|key| String::from_utf8(key.to_vec()).unwrap())
-        .collect::<Vec<String>>();
+#[derive(Debug, Deserialize)]
+struct BackupParams {
+    dir: String,
+}
 
-    Json(keys)
+// post: /backup, 把数据目录复制到 `{"dir": "..."}`
+async fn handler_backup(
+    State(engine): State<Arc<Engine>>,
+    Json(params): Json<BackupParams>,
+) -> Result<&'static str, ApiError> {
+    run_blocking(engine, move |engine| {
+        engine.backup(PathBuf::from(params.dir))
+    })
+    .await?;
+    Ok("OK")
 }
 
-async fn handler_stat(State(engine): State<Arc<Engine>>) -> impl IntoResponse {
-    let stat = match engine.stat() {
-        Ok(stat) => stat,
-        Err(_) => {
-            todo!()
+/// 把写入的数据按块发送到 channel, 客户端断开后返回错误, 让备份停下来
+struct ChannelWriter {
+    tx: mpsc::Sender<Bytes>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .blocking_send(Bytes::copy_from_slice(buf))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// get: /backup, 下载数据目录的 tar 包
+async fn handler_backup_download(State(engine): State<Arc<Engine>>) -> Response {
+    let (tx, rx) = mpsc::channel::<Bytes>(16);
+    tokio::task::spawn_blocking(move || {
+        let writer = BufWriter::with_capacity(64 * 1024, ChannelWriter { tx });
+        // 状态码已经发送出去了, 出错时只能中断连接, 客户端会读到不完整的 tar 包
+        let res = engine
+            .backup_to_writer(writer)
+            .and_then(|writer| writer.into_inner().map_err(|e| e.into_error().into()));
+        if let Err(e) = res {
+            eprintln!("backup download failed: {}", e);
         }
-    };
+    });
 
-    let mut status_map = HashMap::new();
-    status_map.insert("key_num", stat.key_num);
-    status_map.insert("data_file_num", stat.data_file_num);
-    status_map.insert("reclaim_size", stat.reclaim_size);
-    status_map.insert("disk_size", stat.disk_size);
-    Json(status_map)
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|chunk| (Ok::<_, Infallible>(chunk), rx))
+    });
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/x-tar")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"lucasdb-backup.tar\"",
+        )
+        .body(Body::from_stream(body))
+        .unwrap()
 }
 
 fn init_router(engine: Arc<Engine>) -> Router {
@@ -152,7 +340,16 @@ fn init_router(engine: Arc<Engine>) -> Router {
             "/delete/:key",
             delete(handler_delete).with_state(engine.clone()),
         )
-        .route("/stat", get(handler_stat).with_state(engine.clone()));
+        .route("/stat", get(handler_stat).with_state(engine.clone()))
+        .route("/batch", post(handler_batch).with_state(engine.clone()))
+        .route("/scan", get(handler_scan).with_state(engine.clone()))
+        .route("/merge", post(handler_merge).with_state(engine.clone()))
+        .route(
+            "/backup",
+            post(handler_backup)
+                .get(handler_backup_download)
+                .with_state(engine.clone()),
+        );
     let router = Router::new().nest("/lucasdb", api);
     router
 }
@@ -170,3 +367,175 @@ async fn main() -> std::io::Result<()> {
     axum::serve(listener, router).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "../tmp/http".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn setup(name: &str) -> (Router, Arc<Engine>) {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name).join("data");
+        let engine = Arc::new(Engine::open(opts).unwrap());
+        (init_router(engine.clone()), engine)
+    }
+
+    /// 发送请求, 返回状态码和响应的内容
+    async fn send(router: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[test]
+    fn test_api_error_status() {
+        let status = |e: Errors| ApiError::from(e).status;
+        assert_eq!(status(Errors::KeyNotFound), StatusCode::NOT_FOUND);
+        assert_eq!(status(Errors::KeyIsEmpty), StatusCode::BAD_REQUEST);
+        assert_eq!(status(Errors::MergeInProgress), StatusCode::CONFLICT);
+        assert_eq!(status(Errors::ReadOnly), StatusCode::FORBIDDEN);
+        assert_eq!(
+            status(Errors::DatabaseFull),
+            StatusCode::INSUFFICIENT_STORAGE
+        );
+        assert_eq!(
+            status(Errors::FailedToBackupDatabase),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_put_get_delete() {
+        let name = "put_get_delete";
+        let (router, _engine) = setup(name);
+
+        assert_eq!(
+            send(&router, "GET", "/lucasdb/ping", "").await,
+            (StatusCode::OK, "ping".to_string())
+        );
+        let (status, _) = send(
+            &router,
+            "POST",
+            "/lucasdb/put?sync=true",
+            r#"{"key-1": "value-1", "key-2": "value-2"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            send(&router, "GET", "/lucasdb/get/key-1", "").await,
+            (StatusCode::OK, "value-1".to_string())
+        );
+        assert_eq!(
+            send(&router, "GET", "/lucasdb/listkeys?prefix=key&limit=1", "").await,
+            (StatusCode::OK, r#"["key-1"]"#.to_string())
+        );
+
+        let (status, _) = send(&router, "DELETE", "/lucasdb/delete/key-1", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&router, "GET", "/lucasdb/get/key-1", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&router, "POST", "/lucasdb/put", r#"{"": "value"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send(&router, "GET", "/lucasdb/stat", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let stat: HashMap<String, usize> = serde_json::from_str(&body).unwrap();
+        assert_eq!(stat["key_num"], 1);
+
+        clean(name);
+    }
+
+    #[tokio::test]
+    async fn test_http_batch_scan() {
+        let name = "batch_scan";
+        let (router, engine) = setup(name);
+
+        let (status, _) = send(
+            &router,
+            "POST",
+            "/lucasdb/batch",
+            r#"[
+                {"op": "put", "key": "a", "value": "1"},
+                {"op": "put", "key": "b", "value": "2"},
+                {"op": "put", "key": "c", "value": "3"},
+                {"op": "delete", "key": "b"}
+            ]"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(engine.get(Bytes::from("b")).is_err());
+
+        // 格式错误时整个批次都不写入
+        let (status, _) = send(
+            &router,
+            "POST",
+            "/lucasdb/batch",
+            r#"[{"op": "put", "key": "d", "value": "4"}, {"op": "merge", "key": "a"}]"#,
+        )
+        .await;
+        assert!(status.is_client_error());
+        assert!(engine.get(Bytes::from("d")).is_err());
+
+        let (status, body) = send(&router, "GET", "/lucasdb/scan?offset=1", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "{\"key\":\"c\",\"value\":\"3\"}\n");
+
+        clean(name);
+    }
+
+    #[tokio::test]
+    async fn test_http_merge_backup() {
+        let name = "merge_backup";
+        let (router, engine) = setup(name);
+        engine
+            .put(Bytes::from("key"), Bytes::from("value"))
+            .unwrap();
+
+        // 没有无效数据时不需要 merge
+        let (status, _) = send(&router, "POST", "/lucasdb/merge", "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let backup_dir = basepath().join(name).join("backup");
+        let body = serde_json::json!({ "dir": backup_dir }).to_string();
+        let (status, _) = send(&router, "POST", "/lucasdb/backup", &body).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let request = Request::builder()
+            .uri("/lucasdb/backup")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-tar"
+        );
+        let tar = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(!tar.is_empty());
+
+        let mut opts = EngineOptions::default();
+        opts.dir_path = backup_dir;
+        let backup = Engine::open(opts).unwrap();
+        assert_eq!(backup.get(Bytes::from("key")).unwrap(), "value");
+
+        clean(name);
+    }
+}
@@ -0,0 +1,69 @@
+use std::{hint::black_box, path::PathBuf, sync::Mutex, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lucasdb::options::EngineOptions;
+use redis_lucasdb::types::RedisLucasDb;
+
+const THREADS: usize = 8;
+const OPS_PER_THREAD: usize = 500;
+
+fn open(name: &str) -> RedisLucasDb {
+    let mut opts = EngineOptions::default();
+    opts.dir_path = PathBuf::from("../tmp/benches/redis_lucasdb").join(name);
+    let _ = std::fs::remove_dir_all(&opts.dir_path);
+    let db = RedisLucasDb::new(opts).expect("failed to open database");
+    for i in 0..OPS_PER_THREAD {
+        db.set(&format!("key-{}", i), Duration::ZERO, "value")
+            .unwrap();
+    }
+    db
+}
+
+/// 每个线程读取一些key, 再往自己的hash中写入一些field, 模拟多个连接同时执行命令
+fn run_clients<F>(op: F)
+where
+    F: Fn(usize, usize) + Sync,
+{
+    std::thread::scope(|s| {
+        for t in 0..THREADS {
+            let op = &op;
+            s.spawn(move || {
+                for i in 0..OPS_PER_THREAD {
+                    op(t, i);
+                }
+            });
+        }
+    });
+}
+
+/// `write_every`为0时只读, 否则每`write_every`次操作中有一次写入
+fn client_op(db: &RedisLucasDb, t: usize, i: usize, write_every: usize) {
+    if write_every != 0 && i % write_every == 0 {
+        db.hset(&format!("hash-{}", t), &format!("field-{}", i), "value")
+            .unwrap();
+    } else {
+        black_box(db.get(&format!("key-{}", i)).unwrap());
+    }
+}
+
+fn benchmark_concurrent_clients(c: &mut Criterion) {
+    for (name, write_every) in [("read", 0), ("mixed", 4)] {
+        let mut group = c.benchmark_group(format!("redis-concurrent-clients-{}", name));
+
+        // 之前的做法: 所有连接共用一把锁
+        let db = Mutex::new(open(&format!("{}-mutex", name)));
+        group.bench_function("global-mutex", |b| {
+            b.iter(|| run_clients(|t, i| client_op(&db.lock().unwrap(), t, i, write_every)));
+        });
+
+        let db = open(&format!("{}-shared", name));
+        group.bench_function("shared", |b| {
+            b.iter(|| run_clients(|t, i| client_op(&db, t, i, write_every)));
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, benchmark_concurrent_clients);
+criterion_main!(benches);
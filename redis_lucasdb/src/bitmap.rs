@@ -0,0 +1,317 @@
+use bytes::{Bytes, BytesMut};
+use lucasdb::errors::{Errors, Result};
+
+use crate::{string::encode_string, types::RedisLucasDb};
+
+/// 和 redis 一样, bitmap 最大 512MB
+const MAX_BIT_OFFSET: u64 = (1 << 32) - 1;
+
+/// HyperLogLog 保存为 string, 以`HYLL`开头, 之后每个寄存器占一个字节
+const HLL_MAGIC: &[u8] = b"HYLL";
+/// 用 hash 的低`HLL_P`位选择寄存器
+const HLL_P: u32 = 14;
+const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_SIZE: usize = HLL_MAGIC.len() + HLL_REGISTERS;
+
+/// 实现 redis 中的 bitmap(setbit/getbit/bitcount) 和 HyperLogLog(pfadd/pfcount)
+/// 两者都保存为普通的 string, 可以用 get/set 读写原始的字节
+impl RedisLucasDb {
+    /// 设置第`offset`位, 返回原来的值\
+    /// 和 redis 一样从每个字节的最高位开始计数, 长度不够时用0补齐, 原来的过期时间保持不变
+    pub fn setbit(&self, key: impl AsRef<[u8]>, offset: u64, bit: bool) -> Result<bool> {
+        if offset > MAX_BIT_OFFSET {
+            return Err(Errors::BitOffsetOutOfRange);
+        }
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+
+        let (expire, value) = self.find_live_string(key)?.unwrap_or_default();
+        let byte = (offset >> 3) as usize;
+        let mask = 1u8 << (7 - (offset & 7));
+
+        let mut value = BytesMut::from(value.as_ref());
+        if value.len() <= byte {
+            value.resize(byte + 1, 0);
+        }
+        let old = value[byte] & mask != 0;
+        if bit {
+            value[byte] |= mask;
+        } else {
+            value[byte] &= !mask;
+        }
+
+        self.eng
+            .put(Bytes::copy_from_slice(key), encode_string(expire, &value))?;
+        Ok(old)
+    }
+
+    /// 返回第`offset`位的值, 超过长度或者`key`不存在时返回false
+    pub fn getbit(&self, key: impl AsRef<[u8]>, offset: u64) -> Result<bool> {
+        if offset > MAX_BIT_OFFSET {
+            return Err(Errors::BitOffsetOutOfRange);
+        }
+        let value = match self.find_live_string(key.as_ref())? {
+            Some((_, value)) => value,
+            None => return Ok(false),
+        };
+        let byte = (offset >> 3) as usize;
+        Ok(value
+            .get(byte)
+            .is_some_and(|b| b & (1 << (7 - (offset & 7))) != 0))
+    }
+
+    /// 统计值为1的位数\
+    /// `range`是字节的范围`[start, end]`, 负数表示从末尾开始, 为空时统计整个 string
+    pub fn bitcount(&self, key: impl AsRef<[u8]>, range: Option<(i64, i64)>) -> Result<u64> {
+        let value = match self.find_live_string(key.as_ref())? {
+            Some((_, value)) => value,
+            None => return Ok(0),
+        };
+
+        let bytes = match range {
+            None => &value[..],
+            Some((start, end)) => {
+                let len = value.len() as i64;
+                let start = if start < 0 {
+                    (len + start).max(0)
+                } else {
+                    start
+                };
+                let end = if end < 0 { len + end } else { end.min(len - 1) };
+                if start > end {
+                    return Ok(0);
+                }
+                &value[start as usize..=end as usize]
+            }
+        };
+        Ok(bytes.iter().map(|b| b.count_ones() as u64).sum())
+    }
+
+    /// 把`elements`加入 HyperLogLog, 有寄存器变化或者新建了`key`时返回true
+    pub fn pfadd<E: AsRef<[u8]>>(&self, key: impl AsRef<[u8]>, elements: &[E]) -> Result<bool> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+
+        let (expire, mut registers, mut changed) = match self.find_live_string(key)? {
+            Some((expire, value)) => (expire, hll_registers(&value)?.to_vec(), false),
+            None => (0, vec![0u8; HLL_REGISTERS], true),
+        };
+
+        for element in elements {
+            let (index, rank) = hll_position(element.as_ref());
+            if registers[index] < rank {
+                registers[index] = rank;
+                changed = true;
+            }
+        }
+
+        if changed {
+            let mut value = Vec::with_capacity(HLL_SIZE);
+            value.extend_from_slice(HLL_MAGIC);
+            value.extend_from_slice(&registers);
+            self.eng
+                .put(Bytes::copy_from_slice(key), encode_string(expire, &value))?;
+        }
+        Ok(changed)
+    }
+
+    /// 估算`keys`中所有元素并集的基数, 标准误差约 0.81%, 不存在的`key`当作空集
+    pub fn pfcount<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<u64> {
+        let mut merged = vec![0u8; HLL_REGISTERS];
+        for key in keys {
+            if let Some((_, value)) = self.find_live_string(key.as_ref())? {
+                for (m, r) in merged.iter_mut().zip(hll_registers(&value)?) {
+                    *m = (*m).max(*r);
+                }
+            }
+        }
+        Ok(hll_estimate(&merged))
+    }
+}
+
+fn hll_registers(value: &[u8]) -> Result<&[u8]> {
+    if value.len() != HLL_SIZE || !value.starts_with(HLL_MAGIC) {
+        return Err(Errors::InvalidHyperLogLog);
+    }
+    Ok(&value[HLL_MAGIC.len()..])
+}
+
+/// 返回元素对应的寄存器和其余位中第一个1的位置(从1开始)
+fn hll_position(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element, 0xadc8_3b19);
+    let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+    // 设置最高位, 保证 rank 不超过 64 - HLL_P + 1
+    let rest = (hash >> HLL_P) | (1 << (64 - HLL_P));
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+fn hll_estimate(registers: &[u8]) -> u64 {
+    let m = registers.len() as f64;
+    let mut sum = 0f64;
+    let mut zeros = 0;
+    for &r in registers {
+        sum += 1.0 / (1u64 << r) as f64;
+        if r == 0 {
+            zeros += 1;
+        }
+    }
+
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+    let estimate = alpha * m * m / sum;
+    // 基数较小时使用线性计数
+    if estimate <= 2.5 * m && zeros > 0 {
+        return (m * (m / zeros as f64).ln()).round() as u64;
+    }
+    estimate.round() as u64
+}
+
+/// redis 的 HyperLogLog 使用的 hash 函数, 结果只和输入有关, 保存到磁盘之后依然有效
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, b) in tail.iter().enumerate() {
+            h ^= (*b as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use lucasdb::options::EngineOptions;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "../tmp/redis_lucasdb/bitmap".into()
+    }
+
+    fn setup(name: &str) -> RedisLucasDb {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        RedisLucasDb::new(opts).expect("failed to create database")
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_bitmap_setbit_getbit_bitcount() {
+        let name = "setbit_getbit";
+        let db = setup(name);
+
+        assert!(!db.getbit("bits", 7).unwrap());
+        assert!(!db.setbit("bits", 7, true).unwrap());
+        assert!(db.setbit("bits", 7, true).unwrap());
+        assert!(db.getbit("bits", 7).unwrap());
+        assert!(!db.getbit("bits", 6).unwrap());
+        // 从最高位开始计数, 和 redis 的编码一样
+        assert_eq!(
+            db.get_bytes("bits").unwrap().unwrap(),
+            Bytes::from_static(b"\x01")
+        );
+
+        // 长度不够时补0
+        db.setbit("bits", 23, true).unwrap();
+        db.setbit("bits", 16, true).unwrap();
+        assert_eq!(
+            db.get_bytes("bits").unwrap().unwrap(),
+            Bytes::from_static(b"\x01\x00\x81")
+        );
+        assert_eq!(db.bitcount("bits", None).unwrap(), 3);
+        assert_eq!(db.bitcount("bits", Some((1, -1))).unwrap(), 2);
+        assert_eq!(db.bitcount("bits", Some((-1, 100))).unwrap(), 2);
+        assert_eq!(db.bitcount("bits", Some((2, 1))).unwrap(), 0);
+        assert_eq!(db.bitcount("not-exist", None).unwrap(), 0);
+
+        assert!(db.setbit("bits", 16, false).unwrap());
+        assert_eq!(db.bitcount("bits", None).unwrap(), 2);
+
+        // 在已有的 string 上操作, 过期时间保持不变
+        db.set("string", Duration::from_secs(100), "a").unwrap();
+        assert_eq!(db.bitcount("string", None).unwrap(), 3);
+        db.setbit("string", 6, true).unwrap();
+        assert_eq!(db.get("string").unwrap(), Some("c".to_string()));
+        assert_eq!(db.ttl("string").unwrap(), 100);
+
+        assert!(matches!(
+            db.setbit("bits", MAX_BIT_OFFSET + 1, true),
+            Err(Errors::BitOffsetOutOfRange)
+        ));
+        db.sadd("set", "member").unwrap();
+        assert!(matches!(
+            db.getbit("set", 0),
+            Err(Errors::WrongTypeOperation { .. })
+        ));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_bitmap_pfadd_pfcount() {
+        let name = "pfadd_pfcount";
+        let db = setup(name);
+
+        assert_eq!(db.pfcount(&["hll"]).unwrap(), 0);
+        assert!(db.pfadd("hll", &["a", "b", "c"]).unwrap());
+        assert!(!db.pfadd("hll", &["a", "b"]).unwrap());
+        assert_eq!(db.pfcount(&["hll"]).unwrap(), 3);
+        // 没有元素时只创建key
+        assert!(db.pfadd("empty", &[] as &[&str]).unwrap());
+        assert!(!db.pfadd("empty", &[] as &[&str]).unwrap());
+        assert_eq!(db.pfcount(&["empty"]).unwrap(), 0);
+
+        let elements: Vec<String> = (0..10000).map(|i| format!("element-{}", i)).collect();
+        db.pfadd("hll-1", &elements[..6000]).unwrap();
+        db.pfadd("hll-2", &elements[4000..]).unwrap();
+        let within = |count: u64, expected: f64| (count as f64 - expected).abs() / expected < 0.03;
+        assert!(within(db.pfcount(&["hll-1"]).unwrap(), 6000.0));
+        assert!(within(db.pfcount(&["hll-1", "hll-2"]).unwrap(), 10000.0));
+
+        db.set("string", Duration::ZERO, "value").unwrap();
+        assert!(matches!(
+            db.pfadd("string", &["a"]),
+            Err(Errors::InvalidHyperLogLog)
+        ));
+        assert!(matches!(
+            db.pfcount(&["hll", "string"]),
+            Err(Errors::InvalidHyperLogLog)
+        ));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_murmur_hash64a() {
+        // 结果需要保持稳定, 否则已经保存的 HyperLogLog 会失效
+        assert_eq!(murmur_hash64a(b"", 0), 0);
+        assert_eq!(murmur_hash64a(b"hello", 0xadc8_3b19), 0x0f65_6f01_eecf_e400);
+        assert_eq!(
+            murmur_hash64a(b"element-1", 0xadc8_3b19),
+            0x2aaa_d178_dcd5_aee5
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 计算过期时间使用的时钟, 返回距离`UNIX_EPOCH`的纳秒数
+pub trait Clock: Send + Sync {
+    fn now_nanos(&self) -> u128;
+}
+
+/// 系统时钟
+/// 只在创建时读取一次墙上时间, 之后按单调时钟累加, 运行期间系统时间被调整不会影响过期判断
+pub struct SystemClock {
+    wall: u128,
+    instant: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            wall: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_nanos(),
+            instant: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_nanos(&self) -> u128 {
+        self.wall + self.instant.elapsed().as_nanos()
+    }
+}
+
+/// 手动调整的时钟, 测试过期时使用
+#[cfg(test)]
+#[derive(Clone)]
+pub(crate) struct ManualClock(pub(crate) std::sync::Arc<std::sync::atomic::AtomicU64>);
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now_nanos(&self) -> u128 {
+        self.0.load(std::sync::atomic::Ordering::SeqCst) as u128
+    }
+}
@@ -0,0 +1,249 @@
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use lucasdb::options::{EngineOptions, IndexType, SyncPolicy};
+use serde::Deserialize;
+
+/// 监听地址
+pub const ADDR_ENV: &str = "LUCASDB_REDIS_ADDR";
+/// 连接需要的密码
+pub const REQUIREPASS_ENV: &str = "LUCASDB_REDIS_REQUIREPASS";
+/// 允许执行的命令, 用逗号分隔
+pub const ALLOW_COMMANDS_ENV: &str = "LUCASDB_REDIS_ALLOW_COMMANDS";
+/// 禁止执行的命令, 用逗号分隔
+pub const DENY_COMMANDS_ENV: &str = "LUCASDB_REDIS_DENY_COMMANDS";
+
+const DEFAULT_ADDR: &str = "0.0.0.0:56379";
+
+/// 命令行参数, 优先级高于环境变量和配置文件
+#[derive(Debug, Parser)]
+#[command(
+    name = "redis_lucasdb",
+    about = "redis protocol server backed by lucasdb"
+)]
+pub struct ServerArgs {
+    /// TOML 配置文件
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// 监听地址
+    #[arg(long)]
+    pub addr: Option<String>,
+    /// 0 号数据库的目录, 其他数据库使用同级目录`<dir>-db<n>`
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+    /// 持久化策略: always, never, bytes:<n>, ms:<n>
+    #[arg(long, value_parser = parse_sync_policy)]
+    pub sync: Option<SyncPolicy>,
+    /// 单个数据文件的大小, 单位字节
+    #[arg(long)]
+    pub data_file_size: Option<u64>,
+    /// 索引类型: btree, skiplist, spill
+    #[arg(long, value_parser = parse_index_type)]
+    pub index_type: Option<IndexType>,
+    /// 无效数据的占比达到多少时允许 merge
+    #[arg(long)]
+    pub merge_ratio: Option<f32>,
+    /// 连接需要的密码
+    #[arg(long)]
+    pub requirepass: Option<String>,
+}
+
+/// 配置文件的内容, 所有字段都可以省略
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    addr: Option<String>,
+    dir: Option<PathBuf>,
+    sync: Option<String>,
+    data_file_size: Option<u64>,
+    index_type: Option<String>,
+    merge_ratio: Option<f32>,
+    requirepass: Option<String>,
+    allow_commands: Option<Vec<String>>,
+    deny_commands: Option<Vec<String>>,
+}
+
+/// redis 服务的配置
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub addr: String,
+    /// 每个逻辑数据库的配置, 其他数据库的目录由`dir_path`得到
+    pub engine: EngineOptions,
+    /// 设置后每个连接需要先执行`AUTH <password>`才能执行其他命令, 为空表示不需要认证
+    pub requirepass: Option<String>,
+    pub acl: CommandAcl,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_ADDR.to_string(),
+            engine: EngineOptions::default(),
+            requirepass: None,
+            acl: CommandAcl::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// 依次使用默认值、配置文件、环境变量、命令行参数, 后面的覆盖前面的
+    pub fn load(args: ServerArgs) -> Result<Self, String> {
+        let mut config = Self::default();
+        if let Some(path) = &args.config {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read config file {}: {}", path.display(), e))?;
+            let file: FileConfig = toml::from_str(&content)
+                .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+            config.apply_file(file)?;
+        }
+        config.apply_env();
+        config.apply_args(args);
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, file: FileConfig) -> Result<(), String> {
+        if let Some(addr) = file.addr {
+            self.addr = addr;
+        }
+        if let Some(dir) = file.dir {
+            self.engine.dir_path = dir;
+        }
+        if let Some(sync) = file.sync {
+            self.engine.sync_policy = parse_sync_policy(&sync)?;
+        }
+        if let Some(size) = file.data_file_size {
+            self.engine.data_file_size = size;
+        }
+        if let Some(index_type) = file.index_type {
+            self.engine.index_type = parse_index_type(&index_type)?;
+        }
+        if let Some(ratio) = file.merge_ratio {
+            self.engine.data_file_merge_ratio = ratio;
+        }
+        if file.requirepass.is_some() {
+            self.requirepass = file.requirepass;
+        }
+        if let Some(allow) = file.allow_commands {
+            self.acl.allow = Some(allow.iter().map(|name| name.to_lowercase()).collect());
+        }
+        if let Some(deny) = file.deny_commands {
+            self.acl.deny = deny.iter().map(|name| name.to_lowercase()).collect();
+        }
+        Ok(())
+    }
+
+    fn apply_env(&mut self) {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        if let Some(addr) = var(ADDR_ENV) {
+            self.addr = addr;
+        }
+        if let Some(password) = var(REQUIREPASS_ENV) {
+            self.requirepass = Some(password);
+        }
+        if let Some(allow) = var(ALLOW_COMMANDS_ENV) {
+            self.acl.allow = Some(parse_commands(&allow));
+        }
+        if let Some(deny) = var(DENY_COMMANDS_ENV) {
+            self.acl.deny = parse_commands(&deny);
+        }
+    }
+
+    fn apply_args(&mut self, args: ServerArgs) {
+        if let Some(addr) = args.addr {
+            self.addr = addr;
+        }
+        if let Some(dir) = args.dir {
+            self.engine.dir_path = dir;
+        }
+        if let Some(sync) = args.sync {
+            self.engine.sync_policy = sync;
+        }
+        if let Some(size) = args.data_file_size {
+            self.engine.data_file_size = size;
+        }
+        if let Some(index_type) = args.index_type {
+            self.engine.index_type = index_type;
+        }
+        if let Some(ratio) = args.merge_ratio {
+            self.engine.data_file_merge_ratio = ratio;
+        }
+        if args.requirepass.is_some() {
+            self.requirepass = args.requirepass;
+        }
+    }
+
+    /// 检查`AUTH`的密码, 比较时间和密码内容无关
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        let Some(expected) = &self.requirepass else {
+            return false;
+        };
+        let expected = expected.as_bytes();
+        if expected.len() != password.len() {
+            return false;
+        }
+        expected
+            .iter()
+            .zip(password)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+}
+
+/// 按命令名控制能执行哪些命令, 命令名不区分大小写
+#[derive(Debug, Clone, Default)]
+pub struct CommandAcl {
+    /// 不为空时只允许执行其中的命令
+    pub allow: Option<HashSet<String>>,
+    /// 禁止执行的命令, 优先于`allow`
+    pub deny: HashSet<String>,
+}
+
+impl CommandAcl {
+    /// `name`需要是小写
+    pub fn is_allowed(&self, name: &str) -> bool {
+        if self.deny.contains(name) {
+            return false;
+        }
+        match &self.allow {
+            Some(allow) => allow.contains(name),
+            None => true,
+        }
+    }
+}
+
+fn parse_commands(value: &str) -> HashSet<String> {
+    value
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// `always`, `never`, `bytes:<n>`(每写入 n 字节), `ms:<n>`(每隔 n 毫秒)
+fn parse_sync_policy(value: &str) -> Result<SyncPolicy, String> {
+    let invalid = || format!("invalid sync policy '{}'", value);
+    match value.to_lowercase().as_str() {
+        "always" => Ok(SyncPolicy::Always),
+        "never" => Ok(SyncPolicy::Never),
+        other => match other.split_once(':') {
+            Some(("bytes", n)) => n
+                .parse()
+                .map(SyncPolicy::EveryNBytes)
+                .map_err(|_| invalid()),
+            Some(("ms", n)) => n
+                .parse()
+                .map(|ms| SyncPolicy::EveryDuration(Duration::from_millis(ms)))
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+fn parse_index_type(value: &str) -> Result<IndexType, String> {
+    match value.to_lowercase().as_str() {
+        "btree" => Ok(IndexType::BTree),
+        "skiplist" => Ok(IndexType::SkipList),
+        "spill" => Ok(IndexType::Spill),
+        _ => Err(format!("invalid index type '{}'", value)),
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use lucasdb::{
+    errors::{Errors, Result},
+    options::EngineOptions,
+};
+
+use crate::types::RedisLucasDb;
+
+/// 默认的逻辑数据库数量, 和 redis 一样
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// 编号为 0..N 的逻辑数据库(SELECT), 每个数据库对应一个单独的引擎, 第一次使用时才打开
+/// 0 号数据库使用配置中的目录, 其他数据库使用同级目录 `<dir>-db<N>`
+/// `RedisLucasDb`可以在多个线程间共享, 不同的连接可以同时访问同一个数据库
+pub struct Databases {
+    options: EngineOptions,
+    dbs: Vec<OnceLock<Arc<RedisLucasDb>>>,
+    open_lock: Mutex<()>,
+}
+
+impl Databases {
+    pub fn new(options: EngineOptions, num: usize) -> Self {
+        Self {
+            options,
+            dbs: (0..num).map(|_| OnceLock::new()).collect(),
+            open_lock: Mutex::new(()),
+        }
+    }
+
+    /// 数据库的数量
+    pub fn len(&self) -> usize {
+        self.dbs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dbs.is_empty()
+    }
+
+    /// 获取编号为`index`的数据库
+    pub fn get(&self, index: usize) -> Result<Arc<RedisLucasDb>> {
+        let cell = self.dbs.get(index).ok_or(Errors::DbIndexOutOfRange)?;
+        if let Some(db) = cell.get() {
+            return Ok(db.clone());
+        }
+
+        // 防止并发打开同一个目录
+        let _lock = self.open_lock.lock().unwrap();
+        if let Some(db) = cell.get() {
+            return Ok(db.clone());
+        }
+
+        let mut options = self.options.clone();
+        options.dir_path = db_dir_path(&self.options.dir_path, index);
+        let db = RedisLucasDb::new(options)?;
+        Ok(cell.get_or_init(|| Arc::new(db)).clone())
+    }
+
+    /// 清空所有数据库, 没有打开过但是目录已经存在的数据库也会打开并清空
+    pub fn flushall(&self) -> Result<()> {
+        for (index, cell) in self.dbs.iter().enumerate() {
+            if cell.get().is_some() || db_dir_path(&self.options.dir_path, index).is_dir() {
+                self.get(index)?.flushall()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn db_dir_path(dir_path: &PathBuf, index: usize) -> PathBuf {
+    if index == 0 {
+        return dir_path.clone();
+    }
+
+    let mut name = dir_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!("-db{}", index));
+    dir_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "../tmp/redis_lucasdb/databases".into()
+    }
+
+    fn clean(name: &str) {
+        for index in 0..DEFAULT_DATABASES {
+            let _ = std::fs::remove_dir_all(db_dir_path(&basepath().join(name), index));
+        }
+    }
+
+    #[test]
+    fn test_databases_select() {
+        let name = "select";
+        clean(name);
+
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        let dbs = Databases::new(opts, DEFAULT_DATABASES);
+        assert_eq!(dbs.len(), DEFAULT_DATABASES);
+        assert!(matches!(
+            dbs.get(DEFAULT_DATABASES),
+            Err(Errors::DbIndexOutOfRange)
+        ));
+
+        {
+            let db0 = dbs.get(0).unwrap();
+            db0.set("key1", Duration::ZERO, "value1").unwrap();
+            db0.hset("key2", "field", "value").unwrap();
+            db0.sadd("key3", "member").unwrap();
+            assert_eq!(db0.dbsize().unwrap(), 3);
+        }
+
+        {
+            let db1 = dbs.get(1).unwrap();
+            assert_eq!(db1.dbsize().unwrap(), 0);
+            assert!(db1.get("key1").is_err());
+            db1.set("key1", Duration::ZERO, "other").unwrap();
+            assert_eq!(db1.get("key1").unwrap(), Some("other".to_string()));
+        }
+
+        {
+            let db0 = dbs.get(0).unwrap();
+            assert_eq!(db0.get("key1").unwrap(), Some("value1".to_string()));
+            db0.flushdb().unwrap();
+            assert_eq!(db0.dbsize().unwrap(), 0);
+            assert!(db0.get("key1").is_err());
+        }
+
+        // 清空其他数据库不影响当前数据库
+        let db1 = dbs.get(1).unwrap();
+        assert_eq!(db1.dbsize().unwrap(), 1);
+
+        drop(db1);
+        drop(dbs);
+        clean(name);
+    }
+}
@@ -1,15 +1,925 @@
-use crate::types::{RedisDataType, RedisLucasDb};
-use bytes::{Buf, Bytes};
-use lucasdb::errors::Result;
+use std::{collections::BTreeMap, ops::Range, time::Duration};
+
+use crate::{
+    bytes_to_string,
+    metadata::{decode_data_type, internal_key_prefix, Metadata},
+    types::{RedisDataType, RedisLucasDb},
+    EncodeAndDecode,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use lucasdb::{
+    errors::{Errors, Result},
+    options::{IteratorOptions, WriteBatchOptions},
+};
+
+/// string 和元数据的编码都以 type + expire 开头, 见 string.rs 和 metadata.rs
+pub(crate) const EXPIRE_RANGE: Range<usize> = 1..17;
+
 impl RedisLucasDb {
-    pub fn del(&self, key: &str) -> Result<()> {
-        let ret = self.eng.delete(Bytes::copy_from_slice(key.as_bytes()));
-        ret
+    /// 删除`key`, hash/set/list/zset/stream 会同时删除以 key + version 开头的内部数据
+    /// 内部数据较多时分多个批次删除, 元数据在第一个批次中删除
+    /// 返回是否删除了一个没有过期的 key, 检查和删除在同一把 key 锁内完成
+    pub fn del(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+        let live = self.find_live_value(key)?.is_some();
+        self.remove_key(key)?;
+        Ok(live)
+    }
+
+    /// 不加锁的`del`
+    pub(crate) fn remove_key(&self, key: &[u8]) -> Result<()> {
+        let meta_key = Bytes::copy_from_slice(key);
+        let mut value = match self.eng.get(meta_key.clone()) {
+            Ok(value) => value,
+            Err(Errors::KeyNotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if !matches!(decode_data_type(&value), Ok(data_type) if data_type != RedisDataType::String)
+        {
+            return self.eng.delete(meta_key);
+        }
+
+        let meta = Metadata::decode(&mut value);
+        let prefix = internal_key_prefix(key, meta.version);
+
+        let mut internal_keys = Vec::new();
+        {
+            let iter = self.eng.iter(IteratorOptions {
+                prefix,
+                reverse: false,
+                keys_only: true,
+                ..Default::default()
+            });
+            while let Some((internal_key, _)) = iter.next()? {
+                internal_keys.push(internal_key);
+            }
+        }
+
+        let wb_opts = WriteBatchOptions::default();
+        let batch_size = wb_opts.max_batch_num as usize - 1;
+        let mut chunks = internal_keys.chunks(batch_size);
+
+        let wb = self.eng.new_write_batch(wb_opts.clone())?;
+        wb.delete(meta_key)?;
+        for internal_key in chunks.next().unwrap_or_default() {
+            wb.delete(internal_key.clone())?;
+        }
+        wb.commit()?;
+
+        for chunk in chunks {
+            let wb = self.eng.new_write_batch(wb_opts.clone())?;
+            for internal_key in chunk {
+                wb.delete(internal_key.clone())?;
+            }
+            wb.commit()?;
+        }
+        Ok(())
     }
 
     /// 返回`key`的类型
-    pub fn key_type(&self, key: &str) -> Result<RedisDataType> {
-        let mut buf = self.eng.get(Bytes::copy_from_slice(key.as_bytes()))?;
-        Ok(RedisDataType::from(buf.get_u8()))
+    /// `key`不存在或已经过期时返回 KeyNotFound
+    pub fn key_type(&self, key: impl AsRef<[u8]>) -> Result<RedisDataType> {
+        match self.find_live_value(key.as_ref())? {
+            Some(buf) => decode_data_type(&buf),
+            None => Err(Errors::KeyNotFound),
+        }
+    }
+
+    /// 设置`key`的过期时间, 对所有类型都有效, `key`不存在或已经过期时返回false
+    /// `ttl`为0时直接删除`key`
+    pub fn expire(&self, key: impl AsRef<[u8]>, ttl: Duration) -> Result<bool> {
+        let expire = self.now() + ttl.as_nanos();
+        self.set_expire(key.as_ref(), expire)
+    }
+
+    /// 设置`key`在`timestamp`(距离`UNIX_EPOCH`的时间)过期, 和`expire`一样
+    /// `timestamp`已经过去时直接删除`key`
+    pub fn expire_at(&self, key: impl AsRef<[u8]>, timestamp: Duration) -> Result<bool> {
+        self.set_expire(key.as_ref(), timestamp.as_nanos())
+    }
+
+    fn set_expire(&self, key: &[u8], expire: u128) -> Result<bool> {
+        let _guard = self.lock_key(key);
+        self.tick_clock_marker()?;
+        let value = match self.find_live_value(key)? {
+            Some(value) => value,
+            None => return Ok(false),
+        };
+
+        if expire <= self.now() {
+            self.remove_key(key)?;
+            return Ok(true);
+        }
+
+        self.update_expire(key, &value, expire)?;
+        Ok(true)
+    }
+
+    /// 返回`key`剩余的过期时间, 单位秒, 四舍五入\
+    /// `key`不存在或已经过期返回-2, 没有设置过期时间返回-1
+    pub fn ttl(&self, key: impl AsRef<[u8]>) -> Result<i64> {
+        self.remaining_ttl(key.as_ref(), Duration::from_secs(1))
+    }
+
+    /// 和`ttl`一样, 单位毫秒
+    pub fn pttl(&self, key: impl AsRef<[u8]>) -> Result<i64> {
+        self.remaining_ttl(key.as_ref(), Duration::from_millis(1))
+    }
+
+    fn remaining_ttl(&self, key: &[u8], unit: Duration) -> Result<i64> {
+        let value = match self.find_live_value(key)? {
+            Some(value) => value,
+            None => return Ok(-2),
+        };
+
+        let expire = (&value[EXPIRE_RANGE]).get_u128();
+        if expire == 0 {
+            return Ok(-1);
+        }
+        let remain = expire.saturating_sub(self.now());
+        let unit = unit.as_nanos();
+        Ok(i64::try_from((remain + unit / 2) / unit).unwrap_or(i64::MAX))
+    }
+
+    /// 移除`key`的过期时间, `key`不存在或者没有设置过期时间时返回false
+    pub fn persist(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+        let value = match self.find_live_value(key)? {
+            Some(value) => value,
+            None => return Ok(false),
+        };
+
+        if (&value[EXPIRE_RANGE]).get_u128() == 0 {
+            return Ok(false);
+        }
+        self.update_expire(key, &value, 0)?;
+        Ok(true)
+    }
+
+    /// 查找没有过期的`key`, 返回编码后的value, `key`不存在或已经过期时返回None
+    /// 过期的`key`在访问时才判断, 集合类型在读写时删除, string 在之后写入同名的`key`时被覆盖
+    pub(crate) fn find_live_value(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let value = match self.eng.get(Bytes::copy_from_slice(key)) {
+            Ok(value) => value,
+            Err(Errors::KeyNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match decode_data_type(&value) {
+            Ok(RedisDataType::String) => {}
+            // 集合类型的成员都被删除后, 元数据还会保留
+            Ok(_) => {
+                if Metadata::decode(&mut value.clone()).size == 0 {
+                    return Ok(None);
+                }
+            }
+            Err(_) => return Ok(None),
+        }
+
+        let expire = (&value[EXPIRE_RANGE]).get_u128();
+        if expire != 0 && expire <= self.now() {
+            return Ok(None);
+        }
+        Ok(Some(value))
+    }
+
+    /// 删除已经过期的`key`和它的内部数据, 读取时发现过期后调用
+    /// 获取锁之后重新检查, 期间被重新写入的`key`不会被删除
+    pub(crate) fn purge_expired(&self, key: &[u8]) -> Result<()> {
+        let _guard = self.lock_key(key);
+        let value = match self.eng.get(Bytes::copy_from_slice(key)) {
+            Ok(value) => value,
+            Err(Errors::KeyNotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if value.len() < EXPIRE_RANGE.end {
+            return Ok(());
+        }
+        let expire = (&value[EXPIRE_RANGE]).get_u128();
+        if expire != 0 && expire <= self.now() {
+            self.remove_key(key)?;
+        }
+        Ok(())
+    }
+
+    /// 只修改编码中的 expire 部分, 其余部分保持不变
+    fn update_expire(&self, key: &[u8], value: &Bytes, expire: u128) -> Result<()> {
+        let mut buf = BytesMut::from(value.as_ref());
+        buf[EXPIRE_RANGE].copy_from_slice(&expire.to_be_bytes());
+        self.eng.put(Bytes::copy_from_slice(key), buf.freeze())
+    }
+
+    /// 当前数据库中没有过期的`key`数量
+    /// 遍历索引中的`key`计数, 内部数据直接跳过, 只解析 value 开头的类型和过期时间
+    pub fn dbsize(&self) -> Result<usize> {
+        let mut scanner = VisibleKeys::new(self, None)?;
+        let mut count = 0;
+        while scanner.next()?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// 返回所有匹配`pattern`的`key`, 按字节序排序\
+    /// `pattern`支持 `*`, `?`, `[abc]`, `[^a]`, `[a-z]` 和 `\` 转义
+    pub fn keys_bytes(&self, pattern: impl AsRef<[u8]>) -> Result<Vec<Bytes>> {
+        let mut keys = self.visible_keys()?;
+        keys.retain(|key| glob_match(pattern.as_ref(), key));
+        Ok(keys)
+    }
+
+    /// 见`keys_bytes`
+    pub fn keys(&self, pattern: impl AsRef<[u8]>) -> Result<Vec<String>> {
+        self.keys_bytes(pattern)?
+            .into_iter()
+            .map(bytes_to_string)
+            .collect()
+    }
+
+    /// 从`cursor`开始最多遍历`count`个`key`, 返回下一次的游标和其中匹配`pattern`的`key`\
+    /// 游标对应上一次返回的最后一个`key`, 从这个`key`之后继续遍历, 返回的游标为0表示遍历结束\
+    /// 遍历期间一直存在的`key`都会被返回, 期间写入和删除的`key`不会让遍历跳过或重复其他`key`\
+    /// 和 redis 一样, 先取出`count`个`key`再过滤, 所以返回的数量可能少于`count`
+    pub fn scan_bytes(
+        &self,
+        cursor: u64,
+        pattern: Option<&[u8]>,
+        count: usize,
+    ) -> Result<(u64, Vec<Bytes>)> {
+        let after = match cursor {
+            0 => None,
+            _ => Some(self.scan_cursors.lock().unwrap().get(cursor)?),
+        };
+
+        let mut scanner = VisibleKeys::new(self, after)?;
+        let mut keys = Vec::new();
+        for _ in 0..count.max(1) {
+            let Some(key) = scanner.next()? else {
+                return Ok((0, keys));
+            };
+            if pattern.map_or(true, |pattern| glob_match(pattern, &key)) {
+                keys.push(key);
+            }
+        }
+
+        let last = scanner.last.take().unwrap_or_default();
+        Ok((self.scan_cursors.lock().unwrap().insert(last), keys))
+    }
+
+    /// 见`scan_bytes`
+    pub fn scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<String>)> {
+        let (next_cursor, keys) = self.scan_bytes(cursor, pattern.map(str::as_bytes), count)?;
+        let keys = keys
+            .into_iter()
+            .map(bytes_to_string)
+            .collect::<Result<_>>()?;
+        Ok((next_cursor, keys))
+    }
+
+    /// 当前数据库中所有没有过期的`key`, 按字节序排序
+    fn visible_keys(&self) -> Result<Vec<Bytes>> {
+        let mut scanner = VisibleKeys::new(self, None)?;
+        let mut keys = Vec::new();
+        while let Some(key) = scanner.next()? {
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// `key`是集合的元数据时返回它的内部数据前缀
+    fn collection_prefix(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut value = match self.eng.get(Bytes::copy_from_slice(key)) {
+            Ok(value) => value,
+            Err(Errors::KeyNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match decode_data_type(&value) {
+            Ok(RedisDataType::String) | Err(_) => Ok(None),
+            Ok(_) => {
+                let meta = Metadata::decode(&mut value);
+                Ok(Some(internal_key_prefix(key, meta.version)))
+            }
+        }
+    }
+
+    /// 删除当前数据库中的所有数据, 和`flushall`一样分批提交
+    pub fn flushdb(&self) -> Result<()> {
+        self.flushall().map(|_| ())
+    }
+
+    /// 删除数据库中的所有`key`, 包括内部使用的`key`, 返回删除的数量
+    /// 每`WriteBatchOptions::max_batch_num`个`key`提交一次, 中途失败时之前提交的批次不会回滚
+    pub fn flushall(&self) -> Result<usize> {
+        let wb_opts = WriteBatchOptions::default();
+        let batch_size = wb_opts.max_batch_num as usize;
+        // 提交后会清空暂存的数据, 可以继续使用同一个批处理
+        let wb = self.eng.new_write_batch(wb_opts)?;
+        let mut count = 0;
+        let mut pending = 0;
+        for key in self.eng.keys_iter() {
+            wb.delete(key)?;
+            pending += 1;
+            if pending == batch_size {
+                wb.commit()?;
+                count += pending;
+                pending = 0;
+            }
+        }
+        wb.commit()?;
+        count += pending;
+        Ok(count)
+    }
+}
+
+/// 同时保存的 SCAN 游标数量, 超过之后丢弃最早的游标
+const SCAN_CURSOR_NUM: usize = 1024;
+
+/// SCAN 返回给客户端的游标, 游标对应上一次返回的最后一个`key`
+#[derive(Default)]
+pub(crate) struct ScanCursors {
+    next_id: u64,
+    keys: BTreeMap<u64, Bytes>,
+}
+
+impl ScanCursors {
+    fn insert(&mut self, key: Bytes) -> u64 {
+        if self.keys.len() >= SCAN_CURSOR_NUM {
+            self.keys.pop_first();
+        }
+        // 0 表示遍历结束, 不会分配给游标
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.keys.insert(self.next_id, key);
+        self.next_id
+    }
+
+    fn get(&self, cursor: u64) -> Result<Bytes> {
+        self.keys.get(&cursor).cloned().ok_or(Errors::InvalidCursor)
+    }
+}
+
+/// 按字节序遍历用户可见的`key`, 只读取索引中的`key`\
+/// hash/set/list/zset/stream 内部使用的`key`以 key + version 开头, 遇到时直接跳到这个前缀之后
+struct VisibleKeys<'a> {
+    db: &'a RedisLucasDb,
+    iter: lucasdb::Iterator<'a>,
+    now: u128,
+    /// 当前位置之前的集合的内部数据前缀, 之后的`key`以其中一个开头时是内部数据
+    internal_prefixes: Vec<Vec<u8>>,
+    /// 上一次返回的`key`
+    last: Option<Bytes>,
+}
+
+impl<'a> VisibleKeys<'a> {
+    /// 从`after`之后开始遍历, `after`为 None 时从头开始
+    fn new(db: &'a RedisLucasDb, after: Option<Bytes>) -> Result<Self> {
+        let iter = db.eng.iter(IteratorOptions {
+            keys_only: true,
+            ..Default::default()
+        });
+        let mut internal_prefixes = Vec::new();
+        if let Some(after) = &after {
+            iter.seek(after.to_vec());
+            // 之后的内部数据所属的集合, 它的 key 一定是`after`的前缀
+            for n in 1..=after.len() {
+                if let Some(prefix) = db.collection_prefix(&after[..n])? {
+                    internal_prefixes.push(prefix);
+                }
+            }
+        }
+
+        Ok(Self {
+            db,
+            iter,
+            now: db.now(),
+            internal_prefixes,
+            last: after,
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<Bytes>> {
+        while let Some((key, _)) = self.iter.next()? {
+            if self.last.as_ref() == Some(&key) {
+                continue;
+            }
+
+            // 已经越过的前缀之后不会再出现
+            self.internal_prefixes
+                .retain(|prefix| key.starts_with(prefix) || key.as_ref() < prefix.as_slice());
+            if let Some(prefix) = self
+                .internal_prefixes
+                .iter()
+                .find(|prefix| key.starts_with(prefix))
+            {
+                if let Some(end) = prefix_end(prefix) {
+                    self.iter.seek(end);
+                }
+                continue;
+            }
+
+            let mut value = match self.db.eng.get(key.clone()) {
+                Ok(value) => value,
+                Err(Errors::KeyNotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            // 编码格式见 string.rs 和 metadata.rs
+            let expire = match decode_data_type(&value) {
+                Ok(RedisDataType::String) => (&value[EXPIRE_RANGE]).get_u128(),
+                Ok(_) => {
+                    let meta = Metadata::decode(&mut value);
+                    self.internal_prefixes
+                        .push(internal_key_prefix(&key, meta.version));
+                    if meta.size == 0 {
+                        continue;
+                    }
+                    meta.expire
+                }
+                Err(_) => continue,
+            };
+
+            if expire == 0 || expire > self.now {
+                self.last = Some(key.clone());
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// 大于所有以`prefix`开头的`key`的最小值, `prefix`全是0xff时返回 None
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// redis 风格的 glob 匹配
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.first() {
+        None => s.is_empty(),
+        Some(b'*') => {
+            // 连续的 * 等价于一个
+            let rest = &pattern[1..];
+            (0..=s.len()).any(|i| glob_match(rest, &s[i..]))
+        }
+        Some(b'?') => !s.is_empty() && glob_match(&pattern[1..], &s[1..]),
+        Some(b'[') => {
+            let Some((&c, rest_s)) = s.split_first() else {
+                return false;
+            };
+            let mut i = 1;
+            let negate = pattern.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
+            }
+
+            let mut matched = false;
+            while i < pattern.len() && pattern[i] != b']' {
+                if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                    i += 1;
+                    matched |= pattern[i] == c;
+                } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']'
+                {
+                    let (lo, hi) = (
+                        pattern[i].min(pattern[i + 2]),
+                        pattern[i].max(pattern[i + 2]),
+                    );
+                    matched |= lo <= c && c <= hi;
+                    i += 2;
+                } else {
+                    matched |= pattern[i] == c;
+                }
+                i += 1;
+            }
+            // 没有闭合的 [ 按普通字符匹配
+            if i >= pattern.len() {
+                return c == b'[' && glob_match(&pattern[1..], rest_s);
+            }
+            matched != negate && glob_match(&pattern[i + 1..], rest_s)
+        }
+        Some(b'\\') if pattern.len() > 1 => {
+            s.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &s[1..])
+        }
+        Some(&p) => s.first() == Some(&p) && glob_match(&pattern[1..], &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    };
+
+    use lucasdb::options::EngineOptions;
+
+    use super::*;
+    use crate::{clock::ManualClock, types::CLOCK_MARKER_FILE_NAME};
+
+    fn basepath() -> PathBuf {
+        "../tmp/redis_lucasdb/generic".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_generic_expire_ttl_persist() {
+        let name = "expire_ttl_persist";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+
+        let secs = |n: u64| Duration::from_secs(n).as_nanos() as u64;
+        let clock = ManualClock(Arc::new(AtomicU64::new(secs(1000))));
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+
+        db.set("string", Duration::ZERO, "value").unwrap();
+        db.hset("hash", "field", "value").unwrap();
+        db.sadd("set", "member").unwrap();
+        db.rpush("list", "element").unwrap();
+        db.zadd("zset", 1.0, "member").unwrap();
+        let keys = ["string", "hash", "set", "list", "zset"];
+
+        assert!(!db.expire("not-exist", Duration::from_secs(10)).unwrap());
+        assert_eq!(db.ttl("not-exist").unwrap(), -2);
+        for key in keys {
+            assert_eq!(db.ttl(key).unwrap(), -1);
+            assert!(!db.persist(key).unwrap());
+            assert!(db.expire(key, Duration::from_secs(10)).unwrap());
+            assert_eq!(db.ttl(key).unwrap(), 10);
+        }
+
+        // persist 之后不会过期, 其他key按时过期
+        assert!(db.persist("hash").unwrap());
+        assert!(db.expire("set", Duration::from_secs(100)).unwrap());
+        clock.0.store(secs(1004), Ordering::SeqCst);
+        assert_eq!(db.ttl("string").unwrap(), 6);
+        assert_eq!(db.get("string").unwrap(), Some("value".to_string()));
+
+        clock.0.store(secs(1010), Ordering::SeqCst);
+        assert_eq!(db.dbsize().unwrap(), 2);
+        assert_eq!(db.ttl("string").unwrap(), -2);
+        assert!(db.get("string").unwrap().is_none());
+        assert!(!db.expire("string", Duration::from_secs(10)).unwrap());
+        assert!(matches!(db.key_type("list"), Err(Errors::KeyNotFound)));
+        assert!(db.rpop("list").unwrap().is_none());
+        assert_eq!(db.zscore("zset", "member").unwrap(), -1.0);
+        assert_eq!(db.hget("hash", "field").unwrap(), Some("value".to_string()));
+        assert!(db.sismember("set", "member").unwrap());
+        assert_eq!(db.ttl("set").unwrap(), 90);
+
+        // 过期之后重新写入, 不会带上原来的数据和过期时间
+        db.rpush("list", "new").unwrap();
+        assert_eq!(db.ttl("list").unwrap(), -1);
+        assert_eq!(db.rpop("list").unwrap(), Some("new".to_string()));
+
+        // 过期时间重启后仍然有效
+        drop(db);
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+        assert_eq!(db.ttl("hash").unwrap(), -1);
+        assert_eq!(db.ttl("set").unwrap(), 90);
+        assert_eq!(db.ttl("zset").unwrap(), -2);
+
+        // ttl 为0时直接删除
+        assert!(db.expire("hash", Duration::ZERO).unwrap());
+        assert_eq!(db.ttl("hash").unwrap(), -2);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_generic_del_internal_keys() {
+        let name = "del_internal_keys";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        let db = RedisLucasDb::new(opts.clone()).unwrap();
+        let count_keys = |db: &RedisLucasDb| db.eng.list_keys().unwrap().len();
+
+        db.set("string", Duration::ZERO, "value").unwrap();
+        for i in 0..100 {
+            db.hset("hash", &format!("field-{}", i), "value").unwrap();
+            db.sadd("set", &format!("member-{}", i)).unwrap();
+            db.rpush("list", &format!("element-{}", i)).unwrap();
+            db.zadd("zset", i as f64, &format!("member-{}", i)).unwrap();
+        }
+        // 前缀相同的其他key不受影响
+        db.set("hash-other", Duration::ZERO, "value").unwrap();
+        assert_eq!(count_keys(&db), 2 + 4 + 100 * 5);
+
+        for key in ["string", "hash", "set", "list", "zset"] {
+            assert!(db.del(key).unwrap());
+            assert!(matches!(db.key_type(key), Err(Errors::KeyNotFound)));
+            assert!(!db.del(key).unwrap());
+        }
+        assert!(!db.del("not-exist").unwrap());
+        assert_eq!(count_keys(&db), 1);
+        assert_eq!(db.get("hash-other").unwrap(), Some("value".to_string()));
+
+        // 重新创建的集合是空的
+        assert!(db.hset("hash", "field-1", "new").unwrap());
+        assert_eq!(db.hget("hash", "field-1").unwrap(), Some("new".to_string()));
+        assert!(!db.sismember("set", "member-1").unwrap());
+
+        drop(db);
+        let db = RedisLucasDb::new(opts).unwrap();
+        assert_eq!(count_keys(&db), 3);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_generic_expire_lazy_cleanup() {
+        let name = "expire_lazy_cleanup";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+
+        let secs = |n: u64| Duration::from_secs(n).as_nanos() as u64;
+        let clock = ManualClock(Arc::new(AtomicU64::new(secs(1000))));
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+        let count_keys = |db: &RedisLucasDb| db.eng.list_keys().unwrap().len();
+
+        for i in 0..10 {
+            db.hset("hash", format!("field-{}", i), "value").unwrap();
+            db.rpush("list", format!("element-{}", i)).unwrap();
+        }
+        db.set("string", Duration::ZERO, "value").unwrap();
+        assert_eq!(count_keys(&db), 1 + 11 + 11);
+
+        // 毫秒精度
+        assert!(db.expire("hash", Duration::from_millis(1500)).unwrap());
+        assert_eq!(db.pttl("hash").unwrap(), 1500);
+        assert_eq!(db.ttl("hash").unwrap(), 2);
+        assert_eq!(db.pttl("string").unwrap(), -1);
+        assert_eq!(db.pttl("not-exist").unwrap(), -2);
+
+        // 按绝对时间过期, 时间已经过去时直接删除
+        assert!(db.expire_at("list", Duration::from_secs(1010)).unwrap());
+        assert_eq!(db.ttl("list").unwrap(), 10);
+        assert!(db.expire_at("string", Duration::from_secs(999)).unwrap());
+        assert_eq!(db.ttl("string").unwrap(), -2);
+        assert!(!db
+            .expire_at("not-exist", Duration::from_secs(1010))
+            .unwrap());
+        assert_eq!(count_keys(&db), 11 + 11);
+
+        // 读取时发现过期, 同时删除内部数据
+        clock.0.store(secs(1002), Ordering::SeqCst);
+        assert_eq!(db.hlen("hash").unwrap(), 0);
+        assert_eq!(count_keys(&db), 11);
+
+        // 过期的 key 可以被其他类型覆盖, 写入时删除原来的内部数据
+        clock.0.store(secs(1010), Ordering::SeqCst);
+        assert!(db.sadd("list", "member").unwrap());
+        assert!(db.sismember("list", "member").unwrap());
+        assert_eq!(count_keys(&db), 2);
+
+        // 过期的集合类型用 get 读取时当作不存在, 不会返回类型错误
+        assert!(db.expire("list", Duration::from_secs(1)).unwrap());
+        clock.0.store(secs(1011), Ordering::SeqCst);
+        assert!(db.get("list").unwrap().is_none());
+        assert_eq!(db.scard("list").unwrap(), 0);
+        assert_eq!(count_keys(&db), 0);
+
+        // 删除已经过期的 key 不计入删除数量, 但内部数据同样被清理
+        for i in 0..10 {
+            db.hset("hash", format!("field-{}", i), "value").unwrap();
+        }
+        assert!(db.expire("hash", Duration::from_secs(1)).unwrap());
+        clock.0.store(secs(1012), Ordering::SeqCst);
+        assert!(!db.del("hash").unwrap());
+        assert_eq!(count_keys(&db), 0);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_generic_glob_match() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*", "", true),
+            ("*", "anything", true),
+            ("user:*", "user:1", true),
+            ("user:*", "order:1", false),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("*:*:end", "a:b:c:end", true),
+            ("[", "[", true),
+        ];
+        for (pattern, s, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), s.as_bytes()),
+                *expected,
+                "pattern: {}, s: {}",
+                pattern,
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_generic_keys_and_scan() {
+        let name = "keys_and_scan";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        let db = RedisLucasDb::new(opts).unwrap();
+
+        for i in 0..10 {
+            db.set(&format!("user:{}", i), Duration::ZERO, "value")
+                .unwrap();
+        }
+        db.hset("hash", "field", "value").unwrap();
+        db.rpush("list", "element").unwrap();
+        db.zadd("zset", 1.0, "member").unwrap();
+        db.sadd("set", "member").unwrap();
+        db.srem("set", "member").unwrap();
+
+        let keys = db.keys("*").unwrap();
+        assert_eq!(keys.len(), 13);
+        assert!(keys.contains(&"hash".to_string()));
+        assert!(!keys.contains(&"set".to_string()));
+        assert_eq!(
+            db.keys("user:[1-3]").unwrap(),
+            vec!["user:1", "user:2", "user:3"]
+        );
+        assert_eq!(db.keys("?ist").unwrap(), vec!["list"]);
+        assert!(db.keys("nothing*").unwrap().is_empty());
+
+        // 游标遍历完所有的key
+        let mut cursor = 0;
+        let mut scanned = Vec::new();
+        loop {
+            let (next, keys) = db.scan(cursor, None, 4).unwrap();
+            assert!(keys.len() <= 4);
+            scanned.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(scanned, db.keys("*").unwrap());
+
+        let mut cursor = 0;
+        let mut scanned = Vec::new();
+        loop {
+            let (next, keys) = db.scan(cursor, Some("user:*"), 3).unwrap();
+            scanned.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(scanned.len(), 10);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_generic_scan_concurrent_changes() {
+        let name = "scan_concurrent_changes";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        let db = RedisLucasDb::new(opts).unwrap();
+
+        // 集合的内部数据紧跟在集合的 key 之后, 游标停在集合上时继续跳过内部数据
+        for i in 0..10 {
+            db.set(&format!("key:{:02}", i * 2), Duration::ZERO, "value")
+                .unwrap();
+            db.hset(&format!("key:{:02}", i * 2 + 1), "field", "value")
+                .unwrap();
+        }
+        for i in 0..50 {
+            db.hset("key:01", &format!("field-{}", i), "value").unwrap();
+        }
+
+        let mut cursor = 0;
+        let mut scanned = Vec::new();
+        let mut round = 0;
+        loop {
+            let (next, keys) = db.scan(cursor, None, 3).unwrap();
+            assert!(keys.len() <= 3);
+            scanned.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+
+            // 遍历期间写入和删除其他 key, 不影响一直存在的 key
+            round += 1;
+            db.set(&format!("key:{:02}a", round), Duration::ZERO, "value")
+                .unwrap();
+            db.set(&format!("key:{:02}b", 30 - round), Duration::ZERO, "value")
+                .unwrap();
+            db.del(format!("key:{:02}b", 30 - round)).unwrap();
+        }
+
+        for i in 0..20 {
+            let key = format!("key:{:02}", i);
+            assert_eq!(scanned.iter().filter(|k| **k == key).count(), 1);
+        }
+        let mut sorted = scanned.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, scanned);
+
+        assert!(matches!(
+            db.scan(u64::MAX, None, 3),
+            Err(Errors::InvalidCursor)
+        ));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_generic_dbsize_flushdb() {
+        let name = "dbsize_flushdb";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        let db = RedisLucasDb::new(opts).unwrap();
+
+        // 超过一个批次的数量
+        let num = WriteBatchOptions::default().max_batch_num as usize + 10;
+        for i in 0..num {
+            db.set(&format!("key-{}", i), Duration::ZERO, "value")
+                .unwrap();
+        }
+        for i in 0..100 {
+            db.hset("hash", &format!("field-{}", i), "value").unwrap();
+        }
+        assert_eq!(db.dbsize().unwrap(), num + 1);
+
+        db.flushdb().unwrap();
+        assert_eq!(db.dbsize().unwrap(), 0);
+        assert!(db.eng.list_keys().unwrap().is_empty());
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_generic_clock_marker_not_in_keyspace() {
+        let name = "clock_marker_not_in_keyspace";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+
+        let secs = |n: u64| Duration::from_secs(n).as_nanos() as u64;
+        let clock = ManualClock(Arc::new(AtomicU64::new(secs(1000))));
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+
+        // 时间标记不在数据库中, 和之前同名的 key 可以正常读写
+        let key = "__lucasdb_redis_clock_marker__";
+        assert!(db.keys("*").unwrap().is_empty());
+        assert_eq!(db.dbsize().unwrap(), 0);
+        assert!(matches!(db.get(key), Err(Errors::KeyNotFound)));
+        assert!(db.hset(key, "field", "value").unwrap());
+        assert_eq!(db.keys("*").unwrap(), vec![key]);
+        assert!(db.del(key).unwrap());
+        db.set(key, Duration::ZERO, "value").unwrap();
+        clock.0.store(secs(1020), Ordering::SeqCst);
+        db.set(key, Duration::ZERO, "value").unwrap();
+        db.flushdb().unwrap();
+        assert_eq!(db.dbsize().unwrap(), 0);
+
+        // 只读的命令不会写入时间标记, 关闭时写入
+        let marker = opts.dir_path.join(CLOCK_MARKER_FILE_NAME);
+        let persisted = std::fs::read(&marker).unwrap();
+        clock.0.store(secs(1030), Ordering::SeqCst);
+        assert_eq!(db.ttl(key).unwrap(), -2);
+        assert_eq!(std::fs::read(&marker).unwrap(), persisted);
+        drop(db);
+
+        clock.0.store(secs(1000), Ordering::SeqCst);
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+        assert_eq!(db.clock_skew(), Some(Duration::from_secs(30)));
+
+        clean(name);
     }
 }
@@ -1,169 +1,122 @@
-use std::time::SystemTime;
-
 use crate::{
-    metadata::Metadata,
+    bytes_to_string,
+    metadata::{internal_key_prefix, InternalKey},
     types::{RedisDataType, RedisLucasDb},
     EncodeAndDecode,
 };
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use lucasdb::{
     errors::{Errors, Result},
-    options::WriteBatchOptions,
+    options::{IteratorOptions, WriteBatchOptions},
 };
 
-const INITIAL_LIST_MARK: u64 = std::u64::MAX / 2;
-
 pub(crate) struct HashInternalKey {
     pub(crate) key: Vec<u8>,
     pub(crate) version: u128,
     pub(crate) field: Vec<u8>,
 }
 
-impl EncodeAndDecode for HashInternalKey {
+impl InternalKey for HashInternalKey {
+    /// 编码格式: key + version + field
     fn encode(&self) -> Bytes {
-        let mut buf = BytesMut::new();
-        buf.extend_from_slice(&self.key);
-        buf.put_u128(self.version);
+        let mut buf = BytesMut::from(internal_key_prefix(&self.key, self.version).as_slice());
         buf.extend_from_slice(&self.field);
         buf.into()
     }
 
-    fn decode(buf: &mut Bytes) -> Self {
-        todo!()
+    /// 编码中没有记录`key`的长度, 需要传入`key`才能解析出 version 和 field
+    fn decode(key: &[u8], buf: &[u8]) -> Self {
+        let mut buf = &buf[key.len()..];
+        let version = buf.get_u128();
+        HashInternalKey {
+            key: key.to_vec(),
+            version,
+            field: buf.to_vec(),
+        }
     }
 }
 
 impl RedisLucasDb {
-    /// 根据 hash 的 key 查找元数据
-    /// 如果 key 不存在,则创建一个新的元数据并返回
-    pub(crate) fn find_or_new_metadata(
+    pub fn hset(
         &self,
-        key: &str,
-        data_type: RedisDataType,
-    ) -> Result<Metadata> {
-        let mut exist = true;
-        let mut meta = None;
-        match self.eng.get(Bytes::copy_from_slice(key.as_bytes())) {
-            Ok(mut meta_buf) => {
-                let meta_buf_data_type = RedisDataType::from((&meta_buf[0..1])[0]);
-                if data_type != RedisDataType::from(meta_buf_data_type) {
-                    return Err(Errors::WrongTypeOperation {
-                        expected: data_type.to_string(),
-                        actual: meta_buf_data_type.to_string(),
-                    });
-                }
-                let metadata = Metadata::decode(&mut meta_buf);
-                meta = Some(metadata);
-
-                // 是否过期
-                let now = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_nanos();
-                let expire = meta.as_ref().unwrap().expire;
-                if expire != 0 && expire <= now {
-                    exist = false;
-                }
-            }
-            Err(e) => match e {
-                Errors::KeyNotFound => {
-                    exist = false;
-                }
-                _ => return Err(e),
-            },
-        };
-
-        if !exist {
-            let now = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos();
-
-            let mut metadata = Metadata {
-                data_type,
-                expire: 0,
-                version: now,
-                size: 0,
-                head: 0,
-                tail: 0,
-            };
-
-            if data_type == RedisDataType::List {
-                metadata.head = INITIAL_LIST_MARK;
-                metadata.tail = INITIAL_LIST_MARK;
-            }
-
-            meta = Some(metadata);
-        }
-
-        Ok(meta.unwrap())
-    }
-
-    pub fn hset(&self, key: &str, field: &str, value: &str) -> Result<bool> {
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<bool> {
+        let key = key.as_ref();
+        let field = field.as_ref();
+        let value = value.as_ref();
+        let _guard = self.lock_key(key);
         // 查询元数据
         let mut meta = self.find_or_new_metadata(key, RedisDataType::Hash)?;
         // 构造数据部分的key
         let internal_key = HashInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
-            field: field.as_bytes().to_vec(),
+            field: field.to_vec(),
         };
 
-        let mut exist = true;
-        if let Err(e) = self.eng.get(internal_key.encode()) {
-            match e {
-                Errors::KeyNotFound => {
-                    exist = false;
-                }
-                _ => {}
-            }
-        }
-
         let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
+        let exist = match wb.get(internal_key.encode()) {
+            Ok(_) => true,
+            Err(Errors::KeyNotFound) => false,
+            Err(e) => return Err(e),
+        };
         if !exist {
             meta.size += 1;
-            wb.put(Bytes::copy_from_slice(key.as_bytes()), meta.encode())?;
+            wb.put(Bytes::copy_from_slice(key), meta.encode())?;
         }
 
-        wb.put(
-            internal_key.encode(),
-            Bytes::copy_from_slice(value.as_bytes()),
-        )?;
+        wb.put(internal_key.encode(), Bytes::copy_from_slice(value))?;
         wb.commit()?;
 
         Ok(!exist)
     }
 
     /// 当key/field不存在,返回 KeyNotFound
-    pub fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
-        let meta = self.find_or_new_metadata(key, RedisDataType::Hash)?;
+    pub fn hget_bytes(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+    ) -> Result<Option<Bytes>> {
+        let key = key.as_ref();
+        let field = field.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::Hash)?;
         if meta.size == 0 {
             return Ok(None);
         }
 
         let internal_key = HashInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
-            field: field.as_bytes().to_vec(),
+            field: field.to_vec(),
         };
 
         let value = self.eng.get(internal_key.encode())?;
-        let value_string = String::from_utf8(value.to_vec())?;
+        Ok(Some(value))
+    }
 
-        Ok(Some(value_string))
+    /// 见`hget_bytes`, value 不是 utf8 时返回错误
+    pub fn hget(&self, key: impl AsRef<[u8]>, field: impl AsRef<[u8]>) -> Result<Option<String>> {
+        self.hget_bytes(key, field)?
+            .map(bytes_to_string)
+            .transpose()
     }
 
     ///
-    pub fn hdel(&self, key: &str, field: &str) -> Result<bool> {
+    pub fn hdel(&self, key: impl AsRef<[u8]>, field: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let field = field.as_ref();
+        let _guard = self.lock_key(key);
         let mut meta = self.find_or_new_metadata(key, RedisDataType::Hash)?;
         if meta.size == 0 {
             return Ok(false);
         }
 
         let internal_key = HashInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
-            field: field.as_bytes().to_vec(),
+            field: field.to_vec(),
         };
 
         let mut exist = true;
@@ -179,7 +132,7 @@ impl RedisLucasDb {
         if exist {
             let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
             meta.size -= 1;
-            wb.put(Bytes::copy_from_slice(key.as_bytes()), meta.encode())?;
+            wb.put(Bytes::copy_from_slice(key), meta.encode())?;
             wb.delete(internal_key.encode())?;
             wb.commit()?;
 
@@ -188,6 +141,97 @@ impl RedisLucasDb {
 
         Ok(exist)
     }
+
+    /// field 是否存在
+    pub fn hexists(&self, key: impl AsRef<[u8]>, field: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let field = field.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::Hash)?;
+        if meta.size == 0 {
+            return Ok(false);
+        }
+
+        let internal_key = HashInternalKey {
+            key: key.to_vec(),
+            version: meta.version,
+            field: field.to_vec(),
+        };
+
+        match self.eng.get(internal_key.encode()) {
+            Ok(_) => Ok(true),
+            Err(Errors::KeyNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// field 的数量, key 不存在时返回0
+    pub fn hlen(&self, key: impl AsRef<[u8]>) -> Result<u32> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::Hash)?;
+        Ok(meta.size)
+    }
+
+    /// 返回所有的 field 和 value, 按 field 排序
+    pub fn hgetall_bytes(&self, key: impl AsRef<[u8]>) -> Result<Vec<(Bytes, Bytes)>> {
+        self.hash_entries(key.as_ref())
+    }
+
+    /// 见`hgetall_bytes`
+    pub fn hgetall(&self, key: impl AsRef<[u8]>) -> Result<Vec<(String, String)>> {
+        let mut pairs = Vec::new();
+        for (field, value) in self.hgetall_bytes(key)? {
+            pairs.push((bytes_to_string(field)?, bytes_to_string(value)?));
+        }
+        Ok(pairs)
+    }
+
+    /// 返回所有的 field, 按 field 排序
+    pub fn hkeys_bytes(&self, key: impl AsRef<[u8]>) -> Result<Vec<Bytes>> {
+        let entries = self.hash_entries(key.as_ref())?;
+        Ok(entries.into_iter().map(|(field, _)| field).collect())
+    }
+
+    /// 见`hkeys_bytes`
+    pub fn hkeys(&self, key: impl AsRef<[u8]>) -> Result<Vec<String>> {
+        self.hkeys_bytes(key)?
+            .into_iter()
+            .map(bytes_to_string)
+            .collect()
+    }
+
+    /// 返回所有的 value, 按 field 排序
+    pub fn hvals_bytes(&self, key: impl AsRef<[u8]>) -> Result<Vec<Bytes>> {
+        let entries = self.hash_entries(key.as_ref())?;
+        Ok(entries.into_iter().map(|(_, value)| value).collect())
+    }
+
+    /// 见`hvals_bytes`
+    pub fn hvals(&self, key: impl AsRef<[u8]>) -> Result<Vec<String>> {
+        self.hvals_bytes(key)?
+            .into_iter()
+            .map(bytes_to_string)
+            .collect()
+    }
+
+    /// 按 key + version 前缀遍历 hash 的数据部分
+    fn hash_entries(&self, key: &[u8]) -> Result<Vec<(Bytes, Bytes)>> {
+        let meta = self.find_metadata(key, RedisDataType::Hash)?;
+        if meta.size == 0 {
+            return Ok(Vec::new());
+        }
+
+        let iter = self.eng.iter(IteratorOptions {
+            prefix: internal_key_prefix(key, meta.version),
+            ..Default::default()
+        });
+
+        let mut entries = Vec::with_capacity(meta.size as usize);
+        while let Some((internal_key, value)) = iter.next()? {
+            let internal_key = HashInternalKey::decode(key, &internal_key);
+            entries.push((Bytes::from(internal_key.field), value));
+        }
+        Ok(entries)
+    }
 }
 
 #[cfg(test)]
@@ -372,4 +416,44 @@ mod tests {
 
         clean(name);
     }
+
+    #[test]
+    fn test_hash_enumerate() {
+        let name = "enumerate";
+        let (rds, _) = setup(name);
+
+        assert_eq!(rds.hlen("myhash").unwrap(), 0);
+        assert!(rds.hgetall("myhash").unwrap().is_empty());
+        assert!(!rds.hexists("myhash", "field1").unwrap());
+
+        rds.hset("myhash", "field2", "value2").unwrap();
+        rds.hset("myhash", "field1", "value1").unwrap();
+        rds.hset("myhash", "field3", "value3").unwrap();
+        rds.hset("myhash", "field1", "value1-new").unwrap();
+        rds.hdel("myhash", "field3").unwrap();
+        // 以 key 开头的其他 hash 不会被遍历到
+        rds.hset("myhash2", "field", "value").unwrap();
+
+        assert_eq!(rds.hlen("myhash").unwrap(), 2);
+        assert!(rds.hexists("myhash", "field1").unwrap());
+        assert!(!rds.hexists("myhash", "field3").unwrap());
+        assert_eq!(
+            rds.hgetall("myhash").unwrap(),
+            vec![
+                ("field1".to_string(), "value1-new".to_string()),
+                ("field2".to_string(), "value2".to_string()),
+            ]
+        );
+        assert_eq!(rds.hkeys("myhash").unwrap(), vec!["field1", "field2"]);
+        assert_eq!(rds.hvals("myhash").unwrap(), vec!["value1-new", "value2"]);
+
+        rds.set("string", std::time::Duration::ZERO, "value")
+            .unwrap();
+        assert!(matches!(
+            rds.hkeys("string"),
+            Err(Errors::WrongTypeOperation { .. })
+        ));
+
+        clean(name);
+    }
 }
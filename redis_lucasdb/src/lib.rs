@@ -1,13 +1,25 @@
 use bytes::Bytes;
 
+pub mod bitmap;
+pub mod clock;
+pub mod config;
+pub mod databases;
 pub mod generic;
 pub mod hash;
 pub mod list;
 pub(crate) mod metadata;
+pub mod metrics;
+pub mod monitor;
 pub mod set;
+pub mod stream;
 pub mod string;
 pub mod types;
 pub mod zset;
+/// 返回 String 的便捷方法使用, 不是 utf8 时返回错误
+pub(crate) fn bytes_to_string(buf: Bytes) -> lucasdb::errors::Result<String> {
+    Ok(String::from_utf8(buf.to_vec())?)
+}
+
 pub(crate) trait EncodeAndDecode {
     fn encode(&self) -> Bytes;
     fn decode(buf: &mut Bytes) -> Self;
@@ -1,7 +1,12 @@
-use bytes::{BufMut, Bytes, BytesMut};
-use lucasdb::{errors::Result, options::WriteBatchOptions};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use lucasdb::{
+    errors::{Errors, Result},
+    options::{IteratorOptions, WriteBatchOptions},
+};
 
 use crate::{
+    bytes_to_string,
+    metadata::{internal_key_prefix, InternalKey},
     types::{RedisDataType, RedisLucasDb},
     EncodeAndDecode,
 };
@@ -12,43 +17,65 @@ pub(crate) struct ListInternalKey {
     pub(crate) index: u64,
 }
 
-impl EncodeAndDecode for ListInternalKey {
-    fn encode(&self) -> bytes::Bytes {
-        let mut buf = BytesMut::new();
-        buf.extend_from_slice(&self.key);
-        buf.put_u128(self.version);
+impl InternalKey for ListInternalKey {
+    /// 编码格式: key + version + index
+    fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::from(internal_key_prefix(&self.key, self.version).as_slice());
         buf.put_u64(self.index);
         buf.into()
     }
 
-    fn decode(buf: &mut bytes::Bytes) -> Self {
-        todo!()
+    fn decode(key: &[u8], buf: &[u8]) -> Self {
+        let mut buf = &buf[key.len()..];
+        ListInternalKey {
+            key: key.to_vec(),
+            version: buf.get_u128(),
+            index: buf.get_u64(),
+        }
     }
 }
 
 impl RedisLucasDb {
     /// 从list前面push一个element,返回key下有多少个数据
-    pub fn lpush(&self, key: &str, element: &str) -> Result<u32> {
+    pub fn lpush(&self, key: impl AsRef<[u8]>, element: impl AsRef<[u8]>) -> Result<u32> {
         self.inner_push(key, element, true)
     }
 
-    pub fn rpush(&self, key: &str, element: &str) -> Result<u32> {
+    pub fn rpush(&self, key: impl AsRef<[u8]>, element: impl AsRef<[u8]>) -> Result<u32> {
         self.inner_push(key, element, false)
     }
 
-    pub fn lpop(&self, key: &str) -> Result<Option<String>> {
+    pub fn lpop_bytes(&self, key: impl AsRef<[u8]>) -> Result<Option<Bytes>> {
         self.inner_pop(key, true)
     }
 
-    pub fn rpop(&self, key: &str) -> Result<Option<String>> {
+    /// 见`lpop_bytes`, element 不是 utf8 时返回错误
+    pub fn lpop(&self, key: impl AsRef<[u8]>) -> Result<Option<String>> {
+        self.lpop_bytes(key)?.map(bytes_to_string).transpose()
+    }
+
+    pub fn rpop_bytes(&self, key: impl AsRef<[u8]>) -> Result<Option<Bytes>> {
         self.inner_pop(key, false)
     }
 
-    pub fn inner_push(&self, key: &str, element: &str, is_left_push: bool) -> Result<u32> {
+    /// 见`rpop_bytes`, element 不是 utf8 时返回错误
+    pub fn rpop(&self, key: impl AsRef<[u8]>) -> Result<Option<String>> {
+        self.rpop_bytes(key)?.map(bytes_to_string).transpose()
+    }
+
+    pub fn inner_push(
+        &self,
+        key: impl AsRef<[u8]>,
+        element: impl AsRef<[u8]>,
+        is_left_push: bool,
+    ) -> Result<u32> {
+        let key = key.as_ref();
+        let element = element.as_ref();
+        let _guard = self.lock_key(key);
         let mut meta = self.find_or_new_metadata(key, RedisDataType::List)?;
 
         let internal_key = ListInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
             index: match is_left_push {
                 true => meta.head - 1,
@@ -64,17 +91,16 @@ impl RedisLucasDb {
             meta.tail += 1;
         }
         let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
-        wb.put(Bytes::copy_from_slice(key.as_bytes()), meta.encode())?;
-        wb.put(
-            internal_key.encode(),
-            Bytes::copy_from_slice(element.as_bytes()),
-        )?;
+        wb.put(Bytes::copy_from_slice(key), meta.encode())?;
+        wb.put(internal_key.encode(), Bytes::copy_from_slice(element))?;
         wb.commit()?;
 
         Ok(meta.size)
     }
 
-    pub fn inner_pop(&self, key: &str, is_left_pop: bool) -> Result<Option<String>> {
+    pub fn inner_pop(&self, key: impl AsRef<[u8]>, is_left_pop: bool) -> Result<Option<Bytes>> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
         let mut meta = self.find_or_new_metadata(key, RedisDataType::List)?;
 
         if meta.size == 0 {
@@ -82,7 +108,7 @@ impl RedisLucasDb {
         }
 
         let internal_key = ListInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
             index: match is_left_pop {
                 true => meta.head,
@@ -102,12 +128,99 @@ impl RedisLucasDb {
 
         {
             let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
-            wb.put(Bytes::copy_from_slice(key.as_bytes()), meta.encode())?;
+            wb.put(Bytes::copy_from_slice(key), meta.encode())?;
             wb.delete(internal_key.encode())?;
             wb.commit()?;
         }
 
-        Ok(Some(String::from_utf8(element.to_vec())?))
+        Ok(Some(element))
+    }
+
+    /// list 的长度, key 不存在时返回0
+    pub fn llen(&self, key: impl AsRef<[u8]>) -> Result<u32> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::List)?;
+        Ok(meta.size)
+    }
+
+    /// 返回下标为`index`的元素, 负数下标表示从末尾开始, -1 是最后一个\
+    /// 下标越界时返回None
+    pub fn lindex_bytes(&self, key: impl AsRef<[u8]>, index: i64) -> Result<Option<Bytes>> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::List)?;
+        let size = meta.size as i64;
+        let index = if index < 0 { size + index } else { index };
+        if index < 0 || index >= size {
+            return Ok(None);
+        }
+
+        let internal_key = ListInternalKey {
+            key: key.to_vec(),
+            version: meta.version,
+            index: meta.head + index as u64,
+        };
+        match self.eng.get(internal_key.encode()) {
+            Ok(element) => Ok(Some(element)),
+            Err(Errors::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 见`lindex_bytes`
+    pub fn lindex(&self, key: impl AsRef<[u8]>, index: i64) -> Result<Option<String>> {
+        self.lindex_bytes(key, index)?
+            .map(bytes_to_string)
+            .transpose()
+    }
+
+    /// 返回下标在 [start, stop] 之间的元素, 负数下标表示从末尾开始\
+    /// 元素的下标是连续的, 从`start`对应的内部key开始顺序遍历即可
+    pub fn lrange_bytes(&self, key: impl AsRef<[u8]>, start: i64, stop: i64) -> Result<Vec<Bytes>> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::List)?;
+        let size = meta.size as i64;
+        let start = if start < 0 {
+            (size + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            size + stop
+        } else {
+            stop.min(size - 1)
+        };
+        if meta.size == 0 || start > stop {
+            return Ok(Vec::new());
+        }
+
+        let iter = self.eng.iter(IteratorOptions {
+            prefix: internal_key_prefix(key, meta.version),
+            ..Default::default()
+        });
+        let first = ListInternalKey {
+            key: key.to_vec(),
+            version: meta.version,
+            index: meta.head + start as u64,
+        };
+        iter.seek(first.encode().to_vec());
+
+        let count = (stop - start + 1) as usize;
+        let mut elements = Vec::with_capacity(count);
+        while elements.len() < count {
+            match iter.next()? {
+                Some((_, element)) => elements.push(element),
+                None => break,
+            }
+        }
+        Ok(elements)
+    }
+
+    /// 见`lrange_bytes`
+    pub fn lrange(&self, key: impl AsRef<[u8]>, start: i64, stop: i64) -> Result<Vec<String>> {
+        self.lrange_bytes(key, start, stop)?
+            .into_iter()
+            .map(bytes_to_string)
+            .collect()
     }
 }
 
@@ -268,4 +381,69 @@ mod tests {
         }
         clean(name);
     }
+
+    #[test]
+    fn test_list_lrange_llen_lindex() {
+        let name = "lrange";
+        let (db, _) = setup(name);
+
+        assert_eq!(db.llen("key").unwrap(), 0);
+        assert!(db.lrange("key", 0, -1).unwrap().is_empty());
+        assert!(db.lindex("key", 0).unwrap().is_none());
+
+        // 结果: element-0 .. element-5
+        for i in (0..3).rev() {
+            db.lpush("key", &format!("element-{}", i)).unwrap();
+        }
+        for i in 3..6 {
+            db.rpush("key", &format!("element-{}", i)).unwrap();
+        }
+        // 以 key 开头的其他 list 不会被遍历到
+        db.rpush("key-2", "other").unwrap();
+
+        assert_eq!(db.llen("key").unwrap(), 6);
+        assert_eq!(db.lindex("key", 0).unwrap(), Some("element-0".to_string()));
+        assert_eq!(db.lindex("key", 4).unwrap(), Some("element-4".to_string()));
+        assert_eq!(db.lindex("key", -1).unwrap(), Some("element-5".to_string()));
+        assert!(db.lindex("key", 6).unwrap().is_none());
+        assert!(db.lindex("key", -7).unwrap().is_none());
+
+        let all: Vec<String> = (0..6).map(|i| format!("element-{}", i)).collect();
+        assert_eq!(db.lrange("key", 0, -1).unwrap(), all);
+        assert_eq!(db.lrange("key", 2, 3).unwrap(), all[2..4]);
+        assert_eq!(db.lrange("key", -2, 100).unwrap(), all[4..]);
+        assert_eq!(db.lrange("key", -100, 0).unwrap(), all[..1]);
+        assert!(db.lrange("key", 4, 2).unwrap().is_empty());
+        assert!(db.lrange("key", 6, 10).unwrap().is_empty());
+
+        // pop 之后下标从新的头部开始计算
+        db.lpop("key").unwrap();
+        db.rpop("key").unwrap();
+        assert_eq!(db.lindex("key", 0).unwrap(), Some("element-1".to_string()));
+        assert_eq!(db.lrange("key", 0, -1).unwrap(), all[1..5]);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_list_concurrent_push() {
+        let name = "concurrent_push";
+        let (db, _) = setup(name);
+
+        // 不同线程同时往同一个list写入, 元数据不会被覆盖
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let db = &db;
+                s.spawn(move || {
+                    for i in 0..50 {
+                        db.rpush("key", &format!("element-{}-{}", t, i)).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(db.llen("key").unwrap(), 200);
+        assert_eq!(db.lrange("key", 0, -1).unwrap().len(), 200);
+
+        clean(name);
+    }
 }
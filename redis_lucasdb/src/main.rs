@@ -1,40 +1,198 @@
-use lucasdb::errors::Result;
-use std::{collections::HashMap, sync::Mutex, time::Duration};
+use bytes::Bytes;
+use lucasdb::errors::{Errors, Result};
+use std::{
+    collections::HashMap,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
 
-use lucasdb::options::EngineOptions;
-use redis_lucasdb::types::RedisLucasDb;
-const SERVER_ADDR: &str = "0.0.0.0:56379";
+use clap::Parser;
+use redis_lucasdb::{
+    config::{ServerArgs, ServerConfig},
+    databases::{Databases, DEFAULT_DATABASES},
+    metrics::CommandMetrics,
+    monitor::Monitor,
+    stream::StreamId,
+    types::RedisLucasDb,
+};
 
-type CmdHandler = dyn Fn(&mut redcon::Conn, Vec<Vec<u8>>, &Mutex<RedisLucasDb>);
+/// 服务端共享的状态
+struct ServerState {
+    config: ServerConfig,
+    dbs: Databases,
+    metrics: CommandMetrics,
+    monitor: Monitor,
+}
 
-fn init_cmd_handler() -> HashMap<&'static str, Box<CmdHandler>> {
-    let mut supported_commands = HashMap::new();
+type CmdHandler = fn(&mut redcon::Conn, Vec<Vec<u8>>, &RedisLucasDb);
 
-    {
-        supported_commands.insert("set", Box::new(set) as Box<CmdHandler>);
-        supported_commands.insert("get", Box::new(get) as Box<CmdHandler>);
-        supported_commands.insert("hset", Box::new(hset) as Box<CmdHandler>);
-        supported_commands.insert("sadd", Box::new(sadd) as Box<CmdHandler>);
-        supported_commands.insert("lpush", Box::new(lpush) as Box<CmdHandler>);
-        supported_commands.insert("rpush", Box::new(rpush) as Box<CmdHandler>);
-        supported_commands.insert("zadd", Box::new(zadd) as Box<CmdHandler>);
+/// 命令表中的一项
+struct Command {
+    name: &'static str,
+    /// 参数的数量, 包括命令名本身, 负数表示至少需要这么多个
+    arity: i32,
+    handler: CmdHandler,
+}
+
+const fn cmd(name: &'static str, arity: i32, handler: CmdHandler) -> Command {
+    Command {
+        name,
+        arity,
+        handler,
     }
+}
 
-    supported_commands
+/// 所有在`RedisLucasDb`上执行的命令, auth/monitor/info/select/flushall 需要服务端的状态, 单独处理
+const COMMANDS: &[Command] = &[
+    // generic
+    cmd("del", -2, del),
+    cmd("type", 2, key_type),
+    cmd("keys", 2, keys),
+    cmd("scan", -2, scan),
+    cmd("expire", 3, expire),
+    cmd("pexpire", 3, pexpire),
+    cmd("expireat", 3, expireat),
+    cmd("pexpireat", 3, pexpireat),
+    cmd("ttl", 2, ttl),
+    cmd("pttl", 2, pttl),
+    cmd("persist", 2, persist),
+    cmd("dbsize", 1, dbsize),
+    cmd("flushdb", 1, flushdb),
+    // string
+    cmd("set", 3, set),
+    cmd("get", 2, get),
+    cmd("setnx", 3, setnx),
+    cmd("mset", -3, mset),
+    cmd("mget", -2, mget),
+    cmd("incr", 2, incr),
+    cmd("decr", 2, decr),
+    cmd("incrby", 3, incrby),
+    // bitmap
+    cmd("setbit", 4, setbit),
+    cmd("getbit", 3, getbit),
+    cmd("bitcount", -2, bitcount),
+    // stream
+    cmd("xadd", -5, xadd),
+    cmd("xrange", -4, xrange),
+    cmd("xlen", 2, xlen),
+    // hyperloglog
+    cmd("pfadd", -2, pfadd),
+    cmd("pfcount", -2, pfcount),
+    // hash
+    cmd("hset", 4, hset),
+    cmd("hget", 3, hget),
+    cmd("hdel", 3, hdel),
+    cmd("hgetall", 2, hgetall),
+    cmd("hkeys", 2, hkeys),
+    cmd("hvals", 2, hvals),
+    cmd("hlen", 2, hlen),
+    cmd("hexists", 3, hexists),
+    // set
+    cmd("sadd", 3, sadd),
+    cmd("sismember", 3, sismember),
+    cmd("srem", 3, srem),
+    cmd("smembers", 2, smembers),
+    cmd("scard", 2, scard),
+    cmd("spop", 2, spop),
+    // list
+    cmd("lpush", 3, lpush),
+    cmd("rpush", 3, rpush),
+    cmd("lpop", 2, lpop),
+    cmd("rpop", 2, rpop),
+    cmd("llen", 2, llen),
+    cmd("lindex", 3, lindex),
+    cmd("lrange", 4, lrange),
+    // zset
+    cmd("zadd", 4, zadd),
+    cmd("zscore", 3, zscore),
+    cmd("zrange", -4, zrange),
+    cmd("zrangebyscore", -4, zrangebyscore),
+    cmd("zcard", 2, zcard),
+    cmd("zrem", -3, zrem),
+    cmd("zincrby", 4, zincrby),
+    cmd("zrank", 3, zrank),
+];
+
+/// 按命令名查找命令, 查找表只在第一次使用时创建
+fn find_command(name: &str) -> Option<&'static Command> {
+    static TABLE: OnceLock<HashMap<&'static str, &'static Command>> = OnceLock::new();
+    TABLE
+        .get_or_init(|| COMMANDS.iter().map(|cmd| (cmd.name, cmd)).collect())
+        .get(name)
+        .copied()
+}
+
+/// 检查参数数量是否符合命令表中的`arity`
+fn check_arity(cmd: &Command, argc: usize) -> bool {
+    let argc = argc as i32;
+    if cmd.arity >= 0 {
+        argc == cmd.arity
+    } else {
+        argc >= -cmd.arity
+    }
 }
 
 fn main() -> Result<()> {
-    let rds = Mutex::new(RedisLucasDb::new(EngineOptions::default())?);
+    let config = match ServerConfig::load(ServerArgs::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let addr = config.addr.clone();
+    let state = ServerState {
+        dbs: Databases::new(config.engine.clone(), DEFAULT_DATABASES),
+        config,
+        metrics: CommandMetrics::default(),
+        monitor: Monitor::default(),
+    };
 
-    let mut lucasdb_server = redcon::listen(SERVER_ADDR, rds).expect("failed to listen addr");
+    let mut lucasdb_server = redcon::listen(addr, state).expect("failed to listen addr");
 
-    lucasdb_server.command = Some(|conn, rds, args| {
+    lucasdb_server.command = Some(|conn, state, args| {
         let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+        // AUTH 的参数包含密码, 不转发给 monitor
+        if name == "auth" {
+            return auth(conn, args, state);
+        }
+        if state.config.requirepass.is_some() && !conn_state(conn).authenticated {
+            return conn.write_error("NOAUTH Authentication required.");
+        }
+        if !state.config.acl.is_allowed(&name) {
+            return conn.write_error(&format!(
+                "NOPERM this user has no permissions to run the '{}' command",
+                name
+            ));
+        }
 
-        let supported_commands = init_cmd_handler();
+        let db_index = selected_db(conn);
+        state.monitor.feed(db_index, conn.addr(), &args);
+
+        match name.as_str() {
+            "monitor" => return monitor(conn, state),
+            "info" => return info(conn, args, state),
+            "select" => return select(conn, args, state),
+            "flushall" => return flushall(conn, args, state),
+            _ => {}
+        }
 
-        match supported_commands.get(name.as_str()) {
-            Some(handler) => handler(conn, args, rds),
+        match find_command(&name) {
+            Some(cmd) => {
+                if !check_arity(cmd, args.len()) {
+                    return conn.write_error(&format!(
+                        "ERR wrong number of arguments for '{}' command",
+                        cmd.name
+                    ));
+                }
+                let rds = match state.dbs.get(db_index) {
+                    Ok(rds) => rds,
+                    Err(e) => return conn.write_error(&format!("ERR {}", e)),
+                };
+                let start = Instant::now();
+                (cmd.handler)(conn, args, &rds);
+                state.metrics.record(&name, start.elapsed());
+            }
             None => conn.write_error("ERR unknown command"),
         }
     });
@@ -44,19 +202,280 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn set(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {
-    println!("set");
-    if args.len() != 3 {
-        conn.write_error("Err wrong number of arguments");
+/// 每个连接自己的状态, 保存在`conn.context`中
+#[derive(Debug, Clone, Copy, Default)]
+struct ConnState {
+    /// 选择的数据库, 默认是0号
+    db: usize,
+    /// 是否已经通过`AUTH`认证
+    authenticated: bool,
+}
+
+fn conn_state(conn: &redcon::Conn) -> ConnState {
+    conn.context
+        .as_ref()
+        .and_then(|ctx| ctx.downcast_ref::<ConnState>())
+        .copied()
+        .unwrap_or_default()
+}
+
+/// 当前连接选择的数据库
+fn selected_db(conn: &redcon::Conn) -> usize {
+    conn_state(conn).db
+}
+
+/// `AUTH [username] password`, 只支持默认用户`default`
+fn auth(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, state: &ServerState) {
+    let password = match args.len() {
+        2 => &args[1],
+        3 if args[1].eq_ignore_ascii_case(b"default") => &args[2],
+        3 => {
+            conn.write_error("WRONGPASS invalid username-password pair or user is disabled.");
+            return;
+        }
+        _ => {
+            conn.write_error("ERR wrong number of arguments for 'auth' command");
+            return;
+        }
+    };
+
+    if state.config.requirepass.is_none() {
+        conn.write_error(
+            "ERR AUTH <password> called without any password configured for the default user.",
+        );
+        return;
+    }
+
+    let mut conn_state = conn_state(conn);
+    conn_state.authenticated = state.config.check_password(password);
+    conn.context = Some(Box::new(conn_state));
+    if conn_state.authenticated {
+        conn.write_string("OK");
+    } else {
+        conn.write_error("WRONGPASS invalid username-password pair or user is disabled.");
+    }
+}
+
+fn select(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, state: &ServerState) {
+    if args.len() != 2 {
+        conn.write_error("ERR wrong number of arguments");
+        return;
+    }
+
+    let index = match String::from_utf8_lossy(&args[1]).parse::<usize>() {
+        Ok(index) => index,
+        Err(_) => {
+            conn.write_error("ERR value is not an integer or out of range");
+            return;
+        }
+    };
+
+    match state.dbs.get(index) {
+        Ok(_) => {
+            let mut conn_state = conn_state(conn);
+            conn_state.db = index;
+            conn.context = Some(Box::new(conn_state));
+            conn.write_string("OK");
+        }
+        Err(e) => conn.write_error(&format!("ERR {}", e)),
+    }
+}
+
+/// `FLUSHALL [ASYNC|SYNC]`, 清空所有逻辑数据库, 总是同步执行
+fn flushall(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, state: &ServerState) {
+    match args
+        .get(1)
+        .map(|mode| String::from_utf8_lossy(mode).to_lowercase())
+    {
+        None => {}
+        Some(mode) if args.len() == 2 && (mode == "async" || mode == "sync") => {}
+        Some(_) => return conn.write_error("ERR syntax error"),
+    }
+
+    match state.dbs.flushall() {
+        Ok(_) => conn.write_string("OK"),
+        Err(e) => conn.write_error(&format!("ERR {}", e)),
+    }
+}
+
+fn dbsize(conn: &mut redcon::Conn, _args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.dbsize() {
+        Ok(size) => conn.write_integer(size as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn flushdb(conn: &mut redcon::Conn, _args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.flushdb() {
+        Ok(_) => conn.write_string("OK"),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `DEL key [key ...]`, 返回删除的key的数量
+fn del(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let mut deleted = 0;
+    for key in &args[1..] {
+        match rds.del(key) {
+            Ok(true) => deleted += 1,
+            Ok(false) => {}
+            Err(e) => return conn.write_error(e.to_string().as_str()),
+        }
+    }
+    conn.write_integer(deleted);
+}
+
+fn key_type(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.key_type(&args[1]) {
+        Ok(data_type) => conn.write_string(&data_type.to_string().to_lowercase()),
+        Err(Errors::KeyNotFound) => conn.write_string("none"),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn keys(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.keys_bytes(&args[1]) {
+        Ok(keys) => write_bulk_array(conn, &keys),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `SCAN cursor [MATCH pattern] [COUNT count]`
+fn scan(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    if args.len() < 2 || args.len() % 2 != 0 {
+        conn.write_error("ERR wrong number of arguments");
         return;
     }
 
-    let rds = rds.lock().unwrap();
-    let res = rds.set(
-        &String::from_utf8_lossy(&args[1]),
-        Duration::ZERO,
-        &String::from_utf8_lossy(&args[2]),
-    );
+    let cursor = match String::from_utf8_lossy(&args[1]).parse::<u64>() {
+        Ok(cursor) => cursor,
+        Err(_) => return conn.write_error("ERR invalid cursor"),
+    };
+
+    let mut pattern = None;
+    let mut count = 10;
+    for option in args[2..].chunks(2) {
+        let value = &option[1];
+        match String::from_utf8_lossy(&option[0]).to_lowercase().as_str() {
+            "match" => pattern = Some(value.as_slice()),
+            "count" => match String::from_utf8_lossy(value).parse::<usize>() {
+                Ok(n) if n > 0 => count = n,
+                _ => return conn.write_error("ERR value is not an integer or out of range"),
+            },
+            _ => return conn.write_error("ERR syntax error"),
+        }
+    }
+
+    match rds.scan_bytes(cursor, pattern, count) {
+        Ok((next_cursor, keys)) => {
+            conn.write_array(2);
+            conn.write_bulk(next_cursor.to_string().as_bytes());
+            write_bulk_array(conn, &keys);
+        }
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `EXPIRE key seconds`, seconds 不大于0时删除key
+fn expire(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    set_expire(conn, &args, rds, |rds, key, n| {
+        rds.expire(key, Duration::from_secs(n))
+    });
+}
+
+/// `PEXPIRE key milliseconds`
+fn pexpire(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    set_expire(conn, &args, rds, |rds, key, n| {
+        rds.expire(key, Duration::from_millis(n))
+    });
+}
+
+/// `EXPIREAT key unix-time-seconds`, 时间已经过去时删除key
+fn expireat(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    set_expire(conn, &args, rds, |rds, key, n| {
+        rds.expire_at(key, Duration::from_secs(n))
+    });
+}
+
+/// `PEXPIREAT key unix-time-milliseconds`
+fn pexpireat(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    set_expire(conn, &args, rds, |rds, key, n| {
+        rds.expire_at(key, Duration::from_millis(n))
+    });
+}
+
+/// 解析 expire 系列命令的整数参数, 负数按0处理
+fn set_expire(
+    conn: &mut redcon::Conn,
+    args: &[Vec<u8>],
+    rds: &RedisLucasDb,
+    f: impl FnOnce(&RedisLucasDb, &[u8], u64) -> Result<bool>,
+) {
+    let n = match String::from_utf8_lossy(&args[2]).parse::<i64>() {
+        Ok(n) => n,
+        Err(_) => {
+            conn.write_error("ERR value is not an integer or out of range");
+            return;
+        }
+    };
+
+    match f(rds, &args[1], n.max(0) as u64) {
+        Ok(val) => conn.write_integer(val as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn ttl(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.ttl(&args[1]) {
+        Ok(val) => conn.write_integer(val),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn pttl(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.pttl(&args[1]) {
+        Ok(val) => conn.write_integer(val),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn persist(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.persist(&args[1]) {
+        Ok(val) => conn.write_integer(val as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// 把之后收到的所有命令发送给当前连接, 直到连接断开
+fn monitor(conn: &mut redcon::Conn, state: &ServerState) {
+    let receiver = state.monitor.subscribe();
+    conn.write_string("OK");
+    while let Ok(line) = receiver.recv() {
+        conn.write_string(&line);
+    }
+}
+
+/// 目前只支持 `INFO commandstats` 和 `INFO resetstats`
+fn info(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, state: &ServerState) {
+    let section = args
+        .get(1)
+        .map(|v| String::from_utf8_lossy(v).to_lowercase())
+        .unwrap_or_else(|| "commandstats".to_string());
+
+    match section.as_str() {
+        "commandstats" | "all" | "everything" => {
+            conn.write_bulk(state.metrics.info().as_bytes());
+        }
+        "resetstats" => {
+            state.metrics.reset();
+            conn.write_string("OK");
+        }
+        _ => conn.write_bulk(b""),
+    }
+}
+
+fn set(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let res = rds.set(&args[1], Duration::ZERO, &args[2]);
 
     if res.is_err() {
         conn.write_error(&res.err().unwrap().to_string());
@@ -66,100 +485,567 @@ fn set(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {
     conn.write_string("OK");
 }
 
-fn get(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {
-    println!("get");
+fn get(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let res = rds.get_bytes(&args[1]);
 
-    if args.len() != 2 {
-        conn.write_error("Err wrong number of arguments");
+    match res {
+        Ok(Some(val)) => conn.write_bulk(&val),
+        Ok(None) | Err(Errors::KeyNotFound) => conn.write_null(),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn setnx(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let value = &args[2];
+    match rds.setnx(key, value) {
+        Ok(val) => conn.write_integer(val as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `MSET key value [key value ...]`
+fn mset(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    if args.len() < 3 || args.len() % 2 != 1 {
+        conn.write_error("ERR wrong number of arguments");
         return;
     }
 
-    let rds = rds.lock().unwrap();
-    let res = rds.get(&String::from_utf8_lossy(&args[1]));
+    let pairs: Vec<(&[u8], &[u8])> = args[1..]
+        .chunks(2)
+        .map(|pair| (pair[0].as_slice(), pair[1].as_slice()))
+        .collect();
 
-    match res {
-        Ok(val) => {
-            conn.write_string(val.unwrap().as_str());
+    match rds.mset(&pairs) {
+        Ok(_) => conn.write_string("OK"),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `MGET key [key ...]`
+fn mget(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.mget_bytes(&args[1..]) {
+        Ok(values) => {
+            conn.write_array(values.len());
+            for value in values {
+                match value {
+                    Some(value) => conn.write_bulk(&value),
+                    None => conn.write_null(),
+                }
+            }
         }
         Err(e) => conn.write_error(e.to_string().as_str()),
     }
 }
 
-fn hget(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {}
+fn incr(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.incr(&args[1]) {
+        Ok(val) => conn.write_integer(val),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn decr(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.decr(&args[1]) {
+        Ok(val) => conn.write_integer(val),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
 
-fn hset(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {
-    if args.len() != 3 {
-        conn.write_error("Err wrong number of arguments");
-        return;
+fn incrby(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let delta = match String::from_utf8_lossy(&args[2]).parse::<i64>() {
+        Ok(delta) => delta,
+        Err(_) => return conn.write_error("ERR value is not an integer or out of range"),
+    };
+
+    match rds.incrby(&args[1], delta) {
+        Ok(val) => conn.write_integer(val),
+        Err(e) => conn.write_error(e.to_string().as_str()),
     }
+}
 
-    let rds = rds.lock().unwrap();
-    let key = String::from_utf8_lossy(&args[1]);
-    let field = String::from_utf8_lossy(&args[2]);
-    let value = String::from_utf8_lossy(&args[3]);
-    match rds.hset(&key, &field, &value) {
+fn hget(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let field = &args[2];
+    match rds.hget_bytes(key, field) {
+        Ok(Some(val)) => conn.write_bulk(&val),
+        Ok(None) | Err(Errors::KeyNotFound) => conn.write_null(),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn hdel(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let field = &args[2];
+    match rds.hdel(key, field) {
         Ok(val) => conn.write_integer(val as i64),
+        Err(Errors::KeyNotFound) => conn.write_integer(0),
         Err(e) => conn.write_error(e.to_string().as_str()),
     }
 }
 
-fn sadd(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {
-    if args.len() != 3 {
-        conn.write_error("Err wrong number of arguments");
-        return;
+fn hset(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let field = &args[2];
+    let value = &args[3];
+    match rds.hset(key, field, value) {
+        Ok(val) => conn.write_integer(val as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
     }
+}
 
-    let rds = rds.lock().unwrap();
-    let key = String::from_utf8_lossy(&args[1]);
-    let member = String::from_utf8_lossy(&args[2]);
-    match rds.sadd(&key, &member) {
+fn hgetall(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.hgetall_bytes(&args[1]) {
+        Ok(pairs) => {
+            conn.write_array(pairs.len() * 2);
+            for (field, value) in pairs {
+                conn.write_bulk(&field);
+                conn.write_bulk(&value);
+            }
+        }
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn hkeys(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.hkeys_bytes(&args[1]) {
+        Ok(fields) => write_bulk_array(conn, &fields),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn hvals(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.hvals_bytes(&args[1]) {
+        Ok(values) => write_bulk_array(conn, &values),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn hlen(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.hlen(&args[1]) {
+        Ok(len) => conn.write_integer(len as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn hexists(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let field = &args[2];
+    match rds.hexists(key, field) {
         Ok(val) => conn.write_integer(val as i64),
         Err(e) => conn.write_error(e.to_string().as_str()),
     }
 }
 
-fn lpush(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {
-    if args.len() != 3 {
-        conn.write_error("Err wrong number of arguments");
-        return;
+fn write_bulk_array(conn: &mut redcon::Conn, items: &[Bytes]) {
+    conn.write_array(items.len());
+    for item in items {
+        conn.write_bulk(&item);
     }
+}
 
-    let rds = rds.lock().unwrap();
-    let key = String::from_utf8_lossy(&args[1]);
-    let value = String::from_utf8_lossy(&args[2]);
-    match rds.lpush(&key, &value) {
+fn sadd(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let member = &args[2];
+    match rds.sadd(key, member) {
         Ok(val) => conn.write_integer(val as i64),
         Err(e) => conn.write_error(e.to_string().as_str()),
     }
 }
 
-fn rpush(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {
-    if args.len() != 3 {
-        conn.write_error("Err wrong number of arguments");
-        return;
+fn sismember(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let member = &args[2];
+    match rds.sismember(key, member) {
+        Ok(val) => conn.write_integer(val as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
     }
+}
 
-    let rds = rds.lock().unwrap();
-    let key = String::from_utf8_lossy(&args[1]);
-    let value = String::from_utf8_lossy(&args[2]);
-    match rds.rpush(&key, &value) {
+fn srem(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let member = &args[2];
+    match rds.srem(key, member) {
         Ok(val) => conn.write_integer(val as i64),
         Err(e) => conn.write_error(e.to_string().as_str()),
     }
 }
 
-fn zadd(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &Mutex<RedisLucasDb>) {
-    if args.len() != 4 {
-        conn.write_error("Err wrong number of arguments");
-        return;
+fn smembers(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.smembers_bytes(&args[1]) {
+        Ok(members) => write_bulk_array(conn, &members),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn scard(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.scard(&args[1]) {
+        Ok(len) => conn.write_integer(len as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn spop(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.spop_bytes(&args[1]) {
+        Ok(Some(member)) => conn.write_bulk(&member),
+        Ok(None) => conn.write_null(),
+        Err(e) => conn.write_error(e.to_string().as_str()),
     }
+}
+
+fn lpush(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let value = &args[2];
+    match rds.lpush(key, value) {
+        Ok(val) => conn.write_integer(val as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn rpush(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let value = &args[2];
+    match rds.rpush(key, value) {
+        Ok(val) => conn.write_integer(val as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn lpop(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.lpop_bytes(&args[1]) {
+        Ok(Some(element)) => conn.write_bulk(&element),
+        Ok(None) => conn.write_null(),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn rpop(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.rpop_bytes(&args[1]) {
+        Ok(Some(element)) => conn.write_bulk(&element),
+        Ok(None) => conn.write_null(),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn llen(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.llen(&args[1]) {
+        Ok(len) => conn.write_integer(len as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn lindex(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let index = match String::from_utf8_lossy(&args[2]).parse::<i64>() {
+        Ok(index) => index,
+        Err(_) => return conn.write_error("ERR value is not an integer or out of range"),
+    };
+
+    match rds.lindex_bytes(&args[1], index) {
+        Ok(Some(element)) => conn.write_bulk(&element),
+        Ok(None) => conn.write_null(),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn lrange(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let start = String::from_utf8_lossy(&args[2]).parse::<i64>();
+    let stop = String::from_utf8_lossy(&args[3]).parse::<i64>();
+    let (start, stop) = match (start, stop) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        _ => return conn.write_error("ERR value is not an integer or out of range"),
+    };
+
+    match rds.lrange_bytes(&args[1], start, stop) {
+        Ok(elements) => write_bulk_array(conn, &elements),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn zadd(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let score = match String::from_utf8_lossy(&args[2]).parse::<f64>() {
+        Ok(score) if !score.is_nan() => score,
+        _ => return conn.write_error("ERR value is not a valid float"),
+    };
+    let member = &args[3];
+    match rds.zadd(key, score, member) {
+        Ok(val) => conn.write_integer(val as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn zscore(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let key = &args[1];
+    let member = &args[2];
+    // key 不存在时`zscore`返回-1, 和负数分数无法区分
+    if let Err(Errors::KeyNotFound) = rds.key_type(key) {
+        return conn.write_null();
+    }
+    match rds.zscore(key, member) {
+        Ok(score) => conn.write_bulk(score.to_string().as_bytes()),
+        Err(Errors::KeyNotFound) => conn.write_null(),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn zcard(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.zcard(&args[1]) {
+        Ok(len) => conn.write_integer(len as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
 
-    let rds = rds.lock().unwrap();
-    let key = String::from_utf8_lossy(&args[1]);
-    let score = String::from_utf8_lossy(&args[2]);
-    let member = String::from_utf8_lossy(&args[3]);
-    match rds.zadd(&key, score.parse().unwrap(), &member) {
+fn zrem(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.zrem(&args[1], &args[2..]) {
         Ok(val) => conn.write_integer(val as i64),
         Err(e) => conn.write_error(e.to_string().as_str()),
     }
 }
+
+fn zincrby(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let increment = match String::from_utf8_lossy(&args[2]).parse::<f64>() {
+        Ok(increment) if !increment.is_nan() => increment,
+        _ => return conn.write_error("ERR value is not a valid float"),
+    };
+    match rds.zincrby(&args[1], increment, &args[3]) {
+        Ok(score) => conn.write_bulk(score.to_string().as_bytes()),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn zrank(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.zrank(&args[1], &args[2]) {
+        Ok(Some(rank)) => conn.write_integer(rank as i64),
+        Ok(None) => conn.write_null(),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `ZRANGE key start stop [WITHSCORES]`
+fn zrange(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let with_scores = match parse_with_scores(&args) {
+        Some(with_scores) => with_scores,
+        None => return conn.write_error("ERR wrong number of arguments"),
+    };
+
+    let start = String::from_utf8_lossy(&args[2]).parse::<i64>();
+    let stop = String::from_utf8_lossy(&args[3]).parse::<i64>();
+    let (start, stop) = match (start, stop) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        _ => return conn.write_error("ERR value is not an integer or out of range"),
+    };
+
+    match rds.zrange_bytes(&args[1], start, stop) {
+        Ok(members) => write_scored_members(conn, &members, with_scores),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `ZRANGEBYSCORE key min max [WITHSCORES]`, min 和 max 可以是 -inf/+inf
+fn zrangebyscore(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let with_scores = match parse_with_scores(&args) {
+        Some(with_scores) => with_scores,
+        None => return conn.write_error("ERR wrong number of arguments"),
+    };
+
+    let min = String::from_utf8_lossy(&args[2]).parse::<f64>();
+    let max = String::from_utf8_lossy(&args[3]).parse::<f64>();
+    let (min, max) = match (min, max) {
+        (Ok(min), Ok(max)) if !min.is_nan() && !max.is_nan() => (min, max),
+        _ => return conn.write_error("ERR min or max is not a float"),
+    };
+
+    match rds.zrangebyscore_bytes(&args[1], min, max) {
+        Ok(members) => write_scored_members(conn, &members, with_scores),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// 检查参数数量和最后的 WITHSCORES 选项, 参数不合法时返回None
+fn parse_with_scores(args: &[Vec<u8>]) -> Option<bool> {
+    match args.len() {
+        4 => Some(false),
+        5 if args[4].eq_ignore_ascii_case(b"withscores") => Some(true),
+        _ => None,
+    }
+}
+
+fn write_scored_members(conn: &mut redcon::Conn, members: &[(Bytes, f64)], with_scores: bool) {
+    conn.write_array(if with_scores {
+        members.len() * 2
+    } else {
+        members.len()
+    });
+    for (member, score) in members {
+        conn.write_bulk(&member);
+        if with_scores {
+            conn.write_bulk(score.to_string().as_bytes());
+        }
+    }
+}
+
+fn parse_bit_offset(arg: &[u8]) -> Option<u64> {
+    String::from_utf8_lossy(arg).parse::<u64>().ok()
+}
+
+/// `SETBIT key offset value`
+fn setbit(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let Some(offset) = parse_bit_offset(&args[2]) else {
+        conn.write_error("ERR bit offset is not an integer or out of range");
+        return;
+    };
+    let bit = match args[3].as_slice() {
+        b"0" => false,
+        b"1" => true,
+        _ => {
+            conn.write_error("ERR bit is not an integer or out of range");
+            return;
+        }
+    };
+
+    match rds.setbit(&args[1], offset, bit) {
+        Ok(old) => conn.write_integer(old as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `GETBIT key offset`
+fn getbit(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let Some(offset) = parse_bit_offset(&args[2]) else {
+        conn.write_error("ERR bit offset is not an integer or out of range");
+        return;
+    };
+
+    match rds.getbit(&args[1], offset) {
+        Ok(bit) => conn.write_integer(bit as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `BITCOUNT key [start end]`, start/end 是字节的下标
+fn bitcount(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let range = match args.len() {
+        2 => None,
+        4 => {
+            let start = String::from_utf8_lossy(&args[2]).parse::<i64>();
+            let end = String::from_utf8_lossy(&args[3]).parse::<i64>();
+            match (start, end) {
+                (Ok(start), Ok(end)) => Some((start, end)),
+                _ => {
+                    conn.write_error("ERR value is not an integer or out of range");
+                    return;
+                }
+            }
+        }
+        _ => {
+            conn.write_error("ERR syntax error");
+            return;
+        }
+    };
+
+    match rds.bitcount(&args[1], range) {
+        Ok(count) => conn.write_integer(count as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `PFADD key [element ...]`
+fn pfadd(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.pfadd(&args[1], &args[2..]) {
+        Ok(changed) => conn.write_integer(changed as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `PFCOUNT key [key ...]`
+fn pfcount(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.pfcount(&args[1..]) {
+        Ok(count) => conn.write_integer(count as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+/// `XADD key <* | id> field value [field value ...]`
+fn xadd(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    if args.len() % 2 == 0 {
+        conn.write_error("ERR wrong number of arguments for 'xadd' command");
+        return;
+    }
+    let id = match args[2].as_slice() {
+        b"*" => None,
+        id => match StreamId::parse(id, 0) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                conn.write_error(&format!("ERR {}", e));
+                return;
+            }
+        },
+    };
+    let fields: Vec<(&[u8], &[u8])> = args[3..]
+        .chunks(2)
+        .map(|pair| (pair[0].as_slice(), pair[1].as_slice()))
+        .collect();
+
+    match rds.xadd(&args[1], id, &fields) {
+        Ok(id) => conn.write_bulk(id.to_string().as_bytes()),
+        Err(e) => conn.write_error(&format!("ERR {}", e)),
+    }
+}
+
+/// `XRANGE key start end [COUNT count]`, `-`和`+`表示最小和最大的 ID
+fn xrange(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    let start = match args[2].as_slice() {
+        b"-" => Ok(StreamId::MIN),
+        id => StreamId::parse(id, 0),
+    };
+    let end = match args[3].as_slice() {
+        b"+" => Ok(StreamId::MAX),
+        id => StreamId::parse(id, u64::MAX),
+    };
+    let (start, end) = match (start, end) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => {
+            conn.write_error(&format!("ERR {}", e));
+            return;
+        }
+    };
+    let count = match &args[4..] {
+        [] => None,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => {
+            match String::from_utf8_lossy(count).parse::<usize>() {
+                Ok(count) => Some(count),
+                Err(_) => {
+                    conn.write_error("ERR value is not an integer or out of range");
+                    return;
+                }
+            }
+        }
+        _ => {
+            conn.write_error("ERR syntax error");
+            return;
+        }
+    };
+
+    match rds.xrange(&args[1], start, end, count) {
+        Ok(entries) => {
+            conn.write_array(entries.len());
+            for (id, fields) in entries {
+                conn.write_array(2);
+                conn.write_bulk(id.to_string().as_bytes());
+                conn.write_array(fields.len() * 2);
+                for (field, value) in fields {
+                    conn.write_bulk(&field);
+                    conn.write_bulk(&value);
+                }
+            }
+        }
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
+
+fn xlen(conn: &mut redcon::Conn, args: Vec<Vec<u8>>, rds: &RedisLucasDb) {
+    match rds.xlen(&args[1]) {
+        Ok(len) => conn.write_integer(len as i64),
+        Err(e) => conn.write_error(e.to_string().as_str()),
+    }
+}
@@ -1,6 +1,14 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use lucasdb::errors::{Errors, Result};
 
-use crate::{types::RedisDataType, EncodeAndDecode};
+use crate::{
+    generic::EXPIRE_RANGE,
+    types::{RedisDataType, RedisLucasDb},
+    EncodeAndDecode,
+};
+
+/// 新建 list 时 head/tail 的初始位置, 两边都可以 push
+const INITIAL_LIST_MARK: u64 = u64::MAX / 2;
 
 /// 元数据会编码作为一个`key`, 编码格式: \
 /// type + expire + version + size
@@ -33,8 +41,9 @@ impl EncodeAndDecode for Metadata {
         buf.into()
     }
 
+    /// 调用前需要用`decode_data_type`检查编码是否完整
     fn decode(buf: &mut Bytes) -> Self {
-        let data_type = RedisDataType::from(buf.get_u8());
+        let data_type = RedisDataType::try_from(buf.get_u8()).expect("invalid metadata");
         let expire = buf.get_u128();
         let version = buf.get_u128();
         let size = buf.get_u32();
@@ -58,3 +67,370 @@ impl EncodeAndDecode for Metadata {
         }
     }
 }
+
+/// hash/set/list/stream 内部数据的 key, 编码都以 key + version 开头, 同一个集合的数据可以按前缀遍历
+pub(crate) trait InternalKey: Sized {
+    fn encode(&self) -> Bytes;
+    /// `key`是所属集合的 key, 有的编码中没有记录`key`的长度, 需要传入才能解析
+    fn decode(key: &[u8], buf: &[u8]) -> Self;
+}
+
+/// 解析 string 或元数据编码开头的类型, 同时检查长度是否足够解码\
+/// 不是 redis 命令写入的 value(比如用户的 key 和集合的内部数据同名)返回 DeserializeFailed
+pub(crate) fn decode_data_type(value: &[u8]) -> Result<RedisDataType> {
+    let data_type = RedisDataType::try_from(value.first().copied().unwrap_or(u8::MAX))?;
+    let min_len = match data_type {
+        RedisDataType::String => EXPIRE_RANGE.end,
+        // type + expire + version + size, list 还有 head + tail
+        RedisDataType::List => 53,
+        _ => 37,
+    };
+    if value.len() < min_len {
+        return Err(Errors::DeserializeFailed(format!(
+            "{} value too short: {}",
+            data_type,
+            value.len()
+        )));
+    }
+    Ok(data_type)
+}
+
+/// 集合`key`在`version`下所有内部数据的公共前缀
+pub(crate) fn internal_key_prefix(key: &[u8], version: u128) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(key.len() + 16);
+    prefix.extend_from_slice(key);
+    prefix.extend_from_slice(&version.to_be_bytes());
+    prefix
+}
+
+impl RedisLucasDb {
+    /// 根据 key 查找元数据, 写入时使用, 调用方需要持有 key 的锁
+    /// 如果 key 不存在或已经过期,则创建一个新的元数据并返回, 过期的 key 和它的内部数据在这里删除
+    pub(crate) fn find_or_new_metadata(
+        &self,
+        key: &[u8],
+        data_type: RedisDataType,
+    ) -> Result<Metadata> {
+        self.tick_clock_marker()?;
+        match self.load_metadata(key, data_type)? {
+            Some((meta, false)) => Ok(meta),
+            Some((_, true)) => {
+                self.remove_key(key)?;
+                self.new_metadata(data_type)
+            }
+            None => self.new_metadata(data_type),
+        }
+    }
+
+    /// 和`find_or_new_metadata`一样, 读取时使用, 不需要持有 key 的锁
+    /// 发现 key 已经过期时获取锁并删除
+    pub(crate) fn find_metadata(&self, key: &[u8], data_type: RedisDataType) -> Result<Metadata> {
+        match self.load_metadata(key, data_type)? {
+            Some((meta, false)) => Ok(meta),
+            Some((_, true)) => {
+                self.purge_expired(key)?;
+                self.new_metadata(data_type)
+            }
+            None => self.new_metadata(data_type),
+        }
+    }
+
+    /// 读取元数据, 返回的 bool 表示是否已经过期
+    /// 过期的 key 不检查类型, 可以被其他类型覆盖
+    fn load_metadata(
+        &self,
+        key: &[u8],
+        data_type: RedisDataType,
+    ) -> Result<Option<(Metadata, bool)>> {
+        let mut meta_buf = match self.eng.get(Bytes::copy_from_slice(key)) {
+            Ok(meta_buf) => meta_buf,
+            Err(Errors::KeyNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        // 先检查编码是否完整, 再读取过期时间和类型
+        let meta_buf_data_type = decode_data_type(&meta_buf)?;
+        let expire = (&meta_buf[EXPIRE_RANGE]).get_u128();
+        if expire != 0 && expire <= self.now() {
+            return Ok(Some((self.new_metadata(data_type)?, true)));
+        }
+
+        if data_type != meta_buf_data_type {
+            return Err(Errors::WrongTypeOperation {
+                expected: data_type.to_string(),
+                actual: meta_buf_data_type.to_string(),
+            });
+        }
+        Ok(Some((Metadata::decode(&mut meta_buf), false)))
+    }
+
+    fn new_metadata(&self, data_type: RedisDataType) -> Result<Metadata> {
+        let mut metadata = Metadata {
+            data_type,
+            expire: 0,
+            version: self.now(),
+            size: 0,
+            head: 0,
+            tail: 0,
+        };
+
+        if data_type == RedisDataType::List {
+            metadata.head = INITIAL_LIST_MARK;
+            metadata.tail = INITIAL_LIST_MARK;
+        }
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use lucasdb::options::EngineOptions;
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+    use crate::{
+        clock::ManualClock,
+        hash::HashInternalKey,
+        list::ListInternalKey,
+        set::SetInternalKey,
+        stream::{StreamId, StreamInternalKey},
+    };
+
+    fn basepath() -> PathBuf {
+        "../tmp/redis_lucasdb/metadata".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_metadata_encode_decode() {
+        let hash = Metadata {
+            data_type: RedisDataType::Hash,
+            expire: 100,
+            version: 42,
+            size: 3,
+            head: 0,
+            tail: 0,
+        };
+        let decoded = Metadata::decode(&mut hash.encode());
+        assert_eq!(decoded.data_type, RedisDataType::Hash);
+        assert_eq!(decoded.expire, 100);
+        assert_eq!(decoded.version, 42);
+        assert_eq!(decoded.size, 3);
+
+        // 只有 list 记录 head/tail
+        let list = Metadata {
+            data_type: RedisDataType::List,
+            head: INITIAL_LIST_MARK - 1,
+            tail: INITIAL_LIST_MARK + 2,
+            ..hash
+        };
+        assert_eq!(list.encode().len(), hash.encode().len() + 16);
+        let decoded = Metadata::decode(&mut list.encode());
+        assert_eq!(decoded.head, INITIAL_LIST_MARK - 1);
+        assert_eq!(decoded.tail, INITIAL_LIST_MARK + 2);
+    }
+
+    #[test]
+    fn test_internal_key_encode_decode() {
+        // key 可以包含任意字节, 包括和 version 相同的内容
+        let key = b"lucas\x00\xff".as_slice();
+        let prefix = internal_key_prefix(key, 42);
+
+        let hash = HashInternalKey {
+            key: key.to_vec(),
+            version: 42,
+            field: b"field".to_vec(),
+        };
+        let buf = hash.encode();
+        assert!(buf.starts_with(&prefix));
+        let decoded = HashInternalKey::decode(key, &buf);
+        assert_eq!(decoded.version, 42);
+        assert_eq!(decoded.field, b"field");
+
+        let list = ListInternalKey {
+            key: key.to_vec(),
+            version: 42,
+            index: INITIAL_LIST_MARK,
+        };
+        let buf = list.encode();
+        assert!(buf.starts_with(&prefix));
+        let decoded = ListInternalKey::decode(key, &buf);
+        assert_eq!(decoded.key, key);
+        assert_eq!(decoded.version, 42);
+        assert_eq!(decoded.index, INITIAL_LIST_MARK);
+
+        // 空的 member
+        let set = SetInternalKey {
+            key: key.to_vec(),
+            version: 42,
+            member: Vec::new(),
+        };
+        let buf = set.encode();
+        assert!(buf.starts_with(&prefix));
+        let decoded = SetInternalKey::decode(key, &buf);
+        assert_eq!(decoded.version, 42);
+        assert!(decoded.member.is_empty());
+    }
+
+    fn data_type_strategy() -> impl Strategy<Value = RedisDataType> {
+        prop_oneof![
+            Just(RedisDataType::String),
+            Just(RedisDataType::Hash),
+            Just(RedisDataType::Set),
+            Just(RedisDataType::List),
+            Just(RedisDataType::ZSet),
+            Just(RedisDataType::Stream),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_metadata_round_trip(
+            data_type in data_type_strategy(),
+            expire in any::<u128>(),
+            version in any::<u128>(),
+            size in any::<u32>(),
+            head in any::<u64>(),
+            tail in any::<u64>(),
+        ) {
+            let meta = Metadata { data_type, expire, version, size, head, tail };
+            let decoded = Metadata::decode(&mut meta.encode());
+            prop_assert_eq!(decoded.data_type, data_type);
+            prop_assert_eq!(decoded.expire, expire);
+            prop_assert_eq!(decoded.version, version);
+            prop_assert_eq!(decoded.size, size);
+            // 只有 list 记录 head/tail
+            let (head, tail) = match data_type {
+                RedisDataType::List => (head, tail),
+                _ => (0, 0),
+            };
+            prop_assert_eq!((decoded.head, decoded.tail), (head, tail));
+        }
+
+        #[test]
+        fn proptest_internal_key_round_trip(
+            key in vec(any::<u8>(), 0..32),
+            version in any::<u128>(),
+            sub_key in vec(any::<u8>(), 0..32),
+            index in any::<u64>(),
+            (ms, seq) in any::<(u64, u64)>(),
+        ) {
+            let prefix = internal_key_prefix(&key, version);
+
+            let buf = HashInternalKey { key: key.clone(), version, field: sub_key.clone() }.encode();
+            prop_assert!(buf.starts_with(&prefix));
+            let decoded = HashInternalKey::decode(&key, &buf);
+            prop_assert_eq!((decoded.version, &decoded.field), (version, &sub_key));
+
+            let buf = SetInternalKey { key: key.clone(), version, member: sub_key.clone() }.encode();
+            prop_assert!(buf.starts_with(&prefix));
+            let decoded = SetInternalKey::decode(&key, &buf);
+            prop_assert_eq!((decoded.version, &decoded.member), (version, &sub_key));
+
+            let buf = ListInternalKey { key: key.clone(), version, index }.encode();
+            prop_assert!(buf.starts_with(&prefix));
+            let decoded = ListInternalKey::decode(&key, &buf);
+            prop_assert_eq!((decoded.version, decoded.index), (version, index));
+
+            let id = StreamId { ms, seq };
+            let buf = StreamInternalKey { key: key.clone(), version, id }.encode();
+            prop_assert!(buf.starts_with(&prefix));
+            let decoded = StreamInternalKey::decode(&key, &buf);
+            prop_assert_eq!((decoded.version, decoded.id), (version, id));
+        }
+    }
+
+    #[test]
+    fn test_find_metadata() {
+        let name = "find_metadata";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+
+        let secs = |n: u64| Duration::from_secs(n).as_nanos() as u64;
+        let clock = ManualClock(Arc::new(AtomicU64::new(secs(1000))));
+        let db = RedisLucasDb::with_clock(opts, Box::new(clock.clone())).unwrap();
+
+        // 不存在时返回新的元数据, 不写入数据库
+        let meta = db.find_metadata(b"list", RedisDataType::List).unwrap();
+        assert_eq!(meta.size, 0);
+        assert_eq!(meta.head, INITIAL_LIST_MARK);
+        assert_eq!(meta.tail, INITIAL_LIST_MARK);
+        assert!(db.key_type("list").is_err());
+
+        db.hset("hash", "field", "value").unwrap();
+        let meta = db.find_metadata(b"hash", RedisDataType::Hash).unwrap();
+        assert_eq!(meta.size, 1);
+        let version = meta.version;
+        assert!(matches!(
+            db.find_metadata(b"hash", RedisDataType::Set),
+            Err(Errors::WrongTypeOperation { .. })
+        ));
+        assert!(matches!(
+            db.find_metadata(b"hash", RedisDataType::String),
+            Err(Errors::WrongTypeOperation { .. })
+        ));
+
+        // 过期之后不检查类型, 版本号重新生成
+        db.expire("hash", Duration::from_secs(1)).unwrap();
+        clock.0.store(secs(1002), Ordering::SeqCst);
+        let meta = db.find_metadata(b"hash", RedisDataType::Set).unwrap();
+        assert_eq!(meta.data_type, RedisDataType::Set);
+        assert_eq!(meta.size, 0);
+        assert!(meta.version > version);
+        assert!(db.eng.get(Bytes::from_static(b"hash")).is_err());
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_find_metadata_invalid_value() {
+        let name = "find_metadata_invalid_value";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        let db = RedisLucasDb::new(opts).unwrap();
+
+        // 类型未知, 或者类型正确但是长度不够的 value, 比如和集合的内部数据同名的 key
+        let values: [&[u8]; 4] = [b"", b"raw", &[9; 40], &[RedisDataType::Hash as u8; 20]];
+        for value in values {
+            db.eng
+                .put(Bytes::from_static(b"raw"), Bytes::copy_from_slice(value))
+                .unwrap();
+            assert!(matches!(
+                db.hset("raw", "field", "value"),
+                Err(Errors::DeserializeFailed(_))
+            ));
+            assert!(matches!(
+                db.sadd("raw", "member"),
+                Err(Errors::DeserializeFailed(_))
+            ));
+            assert!(matches!(
+                db.zadd("raw", 1.0, "member"),
+                Err(Errors::DeserializeFailed(_))
+            ));
+            assert!(matches!(db.get("raw"), Err(Errors::DeserializeFailed(_))));
+            assert!(matches!(db.key_type("raw"), Err(Errors::KeyNotFound)));
+            assert_eq!(db.eng.get(Bytes::from_static(b"raw")).unwrap(), value);
+
+            // 删除之后可以正常使用
+            db.del("raw").unwrap();
+            assert!(db.hset("raw", "field", "value").unwrap());
+            db.del("raw").unwrap();
+        }
+
+        clean(name);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{collections::HashMap, fmt::Write, sync::Mutex, time::Duration};
+
+/// 单个命令的统计信息
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CommandStats {
+    /// 调用次数
+    pub calls: u64,
+    /// 累计耗时
+    pub total: Duration,
+    /// 最大耗时
+    pub max: Duration,
+}
+
+impl CommandStats {
+    /// 平均耗时
+    pub fn avg(&self) -> Duration {
+        if self.calls == 0 {
+            return Duration::ZERO;
+        }
+        self.total / self.calls as u32
+    }
+}
+
+/// 记录每个命令的调用次数和耗时
+#[derive(Default)]
+pub struct CommandMetrics {
+    stats: Mutex<HashMap<String, CommandStats>>,
+}
+
+impl CommandMetrics {
+    pub fn record(&self, name: &str, elapsed: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.entry(name.to_string()).or_default();
+        entry.calls += 1;
+        entry.total += elapsed;
+        entry.max = entry.max.max(elapsed);
+    }
+
+    /// 所有命令的统计信息, 按命令名排序
+    pub fn snapshot(&self) -> Vec<(String, CommandStats)> {
+        let stats = self.stats.lock().unwrap();
+        let mut ret: Vec<_> = stats.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        ret.sort_by(|a, b| a.0.cmp(&b.0));
+        ret
+    }
+
+    pub fn reset(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+
+    /// 格式化成 redis `INFO commandstats` 的格式
+    pub fn info(&self) -> String {
+        let mut info = String::from("# Commandstats\r\n");
+        for (name, stats) in self.snapshot() {
+            let _ = write!(
+                info,
+                "cmdstat_{}:calls={},usec={},usec_per_call={:.2},usec_max={}\r\n",
+                name,
+                stats.calls,
+                stats.total.as_micros(),
+                stats.total.as_micros() as f64 / stats.calls as f64,
+                stats.max.as_micros()
+            );
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_metrics() {
+        let metrics = CommandMetrics::default();
+        metrics.record("set", Duration::from_micros(10));
+        metrics.record("set", Duration::from_micros(30));
+        metrics.record("get", Duration::from_micros(5));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].0, "get");
+        assert_eq!(snapshot[1].0, "set");
+        assert_eq!(snapshot[1].1.calls, 2);
+        assert_eq!(snapshot[1].1.total, Duration::from_micros(40));
+        assert_eq!(snapshot[1].1.max, Duration::from_micros(30));
+        assert_eq!(snapshot[1].1.avg(), Duration::from_micros(20));
+
+        let info = metrics.info();
+        assert!(info.contains("cmdstat_set:calls=2,usec=40,usec_per_call=20.00,usec_max=30\r\n"));
+
+        metrics.reset();
+        assert!(metrics.snapshot().is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// 每个 MONITOR 连接最多缓存多少条命令, 超过后认为连接已经失效
+const MONITOR_BUFFER_SIZE: usize = 4096;
+
+/// 把收到的命令转发给所有执行了 MONITOR 的连接
+#[derive(Default)]
+pub struct Monitor {
+    subscribers: Mutex<Vec<SyncSender<String>>>,
+}
+
+impl Monitor {
+    /// 注册一个 MONITOR 连接, 之后收到的命令都会发送到返回的`Receiver`
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = mpsc::sync_channel(MONITOR_BUFFER_SIZE);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.lock().unwrap().is_empty()
+    }
+
+    /// 转发一条命令, 发送失败或者堆积太多的连接会被移除
+    pub fn feed(&self, db: usize, addr: &SocketAddr, args: &[Vec<u8>]) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let line = format_command(db, addr, args);
+        subscribers.retain(|sender| match sender.try_send(line.clone()) {
+            Ok(_) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+/// 和 redis 一样的格式: `1339518083.107412 [0 127.0.0.1:60866] "set" "key" "value"`
+fn format_command(db: usize, addr: &SocketAddr, args: &[Vec<u8>]) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut line = format!(
+        "{}.{:06} [{} {}]",
+        now.as_secs(),
+        now.subsec_micros(),
+        db,
+        addr
+    );
+    for arg in args {
+        let _ = write!(line, " {:?}", String::from_utf8_lossy(arg));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monitor_feed() {
+        let monitor = Monitor::default();
+        let addr: SocketAddr = "127.0.0.1:60866".parse().unwrap();
+
+        // 没有订阅者时不做任何事
+        monitor.feed(0, &addr, &[b"get".to_vec()]);
+        assert!(monitor.is_empty());
+
+        let receiver = monitor.subscribe();
+        monitor.feed(
+            3,
+            &addr,
+            &[b"set".to_vec(), b"key".to_vec(), b"va\"lue".to_vec()],
+        );
+        let line = receiver.try_recv().unwrap();
+        assert!(line.ends_with(r#" [3 127.0.0.1:60866] "set" "key" "va\"lue""#));
+
+        // 断开的连接会被移除
+        drop(receiver);
+        monitor.feed(0, &addr, &[b"get".to_vec()]);
+        assert!(monitor.is_empty());
+    }
+}
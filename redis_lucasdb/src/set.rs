@@ -1,11 +1,13 @@
 use crate::{
+    bytes_to_string,
+    metadata::{internal_key_prefix, InternalKey},
     types::{RedisDataType, RedisLucasDb},
     EncodeAndDecode,
 };
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use lucasdb::{
     errors::{Errors, Result},
-    options::WriteBatchOptions,
+    options::{IteratorOptions, WriteBatchOptions},
 };
 
 pub(crate) struct SetInternalKey {
@@ -14,19 +16,23 @@ pub(crate) struct SetInternalKey {
     pub(crate) member: Vec<u8>,
 }
 
-impl EncodeAndDecode for SetInternalKey {
+impl InternalKey for SetInternalKey {
     /// 编码格式: key + version + member + member.len()
-    fn encode(&self) -> bytes::Bytes {
-        let mut buf = BytesMut::new();
-        buf.extend_from_slice(&self.key);
-        buf.put_u128(self.version);
+    fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::from(internal_key_prefix(&self.key, self.version).as_slice());
         buf.extend_from_slice(&self.member);
         buf.put_u32(self.member.len() as u32);
         buf.into()
     }
 
-    fn decode(buf: &mut bytes::Bytes) -> Self {
-        todo!()
+    fn decode(key: &[u8], buf: &[u8]) -> Self {
+        let member_end = buf.len() - 4;
+        let mut rest = &buf[key.len()..member_end];
+        SetInternalKey {
+            key: key.to_vec(),
+            version: rest.get_u128(),
+            member: rest.to_vec(),
+        }
     }
 }
 
@@ -34,47 +40,49 @@ impl RedisLucasDb {
     /// 往`set`添加一个成员\
     /// 添加成功返回true\
     /// 添加失败/member已存在则返回true
-    pub fn sadd(&self, key: &str, member: &str) -> Result<bool> {
+    pub fn sadd(&self, key: impl AsRef<[u8]>, member: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let member = member.as_ref();
+        let _guard = self.lock_key(key);
         let mut meta = self.find_or_new_metadata(key, RedisDataType::Set)?;
 
         let internal_key = SetInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
-            member: member.as_bytes().to_vec(),
+            member: member.to_vec(),
         };
 
-        if let Err(e) = self.eng.get(internal_key.encode()) {
-            match e {
-                lucasdb::errors::Errors::KeyNotFound => {
-                    // 更新元数据
-                    let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
-                    meta.size += 1; // 增加了一个member
-                    wb.put(Bytes::copy_from_slice(key.as_bytes()), meta.encode())?;
-
-                    // 数据部分,value不用存放
-                    wb.put(internal_key.encode(), Bytes::new())?;
-                    wb.commit()?;
-                    return Ok(true);
-                }
-                _ => {}
-            }
+        let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
+        match wb.get(internal_key.encode()) {
+            Ok(_) => return Ok(false),
+            Err(lucasdb::errors::Errors::KeyNotFound) => {}
+            Err(e) => return Err(e),
         }
 
-        Ok(false)
+        // 更新元数据
+        meta.size += 1; // 增加了一个member
+        wb.put(Bytes::copy_from_slice(key), meta.encode())?;
+
+        // 数据部分,value不用存放
+        wb.put(internal_key.encode(), Bytes::new())?;
+        wb.commit()?;
+        Ok(true)
     }
 
     /// 判断member是否在集合中
-    pub fn sismember(&self, key: &str, member: &str) -> Result<bool> {
-        let meta = self.find_or_new_metadata(key, RedisDataType::Set)?;
+    pub fn sismember(&self, key: impl AsRef<[u8]>, member: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let member = member.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::Set)?;
 
         if meta.size == 0 {
             return Ok(false);
         }
 
         let internal_key = SetInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
-            member: member.as_bytes().to_vec(),
+            member: member.to_vec(),
         };
 
         match self.eng.get(internal_key.encode()) {
@@ -90,7 +98,10 @@ impl RedisLucasDb {
 
     /// 将member从set中删除\
     /// 若member不属于set,返回false
-    pub fn srem(&self, key: &str, member: &str) -> Result<bool> {
+    pub fn srem(&self, key: impl AsRef<[u8]>, member: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let member = member.as_ref();
+        let _guard = self.lock_key(key);
         let mut meta = self.find_or_new_metadata(key, RedisDataType::Set)?;
 
         if meta.size == 0 {
@@ -98,16 +109,16 @@ impl RedisLucasDb {
         }
 
         let internal_key = SetInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
-            member: member.as_bytes().to_vec(),
+            member: member.to_vec(),
         };
 
         if let Ok(_) = self.eng.get(internal_key.encode()) {
             // 更新元数据
             meta.size -= 1;
             let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
-            wb.put(Bytes::copy_from_slice(key.as_bytes()), meta.encode())?;
+            wb.put(Bytes::copy_from_slice(key), meta.encode())?;
             wb.delete(internal_key.encode())?;
             wb.commit()?;
             return Ok(true);
@@ -115,6 +126,85 @@ impl RedisLucasDb {
 
         return Ok(false);
     }
+
+    /// 返回集合中的所有成员, 按成员排序
+    pub fn smembers_bytes(&self, key: impl AsRef<[u8]>) -> Result<Vec<Bytes>> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::Set)?;
+        let internal_keys = self.set_internal_keys(key, meta.version, meta.size)?;
+        Ok(internal_keys
+            .into_iter()
+            .map(|internal_key| Bytes::from(internal_key.member))
+            .collect())
+    }
+
+    /// 见`smembers_bytes`
+    pub fn smembers(&self, key: impl AsRef<[u8]>) -> Result<Vec<String>> {
+        self.smembers_bytes(key)?
+            .into_iter()
+            .map(bytes_to_string)
+            .collect()
+    }
+
+    /// 集合中成员的数量, key 不存在时返回0
+    pub fn scard(&self, key: impl AsRef<[u8]>) -> Result<u32> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::Set)?;
+        Ok(meta.size)
+    }
+
+    /// 从集合中删除并返回一个成员, 集合为空时返回None\
+    /// 不是随机选取的, 总是返回排序后的第一个成员
+    pub fn spop_bytes(&self, key: impl AsRef<[u8]>) -> Result<Option<Bytes>> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+        let mut meta = self.find_or_new_metadata(key, RedisDataType::Set)?;
+        if meta.size == 0 {
+            return Ok(None);
+        }
+
+        let internal_key = match self.set_internal_keys(key, meta.version, 1)?.pop() {
+            Some(internal_key) => internal_key,
+            None => return Ok(None),
+        };
+
+        meta.size -= 1;
+        let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
+        wb.put(Bytes::copy_from_slice(key), meta.encode())?;
+        wb.delete(internal_key.encode())?;
+        wb.commit()?;
+
+        Ok(Some(Bytes::from(internal_key.member)))
+    }
+
+    /// 见`spop_bytes`
+    pub fn spop(&self, key: impl AsRef<[u8]>) -> Result<Option<String>> {
+        self.spop_bytes(key)?.map(bytes_to_string).transpose()
+    }
+
+    /// 按 key + version 前缀遍历集合的数据部分, 最多返回`limit`个
+    fn set_internal_keys(
+        &self,
+        key: &[u8],
+        version: u128,
+        limit: u32,
+    ) -> Result<Vec<SetInternalKey>> {
+        let iter = self.eng.iter(IteratorOptions {
+            prefix: internal_key_prefix(key, version),
+            reverse: false,
+            keys_only: true,
+            ..Default::default()
+        });
+
+        let mut internal_keys = Vec::new();
+        while internal_keys.len() < limit as usize {
+            match iter.next()? {
+                Some((buf, _)) => internal_keys.push(SetInternalKey::decode(key, &buf)),
+                None => break,
+            }
+        }
+        Ok(internal_keys)
+    }
 }
 
 #[cfg(test)]
@@ -312,4 +402,52 @@ mod tests {
 
         clean(name);
     }
+
+    #[test]
+    fn test_set_smembers_scard_spop() {
+        let name = "smembers";
+        let (rds, _) = setup(name);
+
+        assert_eq!(rds.scard("lucas-set").unwrap(), 0);
+        assert!(rds.smembers("lucas-set").unwrap().is_empty());
+        assert!(rds.spop("lucas-set").unwrap().is_none());
+
+        for member in ["val-3", "val-1", "val-2"] {
+            rds.sadd("lucas-set", member).unwrap();
+        }
+        // 以 key 开头的其他集合不会被遍历到
+        rds.sadd("lucas-set-2", "val-0").unwrap();
+
+        assert_eq!(rds.scard("lucas-set").unwrap(), 3);
+        assert_eq!(
+            rds.smembers("lucas-set").unwrap(),
+            vec!["val-1", "val-2", "val-3"]
+        );
+
+        assert_eq!(rds.spop("lucas-set").unwrap(), Some("val-1".to_string()));
+        assert_eq!(rds.scard("lucas-set").unwrap(), 2);
+        assert!(!rds.sismember("lucas-set", "val-1").unwrap());
+        assert_eq!(rds.smembers("lucas-set").unwrap(), vec!["val-2", "val-3"]);
+
+        assert!(rds.spop("lucas-set").unwrap().is_some());
+        assert!(rds.spop("lucas-set").unwrap().is_some());
+        assert!(rds.spop("lucas-set").unwrap().is_none());
+        assert_eq!(rds.scard("lucas-set").unwrap(), 0);
+        assert_eq!(rds.smembers("lucas-set-2").unwrap(), vec!["val-0"]);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_set_internal_key_decode() {
+        let internal_key = SetInternalKey {
+            key: b"lucas-set".to_vec(),
+            version: 42,
+            member: b"member".to_vec(),
+        };
+        let decoded = SetInternalKey::decode(&internal_key.key, &internal_key.encode());
+        assert_eq!(decoded.key, internal_key.key);
+        assert_eq!(decoded.version, internal_key.version);
+        assert_eq!(decoded.member, internal_key.member);
+    }
 }
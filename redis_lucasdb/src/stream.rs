@@ -0,0 +1,355 @@
+use std::fmt;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use lucasdb::{
+    errors::{Errors, Result},
+    options::{IteratorOptions, WriteBatchOptions},
+};
+
+use crate::{
+    metadata::{internal_key_prefix, InternalKey},
+    types::{RedisDataType, RedisLucasDb},
+    EncodeAndDecode,
+};
+
+/// stream 中一条数据的 ID, 按 ms, seq 的顺序比较
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// 解析`<ms>-<seq>`, 只有`<ms>`时 seq 使用`default_seq`
+    pub fn parse(s: &[u8], default_seq: u64) -> Result<Self> {
+        let s = std::str::from_utf8(s).map_err(|_| Errors::InvalidStreamId)?;
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().map_err(|_| Errors::InvalidStreamId)?),
+            None => (s, default_seq),
+        };
+        let ms = ms.parse().map_err(|_| Errors::InvalidStreamId)?;
+        Ok(StreamId { ms, seq })
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// stream 中的一条数据
+pub type StreamEntry = (StreamId, Vec<(Bytes, Bytes)>);
+
+pub(crate) struct StreamInternalKey {
+    pub(crate) key: Vec<u8>,
+    pub(crate) version: u128,
+    pub(crate) id: StreamId,
+}
+
+impl InternalKey for StreamInternalKey {
+    /// 编码格式: key + version + ms + seq, 按字节序遍历时就是 ID 的顺序
+    fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::from(internal_key_prefix(&self.key, self.version).as_slice());
+        buf.put_u64(self.id.ms);
+        buf.put_u64(self.id.seq);
+        buf.into()
+    }
+
+    fn decode(key: &[u8], buf: &[u8]) -> Self {
+        let mut buf = &buf[key.len()..];
+        let version = buf.get_u128();
+        StreamInternalKey {
+            key: key.to_vec(),
+            version,
+            id: StreamId {
+                ms: buf.get_u64(),
+                seq: buf.get_u64(),
+            },
+        }
+    }
+}
+
+/// 编码格式: 字段数量 + (field长度 + field + value长度 + value)...
+fn encode_fields<F: AsRef<[u8]>, V: AsRef<[u8]>>(fields: &[(F, V)]) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u32(fields.len() as u32);
+    for (field, value) in fields {
+        for part in [field.as_ref(), value.as_ref()] {
+            buf.put_u32(part.len() as u32);
+            buf.extend_from_slice(part);
+        }
+    }
+    buf.into()
+}
+
+fn decode_fields(mut buf: Bytes) -> Vec<(Bytes, Bytes)> {
+    let count = buf.get_u32() as usize;
+    let mut fields = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len = buf.get_u32() as usize;
+        let field = buf.split_to(len);
+        let len = buf.get_u32() as usize;
+        let value = buf.split_to(len);
+        fields.push((field, value));
+    }
+    fields
+}
+
+/// 实现 redis 中 stream 的 xadd/xrange/xlen, 只支持追加和按 ID 范围读取
+impl RedisLucasDb {
+    /// 追加一条数据, 返回它的 ID\
+    /// `id`为空时自动生成: 毫秒时间戳, 和最后一条数据在同一毫秒或者时钟回拨时沿用最后的 ms 并把 seq 加1\
+    /// 指定的`id`必须大于最后一条数据的 ID, 并且大于`0-0`
+    pub fn xadd<F, V>(
+        &self,
+        key: impl AsRef<[u8]>,
+        id: Option<StreamId>,
+        fields: &[(F, V)],
+    ) -> Result<StreamId>
+    where
+        F: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+        let mut meta = self.find_or_new_metadata(key, RedisDataType::Stream)?;
+        let last = match meta.size {
+            0 => StreamId::MIN,
+            _ => self.last_stream_id(key, meta.version)?,
+        };
+
+        let id = match id {
+            Some(id) if id <= last => return Err(Errors::StreamIdTooSmall),
+            Some(id) => id,
+            None => {
+                let ms = (self.now() / 1_000_000) as u64;
+                if ms > last.ms {
+                    StreamId { ms, seq: 0 }
+                } else {
+                    let seq = last.seq.checked_add(1).ok_or(Errors::StreamIdTooSmall)?;
+                    StreamId { ms: last.ms, seq }
+                }
+            }
+        };
+
+        let internal_key = StreamInternalKey {
+            key: key.to_vec(),
+            version: meta.version,
+            id,
+        };
+        meta.size += 1;
+        let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
+        wb.put(Bytes::copy_from_slice(key), meta.encode())?;
+        wb.put(internal_key.encode(), encode_fields(fields))?;
+        wb.commit()?;
+        Ok(id)
+    }
+
+    /// 按 ID 从小到大返回`[start, end]`范围内的数据, 最多返回`count`条
+    pub fn xrange(
+        &self,
+        key: impl AsRef<[u8]>,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::Stream)?;
+        let count = count.unwrap_or(usize::MAX);
+        if meta.size == 0 || start > end || count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let iter = self.eng.iter(IteratorOptions {
+            prefix: internal_key_prefix(key, meta.version),
+            ..Default::default()
+        });
+        let first = StreamInternalKey {
+            key: key.to_vec(),
+            version: meta.version,
+            id: start,
+        };
+        iter.seek(first.encode().to_vec());
+
+        let mut entries = Vec::new();
+        while entries.len() < count {
+            let Some((internal_key, value)) = iter.next()? else {
+                break;
+            };
+            let id = StreamInternalKey::decode(key, &internal_key).id;
+            if id > end {
+                break;
+            }
+            entries.push((id, decode_fields(value)));
+        }
+        Ok(entries)
+    }
+
+    /// stream 中的数据数量, key 不存在时返回0
+    pub fn xlen(&self, key: impl AsRef<[u8]>) -> Result<u32> {
+        let meta = self.find_metadata(key.as_ref(), RedisDataType::Stream)?;
+        Ok(meta.size)
+    }
+
+    /// 反向遍历找到最后一条数据的 ID
+    fn last_stream_id(&self, key: &[u8], version: u128) -> Result<StreamId> {
+        let iter = self.eng.iter(IteratorOptions {
+            prefix: internal_key_prefix(key, version),
+            reverse: true,
+            keys_only: true,
+            ..Default::default()
+        });
+        Ok(match iter.next()? {
+            Some((internal_key, _)) => StreamInternalKey::decode(key, &internal_key).id,
+            None => StreamId::MIN,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use lucasdb::options::EngineOptions;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn basepath() -> PathBuf {
+        "../tmp/redis_lucasdb/stream".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    #[test]
+    fn test_stream_xadd_xrange_xlen() {
+        let name = "xadd_xrange";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+
+        let millis = |n: u64| Duration::from_millis(n).as_nanos() as u64;
+        let clock = ManualClock(Arc::new(AtomicU64::new(millis(1000))));
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+
+        assert_eq!(db.xlen("stream").unwrap(), 0);
+        assert!(db
+            .xrange("stream", StreamId::MIN, StreamId::MAX, None)
+            .unwrap()
+            .is_empty());
+
+        // 自动生成的 ID, 同一毫秒内 seq 递增
+        let fields = [("field", "value")];
+        assert_eq!(db.xadd("stream", None, &fields).unwrap(), id(1000, 0));
+        assert_eq!(db.xadd("stream", None, &fields).unwrap(), id(1000, 1));
+        clock.0.store(millis(1005), Ordering::SeqCst);
+        assert_eq!(db.xadd("stream", None, &fields).unwrap(), id(1005, 0));
+
+        // 指定的 ID 必须递增
+        assert!(matches!(
+            db.xadd("stream", Some(id(1005, 0)), &fields),
+            Err(Errors::StreamIdTooSmall)
+        ));
+        assert!(matches!(
+            db.xadd("other", Some(StreamId::MIN), &fields),
+            Err(Errors::StreamIdTooSmall)
+        ));
+        let multi = [("a", "1"), ("b", "")];
+        assert_eq!(
+            db.xadd("stream", Some(id(2000, 5)), &multi).unwrap(),
+            id(2000, 5)
+        );
+        // 指定的 ID 比当前时间大, 之后自动生成的 ID 沿用它的 ms
+        assert_eq!(db.xadd("stream", None, &fields).unwrap(), id(2000, 6));
+        assert_eq!(db.xlen("stream").unwrap(), 5);
+
+        let ids = |entries: Vec<StreamEntry>| entries.into_iter().map(|e| e.0).collect::<Vec<_>>();
+        assert_eq!(
+            ids(db
+                .xrange("stream", StreamId::MIN, StreamId::MAX, None)
+                .unwrap()),
+            vec![
+                id(1000, 0),
+                id(1000, 1),
+                id(1005, 0),
+                id(2000, 5),
+                id(2000, 6)
+            ]
+        );
+        assert_eq!(
+            ids(db.xrange("stream", id(1000, 1), id(2000, 5), None).unwrap()),
+            vec![id(1000, 1), id(1005, 0), id(2000, 5)]
+        );
+        assert_eq!(
+            ids(db
+                .xrange("stream", id(1001, 0), StreamId::MAX, Some(1))
+                .unwrap()),
+            vec![id(1005, 0)]
+        );
+
+        let entries = db.xrange("stream", id(2000, 5), id(2000, 5), None).unwrap();
+        assert_eq!(
+            entries[0].1,
+            vec![
+                (Bytes::from("a"), Bytes::from("1")),
+                (Bytes::from("b"), Bytes::new())
+            ]
+        );
+
+        // 重启之后继续递增
+        drop(db);
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+        assert_eq!(db.xadd("stream", None, &fields).unwrap(), id(2000, 7));
+        assert_eq!(db.key_type("stream").unwrap(), RedisDataType::Stream);
+        assert!(matches!(
+            db.hget("stream", "field"),
+            Err(Errors::WrongTypeOperation { .. })
+        ));
+
+        db.del("stream").unwrap();
+        assert_eq!(db.xlen("stream").unwrap(), 0);
+        assert_eq!(db.xadd("stream", None, &fields).unwrap(), id(1005, 0));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_stream_id_parse() {
+        assert_eq!(
+            StreamId::parse(b"1526919030474-55", 0).unwrap(),
+            id(1526919030474, 55)
+        );
+        assert_eq!(StreamId::parse(b"100", 0).unwrap(), id(100, 0));
+        assert_eq!(
+            StreamId::parse(b"100", u64::MAX).unwrap(),
+            id(100, u64::MAX)
+        );
+        assert_eq!(id(100, 2).to_string(), "100-2");
+        for invalid in [&b""[..], b"-", b"a-1", b"1-b", b"1-2-3", b"-1"] {
+            assert!(matches!(
+                StreamId::parse(invalid, 0),
+                Err(Errors::InvalidStreamId)
+            ));
+        }
+    }
+}
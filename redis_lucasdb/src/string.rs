@@ -1,46 +1,177 @@
 use core::time;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use lucasdb::errors::{Errors, Result};
+use lucasdb::{
+    errors::{Errors, Result},
+    options::WriteBatchOptions,
+};
 
-use crate::types::{RedisDataType, RedisLucasDb};
+use crate::{
+    bytes_to_string,
+    metadata::decode_data_type,
+    types::{RedisDataType, RedisLucasDb},
+};
 
-/// 实现redis中对string的操作:get, set
+/// 编码格式： type + ttl + value(用户传进的value)
+pub(crate) fn encode_string(expire: u128, value: &[u8]) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(RedisDataType::String as u8); // 1.type
+    buf.put_u128(expire); // 2.ttl
+
+    // 3.value部分
+    buf.extend_from_slice(value);
+    buf.into()
+}
+
+/// 实现redis中对string的操作:get, set, setnx, mset, mget, incr, decr
+/// key 和 value 都是二进制安全的, 返回 String 的方法只是方便使用的包装
 impl RedisLucasDb {
     /// value会经过编码再进行存储
     /// 编码格式： type + ttl + value(用户传进的value)
-    pub fn set(&self, key: &str, ttl: std::time::Duration, value: &str) -> Result<()> {
-        if value.len() == 0 {
-            return Ok(());
+    pub fn set(
+        &self,
+        key: impl AsRef<[u8]>,
+        ttl: std::time::Duration,
+        value: impl AsRef<[u8]>,
+    ) -> Result<()> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+        self.put_string(key, ttl, value.as_ref())
+    }
+
+    /// `key`不存在或者已经过期时才写入, 写入成功返回true
+    pub fn setnx(&self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+        if self.find_live_value(key)?.is_some() {
+            return Ok(false);
         }
+        self.put_string(key, time::Duration::ZERO, value.as_ref())?;
+        Ok(true)
+    }
 
-        let mut buf = BytesMut::new();
-        buf.put_u8(RedisDataType::String as u8); // 1.type
+    /// 使用 WriteBatch 同时写入多个key, 要么都成功要么都失败
+    pub fn mset<K, V>(&self, pairs: &[(K, V)]) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let keys: Vec<&[u8]> = pairs.iter().map(|(key, _)| key.as_ref()).collect();
+        let _guards = self.lock_keys(&keys);
 
-        let mut expire = 0; // 过期时间,纳秒
-        if ttl != time::Duration::ZERO {
-            if let Some(v) = SystemTime::now().checked_add(ttl) {
-                expire = v.duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
+        for (key, value) in pairs {
+            if value.as_ref().is_empty() {
+                continue;
             }
+            wb.put(
+                Bytes::copy_from_slice(key.as_ref()),
+                encode_string(0, value.as_ref()),
+            )?;
         }
+        wb.commit()
+    }
 
-        buf.put_u128(expire); // 2.ttl
+    /// 依次返回每个key的值, key不存在、已经过期或者不是 string 时返回None
+    pub fn mget_bytes<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Bytes>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            let value = match self.get_bytes(key) {
+                Ok(value) => value,
+                Err(Errors::KeyNotFound) | Err(Errors::WrongTypeOperation { .. }) => None,
+                Err(e) => return Err(e),
+            };
+            values.push(value);
+        }
+        Ok(values)
+    }
 
-        // 3.value部分
-        buf.extend_from_slice(value.as_bytes());
+    /// 见`mget_bytes`
+    pub fn mget<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<String>>> {
+        self.mget_bytes(keys)?
+            .into_iter()
+            .map(|value| value.map(bytes_to_string).transpose())
+            .collect()
+    }
+
+    /// 把值加1, 返回加1之后的值
+    pub fn incr(&self, key: impl AsRef<[u8]>) -> Result<i64> {
+        self.incrby(key, 1)
+    }
+
+    /// 把值减1, 返回减1之后的值
+    pub fn decr(&self, key: impl AsRef<[u8]>) -> Result<i64> {
+        self.incrby(key, -1)
+    }
+
+    /// 把值当作 i64 加上`delta`, 返回相加之后的值\
+    /// key不存在时从0开始, 原来的过期时间保持不变
+    pub fn incrby(&self, key: impl AsRef<[u8]>, delta: i64) -> Result<i64> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+
+        let (expire, current) = match self.find_live_string(key)? {
+            Some((expire, value)) => (expire, bytes_to_string(value)?.parse::<i64>()?),
+            None => (0, 0),
+        };
+
+        let value = current.checked_add(delta).ok_or(Errors::IntegerOverflow)?;
+        self.eng.put(
+            Bytes::copy_from_slice(key),
+            encode_string(expire, value.to_string().as_bytes()),
+        )?;
+        Ok(value)
+    }
+
+    /// 查找没有过期的 string, 返回过期时间和用户的 value, 用于在原来的值上修改\
+    /// `key`不是 string 时返回类型错误
+    pub(crate) fn find_live_string(&self, key: &[u8]) -> Result<Option<(u128, Bytes)>> {
+        let mut buf = match self.find_live_value(key)? {
+            Some(buf) => buf,
+            None => return Ok(None),
+        };
+        let key_type = decode_data_type(&buf)?;
+        buf.advance(1);
+        if key_type != RedisDataType::String {
+            return Err(Errors::WrongTypeOperation {
+                expected: RedisDataType::String.to_string(),
+                actual: key_type.to_string(),
+            });
+        }
+        let expire = buf.get_u128();
+        Ok(Some((expire, buf)))
+    }
+
+    /// 不加锁的`set`
+    fn put_string(&self, key: &[u8], ttl: std::time::Duration, value: &[u8]) -> Result<()> {
+        if value.len() == 0 {
+            return Ok(());
+        }
+
+        self.tick_clock_marker()?;
+        let mut expire = 0; // 过期时间,纳秒
+        if ttl != time::Duration::ZERO {
+            expire = self.now() + ttl.as_nanos();
+        }
 
         self.eng
-            .put(Bytes::copy_from_slice(key.as_bytes()), buf.into())?;
+            .put(Bytes::copy_from_slice(key), encode_string(expire, value))?;
 
         Ok(())
     }
 
     // 拿到的value需要解码
     /// 编码格式： type + ttl + value(用户传进的value)
-    pub fn get(&self, key: &str) -> Result<Option<String>> {
-        let mut buf = self.eng.get(Bytes::copy_from_slice(key.as_bytes()))?;
-        let key_type = RedisDataType::from(buf.get_u8());
+    pub fn get_bytes(&self, key: impl AsRef<[u8]>) -> Result<Option<Bytes>> {
+        let mut buf = self.eng.get(Bytes::copy_from_slice(key.as_ref()))?;
+        let key_type = decode_data_type(&buf)?;
+        buf.advance(1);
+
+        // 判断过期时间, 过期的 key 不管是什么类型都当作不存在
+        let expire = buf.get_u128();
+        if expire > 0 && expire <= self.now() {
+            return Ok(None);
+        }
 
         // 判断key的类型能否执行get操作
         if key_type != RedisDataType::String {
@@ -50,34 +181,32 @@ impl RedisLucasDb {
             });
         }
 
-        // 判断过期时间
-        let expire = buf.get_u128();
-        if expire > 0 {
-            let now = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos();
-            if expire <= now {
-                // 过期了
-                return Ok(None);
-            }
-        }
-
         // 取出真正的value
-        // get_u8和get_u128会移动ptr位置,所以直接to_vec就得到value了
-        let value = buf.to_vec();
+        // get_u8和get_u128会移动ptr位置,剩下的部分就是value
+        Ok(Some(buf))
+    }
 
-        Ok(Some(String::from_utf8(value).unwrap()))
+    /// 见`get_bytes`, value 不是 utf8 时返回错误
+    pub fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<String>> {
+        self.get_bytes(key)?.map(bytes_to_string).transpose()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{path::PathBuf, time::Duration};
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
     use lucasdb::options::EngineOptions;
 
     use super::*;
+    use crate::clock::ManualClock;
 
     fn basepath() -> PathBuf {
         "../tmp/redis_lucasdb/string".into()
@@ -133,4 +262,171 @@ mod tests {
 
         clean(name);
     }
+
+    #[test]
+    fn test_string_binary_safe() {
+        let name = "binary_safe";
+        let (db, _) = setup(name);
+
+        let key: &[u8] = b"key\xff\x00";
+        let value: &[u8] = b"\x00\x01\xfe\xff";
+        db.set(key, Duration::ZERO, value).unwrap();
+        assert_eq!(
+            db.get_bytes(key).unwrap(),
+            Some(Bytes::copy_from_slice(value))
+        );
+        // 不是 utf8 时 String 版本返回错误
+        assert!(db.get(key).is_err());
+
+        db.hset(key, value, value).unwrap_err();
+        db.hset("hash", value, value).unwrap();
+        assert_eq!(
+            db.hget_bytes("hash", value).unwrap(),
+            Some(Bytes::copy_from_slice(value))
+        );
+        assert_eq!(
+            db.hkeys_bytes("hash").unwrap(),
+            vec![Bytes::copy_from_slice(value)]
+        );
+
+        // member 以 0xff 开头时也不会和 score 索引混在一起
+        db.zadd("zset", 1.0, value).unwrap();
+        db.zadd("zset", 2.0, b"\xff\xff").unwrap();
+        assert_eq!(db.zscore("zset", b"\xff\xff").unwrap(), 2.0);
+        assert_eq!(
+            db.zrange_bytes("zset", 0, -1).unwrap(),
+            vec![
+                (Bytes::copy_from_slice(value), 1.0),
+                (Bytes::from_static(b"\xff\xff"), 2.0)
+            ]
+        );
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_string_ttl_clock_skew() {
+        let name = "ttl_clock_skew";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+
+        let secs = |n: u64| Duration::from_secs(n).as_nanos() as u64;
+        let clock = ManualClock(Arc::new(AtomicU64::new(secs(1000))));
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+        assert!(db.clock_skew().is_none());
+
+        db.set("key1", Duration::from_secs(10), "value1").unwrap();
+        assert_eq!(db.get("key1").unwrap(), Some("value1".to_string()));
+
+        clock.0.store(secs(1020), Ordering::SeqCst);
+        assert!(db.get("key1").unwrap().is_none());
+
+        // 时钟回拨, 已经过期的key不会重新出现
+        clock.0.store(secs(1005), Ordering::SeqCst);
+        assert!(db.get("key1").unwrap().is_none());
+        drop(db);
+
+        // 重启时检测到时钟回拨
+        let db = RedisLucasDb::with_clock(opts.clone(), Box::new(clock.clone())).unwrap();
+        assert_eq!(db.clock_skew(), Some(Duration::from_secs(15)));
+        assert!(db.get("key1").unwrap().is_none());
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_string_setnx_mset_mget() {
+        let name = "setnx_mset_mget";
+        let (db, _) = setup(name);
+
+        assert!(db.setnx("key1", "value1").unwrap());
+        assert!(!db.setnx("key1", "value2").unwrap());
+        assert_eq!(db.get("key1").unwrap(), Some("value1".to_string()));
+
+        db.mset(&[("key2", "value2"), ("key3", "value3"), ("key1", "new")])
+            .unwrap();
+        db.sadd("set", "member").unwrap();
+        assert_eq!(
+            db.mget(&["key1", "key2", "not-exist", "set", "key3"])
+                .unwrap(),
+            vec![
+                Some("new".to_string()),
+                Some("value2".to_string()),
+                None,
+                None,
+                Some("value3".to_string()),
+            ]
+        );
+
+        // 其他类型的key也算存在
+        assert!(!db.setnx("set", "value").unwrap());
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_string_incr_decr() {
+        let name = "incr_decr";
+        let (db, _) = setup(name);
+
+        assert_eq!(db.incr("counter").unwrap(), 1);
+        assert_eq!(db.incrby("counter", 10).unwrap(), 11);
+        assert_eq!(db.decr("counter").unwrap(), 10);
+        assert_eq!(db.incrby("counter", -20).unwrap(), -10);
+        assert_eq!(db.get("counter").unwrap(), Some("-10".to_string()));
+
+        db.set("text", Duration::ZERO, "abc").unwrap();
+        assert!(matches!(db.incr("text"), Err(Errors::ParseIntError(_))));
+        db.set("max", Duration::ZERO, &i64::MAX.to_string())
+            .unwrap();
+        assert!(matches!(db.incr("max"), Err(Errors::IntegerOverflow)));
+        db.sadd("set", "member").unwrap();
+        assert!(matches!(
+            db.incr("set"),
+            Err(Errors::WrongTypeOperation { .. })
+        ));
+
+        // 多个线程同时修改同一个key
+        let db = Arc::new(db);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        db.incr("shared").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(db.get("shared").unwrap(), Some("400".to_string()));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_string_incr_keep_ttl() {
+        let name = "incr_keep_ttl";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+
+        let secs = |n: u64| Duration::from_secs(n).as_nanos() as u64;
+        let clock = ManualClock(Arc::new(AtomicU64::new(secs(1000))));
+        let db = RedisLucasDb::with_clock(opts, Box::new(clock.clone())).unwrap();
+
+        db.set("counter", Duration::from_secs(10), "5").unwrap();
+        assert_eq!(db.incr("counter").unwrap(), 6);
+        assert_eq!(db.ttl("counter").unwrap(), 10);
+
+        // 过期之后从0开始
+        clock.0.store(secs(1010), Ordering::SeqCst);
+        assert_eq!(db.incr("counter").unwrap(), 1);
+        assert_eq!(db.ttl("counter").unwrap(), -1);
+
+        clean(name);
+    }
 }
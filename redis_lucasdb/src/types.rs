@@ -1,8 +1,32 @@
 use core::fmt;
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
 
-use lucasdb::errors::Result;
+use log::warn;
+use lucasdb::errors::{Errors, Result};
 use lucasdb::options::EngineOptions;
 
+use crate::{
+    clock::{Clock, SystemClock},
+    generic::ScanCursors,
+};
+
+/// 保存最近一次使用的时间, 重启时用来检测时钟回拨
+/// 放在数据目录下的单独文件中, 不占用数据库的 key, 客户端无法读写
+pub(crate) const CLOCK_MARKER_FILE_NAME: &str = "redis-clock-marker";
+
+/// 时间标记写入文件的最小间隔, 纳秒
+const CLOCK_MARKER_INTERVAL: u128 = 1_000_000_000;
+
+/// 按key加锁时锁的数量, 不同的key可能共用同一把锁
+const KEY_LOCK_NUM: usize = 64;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RedisDataType {
     String,
@@ -10,17 +34,24 @@ pub enum RedisDataType {
     Set,
     List,
     ZSet,
+    Stream,
 }
 
-impl From<u8> for RedisDataType {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for RedisDataType {
+    type Error = Errors;
+
+    fn try_from(value: u8) -> Result<Self> {
         match value {
-            0 => RedisDataType::String,
-            1 => RedisDataType::Hash,
-            2 => RedisDataType::Set,
-            3 => RedisDataType::List,
-            4 => RedisDataType::ZSet,
-            _ => panic!("Invalid Redis data type"),
+            0 => Ok(RedisDataType::String),
+            1 => Ok(RedisDataType::Hash),
+            2 => Ok(RedisDataType::Set),
+            3 => Ok(RedisDataType::List),
+            4 => Ok(RedisDataType::ZSet),
+            5 => Ok(RedisDataType::Stream),
+            _ => Err(Errors::DeserializeFailed(format!(
+                "invalid redis data type: {}",
+                value
+            ))),
         }
     }
 }
@@ -33,17 +64,198 @@ impl fmt::Display for RedisDataType {
             RedisDataType::Set => write!(f, "Set"),
             RedisDataType::List => write!(f, "List"),
             RedisDataType::ZSet => write!(f, "ZSet"),
+            RedisDataType::Stream => write!(f, "Stream"),
         }
     }
 }
 
+struct ClockState {
+    /// 返回过的最大时间
+    last: u128,
+    /// 已经写入数据库的时间标记
+    persisted: u128,
+}
+
 pub struct RedisLucasDb {
     pub(crate) eng: lucasdb::db::Engine,
+    clock: Box<dyn Clock>,
+    clock_marker_path: PathBuf,
+    clock_state: Mutex<ClockState>,
+    clock_skew: Option<Duration>,
+    /// 读-改-写的命令按key加锁, 多个连接可以同时操作不同的key
+    key_locks: Vec<Mutex<()>>,
+    pub(crate) scan_cursors: Mutex<ScanCursors>,
 }
 
 impl RedisLucasDb {
     pub fn new(options: EngineOptions) -> Result<Self> {
+        Self::with_clock(options, Box::new(SystemClock::default()))
+    }
+
+    /// 使用指定的时钟计算过期时间
+    pub fn with_clock(options: EngineOptions, clock: Box<dyn Clock>) -> Result<Self> {
+        let clock_marker_path = options.dir_path.join(CLOCK_MARKER_FILE_NAME);
         let engine = lucasdb::db::Engine::open(options)?;
-        Ok(Self { eng: engine })
+
+        let marker = match fs::read(&clock_marker_path) {
+            Ok(buf) => buf.try_into().map(u128::from_be_bytes).unwrap_or(0),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        // 当前时间比上次记录的时间还早, 说明时钟被回拨了
+        // 之后以记录的时间为准, 防止已经过期的key重新出现
+        let now = clock.now_nanos();
+        let mut clock_skew = None;
+        if now < marker {
+            let skew = Duration::from_nanos(u64::try_from(marker - now).unwrap_or(u64::MAX));
+            warn!(
+                "system clock is {:?} behind the last recorded time, expiry will use the recorded time",
+                skew
+            );
+            clock_skew = Some(skew);
+        }
+
+        let db = Self {
+            eng: engine,
+            clock,
+            clock_marker_path,
+            clock_state: Mutex::new(ClockState {
+                last: now.max(marker),
+                persisted: 0,
+            }),
+            clock_skew,
+            key_locks: (0..KEY_LOCK_NUM).map(|_| Mutex::new(())).collect(),
+            scan_cursors: Mutex::default(),
+        };
+        db.persist_clock_marker()?;
+        Ok(db)
+    }
+
+    /// 启动时检测到的时钟回拨量
+    pub fn clock_skew(&self) -> Option<Duration> {
+        self.clock_skew
+    }
+
+    /// 当前时间, 距离`UNIX_EPOCH`的纳秒数
+    /// 不会小于之前返回过的值, 时钟回拨时保持不变, 直到时钟追上来
+    pub(crate) fn now(&self) -> u128 {
+        let mut state = self
+            .clock_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        state.last = state.last.max(self.clock.now_nanos());
+        state.last
+    }
+
+    /// 写入数据前调用, 距离上次写入时间标记超过`CLOCK_MARKER_INTERVAL`时更新时间标记
+    /// 只读的命令不会写入, 关闭时会写入最后的时间
+    pub(crate) fn tick_clock_marker(&self) -> Result<()> {
+        let now = self.now();
+        if now
+            < self
+                .clock_state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .persisted
+                + CLOCK_MARKER_INTERVAL
+        {
+            return Ok(());
+        }
+        self.persist_clock_marker()
+    }
+
+    /// 立即把当前时间写入时间标记
+    /// 先写入临时文件再重命名, 写入中途崩溃不会留下不完整的时间标记
+    fn persist_clock_marker(&self) -> Result<()> {
+        let mut state = self
+            .clock_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        state.last = state.last.max(self.clock.now_nanos());
+
+        let tmp_path = self.clock_marker_path.with_extension("tmp");
+        fs::write(&tmp_path, state.last.to_be_bytes())?;
+        fs::rename(&tmp_path, &self.clock_marker_path)?;
+        state.persisted = state.last;
+        Ok(())
+    }
+
+    /// 获取`key`对应的锁, 持有期间其他线程不能修改同一个key
+    /// 锁只用来互斥, 不保护数据, 持有锁的线程 panic 之后其他线程可以继续使用
+    pub(crate) fn lock_key(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+        self.key_locks[self.key_lock_index(key)]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 同时获取多个key的锁, 按锁的下标顺序获取, 避免死锁
+    pub(crate) fn lock_keys(&self, keys: &[&[u8]]) -> Vec<MutexGuard<'_, ()>> {
+        let mut indexes: Vec<usize> = keys.iter().map(|key| self.key_lock_index(key)).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes
+            .into_iter()
+            .map(|index| {
+                self.key_locks[index]
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+            })
+            .collect()
+    }
+
+    fn key_lock_index(&self, key: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.key_locks.len()
+    }
+}
+
+impl Drop for RedisLucasDb {
+    fn drop(&mut self) {
+        if let Err(e) = self.persist_clock_marker() {
+            warn!("failed to persist clock marker: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "../tmp/redis_lucasdb/types".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_lock_key_after_panic() {
+        let name = "lock_key_after_panic";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        let db = RedisLucasDb::new(opts).unwrap();
+
+        // 持有锁的线程 panic, 同一把锁上的其他命令不受影响
+        std::thread::scope(|s| {
+            let handle = s.spawn(|| {
+                let _guard = db.lock_key(b"key");
+                panic!("panic while holding the key lock");
+            });
+            assert!(handle.join().is_err());
+        });
+        assert!(db.key_locks[db.key_lock_index(b"key")].is_poisoned());
+
+        assert!(db.hset("key", "field", "value").unwrap());
+        assert!(!db.hset("key", "field", "value2").unwrap());
+        db.set("other", Duration::ZERO, "value").unwrap();
+        let _guards = db.lock_keys(&[b"key", b"other"]);
+
+        clean(name);
     }
 }
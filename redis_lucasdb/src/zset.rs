@@ -1,12 +1,21 @@
 use crate::{
+    bytes_to_string,
+    metadata::internal_key_prefix,
     types::{RedisDataType, RedisLucasDb},
     EncodeAndDecode,
 };
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use lucasdb::{
     errors::{Errors, Result},
-    options::WriteBatchOptions,
+    options::{IteratorOptions, WriteBatchOptions},
 };
+use std::collections::HashSet;
+
+/// member 数据在 key + version 之后的标记
+const MEMBER_MARK: u8 = 0x00;
+/// score 索引在 key + version 之后的标记\
+/// member 可以是任意字节, 用不同的标记把 score 索引和 member 数据分开
+const SCORE_INDEX_MARK: u8 = 0xff;
 
 pub(crate) struct ZSetInternalKey {
     pub(crate) key: Vec<u8>,
@@ -16,75 +25,124 @@ pub(crate) struct ZSetInternalKey {
 }
 
 impl ZSetInternalKey {
-    /// 用来根据key+memer拿到score
+    /// 用来根据key+memer拿到score\
+    /// 编码格式: key + version + 0x00 + member
     fn encode_member(&self) -> bytes::Bytes {
         let mut buf = BytesMut::new();
 
-        buf.extend_from_slice(&self.key);
-        buf.put_u128(self.version);
+        buf.extend_from_slice(&internal_key_prefix(&self.key, self.version));
+        buf.put_u8(MEMBER_MARK);
         buf.extend_from_slice(&self.member);
 
         buf.into()
     }
 
-    /// 用于将member按照score进行排序
+    /// 用于将member按照score进行排序\
+    /// 编码格式: key + version + 0xff + score + member + member.len()
     fn encode_score(&self) -> bytes::Bytes {
         let mut buf = BytesMut::new();
 
-        buf.extend_from_slice(&self.key);
-        buf.put_u128(self.version);
-        // buf.put_f64(self.score);
-        buf.extend_from_slice(&self.score.to_string().as_bytes());
+        buf.extend_from_slice(&Self::score_prefix(&self.key, self.version));
+        buf.put_u64(encode_sortable_score(self.score));
         buf.extend_from_slice(&self.member);
         buf.put_u32(self.member.len() as u32);
 
         buf.into()
     }
+
+    /// `encode_score`的逆过程
+    fn decode_score(buf: &mut Bytes) -> Self {
+        let member_end = buf.len() - 4;
+        let member_len = (&buf[member_end..]).get_u32() as usize;
+        let score_end = member_end - member_len;
+        let version_end = score_end - 8 - 1;
+        let key_end = version_end - 16;
+
+        ZSetInternalKey {
+            key: buf[..key_end].to_vec(),
+            version: (&buf[key_end..version_end]).get_u128(),
+            score: decode_sortable_score((&buf[score_end - 8..score_end]).get_u64()),
+            member: buf[score_end..member_end].to_vec(),
+        }
+    }
+
+    /// 同一个 zset 中所有 score 索引的前缀
+    fn score_prefix(key: &[u8], version: u128) -> Vec<u8> {
+        let mut prefix = internal_key_prefix(key, version);
+        prefix.push(SCORE_INDEX_MARK);
+        prefix
+    }
+}
+
+/// 把 f64 编码成按字节比较时和数值顺序一致的 u64\
+/// 正数翻转符号位, 负数翻转所有位, -0.0 和 0.0 编码相同
+fn encode_sortable_score(score: f64) -> u64 {
+    let bits = (score + 0.0).to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits ^ (1 << 63)
+    }
+}
+
+fn decode_sortable_score(encoded: u64) -> f64 {
+    let bits = if encoded >> 63 == 1 {
+        encoded ^ (1 << 63)
+    } else {
+        !encoded
+    };
+    f64::from_bits(bits)
 }
 
 impl RedisLucasDb {
-    /// 不支持负数score
     /// 如果member已经存在,只更新score,返回false
-    pub fn zadd(&self, key: &str, score: f64, member: &str) -> Result<bool> {
+    pub fn zadd(
+        &self,
+        key: impl AsRef<[u8]>,
+        score: f64,
+        member: impl AsRef<[u8]>,
+    ) -> Result<bool> {
+        let key = key.as_ref();
+        let member = member.as_ref();
+        let _guard = self.lock_key(key);
+        self.put_score(key, score, member)
+    }
+
+    /// 不加锁的`zadd`
+    fn put_score(&self, key: &[u8], score: f64, member: &[u8]) -> Result<bool> {
         let mut meta = self.find_or_new_metadata(key, RedisDataType::ZSet)?;
         let internal_key = ZSetInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
             score,
-            member: member.as_bytes().to_vec(),
+            member: member.to_vec(),
         };
 
         let mut exist = true;
         let mut old_score = 0.0;
 
-        match self.eng.get(internal_key.encode_member()) {
-            Ok(val) => {
-                let val = String::from_utf8(val.to_vec())?;
-                old_score = val.parse().unwrap();
+        match self.member_score(&internal_key)? {
+            Some(score_in_db) => {
+                old_score = score_in_db;
                 if old_score == score {
                     return Ok(false);
                 }
             }
-            Err(e) => match e {
-                Errors::KeyNotFound => {
-                    exist = false;
-                }
-                _ => return Err(e),
-            },
+            None => exist = false,
         }
 
         // 更新元数据
         let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
         if !exist {
             meta.size += 1;
-            wb.put(Bytes::copy_from_slice(key.as_bytes()), meta.encode())?;
+            wb.put(Bytes::copy_from_slice(key), meta.encode())?;
         } else {
             // 删掉旧的
             let old_internal_key = ZSetInternalKey {
-                key: key.as_bytes().to_vec(),
+                key: key.to_vec(),
                 version: meta.version,
                 score: old_score,
-                member: member.as_bytes().to_vec(),
+                member: member.to_vec(),
             };
             wb.delete(old_internal_key.encode_score())?;
         }
@@ -97,18 +155,143 @@ impl RedisLucasDb {
         Ok(!exist)
     }
 
-    /// 返回key-member的score
-    pub fn zscore(&self, key: &str, member: &str) -> Result<f64> {
+    /// 读取 member 数据中保存的 score, member 不存在时返回None
+    fn member_score(&self, internal_key: &ZSetInternalKey) -> Result<Option<f64>> {
+        match self.eng.get(internal_key.encode_member()) {
+            Ok(val) => Ok(Some(bytes_to_string(val)?.parse().unwrap())),
+            Err(Errors::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 有序集合中 member 的数量
+    pub fn zcard(&self, key: impl AsRef<[u8]>) -> Result<u32> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::ZSet)?;
+        Ok(meta.size)
+    }
+
+    /// 删除多个 member, 同时删除 member 数据和 score 索引, 返回实际删除的 member 数量
+    pub fn zrem<M: AsRef<[u8]>>(&self, key: impl AsRef<[u8]>, members: &[M]) -> Result<u32> {
+        let key = key.as_ref();
+        let _guard = self.lock_key(key);
+        let mut meta = self.find_or_new_metadata(key, RedisDataType::ZSet)?;
+        if meta.size == 0 {
+            return Ok(0);
+        }
+
+        let wb = self.eng.new_write_batch(WriteBatchOptions::default())?;
+        let mut seen = HashSet::new();
+        let mut removed = 0;
+        for member in members {
+            let member = member.as_ref();
+            // 重复的 member 只删除一次
+            if !seen.insert(member) {
+                continue;
+            }
+            let mut internal_key = ZSetInternalKey {
+                key: key.to_vec(),
+                version: meta.version,
+                score: 0f64,
+                member: member.to_vec(),
+            };
+            internal_key.score = match self.member_score(&internal_key)? {
+                Some(score) => score,
+                None => continue,
+            };
+            wb.delete(internal_key.encode_member())?;
+            wb.delete(internal_key.encode_score())?;
+            removed += 1;
+        }
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        meta.size -= removed;
+        wb.put(Bytes::copy_from_slice(key), meta.encode())?;
+        wb.commit()?;
+        Ok(removed)
+    }
+
+    /// 给 member 的 score 加上`increment`, 返回新的 score, member 不存在时从0开始
+    pub fn zincrby(
+        &self,
+        key: impl AsRef<[u8]>,
+        increment: f64,
+        member: impl AsRef<[u8]>,
+    ) -> Result<f64> {
+        let key = key.as_ref();
+        let member = member.as_ref();
+        let _guard = self.lock_key(key);
         let meta = self.find_or_new_metadata(key, RedisDataType::ZSet)?;
+        let internal_key = ZSetInternalKey {
+            key: key.to_vec(),
+            version: meta.version,
+            score: 0f64,
+            member: member.to_vec(),
+        };
+
+        let score = self.member_score(&internal_key)?.unwrap_or(0f64) + increment;
+        // 比如 +inf 加上 -inf
+        if score.is_nan() {
+            return Err(Errors::ScoreIsNaN);
+        }
+        self.put_score(key, score, member)?;
+        Ok(score)
+    }
+
+    /// 按 score 从小到大排序时 member 的下标, 从0开始, member 不存在时返回None\
+    /// 按顺序遍历 score 索引, 耗时和排名成正比
+    pub fn zrank(&self, key: impl AsRef<[u8]>, member: impl AsRef<[u8]>) -> Result<Option<u64>> {
+        let key = key.as_ref();
+        let member = member.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::ZSet)?;
+        if meta.size == 0 {
+            return Ok(None);
+        }
+
+        let mut internal_key = ZSetInternalKey {
+            key: key.to_vec(),
+            version: meta.version,
+            score: 0f64,
+            member: member.to_vec(),
+        };
+        internal_key.score = match self.member_score(&internal_key)? {
+            Some(score) => score,
+            None => return Ok(None),
+        };
+        let target = internal_key.encode_score();
+
+        let iter = self.eng.iter(IteratorOptions {
+            prefix: ZSetInternalKey::score_prefix(key, meta.version),
+            reverse: false,
+            keys_only: true,
+            ..Default::default()
+        });
+        let mut rank = 0;
+        while let Some((score_key, _)) = iter.next()? {
+            if score_key == target {
+                return Ok(Some(rank));
+            }
+            rank += 1;
+        }
+        Ok(None)
+    }
+
+    /// 返回key-member的score
+    pub fn zscore(&self, key: impl AsRef<[u8]>, member: impl AsRef<[u8]>) -> Result<f64> {
+        let key = key.as_ref();
+        let member = member.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::ZSet)?;
         if meta.size == 0 {
             return Ok(-1 as f64);
         }
 
         let internal_key = ZSetInternalKey {
-            key: key.as_bytes().to_vec(),
+            key: key.to_vec(),
             version: meta.version,
             score: 0f64,
-            member: member.as_bytes().to_vec(),
+            member: member.to_vec(),
         };
 
         let score_bytes = self.eng.get(internal_key.encode_member())?;
@@ -116,6 +299,116 @@ impl RedisLucasDb {
         let score = score_str.parse().unwrap();
         Ok(score)
     }
+
+    /// 按 score 从小到大排序, 返回下标在 [start, stop] 之间的 member 和 score\
+    /// 负数下标表示从末尾开始, -1 是最后一个
+    pub fn zrange_bytes(
+        &self,
+        key: impl AsRef<[u8]>,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(Bytes, f64)>> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::ZSet)?;
+        let size = meta.size as i64;
+        let start = if start < 0 {
+            (size + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            size + stop
+        } else {
+            stop.min(size - 1)
+        };
+        if meta.size == 0 || start > stop {
+            return Ok(Vec::new());
+        }
+
+        let iter = self.eng.iter(IteratorOptions {
+            prefix: ZSetInternalKey::score_prefix(key, meta.version),
+            reverse: false,
+            keys_only: true,
+            ..Default::default()
+        });
+
+        let mut members = Vec::with_capacity((stop - start + 1) as usize);
+        let mut index = 0;
+        while let Some((mut buf, _)) = iter.next()? {
+            if index > stop {
+                break;
+            }
+            if index >= start {
+                let internal_key = ZSetInternalKey::decode_score(&mut buf);
+                members.push((Bytes::from(internal_key.member), internal_key.score));
+            }
+            index += 1;
+        }
+        Ok(members)
+    }
+
+    /// 见`zrange_bytes`, member 不是 utf8 时返回错误
+    pub fn zrange(
+        &self,
+        key: impl AsRef<[u8]>,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(String, f64)>> {
+        scored_members_to_string(self.zrange_bytes(key, start, stop)?)
+    }
+
+    /// 按 score 从小到大排序, 返回 score 在 [min, max] 之间的 member 和 score
+    pub fn zrangebyscore_bytes(
+        &self,
+        key: impl AsRef<[u8]>,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<(Bytes, f64)>> {
+        let key = key.as_ref();
+        let meta = self.find_metadata(key, RedisDataType::ZSet)?;
+        if meta.size == 0 || min > max {
+            return Ok(Vec::new());
+        }
+
+        let prefix = ZSetInternalKey::score_prefix(key, meta.version);
+        let iter = self.eng.iter(IteratorOptions {
+            prefix: prefix.clone(),
+            reverse: false,
+            keys_only: true,
+            ..Default::default()
+        });
+        // 直接跳到第一个不小于 min 的 score
+        let mut seek_key = prefix;
+        seek_key.put_u64(encode_sortable_score(min));
+        iter.seek(seek_key);
+
+        let mut members = Vec::new();
+        while let Some((mut buf, _)) = iter.next()? {
+            let internal_key = ZSetInternalKey::decode_score(&mut buf);
+            if internal_key.score > max {
+                break;
+            }
+            members.push((Bytes::from(internal_key.member), internal_key.score));
+        }
+        Ok(members)
+    }
+
+    /// 见`zrangebyscore_bytes`
+    pub fn zrangebyscore(
+        &self,
+        key: impl AsRef<[u8]>,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<(String, f64)>> {
+        scored_members_to_string(self.zrangebyscore_bytes(key, min, max)?)
+    }
+}
+
+fn scored_members_to_string(members: Vec<(Bytes, f64)>) -> Result<Vec<(String, f64)>> {
+    members
+        .into_iter()
+        .map(|(member, score)| Ok((bytes_to_string(member)?, score)))
+        .collect()
 }
 
 #[cfg(test)]
@@ -123,6 +416,7 @@ mod tests {
     use std::path::PathBuf;
 
     use lucasdb::options::EngineOptions;
+    use proptest::{collection::vec, prelude::*};
 
     use super::*;
 
@@ -181,30 +475,198 @@ mod tests {
         let (db, _) = setup(name);
 
         {
+            let res = db.zadd("key", 12f64, "member-1");
+            assert!(res.is_ok());
+            assert_eq!(res.unwrap(), true);
 
-            
-        let res = db.zadd("key", 12f64, "member-1");
-        assert!(res.is_ok());
-        assert_eq!(res.unwrap(), true);
-
-        let res = db.zadd("key", 520f64, "member-2");
-        assert!(res.is_ok());
-        assert_eq!(res.unwrap(), true);
+            let res = db.zadd("key", 520f64, "member-2");
+            assert!(res.is_ok());
+            assert_eq!(res.unwrap(), true);
         }
 
-
-
         // 获取分数
         {
             let res = db.zscore("key", "member-1");
             assert!(res.is_ok());
-            assert_eq!(res.unwrap(),12f64);
-
+            assert_eq!(res.unwrap(), 12f64);
 
             let res = db.zscore("key", "member-2");
             assert!(res.is_ok());
-            assert_eq!(res.unwrap(),520f64);
+            assert_eq!(res.unwrap(), 520f64);
+        }
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_zset_zcard_zrem_zincrby_zrank() {
+        let name = "zrem";
+        let (db, _) = setup(name);
+
+        assert_eq!(db.zcard("key").unwrap(), 0);
+        assert_eq!(db.zrank("key", "a").unwrap(), None);
+        assert_eq!(db.zrem("key", &["a"]).unwrap(), 0);
+
+        db.zadd("key", 3f64, "c").unwrap();
+        db.zadd("key", 1f64, "a").unwrap();
+        db.zadd("key", 2f64, "b").unwrap();
+        assert_eq!(db.zcard("key").unwrap(), 3);
+        assert_eq!(db.zrank("key", "a").unwrap(), Some(0));
+        assert_eq!(db.zrank("key", "c").unwrap(), Some(2));
+        assert_eq!(db.zrank("key", "missing").unwrap(), None);
+
+        // 增加分数后排名变化, 不存在的 member 从0开始
+        assert_eq!(db.zincrby("key", 10f64, "a").unwrap(), 11f64);
+        assert_eq!(db.zrank("key", "a").unwrap(), Some(2));
+        assert_eq!(db.zincrby("key", -1.5f64, "d").unwrap(), -1.5f64);
+        assert_eq!(db.zrank("key", "d").unwrap(), Some(0));
+        assert_eq!(db.zcard("key").unwrap(), 4);
+        db.zadd("inf", f64::INFINITY, "a").unwrap();
+        assert!(matches!(
+            db.zincrby("inf", f64::NEG_INFINITY, "a"),
+            Err(Errors::ScoreIsNaN)
+        ));
+
+        // 删除后 member 数据和 score 索引都不存在
+        assert_eq!(db.zrem("key", &["b"]).unwrap(), 1);
+        assert_eq!(db.zrem("key", &["b"]).unwrap(), 0);
+        assert_eq!(db.zcard("key").unwrap(), 3);
+        assert!(matches!(db.zscore("key", "b"), Err(Errors::KeyNotFound)));
+        assert_eq!(
+            db.zrange("key", 0, -1).unwrap(),
+            vec![
+                ("d".to_string(), -1.5),
+                ("c".to_string(), 3.0),
+                ("a".to_string(), 11.0),
+            ]
+        );
+
+        // 一次删除多个 member, 重复和不存在的 member 不计数
+        assert_eq!(db.zrem("key", &["d", "missing", "a", "d"]).unwrap(), 2);
+        assert_eq!(db.zcard("key").unwrap(), 1);
+        assert_eq!(
+            db.zrange("key", 0, -1).unwrap(),
+            vec![("c".to_string(), 3.0)]
+        );
+
+        clean(name);
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_zset_internal_key_round_trip(
+            key in vec(any::<u8>(), 0..32),
+            version in any::<u128>(),
+            score in any::<f64>().prop_filter("NaN", |s| !s.is_nan()),
+            member in vec(any::<u8>(), 0..32),
+        ) {
+            let internal_key = ZSetInternalKey { key: key.clone(), version, score, member: member.clone() };
+            let member_key = internal_key.encode_member();
+            prop_assert!(member_key.starts_with(&internal_key_prefix(&key, version)));
+            prop_assert_eq!(&member_key[member_key.len() - member.len()..], &member[..]);
+
+            let decoded = ZSetInternalKey::decode_score(&mut internal_key.encode_score());
+            prop_assert_eq!(decoded.key, key);
+            prop_assert_eq!(decoded.version, version);
+            // -0.0 和 0.0 编码相同
+            prop_assert_eq!(decoded.score, score + 0.0);
+            prop_assert_eq!(decoded.member, member);
+        }
+
+        #[test]
+        fn proptest_zset_sortable_score(a in any::<f64>(), b in any::<f64>()) {
+            prop_assume!(!a.is_nan() && !b.is_nan());
+            let (ea, eb) = (encode_sortable_score(a), encode_sortable_score(b));
+            prop_assert_eq!(a.partial_cmp(&b), Some(ea.cmp(&eb)));
+            prop_assert_eq!(ea.cmp(&eb), ea.to_be_bytes().cmp(&eb.to_be_bytes()));
         }
+    }
+
+    #[test]
+    fn test_zset_sortable_score() {
+        let scores = [
+            f64::NEG_INFINITY,
+            -1e10,
+            -2.5,
+            -1.0,
+            -0.0,
+            0.0,
+            1e-10,
+            1.0,
+            2.5,
+            10.0,
+            1e10,
+            f64::INFINITY,
+        ];
+        for pair in scores.windows(2) {
+            let (a, b) = (
+                encode_sortable_score(pair[0]),
+                encode_sortable_score(pair[1]),
+            );
+            assert!(a <= b, "{} should sort before {}", pair[0], pair[1]);
+            assert!(a.to_be_bytes() <= b.to_be_bytes());
+        }
+        for score in scores {
+            assert_eq!(decode_sortable_score(encode_sortable_score(score)), score);
+        }
+    }
+
+    #[test]
+    fn test_zset_zrange() {
+        let name = "zrange";
+        let (db, _) = setup(name);
+
+        assert!(db.zrange("key", 0, -1).unwrap().is_empty());
+
+        db.zadd("key", 10f64, "ten").unwrap();
+        db.zadd("key", -2.5f64, "minus").unwrap();
+        db.zadd("key", 9f64, "nine").unwrap();
+        db.zadd("key", 100f64, "hundred").unwrap();
+        db.zadd("key", 0f64, "zero").unwrap();
+        // 修改分数之后位置也会变化
+        db.zadd("key", 1f64, "hundred").unwrap();
+        // 以 key 开头的其他 zset 不会被遍历到
+        db.zadd("key-2", 5f64, "other").unwrap();
+
+        let members = |items: Vec<(String, f64)>| -> Vec<String> {
+            items.into_iter().map(|(member, _)| member).collect()
+        };
+
+        assert_eq!(
+            db.zrange("key", 0, -1).unwrap(),
+            vec![
+                ("minus".to_string(), -2.5),
+                ("zero".to_string(), 0.0),
+                ("hundred".to_string(), 1.0),
+                ("nine".to_string(), 9.0),
+                ("ten".to_string(), 10.0),
+            ]
+        );
+        assert_eq!(
+            members(db.zrange("key", 1, 2).unwrap()),
+            vec!["zero", "hundred"]
+        );
+        assert_eq!(
+            members(db.zrange("key", -2, 100).unwrap()),
+            vec!["nine", "ten"]
+        );
+        assert!(db.zrange("key", 3, 1).unwrap().is_empty());
+        assert!(db.zrange("key", 5, 10).unwrap().is_empty());
+
+        assert_eq!(
+            members(db.zrangebyscore("key", 0.0, 9.0).unwrap()),
+            vec!["zero", "hundred", "nine"]
+        );
+        assert_eq!(
+            members(db.zrangebyscore("key", f64::NEG_INFINITY, -1.0).unwrap()),
+            vec!["minus"]
+        );
+        assert_eq!(
+            members(db.zrangebyscore("key", 9.5, f64::INFINITY).unwrap()),
+            vec!["ten"]
+        );
+        assert!(db.zrangebyscore("key", 2.0, 3.0).unwrap().is_empty());
+        assert_eq!(db.zscore("key", "hundred").unwrap(), 1.0);
 
         clean(name);
     }
@@ -1,8 +1,10 @@
 use crate::{
-    data::log_record::{LogRecord, LogRecordType},
+    data::log_record::{now_timestamp, LogRecord, LogRecordType},
     db::Engine,
     options::WriteBatchOptions,
     prelude::*,
+    utils,
+    watch::Operation,
 };
 use std::{
     collections::HashMap,
@@ -11,6 +13,7 @@ use std::{
 
 use bytes::Bytes;
 use parking_lot::Mutex;
+use tracing::{field::Empty, instrument, Span};
 
 use super::log_record_key_with_seq;
 
@@ -23,6 +26,7 @@ pub struct WriteBatch<'a> {
 
 impl Engine {
     pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch> {
+        self.check_writable()?;
         Ok(WriteBatch {
             pending_wirtes: Arc::new(Mutex::new(HashMap::new())),
             engine: self,
@@ -33,33 +37,54 @@ impl Engine {
 
 impl WriteBatch<'_> {
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
-        if key.is_empty() {
-            return Err(Errors::KeyIsEmpty);
-        }
+        self.engine.check_key_value_size(&key, &value)?;
 
         // 暂存数据
         let log_record = LogRecord {
             key: key.to_vec(),
             value: value.to_vec(),
             rec_type: LogRecordType::Normal,
+            timestamp: 0,
         };
 
         let mut pending_write = self.pending_wirtes.lock();
 
-        pending_write.insert(key.to_vec(), log_record);
+        self.add_pending_size(&log_record);
+        if let Some(old_record) = pending_write.insert(key.to_vec(), log_record) {
+            self.sub_pending_size(&old_record);
+        }
         Ok(())
     }
 
+    /// 读取`key`, 先查找这个批处理中还没有提交的数据, 没有时再从数据库中读取
+    /// 在批处理中被删除的`key`返回`Errors::KeyNotFound`
+    pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let pending_write = self.pending_wirtes.lock();
+        if let Some(record) = pending_write.get(key.as_ref()) {
+            return match record.rec_type {
+                LogRecordType::Deleted => Err(Errors::KeyNotFound),
+                _ => Ok(Bytes::from(record.value.clone())),
+            };
+        }
+        drop(pending_write);
+
+        self.engine.get(key)
+    }
+
     pub fn delete(&self, key: Bytes) -> Result<()> {
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
         let mut pending_write = self.pending_wirtes.lock();
-        let index_pos = self.engine.index.get(key.to_vec());
+        let index_pos = self.engine.index.get(key.to_vec())?;
         if index_pos.is_none() {
             // 检查pending_wirte
-            if pending_write.contains_key(&key.to_vec()) {
-                pending_write.remove(&key.to_vec());
+            if let Some(old_record) = pending_write.remove(&key.to_vec()) {
+                self.sub_pending_size(&old_record);
             }
 
             return Ok(());
@@ -70,62 +95,199 @@ impl WriteBatch<'_> {
             key: key.to_vec(),
             value: Default::default(),
             rec_type: LogRecordType::Deleted,
+            timestamp: 0,
         };
 
-        pending_write.insert(key.to_vec(), log_record);
+        self.add_pending_size(&log_record);
+        if let Some(old_record) = pending_write.insert(key.to_vec(), log_record) {
+            self.sub_pending_size(&old_record);
+        }
         Ok(())
     }
 
+    /// 放弃所有还没提交的数据
+    pub fn rollback(&self) {
+        let mut pending_write = self.pending_wirtes.lock();
+        self.clear_pending(&mut pending_write);
+    }
+
+    /// 暂存数据占用的内存, 包括 HashMap 的 key 和 `LogRecord`
+    fn pending_size(record: &LogRecord) -> usize {
+        record.key.len() * 2 + record.value.len() + std::mem::size_of::<(Vec<u8>, LogRecord)>()
+    }
+
+    fn add_pending_size(&self, record: &LogRecord) {
+        self.engine
+            .pending_batch_bytes
+            .fetch_add(Self::pending_size(record), Ordering::SeqCst);
+    }
+
+    fn sub_pending_size(&self, record: &LogRecord) {
+        self.engine
+            .pending_batch_bytes
+            .fetch_sub(Self::pending_size(record), Ordering::SeqCst);
+    }
+
+    fn clear_pending(&self, pending_write: &mut HashMap<Vec<u8>, LogRecord>) {
+        for record in pending_write.values() {
+            self.sub_pending_size(record);
+        }
+        pending_write.clear();
+    }
+
+    /// `key`和`value`的字节数, 用于检查`max_batch_bytes`
+    fn record_bytes(record: &LogRecord) -> usize {
+        record.key.len() + record.value.len()
+    }
+
+    /// 按`max_batch_num`和`max_batch_bytes`把暂存的数据分成若干个事务
+    /// 没有开启`auto_commit_chunks`时超过限制直接报错
+    fn split_chunks(
+        &self,
+        pending_write: &HashMap<Vec<u8>, LogRecord>,
+    ) -> Result<Vec<Vec<Vec<u8>>>> {
+        let max_num = self.options.max_batch_num as usize;
+        let max_bytes = self.options.max_batch_bytes.unwrap_or(usize::MAX);
+
+        if !self.options.auto_commit_chunks {
+            if pending_write.len() > max_num {
+                return Err(Errors::ExceedMaxBatchNum {
+                    max: self.options.max_batch_num,
+                    current: pending_write.len() as u32,
+                });
+            }
+            let total_bytes = pending_write.values().map(Self::record_bytes).sum();
+            if total_bytes > max_bytes {
+                return Err(Errors::ExceedMaxBatchBytes {
+                    max: max_bytes,
+                    current: total_bytes,
+                });
+            }
+            return Ok(vec![pending_write.keys().cloned().collect()]);
+        }
+
+        let mut chunks = Vec::new();
+        let mut chunk = Vec::new();
+        let mut chunk_bytes = 0;
+        for (key, record) in pending_write.iter() {
+            // 单条数据超过限制时无法拆分
+            let bytes = Self::record_bytes(record);
+            if bytes > max_bytes {
+                return Err(Errors::ExceedMaxBatchBytes {
+                    max: max_bytes,
+                    current: bytes,
+                });
+            }
+            if !chunk.is_empty() && (chunk.len() >= max_num || chunk_bytes + bytes > max_bytes) {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_bytes = 0;
+            }
+            chunk.push(key.clone());
+            chunk_bytes += bytes;
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+
     /// 提交数据,更新内存索引
+    /// 写入到一半失败时, 已经写入的数据没有 TxnFinished 标识, 重启时会被丢弃
     pub fn commit(&self) -> Result<()> {
+        self.commit_with_seq_nos().map(|_| ())
+    }
+
+    /// 提交数据, 按顺序返回使用的事务序列号, 没有暂存的数据时返回空列表
+    /// 开启`auto_commit_chunks`时可能拆成多个事务, 某个事务失败时之前的事务已经生效,
+    /// 没有提交的数据仍然暂存在批处理中, 可以再次提交
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(records = Empty, txns = Empty, elapsed_us = Empty)
+    )]
+    pub fn commit_with_seq_nos(&self) -> Result<Vec<usize>> {
+        let _timer = utils::trace::span_timer();
         let mut pending_write = self.pending_wirtes.lock();
+        Span::current().record("records", pending_write.len());
         if pending_write.len() == 0 {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
-        if pending_write.len() as u32 > self.options.max_batch_num {
-            return Err(Errors::ExceedMaxBatchNum {
-                max: self.options.max_batch_num,
-                current: pending_write.len() as u32,
-            });
-        }
+        let chunks = self.split_chunks(&pending_write)?;
+        Span::current().record("txns", chunks.len());
 
-        // 加锁保证串行化
+        // 加锁保证串行化, 拆分出的事务使用连续的序列号
         let _lock = self.engine.batch_commit_lock.lock();
 
+        let mut seq_nos = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            let items: Vec<&LogRecord> = chunk.iter().map(|key| &pending_write[key]).collect();
+            seq_nos.push(self.commit_txn(&items)?);
+
+            // 清空已经提交的暂存数据
+            for key in chunk {
+                if let Some(record) = pending_write.remove(&key) {
+                    self.sub_pending_size(&record);
+                }
+            }
+        }
+
+        Ok(seq_nos)
+    }
+
+    /// 把`items`作为一个事务写入数据文件并更新内存索引, 返回事务序列号
+    /// 调用时需要持有`batch_commit_lock`
+    fn commit_txn(&self, items: &[&LogRecord]) -> Result<usize> {
         // 获取全局事务序列号
         // 让当前seq_no+1, 然后返回上一个seq_no的值
         let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
+        // 同一个事务中的数据使用提交时的时间戳
+        let timestamp = now_timestamp();
 
         // 写到数据文件中
-        let mut positions = HashMap::new();
-        for (_, item) in pending_write.iter() {
-            let mut record = LogRecord {
-                key: log_record_key_with_seq(item.key.clone(), seq_no)?,
-                value: item.value.clone(),
-                rec_type: item.rec_type,
-            };
+        let write_res = (|| {
+            let mut positions = HashMap::new();
+            for item in items {
+                let mut record = LogRecord {
+                    key: log_record_key_with_seq(item.key.clone(), seq_no)?,
+                    value: item.value.clone(),
+                    rec_type: item.rec_type,
+                    timestamp,
+                };
+
+                let pos = self.engine.append_log_record(&mut record)?;
+                positions.insert(item.key.clone(), pos);
+                if let Some(listener) = self.engine.event_listener() {
+                    listener.on_txn_record_append(seq_no, positions.len());
+                }
+            }
 
-            let pos = self.engine.append_log_record(&mut record)?;
-            positions.insert(item.key.clone(), pos);
-        }
+            // 标识事务完成
+            let mut finish_log_record = LogRecord {
+                key: log_record_key_with_seq(TXN_FINISHED_KEY.to_vec(), seq_no)?,
+                value: Default::default(),
+                rec_type: LogRecordType::TxnFinished,
+                timestamp: 0,
+            };
 
-        // 标识事务完成
-        let mut finish_log_record = LogRecord {
-            key: log_record_key_with_seq(TXN_FINISHED_KEY.to_vec(), seq_no)?,
-            value: Default::default(),
-            rec_type: LogRecordType::TxnFinished,
+            self.engine.append_log_record(&mut finish_log_record)?;
+            Ok(positions)
+        })();
+        let positions = match write_res {
+            Ok(positions) => positions,
+            Err(e) => {
+                self.engine.aborted_txn_seqs.lock().insert(seq_no);
+                return Err(e);
+            }
         };
 
-        self.engine.append_log_record(&mut finish_log_record)?;
-
         // 如果配置了持久化,就sync
         if self.options.sync_writes {
             self.engine.sync()?;
         }
 
         // 更新内存索引
-        for (_, item) in pending_write.iter() {
+        for item in items {
             let record_pos = positions.get(&item.key);
             if record_pos.is_none() {
                 continue;
@@ -134,34 +296,51 @@ impl WriteBatch<'_> {
 
             match item.rec_type {
                 LogRecordType::Deleted => {
-                    if let Some(old_pos) = self.engine.index.delete(item.key.clone()) {
-                        self.engine
-                            .reclaim_size
-                            .fetch_add(old_pos.size, Ordering::SeqCst);
+                    if let Some(old_pos) = self.engine.update_index(item.key.clone(), None)? {
+                        self.engine.add_reclaim_size(&old_pos);
                     }
                 }
                 _ => {
-                    if let Some(old_pos) = self.engine.index.put(item.key.clone(), *record_pos) {
-                        self.engine
-                            .reclaim_size
-                            .fetch_add(old_pos.size, Ordering::SeqCst);
+                    if let Some(old_pos) = self
+                        .engine
+                        .update_index(item.key.clone(), Some(*record_pos))?
+                    {
+                        self.engine.add_reclaim_size(&old_pos);
                     }
                 }
             }
         }
 
-        // 清空暂存数据
-        pending_write.clear();
+        // 按写入顺序通知订阅者
+        let mut events: Vec<_> = items
+            .iter()
+            .filter_map(|item| positions.get(&item.key).map(|pos| (pos, item)))
+            .collect();
+        events.sort_by_key(|(pos, _)| (pos.file_id, pos.offset));
+        for (pos, item) in events {
+            let op = match item.rec_type {
+                LogRecordType::Deleted => Operation::Delete,
+                _ => Operation::Put,
+            };
+            self.engine.notify_watchers(op, &item.key, &item.value, pos);
+        }
 
-        Ok(())
+        Ok(seq_no)
+    }
+}
+
+impl Drop for WriteBatch<'_> {
+    fn drop(&mut self) {
+        let mut pending_write = self.pending_wirtes.lock();
+        self.clear_pending(&mut pending_write);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
+    use std::{path::PathBuf, sync::Arc};
 
-    use crate::options::EngineOptions;
+    use crate::{event::EventListener, options::EngineOptions};
 
     use super::*;
     fn basepath() -> PathBuf {
@@ -305,6 +484,49 @@ mod tests {
         clean("delete");
     }
 
+    #[test]
+    fn test_write_batch_read_own_writes() {
+        setup("read_own_writes");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("read_own_writes");
+        let db = Engine::open(opts).expect("failed to open database");
+
+        db.put(Bytes::from("key-1"), Bytes::from("value-1"))
+            .unwrap();
+        db.put(Bytes::from("key-2"), Bytes::from("value-2"))
+            .unwrap();
+
+        let wb = db
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("new write batch failed");
+        // 没有暂存的数据时从数据库读取
+        assert_eq!(wb.get(Bytes::from("key-1")).unwrap(), "value-1");
+
+        wb.put(Bytes::from("key-1"), Bytes::from("value-1-new"))
+            .unwrap();
+        wb.put(Bytes::from("key-3"), Bytes::from("value-3"))
+            .unwrap();
+        wb.delete(Bytes::from("key-2")).unwrap();
+        assert_eq!(wb.get(Bytes::from("key-1")).unwrap(), "value-1-new");
+        assert_eq!(wb.get(Bytes::from("key-3")).unwrap(), "value-3");
+        assert!(matches!(
+            wb.get(Bytes::from("key-2")),
+            Err(Errors::KeyNotFound)
+        ));
+        assert!(matches!(wb.get(Bytes::new()), Err(Errors::KeyIsEmpty)));
+
+        // 提交之前数据库中的数据不变
+        assert_eq!(db.get(Bytes::from("key-1")).unwrap(), "value-1");
+        assert_eq!(db.get(Bytes::from("key-2")).unwrap(), "value-2");
+        assert!(db.get(Bytes::from("key-3")).is_err());
+
+        wb.commit().unwrap();
+        assert_eq!(db.get(Bytes::from("key-1")).unwrap(), "value-1-new");
+        assert!(db.get(Bytes::from("key-2")).is_err());
+
+        clean("read_own_writes");
+    }
+
     #[test]
     fn test_write_batch_after_reopen() {
         // 重启之后读取事务序列号
@@ -340,10 +562,228 @@ mod tests {
         db.close().expect("failed to close database");
         let db = Engine::open(opts.clone()).expect("failed to open database");
 
-        // 验证事务序列号
+        // 验证事务序列号, 重启后不会复用已经用过的序列号
         let seq_no = db.seq_no.load(Ordering::SeqCst);
-        assert_eq!(3, seq_no);
+        assert_eq!(4, seq_no);
 
         clean("reopen");
     }
+
+    #[test]
+    fn test_write_batch_rollback() {
+        setup("rollback");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("rollback");
+
+        let db = Engine::open(opts).expect("failed to open database");
+        let wb = db
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("new write batch failed");
+
+        assert!(wb.put(Bytes::from("key-1"), Bytes::from("value-1")).is_ok());
+        assert!(wb.put(Bytes::from("key-2"), Bytes::from("value-2")).is_ok());
+        wb.rollback();
+
+        // 回滚之后提交, 什么都不会写入
+        assert!(wb.commit().is_ok());
+        assert!(db.get(Bytes::from("key-1")).is_err());
+        assert!(db.get(Bytes::from("key-2")).is_err());
+
+        // 回滚之后还可以继续使用
+        assert!(wb.put(Bytes::from("key-3"), Bytes::from("value-3")).is_ok());
+        assert!(wb.commit().is_ok());
+        assert_eq!(
+            db.get(Bytes::from("key-3")).unwrap(),
+            Bytes::from("value-3")
+        );
+
+        clean("rollback");
+    }
+
+    const CRASH_AFTER_RECORDS_ENV: &str = "LUCASDB_CRASH_AFTER_RECORDS";
+    const CRASH_DIR_ENV: &str = "LUCASDB_CRASH_DIR";
+
+    /// 故障注入: 提交时写入了指定条数的数据后直接终止进程, 模拟崩溃
+    struct CrashListener {
+        after_records: usize,
+    }
+
+    impl EventListener for CrashListener {
+        fn on_txn_record_append(&self, _seq_no: usize, written: usize) {
+            if written == self.after_records {
+                std::process::abort();
+            }
+        }
+    }
+
+    /// 由 `test_write_batch_crash_mid_commit` 在子进程中运行
+    #[test]
+    #[ignore]
+    fn test_write_batch_crash_child() {
+        let dir_path = match std::env::var(CRASH_DIR_ENV) {
+            Ok(dir_path) => PathBuf::from(dir_path),
+            Err(_) => return,
+        };
+        let after_records = std::env::var(CRASH_AFTER_RECORDS_ENV)
+            .expect("crash point is not set")
+            .parse()
+            .expect("invalid crash point");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = dir_path;
+        opts.event_listener = Some(Arc::new(CrashListener { after_records }));
+        let db = Engine::open(opts).expect("failed to open database");
+
+        let wb = db
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("new write batch failed");
+        let _ = wb.put(Bytes::from("key-0"), Bytes::from("new-value"));
+        for i in 1..5 {
+            let _ = wb.put(Bytes::from(format!("key-{}", i)), Bytes::from("value"));
+        }
+        let _ = wb.commit();
+        panic!("should crash before commit finished");
+    }
+
+    #[test]
+    fn test_write_batch_crash_mid_commit() {
+        let name = "crash";
+        clean(name);
+        setup(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+
+        {
+            let db = Engine::open(opts.clone()).expect("failed to open database");
+            db.put(Bytes::from("key-0"), Bytes::from("old-value"))
+                .unwrap();
+        }
+
+        // 子进程提交到一半时崩溃
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "batch::batch::tests::test_write_batch_crash_child",
+                "--ignored",
+                "--nocapture",
+            ])
+            .env(CRASH_AFTER_RECORDS_ENV, "2")
+            .env(CRASH_DIR_ENV, &opts.dir_path)
+            .status()
+            .expect("failed to run child process");
+        assert!(!status.success());
+
+        // 重启后, 提交到一半的数据不可见
+        let db = Engine::open(opts.clone()).expect("failed to open database");
+        assert_eq!(
+            db.get(Bytes::from("key-0")).unwrap(),
+            Bytes::from("old-value")
+        );
+        for i in 1..5 {
+            assert!(db.get(Bytes::from(format!("key-{}", i))).is_err());
+        }
+        assert!(db.reclaim_size.load(Ordering::SeqCst) > 0);
+
+        // 新的事务不会把残留的数据一起提交
+        {
+            let wb = db
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("new write batch failed");
+            let _ = wb.put(Bytes::from("key-10"), Bytes::from("value-10"));
+            wb.commit().unwrap();
+        }
+        std::mem::drop(db);
+
+        let db = Engine::open(opts.clone()).expect("failed to open database");
+        assert_eq!(
+            db.get(Bytes::from("key-0")).unwrap(),
+            Bytes::from("old-value")
+        );
+        assert_eq!(
+            db.get(Bytes::from("key-10")).unwrap(),
+            Bytes::from("value-10")
+        );
+        for i in 1..5 {
+            assert!(db.get(Bytes::from(format!("key-{}", i))).is_err());
+        }
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_write_batch_chunked_commit() {
+        let name = "chunked";
+        clean(name);
+        setup(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        let db = Engine::open(opts.clone()).expect("failed to open database");
+
+        // 没有开启自动拆分时, 超过字节数限制直接报错, 数据仍然暂存
+        let wb = db
+            .new_write_batch(
+                WriteBatchOptions::builder()
+                    .max_batch_num(100)
+                    .sync_writes(false)
+                    .max_batch_bytes(64)
+                    .build(),
+            )
+            .unwrap();
+        for i in 0..10 {
+            wb.put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        assert!(matches!(
+            wb.commit(),
+            Err(Errors::ExceedMaxBatchBytes {
+                max: 64,
+                current: 100
+            })
+        ));
+        assert!(db.get(Bytes::from("key-0")).is_err());
+        assert_eq!(wb.get(Bytes::from("key-0")).unwrap(), "value");
+        drop(wb);
+
+        // 开启之后按条数和字节数拆成多个连续的事务
+        let wb = db
+            .new_write_batch(
+                WriteBatchOptions::builder()
+                    .max_batch_num(3)
+                    .sync_writes(false)
+                    .max_batch_bytes(64)
+                    .auto_commit_chunks(true)
+                    .build(),
+            )
+            .unwrap();
+        for i in 0..10 {
+            wb.put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        let first = db.seq_no.load(Ordering::SeqCst);
+        let seq_nos = wb.commit_with_seq_nos().unwrap();
+        assert_eq!(seq_nos, (first..first + 4).collect::<Vec<_>>());
+        assert!(wb.commit_with_seq_nos().unwrap().is_empty());
+        assert_eq!(db.pending_batch_bytes.load(Ordering::SeqCst), 0);
+
+        // 单条数据超过字节数限制时无法拆分
+        wb.put(Bytes::from("big"), Bytes::from(vec![0u8; 64]))
+            .unwrap();
+        assert!(matches!(
+            wb.commit(),
+            Err(Errors::ExceedMaxBatchBytes {
+                max: 64,
+                current: 67
+            })
+        ));
+        wb.rollback();
+        drop(wb);
+
+        // 重启之后每个事务的数据都可见
+        std::mem::drop(db);
+        let db = Engine::open(opts).expect("failed to reopen database");
+        for i in 0..10 {
+            assert_eq!(db.get(Bytes::from(format!("key-{}", i))).unwrap(), "value");
+        }
+
+        clean(name);
+    }
 }
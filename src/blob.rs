@@ -0,0 +1,510 @@
+use crate::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    batch::parse_log_record_key,
+    data::{
+        data_file::{get_blob_file_name, DataFile},
+        encryption::RecordCipher,
+        log_record::{LogRecord, LogRecordPos, LogRecordType},
+        BLOB_FILE_NAME_SUFFIX,
+    },
+    db::{older_file_io_type, Engine},
+    fio::IOType,
+    options::EngineOptions,
+};
+
+/// merge 之后需要删除的 blob 文件, 在 merge 的临时目录中, 内容是逗号分隔的文件id
+pub(crate) const BLOB_GC_FILE_NAME: &str = "blob-gc";
+
+/// 数据目录中的 blob 文件
+/// 打开数据库之后第一次写入大`value`时创建新的文件, 不会追加到上次可能写了一半的文件后面
+pub(crate) struct BlobFiles {
+    /// 正在写入的文件
+    active: Option<DataFile>,
+    /// 不再写入的文件
+    older: HashMap<u32, DataFile>,
+    /// 下一个可以使用的文件id, merge 重写 blob 时也从这里分配
+    next_file_id: u32,
+    /// 最近一次`rotate`时的`next_file_id`, id 比它小的文件不会再写入
+    sealed_before: u32,
+}
+
+impl BlobFiles {
+    /// 加载数据目录中的 blob 文件
+    pub(crate) fn load(options: &EngineOptions, cipher: Option<RecordCipher>) -> Result<Self> {
+        let mut older = HashMap::new();
+        let mut next_file_id = 0;
+        // 内存模式没有数据目录, 也不会分离大`value`
+        let entries = match options.ephemeral {
+            true => Vec::new(),
+            false => fs::read_dir(&options.dir_path)?.collect(),
+        };
+        for entry in entries {
+            let file_name = entry?.file_name();
+            let Some(file_id) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(BLOB_FILE_NAME_SUFFIX))
+                .and_then(|id| id.parse::<u32>().ok())
+            else {
+                continue;
+            };
+
+            let blob_file = match options.read_only {
+                true => DataFile::open_blob_read_only(options.dir_path.clone(), file_id)?,
+                false => DataFile::new_blob_file(
+                    options.dir_path.clone(),
+                    file_id,
+                    older_file_io_type(options),
+                )?,
+            };
+            let blob_file = blob_file
+                .with_cipher(cipher.clone())
+                .with_checksum(options.checksum);
+            older.insert(file_id, blob_file);
+            next_file_id = next_file_id.max(file_id + 1);
+        }
+
+        Ok(BlobFiles {
+            active: None,
+            older,
+            next_file_id,
+            sealed_before: next_file_id,
+        })
+    }
+
+    pub(crate) fn get(&self, file_id: u32) -> Option<&DataFile> {
+        match &self.active {
+            Some(active) if active.get_file_id() == file_id => Some(active),
+            _ => self.older.get(&file_id),
+        }
+    }
+
+    /// 追加一条编码之后的 blob, 当前文件写不下时换一个新文件
+    fn append(
+        &mut self,
+        options: &EngineOptions,
+        cipher: Option<RecordCipher>,
+        encoded_blob: &[u8],
+    ) -> Result<LogRecordPos> {
+        let len = encoded_blob.len() as u64;
+        if self
+            .active
+            .as_ref()
+            .is_some_and(|active| active.get_write_off() + len > options.data_file_size)
+        {
+            self.seal()?;
+        }
+
+        let active = match self.active.take() {
+            Some(active) => active,
+            None => {
+                let file_id = self.allocate_file_id();
+                DataFile::new_blob_file(options.dir_path.clone(), file_id, IOType::StandardFileIO)?
+                    .with_cipher(cipher)
+                    .with_checksum(options.checksum)
+            }
+        };
+        let pos = LogRecordPos {
+            file_id: active.get_file_id(),
+            offset: active.get_write_off(),
+            size: encoded_blob.len(),
+        };
+        let res = active.write(encoded_blob);
+        self.active = Some(active);
+        res?;
+        Ok(pos)
+    }
+
+    /// 持久化正在写入的文件, 然后不再写入
+    fn seal(&mut self) -> Result<()> {
+        if let Some(active) = self.active.take() {
+            active.sync()?;
+            self.older.insert(active.get_file_id(), active);
+        }
+        Ok(())
+    }
+
+    /// 和活跃文件一起切换, 之后写入的 blob 都在 id 不小于`sealed_before`的文件中
+    pub(crate) fn rotate(&mut self) -> Result<()> {
+        self.seal()?;
+        self.sealed_before = self.next_file_id;
+        Ok(())
+    }
+
+    fn allocate_file_id(&mut self) -> u32 {
+        let file_id = self.next_file_id;
+        self.next_file_id += 1;
+        file_id
+    }
+
+    /// 持久化正在写入的文件, 需要在持久化引用它的数据文件之前调用
+    pub(crate) fn sync(&self) -> Result<()> {
+        match &self.active {
+            Some(active) => active.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// 正在写入的文件id和写入的位置
+    pub(crate) fn active_write_off(&self) -> Option<(u32, u64)> {
+        self.active
+            .as_ref()
+            .map(|active| (active.get_file_id(), active.get_write_off()))
+    }
+
+    pub(crate) fn next_file_id(&self) -> u32 {
+        self.next_file_id
+    }
+
+    /// 所有 blob 文件的大小
+    pub(crate) fn size(&self) -> Result<u64> {
+        let mut size = 0;
+        for blob_file in self.older.values().chain(self.active.iter()) {
+            size += blob_file.file_size()?;
+        }
+        Ok(size)
+    }
+}
+
+/// merge 时把还有效的 blob 复制到新的 blob 文件中, 新文件写在 merge 的临时目录
+pub(crate) struct BlobRewriter<'a> {
+    engine: &'a Engine,
+    merge_path: PathBuf,
+    /// 需要重写的 blob 文件
+    gc_file_ids: HashSet<u32>,
+    current: Option<DataFile>,
+}
+
+impl<'a> BlobRewriter<'a> {
+    /// `value`是数据文件中的 blob 位置, 在需要重写的文件中时复制到新文件, 返回新的位置
+    pub(crate) fn rewrite(&mut self, value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let pos = LogRecordPos::decode(value.to_vec())?;
+        if !self.gc_file_ids.contains(&pos.file_id) {
+            return Ok(None);
+        }
+
+        // 复制编码之后的数据, 不需要解压和解密
+        let raw = {
+            let blob_files = self.engine.blob_files.read();
+            let blob_file = blob_files
+                .get(pos.file_id)
+                .ok_or(Errors::DataFileNotFound)?;
+            blob_file.read_raw(pos.offset, pos.size)?
+        };
+
+        let data_file_size = self.engine.options.data_file_size;
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|current| current.get_write_off() + raw.len() as u64 > data_file_size)
+        {
+            self.finish()?;
+        }
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => {
+                let file_id = self.engine.blob_files.write().allocate_file_id();
+                DataFile::new_blob_file(self.merge_path.clone(), file_id, IOType::StandardFileIO)?
+                    .with_checksum(self.engine.options.checksum)
+            }
+        };
+        let new_pos = LogRecordPos {
+            file_id: current.get_file_id(),
+            offset: current.get_write_off(),
+            size: raw.len(),
+        };
+        let res = current.write(&raw);
+        self.current = Some(current);
+        res?;
+        Ok(Some(new_pos.encode()?))
+    }
+
+    /// 持久化正在写入的文件
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        if let Some(current) = self.current.take() {
+            current.sync()?;
+        }
+        Ok(())
+    }
+
+    /// 记录需要删除的 blob 文件, 重启之后 merge 的结果生效时删除
+    pub(crate) fn write_gc_file(&self) -> Result<()> {
+        let mut file_ids: Vec<u32> = self.gc_file_ids.iter().copied().collect();
+        file_ids.sort();
+        let content: Vec<String> = file_ids.iter().map(|id| id.to_string()).collect();
+        fs::write(self.merge_path.join(BLOB_GC_FILE_NAME), content.join(","))?;
+        Ok(())
+    }
+}
+
+impl Engine {
+    /// 是否要把`log_record`的`value`写到 blob 文件
+    pub(crate) fn is_big_value(&self, log_record: &LogRecord) -> bool {
+        log_record.rec_type == LogRecordType::Normal
+            && self
+                .options
+                .big_value_threshold
+                .is_some_and(|threshold| log_record.value.len() > threshold)
+    }
+
+    /// 写入编码之后的 blob, 返回写到数据文件中代替它的`LogRecord`
+    /// 调用时需要持有活跃文件的写锁, blob 文件和活跃文件一起切换
+    /// 数据文件中的`LogRecord`使用原来的时间戳
+    pub(crate) fn append_blob(
+        &self,
+        key: &[u8],
+        encoded_blob: &[u8],
+        timestamp: u64,
+    ) -> Result<LogRecord> {
+        let pos =
+            self.blob_files
+                .write()
+                .append(&self.options, self.cipher.clone(), encoded_blob)?;
+        self.disk_size
+            .fetch_add(encoded_blob.len() as u64, Ordering::SeqCst);
+        Ok(LogRecord {
+            key: key.to_vec(),
+            value: pos.encode()?,
+            rec_type: LogRecordType::BlobIndex,
+            timestamp,
+        })
+    }
+
+    /// 数据文件中读到的`value`是 blob 的位置时, 从 blob 文件中读取真正的`value`
+    pub(crate) fn resolve_blob(&self, rec_type: LogRecordType, value: Vec<u8>) -> Result<Vec<u8>> {
+        if rec_type != LogRecordType::BlobIndex {
+            return Ok(value);
+        }
+
+        let pos = LogRecordPos::decode(value)?;
+        let blob_files = self.blob_files.read();
+        let blob_file = blob_files
+            .get(pos.file_id)
+            .ok_or(Errors::DataFileNotFound)?;
+        match blob_file.read_log_record_bytes(pos.offset, Some(pos.size)) {
+            Ok(read_log_record) => Ok(read_log_record.value.to_vec()),
+            Err(Errors::InvalidLogRecordCrc) => Err(self.quarantine_record(blob_file, &pos)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 统计 merge 的数据文件中还有效的 blob, 选出无效数据占比达到`data_file_merge_ratio`的 blob 文件
+    /// 只考虑 merge 开始时已经不再写入的文件, 之后写入的 blob 只会被没有参与 merge 的数据文件引用
+    pub(crate) fn new_blob_rewriter(
+        &self,
+        merge_files: &[DataFile],
+        merge_path: &Path,
+    ) -> Result<BlobRewriter<'_>> {
+        let mut live_size: HashMap<u32, u64> = HashMap::new();
+        for data_file in merge_files {
+            let mut offset = 0;
+            loop {
+                let read_log_record = match data_file.read_log_record(offset) {
+                    Ok(read_log_record) => read_log_record,
+                    Err(Errors::ReadDataFileEOF) => break,
+                    Err(e) => return Err(e),
+                };
+                let record = read_log_record.record;
+                if record.rec_type == LogRecordType::BlobIndex {
+                    let (real_key, _) = parse_log_record_key(record.key)?;
+                    let is_live = self.index.get(real_key)?.is_some_and(|pos| {
+                        pos.file_id == data_file.get_file_id() && pos.offset == offset
+                    });
+                    if is_live {
+                        let blob_pos = LogRecordPos::decode(record.value)?;
+                        *live_size.entry(blob_pos.file_id).or_default() += blob_pos.size as u64;
+                    }
+                }
+                offset += read_log_record.size as u64;
+            }
+        }
+
+        let mut gc_file_ids = HashSet::new();
+        {
+            let blob_files = self.blob_files.read();
+            for (file_id, blob_file) in blob_files.older.iter() {
+                if *file_id >= blob_files.sealed_before {
+                    continue;
+                }
+                let size = blob_file.file_size()?;
+                let live = live_size.get(file_id).copied().unwrap_or(0);
+                let garbage_ratio = match size {
+                    0 => 1.0,
+                    _ => 1.0 - live as f32 / size as f32,
+                };
+                if garbage_ratio >= self.options.data_file_merge_ratio {
+                    gc_file_ids.insert(*file_id);
+                }
+            }
+        }
+
+        Ok(BlobRewriter {
+            engine: self,
+            merge_path: merge_path.to_path_buf(),
+            gc_file_ids,
+            current: None,
+        })
+    }
+}
+
+/// merge 的结果生效时, 删除已经重写过的 blob 文件, 重复执行不会出错
+pub(crate) fn remove_gc_blob_files(dir_path: &Path, merge_path: &Path) -> Result<()> {
+    let gc_file = merge_path.join(BLOB_GC_FILE_NAME);
+    if !gc_file.is_file() {
+        return Ok(());
+    }
+    let content = fs::read_to_string(gc_file)?;
+    for file_id in content.split(',').filter(|id| !id.is_empty()) {
+        let file_id = file_id.parse::<u32>()?;
+        let blob_file = get_blob_file_name(dir_path, file_id);
+        if blob_file.is_file() {
+            fs::remove_file(blob_file)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::options::WriteBatchOptions;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/blob".into()
+    }
+
+    fn setup(name: &str) -> EngineOptions {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 4 * 1024;
+        opts.data_file_merge_ratio = 0f32;
+        opts.big_value_threshold = Some(64);
+        opts
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn blob_file_ids(opts: &EngineOptions) -> Vec<u32> {
+        let mut file_ids: Vec<u32> = fs::read_dir(&opts.dir_path)
+            .unwrap()
+            .filter_map(|entry| {
+                let name = entry.unwrap().file_name().into_string().unwrap();
+                name.strip_suffix(BLOB_FILE_NAME_SUFFIX)?.parse().ok()
+            })
+            .collect();
+        file_ids.sort();
+        file_ids
+    }
+
+    fn big_value(i: usize) -> Bytes {
+        Bytes::from(format!("{:0>512}", i))
+    }
+
+    #[test]
+    fn test_blob_put_get() {
+        let name = "put-get";
+        let opts = setup(name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..20 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), big_value(i))
+                .unwrap();
+        }
+        engine.put(Bytes::from("small"), Bytes::from("v")).unwrap();
+
+        {
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .unwrap();
+            wb.put(Bytes::from("batch"), big_value(100)).unwrap();
+            wb.commit().unwrap();
+        }
+
+        assert_eq!(engine.get(Bytes::from("key-3")).unwrap(), big_value(3));
+        assert_eq!(engine.get(Bytes::from("batch")).unwrap(), big_value(100));
+        // 数据文件中只有位置, 还在第一个文件中
+        assert!(engine.older_files.read().is_empty());
+        assert!(blob_file_ids(&opts).len() > 1);
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        for i in 0..20 {
+            assert_eq!(
+                engine.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                big_value(i)
+            );
+        }
+        assert_eq!(engine.get(Bytes::from("small")).unwrap(), Bytes::from("v"));
+        assert_eq!(engine.get(Bytes::from("batch")).unwrap(), big_value(100));
+
+        // 重新打开之后写入新的 blob 文件
+        let file_ids = blob_file_ids(&opts);
+        engine.put(Bytes::from("key-0"), big_value(1000)).unwrap();
+        assert_eq!(
+            blob_file_ids(&opts).last(),
+            Some(&(file_ids.last().unwrap() + 1))
+        );
+        assert_eq!(engine.get(Bytes::from("key-0")).unwrap(), big_value(1000));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_blob_gc_after_merge() {
+        let name = "gc";
+        let opts = setup(name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..20 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), big_value(i))
+                .unwrap();
+        }
+        let old_file_ids = blob_file_ids(&opts);
+        // 覆盖一部分, 删除一部分, 旧的 blob 文件中还有少量有效数据
+        for i in 0..18 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("small"))
+                .unwrap();
+        }
+        engine.delete(Bytes::from("key-18")).unwrap();
+
+        engine.merge().expect("failed to merge");
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        let file_ids = blob_file_ids(&opts);
+        for file_id in old_file_ids.iter() {
+            assert!(!file_ids.contains(file_id));
+        }
+        assert_eq!(file_ids.len(), 1);
+        assert_eq!(
+            engine.get(Bytes::from("key-0")).unwrap(),
+            Bytes::from("small")
+        );
+        assert_eq!(engine.get(Bytes::from("key-19")).unwrap(), big_value(19));
+        assert!(matches!(
+            engine.get(Bytes::from("key-18")),
+            Err(Errors::KeyNotFound)
+        ));
+        assert!(engine.verify().unwrap().is_ok());
+        drop(engine);
+
+        // merge 生效之后再次打开
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.get(Bytes::from("key-19")).unwrap(), big_value(19));
+
+        clean(name);
+    }
+}
@@ -1,30 +1,53 @@
 use crate::{
-    data::log_record::{max_log_record_header_size, LogRecordType},
+    data::{
+        encryption::RecordCipher,
+        log_record::{
+            max_log_record_header_size, LogRecordType, ENCRYPTED_FLAG, RECORD_TYPE_MASK,
+            TIMESTAMP_FLAG,
+        },
+    },
     fio::{new_io_manager, IOType},
+    options::{ChecksumType, CompressionType},
     prelude::*,
 };
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use parking_lot::RwLock;
-use prost::{decode_length_delimiter, length_delimiter_len};
+use prost::{
+    decode_length_delimiter,
+    encoding::{decode_varint, encoded_len_varint},
+    length_delimiter_len,
+};
 
 use crate::fio;
 
 use super::{
-    log_record::{LogRecord, LogRecordPos, ReadLogRecord},
-    HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
+    log_record::{LogRecord, LogRecordPos, ReadLogRecord, ReadLogRecordBytes},
+    BLOB_FILE_NAME_SUFFIX, HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
 };
 
+/// 不知道数据的大小时, 第一次读取在最大的头部之后多读的字节数, 不超过它的数据一次就能读完
+const READ_AHEAD_SIZE: usize = 4096;
+/// 查找下一条合法数据时每次读取的字节数
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
+
 /// 数据文件,实际存储多个key-value的文件
 /// 一个 DataFile 就对应一个文件
 /// DataFile中存储的`LogRecord`是编码之后的
 /// Header: Type(1字节) + KeySize(可变长编码) + ValueSize(可变长编码)
-/// Body(Key + Value + CRC)
+/// Body(Key + Value + 校验值)
 pub struct DataFile {
     file_id: Arc<RwLock<u32>>,
     write_off: Arc<RwLock<u64>>, // 当前写偏移,记录文件写入的位置
     io_manager: Box<dyn fio::IOManager>,
+    /// 解密`value`, 为空时读到加密的数据返回`Errors::EncryptionKeyRequired`
+    cipher: Option<RecordCipher>,
+    /// 每条数据末尾的校验方式, hint 等内部文件总是使用 crc32
+    checksum: ChecksumType,
 }
 
 impl DataFile {
@@ -37,8 +60,54 @@ impl DataFile {
             file_id: Arc::new(RwLock::new(file_id)),
             write_off: Arc::new(RwLock::new(0)),
             io_manager: io_manager,
+            cipher: None,
+            checksum: ChecksumType::Crc32,
+        })
+    }
+    /// 以只读方式打开已经存在的数据文件, 用于只读副本
+    pub fn open_read_only(dir_path: PathBuf, file_id: u32) -> Result<DataFile> {
+        let file_name = get_data_file_name(&dir_path, file_id);
+        if !file_name.is_file() {
+            return Err(Errors::DataFileNotFound);
+        }
+
+        let io_manager = Box::new(fio::file_io::FileIO::open_read_only(file_name)?);
+        Ok(DataFile {
+            file_id: Arc::new(RwLock::new(file_id)),
+            write_off: Arc::new(RwLock::new(0)),
+            io_manager,
+            cipher: None,
+            checksum: ChecksumType::Crc32,
         })
     }
+
+    /// 保存从数据文件中分离出来的大`value`, 格式和数据文件相同
+    pub fn new_blob_file(dir_path: PathBuf, file_id: u32, io_type: IOType) -> Result<DataFile> {
+        let file_name = get_blob_file_name(&dir_path, file_id);
+
+        let io_manager = new_io_manager(file_name, io_type)?;
+        Ok(DataFile {
+            file_id: Arc::new(RwLock::new(file_id)),
+            write_off: Arc::new(RwLock::new(0)),
+            io_manager,
+            cipher: None,
+            checksum: ChecksumType::Crc32,
+        })
+    }
+
+    /// 以只读方式打开已经存在的 blob 文件
+    pub fn open_blob_read_only(dir_path: PathBuf, file_id: u32) -> Result<DataFile> {
+        let file_name = get_blob_file_name(&dir_path, file_id);
+        let io_manager = Box::new(fio::file_io::FileIO::open_read_only(file_name)?);
+        Ok(DataFile {
+            file_id: Arc::new(RwLock::new(file_id)),
+            write_off: Arc::new(RwLock::new(0)),
+            io_manager,
+            cipher: None,
+            checksum: ChecksumType::Crc32,
+        })
+    }
+
     pub fn new_seq_no_file(dir_path: PathBuf) -> Result<DataFile> {
         // 根据 dir_path 和 file_id 构建出完整的文件名称
         let file_name = dir_path.join(SEQ_NO_FILE_NAME);
@@ -48,6 +117,8 @@ impl DataFile {
             file_id: Arc::new(RwLock::new(0)),
             write_off: Arc::new(RwLock::new(0)),
             io_manager: io_manager,
+            cipher: None,
+            checksum: ChecksumType::Crc32,
         })
     }
 
@@ -61,6 +132,8 @@ impl DataFile {
             file_id: Arc::new(RwLock::new(0)),
             write_off: Arc::new(RwLock::new(0)),
             io_manager: io_manager,
+            cipher: None,
+            checksum: ChecksumType::Crc32,
         })
     }
 
@@ -74,9 +147,23 @@ impl DataFile {
             file_id: Arc::new(RwLock::new(0)),
             write_off: Arc::new(RwLock::new(0)),
             io_manager: io_manager,
+            cipher: None,
+            checksum: ChecksumType::Crc32,
         })
     }
 
+    /// 设置解密`value`使用的密钥
+    pub(crate) fn with_cipher(mut self, cipher: Option<RecordCipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// 设置数据的校验方式, 需要和写入时使用的一致
+    pub(crate) fn with_checksum(mut self, checksum: ChecksumType) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
     pub fn file_size(&self) -> Result<u64> {
         self.io_manager.size()
     }
@@ -98,19 +185,26 @@ impl DataFile {
         *read_guard
     }
 
+    /// 在`write_off`的位置写入数据, 文件可能预分配了空间, 所以不能直接追加到文件末尾
     pub fn write(&self, buf: &[u8]) -> Result<usize> {
-        let n_bytes = self.io_manager.write(buf)?;
         let mut write_off = self.write_off.write();
+        let n_bytes = self.io_manager.write_at(buf, *write_off)?;
         *write_off += n_bytes as u64;
 
         Ok(n_bytes)
     }
 
+    /// 预分配磁盘空间, 多出来的部分全是0, 读取时会被当作文件末尾
+    pub fn preallocate(&self, size: u64) -> Result<()> {
+        self.io_manager.allocate(size)
+    }
+
     pub fn write_hint_record(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<()> {
         let hint_record = LogRecord {
             key,
             value: pos.encode()?,
             rec_type: LogRecordType::Normal,
+            timestamp: 0,
         };
         let encoded_record = hint_record.encode()?;
         self.write(&encoded_record)?;
@@ -119,11 +213,36 @@ impl DataFile {
 
     /// 给定 `offset` 读取相应的 LogRecord
     pub fn read_log_record(&self, offset: u64) -> Result<ReadLogRecord> {
-        let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
-        self.io_manager.read(&mut header_buf, offset)?;
+        let read = self.read_log_record_bytes(offset, None)?;
+        Ok(ReadLogRecord {
+            record: LogRecord {
+                key: read.key.to_vec(),
+                value: read.value.to_vec(),
+                rec_type: read.rec_type,
+                timestamp: read.timestamp,
+            },
+            size: read.size,
+        })
+    }
 
-        // 第一个字节是 Type
-        let rec_type = header_buf.get_u8();
+    /// 和`read_log_record`一样, 但是`key`和`value`使用`Bytes`
+    /// 内存映射的文件不拷贝数据, 返回的`Bytes`会让映射一直保留到它们释放
+    /// `size_hint`是已知的整条数据的大小(比如索引中记录的), 为空时按`READ_AHEAD_SIZE`多读一些
+    /// 第一次读取包含了整条数据时不需要再读, 否则再读一次整条数据
+    pub(crate) fn read_log_record_bytes(
+        &self,
+        offset: u64,
+        size_hint: Option<usize>,
+    ) -> Result<ReadLogRecordBytes> {
+        let max_header_size = max_log_record_header_size();
+        let read_size = size_hint
+            .unwrap_or(max_header_size + READ_AHEAD_SIZE)
+            .max(max_header_size);
+        let (first_read, first_read_len) = self.read_bytes(offset, read_size, max_header_size)?;
+        let mut header_buf = first_read.clone();
+
+        // 第一个字节是 Type, 高2位是压缩方式, 第6位标识是否加密, 第5位标识是否有时间戳
+        let type_byte = header_buf.get_u8();
 
         // key、value的长度
         let key_size = decode_length_delimiter(&mut header_buf)?;
@@ -134,31 +253,148 @@ impl DataFile {
             return Err(Errors::ReadDataFileEOF);
         }
 
+        let rec_type = LogRecordType::from_u8(type_byte & RECORD_TYPE_MASK)?;
+        let compression = CompressionType::from_type_byte(type_byte)?;
+        let timestamp = match type_byte & TIMESTAMP_FLAG {
+            0 => 0,
+            _ => decode_varint(&mut header_buf)?,
+        };
+
         // 获取实际Header大小
-        let actual_header_size =
-            length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1; // 1是type的长度
+        let actual_header_size = length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1 // 1是type的长度
+                + timestamp_len(type_byte, timestamp);
+
+        let checksum_size = self.checksum.size();
+        // 损坏的头部中 key、value 的长度可能是任意值, 超过文件末尾时和校验失败一样处理, 不按它分配内存
+        let size = record_size(actual_header_size, key_size, value_size, checksum_size)
+            .ok_or(Errors::InvalidLogRecordCrc)?;
+        let record_buf = match size <= first_read_len {
+            true => first_read.slice(..size),
+            false if offset.saturating_add(size as u64) > self.io_manager.size()? => {
+                return Err(Errors::InvalidLogRecordCrc);
+            }
+            false => match self.read_bytes(offset, size, size)? {
+                (buf, len) if len >= size => buf,
+                _ => return Err(Errors::InvalidLogRecordCrc),
+            },
+        };
+        // 校验值要覆盖原始的 header
+        let (raw_header, kv_buf) = (
+            record_buf.slice(..actual_header_size),
+            record_buf.slice(actual_header_size..),
+        );
+
+        // 按文件的校验方式重新计算, 和记录的校验值比较, 不校验时两者都是0
+        let actual_crc = self
+            .checksum
+            .compute(&[&raw_header, &kv_buf[..key_size + value_size]]);
+        let mut crc_buf = &kv_buf[key_size + value_size..];
+        let crc = crc_buf.get_uint(checksum_size);
+        if crc != actual_crc {
+            return Err(Errors::InvalidLogRecordCrc);
+        }
 
-        let mut kv_buf = BytesMut::zeroed(key_size + value_size + CRC_SIZE);
-        self.io_manager
-            .read(&mut kv_buf, offset + actual_header_size as u64)?;
+        let key = kv_buf.slice(..key_size);
+        let mut value = kv_buf.slice(key_size..key_size + value_size);
+        // 加密的是压缩之后的数据, 先解密再解压
+        if type_byte & ENCRYPTED_FLAG != 0 {
+            let cipher = self.cipher.as_ref().ok_or(Errors::EncryptionKeyRequired)?;
+            value = cipher.decrypt(&value, &key)?.into();
+        }
+        if compression != CompressionType::None {
+            value = compression.decompress(&value)?.into();
+        }
+
+        Ok(ReadLogRecordBytes {
+            key,
+            value,
+            rec_type,
+            timestamp,
+            size,
+        })
+    }
+
+    /// 读取`[offset, offset + len)`, 剩余的数据不够`min_len`时超出文件末尾的部分是0, 和直接读到缓冲区中一样
+    /// 同时返回实际读到的字节数, 补上的0只用于解析头部, 不能当成数据的内容
+    /// 内存映射的文件直接引用映射的内存, 不拷贝数据
+    fn read_bytes(&self, offset: u64, len: usize, min_len: usize) -> Result<(Bytes, usize)> {
+        match self.io_manager.read_bytes(offset, len)? {
+            Some(bytes) if bytes.len() >= min_len => {
+                let read_len = bytes.len();
+                Ok((bytes, read_len))
+            }
+            _ => {
+                let mut buf = BytesMut::zeroed(len);
+                let read_len = self.io_manager.read(&mut buf, offset)?;
+                Ok((buf.freeze(), read_len))
+            }
+        }
+    }
 
-        let log_record = LogRecord {
-            key: kv_buf.get(..key_size).unwrap().to_vec(),
-            value: kv_buf.get(key_size..kv_buf.len() - 4).unwrap().to_vec(),
-            rec_type: LogRecordType::from_u8(rec_type),
+    /// 只解析`offset`处数据的头部, 返回整条数据的大小, 用于跳过损坏的数据
+    pub fn read_log_record_size(&self, offset: u64) -> Result<usize> {
+        let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
+        self.io_manager.read(&mut header_buf, offset)?;
+
+        let type_byte = header_buf.get_u8();
+        let key_size = decode_length_delimiter(&mut header_buf)?;
+        let value_size = decode_length_delimiter(&mut header_buf)?;
+        if key_size == 0 && value_size == 0 {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        let timestamp = match type_byte & TIMESTAMP_FLAG {
+            0 => 0,
+            _ => decode_varint(&mut header_buf)?,
         };
 
-        // 校验 crc
-        kv_buf.advance(key_size + value_size); // 移动指针,当前指向的crc的值
-        let crc = kv_buf.get_u32();
-        if crc != log_record.get_crc() {
-            return Err(Errors::InvalidLogRecordCrc);
+        let header_size = length_delimiter_len(key_size)
+            + length_delimiter_len(value_size)
+            + 1
+            + timestamp_len(type_byte, timestamp);
+        record_size(header_size, key_size, value_size, self.checksum.size())
+            .ok_or(Errors::DataFileBroken)
+    }
+
+    /// 从`offset`开始逐字节查找下一条能通过校验的数据, 用来区分末尾写入到一半的数据和文件中间损坏的数据
+    /// 不校验时任意内容都可能被当作合法的数据, 总是返回 None
+    pub(crate) fn find_valid_log_record(&self, offset: u64) -> Result<Option<u64>> {
+        if self.checksum == ChecksumType::None {
+            return Ok(None);
         }
+        let file_size = self.io_manager.size()?;
+        let mut chunk_offset = offset;
+        while chunk_offset < file_size {
+            let chunk = self.read_raw(chunk_offset, SCAN_CHUNK_SIZE)?;
+            if chunk.is_empty() {
+                break;
+            }
+            for (i, type_byte) in chunk.iter().enumerate() {
+                // 预分配的0和大部分垃圾数据的 type 不合法, 不需要解析
+                if LogRecordType::from_u8(type_byte & RECORD_TYPE_MASK).is_err()
+                    || CompressionType::from_type_byte(*type_byte).is_err()
+                {
+                    continue;
+                }
+                let candidate = chunk_offset + i as u64;
+                match self.read_log_record_bytes(candidate, None) {
+                    Err(Errors::IO(e)) => return Err(Errors::IO(e)),
+                    Err(Errors::ReadDataFileEOF) => {}
+                    Err(e) if is_torn_record_error(&e) => {}
+                    // 解密、解压失败时校验值已经通过了
+                    _ => return Ok(Some(candidate)),
+                }
+            }
+            chunk_offset += chunk.len() as u64;
+        }
+        Ok(None)
+    }
 
-        Ok(ReadLogRecord {
-            record: log_record,
-            size: actual_header_size + key_size + value_size + CRC_SIZE,
-        })
+    /// 读取`offset`开始的`size`个字节的原始数据
+    pub fn read_raw(&self, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0; size];
+        let n = self.io_manager.read(&mut buf, offset)?;
+        buf.truncate(n);
+        Ok(buf)
     }
 
     pub fn set_io_manager(&mut self, dir_path: PathBuf, io_type: IOType) -> Result<()> {
@@ -169,13 +405,51 @@ impl DataFile {
     }
 }
 
+/// 头部中时间戳占用的字节数, 没有设置`TIMESTAMP_FLAG`时为0
+fn timestamp_len(type_byte: u8, timestamp: u64) -> usize {
+    match type_byte & TIMESTAMP_FLAG {
+        0 => 0,
+        _ => encoded_len_varint(timestamp),
+    }
+}
+
 pub fn get_data_file_name(path: &PathBuf, file_id: u32) -> PathBuf {
     let v = format!("{:09}{}", file_id, DATA_FILE_NAME_SUFFIX);
     path.join(v)
 }
+
+pub fn get_blob_file_name(path: &Path, file_id: u32) -> PathBuf {
+    let v = format!("{:09}{}", file_id, BLOB_FILE_NAME_SUFFIX);
+    path.join(v)
+}
+
+/// 写入到一半的数据读取时的错误: 校验值不对, 或者头部无法解析
+pub(crate) fn is_torn_record_error(e: &Errors) -> bool {
+    matches!(
+        e,
+        Errors::InvalidLogRecordCrc | Errors::DataFileBroken | Errors::DecodeError(_)
+    )
+}
+
+/// 整条数据的大小, 溢出时返回 None
+fn record_size(
+    header_size: usize,
+    key_size: usize,
+    value_size: usize,
+    checksum_size: usize,
+) -> Option<usize> {
+    header_size
+        .checked_add(key_size)?
+        .checked_add(value_size)?
+        .checked_add(checksum_size)
+}
+
 #[cfg(test)]
 mod tests {
+    use proptest::{collection::vec, option, prelude::*};
+
     use super::*;
+    use crate::data::log_record::now_timestamp;
     fn basepath() -> PathBuf {
         "./tmp/data_file".into()
     }
@@ -322,6 +596,7 @@ mod tests {
                 key: key.clone(),
                 value: value.clone(),
                 rec_type: LogRecordType::Normal,
+                timestamp: 0,
             };
 
             let encode_res = log_record.encode();
@@ -348,6 +623,7 @@ mod tests {
                 key: key.clone(),
                 value: value.clone(),
                 rec_type: LogRecordType::Normal,
+                timestamp: 0,
             };
 
             let encode_res = log_record.encode();
@@ -378,6 +654,7 @@ mod tests {
                 key: key.clone(),
                 value: Default::default(),
                 rec_type: LogRecordType::Deleted,
+                timestamp: 0,
             };
 
             let encode_res = log_record.encode();
@@ -401,4 +678,390 @@ mod tests {
 
         clean("read");
     }
+
+    #[test]
+    fn test_data_file_read_compressed_log_record() {
+        setup("compressed");
+        let dir_path = PathBuf::from(basepath().join("compressed"));
+        let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFileIO).unwrap();
+
+        // 同一个文件中混合存放不同压缩方式的数据
+        let value = "LucasDBValue".repeat(100).into_bytes();
+        let mut offset = 0;
+        for compression in [
+            CompressionType::None,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ] {
+            let log_record = LogRecord {
+                key: "lucas".as_bytes().to_vec(),
+                value: value.clone(),
+                rec_type: LogRecordType::Normal,
+                timestamp: 0,
+            };
+            let encode = log_record.encode_with_compression(compression).unwrap();
+            let n = data_file.write(&encode).unwrap();
+
+            let read_log_record = data_file.read_log_record(offset).unwrap();
+            assert_eq!(read_log_record.size, n);
+            assert_eq!(read_log_record.record.value, value);
+            assert_eq!(read_log_record.record.rec_type, LogRecordType::Normal);
+            if compression != CompressionType::None {
+                assert!(n < value.len());
+            }
+            offset += n as u64;
+        }
+
+        clean("compressed");
+    }
+
+    #[test]
+    fn test_data_file_read_log_record_timestamp() {
+        setup("timestamp");
+        let dir_path = PathBuf::from(basepath().join("timestamp"));
+        let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFileIO).unwrap();
+
+        // 有时间戳和没有时间戳的数据混合存放
+        let mut offset = 0;
+        for (timestamp, compression) in [
+            (0, CompressionType::None),
+            (1, CompressionType::None),
+            (now_timestamp(), CompressionType::None),
+            (u64::MAX, CompressionType::Lz4),
+        ] {
+            let log_record = LogRecord {
+                key: "lucas".as_bytes().to_vec(),
+                value: "LucasDBValue".repeat(10).into_bytes(),
+                rec_type: LogRecordType::Normal,
+                timestamp,
+            };
+            let encode = log_record.encode_with_compression(compression).unwrap();
+            assert_eq!(encode[0] & TIMESTAMP_FLAG != 0, timestamp != 0);
+            let n = data_file.write(&encode).unwrap();
+
+            let read_log_record = data_file.read_log_record(offset).unwrap();
+            assert_eq!(read_log_record.size, n);
+            assert_eq!(read_log_record.record.timestamp, timestamp);
+            assert_eq!(read_log_record.record.value, log_record.value);
+            assert_eq!(read_log_record.record.rec_type, LogRecordType::Normal);
+            assert_eq!(data_file.read_log_record_size(offset).unwrap(), n);
+            offset += n as u64;
+        }
+        assert!(matches!(
+            data_file.read_log_record(offset),
+            Err(Errors::ReadDataFileEOF)
+        ));
+
+        clean("timestamp");
+    }
+
+    #[test]
+    fn test_data_file_read_log_record_checksum() {
+        setup("checksum");
+        let dir_path = PathBuf::from(basepath().join("checksum"));
+
+        for (file_id, checksum) in [
+            ChecksumType::Crc32,
+            ChecksumType::XxHash64,
+            ChecksumType::None,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let data_file = DataFile::new(dir_path.clone(), file_id as u32, IOType::StandardFileIO)
+                .unwrap()
+                .with_checksum(checksum);
+            let log_record = LogRecord {
+                key: "lucas".as_bytes().to_vec(),
+                value: "LucasDBValue".as_bytes().to_vec(),
+                rec_type: LogRecordType::Normal,
+                timestamp: now_timestamp(),
+            };
+            let encode = log_record
+                .encode_with(CompressionType::None, checksum, None)
+                .unwrap();
+            let n = data_file.write(&encode).unwrap();
+
+            let read_log_record = data_file.read_log_record(0).unwrap();
+            assert_eq!(read_log_record.size, n);
+            assert_eq!(read_log_record.record.value, log_record.value);
+            assert_eq!(data_file.read_log_record_size(0).unwrap(), n);
+
+            // 修改 value 的最后一个字节, 只有不校验时才能读出来
+            data_file.set_write_off((n - checksum.size() - 1) as u64);
+            data_file.write(b"x").unwrap();
+            let res = data_file.read_log_record(0);
+            match checksum {
+                ChecksumType::None => assert_eq!(res.unwrap().record.value, b"LucasDBValux"),
+                _ => assert!(matches!(res, Err(Errors::InvalidLogRecordCrc))),
+            }
+        }
+
+        clean("checksum");
+    }
+
+    #[test]
+    fn test_data_file_read_log_record_bytes() {
+        setup("bytes");
+        let dir_path = PathBuf::from(basepath().join("bytes"));
+
+        let mut encoded = vec![];
+        let mut records = vec![];
+        for (i, compression) in [CompressionType::None, CompressionType::Lz4]
+            .into_iter()
+            .enumerate()
+        {
+            let log_record = LogRecord {
+                key: format!("lucas-{}", i).into_bytes(),
+                value: "LucasDBValue".repeat(10).into_bytes(),
+                rec_type: LogRecordType::Normal,
+                timestamp: now_timestamp(),
+            };
+            encoded.extend(log_record.encode_with_compression(compression).unwrap());
+            records.push(log_record);
+        }
+        let data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFileIO).unwrap();
+        data_file.write(&encoded).unwrap();
+        data_file.sync().unwrap();
+
+        // 内存映射和标准文件IO读到的数据相同
+        for io_type in [IOType::StandardFileIO, IOType::MemoryMap] {
+            let data_file = DataFile::new(dir_path.clone(), 0, io_type).unwrap();
+            let mut offset = 0;
+            for log_record in records.iter() {
+                let read = data_file.read_log_record_bytes(offset, None).unwrap();
+                assert_eq!(read.key, log_record.key);
+                assert_eq!(read.value, log_record.value);
+                assert_eq!(read.timestamp, log_record.timestamp);
+                let read_log_record = data_file.read_log_record(offset).unwrap();
+                assert_eq!(read_log_record.size, read.size);
+                assert_eq!(read_log_record.record.value, log_record.value);
+                offset += read.size as u64;
+            }
+            assert!(matches!(
+                data_file.read_log_record_bytes(offset, None),
+                Err(Errors::ReadDataFileEOF)
+            ));
+        }
+
+        clean("bytes");
+    }
+
+    /// 统计读取次数的IO
+    struct CountingIO {
+        inner: Box<dyn fio::IOManager>,
+        reads: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl fio::IOManager for CountingIO {
+        fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+            self.reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.read(buf, offset)
+        }
+        fn write(&self, buf: &[u8]) -> Result<usize> {
+            self.inner.write(buf)
+        }
+        fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+            self.inner.write_at(buf, offset)
+        }
+        fn allocate(&self, size: u64) -> Result<()> {
+            self.inner.allocate(size)
+        }
+        fn sync(&self) -> Result<()> {
+            self.inner.sync()
+        }
+        fn size(&self) -> Result<u64> {
+            self.inner.size()
+        }
+    }
+
+    #[test]
+    fn test_data_file_read_log_record_single_read() {
+        setup("single_read");
+        let dir_path = PathBuf::from(basepath().join("single_read"));
+        let mut data_file = DataFile::new(dir_path.clone(), 0, IOType::StandardFileIO).unwrap();
+        let reads = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        data_file.io_manager = Box::new(CountingIO {
+            inner: std::mem::replace(
+                &mut data_file.io_manager,
+                Box::new(fio::memory::MemoryIO::new()),
+            ),
+            reads: reads.clone(),
+        });
+        let read_count = || reads.swap(0, std::sync::atomic::Ordering::SeqCst);
+
+        // 小数据一次读完, 超过`READ_AHEAD_SIZE`的数据需要再读一次
+        let mut positions = vec![];
+        for value_size in [10, 100, READ_AHEAD_SIZE * 2, 10] {
+            let log_record = LogRecord {
+                key: "lucas".as_bytes().to_vec(),
+                value: vec![b'v'; value_size],
+                rec_type: LogRecordType::Normal,
+                timestamp: now_timestamp(),
+            };
+            let offset = data_file.get_write_off();
+            let size = data_file.write(&log_record.encode().unwrap()).unwrap();
+            positions.push((offset, size, value_size));
+        }
+        for (offset, size, value_size) in positions.iter().copied() {
+            let read = data_file.read_log_record_bytes(offset, None).unwrap();
+            assert_eq!(read.size, size);
+            assert_eq!(read.value.len(), value_size);
+            let expected = match value_size > READ_AHEAD_SIZE {
+                true => 2,
+                false => 1,
+            };
+            assert_eq!(read_count(), expected);
+
+            // 知道大小时总是只读一次
+            let read = data_file.read_log_record_bytes(offset, Some(size)).unwrap();
+            assert_eq!(read.value.len(), value_size);
+            assert_eq!(read_count(), 1);
+        }
+
+        // 大小不对时依然能读出完整的数据
+        let (offset, size, value_size) = positions[2];
+        let read = data_file.read_log_record_bytes(offset, Some(10)).unwrap();
+        assert_eq!((read.size, read.value.len()), (size, value_size));
+        let read = data_file
+            .read_log_record_bytes(offset, Some(size * 2))
+            .unwrap();
+        assert_eq!((read.size, read.value.len()), (size, value_size));
+
+        clean("single_read");
+    }
+
+    fn record_strategy() -> impl Strategy<Value = LogRecord> {
+        let rec_type = prop_oneof![
+            Just(LogRecordType::Normal),
+            Just(LogRecordType::Deleted),
+            Just(LogRecordType::TxnFinished),
+            Just(LogRecordType::BlobIndex),
+        ];
+        // 重复的字节可以被压缩, 随机的字节一般压缩不了
+        let value = prop_oneof![
+            vec(any::<u8>(), 0..2 * READ_AHEAD_SIZE),
+            (any::<u8>(), 0..2 * READ_AHEAD_SIZE).prop_map(|(b, n)| vec![b; n]),
+        ];
+        // key 和 value 都为空时会被当作文件末尾, key 至少有一个字节
+        (vec(any::<u8>(), 1..64), value, rec_type, any::<u64>()).prop_map(
+            |(key, value, rec_type, timestamp)| LogRecord {
+                key,
+                value,
+                rec_type,
+                timestamp,
+            },
+        )
+    }
+
+    fn options_strategy() -> impl Strategy<Value = (CompressionType, ChecksumType, Option<[u8; 32]>)>
+    {
+        (
+            prop_oneof![
+                Just(CompressionType::None),
+                Just(CompressionType::Lz4),
+                Just(CompressionType::Zstd),
+            ],
+            prop_oneof![
+                Just(ChecksumType::Crc32),
+                Just(ChecksumType::XxHash64),
+                Just(ChecksumType::None),
+            ],
+            option::of(any::<[u8; 32]>()),
+        )
+    }
+
+    /// 依次读取文件中的所有数据, 遇到错误时停止, 读取任意内容都不能 panic
+    fn read_all(data_file: &DataFile) -> Vec<ReadLogRecord> {
+        let mut records = vec![];
+        let mut offset = 0;
+        while let Ok(read) = data_file.read_log_record(offset) {
+            offset += read.size as u64;
+            records.push(read);
+        }
+        records
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn proptest_log_record_round_trip(
+            records in vec(record_strategy(), 1..8),
+            (compression, checksum, encryption_key) in options_strategy(),
+        ) {
+            let cipher = encryption_key.as_ref().map(RecordCipher::new);
+            let data_file = DataFile::new(PathBuf::new(), 0, IOType::InMemory)
+                .unwrap()
+                .with_checksum(checksum)
+                .with_cipher(cipher.clone());
+
+            let mut positions = vec![];
+            for record in &records {
+                let encoded = record
+                    .encode_with(compression, checksum, cipher.as_ref())
+                    .unwrap();
+                positions.push((data_file.get_write_off(), encoded.len()));
+                data_file.write(&encoded).unwrap();
+            }
+
+            let read = read_all(&data_file);
+            prop_assert_eq!(read.len(), records.len());
+            for ((read, record), (offset, size)) in read.iter().zip(&records).zip(positions) {
+                prop_assert_eq!(&read.record.key, &record.key);
+                prop_assert_eq!(&read.record.value, &record.value);
+                prop_assert_eq!(read.record.rec_type, record.rec_type);
+                prop_assert_eq!(read.record.timestamp, record.timestamp);
+                prop_assert_eq!(read.size, size);
+                prop_assert_eq!(data_file.read_log_record_size(offset).unwrap(), size);
+
+                let bytes = data_file.read_log_record_bytes(offset, Some(size)).unwrap();
+                prop_assert_eq!(&bytes.value[..], &record.value[..]);
+            }
+        }
+
+        #[test]
+        fn proptest_read_arbitrary_bytes(
+            data in vec(any::<u8>(), 0..1024),
+            (_, checksum, encryption_key) in options_strategy(),
+        ) {
+            let data_file = DataFile::new(PathBuf::new(), 0, IOType::InMemory)
+                .unwrap()
+                .with_checksum(checksum)
+                .with_cipher(encryption_key.as_ref().map(RecordCipher::new));
+            data_file.write(&data).unwrap();
+
+            read_all(&data_file);
+            for offset in 0..data.len() as u64 {
+                let _ = data_file.read_log_record_size(offset);
+                let _ = data_file.read_log_record_bytes(offset, Some(data.len()));
+            }
+        }
+
+        #[test]
+        fn proptest_read_corrupted_record(
+            record in record_strategy(),
+            (compression, checksum, encryption_key) in options_strategy(),
+            corruptions in vec((any::<prop::sample::Index>(), any::<u8>()), 1..4),
+        ) {
+            let cipher = encryption_key.as_ref().map(RecordCipher::new);
+            let mut encoded = record
+                .encode_with(compression, checksum, cipher.as_ref())
+                .unwrap();
+            for (index, byte) in corruptions {
+                let i = index.index(encoded.len());
+                encoded[i] = byte;
+            }
+
+            // 不校验时损坏的头部、压缩或者加密的数据都会被读到, 只能返回错误
+            let data_file = DataFile::new(PathBuf::new(), 0, IOType::InMemory)
+                .unwrap()
+                .with_checksum(checksum)
+                .with_cipher(cipher);
+            data_file.write(&encoded).unwrap();
+            for read in read_all(&data_file) {
+                prop_assert!(read.size <= encoded.len());
+            }
+        }
+    }
 }
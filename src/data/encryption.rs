@@ -0,0 +1,97 @@
+use crate::prelude::*;
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+use crate::options::EngineOptions;
+
+/// 每条数据使用随机的 nonce, 192 位的 nonce 不需要担心重复
+pub(crate) const NONCE_SIZE: usize = 24;
+
+/// 加密`LogRecord`的`value`, 加密之后的格式为 nonce + 密文 + tag
+/// `key`作为附加数据参与认证, 密文不能被挪到其他`key`下面
+#[derive(Clone)]
+pub(crate) struct RecordCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl RecordCipher {
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        RecordCipher {
+            cipher: XChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    /// 没有配置`encryption_key`时返回 None
+    pub(crate) fn from_options(options: &EngineOptions) -> Option<Self> {
+        options.encryption_key.as_ref().map(Self::new)
+    }
+
+    pub(crate) fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| Errors::EncryptFailed)?;
+
+        let mut buf = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&ciphertext);
+        Ok(buf)
+    }
+
+    /// 密钥错误或者数据被篡改时返回`Errors::DecryptFailed`
+    pub(crate) fn decrypt(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_SIZE {
+            return Err(Errors::DecryptFailed);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| Errors::DecryptFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_cipher() {
+        let cipher = RecordCipher::new(&[7; 32]);
+        let encrypted = cipher.encrypt(b"value", b"key").unwrap();
+        assert_eq!(encrypted.len(), NONCE_SIZE + 5 + 16);
+        assert_eq!(cipher.decrypt(&encrypted, b"key").unwrap(), b"value");
+
+        // 同样的数据每次加密的结果不同
+        assert_ne!(cipher.encrypt(b"value", b"key").unwrap(), encrypted);
+
+        // 错误的 key/密钥/被篡改的数据
+        assert!(matches!(
+            cipher.decrypt(&encrypted, b"other"),
+            Err(Errors::DecryptFailed)
+        ));
+        let other = RecordCipher::new(&[8; 32]);
+        assert!(matches!(
+            other.decrypt(&encrypted, b"key"),
+            Err(Errors::DecryptFailed)
+        ));
+        let mut tampered = encrypted.clone();
+        tampered[NONCE_SIZE] ^= 1;
+        assert!(cipher.decrypt(&tampered, b"key").is_err());
+    }
+}
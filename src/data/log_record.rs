@@ -1,6 +1,24 @@
-use crate::prelude::*;
-use bytes::{BufMut, BytesMut};
-use prost::{decode_length_delimiter, encode_length_delimiter, length_delimiter_len};
+use crate::{
+    options::{ChecksumType, CompressionType},
+    prelude::*,
+};
+
+use super::encryption::RecordCipher;
+use bytes::{BufMut, Bytes, BytesMut};
+use prost::{
+    decode_length_delimiter, encode_length_delimiter,
+    encoding::{encode_varint, encoded_len_varint},
+    length_delimiter_len,
+};
+
+/// type 字节的低4位是`LogRecordType`, 第5位标识头部是否有时间戳, 第6位标识`value`是否加密,
+/// 高2位标识`value`的压缩方式
+pub(crate) const RECORD_TYPE_MASK: u8 = 0b0000_1111;
+pub(crate) const TIMESTAMP_FLAG: u8 = 0b0001_0000;
+pub(crate) const ENCRYPTED_FLAG: u8 = 0b0010_0000;
+const COMPRESSION_MASK: u8 = 0b1100_0000;
+const COMPRESSION_LZ4_FLAG: u8 = 0b0100_0000;
+const COMPRESSION_ZSTD_FLAG: u8 = 0b1000_0000;
 
 /// 数据类型
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -10,20 +28,38 @@ pub enum LogRecordType {
     Deleted = 2,
     /// 标识事务完成
     TxnFinished = 3,
+    /// `value`保存在 blob 文件中, 这里的`value`是编码之后的 blob 位置
+    BlobIndex = 4,
 }
 impl LogRecordType {
-    pub fn from_u8(value: u8) -> Self {
+    /// 写入到一半的数据可能是任意值, 返回错误而不是 panic
+    pub fn from_u8(value: u8) -> Result<Self> {
         match value {
-            1 => LogRecordType::Normal,
-            2 => LogRecordType::Deleted,
-            3 => LogRecordType::TxnFinished,
-            _ => panic!("Invalid log record type"),
+            1 => Ok(LogRecordType::Normal),
+            2 => Ok(LogRecordType::Deleted),
+            3 => Ok(LogRecordType::TxnFinished),
+            4 => Ok(LogRecordType::BlobIndex),
+            _ => Err(Errors::DataFileBroken),
         }
     }
 }
 
+/// `Engine::get_with_meta`的结果, 包含`value`和数据在磁盘中的位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueMeta {
+    pub value: Bytes,
+    /// 最后一次写入的毫秒时间戳, 升级到版本2之前写入的数据为0
+    pub timestamp: u64,
+    /// 数据所在的文件id
+    pub file_id: u32,
+    /// 数据在文件中的起始位置
+    pub offset: u64,
+    /// 数据编码之后在数据文件中的长度, `value`分离到 blob 文件时不包括 blob 中的内容
+    pub size: usize,
+}
+
 /// 数据在磁盘中的索引
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct LogRecordPos {
     /// 文件id,表示`LogRecord`存放到了哪个文件中
     pub(crate) file_id: u32,
@@ -33,6 +69,21 @@ pub struct LogRecordPos {
     pub(crate) size: usize,
 }
 impl LogRecordPos {
+    /// 数据所在的文件id
+    pub fn file_id(&self) -> u32 {
+        self.file_id
+    }
+
+    /// 数据在文件中的起始位置
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// 数据编码之后的长度
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn encode(&self) -> Result<Vec<u8>> {
         let mut buf = BytesMut::new();
         encode_length_delimiter(self.file_id as usize, &mut buf)?;
@@ -63,73 +114,231 @@ pub struct LogRecord {
     pub(crate) key: Vec<u8>,
     pub(crate) value: Vec<u8>,
     pub(crate) rec_type: LogRecordType,
+    /// 写入时的毫秒时间戳, 为0时不写入头部, 内部使用的数据(hint、事务完成标识等)不需要时间戳
+    pub(crate) timestamp: u64,
+}
+
+/// 当前的毫秒时间戳, 写入用户数据时记录到`LogRecord`中
+pub(crate) fn now_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
 }
 
 impl LogRecord {
     /// 对 `LogRecord` 进行编码
     /// ```md
-    /// | type    | key size          | value size          | key   | value | crc 校验值  |
-    /// | ----    | ----------------- | ------------------  | ----- | ----- | ---------- |
-    /// | 1 字节  | 变长 (最大 5 字节)  | 变长 (最大 5 字节)   | 变长  | 变长   | 4 字节     |
+    /// | type    | key size          | value size          | timestamp          | key   | value | 校验值          |
+    /// | ----    | ----------------- | ------------------  | ------------------ | ----- | ----- | -------------- |
+    /// | 1 字节  | 变长 (最大 5 字节)  | 变长 (最大 5 字节)   | 变长 (最大 10 字节) | 变长  | 变长   | 0、4 或 8 字节  |
     /// ```
+    /// timestamp 只在 type 字节设置了`TIMESTAMP_FLAG`时存在
+    /// 校验值的长度由`ChecksumType`决定, 这里使用 crc32, hint 等内部文件总是使用 crc32
     pub fn encode(&self) -> Result<Vec<u8>> {
-        let (enc_buf, _) = self.encode_and_get_crc()?;
+        self.encode_with_compression(CompressionType::None)
+    }
+
+    /// 编码时压缩`value`, 只压缩`Normal`类型的数据
+    /// 压缩后没有变小就不压缩, type 字节的高2位记录实际使用的压缩方式
+    /// crc 校验的是压缩之后写入磁盘的数据
+    pub fn encode_with_compression(&self, compression: CompressionType) -> Result<Vec<u8>> {
+        self.encode_with(compression, ChecksumType::Crc32, None)
+    }
+
+    /// 先压缩再加密`value`, 只加密`Normal`类型的数据, 加密的数据在 type 字节中设置`ENCRYPTED_FLAG`
+    /// 最后用`checksum`计算整条数据的校验值, 读取时数据文件需要使用同样的校验方式
+    pub(crate) fn encode_with(
+        &self,
+        compression: CompressionType,
+        checksum: ChecksumType,
+        cipher: Option<&RecordCipher>,
+    ) -> Result<Vec<u8>> {
+        let (enc_buf, _) = self.encode_and_get_crc(compression, checksum, cipher)?;
         Ok(enc_buf)
     }
+
+    #[cfg(test)]
     pub fn get_crc(&self) -> u32 {
-        let (_, crc) = self.encode_and_get_crc().unwrap_or((Vec::new(), 0));
-        crc
+        let (_, crc) = self
+            .encode_and_get_crc(CompressionType::None, ChecksumType::Crc32, None)
+            .unwrap_or((Vec::new(), 0));
+        crc as u32
     }
     /// 返回 `LogRecord` 编码后的长度
-    fn encoded_length(&self) -> usize {
+    fn encoded_length(&self, checksum: ChecksumType) -> usize {
         std::mem::size_of::<u8>()
             + length_delimiter_len(self.key.len())
             + length_delimiter_len(self.value.len())
+            + encoded_len_varint(self.timestamp)
             + self.key.len()
             + self.value.len()
-            + CRC_SIZE
+            + checksum.size()
     }
 
-    fn encode_and_get_crc(&self) -> Result<(Vec<u8>, u32)> {
+    /// 返回编码之后的数据和校验值, 不校验时校验值为0
+    fn encode_and_get_crc(
+        &self,
+        compression: CompressionType,
+        checksum: ChecksumType,
+        cipher: Option<&RecordCipher>,
+    ) -> Result<(Vec<u8>, u64)> {
         let mut buf = BytesMut::new();
-        buf.reserve(self.encoded_length());
+        buf.reserve(self.encoded_length(checksum));
+
+        let mut compressed = None;
+        if compression != CompressionType::None
+            && self.rec_type == LogRecordType::Normal
+            && !self.value.is_empty()
+        {
+            let v = compression.compress(&self.value)?;
+            if v.len() < self.value.len() {
+                compressed = Some(v);
+            }
+        }
+        let (mut flag, mut value) = match &compressed {
+            Some(v) => (compression.flag(), v.as_slice()),
+            None => (0, self.value.as_slice()),
+        };
+
+        let mut encrypted = None;
+        if let Some(cipher) = cipher {
+            if self.rec_type == LogRecordType::Normal {
+                encrypted = Some(cipher.encrypt(value, &self.key)?);
+            }
+        }
+        if let Some(v) = &encrypted {
+            flag |= ENCRYPTED_FLAG;
+            value = v.as_slice();
+        }
+        if self.timestamp != 0 {
+            flag |= TIMESTAMP_FLAG;
+        }
 
         // 第一个字节:type
-        buf.put_u8(self.rec_type as u8);
+        buf.put_u8(self.rec_type as u8 | flag);
 
         // 存放 key、value的长度
         encode_length_delimiter(self.key.len(), &mut buf)?;
-        encode_length_delimiter(self.value.len(), &mut buf)?;
+        encode_length_delimiter(value.len(), &mut buf)?;
+        if self.timestamp != 0 {
+            encode_varint(self.timestamp, &mut buf);
+        }
 
         // 实际的key、value
         buf.extend_from_slice(&self.key);
-        buf.extend_from_slice(&self.value);
+        buf.extend_from_slice(value);
 
-        // 存放crc
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.update(&buf);
-        let crc = hasher.finalize();
-
-        buf.put_u32(crc);
+        // 存放校验值
+        let crc = checksum.compute(&[&buf]);
+        buf.put_uint(crc, checksum.size());
 
         Ok((buf.to_vec(), crc))
     }
 }
 
+impl ChecksumType {
+    /// 校验值在数据末尾占用的字节数
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            ChecksumType::Crc32 => CRC_SIZE,
+            ChecksumType::XxHash64 => 8,
+            ChecksumType::None => 0,
+        }
+    }
+
+    /// 依次计算`parts`拼接之后的校验值, 不校验时返回0
+    pub(crate) fn compute(&self, parts: &[&[u8]]) -> u64 {
+        match self {
+            ChecksumType::Crc32 => {
+                let mut hasher = crc32fast::Hasher::new();
+                parts.iter().for_each(|part| hasher.update(part));
+                hasher.finalize() as u64
+            }
+            ChecksumType::XxHash64 => {
+                let mut hasher = xxhash_rust::xxh64::Xxh64::new(0);
+                parts.iter().for_each(|part| hasher.update(part));
+                hasher.digest()
+            }
+            ChecksumType::None => 0,
+        }
+    }
+}
+
+impl CompressionType {
+    fn flag(&self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => COMPRESSION_LZ4_FLAG,
+            CompressionType::Zstd => COMPRESSION_ZSTD_FLAG,
+        }
+    }
+
+    /// 从 type 字节中解析出压缩方式
+    pub(crate) fn from_type_byte(value: u8) -> Result<Self> {
+        match value & COMPRESSION_MASK {
+            0 => Ok(CompressionType::None),
+            COMPRESSION_LZ4_FLAG => Ok(CompressionType::Lz4),
+            COMPRESSION_ZSTD_FLAG => Ok(CompressionType::Zstd),
+            _ => Err(Errors::DataFileBroken),
+        }
+    }
+
+    pub(crate) fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            CompressionType::Zstd => Ok(zstd::bulk::compress(data, 0)?),
+        }
+    }
+
+    pub(crate) fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Lz4 => {
+                // 开头的4个字节是原始长度, lz4 的压缩比不会超过 255, 损坏的长度不能用来分配内存
+                let [a, b, c, d, compressed @ ..] = data else {
+                    return Err(Errors::DecompressFailed);
+                };
+                let size = u32::from_le_bytes([*a, *b, *c, *d]) as usize;
+                if size > compressed.len().saturating_mul(255) {
+                    return Err(Errors::DecompressFailed);
+                }
+                lz4_flex::decompress(compressed, size).map_err(|_| Errors::DecompressFailed)
+            }
+            CompressionType::Zstd => zstd::decode_all(data).map_err(|_| Errors::DecompressFailed),
+        }
+    }
+}
+
 /// 从数据文件中读取的`LogRecord`的额外信息
 #[derive(Debug)]
 pub struct ReadLogRecord {
     pub(crate) record: LogRecord,
-    /// 头部大小 + Key Size + Value Size + CRC校验值的大小
+    /// 头部大小 + Key Size + Value Size + 校验值的大小
+    pub(crate) size: usize,
+}
+
+/// 从数据文件中读取的`LogRecord`, `key`和`value`不拷贝到`Vec`中
+/// 内存映射的文件中没有压缩和加密的`value`直接引用映射的内存
+#[derive(Debug)]
+pub(crate) struct ReadLogRecordBytes {
+    pub(crate) key: Bytes,
+    pub(crate) value: Bytes,
+    pub(crate) rec_type: LogRecordType,
+    pub(crate) timestamp: u64,
+    /// 头部大小 + Key Size + Value Size + 校验值的大小
     pub(crate) size: usize,
 }
 
 /// 获取单个 `LogRecord`的 header 部分的最大值
-/// Type + KeySize + ValueSize
-/// 其中 KeySize 和 ValueSize 都是 u32类型的, 是可变长编码,根据整数大小来决定使用多少个字节
+/// Type + KeySize + ValueSize + Timestamp
+/// 其中 KeySize 和 ValueSize 都是 u32类型的, Timestamp 是 u64 类型的, 都是可变长编码,根据整数大小来决定使用多少个字节
 pub fn max_log_record_header_size() -> usize {
-    // Type +  KeySize + ValueSize
-    std::mem::size_of::<u8>() + length_delimiter_len(std::u32::MAX as usize) * 2
+    // Type +  KeySize + ValueSize + Timestamp
+    std::mem::size_of::<u8>()
+        + length_delimiter_len(std::u32::MAX as usize) * 2
+        + encoded_len_varint(u64::MAX)
 }
 
 #[cfg(test)]
@@ -137,6 +346,7 @@ mod tests {
     use std::path::PathBuf;
 
     use bytes::{Buf, Bytes};
+    use proptest::{collection::vec, prelude::*};
 
     use super::*;
     fn basepath() -> &'static str {
@@ -177,6 +387,7 @@ mod tests {
                 key: key,
                 value: value,
                 rec_type: LogRecordType::Normal,
+                timestamp: 0,
             };
 
             // 编码
@@ -202,6 +413,7 @@ mod tests {
                 key: key,
                 value: value,
                 rec_type: LogRecordType::Normal,
+                timestamp: 0,
             };
 
             // 编码
@@ -226,6 +438,7 @@ mod tests {
                 key: key,
                 value: Default::default(),
                 rec_type: LogRecordType::Deleted,
+                timestamp: 0,
             };
 
             // 编码
@@ -260,4 +473,28 @@ mod tests {
         assert_eq!(pos.offset, decoded_pos.offset);
         assert_eq!(pos.size, decoded_pos.size);
     }
+
+    proptest! {
+        #[test]
+        fn proptest_log_record_pos_round_trip(
+            file_id in any::<u32>(),
+            offset in any::<u64>(),
+            size in any::<usize>(),
+        ) {
+            let pos = LogRecordPos { file_id, offset, size };
+            prop_assert_eq!(LogRecordPos::decode(pos.encode().unwrap()).unwrap(), pos);
+        }
+
+        #[test]
+        fn proptest_log_record_pos_decode_arbitrary_bytes(buf in vec(any::<u8>(), 0..32)) {
+            let _ = LogRecordPos::decode(buf);
+        }
+
+        #[test]
+        fn proptest_decompress_arbitrary_bytes(data in vec(any::<u8>(), 0..256)) {
+            for compression in [CompressionType::Lz4, CompressionType::Zstd] {
+                let _ = compression.decompress(&data);
+            }
+        }
+    }
 }
@@ -1,6 +1,12 @@
 pub mod data_file;
+pub(crate) mod encryption;
 pub mod log_record;
 
 pub(crate) const HINT_FILE_NAME: &'static str = "hint-index";
 pub(crate) const MERGE_FINISHED_FILE_NAME: &'static str = "merge-finished";
 pub(crate) const SEQ_NO_FILE_NAME: &'static str = "__seq_no_file__";
+/// 记录文件格式版本的清单
+pub(crate) const MANIFEST_FILE_NAME: &str = "MANIFEST";
+/// 保存大`value`的 blob 文件后缀
+pub(crate) const BLOB_FILE_NAME_SUFFIX: &str = ".blob";
+pub(crate) const QUARANTINE_FILE_NAME: &'static str = "quarantine";
@@ -1,43 +1,64 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     fs::{self, File},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{Read, Write},
     path::PathBuf,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
         Arc,
     },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 use crate::{
     // batch::{log_record_key_with_seq, parse_log_record_key},
     batch::{log_record_key_with_seq, parse_log_record_key, TransactionRecord},
+    blob::BlobFiles,
     data::{
-        data_file::DataFile,
-        log_record::{LogRecord, LogRecordPos, LogRecordType},
-        MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
+        data_file::{get_data_file_name, is_torn_record_error, DataFile},
+        encryption::RecordCipher,
+        log_record::{now_timestamp, LogRecord, LogRecordPos, LogRecordType, ValueMeta},
+        BLOB_FILE_NAME_SUFFIX, QUARANTINE_FILE_NAME, SEQ_NO_FILE_NAME,
     },
+    event::{EventListener, OpenProgress},
     fio::IOType,
-    index,
-    merge::load_merge_files,
-    options::EngineOptions,
+    index::{self, spill::INDEX_SPILL_DIR_NAME, IndexUpdate},
+    ingest::INGEST_TMP_DIR_NAME,
+    manifest::{self, Manifest},
+    merge::{get_merge_path, load_merge_files, load_merge_info},
+    options::{
+        CompressionType, EngineOptions, IndexType, IteratorOptions, RateLimit, RecoveryMode,
+        SyncPolicy, WriteBatchOptions,
+    },
     prelude::*,
-    stat::Stat,
+    rate_limit::RateLimiters,
+    replication,
+    snapshot::SnapshotView,
+    stat::{MemoryUsage, Stat},
     utils,
+    verify::RecoveryReport,
+    watch::{Operation, Watcher},
 };
 use bytes::Bytes;
 use fs2::FileExt;
-use log::{error, warn};
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
+use tracing::{error, field::Empty, instrument, warn, Span};
 
 const INITIAL_FILE_ID: u32 = 0;
 const SEQ_NO_KEY: &str = "__seq_number_key__";
+const KEY_LOCK_NUM: usize = 64;
 pub(crate) const FILE_LOCK_NAME: &str = "lucasdb.lock";
 pub struct Engine {
     pub(crate) options: Arc<EngineOptions>,
     pub(crate) active_file: Arc<RwLock<DataFile>>, // 当前活跃文件
     pub(crate) older_files: Arc<RwLock<HashMap<u32, DataFile>>>, // 旧的数据文件
-    pub(crate) index: Box<dyn index::Indexer>,     // 数据内存索引(并发安全)
-    file_ids: Vec<u32>, // 数据库启动时,获取到的id信息,只用于加载索引时使用
+    /// 从数据文件中分离出来的大`value`
+    pub(crate) blob_files: Arc<RwLock<BlobFiles>>,
+    pub(crate) index: Box<dyn index::Indexer>, // 数据内存索引(并发安全)
+    file_ids: Vec<u32>,                        // 数据库启动时,获取到的id信息,只用于加载索引时使用
 
     pub(crate) batch_commit_lock: Mutex<()>, // 事务提交的锁,保证事务串行化
     pub(crate) seq_no: Arc<AtomicUsize>,     // 事务序列号
@@ -46,53 +67,170 @@ pub struct Engine {
 
     pub(crate) is_initial: bool, //是否第一次初始化目录
 
-    file_lock: File, // 文件锁,保证只能在数据目录上打开文件
+    file_lock: Option<File>, // 文件锁,保证只能在数据目录上打开文件, 内存模式没有文件锁
     /// 累计写入了多少字节
     bytes_write: Arc<AtomicUsize>,
     /// 累计还有多少空间可以merge
     pub(crate) reclaim_size: Arc<AtomicUsize>,
+    /// 每个数据文件中可以回收的数据量, 用于部分merge时挑选文件
+    pub(crate) file_reclaim_size: Mutex<HashMap<u32, usize>>,
+    /// 没有完成的事务序列号(提交失败或者崩溃), merge 时丢弃这些事务的数据
+    pub(crate) aborted_txn_seqs: Mutex<HashSet<usize>>,
+    /// 写入序号,每追加一条数据就加1
+    write_seq: AtomicU64,
+    /// `SyncPolicy::Always` 模式下的组提交
+    group_commit: GroupCommit,
+    /// `SyncPolicy::EveryDuration` 模式下定时持久化的后台线程
+    flusher: Option<Flusher>,
+    /// 数据目录(包括merge临时目录)占用的磁盘空间, 用于限制数据库大小
+    pub(crate) disk_size: AtomicU64,
+    /// 因为超过数据库大小上限而被拒绝的写入次数
+    pub(crate) db_full_count: AtomicUsize,
+    /// 启动时从活跃文件末尾截断的字节数
+    pub(crate) discarded_bytes: u64,
+    /// 数据变更的订阅者
+    pub(crate) watchers: RwLock<Vec<Watcher>>,
+    /// 还没有释放的快照, 修改索引之前需要把原来的位置记到快照中
+    pub(crate) snapshots: RwLock<Vec<Arc<SnapshotView>>>,
+    /// 还没提交的`WriteBatch`暂存数据占用的内存
+    pub(crate) pending_batch_bytes: AtomicUsize,
+    /// `compare_and_swap`等条件写入按key加锁, 不同的key可以同时执行
+    key_locks: Vec<Mutex<()>>,
+    /// 标识这一次打开, 重启之后 merge 的结果生效, 数据的位置会发生变化, 副本需要重新全量同步
+    pub(crate) run_id: u64,
+    /// 加密/解密`value`, 没有配置`encryption_key`时为空
+    pub(crate) cipher: Option<RecordCipher>,
+    /// 是否已经调用了`close`, 之后的读写返回`Errors::EngineClosed`
+    closed: AtomicBool,
+    /// 前台写入和 merge 的限速器
+    pub(crate) rate_limiters: RateLimiters,
+    /// 打开时检查内存索引的结果
+    pub(crate) recovery_report: RecoveryReport,
 }
 
-impl Engine {
-    pub fn open(options: EngineOptions) -> Result<Self> {
-        // 校验options
-        check_options(&options)?;
+/// 组提交: 并发写入的线程中只有一个(leader)执行 sync,
+/// sync 完成后唤醒所有等待的线程, 减少 fsync 的次数
+#[derive(Default)]
+struct GroupCommit {
+    state: Mutex<GroupCommitState>,
+    cond: Condvar,
+}
 
-        // 判断数据目录是否存在,如果不存在,就创建
-        let mut is_initial = false;
+#[derive(Default)]
+struct GroupCommitState {
+    /// 已经持久化的最大写入序号
+    synced_seq: u64,
+    /// 是否有线程正在执行 sync
+    syncing: bool,
+}
 
-        if let Err(e) = utils::file::create_dir_if_not_exist(&options.dir_path) {
-            error!("create database directory error: {}", e);
-            return Err(Errors::IO(e));
-        }
+/// 定时持久化活跃文件的后台线程, 释放时通知线程退出并等待它结束
+struct Flusher {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
 
-        let entries = fs::read_dir(&options.dir_path)?;
-        if entries.count() == 0 {
-            is_initial = true;
-        }
+impl Flusher {
+    /// 每隔`interval`检查一次, 上次之后有写入时 sync 活跃文件
+    fn spawn(
+        active_file: Arc<RwLock<DataFile>>,
+        blob_files: Arc<RwLock<BlobFiles>>,
+        bytes_write: Arc<AtomicUsize>,
+        listener: Option<Arc<dyn EventListener>>,
+        interval: Duration,
+    ) -> Result<Self> {
+        let (stop, stop_rx) = mpsc::channel::<()>();
+        let handle = std::thread::Builder::new()
+            .name("lucasdb-flusher".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                    if bytes_write.swap(0, Ordering::SeqCst) == 0 {
+                        continue;
+                    }
+                    let res = sync_active_file(
+                        &active_file.read(),
+                        &blob_files.read(),
+                        listener.as_deref(),
+                    );
+                    if let Err(e) = res {
+                        error!("background sync error: {}", e);
+                    }
+                }
+            })?;
+        Ok(Flusher {
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+}
 
-        // 检查是否已经打开了一个Engine
-        let file_lock = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(options.dir_path.join(FILE_LOCK_NAME))?;
-        if let Err(_) = file_lock.try_lock_exclusive() {
-            // 没拿到文件锁
-            return Err(Errors::DatabaseIsUsing);
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        // 关闭通道, 线程的 recv_timeout 立即返回
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("background flusher panicked");
+            }
         }
+    }
+}
 
-        // 加载merge数据目录
-        load_merge_files(options.dir_path.clone())?;
+impl Engine {
+    #[instrument(
+        skip_all,
+        fields(
+            dir = %options.dir_path.display(),
+            read_only = options.read_only,
+            data_files = Empty,
+            keys = Empty,
+            elapsed_us = Empty,
+        )
+    )]
+    pub fn open(mut options: EngineOptions) -> Result<Self> {
+        let _timer = utils::trace::span_timer();
+        // 校验options
+        check_options(&options)?;
+        if let Some(interval) = options.sync_interval {
+            options.sync_policy = SyncPolicy::EveryDuration(interval);
+        }
+        let deadline = options.open_timeout.map(|timeout| Instant::now() + timeout);
 
-        // 加载数据文件
-        let mut data_files = load_data_files(&options.dir_path, options.use_mmap_when_startup)?;
-        // 列表中的第一个文件是活跃文件
-        data_files.reverse();
+        // 内存模式不使用数据目录, 没有文件锁、清单和需要加载的文件
+        let cipher = RecordCipher::from_options(&options);
+        let (is_initial, file_lock, mut data_files) = match options.ephemeral {
+            true => (true, None, Vec::new()),
+            false => {
+                let (is_initial, file_lock, manifest) = prepare_data_dir(&options)?;
+                // 数据使用创建时的校验方式, 不能通过配置修改
+                options.checksum = manifest.checksum;
+                // 加载数据文件
+                let io_type = match options.use_mmap_when_startup {
+                    true => IOType::MemoryMap,
+                    false => older_file_io_type(&options),
+                };
+                let data_files = load_data_files(&options.dir_path, io_type, options.read_only)?
+                    .into_iter()
+                    .map(|data_file| {
+                        data_file
+                            .with_cipher(cipher.clone())
+                            .with_checksum(options.checksum)
+                    })
+                    .collect::<Vec<_>>();
+                (is_initial, Some(file_lock), data_files)
+            }
+        };
+        if options.read_only && data_files.is_empty() {
+            return Err(Errors::DataFileNotFound);
+        }
+        // 按文件id从小到大排列, 加载索引时要按这个顺序
         let mut file_ids = vec![];
         for v in data_files.iter() {
             file_ids.push(v.get_file_id());
         }
+        Span::current().record("data_files", file_ids.len());
+        // 列表中的第一个文件是活跃文件
+        data_files.reverse();
 
         let mut older_files = HashMap::new();
         if data_files.len() > 1 {
@@ -105,18 +243,16 @@ impl Engine {
 
         let active_file = match data_files.pop() {
             Some(v) => v,
-            None => DataFile::new(
-                options.dir_path.clone(),
-                INITIAL_FILE_ID,
-                IOType::StandardFileIO,
-            )?,
+            None => new_active_file(&options, INITIAL_FILE_ID)?,
         };
+        let blob_files = BlobFiles::load(&options, cipher.clone())?;
 
         let mut engine = Self {
             options: Arc::new(options.clone()),
             active_file: Arc::new(RwLock::new(active_file)),
             older_files: Arc::new(RwLock::new(older_files)),
-            index: Box::new(index::new_indexer(options.index_type)),
+            blob_files: Arc::new(RwLock::new(blob_files)),
+            index: index::new_indexer(&options)?,
             file_ids: file_ids,
             batch_commit_lock: Mutex::new(()),
             seq_no: Arc::new(AtomicUsize::new(1)),
@@ -125,28 +261,95 @@ impl Engine {
             file_lock,
             bytes_write: Arc::new(AtomicUsize::new(0)),
             reclaim_size: Arc::new(AtomicUsize::new(0)),
+            file_reclaim_size: Mutex::new(HashMap::new()),
+            aborted_txn_seqs: Mutex::new(HashSet::new()),
+            write_seq: AtomicU64::new(0),
+            group_commit: GroupCommit::default(),
+            flusher: None,
+            disk_size: AtomicU64::new(0),
+            db_full_count: AtomicUsize::new(0),
+            discarded_bytes: 0,
+            watchers: RwLock::new(Vec::new()),
+            snapshots: RwLock::new(Vec::new()),
+            pending_batch_bytes: AtomicUsize::new(0),
+            key_locks: (0..KEY_LOCK_NUM).map(|_| Mutex::new(())).collect(),
+            run_id: replication::new_run_id(),
+            cipher,
+            closed: AtomicBool::new(false),
+            rate_limiters: RateLimiters::new(options.rate_limit),
+            recovery_report: RecoveryReport::default(),
         };
 
-        // 从 hint 文件加载索引
-        engine.load_index_from_hint_file()?;
+        // 从 hint 文件加载索引, 内存模式没有 hint 文件
+        if !engine.options.ephemeral {
+            engine.load_index_from_hint_file()?;
+        }
         // 加载内存索引
-        let current_seq_no = engine.load_index_from_data_files()?;
-        // 更新当前事务序列号
-        if current_seq_no > 0 {
-            engine.seq_no.store(current_seq_no, Ordering::SeqCst);
+        let current_seq_no = engine.load_index_from_data_files(deadline)?;
+        // 数据文件被删除之后, hint 文件中的索引会指向不存在的文件
+        match engine.options.recovery_mode {
+            RecoveryMode::None => {}
+            RecoveryMode::Report => engine.recovery_report = engine.check_dangling_index(false)?,
+            RecoveryMode::Repair => engine.recovery_report = engine.check_dangling_index(true)?,
+        }
+        Span::current().record("keys", engine.index.len());
+        // 只读模式不会写入, 下面只和写入有关
+        if engine.options.read_only {
+            return Ok(engine);
         }
+        // 内存模式是一个新的空数据库, 不需要恢复序列号和切换IO类型
+        if engine.options.ephemeral {
+            engine.start_flusher()?;
+            return Ok(engine);
+        }
+
+        // 上次关闭时保存的下一个事务序列号, merge 会清除数据文件中的序列号
+        let saved_seq_no = match engine.load_seq_no() {
+            Ok(seq_no) => seq_no,
+            Err(Errors::SeqNoFileNotExist) => 0,
+            Err(e) => return Err(e),
+        };
+        // 更新当前事务序列号
+        // 序列号不能复用, 否则崩溃时残留的半个事务会和新事务使用同一个序列号, 重启时被一起提交
+        let next_seq_no = std::cmp::max(current_seq_no + 1, saved_seq_no);
+        engine.seq_no.store(next_seq_no, Ordering::SeqCst);
 
         // 重置IO类型,启动后不使用MMap
         if engine.options.use_mmap_when_startup {
             engine.reset_io_type()?;
         }
 
+        // 已经存在的活跃文件也使用写入的IO类型, 并预分配空间
+        {
+            let mut active_file = engine.active_file.write();
+            if engine.options.write_io_type != IOType::StandardFileIO {
+                active_file.set_io_manager(
+                    engine.options.dir_path.clone(),
+                    engine.options.write_io_type,
+                )?;
+            }
+            if preallocate_active_file(&engine.options) {
+                active_file.preallocate(engine.options.data_file_size)?;
+            }
+        }
+
+        engine.start_flusher()?;
+
+        // 统计数据目录当前的大小
+        if engine.options.max_db_size_bytes.is_some() {
+            let merge_path = get_merge_path(engine.options.dir_path.clone());
+            let size = utils::file::dir_disk_size(&engine.options.dir_path)
+                + utils::file::dir_disk_size(&merge_path);
+            engine.disk_size.store(size, Ordering::SeqCst);
+        }
+
         Ok(engine)
     }
 
     /// 备份数据目录
     pub fn backup(&self, dir_path: PathBuf) -> Result<()> {
-        let exclude = [FILE_LOCK_NAME];
+        self.check_persistent("backup")?;
+        let exclude = [FILE_LOCK_NAME, INDEX_SPILL_DIR_NAME, INGEST_TMP_DIR_NAME];
         if let Err(e) = utils::file::copy_dir(self.options.dir_path.clone(), dir_path, &exclude) {
             error!("failed to copy directory: {}", e);
             return Err(Errors::FailedToBackupDatabase);
@@ -154,6 +357,82 @@ impl Engine {
 
         Ok(())
     }
+
+    /// 把数据目录打包成 tar 写到`writer`, 备份的机器不需要能访问数据目录
+    /// 打包期间不能 merge, 只在记录文件列表时短暂阻塞写入和切换活跃文件,
+    /// 之后的写入在活跃文件的末尾或者新的数据文件中, 不会包括在备份里
+    pub fn backup_to_writer<W: Write>(&self, writer: W) -> Result<W> {
+        self.check_open()?;
+        self.check_persistent("backup")?;
+        let _merge_lock = self.merging_lock.lock();
+
+        let (active_file_id, active_size, blob_limit) = {
+            let active_file = self.active_file.write();
+            let blob_files = self.blob_files.read();
+            sync_active_file(&active_file, &blob_files, self.event_listener())?;
+            let blob_limit = (blob_files.next_file_id(), blob_files.active_write_off());
+            (
+                active_file.get_file_id(),
+                active_file.get_write_off(),
+                blob_limit,
+            )
+        };
+
+        let mut builder = tar::Builder::new(writer);
+        let mut entries =
+            fs::read_dir(&self.options.dir_path)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            // 只有锁文件以外的普通文件, 子目录中是临时文件
+            let file_name = entry.file_name();
+            let name = file_name.to_string_lossy();
+            if !entry.file_type()?.is_file() || name == FILE_LOCK_NAME {
+                continue;
+            }
+
+            let file = File::open(entry.path())?;
+            let mut size = file.metadata()?.len();
+            let file_id = name
+                .strip_suffix(DATA_FILE_NAME_SUFFIX)
+                .and_then(|id| id.parse::<u32>().ok());
+            match file_id {
+                Some(id) if id > active_file_id => continue,
+                // 预分配的活跃文件后面是空白
+                Some(id) if id == active_file_id => size = active_size,
+                _ => {}
+            }
+            let blob_file_id = name
+                .strip_suffix(BLOB_FILE_NAME_SUFFIX)
+                .and_then(|id| id.parse::<u32>().ok());
+            match (blob_file_id, blob_limit) {
+                (Some(id), (next_file_id, _)) if id >= next_file_id => continue,
+                (Some(id), (_, Some((active_id, write_off)))) if id == active_id => {
+                    size = write_off
+                }
+                _ => {}
+            }
+
+            let mut header = tar::Header::new_gnu();
+            header.set_metadata(&file.metadata()?);
+            header.set_size(size);
+            builder.append_data(&mut header, name.as_ref(), file.take(size))?;
+        }
+        Ok(builder.into_inner()?)
+    }
+    /// `SyncPolicy::EveryDuration`时启动定时持久化的后台线程
+    fn start_flusher(&mut self) -> Result<()> {
+        if let SyncPolicy::EveryDuration(interval) = self.options.sync_policy {
+            self.flusher = Some(Flusher::spawn(
+                self.active_file.clone(),
+                self.blob_files.clone(),
+                self.bytes_write.clone(),
+                self.options.event_listener.clone(),
+                interval,
+            )?);
+        }
+        Ok(())
+    }
+
     fn reset_io_type(&mut self) -> Result<()> {
         {
             // 重置活跃文件
@@ -165,7 +444,10 @@ impl Engine {
             // 重置旧的数据文件
             let mut older_files = self.older_files.write();
             for (_, file) in older_files.iter_mut() {
-                file.set_io_manager(self.options.dir_path.clone(), IOType::StandardFileIO)?;
+                file.set_io_manager(
+                    self.options.dir_path.clone(),
+                    older_file_io_type(&self.options),
+                )?;
             }
         }
 
@@ -173,146 +455,497 @@ impl Engine {
     }
 
     /// 存储`key`/`value`, `key`不能为空
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            key_len = key.len(),
+            value_len = value.len(),
+            file_id = Empty,
+            offset = Empty,
+            elapsed_us = Empty,
+        )
+    )]
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
-        if key.is_empty() {
-            return Err(Errors::KeyIsEmpty);
-        }
+        self.put_with(key, value, false)
+    }
+
+    /// 和`put`一样, 但是不管`sync_policy`是什么, 返回之前数据都已经持久化
+    /// 多个线程同时调用时通过组提交共享一次 sync
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            key_len = key.len(),
+            value_len = value.len(),
+            file_id = Empty,
+            offset = Empty,
+            elapsed_us = Empty,
+        )
+    )]
+    pub fn put_sync(&self, key: Bytes, value: Bytes) -> Result<()> {
+        self.put_with(key, value, true)
+    }
+
+    fn put_with(&self, key: Bytes, value: Bytes, sync: bool) -> Result<()> {
+        let _timer = utils::trace::span_timer();
+        self.check_writable()?;
+        self.check_key_value_size(&key, &value)?;
         let mut log_record = LogRecord {
             key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO)?,
             value: value.to_vec(),
             rec_type: LogRecordType::Normal,
+            timestamp: now_timestamp(),
         };
 
         let log_record_pos = self.append_log_record(&mut log_record)?;
+        Span::current()
+            .record("file_id", log_record_pos.file_id)
+            .record("offset", log_record_pos.offset);
+        // `SyncPolicy::Always`时写入的时候已经持久化了
+        if sync && self.options.sync_policy != SyncPolicy::Always {
+            self.group_sync(self.write_seq.load(Ordering::SeqCst))?;
+        }
 
         // 更新内存索引
-        if let Some(old_value) = self.index.put(key.to_vec(), log_record_pos) {
-            self.reclaim_size
-                .fetch_add(old_value.size, Ordering::SeqCst);
+        if let Some(old_value) = self.update_index(key.to_vec(), Some(log_record_pos))? {
+            self.add_reclaim_size(&old_value);
+        }
+
+        self.notify_watchers(Operation::Put, &key, &value, &log_record_pos);
+
+        Ok(())
+    }
+
+    /// 检查`key`不为空, 并且`key`/`value`没有超过配置的最大长度
+    pub(crate) fn check_key_value_size(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        if let Some(max) = self.options.max_key_size {
+            if key.len() > max {
+                return Err(Errors::KeyTooLarge {
+                    max,
+                    actual: key.len(),
+                });
+            }
+        }
+        if let Some(max) = self.options.max_value_size {
+            if value.len() > max {
+                return Err(Errors::ValueTooLarge {
+                    max,
+                    actual: value.len(),
+                });
+            }
         }
+        Ok(())
+    }
+
+    /// 关闭之后返回`Errors::EngineClosed`
+    pub(crate) fn check_open(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Errors::EngineClosed);
+        }
+        Ok(())
+    }
 
+    /// 关闭之后返回`Errors::EngineClosed`, 只读模式下返回`Errors::ReadOnly`
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        self.check_open()?;
+        if self.options.read_only {
+            return Err(Errors::ReadOnly);
+        }
         Ok(())
     }
 
     /// 追加写入数据
     /// 返回内存索引信息
+    #[instrument(
+        level = "trace",
+        skip_all,
+        fields(
+            rec_type = ?log_record.rec_type,
+            file_id = Empty,
+            offset = Empty,
+            size = Empty,
+            elapsed_us = Empty,
+        )
+    )]
     pub(crate) fn append_log_record(&self, log_record: &mut LogRecord) -> Result<LogRecordPos> {
+        let _timer = utils::trace::span_timer();
         let dir_path = &self.options.dir_path;
 
-        // 对写入的record进行编码
-        let encoded_record = log_record.encode()?;
-        let encoded_record_len = encoded_record.len() as u64;
+        // 对写入的record进行编码, 大的`value`编码之后写到 blob 文件
+        let big_value = self.is_big_value(log_record);
+        let mut encoded_record = log_record.encode_with(
+            self.options.compression,
+            self.options.checksum,
+            self.cipher.as_ref(),
+        )?;
+        let mut encoded_record_len = encoded_record.len() as u64;
+        // 一条数据必须能放进一个数据文件, 否则切换活跃文件后依然放不下
+        if encoded_record_len > self.options.data_file_size {
+            return Err(Errors::RecordTooLarge {
+                size: encoded_record_len,
+                data_file_size: self.options.data_file_size,
+            });
+        }
+
+        // 在拿到活跃文件的锁之前等待, 不影响其他线程读取
+        if let Some(limiter) = &self.rate_limiters.write {
+            limiter.request(encoded_record_len as usize);
+        }
 
         // 获取到当前活跃文件
         let mut active_file = self.active_file.write();
+        // 入口处检查之后可能已经关闭了, `close`持有活跃文件的写锁设置标记, 这里再检查一次
+        self.check_open()?;
+
+        // 判断写入后是否超过数据库大小的上限
+        if let Some(max_size) = self.options.max_db_size_bytes {
+            if self.disk_size.load(Ordering::SeqCst) + encoded_record_len > max_size {
+                self.db_full_count.fetch_add(1, Ordering::SeqCst);
+                return Err(Errors::DatabaseFull);
+            }
+        }
+
+        // 数据文件中只保存 blob 的位置
+        if big_value {
+            let blob_index =
+                self.append_blob(&log_record.key, &encoded_record, log_record.timestamp)?;
+            encoded_record =
+                blob_index.encode_with(CompressionType::None, self.options.checksum, None)?;
+            encoded_record_len = encoded_record.len() as u64;
+        }
+
         // 活跃文件达到阈值了, 需要持久化,然后开一个新的活跃文件
         if active_file.get_write_off() + encoded_record_len > self.options.data_file_size {
-            active_file.sync()?;
+            sync_active_file(&active_file, &self.blob_files.read(), self.event_listener())?;
             // 当前活跃文件成为旧的活跃文件
             let current_active_file_id = active_file.get_file_id();
-            let old_file = DataFile::new(
-                dir_path.to_owned(),
-                current_active_file_id,
-                IOType::StandardFileIO,
-            )?;
+            // 打开新的数据文件
+            let new_file = new_active_file(&self.options, current_active_file_id + 1)?;
+            let old_file = match self.options.ephemeral {
+                // 内存中的数据只在原来的对象里, 不能按文件名重新打开
+                true => std::mem::replace(&mut *active_file, new_file),
+                false => {
+                    *active_file = new_file;
+                    DataFile::new(
+                        dir_path.to_owned(),
+                        current_active_file_id,
+                        older_file_io_type(&self.options),
+                    )?
+                    .with_cipher(self.cipher.clone())
+                    .with_checksum(self.options.checksum)
+                }
+            };
 
             let mut older_files = self.older_files.write();
 
             older_files.insert(current_active_file_id, old_file);
-
-            // 打开新的数据文件
-            let new_file = DataFile::new(
-                dir_path.clone(),
-                current_active_file_id + 1,
-                IOType::StandardFileIO,
-            )?;
-            *active_file = new_file;
+            if let Some(listener) = self.event_listener() {
+                listener.on_file_rotate(current_active_file_id);
+            }
         }
 
         // 追加写数据到当前活跃文件
         let write_off = active_file.get_write_off();
         active_file.write(&encoded_record)?;
+        self.disk_size
+            .fetch_add(encoded_record_len, Ordering::SeqCst);
+        let write_seq = self.write_seq.fetch_add(1, Ordering::SeqCst) + 1;
 
-        // 更新累计写入字节数
-        let previous = self
-            .bytes_write
-            .fetch_add(encoded_record.len(), Ordering::SeqCst);
+        // 构造内存索引
+        let pos = LogRecordPos {
+            file_id: active_file.get_file_id(),
+            offset: write_off,
+            size: encoded_record.len(),
+        };
+        Span::current()
+            .record("file_id", pos.file_id)
+            .record("offset", pos.offset)
+            .record("size", pos.size);
 
         // 根据配置项来决定是否持久化
-        let mut need_sync = self.options.sync_writes;
-        if !need_sync
-            && self.options.bytes_per_sync > 0
-            && previous + encoded_record.len() >= self.options.bytes_per_sync
-        {
-            need_sync = true;
+        match self.options.sync_policy {
+            // 每次写入都要持久化, 释放活跃文件的锁之后再组提交
+            SyncPolicy::Always => {
+                drop(active_file);
+                self.group_sync(write_seq)?;
+            }
+            SyncPolicy::Never => {}
+            SyncPolicy::EveryNBytes(bytes_per_sync) => {
+                // 更新累计写入字节数
+                let previous = self
+                    .bytes_write
+                    .fetch_add(encoded_record.len(), Ordering::SeqCst);
+                if previous + encoded_record.len() >= bytes_per_sync {
+                    sync_active_file(&active_file, &self.blob_files.read(), self.event_listener())?;
+                    // 清空累计值
+                    self.bytes_write.store(0, Ordering::SeqCst);
+                }
+            }
+            // 后台线程根据累计值判断有没有新的写入
+            SyncPolicy::EveryDuration(_) => {
+                self.bytes_write
+                    .fetch_add(encoded_record.len(), Ordering::SeqCst);
+            }
         }
 
-        if need_sync {
-            active_file.sync()?;
-            // 清空累计值
-            self.bytes_write.store(0, Ordering::SeqCst);
+        Ok(pos)
+    }
+
+    /// 等待写入序号为`write_seq`的数据被持久化
+    /// 没有线程在 sync 时,当前线程成为 leader, 一次 sync 覆盖所有已经写入的数据
+    fn group_sync(&self, write_seq: u64) -> Result<()> {
+        let mut state = self.group_commit.state.lock();
+        loop {
+            if state.synced_seq >= write_seq {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            self.group_commit.cond.wait(&mut state);
         }
 
-        // 构造内存索引
-        Ok(LogRecordPos {
-            file_id: active_file.get_file_id(),
-            offset: write_off,
-            size: encoded_record.len(),
-        })
+        state.syncing = true;
+        let (target, res) = MutexGuard::unlocked(&mut state, || {
+            // 等待更多的写入加入这一组
+            if !self.options.group_commit_max_wait.is_zero() {
+                std::thread::sleep(self.options.group_commit_max_wait);
+            }
+            // 序号不大于 target 的数据都已经写入,
+            // 切换活跃文件时旧文件会先 sync, 所以只需要 sync 当前活跃文件
+            let target = self.write_seq.load(Ordering::SeqCst);
+            let res = sync_active_file(
+                &self.active_file.read(),
+                &self.blob_files.read(),
+                self.event_listener(),
+            );
+            (target, res)
+        });
+
+        state.syncing = false;
+        if res.is_ok() && target > state.synced_seq {
+            state.synced_seq = target;
+        }
+        self.group_commit.cond.notify_all();
+        res
     }
 
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(key_len = key.len(), file_id = Empty, offset = Empty, elapsed_us = Empty)
+    )]
     pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        let _timer = utils::trace::span_timer();
+        self.check_open()?;
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
 
         // 从内存索引中查找key的位置
-        let pos = self.index.get(key.to_vec());
+        let pos = self.index.get(key.to_vec())?;
         if pos.is_none() {
             return Err(Errors::KeyNotFound);
         }
 
         let pos = pos.unwrap();
+        Span::current()
+            .record("file_id", pos.file_id)
+            .record("offset", pos.offset);
         self.get_value_by_position(&pos)
     }
 
+    /// 和`get`一样读取`value`, 同时返回数据所在的文件、位置和最后一次写入的时间, 用于排查问题和判断数据的新旧
+    pub fn get_with_meta(&self, key: Bytes) -> Result<ValueMeta> {
+        self.check_open()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let pos = self.index.get(key.to_vec())?.ok_or(Errors::KeyNotFound)?;
+        self.get_meta_by_position(&pos)
+    }
+
+    /// 判断`key`是否存在, 只查询内存索引, 不读取磁盘
+    pub fn contains_key(&self, key: Bytes) -> Result<bool> {
+        self.check_open()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        Ok(self.index.get(key.to_vec())?.is_some())
+    }
+
+    /// `key`的数量
+    pub fn len(&self) -> Result<usize> {
+        self.check_open()?;
+        Ok(self.index.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
     pub(crate) fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
+        Ok(self.get_meta_by_position(log_record_pos)?.value)
+    }
+
+    /// 读取`value`和它的时间戳
+    pub(crate) fn get_meta_by_position(&self, log_record_pos: &LogRecordPos) -> Result<ValueMeta> {
+        self.check_open()?;
         // 数据在磁盘中的位置,在哪个文件,偏移量
         let log_record_pos = log_record_pos;
 
         let active_file = self.active_file.read();
         let older_files = self.older_files.read();
 
-        // 取到磁盘中的数据
-        let log_record = match active_file.get_file_id() == log_record_pos.file_id {
-            true => active_file.read_log_record(log_record_pos.offset)?.record,
+        let data_file = match active_file.get_file_id() == log_record_pos.file_id {
+            true => &*active_file,
             false => {
                 let data_file = older_files.get(&log_record_pos.file_id);
                 if data_file.is_none() {
                     return Err(Errors::DataFileNotFound);
                 }
+                data_file.unwrap()
+            }
+        };
+
+        let (value, timestamp) = self.read_value(data_file, log_record_pos)?;
+        Ok(ValueMeta {
+            value,
+            timestamp,
+            file_id: log_record_pos.file_id,
+            offset: log_record_pos.offset,
+            size: log_record_pos.size,
+        })
+    }
+
+    /// 批量读取, 返回的结果和`keys`的顺序一致
+    /// 只获取一次数据文件的读锁, 并按 (file_id, offset) 的顺序读取, 减少随机IO
+    pub fn multi_get(&self, keys: &[Bytes]) -> Vec<Result<Bytes>> {
+        if self.check_open().is_err() {
+            return keys.iter().map(|_| Err(Errors::EngineClosed)).collect();
+        }
+        let mut results: Vec<Result<Bytes>> =
+            keys.iter().map(|_| Err(Errors::KeyNotFound)).collect();
+
+        let mut positions = Vec::with_capacity(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            if key.is_empty() {
+                results[i] = Err(Errors::KeyIsEmpty);
+                continue;
+            }
+            match self.index.get(key.to_vec()) {
+                Ok(Some(pos)) => positions.push((i, pos)),
+                Ok(None) => {}
+                Err(e) => results[i] = Err(e),
+            }
+        }
+        positions.sort_by_key(|(_, pos)| (pos.file_id, pos.offset));
 
-                data_file
-                    .unwrap()
-                    .read_log_record(log_record_pos.offset)?
-                    .record
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        for (i, pos) in positions {
+            let data_file = match active_file.get_file_id() == pos.file_id {
+                true => &*active_file,
+                false => match older_files.get(&pos.file_id) {
+                    Some(data_file) => data_file,
+                    None => {
+                        results[i] = Err(Errors::DataFileNotFound);
+                        continue;
+                    }
+                },
+            };
+            results[i] = self.read_value(data_file, &pos).map(|(value, _)| value);
+        }
+        results
+    }
+
+    /// 从`data_file`中读取`log_record_pos`位置的数据
+    /// 返回`value`和写入时的时间戳
+    fn read_value(
+        &self,
+        data_file: &DataFile,
+        log_record_pos: &LogRecordPos,
+    ) -> Result<(Bytes, u64)> {
+        // 取到磁盘中的数据, 内存映射的文件中没有压缩和加密的`value`不拷贝
+        let log_record = match data_file
+            .read_log_record_bytes(log_record_pos.offset, Some(log_record_pos.size))
+        {
+            Ok(log_record) => log_record,
+            Err(Errors::InvalidLogRecordCrc) => {
+                return Err(self.quarantine_record(data_file, log_record_pos))
             }
+            Err(e) => return Err(e),
         };
 
         // 判断这个数据是否有效
         match log_record.rec_type {
-            LogRecordType::Deleted => return Err(Errors::KeyNotFound),
-            _ => return Ok(log_record.value.into()),
+            LogRecordType::Deleted => Err(Errors::KeyNotFound),
+            LogRecordType::BlobIndex => Ok((
+                self.resolve_blob(log_record.rec_type, log_record.value.to_vec())?
+                    .into(),
+                log_record.timestamp,
+            )),
+            _ => Ok((log_record.value, log_record.timestamp)),
         }
     }
 
+    /// 读取到crc校验失败的数据时, 把原始数据记录到 quarantine 文件中, 方便排查
+    /// 返回读取操作应该返回的错误
+    pub(crate) fn quarantine_record(&self, data_file: &DataFile, pos: &LogRecordPos) -> Errors {
+        let file_id = data_file.get_file_id();
+        error!(
+            "corrupted log record, file id:{}, offset:{}, size:{}",
+            file_id, pos.offset, pos.size
+        );
+
+        if let Err(e) = self.write_quarantine_file(data_file, pos) {
+            error!("failed to write quarantine file: {}", e);
+        }
+
+        match self.options.skip_corrupted_records {
+            true => Errors::KeyNotFound,
+            false => Errors::CorruptedLogRecord {
+                file_id,
+                offset: pos.offset,
+            },
+        }
+    }
+
+    fn write_quarantine_file(&self, data_file: &DataFile, pos: &LogRecordPos) -> Result<()> {
+        self.check_writable()?;
+        self.check_persistent("quarantine")?;
+        let raw = data_file.read_raw(pos.offset, pos.size)?;
+        let hex: String = raw.iter().map(|b| format!("{:02x}", b)).collect();
+        // 一次写入一整行, 多个线程同时写入时不会交错
+        let line = format!(
+            "file_id:{} offset:{} size:{} bytes:{}\n",
+            data_file.get_file_id(),
+            pos.offset,
+            pos.size,
+            hex
+        );
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.options.dir_path.join(QUARANTINE_FILE_NAME))?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
     pub fn delete(&self, key: Bytes) -> Result<()> {
+        self.check_writable()?;
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
 
         // 从内存索引中取数据
-        let pos = self.index.get(key.to_vec());
+        let pos = self.index.get(key.to_vec())?;
         if pos.is_none() {
             return Ok(());
         }
@@ -322,23 +955,140 @@ impl Engine {
             key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO)?,
             value: Default::default(),
             rec_type: LogRecordType::Deleted,
+            timestamp: now_timestamp(),
         };
 
         // 追加写入
         let pos = self.append_log_record(&mut record)?;
-        self.reclaim_size.fetch_add(pos.size, Ordering::SeqCst);
+        self.add_reclaim_size(&pos);
 
         // 从内存索引中删除
-        if let Some(old_pos) = self.index.delete(key.to_vec()) {
-            self.reclaim_size.fetch_add(old_pos.size, Ordering::SeqCst);
+        if let Some(old_pos) = self.update_index(key.to_vec(), None)? {
+            self.add_reclaim_size(&old_pos);
         }
 
+        self.notify_watchers(Operation::Delete, &key, &[], &pos);
+
         Ok(())
     }
 
+    /// 比较并交换: `key`当前的值等于`expected`时写入`new`, 返回是否写入
+    /// `expected`为空表示`key`不存在, `new`为空表示删除`key`
+    /// 同一个`key`的`compare_and_swap`互斥执行, 普通的`put`/`delete`不加锁
+    pub fn compare_and_swap(
+        &self,
+        key: Bytes,
+        expected: Option<Bytes>,
+        new: Option<Bytes>,
+    ) -> Result<bool> {
+        self.check_writable()?;
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let _guard = self.lock_key(&key);
+        let current = match self.get(key.clone()) {
+            Ok(value) => Some(value),
+            Err(Errors::KeyNotFound) => None,
+            Err(e) => return Err(e),
+        };
+        if current != expected {
+            return Ok(false);
+        }
+
+        match new {
+            Some(value) => self.put(key, value)?,
+            None => self.delete(key)?,
+        }
+        Ok(true)
+    }
+
+    /// `key`不存在时写入, 返回是否写入, 可以用来实现锁、租约
+    /// 和`compare_and_swap`一样, 同一个`key`的条件写入互斥执行
+    pub fn put_if_absent(&self, key: Bytes, value: Bytes) -> Result<bool> {
+        self.compare_and_swap(key, None, Some(value))
+    }
+
+    /// `key`当前的值等于`expected`时删除, 返回是否删除, 用于只释放自己持有的锁
+    pub fn delete_if(&self, key: Bytes, expected: Bytes) -> Result<bool> {
+        self.compare_and_swap(key, Some(expected), None)
+    }
+
+    /// 获取`key`对应的锁, 持有期间其他线程不能对同一个key执行`compare_and_swap`、`put_if_absent`、`delete_if`
+    pub(crate) fn lock_key(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.key_locks[hasher.finish() as usize % self.key_locks.len()].lock()
+    }
+
+    /// 删除`[start, end)`范围内的所有`key`, 返回删除的数量
+    /// 每`WriteBatchOptions::max_batch_num`个`key`提交一次, 中途失败时之前提交的批次不会回滚
+    pub fn delete_range(&self, start: Bytes, end: Bytes) -> Result<usize> {
+        self.check_writable()?;
+        if start >= end {
+            return Ok(0);
+        }
+        self.delete_matching(IteratorOptions::default(), &start, |key| key < end.as_ref())
+    }
+
+    /// 删除所有以`prefix`开头的`key`, 返回删除的数量, `prefix`不能为空
+    pub fn delete_prefix(&self, prefix: Bytes) -> Result<usize> {
+        self.check_writable()?;
+        if prefix.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        let options = IteratorOptions {
+            prefix: prefix.to_vec(),
+            ..Default::default()
+        };
+        self.delete_matching(options, &prefix, |_| true)
+    }
+
+    /// 从`seek_key`开始按顺序遍历索引, 删除`keep_going`返回 true 的`key`, 直到第一个返回 false 的`key`
+    pub(crate) fn delete_matching<F>(
+        &self,
+        options: IteratorOptions,
+        seek_key: &[u8],
+        keep_going: F,
+    ) -> Result<usize>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let batch_options = WriteBatchOptions {
+            sync_writes: self.options.sync_policy == SyncPolicy::Always,
+            ..Default::default()
+        };
+        let chunk_size = batch_options.max_batch_num as usize;
+
+        let mut index_iter = self.index.iterator(options);
+        index_iter.seek(seek_key.to_vec());
+
+        // 提交后会清空暂存的数据, 可以继续使用同一个批处理
+        let wb = self.new_write_batch(batch_options)?;
+        let mut count = 0;
+        let mut pending = 0;
+        while let Some((key, _)) = index_iter.next() {
+            if !keep_going(key) {
+                break;
+            }
+            wb.delete(Bytes::copy_from_slice(key))?;
+            pending += 1;
+            if pending == chunk_size {
+                wb.commit()?;
+                count += pending;
+                pending = 0;
+            }
+        }
+        wb.commit()?;
+        count += pending;
+
+        Ok(count)
+    }
+
     /// 启动时用到,从数据文件中加载内存索引
     /// 遍历所有数据文件,将key的位置记录起来
-    fn load_index_from_data_files(&mut self) -> Result<usize> {
+    /// 超过`deadline`时返回`Errors::OpenTimeout`
+    fn load_index_from_data_files(&mut self, deadline: Option<Instant>) -> Result<usize> {
         let mut current_seq_no = NON_TRANSACTION_SEQ_NO;
         if self.file_ids.is_empty() {
             return Ok(current_seq_no);
@@ -347,122 +1097,218 @@ impl Engine {
         // 拿到最近未参与merge的文件id
         let mut has_merge = false;
         let mut non_merge_fid = 0;
-        let merge_fin_file = self.options.dir_path.join(MERGE_FINISHED_FILE_NAME);
-        if merge_fin_file.is_file() {
-            let merge_fin_file = DataFile::new_merge_fin_file(self.options.dir_path.clone())?;
-            let merge_fin_record = merge_fin_file.read_log_record(0)?;
-            let v = String::from_utf8(merge_fin_record.record.value).unwrap_or_default();
-            non_merge_fid = v.parse::<u32>().unwrap_or(0);
+        if let Some(merge) = load_merge_info(&self.options.dir_path)? {
+            non_merge_fid = merge.non_merge_fid;
+            // merge 之后的数据没有序列号, 从清单中恢复
+            current_seq_no = merge.max_seq_no;
             has_merge = true;
         }
 
         let active_file = self.active_file.read();
         let older_files = self.older_files.read();
+        let active_file_id = active_file.get_file_id();
+
+        let older_file_ids: Vec<u32> = self
+            .file_ids
+            .iter()
+            .copied()
+            .filter(|file_id| !(has_merge && *file_id < non_merge_fid))
+            .filter(|file_id| *file_id != active_file_id)
+            .collect();
+        // 旧的数据文件和活跃文件
+        let progress = OpenTracker::new(self.event_listener(), deadline, older_file_ids.len() + 1);
+        let read_older_file = |file_id: &u32| -> Result<Vec<TransactionRecord>> {
+            progress.check_timeout()?;
+            let records = match older_files.get(file_id) {
+                Some(data_file) => read_log_records(data_file, false)?.0,
+                None => {
+                    warn!("can't find file_id [{}] in older files", file_id);
+                    Vec::new()
+                }
+            };
+            progress.file_loaded(records.len());
+            Ok(records)
+        };
 
         // 暂存事务相关的数据
         let mut transaction_records = HashMap::new();
 
-        for (i, file_id) in self.file_ids.iter().enumerate() {
-            if has_merge && *file_id < non_merge_fid {
-                continue;
-            }
-            let mut offset = 0;
-            loop {
-                let log_record_res = match *file_id == active_file.get_file_id() {
-                    true => active_file.read_log_record(offset),
-                    false => {
-                        let data_file = match older_files.get(file_id) {
-                            Some(file) => file,
-                            None => {
-                                warn!("can't find file_id [{}] in older files", file_id);
-                                continue;
-                            }
-                        };
-                        data_file.read_log_record(offset)
-                    }
-                };
-
-                let (mut log_record, size) = match log_record_res {
-                    Ok(result) => (result.record, result.size),
-                    Err(e) => {
-                        // EOF: 读到文件末尾
-                        match e {
-                            Errors::ReadDataFileEOF => break,
-                            _ => return Err(e),
-                        }
+        let threads = self
+            .options
+            .startup_threads
+            .clamp(1, older_file_ids.len().max(1));
+        if threads > 1 {
+            // 旧的数据文件不会再修改, 可以并发解析, 再按照文件id的顺序更新索引
+            // 最多同时读取`threads`个文件, 按顺序取回一个文件的结果后再开始读下一个,
+            // 解析出来还没有应用的数据只有这几个文件的, 不会把所有文件的数据都放在内存中
+            let read_older_file = &read_older_file;
+            let mut file_ids = older_file_ids.iter();
+            std::thread::scope(|s| -> Result<()> {
+                let mut pending = VecDeque::with_capacity(threads);
+                for file_id in file_ids.by_ref().take(threads) {
+                    pending.push_back(s.spawn(move || read_older_file(file_id)));
+                }
+                while let Some(handle) = pending.pop_front() {
+                    let records = handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))?;
+                    // 先开始读下一个文件, 再更新索引
+                    if let Some(file_id) = file_ids.next() {
+                        pending.push_back(s.spawn(move || read_older_file(file_id)));
                     }
-                };
+                    let seq_no = self.apply_log_records(records, &mut transaction_records)?;
+                    current_seq_no = current_seq_no.max(seq_no);
+                }
+                Ok(())
+            })?;
+        } else {
+            for file_id in older_file_ids.iter() {
+                let records = read_older_file(file_id)?;
+                let seq_no = self.apply_log_records(records, &mut transaction_records)?;
+                current_seq_no = current_seq_no.max(seq_no);
+            }
+        }
 
-                // 构建内存索引
-                let log_record_pos = LogRecordPos {
-                    file_id: *file_id,
-                    offset,
-                    size: size,
-                };
+        // 活跃文件
+        progress.check_timeout()?;
+        let (records, offset) = read_log_records(&active_file, true)?;
+        progress.file_loaded(records.len());
+        let seq_no = self.apply_log_records(records, &mut transaction_records)?;
+        current_seq_no = current_seq_no.max(seq_no);
+
+        // 截断最后一条完整数据之后的内容, 否则之后追加的数据会写在垃圾数据后面
+        // 预分配的空间和 direct io 填充的部分全是0, 读取时当作文件末尾, 不需要截断
+        let file_size = active_file.file_size()?;
+        let preallocated = (preallocate_active_file(&self.options)
+            || self.options.write_io_type == IOType::DirectIO)
+            && matches!(
+                active_file.read_log_record(offset),
+                Err(Errors::ReadDataFileEOF)
+            );
+        // 只读模式下末尾的数据可能是其他进程正在写入的, 不能截断
+        if file_size > offset && !preallocated && !self.options.read_only {
+            warn!(
+                "truncate active file [{}] from {} to {}",
+                active_file_id, file_size, offset
+            );
+            let file_name = get_data_file_name(&self.options.dir_path, active_file_id);
+            let file = fs::OpenOptions::new().write(true).open(file_name)?;
+            file.set_len(offset)?;
+            file.sync_all()?;
+            self.discarded_bytes = file_size - offset;
+        }
+        // 设置活跃文件的offset
+        active_file.set_write_off(offset);
+
+        // 没有 TxnFinished 标识的事务是提交到一半崩溃的, 直接丢弃
+        for (seq_no, records) in transaction_records.iter() {
+            warn!(
+                "discard {} records of unfinished transaction {}",
+                records.len(),
+                seq_no
+            );
+            for txn_record in records.iter() {
+                self.add_reclaim_size(&txn_record.pos);
+            }
+            self.aborted_txn_seqs.lock().insert(*seq_no);
+        }
 
-                let (real_key, seq_no) = parse_log_record_key(log_record.key.clone())?;
-                if seq_no == NON_TRANSACTION_SEQ_NO {
-                    self.update_index(real_key, log_record.rec_type, log_record_pos);
-                } else {
-                    // 事务数据
-                    if log_record.rec_type == LogRecordType::TxnFinished {
-                        // 更新内存索引,这是个合法的事务数据
-                        let records: &Vec<TransactionRecord> = transaction_records
-                            .get(&seq_no)
-                            .ok_or(Errors::TxnNumberNotFound(seq_no))?;
-
-                        for txn_record in records.iter() {
-                            self.update_index(
-                                txn_record.record.key.clone(),
-                                txn_record.record.rec_type,
-                                txn_record.pos,
-                            );
-                        }
+        Ok(current_seq_no)
+    }
 
-                        transaction_records.remove(&seq_no);
-                    } else {
-                        // 批量提交的数据,暂存
-                        log_record.key = real_key;
-                        transaction_records
-                            .entry(seq_no)
-                            .or_insert(Vec::new())
-                            .push(TransactionRecord {
-                                record: log_record,
-                                pos: log_record_pos,
-                            });
+    /// 按顺序把一个数据文件中的数据更新到内存索引, 返回最大的事务序列号
+    /// 整个文件的修改用`Indexer::extend`一次写入, 不需要每条数据获取一次锁
+    fn apply_log_records(
+        &self,
+        records: Vec<TransactionRecord>,
+        transaction_records: &mut HashMap<usize, Vec<TransactionRecord>>,
+    ) -> Result<usize> {
+        let mut current_seq_no = NON_TRANSACTION_SEQ_NO;
+        let mut updates = Vec::with_capacity(records.len());
+        for TransactionRecord {
+            record: mut log_record,
+            pos: log_record_pos,
+        } in records
+        {
+            let (real_key, seq_no) = parse_log_record_key(log_record.key.clone())?;
+            if seq_no == NON_TRANSACTION_SEQ_NO {
+                self.push_index_update(&mut updates, real_key, log_record.rec_type, log_record_pos);
+            } else {
+                // 事务数据
+                if log_record.rec_type == LogRecordType::TxnFinished {
+                    // 更新内存索引,这是个合法的事务数据
+                    // 部分merge会去掉事务数据的序列号, 只保留 TxnFinished, 这时没有暂存的数据
+                    let records = transaction_records.remove(&seq_no).unwrap_or_default();
+                    for txn_record in records {
+                        self.push_index_update(
+                            &mut updates,
+                            txn_record.record.key,
+                            txn_record.record.rec_type,
+                            txn_record.pos,
+                        );
                     }
+                } else {
+                    // 批量提交的数据,暂存
+                    log_record.key = real_key;
+                    transaction_records
+                        .entry(seq_no)
+                        .or_default()
+                        .push(TransactionRecord {
+                            record: log_record,
+                            pos: log_record_pos,
+                        });
                 }
-                if seq_no > current_seq_no {
-                    current_seq_no = seq_no;
-                }
-                offset += size as u64;
             }
-
-            // 设置活跃文件的offset
-            if i == self.file_ids.len() - 1 {
-                active_file.set_write_off(offset);
+            if seq_no > current_seq_no {
+                current_seq_no = seq_no;
             }
         }
-
+        for old_pos in self.index.extend(updates)? {
+            self.add_reclaim_size(&old_pos);
+        }
         Ok(current_seq_no)
     }
 
-    fn update_index(&self, key: Vec<u8>, rec_type: LogRecordType, pos: LogRecordPos) {
-        if rec_type == LogRecordType::Normal {
-            if let Some(old_pos) = self.index.put(key, pos) {
-                self.reclaim_size.fetch_add(old_pos.size, Ordering::SeqCst);
-            }
+    /// 把一条数据对索引的修改加入到`updates`, 删除标记本身可以直接回收
+    fn push_index_update(
+        &self,
+        updates: &mut Vec<IndexUpdate>,
+        key: Vec<u8>,
+        rec_type: LogRecordType,
+        pos: LogRecordPos,
+    ) {
+        if matches!(rec_type, LogRecordType::Normal | LogRecordType::BlobIndex) {
+            updates.push((key, Some(pos)));
         } else if rec_type == LogRecordType::Deleted {
-            let mut size = pos.size;
-            if let Some(old_pos) = self.index.delete(key) {
-                size += old_pos.size;
-            }
-            self.reclaim_size.fetch_add(size, Ordering::SeqCst);
+            self.add_reclaim_size(&pos);
+            updates.push((key, None));
         }
     }
 
+    /// 累加可以回收的数据量, 同时记到数据所在的文件上
+    pub(crate) fn add_reclaim_size(&self, pos: &LogRecordPos) {
+        self.reclaim_size.fetch_add(pos.size, Ordering::SeqCst);
+        *self
+            .file_reclaim_size
+            .lock()
+            .entry(pos.file_id)
+            .or_default() += pos.size;
+    }
+
     /// 关闭数据库
     pub fn close(&self) -> Result<()> {
+        // 持有活跃文件的写锁设置标记: 正在追加的写入先完成, 之后的写入拿到锁后会看到标记
+        let active_file = self.active_file.write();
+        // 已经关闭过了, `Drop`不会再关闭一次
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // 内存模式没有需要持久化的数据, 也没有文件锁
+        if self.options.ephemeral {
+            return Ok(());
+        }
+
         // 数据目录不在旧返回
         {
             if !self.options.dir_path.is_dir() {
@@ -470,6 +1316,12 @@ impl Engine {
             }
         }
 
+        // 只读模式没有需要持久化的数据
+        if self.options.read_only {
+            self.unlock_data_dir()?;
+            return Ok(());
+        }
+
         // 记录当前事务序列号
         {
             let seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone())?;
@@ -478,29 +1330,44 @@ impl Engine {
                 key: SEQ_NO_KEY.as_bytes().to_vec(),
                 value: seq_no.to_string().into_bytes(),
                 rec_type: LogRecordType::Normal,
+                timestamp: 0,
             };
             seq_no_file.write(&record.encode()?)?;
             seq_no_file.sync()?;
         }
 
         // 活跃文件持久化
-        {
-            let active_file = self.active_file.read();
-            active_file.sync()?;
-        }
+        sync_active_file(&active_file, &self.blob_files.read(), self.event_listener())?;
         // 释放文件锁
-        {
-            self.file_lock.unlock()?;
-        }
+        self.unlock_data_dir()?;
         // 其他资源
 
         Ok(())
     }
 
+    fn unlock_data_dir(&self) -> Result<()> {
+        if let Some(file_lock) = &self.file_lock {
+            file_lock.unlock()?;
+        }
+        Ok(())
+    }
+
+    /// 内存模式不支持需要数据目录的操作, 返回`Errors::EphemeralUnsupported`
+    pub(crate) fn check_persistent(&self, operation: &str) -> Result<()> {
+        if self.options.ephemeral {
+            return Err(Errors::EphemeralUnsupported(operation.to_string()));
+        }
+        Ok(())
+    }
+
     /// 持久化活跃文件
     pub fn sync(&self) -> Result<()> {
-        let active_file = self.active_file.read();
-        active_file.sync()
+        self.check_open()?;
+        sync_active_file(
+            &self.active_file.read(),
+            &self.blob_files.read(),
+            self.event_listener(),
+        )
     }
 
     // 从数据文件中读取索引号
@@ -511,7 +1378,15 @@ impl Engine {
         }
         let seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone())?;
 
-        let record = seq_no_file.read_log_record(0)?;
+        // 上次关闭时写到一半崩溃了, 和没有正常关闭一样处理
+        let record = match seq_no_file.read_log_record(0) {
+            Ok(record) => record,
+            Err(Errors::ReadDataFileEOF | Errors::InvalidLogRecordCrc) => {
+                fs::remove_file(file_name)?;
+                return Err(Errors::SeqNoFileNotExist);
+            }
+            Err(e) => return Err(e),
+        };
         let v = String::from_utf8(record.record.value)?;
         let seq_no = v.parse::<usize>()?;
 
@@ -522,15 +1397,34 @@ impl Engine {
     }
 
     pub fn stat(&self) -> Result<Stat> {
-        let keys = self.list_keys()?;
+        self.check_open()?;
         let older_files = self.older_files.read();
         Ok(Stat {
-            key_num: keys.len(),
+            key_num: self.index.len(),
+            index: self.index.stat(),
             data_file_num: older_files.len(),
             reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
             disk_size: utils::file::dir_disk_size(&self.options.dir_path) as usize,
+            db_full_count: self.db_full_count.load(Ordering::SeqCst),
+            discarded_bytes: self.discarded_bytes as usize,
+            write_throttle: self.rate_limiters.write.as_ref().map(|l| l.stat()),
+            merge_throttle: self.rate_limiters.merge.as_ref().map(|l| l.stat()),
         })
     }
+
+    /// 估算引擎占用的内存, 用于在一个进程中运行多个引擎时分配内存预算
+    /// 需要遍历内存索引, 不适合频繁调用
+    pub fn estimate_memory_usage(&self) -> MemoryUsage {
+        let data_file_num = self.older_files.read().len() + 1;
+        MemoryUsage {
+            index: self.index.memory_usage(),
+            data_files: data_file_num * std::mem::size_of::<DataFile>(),
+            pending_batches: self.pending_batch_bytes.load(Ordering::SeqCst),
+            watchers: self.watchers.read().len() * std::mem::size_of::<Watcher>(),
+            #[cfg(feature = "alloc-stats")]
+            allocated: crate::alloc::allocated_bytes(),
+        }
+    }
 }
 
 // 析构
@@ -542,8 +1436,78 @@ impl Drop for Engine {
     }
 }
 
-/// 从dir_path中加载数据文件
-fn load_data_files(dir_path: &PathBuf, use_mmap: bool) -> Result<Vec<DataFile>> {
+/// 创建数据目录、获取文件锁、检查清单并加载 merge 的结果, 返回是否第一次初始化目录、文件锁和清单
+fn prepare_data_dir(options: &EngineOptions) -> Result<(bool, File, Manifest)> {
+    // 判断数据目录是否存在,如果不存在,就创建
+    // 只读模式不创建, 目录不存在时下面读取目录会返回错误
+    let mut is_initial = false;
+
+    if !options.read_only {
+        if let Err(e) = utils::file::create_dir_if_not_exist(&options.dir_path) {
+            error!("create database directory error: {}", e);
+            return Err(Errors::IO(e));
+        }
+    }
+
+    let entries = fs::read_dir(&options.dir_path)?;
+    if entries.count() == 0 {
+        is_initial = true;
+    }
+
+    // 检查是否已经打开了一个Engine
+    // 写入进程使用排他锁, 只读进程使用共享锁
+    let file_lock = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(options.dir_path.join(FILE_LOCK_NAME))?;
+    let locked = lock_file_with_wait(&file_lock, !options.read_only, options.lock_wait);
+    if options.read_only {
+        // 共享锁阻止之后的写入进程打开数据库(打开时会替换merge后的文件、截断活跃文件)
+        // 已经在运行的写入进程只会追加写入, 拿不到共享锁时依然可以读取打开时的数据
+        if !locked {
+            warn!("database is opened by another process, read without the shared lock");
+        }
+    } else if !locked {
+        // 没拿到文件锁
+        return Err(Errors::DatabaseIsUsing);
+    }
+
+    // 检查文件格式的版本, 第一次初始化时写入清单
+    let manifest = manifest::check_manifest(options, is_initial)?;
+
+    // 加载merge数据目录
+    if !options.read_only {
+        load_merge_files(options.dir_path.clone())?;
+    }
+
+    Ok((is_initial, file_lock, manifest))
+}
+
+/// 获取文件锁, 拿不到时每隔一段时间重试, 最多等待`wait`, 返回是否拿到了锁
+pub(crate) fn lock_file_with_wait(file: &File, exclusive: bool, wait: Duration) -> bool {
+    const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+    let deadline = Instant::now() + wait;
+    loop {
+        let res = match exclusive {
+            true => FileExt::try_lock_exclusive(file),
+            false => FileExt::try_lock_shared(file),
+        };
+        if res.is_ok() {
+            return true;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        std::thread::sleep(RETRY_INTERVAL.min(deadline - now));
+    }
+}
+
+/// 从dir_path中加载数据文件, `read_only`时以只读方式打开, 忽略`io_type`
+fn load_data_files(dir_path: &PathBuf, io_type: IOType, read_only: bool) -> Result<Vec<DataFile>> {
     let dir = fs::read_dir(dir_path);
     if dir.is_err() {
         return Err(Errors::DataFileLoadError(dir.unwrap_err()));
@@ -591,24 +1555,189 @@ fn load_data_files(dir_path: &PathBuf, use_mmap: bool) -> Result<Vec<DataFile>>
     // 排序,文件id最大的默认是活跃文件
     file_ids.sort();
 
-    let mut io_type = IOType::StandardFileIO;
-    if use_mmap {
-        io_type = IOType::MemoryMap;
-    }
-
     for file_id in file_ids.iter() {
-        let data_file = DataFile::new(dir_path.clone(), *file_id, io_type)?;
+        let data_file = match read_only {
+            true => DataFile::open_read_only(dir_path.clone(), *file_id)?,
+            false => DataFile::new(dir_path.clone(), *file_id, io_type)?,
+        };
         data_files.push(data_file);
     }
     return Ok(data_files);
 }
 
+/// 打开一个新的活跃文件, 根据配置预分配空间
+pub(crate) fn new_active_file(options: &EngineOptions, file_id: u32) -> Result<DataFile> {
+    let io_type = match options.ephemeral {
+        true => IOType::InMemory,
+        false => options.write_io_type,
+    };
+    let data_file = DataFile::new(options.dir_path.clone(), file_id, io_type)?
+        .with_cipher(RecordCipher::from_options(options))
+        .with_checksum(options.checksum);
+    if preallocate_active_file(options) {
+        data_file.preallocate(options.data_file_size)?;
+    }
+    Ok(data_file)
+}
+
+/// mmap 写入之前需要先扩展文件, 所以总是预分配, 内存模式不预分配
+fn preallocate_active_file(options: &EngineOptions) -> bool {
+    !options.ephemeral
+        && (options.preallocate_data_file || options.write_io_type == IOType::MemoryMap)
+}
+
+/// 旧的数据文件只用于读取, 使用 direct io / io_uring 时读取也使用同样的方式
+pub(crate) fn older_file_io_type(options: &EngineOptions) -> IOType {
+    match options.write_io_type {
+        IOType::DirectIO => IOType::DirectIO,
+        #[cfg(feature = "io-uring")]
+        IOType::IoUring => IOType::IoUring,
+        _ => IOType::StandardFileIO,
+    }
+}
+
+/// 先持久化活跃文件引用的 blob, 再持久化活跃文件, 成功后通知`EventListener::on_sync`
+pub(crate) fn sync_active_file(
+    active_file: &DataFile,
+    blob_files: &BlobFiles,
+    listener: Option<&dyn EventListener>,
+) -> Result<()> {
+    blob_files.sync()?;
+    active_file.sync()?;
+    if let Some(listener) = listener {
+        listener.on_sync(active_file.get_file_id());
+    }
+    Ok(())
+}
+
+/// 打开时加载索引的进度, 通知`EventListener::on_open_progress`并检查是否超时
+struct OpenTracker<'a> {
+    listener: Option<&'a dyn EventListener>,
+    deadline: Option<Instant>,
+    files_discovered: usize,
+    files_loaded: AtomicUsize,
+    records_indexed: AtomicUsize,
+}
+
+impl<'a> OpenTracker<'a> {
+    fn new(
+        listener: Option<&'a dyn EventListener>,
+        deadline: Option<Instant>,
+        files_discovered: usize,
+    ) -> Self {
+        let tracker = OpenTracker {
+            listener,
+            deadline,
+            files_discovered,
+            files_loaded: AtomicUsize::new(0),
+            records_indexed: AtomicUsize::new(0),
+        };
+        tracker.notify(0, 0);
+        tracker
+    }
+
+    fn check_timeout(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(Errors::OpenTimeout {
+                files_loaded: self.files_loaded.load(Ordering::SeqCst),
+                files_discovered: self.files_discovered,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// 读完了一个数据文件中的`records`条数据
+    fn file_loaded(&self, records: usize) {
+        let files_loaded = self.files_loaded.fetch_add(1, Ordering::SeqCst) + 1;
+        let records_indexed = self.records_indexed.fetch_add(records, Ordering::SeqCst) + records;
+        self.notify(files_loaded, records_indexed);
+    }
+
+    fn notify(&self, files_loaded: usize, records_indexed: usize) {
+        if let Some(listener) = self.listener {
+            listener.on_open_progress(&OpenProgress {
+                files_discovered: self.files_discovered,
+                files_loaded,
+                records_indexed,
+            });
+        }
+    }
+}
+
+/// 读取数据文件中的所有数据, 返回数据和最后一条完整数据的结束位置
+/// 活跃文件末尾可能有写入到一半的数据, 校验失败并且后面没有合法的数据时停止读取
+fn read_log_records(
+    data_file: &DataFile,
+    is_active_file: bool,
+) -> Result<(Vec<TransactionRecord>, u64)> {
+    let file_id = data_file.get_file_id();
+    let mut records = Vec::new();
+    let mut offset = 0;
+    loop {
+        let (mut log_record, size) = match data_file.read_log_record(offset) {
+            Ok(result) => (result.record, result.size),
+            Err(e) => {
+                // EOF: 读到文件末尾
+                match e {
+                    Errors::ReadDataFileEOF => break,
+                    // 活跃文件末尾写入到一半的数据, 后面会被截断
+                    // 后面还有合法的数据时是文件中间损坏了, 截断会丢掉这些数据, IO 错误也不能截断
+                    _ if is_active_file && is_torn_record_error(&e) => {
+                        if let Some(next) = data_file.find_valid_log_record(offset + 1)? {
+                            error!(
+                                "corrupted log record in active file [{}], offset: {}, next valid record: {}, error: {}",
+                                file_id, offset, next, e
+                            );
+                            return Err(Errors::CorruptedLogRecord { file_id, offset });
+                        }
+                        warn!(
+                            "invalid log record in active file [{}], offset: {}, error: {}",
+                            file_id, offset, e
+                        );
+                        break;
+                    }
+                    _ => return Err(e),
+                }
+            }
+        };
+
+        // 构建索引用不到value
+        log_record.value = Vec::new();
+        records.push(TransactionRecord {
+            record: log_record,
+            pos: LogRecordPos {
+                file_id,
+                offset,
+                size,
+            },
+        });
+        offset += size as u64;
+    }
+    Ok((records, offset))
+}
+
 fn check_options(opts: &EngineOptions) -> Result<()> {
     let dir_path = opts.dir_path.to_str();
-    if dir_path.is_none() || dir_path.unwrap().is_empty() {
+    if !opts.ephemeral && (dir_path.is_none() || dir_path.unwrap().is_empty()) {
         return Err(Errors::DirPathIsEmpty);
     }
 
+    if opts.ephemeral {
+        if opts.read_only {
+            return Err(Errors::EphemeralUnsupported("read-only mode".to_string()));
+        }
+        if opts.index_type == IndexType::Spill {
+            return Err(Errors::EphemeralUnsupported("spill index".to_string()));
+        }
+        if opts.big_value_threshold.is_some() {
+            return Err(Errors::EphemeralUnsupported(
+                "big value separation".to_string(),
+            ));
+        }
+    } else if opts.write_io_type == IOType::InMemory {
+        return Err(Errors::InMemoryRequiresEphemeral);
+    }
+
     if opts.data_file_size <= 0 {
         return Err(Errors::DataFileSizeTooSmall);
     }
@@ -617,11 +1746,36 @@ fn check_options(opts: &EngineOptions) -> Result<()> {
         return Err(Errors::InvalidMergeRatio);
     }
 
+    match opts.sync_policy {
+        SyncPolicy::EveryNBytes(0) => return Err(Errors::InvalidSyncPolicy),
+        SyncPolicy::EveryDuration(interval) if interval.is_zero() => {
+            return Err(Errors::InvalidSyncPolicy)
+        }
+        _ => {}
+    }
+
+    match (opts.sync_interval, opts.sync_policy) {
+        (Some(interval), _) if interval.is_zero() => return Err(Errors::InvalidSyncPolicy),
+        (None, _) | (Some(_), SyncPolicy::Never) => {}
+        (Some(interval), SyncPolicy::EveryDuration(policy_interval))
+            if interval == policy_interval => {}
+        _ => return Err(Errors::InvalidSyncPolicy),
+    }
+
+    match opts.rate_limit {
+        Some(RateLimit::Shared(0))
+        | Some(RateLimit::Separate { write: Some(0), .. })
+        | Some(RateLimit::Separate { merge: Some(0), .. }) => return Err(Errors::InvalidRateLimit),
+        _ => {}
+    }
+
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::options::{CompressionType, IndexType, WriteBatchOptions};
+
     use super::*;
     fn basepath() -> PathBuf {
         "./tmp/db".into()
@@ -731,6 +1885,95 @@ mod tests {
         clean("get");
     }
 
+    #[test]
+    fn test_db_contains_key() {
+        setup("contains_key");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("contains_key");
+
+        let db = Engine::open(opts).expect("failed to open engine");
+        assert!(db.is_empty().unwrap());
+        assert_eq!(db.len().unwrap(), 0);
+
+        db.put(Bytes::from("key-1"), Bytes::from("value-1"))
+            .unwrap();
+        db.put(Bytes::from("key-2"), Bytes::from("")).unwrap();
+        assert!(db.contains_key(Bytes::from("key-1")).unwrap());
+        assert!(db.contains_key(Bytes::from("key-2")).unwrap());
+        assert!(!db.contains_key(Bytes::from("key-3")).unwrap());
+        assert!(matches!(
+            db.contains_key(Bytes::new()),
+            Err(Errors::KeyIsEmpty)
+        ));
+        assert!(!db.is_empty().unwrap());
+        assert_eq!(db.len().unwrap(), 2);
+
+        db.delete(Bytes::from("key-1")).unwrap();
+        assert!(!db.contains_key(Bytes::from("key-1")).unwrap());
+        assert_eq!(db.len().unwrap(), 1);
+
+        clean("contains_key");
+    }
+
+    #[test]
+    fn test_db_get_with_meta() {
+        setup("get_with_meta");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("get_with_meta");
+        opts.data_file_size = 1024;
+        opts.data_file_merge_ratio = 0f32;
+
+        let db = Engine::open(opts).expect("failed to open engine");
+        let before = now_timestamp();
+        db.put(Bytes::from("key-1"), Bytes::from("value-1"))
+            .unwrap();
+        let meta = db.get_with_meta(Bytes::from("key-1")).unwrap();
+        let pos = db.index.get(b"key-1".to_vec()).unwrap().unwrap();
+        assert_eq!(meta.value, Bytes::from("value-1"));
+        assert!(meta.timestamp >= before && meta.timestamp <= now_timestamp());
+        assert_eq!(
+            (meta.file_id, meta.offset, meta.size),
+            (pos.file_id, pos.offset, pos.size)
+        );
+
+        // 写满一个文件之后, 新写入的数据在后面的文件中
+        for i in 0..100 {
+            let key = Bytes::from(format!("key-{:03}", i));
+            db.put(key, Bytes::from(vec![b'v'; 32])).unwrap();
+        }
+        db.put(Bytes::from("key-1"), Bytes::from("value-2"))
+            .unwrap();
+        let updated = db.get_with_meta(Bytes::from("key-1")).unwrap();
+        assert_eq!(updated.value, Bytes::from("value-2"));
+        assert!(updated.file_id > meta.file_id);
+        assert!(updated.timestamp >= meta.timestamp);
+
+        // 事务中的数据也有时间戳
+        let wb = db.new_write_batch(WriteBatchOptions::default()).unwrap();
+        wb.put(Bytes::from("key-2"), Bytes::from("value-2"))
+            .unwrap();
+        wb.commit().unwrap();
+        let txn = db.get_with_meta(Bytes::from("key-2")).unwrap();
+        assert!(txn.timestamp >= updated.timestamp);
+
+        // merge 之后保留原来的时间戳
+        db.merge().unwrap();
+        let merged = db.get_with_meta(Bytes::from("key-2")).unwrap();
+        assert_eq!(merged.timestamp, txn.timestamp);
+
+        db.delete(Bytes::from("key-1")).unwrap();
+        assert!(matches!(
+            db.get_with_meta(Bytes::from("key-1")),
+            Err(Errors::KeyNotFound)
+        ));
+        assert!(matches!(
+            db.get_with_meta(Bytes::new()),
+            Err(Errors::KeyIsEmpty)
+        ));
+
+        clean("get_with_meta");
+    }
+
     #[test]
     fn test_db_delete() {
         setup("delete");
@@ -761,6 +2004,254 @@ mod tests {
         clean("delete");
     }
 
+    #[test]
+    fn test_db_delete_range_and_prefix() {
+        setup("delete_range");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("delete_range").into();
+        let db = Engine::open(opts).unwrap();
+
+        for i in 0..25 {
+            db.put(Bytes::from(format!("a-{:03}", i)), Bytes::from("v"))
+                .unwrap();
+            db.put(Bytes::from(format!("b-{:03}", i)), Bytes::from("v"))
+                .unwrap();
+        }
+
+        // [a-005, a-015)
+        let count = db
+            .delete_range(Bytes::from("a-005"), Bytes::from("a-015"))
+            .unwrap();
+        assert_eq!(count, 10);
+        assert!(db.contains_key(Bytes::from("a-004")).unwrap());
+        assert!(!db.contains_key(Bytes::from("a-005")).unwrap());
+        assert!(!db.contains_key(Bytes::from("a-014")).unwrap());
+        assert!(db.contains_key(Bytes::from("a-015")).unwrap());
+        assert_eq!(
+            db.delete_range(Bytes::from("a-015"), Bytes::from("a-015"))
+                .unwrap(),
+            0
+        );
+
+        assert_eq!(db.delete_prefix(Bytes::from("b-")).unwrap(), 25);
+        assert_eq!(db.delete_prefix(Bytes::from("b-")).unwrap(), 0);
+        assert!(matches!(
+            db.delete_prefix(Bytes::new()),
+            Err(Errors::KeyIsEmpty)
+        ));
+        assert_eq!(db.len().unwrap(), 15);
+
+        // 删除的结果在重启后依然有效
+        db.close().unwrap();
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("delete_range").into();
+        let db = Engine::open(opts).unwrap();
+        assert_eq!(db.len().unwrap(), 15);
+        assert!(!db.contains_key(Bytes::from("b-000")).unwrap());
+
+        clean("delete_range");
+    }
+
+    #[test]
+    fn test_db_key_value_size_limit() {
+        setup("size_limit");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("size_limit").into();
+        opts.data_file_size = 1024;
+        opts.max_key_size = Some(8);
+        opts.max_value_size = Some(16);
+        let db = Engine::open(opts).unwrap();
+
+        assert!(matches!(
+            db.put(Bytes::from("key-too-large"), Bytes::from("v")),
+            Err(Errors::KeyTooLarge { max: 8, actual: 13 })
+        ));
+        assert!(matches!(
+            db.put(Bytes::from("key"), Bytes::from(vec![0u8; 17])),
+            Err(Errors::ValueTooLarge {
+                max: 16,
+                actual: 17
+            })
+        ));
+        db.put(Bytes::from("key"), Bytes::from(vec![0u8; 16]))
+            .unwrap();
+
+        let wb = db.new_write_batch(WriteBatchOptions::default()).unwrap();
+        assert!(matches!(
+            wb.put(Bytes::from("key-too-large"), Bytes::from("v")),
+            Err(Errors::KeyTooLarge { .. })
+        ));
+        assert!(matches!(
+            wb.put(Bytes::from("key"), Bytes::from(vec![0u8; 17])),
+            Err(Errors::ValueTooLarge { .. })
+        ));
+        drop(wb);
+        db.close().unwrap();
+
+        // 没有限制时, 放不进一个数据文件的数据也会被拒绝
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("size_limit").into();
+        opts.data_file_size = 1024;
+        let db = Engine::open(opts).unwrap();
+        assert!(matches!(
+            db.put(Bytes::from("key"), Bytes::from(vec![0u8; 1024])),
+            Err(Errors::RecordTooLarge {
+                data_file_size: 1024,
+                ..
+            })
+        ));
+        assert_eq!(db.get(Bytes::from("key")).unwrap().len(), 16);
+
+        clean("size_limit");
+    }
+
+    #[test]
+    fn test_db_compare_and_swap() {
+        setup("compare_and_swap");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("compare_and_swap").into();
+        let db = Arc::new(Engine::open(opts).unwrap());
+        let key = Bytes::from("cas-key");
+
+        // key 不存在时才写入
+        assert!(db
+            .compare_and_swap(key.clone(), None, Some(Bytes::from("1")))
+            .unwrap());
+        assert!(!db
+            .compare_and_swap(key.clone(), None, Some(Bytes::from("2")))
+            .unwrap());
+        assert!(!db
+            .compare_and_swap(key.clone(), Some(Bytes::from("0")), Some(Bytes::from("2")))
+            .unwrap());
+        assert_eq!(db.get(key.clone()).unwrap(), "1");
+
+        // new 为空时删除
+        assert!(db
+            .compare_and_swap(key.clone(), Some(Bytes::from("1")), None)
+            .unwrap());
+        assert!(matches!(db.get(key.clone()), Err(Errors::KeyNotFound)));
+
+        // 并发计数, 失败时重试, 最终结果不会丢失更新
+        db.put(key.clone(), Bytes::from("0")).unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                let key = key.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        loop {
+                            let current = db.get(key.clone()).unwrap();
+                            let n: u64 = String::from_utf8(current.to_vec())
+                                .unwrap()
+                                .parse()
+                                .unwrap();
+                            let next = Bytes::from((n + 1).to_string());
+                            if db
+                                .compare_and_swap(key.clone(), Some(current), Some(next))
+                                .unwrap()
+                            {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(db.get(key.clone()).unwrap(), "200");
+
+        clean("compare_and_swap");
+    }
+
+    #[test]
+    fn test_db_put_if_absent_and_delete_if() {
+        setup("put_if_absent");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("put_if_absent");
+        let db = Arc::new(Engine::open(opts).unwrap());
+        let key = Bytes::from("lock");
+
+        assert!(db
+            .put_if_absent(key.clone(), Bytes::from("owner-1"))
+            .unwrap());
+        assert!(!db
+            .put_if_absent(key.clone(), Bytes::from("owner-2"))
+            .unwrap());
+        assert_eq!(db.get(key.clone()).unwrap(), "owner-1");
+
+        // 只有持有者可以删除
+        assert!(!db.delete_if(key.clone(), Bytes::from("owner-2")).unwrap());
+        assert!(db.delete_if(key.clone(), Bytes::from("owner-1")).unwrap());
+        assert!(!db.delete_if(key.clone(), Bytes::from("owner-1")).unwrap());
+        assert!(matches!(db.get(key.clone()), Err(Errors::KeyNotFound)));
+        assert!(matches!(
+            db.put_if_absent(Bytes::new(), Bytes::from("v")),
+            Err(Errors::KeyIsEmpty)
+        ));
+
+        // 同时抢锁时只有一个线程成功
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let db = db.clone();
+                let key = key.clone();
+                std::thread::spawn(move || {
+                    db.put_if_absent(key, Bytes::from(format!("owner-{}", i)))
+                        .unwrap()
+                })
+            })
+            .collect();
+        let acquired = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|acquired| *acquired)
+            .count();
+        assert_eq!(acquired, 1);
+
+        clean("put_if_absent");
+    }
+
+    #[test]
+    fn test_db_multi_get() {
+        setup("multi_get");
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join("multi_get").into();
+        opts.data_file_size = 4 * 1024;
+        let db = Engine::open(opts).unwrap();
+
+        // 数据分布在多个数据文件中
+        for i in 0..200 {
+            db.put(
+                Bytes::from(format!("key-{:03}", i)),
+                Bytes::from(format!("value-{:03}", i)),
+            )
+            .unwrap();
+        }
+        assert!(db.older_files.read().len() > 0);
+        db.delete(Bytes::from("key-100")).unwrap();
+
+        let keys = vec![
+            Bytes::from("key-199"),
+            Bytes::from("key-000"),
+            Bytes::from("missing"),
+            Bytes::new(),
+            Bytes::from("key-100"),
+            Bytes::from("key-050"),
+        ];
+        let results = db.multi_get(&keys);
+        assert_eq!(results.len(), keys.len());
+        assert_eq!(results[0].as_ref().unwrap(), "value-199");
+        assert_eq!(results[1].as_ref().unwrap(), "value-000");
+        assert!(matches!(results[2], Err(Errors::KeyNotFound)));
+        assert!(matches!(results[3], Err(Errors::KeyIsEmpty)));
+        assert!(matches!(results[4], Err(Errors::KeyNotFound)));
+        assert_eq!(results[5].as_ref().unwrap(), "value-050");
+        assert!(db.multi_get(&[]).is_empty());
+
+        clean("multi_get");
+    }
+
     #[test]
     fn test_db_close() {
         setup("close");
@@ -782,6 +2273,71 @@ mod tests {
         clean("close");
     }
 
+    #[test]
+    fn test_db_use_after_close() {
+        let dir_name = "use_after_close";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+
+        let db = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+        db.put(Bytes::from("k"), Bytes::from("v")).unwrap();
+        let iter = db.iter(IteratorOptions::default());
+
+        // 并发写入的线程在关闭之后全部失败, 不会写到已经释放的数据库中
+        let writer = {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                let mut i = 0;
+                loop {
+                    match db.put(Bytes::from(format!("key-{}", i)), Bytes::from("v")) {
+                        Ok(()) => i += 1,
+                        Err(Errors::EngineClosed) => return,
+                        Err(e) => panic!("unexpected error: {}", e),
+                    }
+                }
+            })
+        };
+        std::thread::sleep(Duration::from_millis(10));
+        db.close().unwrap();
+        writer.join().unwrap();
+
+        // 重复关闭不会出错
+        assert!(db.close().is_ok());
+        assert!(matches!(
+            db.get(Bytes::from("k")),
+            Err(Errors::EngineClosed)
+        ));
+        assert!(matches!(
+            db.delete(Bytes::from("k")),
+            Err(Errors::EngineClosed)
+        ));
+        assert!(matches!(iter.next(), Err(Errors::EngineClosed)));
+        assert!(matches!(db.merge(), Err(Errors::EngineClosed)));
+        assert!(matches!(
+            db.contains_key(Bytes::from("k")),
+            Err(Errors::EngineClosed)
+        ));
+        assert!(matches!(db.len(), Err(Errors::EngineClosed)));
+        assert!(matches!(
+            db.multi_get(&[Bytes::from("k")])[0],
+            Err(Errors::EngineClosed)
+        ));
+        assert!(matches!(db.stat(), Err(Errors::EngineClosed)));
+        assert!(matches!(
+            db.new_write_batch(WriteBatchOptions::default()),
+            Err(Errors::EngineClosed)
+        ));
+        drop(iter);
+        drop(db);
+
+        // 关闭前写入的数据都在
+        let db = Engine::open(opts).expect("failed to reopen engine");
+        assert_eq!(db.get(Bytes::from("k")).unwrap(), Bytes::from("v"));
+
+        clean(dir_name);
+    }
+
     #[test]
     fn test_db_sync() {
         setup("sync");
@@ -803,6 +2359,162 @@ mod tests {
         clean("sync");
     }
 
+    #[test]
+    fn test_db_put_sync() {
+        let dir_name = "put_sync";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.sync_policy = SyncPolicy::Never;
+
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        db.put(Bytes::from("k1"), Bytes::from("v1")).unwrap();
+        assert!(db.group_commit.state.lock().synced_seq < db.write_seq.load(Ordering::SeqCst));
+
+        // 返回之前所有已经写入的数据都持久化了
+        db.put_sync(Bytes::from("k2"), Bytes::from("v2")).unwrap();
+        assert_eq!(
+            db.group_commit.state.lock().synced_seq,
+            db.write_seq.load(Ordering::SeqCst)
+        );
+        assert_eq!(db.get(Bytes::from("k2")).unwrap(), Bytes::from("v2"));
+
+        db.close().unwrap();
+        assert!(matches!(
+            db.put_sync(Bytes::from("k3"), Bytes::from("v3")),
+            Err(Errors::EngineClosed)
+        ));
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_sync_policy() {
+        let dir_name = "sync_policy";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+
+        for policy in [
+            SyncPolicy::EveryNBytes(0),
+            SyncPolicy::EveryDuration(Duration::ZERO),
+        ] {
+            opts.sync_policy = policy;
+            assert!(matches!(
+                Engine::open(opts.clone()),
+                Err(Errors::InvalidSyncPolicy)
+            ));
+        }
+
+        // 累计写入超过阈值后持久化并清空累计值
+        opts.sync_policy = SyncPolicy::EveryNBytes(64);
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        db.put(Bytes::from("k1"), Bytes::from("v")).unwrap();
+        assert!(db.bytes_write.load(Ordering::SeqCst) > 0);
+        db.put(Bytes::from("k2"), Bytes::from("v".repeat(64)))
+            .unwrap();
+        assert_eq!(db.bytes_write.load(Ordering::SeqCst), 0);
+        assert!(db.flusher.is_none());
+        std::mem::drop(db);
+
+        // 后台线程定时持久化
+        opts.sync_policy = SyncPolicy::EveryDuration(Duration::from_millis(10));
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        db.put(Bytes::from("k3"), Bytes::from("v")).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while db.bytes_write.load(Ordering::SeqCst) > 0 {
+            assert!(Instant::now() < deadline, "background flusher did not run");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        // 关闭时等待后台线程退出
+        std::mem::drop(db);
+        let db = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(db.get(Bytes::from("k3")).unwrap(), Bytes::from("v"));
+        std::mem::drop(db);
+
+        // `sync_interval`和`SyncPolicy::EveryDuration`一样, 不能和其他策略同时设置
+        for (interval, policy) in [
+            (Duration::ZERO, SyncPolicy::Never),
+            (Duration::from_millis(10), SyncPolicy::Always),
+            (Duration::from_millis(10), SyncPolicy::EveryNBytes(64)),
+            (
+                Duration::from_millis(10),
+                SyncPolicy::EveryDuration(Duration::from_millis(20)),
+            ),
+        ] {
+            opts.sync_interval = Some(interval);
+            opts.sync_policy = policy;
+            assert!(matches!(
+                Engine::open(opts.clone()),
+                Err(Errors::InvalidSyncPolicy)
+            ));
+        }
+        opts.sync_interval = Some(Duration::from_millis(10));
+        opts.sync_policy = SyncPolicy::Never;
+        let db = Engine::open(opts).expect("failed to open engine");
+        assert_eq!(
+            db.options.sync_policy,
+            SyncPolicy::EveryDuration(Duration::from_millis(10))
+        );
+        db.put(Bytes::from("k4"), Bytes::from("v")).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while db.bytes_write.load(Ordering::SeqCst) > 0 {
+            assert!(Instant::now() < deadline, "background flusher did not run");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        std::mem::drop(db);
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_group_commit() {
+        let dir_name = "group_commit";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.sync_policy = SyncPolicy::Always;
+        opts.group_commit_max_wait = std::time::Duration::from_millis(1);
+
+        let db = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+        // 多个线程并发写入
+        let mut handles = vec![];
+        for t in 0..8 {
+            let db = db.clone();
+            handles.push(std::thread::spawn(move || {
+                for i in 0..100 {
+                    let key = Bytes::from(format!("key-{}-{}", t, i));
+                    let value = Bytes::from(format!("value-{}-{}", t, i));
+                    assert!(db.put(key, value).is_ok());
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 所有写入都已经持久化
+        {
+            let state = db.group_commit.state.lock();
+            assert_eq!(state.synced_seq, db.write_seq.load(Ordering::SeqCst));
+            assert!(!state.syncing);
+        }
+
+        // 重启后校验数据
+        std::mem::drop(db);
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        for t in 0..8 {
+            for i in 0..100 {
+                let key = Bytes::from(format!("key-{}-{}", t, i));
+                let value = db.get(key).expect("failed to get value");
+                assert_eq!(value, Bytes::from(format!("value-{}-{}", t, i)));
+            }
+        }
+
+        clean(dir_name);
+    }
+
     #[test]
     fn test_db_file_lock() {
         let dir_name = "file_lock";
@@ -834,6 +2546,174 @@ mod tests {
         clean(&dir_name);
     }
 
+    #[test]
+    fn test_db_lock_wait() {
+        let dir_name = "lock_wait";
+        setup(&dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name).into();
+
+        let db = Engine::open(opts.clone()).unwrap();
+        db.put(Bytes::from("key"), Bytes::from("value")).unwrap();
+
+        // 等待的时间不够, 依然返回 DatabaseIsUsing
+        opts.lock_wait = Duration::from_millis(50);
+        let start = Instant::now();
+        assert!(matches!(
+            Engine::open(opts.clone()),
+            Err(Errors::DatabaseIsUsing)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // 另一个实例在等待期间关闭
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            std::mem::drop(db);
+        });
+        opts.lock_wait = Duration::from_secs(10);
+        let db = Engine::open(opts.clone()).unwrap();
+        assert_eq!(db.get(Bytes::from("key")).unwrap(), Bytes::from("value"));
+        handle.join().unwrap();
+
+        // 只读实例持有共享锁时, 写入实例也会等待
+        std::mem::drop(db);
+        let mut ro_opts = opts.clone();
+        ro_opts.read_only = true;
+        let ro = Engine::open(ro_opts).unwrap();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            std::mem::drop(ro);
+        });
+        assert!(Engine::open(opts.clone()).is_ok());
+        handle.join().unwrap();
+
+        clean(&dir_name);
+    }
+
+    #[test]
+    fn test_db_read_only() {
+        let dir_name = "read_only";
+        setup(&dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name).into();
+        let mut ro_opts = opts.clone();
+        ro_opts.read_only = true;
+
+        // 目录不存在时不会创建
+        let mut missing = ro_opts.clone();
+        missing.dir_path = basepath().join("read_only_missing");
+        assert!(Engine::open(missing.clone()).is_err());
+        assert!(!missing.dir_path.exists());
+
+        // 写入进程正在运行时也可以只读打开
+        let db = Engine::open(opts.clone()).unwrap();
+        db.put(Bytes::from("key-1"), Bytes::from("value-1"))
+            .unwrap();
+        db.sync().unwrap();
+
+        let ro = Engine::open(ro_opts.clone()).unwrap();
+        assert_eq!(
+            ro.get(Bytes::from("key-1")).unwrap(),
+            Bytes::from("value-1")
+        );
+        assert!(matches!(
+            ro.put(Bytes::from("key-2"), Bytes::from("value-2")),
+            Err(Errors::ReadOnly)
+        ));
+        assert!(matches!(
+            ro.delete(Bytes::from("key-1")),
+            Err(Errors::ReadOnly)
+        ));
+        assert!(matches!(
+            ro.new_write_batch(WriteBatchOptions::default()),
+            Err(Errors::ReadOnly)
+        ));
+        assert!(matches!(ro.merge(), Err(Errors::ReadOnly)));
+        std::mem::drop(ro);
+        std::mem::drop(db);
+
+        // 只读进程持有共享锁时, 写入进程不能打开, 多个只读进程可以同时打开
+        let seq_no_file = opts.dir_path.join(SEQ_NO_FILE_NAME);
+        assert!(seq_no_file.is_file());
+        let ro = Engine::open(ro_opts.clone()).unwrap();
+        let ro2 = Engine::open(ro_opts.clone()).unwrap();
+        assert!(matches!(
+            Engine::open(opts.clone()),
+            Err(Errors::DatabaseIsUsing)
+        ));
+        assert_eq!(
+            ro2.get(Bytes::from("key-1")).unwrap(),
+            Bytes::from("value-1")
+        );
+        std::mem::drop(ro);
+        std::mem::drop(ro2);
+        // 只读打开不会修改数据目录
+        assert!(seq_no_file.is_file());
+
+        let db = Engine::open(opts.clone()).unwrap();
+        assert_eq!(
+            db.get(Bytes::from("key-1")).unwrap(),
+            Bytes::from("value-1")
+        );
+
+        clean(&dir_name);
+    }
+
+    #[test]
+    fn test_db_rate_limit() {
+        let dir_name = "rate_limit";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+
+        for rate_limit in [
+            RateLimit::Shared(0),
+            RateLimit::Separate {
+                write: Some(0),
+                merge: None,
+            },
+        ] {
+            opts.rate_limit = Some(rate_limit);
+            assert!(matches!(
+                Engine::open(opts.clone()),
+                Err(Errors::InvalidRateLimit)
+            ));
+        }
+
+        // 只限制前台写入, 超过一秒的额度之后开始等待
+        opts.rate_limit = Some(RateLimit::Separate {
+            write: Some(4096),
+            merge: None,
+        });
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        let start = Instant::now();
+        for i in 0..6 {
+            db.put(
+                Bytes::from(format!("key-{}", i)),
+                Bytes::from("v".repeat(1024)),
+            )
+            .unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        let stat = db.stat().unwrap();
+        assert!(stat.merge_throttle.is_none());
+        let throttle = stat.write_throttle.unwrap();
+        assert_eq!(throttle.bytes_per_sec, 4096);
+        assert!(throttle.throttled_count > 0);
+        assert!(throttle.throttled_time >= Duration::from_millis(300));
+        drop(db);
+
+        // 共用额度时两者的状态相同
+        opts.rate_limit = Some(RateLimit::Shared(1 << 20));
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        let stat = db.stat().unwrap();
+        assert_eq!(stat.write_throttle.unwrap().bytes_per_sec, 1 << 20);
+        assert_eq!(stat.merge_throttle.unwrap().bytes_per_sec, 1 << 20);
+
+        clean(dir_name);
+    }
+
     #[test]
     fn test_db_stat() {
         let dir_name = "db_stat";
@@ -884,11 +2764,672 @@ mod tests {
             println!("stat: {:#?}", stat);
 
             assert!(stat.reclaim_size > 0);
+            assert_eq!(stat.key_num, db.len().unwrap());
+            assert_eq!(stat.index.index_type, IndexType::BTree);
+            assert_eq!(stat.index.in_memory_keys, stat.key_num);
+            assert_eq!(stat.index.spilled_keys, 0);
         }
 
         clean(&dir_name);
     }
 
+    #[test]
+    fn test_db_max_size() {
+        let dir_name = "max_size";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.max_db_size_bytes = Some(1024);
+
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 写到上限为止
+        let mut written = 0;
+        let err = loop {
+            let key = Bytes::from(format!("key-{}", written));
+            match db.put(key, Bytes::from("value-value-value")) {
+                Ok(_) => written += 1,
+                Err(e) => break e,
+            }
+        };
+        match err {
+            Errors::DatabaseFull => {}
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        assert!(written > 0);
+        assert!(db.disk_size.load(Ordering::SeqCst) <= 1024);
+
+        // 已经写入的数据不受影响
+        assert!(db.get(Bytes::from("key-0")).is_ok());
+
+        let stat = db.stat().unwrap();
+        assert_eq!(1, stat.db_full_count);
+
+        // 重启后依然生效
+        std::mem::drop(db);
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        let res = db.put(Bytes::from("another-key"), Bytes::from("value-value-value"));
+        assert!(matches!(res, Err(Errors::DatabaseFull)));
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_compression() {
+        let dir_name = "compression";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.data_file_merge_ratio = 0.0;
+        let value = Bytes::from("{\"name\": \"lucas\"}".repeat(64));
+
+        // 不同的压缩方式写入同一个数据库
+        for (i, compression) in [
+            CompressionType::None,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            opts.compression = compression;
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            db.put(Bytes::from(format!("key-{}", i)), value.clone())
+                .unwrap();
+            db.put(Bytes::from("empty"), Bytes::new()).unwrap();
+            assert_eq!(db.get(Bytes::from(format!("key-{}", i))).unwrap(), value);
+        }
+
+        // 重启之后都可以读取, merge 之后也一样
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3 {
+            assert_eq!(db.get(Bytes::from(format!("key-{}", i))).unwrap(), value);
+        }
+        db.merge().unwrap();
+        std::mem::drop(db);
+
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3 {
+            assert_eq!(db.get(Bytes::from(format!("key-{}", i))).unwrap(), value);
+        }
+        assert_eq!(db.get(Bytes::from("empty")).unwrap(), Bytes::new());
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_encryption() {
+        let dir_name = "encryption";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.data_file_merge_ratio = 0.0;
+        opts.compression = CompressionType::Lz4;
+        opts.encryption_key = Some([1; 32]);
+        let secret = Bytes::from("secret-value-".repeat(16));
+
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        db.put(Bytes::from("key-1"), secret.clone()).unwrap();
+        db.put(Bytes::from("key-2"), Bytes::from("v2")).unwrap();
+        db.delete(Bytes::from("key-2")).unwrap();
+        assert_eq!(db.get(Bytes::from("key-1")).unwrap(), secret);
+        std::mem::drop(db);
+
+        // 磁盘上没有明文
+        let file_name = get_data_file_name(&opts.dir_path, INITIAL_FILE_ID);
+        let content = fs::read(file_name).unwrap();
+        assert!(!content
+            .windows(b"secret-value-".len())
+            .any(|w| w == b"secret-value-"));
+
+        // 没有密钥或者密钥错误时不能打开
+        let mut no_key = opts.clone();
+        no_key.encryption_key = None;
+        assert!(matches!(
+            Engine::open(no_key),
+            Err(Errors::EncryptionKeyRequired)
+        ));
+        let mut wrong_key = opts.clone();
+        wrong_key.encryption_key = Some([2; 32]);
+        assert!(matches!(
+            Engine::open(wrong_key),
+            Err(Errors::InvalidEncryptionKey)
+        ));
+
+        // merge 之后的数据同样加密
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        db.merge().unwrap();
+        std::mem::drop(db);
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(db.get(Bytes::from("key-1")).unwrap(), secret);
+        assert!(matches!(
+            db.get(Bytes::from("key-2")),
+            Err(Errors::KeyNotFound)
+        ));
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_spill_index() {
+        let dir_name = "spill-index";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.index_type = IndexType::Spill;
+        opts.max_index_memory = Some(64 * 1024);
+
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..5000 {
+            db.put(Bytes::from(format!("key-{:05}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        db.delete(Bytes::from("key-00010")).unwrap();
+        assert!(db.index.memory_usage() <= 64 * 1024);
+        let stat = db.stat().unwrap().index;
+        assert!(stat.spilled_keys > 0);
+        assert_eq!(stat.in_memory_keys + stat.spilled_keys, 4999);
+        assert_eq!(db.index.len(), 4999);
+        assert_eq!(db.list_keys().unwrap().len(), 4999);
+        assert!(opts.dir_path.join(INDEX_SPILL_DIR_NAME).is_dir());
+
+        // 备份不包括溢出文件
+        let backup_dir = basepath().join("spill-index-backup");
+        let _ = fs::remove_dir_all(&backup_dir);
+        db.backup(backup_dir.clone()).unwrap();
+        assert!(!backup_dir.join(INDEX_SPILL_DIR_NAME).exists());
+        let _ = fs::remove_dir_all(&backup_dir);
+        std::mem::drop(db);
+
+        // 重启时重新构建
+        let db = Engine::open(opts).expect("failed to reopen engine");
+        assert_eq!(db.index.len(), 4999);
+        assert_eq!(
+            db.get(Bytes::from("key-00001")).unwrap(),
+            Bytes::from("value")
+        );
+        assert!(matches!(
+            db.get(Bytes::from("key-00010")),
+            Err(Errors::KeyNotFound)
+        ));
+        std::mem::drop(db);
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_corrupted_record() {
+        let dir_name = "corrupted";
+        setup(dir_name);
+
+        for skip in [false, true] {
+            let mut opts = EngineOptions::default();
+            opts.dir_path = basepath().join(dir_name).join(skip.to_string());
+            opts.skip_corrupted_records = skip;
+
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            db.put(Bytes::from("key-1"), Bytes::from("value-1"))
+                .unwrap();
+            db.put(Bytes::from("key-2"), Bytes::from("value-2"))
+                .unwrap();
+
+            // 修改磁盘上 key-1 的最后一个字节(crc)
+            let pos = db.index.get(b"key-1".to_vec()).unwrap().unwrap();
+            let file_name = crate::data::data_file::get_data_file_name(&opts.dir_path, pos.file_id);
+            let mut content = fs::read(&file_name).unwrap();
+            content[pos.offset as usize + pos.size - 1] ^= 0xff;
+            fs::write(&file_name, content).unwrap();
+
+            match db.get(Bytes::from("key-1")) {
+                Err(Errors::KeyNotFound) if skip => {}
+                Err(Errors::CorruptedLogRecord { file_id, offset }) if !skip => {
+                    assert_eq!(file_id, pos.file_id);
+                    assert_eq!(offset, pos.offset);
+                }
+                res => panic!("unexpected result: {:?}", res),
+            }
+            assert_eq!(
+                db.get(Bytes::from("key-2")).unwrap(),
+                Bytes::from("value-2")
+            );
+
+            // 损坏的数据记录到了 quarantine 文件中
+            let quarantine = fs::read_to_string(opts.dir_path.join(QUARANTINE_FILE_NAME)).unwrap();
+            let line = quarantine.lines().next().unwrap();
+            let prefix = format!(
+                "file_id:{} offset:{} size:{} bytes:",
+                pos.file_id, pos.offset, pos.size
+            );
+            assert!(line.starts_with(&prefix));
+            assert_eq!(line.len(), prefix.len() + pos.size * 2);
+        }
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_truncate_torn_write() {
+        let dir_name = "torn_write";
+        setup(dir_name);
+
+        for use_mmap in [true, false] {
+            let mut opts = EngineOptions::default();
+            opts.dir_path = basepath().join(dir_name).join(use_mmap.to_string());
+            opts.use_mmap_when_startup = use_mmap;
+
+            let valid_size = {
+                let db = Engine::open(opts.clone()).expect("failed to open engine");
+                db.put(Bytes::from("key-1"), Bytes::from("value-1"))
+                    .unwrap();
+                db.put(Bytes::from("key-2"), Bytes::from("value-2"))
+                    .unwrap();
+                let write_off = db.active_file.read().get_write_off();
+                write_off
+            };
+
+            // 模拟写入到一半时崩溃: 一条不完整的数据, 后面跟着垃圾数据
+            let file_name = get_data_file_name(&opts.dir_path, INITIAL_FILE_ID);
+            let record = LogRecord {
+                key: log_record_key_with_seq(b"key-3".to_vec(), NON_TRANSACTION_SEQ_NO).unwrap(),
+                value: b"value-3".to_vec(),
+                rec_type: LogRecordType::Normal,
+                timestamp: 0,
+            }
+            .encode()
+            .unwrap();
+            let mut content = fs::read(&file_name).unwrap();
+            content.extend_from_slice(&record[..record.len() / 2]);
+            content.extend_from_slice(&[0xee; 7]);
+            fs::write(&file_name, &content).unwrap();
+            let garbage_size = content.len() as u64 - valid_size;
+
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            assert_eq!(db.stat().unwrap().discarded_bytes as u64, garbage_size);
+            assert_eq!(fs::metadata(&file_name).unwrap().len(), valid_size);
+            assert_eq!(
+                db.get(Bytes::from("key-1")).unwrap(),
+                Bytes::from("value-1")
+            );
+            assert_eq!(
+                db.get(Bytes::from("key-2")).unwrap(),
+                Bytes::from("value-2")
+            );
+            assert!(db.get(Bytes::from("key-3")).is_err());
+
+            // 之后写入的数据紧跟在最后一条完整数据后面
+            db.put(Bytes::from("key-4"), Bytes::from("value-4"))
+                .unwrap();
+            std::mem::drop(db);
+
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            assert_eq!(db.stat().unwrap().discarded_bytes, 0);
+            assert_eq!(
+                db.get(Bytes::from("key-4")).unwrap(),
+                Bytes::from("value-4")
+            );
+            assert_eq!(
+                db.get(Bytes::from("key-1")).unwrap(),
+                Bytes::from("value-1")
+            );
+        }
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_corrupted_active_file() {
+        let dir_name = "corrupted_active_file";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+
+        let first_size = {
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            db.put(Bytes::from("key-1"), Bytes::from("value-1"))
+                .unwrap();
+            let first_size = db.active_file.read().get_write_off();
+            db.put(Bytes::from("key-2"), Bytes::from("value-2"))
+                .unwrap();
+            db.put(Bytes::from("key-3"), Bytes::from("value-3"))
+                .unwrap();
+            first_size
+        };
+
+        // 中间的数据损坏, 后面还有完整的数据, 不能截断
+        let file_name = get_data_file_name(&opts.dir_path, INITIAL_FILE_ID);
+        let mut content = fs::read(&file_name).unwrap();
+        let file_size = content.len() as u64;
+        content[first_size as usize + 8] ^= 0xff;
+        fs::write(&file_name, &content).unwrap();
+
+        let res = Engine::open(opts.clone());
+        assert!(matches!(
+            res,
+            Err(Errors::CorruptedLogRecord {
+                file_id: INITIAL_FILE_ID,
+                offset
+            }) if offset == first_size
+        ));
+        assert_eq!(fs::metadata(&file_name).unwrap().len(), file_size);
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_parallel_startup() {
+        let dir_name = "parallel_startup";
+        setup(dir_name);
+
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.data_file_size = 4 * 1024;
+        opts.data_file_merge_ratio = 0.0;
+
+        {
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            for i in 0..2000 {
+                let key = Bytes::from(format!("key-{:04}", i));
+                db.put(key, Bytes::from(format!("value-{}", i))).unwrap();
+            }
+            // 后面的文件覆盖/删除前面文件中的数据
+            for i in 0..500 {
+                let key = Bytes::from(format!("key-{:04}", i));
+                db.put(key, Bytes::from("new-value")).unwrap();
+            }
+            for i in 500..1000 {
+                db.delete(Bytes::from(format!("key-{:04}", i))).unwrap();
+            }
+            let wb = db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            for i in 1000..1500 {
+                wb.put(
+                    Bytes::from(format!("key-{:04}", i)),
+                    Bytes::from("batch-value"),
+                )
+                .unwrap();
+            }
+            wb.commit().unwrap();
+            assert!(db.older_files.read().len() > 8);
+        }
+
+        let sequential = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = sequential.list_keys().unwrap();
+        let stat = sequential.stat().unwrap();
+        let seq_no = sequential.seq_no.load(Ordering::SeqCst);
+        std::mem::drop(sequential);
+
+        opts.startup_threads = 4;
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(db.list_keys().unwrap(), keys);
+        assert_eq!(keys.len(), 1500);
+        assert_eq!(db.stat().unwrap().reclaim_size, stat.reclaim_size);
+        assert_eq!(db.seq_no.load(Ordering::SeqCst), seq_no);
+        assert_eq!(
+            db.get(Bytes::from("key-0001")).unwrap(),
+            Bytes::from("new-value")
+        );
+        assert!(db.get(Bytes::from("key-0600")).is_err());
+        assert_eq!(
+            db.get(Bytes::from("key-1200")).unwrap(),
+            Bytes::from("batch-value")
+        );
+        assert_eq!(
+            db.get(Bytes::from("key-1800")).unwrap(),
+            Bytes::from("value-1800")
+        );
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_preallocate_data_file() {
+        let dir_name = "preallocate";
+        setup(dir_name);
+
+        for use_mmap in [true, false] {
+            let mut opts = EngineOptions::default();
+            opts.dir_path = basepath().join(dir_name).join(use_mmap.to_string());
+            opts.use_mmap_when_startup = use_mmap;
+            opts.data_file_size = 8 * 1024;
+            opts.preallocate_data_file = true;
+
+            {
+                let db = Engine::open(opts.clone()).expect("failed to open engine");
+                for i in 0..1000 {
+                    let key = Bytes::from(format!("key-{:04}", i));
+                    db.put(key, Bytes::from(format!("value-{}", i))).unwrap();
+                }
+                // 切换过活跃文件, 新的活跃文件也预分配了空间
+                assert!(db.older_files.read().len() > 0);
+                let active_file_id = db.active_file.read().get_file_id();
+                let file_name = get_data_file_name(&opts.dir_path, active_file_id);
+                assert_eq!(fs::metadata(file_name).unwrap().len(), opts.data_file_size);
+            }
+
+            // 预分配的空间不会被当成写入到一半的数据
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            assert_eq!(db.stat().unwrap().discarded_bytes, 0);
+            assert_eq!(db.len().unwrap(), 1000);
+            db.put(Bytes::from("key-new"), Bytes::from("value-new"))
+                .unwrap();
+            std::mem::drop(db);
+
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            assert_eq!(db.len().unwrap(), 1001);
+            assert_eq!(
+                db.get(Bytes::from("key-0999")).unwrap(),
+                Bytes::from("value-999")
+            );
+            assert_eq!(
+                db.get(Bytes::from("key-new")).unwrap(),
+                Bytes::from("value-new")
+            );
+        }
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_mmap_write() {
+        let dir_name = "mmap_write";
+        setup(dir_name);
+
+        for use_mmap in [true, false] {
+            let mut opts = EngineOptions::default();
+            opts.dir_path = basepath().join(dir_name).join(use_mmap.to_string());
+            opts.use_mmap_when_startup = use_mmap;
+            opts.data_file_size = 8 * 1024;
+            opts.write_io_type = IOType::MemoryMap;
+
+            {
+                let db = Engine::open(opts.clone()).expect("failed to open engine");
+                for i in 0..1000 {
+                    let key = Bytes::from(format!("key-{:04}", i));
+                    db.put(key, Bytes::from(format!("value-{}", i))).unwrap();
+                }
+                assert!(db.older_files.read().len() > 0);
+                let active_file_id = db.active_file.read().get_file_id();
+                let file_name = get_data_file_name(&opts.dir_path, active_file_id);
+                assert_eq!(fs::metadata(file_name).unwrap().len(), opts.data_file_size);
+            }
+
+            // 预分配的空间不会被当成写入到一半的数据
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            assert_eq!(db.stat().unwrap().discarded_bytes, 0);
+            assert_eq!(db.len().unwrap(), 1000);
+            db.put(Bytes::from("key-new"), Bytes::from("value-new"))
+                .unwrap();
+            std::mem::drop(db);
+
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            assert_eq!(db.len().unwrap(), 1001);
+            assert_eq!(
+                db.get(Bytes::from("key-0999")).unwrap(),
+                Bytes::from("value-999")
+            );
+            assert_eq!(
+                db.get(Bytes::from("key-new")).unwrap(),
+                Bytes::from("value-new")
+            );
+        }
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_direct_io() {
+        let dir_name = "direct_io";
+        setup(dir_name);
+
+        for use_mmap in [true, false] {
+            let mut opts = EngineOptions::default();
+            opts.dir_path = basepath().join(dir_name).join(use_mmap.to_string());
+            opts.use_mmap_when_startup = use_mmap;
+            opts.data_file_size = 8 * 1024;
+            opts.write_io_type = IOType::DirectIO;
+
+            {
+                let db = Engine::open(opts.clone()).expect("failed to open engine");
+                for i in 0..1000 {
+                    let key = Bytes::from(format!("key-{:04}", i));
+                    db.put(key, Bytes::from(format!("value-{}", i))).unwrap();
+                }
+                for i in 0..100 {
+                    db.delete(Bytes::from(format!("key-{:04}", i))).unwrap();
+                }
+                assert!(db.older_files.read().len() > 0);
+                assert_eq!(
+                    db.get(Bytes::from("key-0500")).unwrap(),
+                    Bytes::from("value-500")
+                );
+            }
+
+            // 填充的部分不会被当成写入到一半的数据
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            assert_eq!(db.stat().unwrap().discarded_bytes, 0);
+            assert_eq!(db.len().unwrap(), 900);
+            db.put(Bytes::from("key-new"), Bytes::from("value-new"))
+                .unwrap();
+            std::mem::drop(db);
+
+            let db = Engine::open(opts.clone()).expect("failed to open engine");
+            assert_eq!(db.len().unwrap(), 901);
+            assert_eq!(
+                db.get(Bytes::from("key-0999")).unwrap(),
+                Bytes::from("value-999")
+            );
+            assert_eq!(
+                db.get(Bytes::from("key-new")).unwrap(),
+                Bytes::from("value-new")
+            );
+        }
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_memory_usage() {
+        let dir_name = "memory_usage";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        let db = Engine::open(opts).expect("failed to open engine");
+
+        let usage = db.estimate_memory_usage();
+        assert_eq!(usage.index, 0);
+        assert_eq!(usage.pending_batches, 0);
+
+        for i in 0..100 {
+            let key = Bytes::from(format!("key-{:05}", i));
+            db.put(key, Bytes::from("value")).unwrap();
+        }
+        let usage = db.estimate_memory_usage();
+        assert!(usage.index > 100 * 9);
+        assert!(usage.total() > usage.index);
+
+        // 未提交的批量数据
+        {
+            let wb = db
+                .new_write_batch(crate::options::WriteBatchOptions::default())
+                .unwrap();
+            wb.put(Bytes::from("batch-1"), Bytes::from(vec![0u8; 1024]))
+                .unwrap();
+            wb.put(Bytes::from("batch-1"), Bytes::from(vec![0u8; 1024]))
+                .unwrap();
+            let pending = db.estimate_memory_usage().pending_batches;
+            assert!(pending > 1024 && pending < 2048);
+
+            wb.commit().unwrap();
+            assert_eq!(db.estimate_memory_usage().pending_batches, 0);
+
+            wb.put(Bytes::from("batch-2"), Bytes::from("value"))
+                .unwrap();
+            assert!(db.estimate_memory_usage().pending_batches > 0);
+        }
+        // WriteBatch 被丢弃后不再计算
+        assert_eq!(db.estimate_memory_usage().pending_batches, 0);
+
+        clean(dir_name);
+    }
+
+    #[test]
+    fn test_db_backup_to_writer() {
+        let dir_name = "backup-to-writer";
+        let restore_dir = basepath().join("backup-to-writer-restore");
+        setup(dir_name);
+        let _ = fs::remove_dir_all(&restore_dir);
+
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.data_file_size = 32 * 1024;
+        opts.preallocate_data_file = true;
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..2000 {
+            db.put(
+                Bytes::from(format!("key-{}", i)),
+                Bytes::from(format!("value-{}", i)),
+            )
+            .unwrap();
+        }
+        db.delete(Bytes::from("key-1")).unwrap();
+        assert!(db.older_files.read().len() > 0);
+
+        let archive = db.backup_to_writer(Vec::new()).unwrap();
+        // 备份之后的写入不在备份中
+        db.put(Bytes::from("after-backup"), Bytes::from("v"))
+            .unwrap();
+
+        let mut names = vec![];
+        let mut tar = tar::Archive::new(archive.as_slice());
+        for entry in tar.entries().unwrap() {
+            names.push(entry.unwrap().path().unwrap().display().to_string());
+        }
+        assert!(names.contains(&crate::data::MANIFEST_FILE_NAME.to_string()));
+        assert!(!names.contains(&FILE_LOCK_NAME.to_string()));
+        tar::Archive::new(archive.as_slice())
+            .unpack(&restore_dir)
+            .unwrap();
+
+        opts.dir_path = restore_dir.clone();
+        let restored = Engine::open(opts).expect("failed to open restored engine");
+        assert_eq!(restored.len().unwrap(), 1999);
+        assert_eq!(
+            restored.get(Bytes::from("key-1999")).unwrap(),
+            Bytes::from("value-1999")
+        );
+        assert!(matches!(
+            restored.get(Bytes::from("key-1")),
+            Err(Errors::KeyNotFound)
+        ));
+        assert!(matches!(
+            restored.get(Bytes::from("after-backup")),
+            Err(Errors::KeyNotFound)
+        ));
+
+        std::mem::drop(restored);
+        let _ = fs::remove_dir_all(&restore_dir);
+        clean(dir_name);
+    }
+
     #[test]
     fn test_db_backup() {
         let dir_name = "backup-test";
@@ -949,4 +3490,78 @@ mod tests {
         clean(dir_name);
         clean(backup_dir_name);
     }
+
+    #[test]
+    fn test_db_ephemeral() {
+        let dir_name = "ephemeral";
+        clean(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.data_file_size = 128;
+        opts.ephemeral = true;
+
+        let db = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..50 {
+            db.put(
+                Bytes::from(format!("key-{:02}", i)),
+                Bytes::from(format!("value-{}", i)),
+            )
+            .unwrap();
+        }
+        db.delete(Bytes::from("key-00")).unwrap();
+        {
+            let wb = db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            wb.put(Bytes::from("key-01"), Bytes::from("batch")).unwrap();
+            wb.commit().unwrap();
+        }
+
+        // 切换活跃文件之后旧文件中的数据依然可以读取
+        assert!(db.older_files.read().len() > 1);
+        assert_eq!(db.len().unwrap(), 49);
+        assert!(matches!(
+            db.get(Bytes::from("key-00")),
+            Err(Errors::KeyNotFound)
+        ));
+        assert_eq!(db.get(Bytes::from("key-01")).unwrap(), Bytes::from("batch"));
+        assert_eq!(
+            db.get(Bytes::from("key-49")).unwrap(),
+            Bytes::from("value-49")
+        );
+        assert_eq!(db.list_keys().unwrap().len(), 49);
+        assert!(db.sync().is_ok());
+
+        // 需要数据目录的操作不支持
+        assert!(matches!(db.merge(), Err(Errors::EphemeralUnsupported(_))));
+        assert!(matches!(
+            db.backup(basepath().join("ephemeral-backup")),
+            Err(Errors::EphemeralUnsupported(_))
+        ));
+
+        // 可以同时打开多个, 互相不影响
+        let other = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(other.is_empty().unwrap());
+        drop(other);
+
+        db.close().unwrap();
+        drop(db);
+        assert!(!opts.dir_path.exists());
+
+        // 不需要数据目录
+        opts.dir_path = PathBuf::new();
+        assert!(Engine::open(opts.clone()).is_ok());
+
+        opts.read_only = true;
+        assert!(matches!(
+            Engine::open(opts.clone()),
+            Err(Errors::EphemeralUnsupported(_))
+        ));
+
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        opts.write_io_type = IOType::InMemory;
+        assert!(matches!(
+            Engine::open(opts),
+            Err(Errors::InMemoryRequiresEphemeral)
+        ));
+    }
 }
@@ -10,6 +10,19 @@ pub enum Errors {
     #[error("key not found")]
     KeyNotFound,
 
+    #[error("key is too large, max:{}, actual:{}", max, actual)]
+    KeyTooLarge { max: usize, actual: usize },
+
+    #[error("value is too large, max:{}, actual:{}", max, actual)]
+    ValueTooLarge { max: usize, actual: usize },
+
+    #[error(
+        "log record is larger than a data file, size:{}, data file size:{}",
+        size,
+        data_file_size
+    )]
+    RecordTooLarge { size: u64, data_file_size: u64 },
+
     #[error("failed to update index")]
     IndexUpdateFailed,
 
@@ -43,6 +56,9 @@ pub enum Errors {
     #[error("exceed the max batch num, max:{}, current:{}", max, current)]
     ExceedMaxBatchNum { max: u32, current: u32 },
 
+    #[error("exceed the max batch bytes, max:{}, current:{}", max, current)]
+    ExceedMaxBatchBytes { max: usize, current: usize },
+
     #[error("transaction sequence number not found: {0}")]
     TxnNumberNotFound(usize),
 
@@ -57,8 +73,27 @@ pub enum Errors {
 
     #[error("the database dir is used by another process")]
     DatabaseIsUsing,
+    #[error(
+        "open timed out after loading {} of {} data files",
+        files_loaded,
+        files_discovered
+    )]
+    OpenTimeout {
+        files_loaded: usize,
+        files_discovered: usize,
+    },
+    #[error("the database is opened in read-only mode")]
+    ReadOnly,
+    #[error("the engine has been closed")]
+    EngineClosed,
+    #[error("subscription is closed because the receiver fell behind")]
+    SubscriptionOverflow,
     #[error("invalid merge ratio")]
     InvalidMergeRatio,
+    #[error("invalid sync policy, bytes and duration must be greater than 0")]
+    InvalidSyncPolicy,
+    #[error("invalid rate limit, bytes per second must be greater than 0")]
+    InvalidRateLimit,
 
     #[error("do not reach the merge ratio, now:{0}, ratio:{1}", now, ratio)]
     MergeRatioUnreached { now: f32, ratio: f32 },
@@ -70,9 +105,104 @@ pub enum Errors {
     )]
     MergeSpaceNotEnough { actual: u64, expected: u64 },
 
+    #[error("merge output exceeds the {reserved} reserved data files")]
+    MergeOutputTooLarge { reserved: u32 },
+
     #[error("failed to copy database directory")]
     FailedToBackupDatabase,
 
     #[error("wrong type operation, expected:{}, actual:{}", expected, actual)]
     WrongTypeOperation { expected: String, actual: String },
+
+    #[error("database size exceeds the max size")]
+    DatabaseFull,
+
+    #[error("failed to decompress value")]
+    DecompressFailed,
+
+    #[error("corrupted log record, file id:{}, offset:{}", file_id, offset)]
+    CorruptedLogRecord { file_id: u32, offset: u64 },
+
+    #[error("DB index is out of range")]
+    DbIndexOutOfRange,
+
+    #[error("increment or decrement would overflow")]
+    IntegerOverflow,
+
+    #[error("resulting score is not a number")]
+    ScoreIsNaN,
+
+    #[error("bit offset is not an integer or out of range")]
+    BitOffsetOutOfRange,
+
+    #[error("key is not a valid HyperLogLog string value")]
+    InvalidHyperLogLog,
+
+    #[error("invalid stream ID specified as stream command argument")]
+    InvalidStreamId,
+
+    #[error("the ID specified in XADD is equal or smaller than the target stream top item")]
+    StreamIdTooSmall,
+
+    #[error("invalid cursor")]
+    InvalidCursor,
+
+    #[error("failed to serialize: {0}")]
+    SerializeFailed(String),
+
+    #[error("failed to deserialize: {0}")]
+    DeserializeFailed(String),
+
+    #[error("invalid replication message: {0}")]
+    InvalidReplicationMessage(String),
+
+    #[error("namespace not found: {0}")]
+    NamespaceNotFound(String),
+
+    #[error("failed to encrypt value")]
+    EncryptFailed,
+
+    #[error("failed to decrypt value, wrong encryption key or corrupted data")]
+    DecryptFailed,
+
+    #[error("database is encrypted, encryption key is required")]
+    EncryptionKeyRequired,
+
+    #[error("encryption key does not match the database")]
+    InvalidEncryptionKey,
+
+    #[error("invalid manifest file: {0}")]
+    InvalidManifest(String),
+
+    #[error("invalid merge finished file: {0}")]
+    InvalidMergeFinishedFile(String),
+
+    #[error("invalid backup: {0}")]
+    InvalidBackup(String),
+
+    #[error("keys to ingest must be sorted in ascending order without duplicates")]
+    IngestKeysNotSorted,
+
+    #[error("{0} is not supported in ephemeral mode")]
+    EphemeralUnsupported(String),
+
+    #[error("in-memory io type can only be used in ephemeral mode")]
+    InMemoryRequiresEphemeral,
+
+    #[error("invalid export stream: {0}")]
+    InvalidExportStream(String),
+
+    #[error(
+        "unsupported format version {}, supported up to {}",
+        version,
+        supported
+    )]
+    UnsupportedFormatVersion { version: u32, supported: u32 },
+
+    #[error(
+        "format version {} needs to be upgraded to {}, call Engine::upgrade first",
+        version,
+        current
+    )]
+    UpgradeRequired { version: u32, current: u32 },
 }
@@ -0,0 +1,254 @@
+use crate::prelude::*;
+use std::fmt;
+
+use crate::db::Engine;
+
+/// 引擎内部事件的回调, 通过`EngineOptions::event_listener`设置, 用于统计、触发复制或者让缓存失效
+/// 回调在触发事件的线程中同步执行, 可能持有引擎内部的锁, 不能阻塞太久, 也不能再调用引擎的写入接口
+/// 所有方法都有空的默认实现, 只需要实现关心的事件
+pub trait EventListener: Send + Sync {
+    /// 活跃文件写满或者 merge 时切换了活跃文件, `file_id`是不再写入的旧文件
+    fn on_file_rotate(&self, _file_id: u32) {}
+
+    /// merge 拿到锁之后开始执行
+    fn on_merge_start(&self) {}
+
+    /// merge 结束, 失败时`result`中是错误
+    fn on_merge_finish(&self, _result: &Result<()>) {}
+
+    /// 活跃文件`file_id`持久化成功
+    fn on_sync(&self, _file_id: u32) {}
+
+    /// 写入了`key`, 包括批量写入和导入
+    fn on_put(&self, _key: &[u8], _value: &[u8]) {}
+
+    /// 删除了`key`
+    fn on_delete(&self, _key: &[u8]) {}
+
+    /// 批量提交时事务中的第`written`条数据写入了数据文件, 此时还没有写入 TxnFinished 标识
+    /// 持有提交锁时调用, 可以用于在测试中模拟提交到一半时崩溃
+    fn on_txn_record_append(&self, _seq_no: usize, _written: usize) {}
+
+    /// 打开数据库时加载索引的进度, 开始读取数据文件前和每读完一个数据文件各通知一次
+    /// 设置了`startup_threads`时在加载的线程中执行, 收到的进度可能不是递增的
+    fn on_open_progress(&self, _progress: &OpenProgress) {}
+}
+
+/// 打开数据库时从数据文件加载索引的进度, 不包括从 hint 文件加载的部分
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OpenProgress {
+    /// 需要读取的数据文件数量
+    pub files_discovered: usize,
+    /// 已经读完的数据文件数量
+    pub files_loaded: usize,
+    /// 已经读取的数据条数
+    pub records_indexed: usize,
+}
+
+impl fmt::Debug for dyn EventListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventListener")
+    }
+}
+
+impl Engine {
+    pub(crate) fn event_listener(&self) -> Option<&dyn EventListener> {
+        self.options.event_listener.as_deref()
+    }
+
+    /// merge 前后通知`EventListener`
+    pub(crate) fn run_merge<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        if let Some(listener) = self.event_listener() {
+            listener.on_merge_start();
+        }
+        let res = f();
+        if let Some(listener) = self.event_listener() {
+            listener.on_merge_finish(&res);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    use bytes::Bytes;
+    use parking_lot::Mutex;
+
+    use crate::options::{EngineOptions, SyncPolicy, WriteBatchOptions};
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/event".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    /// 按顺序记录收到的事件
+    #[derive(Default)]
+    struct RecordingListener {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl RecordingListener {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.events.lock())
+        }
+    }
+
+    impl EventListener for RecordingListener {
+        fn on_file_rotate(&self, file_id: u32) {
+            self.events.lock().push(format!("rotate {}", file_id));
+        }
+
+        fn on_merge_start(&self) {
+            self.events.lock().push("merge start".to_string());
+        }
+
+        fn on_merge_finish(&self, result: &Result<()>) {
+            self.events
+                .lock()
+                .push(format!("merge finish {}", result.is_ok()));
+        }
+
+        fn on_sync(&self, file_id: u32) {
+            self.events.lock().push(format!("sync {}", file_id));
+        }
+
+        fn on_put(&self, key: &[u8], value: &[u8]) {
+            self.events.lock().push(format!(
+                "put {} {}",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(value)
+            ));
+        }
+
+        fn on_delete(&self, key: &[u8]) {
+            self.events
+                .lock()
+                .push(format!("delete {}", String::from_utf8_lossy(key)));
+        }
+
+        fn on_open_progress(&self, progress: &OpenProgress) {
+            self.events.lock().push(format!(
+                "open {}/{} {}",
+                progress.files_loaded, progress.files_discovered, progress.records_indexed
+            ));
+        }
+    }
+
+    #[test]
+    fn test_event_listener() {
+        let name = "listener";
+        clean(name);
+        let listener = Arc::new(RecordingListener::default());
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 96;
+        opts.data_file_merge_ratio = 0f32;
+        opts.sync_policy = SyncPolicy::Never;
+        opts.event_listener = Some(listener.clone());
+        assert_eq!(format!("{:?}", opts.event_listener), "Some(EventListener)");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("k1"), Bytes::from("v1")).unwrap();
+        engine.delete(Bytes::from("k1")).unwrap();
+        {
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .unwrap();
+            wb.put(Bytes::from("k2"), Bytes::from("v2")).unwrap();
+            wb.commit().unwrap();
+        }
+        engine.sync().unwrap();
+        // 批量写入提交时先持久化, 然后通知
+        assert_eq!(
+            listener.take(),
+            vec!["put k1 v1", "delete k1", "sync 0", "put k2 v2", "sync 0"]
+        );
+
+        // 活跃文件写满之后切换
+        engine
+            .put(Bytes::from("k3"), Bytes::from("v".repeat(40)))
+            .unwrap();
+        assert_eq!(
+            listener.take(),
+            vec![
+                "sync 0",
+                "rotate 0",
+                format!("put k3 {}", "v".repeat(40)).as_str()
+            ]
+        );
+
+        engine.merge().unwrap();
+        assert_eq!(
+            listener.take(),
+            vec!["merge start", "sync 1", "rotate 1", "merge finish true"]
+        );
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_open_progress() {
+        let name = "open-progress";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 64;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        drop(engine);
+        let files = std::fs::read_dir(&opts.dir_path)
+            .unwrap()
+            .filter(|entry| {
+                let file_name = entry.as_ref().unwrap().file_name();
+                file_name.to_string_lossy().ends_with(DATA_FILE_NAME_SUFFIX)
+            })
+            .count();
+        assert!(files > 1);
+
+        // 开始读取前和每读完一个数据文件通知一次
+        let listener = Arc::new(RecordingListener::default());
+        opts.event_listener = Some(listener.clone());
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        drop(engine);
+        let events: Vec<_> = listener
+            .take()
+            .into_iter()
+            .filter(|event| event.starts_with("open"))
+            .collect();
+        assert_eq!(events.len(), files + 1);
+        assert_eq!(events[0], format!("open 0/{} 0", files));
+        assert_eq!(events[files], format!("open {}/{} 10", files, files));
+
+        // 超时之后返回错误, 不影响之后再打开
+        opts.open_timeout = Some(Duration::ZERO);
+        assert!(matches!(
+            Engine::open(opts.clone()),
+            Err(Errors::OpenTimeout {
+                files_loaded: 0,
+                files_discovered,
+            }) if files_discovered == files
+        ));
+        opts.open_timeout = Some(Duration::from_secs(60));
+        let engine = Engine::open(opts).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("key-9")).unwrap(),
+            Bytes::from("value")
+        );
+
+        clean(name);
+    }
+}
@@ -0,0 +1,483 @@
+use crate::prelude::*;
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use bon::Builder;
+use bytes::Bytes;
+
+use crate::{data::data_file::get_data_file_name, db::Engine, options::IteratorOptions};
+
+/// `export_to`生成的数据流开头的标识
+const STREAM_MAGIC: &[u8; 8] = b"LUCASEXP";
+/// 数据流格式的版本, 格式变化时增加, 新版本可以读取旧版本的数据流
+const STREAM_VERSION: u32 = 1;
+const STREAM_RECORD: u8 = 1;
+const STREAM_END: u8 = 2;
+
+/// 导出文件的格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// 逗号分隔, 第一行是表头 `key,value`
+    Csv,
+    /// 每行一个 JSON 对象: `{"key": ..., "value": ...}`
+    Ndjson,
+}
+
+/// 解码之后的`value`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportValue {
+    /// 普通字符串,导出时会转义
+    Text(String),
+    /// 已经是合法的 JSON, Ndjson 格式下原样写入, Csv 格式下当作字符串处理
+    Json(String),
+}
+
+/// 将 `value` 解码成字符串/JSON 的回调
+pub type ValueDecoder<'a> = &'a dyn Fn(&Bytes) -> ExportValue;
+
+/// 每导出一条数据调用一次, 参数是已经导出的数据条数
+pub type ExportProgress<'a> = &'a dyn Fn(usize);
+
+/// 导出时的过滤条件
+#[derive(Debug, Clone, Default, Builder)]
+pub struct ExportFilter {
+    /// 只导出带有这个前缀的`key`
+    #[builder(default)]
+    pub prefix: Vec<u8>,
+    /// 只导出所在数据文件在这个时间之后修改过的`key`
+    /// 数据文件只记录了修改时间, 所以是按文件过滤的, 可能会包含更早写入的数据
+    pub modified_after: Option<SystemTime>,
+}
+
+impl Engine {
+    /// 将数据库中的所有数据导出到`path`, 用于交给分析工具处理
+    /// `value_decoder` 为空时, `value` 按 utf8 (有损) 转成字符串
+    /// 通过迭代器逐条写入, 不会把所有数据读到内存中
+    /// 返回导出的数据条数
+    pub fn export<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: Format,
+        value_decoder: Option<ValueDecoder>,
+    ) -> Result<usize> {
+        self.export_filtered(path, format, value_decoder, &ExportFilter::default(), None)
+    }
+
+    /// 和`export`一样, 只导出满足`filter`的数据
+    pub fn export_filtered<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: Format,
+        value_decoder: Option<ValueDecoder>,
+        filter: &ExportFilter,
+        progress: Option<ExportProgress>,
+    ) -> Result<usize> {
+        let file_ids = match filter.modified_after {
+            Some(after) => Some(self.data_files_modified_after(after)?),
+            None => None,
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        if format == Format::Csv {
+            writer.write_all(b"key,value\n")?;
+        }
+
+        let mut count = 0;
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.prefix = filter.prefix.clone();
+        let iter = self.iter(iter_opts);
+        while let Some((key, value)) = iter.next()? {
+            if let Some(file_ids) = &file_ids {
+                match self.index.get(key.to_vec())? {
+                    Some(pos) if file_ids.contains(&pos.file_id) => {}
+                    _ => continue,
+                }
+            }
+
+            let key = String::from_utf8_lossy(&key);
+            let value = match value_decoder {
+                Some(decoder) => decoder(&value),
+                None => ExportValue::Text(String::from_utf8_lossy(&value).into_owned()),
+            };
+
+            match format {
+                Format::Csv => {
+                    let value = match &value {
+                        ExportValue::Text(v) | ExportValue::Json(v) => v,
+                    };
+                    writeln!(writer, "{},{}", csv_escape(&key), csv_escape(value))?;
+                }
+                Format::Ndjson => {
+                    let value = match value {
+                        ExportValue::Text(v) => json_escape(&v),
+                        ExportValue::Json(v) => v,
+                    };
+                    writeln!(
+                        writer,
+                        "{{\"key\":{},\"value\":{}}}",
+                        json_escape(&key),
+                        value
+                    )?;
+                }
+            }
+            count += 1;
+            if let Some(progress) = progress {
+                progress(count);
+            }
+        }
+
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// 按`key`的顺序把所有数据写到`writer`, 返回导出的数据条数
+    /// 数据流和数据文件的格式、索引类型、压缩和加密无关, 可以在不同的版本和机器之间迁移, 用`import_from`导入
+    /// `value`按原样导出, redis 等上层保存在`value`中的过期时间也会保留
+    /// 格式: `LUCASEXP` + 版本(4字节), 每条数据是 1 + key长度(4字节) + key + value长度(4字节) + value + crc(4字节),
+    /// 最后是 2 + 数据条数(8字节), 整数都是大端序
+    pub fn export_to<W: Write>(&self, writer: W) -> Result<usize> {
+        self.check_open()?;
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(STREAM_MAGIC)?;
+        writer.write_all(&STREAM_VERSION.to_be_bytes())?;
+
+        let mut count = 0u64;
+        let iter = self.iter(IteratorOptions::default());
+        while let Some((key, value)) = iter.next()? {
+            writer.write_all(&[STREAM_RECORD])?;
+            writer.write_all(&(key.len() as u32).to_be_bytes())?;
+            writer.write_all(&key)?;
+            writer.write_all(&(value.len() as u32).to_be_bytes())?;
+            writer.write_all(&value)?;
+            writer.write_all(&stream_record_crc(&key, &value).to_be_bytes())?;
+            count += 1;
+        }
+        writer.write_all(&[STREAM_END])?;
+        writer.write_all(&count.to_be_bytes())?;
+        writer.flush()?;
+        Ok(count as usize)
+    }
+
+    /// 导入`export_to`生成的数据流, 已经存在的`key`会被覆盖, 返回导入的数据条数
+    /// 边读边写入, 数据流损坏或者不完整时返回错误, 出错之前读到的数据已经写入
+    pub fn import_from<R: Read>(&self, reader: R) -> Result<usize> {
+        self.check_writable()?;
+        let mut reader = BufReader::new(reader);
+        let mut magic = [0u8; 8];
+        read_stream(&mut reader, &mut magic)?;
+        if &magic != STREAM_MAGIC {
+            return Err(Errors::InvalidExportStream("bad magic".to_string()));
+        }
+        let version = read_stream_u32(&mut reader)?;
+        if version > STREAM_VERSION {
+            return Err(Errors::UnsupportedFormatVersion {
+                version,
+                supported: STREAM_VERSION,
+            });
+        }
+
+        let mut count = 0u64;
+        loop {
+            let mut tag = [0u8; 1];
+            read_stream(&mut reader, &mut tag)?;
+            match tag[0] {
+                STREAM_RECORD => {
+                    let key = read_stream_bytes(&mut reader)?;
+                    let value = read_stream_bytes(&mut reader)?;
+                    if read_stream_u32(&mut reader)? != stream_record_crc(&key, &value) {
+                        return Err(Errors::InvalidExportStream(format!(
+                            "crc mismatch at record {}",
+                            count
+                        )));
+                    }
+                    self.put(Bytes::from(key), Bytes::from(value))?;
+                    count += 1;
+                }
+                STREAM_END => {
+                    let mut expected = [0u8; 8];
+                    read_stream(&mut reader, &mut expected)?;
+                    let expected = u64::from_be_bytes(expected);
+                    if expected != count {
+                        return Err(Errors::InvalidExportStream(format!(
+                            "expected {} records, got {}",
+                            expected, count
+                        )));
+                    }
+                    break;
+                }
+                tag => {
+                    return Err(Errors::InvalidExportStream(format!(
+                        "unknown record tag {}",
+                        tag
+                    )))
+                }
+            }
+        }
+        self.sync()?;
+        Ok(count as usize)
+    }
+
+    /// 在`after`之后修改过的数据文件id
+    fn data_files_modified_after(&self, after: SystemTime) -> Result<HashSet<u32>> {
+        self.check_persistent("export modified after")?;
+        let mut file_ids: Vec<u32> = self.older_files.read().keys().copied().collect();
+        file_ids.push(self.active_file.read().get_file_id());
+
+        let mut modified = HashSet::new();
+        for file_id in file_ids {
+            let path = get_data_file_name(&self.options.dir_path, file_id);
+            if std::fs::metadata(path)?.modified()? >= after {
+                modified.insert(file_id);
+            }
+        }
+        Ok(modified)
+    }
+}
+
+fn stream_record_crc(key: &[u8], value: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(key);
+    hasher.update(value);
+    hasher.finalize()
+}
+
+/// 读满`buf`, 数据流提前结束时返回`Errors::InvalidExportStream`
+fn read_stream(reader: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => {
+            Errors::InvalidExportStream("unexpected end of stream".to_string())
+        }
+        _ => e.into(),
+    })
+}
+
+fn read_stream_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    read_stream(reader, &mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+/// 长度(4字节) + 内容
+fn read_stream_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_stream_u32(reader)? as usize;
+    // 长度可能是损坏的数据, 不按它预先分配内存
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(Errors::InvalidExportStream(
+            "unexpected end of stream".to_string(),
+        ));
+    }
+    Ok(buf)
+}
+
+/// 字段中包含 逗号/引号/换行 时,需要用引号包起来,引号写两次
+fn csv_escape(field: &str) -> String {
+    if !field.contains([',', '"', '\n', '\r']) {
+        return field.to_string();
+    }
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// 转成 JSON 字符串(带引号)
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::options::EngineOptions;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/export".into()
+    }
+
+    fn setup(name: &str) -> Engine {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name).join("db");
+        Engine::open(opts).expect("failed to open engine")
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let name = "csv";
+        let engine = setup(name);
+
+        let _ = engine.put(Bytes::from("key-1"), Bytes::from("value-1"));
+        let _ = engine.put(Bytes::from("key-2"), Bytes::from("hello, \"lucas\""));
+
+        let out = basepath().join(name).join("out.csv");
+        let count = engine.export(&out, Format::Csv, None).unwrap();
+        assert_eq!(2, count);
+
+        let content = std::fs::read_to_string(&out).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[0], "key,value");
+        assert_eq!(lines[1], "key-1,value-1");
+        assert_eq!(lines[2], "key-2,\"hello, \"\"lucas\"\"\"");
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_export_ndjson_with_decoder() {
+        let name = "ndjson";
+        let engine = setup(name);
+
+        let _ = engine.put(Bytes::from("key-1"), Bytes::from("1"));
+        let _ = engine.put(Bytes::from("key-2"), Bytes::from("line\nbreak"));
+
+        let out = basepath().join(name).join("out.ndjson");
+
+        // 不解码, value 当作字符串
+        {
+            let count = engine.export(&out, Format::Ndjson, None).unwrap();
+            assert_eq!(2, count);
+            let content = std::fs::read_to_string(&out).unwrap();
+            let lines: Vec<&str> = content.lines().collect();
+            assert_eq!(lines[0], r#"{"key":"key-1","value":"1"}"#);
+            assert_eq!(lines[1], r#"{"key":"key-2","value":"line\nbreak"}"#);
+        }
+
+        // 解码成 JSON, 原样写入
+        {
+            let decoder = |v: &Bytes| match std::str::from_utf8(v) {
+                Ok(s) if s.parse::<i64>().is_ok() => ExportValue::Json(s.to_string()),
+                _ => ExportValue::Text(String::from_utf8_lossy(v).into_owned()),
+            };
+            let count = engine.export(&out, Format::Ndjson, Some(&decoder)).unwrap();
+            assert_eq!(2, count);
+            let content = std::fs::read_to_string(&out).unwrap();
+            let lines: Vec<&str> = content.lines().collect();
+            assert_eq!(lines[0], r#"{"key":"key-1","value":1}"#);
+        }
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_export_filtered() {
+        let name = "filtered";
+        let engine = setup(name);
+
+        let _ = engine.put(Bytes::from("user:1"), Bytes::from("a"));
+        let _ = engine.put(Bytes::from("user:2"), Bytes::from("b"));
+        let _ = engine.put(Bytes::from("order:1"), Bytes::from("c"));
+
+        let out = basepath().join(name).join("out.ndjson");
+        let exported = std::cell::Cell::new(0);
+        let progress = |n: usize| exported.set(n);
+        let filter = ExportFilter::builder().prefix(b"user:".to_vec()).build();
+        let count = engine
+            .export_filtered(&out, Format::Ndjson, None, &filter, Some(&progress))
+            .unwrap();
+        assert_eq!(2, count);
+        assert_eq!(2, exported.get());
+        let content = std::fs::read_to_string(&out).unwrap();
+        assert!(!content.contains("order:1"));
+
+        // 所有数据文件都在这个时间之前修改
+        let filter = ExportFilter::builder()
+            .modified_after(SystemTime::now() + std::time::Duration::from_secs(3600))
+            .build();
+        let count = engine
+            .export_filtered(&out, Format::Ndjson, None, &filter, None)
+            .unwrap();
+        assert_eq!(0, count);
+
+        let filter = ExportFilter::builder()
+            .modified_after(SystemTime::UNIX_EPOCH)
+            .build();
+        let count = engine
+            .export_filtered(&out, Format::Ndjson, None, &filter, None)
+            .unwrap();
+        assert_eq!(3, count);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_export_to_import_from() {
+        let name = "stream";
+        let engine = setup(name);
+        for i in 0..100 {
+            engine
+                .put(
+                    Bytes::from(format!("key-{:03}", i)),
+                    Bytes::from(format!("value-{}", i)),
+                )
+                .unwrap();
+        }
+        engine.put(Bytes::from("empty"), Bytes::new()).unwrap();
+        engine.delete(Bytes::from("key-050")).unwrap();
+
+        let mut stream = vec![];
+        assert_eq!(engine.export_to(&mut stream).unwrap(), 100);
+
+        // 导入到不同索引类型、使用压缩的数据库
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name).join("other");
+        opts.index_type = crate::options::IndexType::Spill;
+        opts.compression = crate::options::CompressionType::Lz4;
+        let other = Engine::open(opts).expect("failed to open engine");
+        assert_eq!(other.import_from(stream.as_slice()).unwrap(), 100);
+        assert_eq!(other.len().unwrap(), 100);
+        assert_eq!(
+            other.get(Bytes::from("key-099")).unwrap(),
+            Bytes::from("value-99")
+        );
+        assert_eq!(other.get(Bytes::from("empty")).unwrap(), Bytes::new());
+        assert!(matches!(
+            other.get(Bytes::from("key-050")),
+            Err(Errors::KeyNotFound)
+        ));
+
+        // 损坏和不完整的数据流
+        let mut corrupted = stream.clone();
+        let last = corrupted.len() - 20;
+        corrupted[last] ^= 0xff;
+        assert!(matches!(
+            other.import_from(corrupted.as_slice()),
+            Err(Errors::InvalidExportStream(_))
+        ));
+        assert!(matches!(
+            other.import_from(&stream[..stream.len() - 4]),
+            Err(Errors::InvalidExportStream(_))
+        ));
+        assert!(matches!(
+            other.import_from(&b"not an export stream"[..]),
+            Err(Errors::InvalidExportStream(_))
+        ));
+
+        clean(name);
+    }
+}
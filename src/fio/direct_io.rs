@@ -0,0 +1,282 @@
+use crate::prelude::*;
+use std::{
+    fs::{File, OpenOptions},
+    path::PathBuf,
+};
+
+use fs2::FileExt as _;
+use parking_lot::RwLock;
+use tracing::error;
+
+use super::{read_at, write_all_at, IOManager};
+
+/// 读写的偏移和长度都要按这个大小对齐
+pub const DIRECT_IO_ALIGN: usize = 4096;
+
+/// 绕过页缓存的文件IO(linux 上使用 O_DIRECT 打开文件), 适用于使用者自己实现了缓存的场景
+/// 读写都以`DIRECT_IO_ALIGN`对齐的块为单位, 写入不完整的块时会先读出原来的内容
+/// 最后一个块不满时用0填充, 所以单独记录数据的逻辑长度, 填充的部分读取时会被当作文件末尾
+/// 其他平台上按普通文件打开, 读写方式不变
+pub struct DirectIO {
+    fd: RwLock<File>,
+    /// 数据的逻辑长度
+    len: RwLock<u64>,
+}
+
+impl DirectIO {
+    pub fn new(file_name: PathBuf) -> Result<Self> {
+        match open_direct(&file_name) {
+            Ok(file) => {
+                let len = file.metadata()?.len();
+                Ok(Self {
+                    fd: RwLock::new(file),
+                    len: RwLock::new(len),
+                })
+            }
+            Err(e) => {
+                error!("open data file error: {}", e);
+                Err(Errors::IO(e))
+            }
+        }
+    }
+}
+
+impl IOManager for DirectIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let len = *self.len.read();
+        if offset >= len {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        let end = std::cmp::min(offset + buf.len() as u64, len);
+
+        let start = align_down(offset);
+        let mut block = AlignedBuf::new((align_up(end) - start) as usize);
+        let n = read_full(&self.fd.read(), block.as_mut(), start)?;
+
+        // 文件不是用 direct io 写入时, 最后一个块可能不完整
+        let end = std::cmp::min(end, start + n as u64);
+        if offset >= end {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        let from = (offset - start) as usize;
+        let n = (end - offset) as usize;
+        buf[..n].copy_from_slice(&block.as_ref()[from..from + n]);
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let offset = *self.len.read();
+        self.write_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let fd = self.fd.write();
+        let end = offset + buf.len() as u64;
+        let start = align_down(offset);
+        let mut block = AlignedBuf::new((align_up(end) - start) as usize);
+
+        // 首尾两个块不完整时, 保留块中原来的内容
+        if offset > start {
+            read_full(&fd, &mut block.as_mut()[..DIRECT_IO_ALIGN], start)?;
+        }
+        // 只有一个块时, 上面已经读取过了
+        let tail_start = align_down(end);
+        if end > tail_start && (tail_start > start || offset == start) {
+            let from = (tail_start - start) as usize;
+            read_full(
+                &fd,
+                &mut block.as_mut()[from..from + DIRECT_IO_ALIGN],
+                tail_start,
+            )?;
+        }
+
+        let from = (offset - start) as usize;
+        block.as_mut()[from..from + buf.len()].copy_from_slice(buf);
+        write_full(&fd, block.as_ref(), start)?;
+
+        let mut len = self.len.write();
+        *len = std::cmp::max(*len, end);
+        Ok(buf.len())
+    }
+
+    fn allocate(&self, size: u64) -> Result<()> {
+        let fd = self.fd.write();
+        if let Err(e) = fd.allocate(size) {
+            error!("allocate data file err: {}", e);
+            return Err(Errors::IO(e));
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        if let Err(e) = self.fd.read().sync_all() {
+            error!("sync data file err: {}", e);
+            return Err(Errors::IO(e));
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(*self.len.read())
+    }
+}
+
+/// linux 上使用 O_DIRECT 打开, 文件系统不支持时(比如 tmpfs 返回 EINVAL)按普通文件打开
+fn open_direct(file_name: &std::path::Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).read(true).write(true);
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut direct = options.clone();
+        direct.custom_flags(libc::O_DIRECT);
+        match direct.open(file_name) {
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                tracing::warn!("O_DIRECT is not supported, fallback to buffered io: {}", e);
+            }
+            res => return res,
+        }
+    }
+
+    options.open(file_name)
+}
+
+fn align_down(offset: u64) -> u64 {
+    offset / DIRECT_IO_ALIGN as u64 * DIRECT_IO_ALIGN as u64
+}
+
+fn align_up(offset: u64) -> u64 {
+    align_down(offset + DIRECT_IO_ALIGN as u64 - 1)
+}
+
+/// 读取到`buf`填满或者文件末尾, 返回读取的字节数, 文件末尾之后的部分保持为0
+fn read_full(fd: &File, buf: &mut [u8], offset: u64) -> Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match read_at(fd, &mut buf[n..], offset + n as u64) {
+            Ok(size) => {
+                n += size;
+                // 读到了文件末尾, 继续读取时偏移不再对齐
+                if size == 0 || size % DIRECT_IO_ALIGN != 0 {
+                    break;
+                }
+            }
+            Err(e) => {
+                error!("read from data file err: {}", e);
+                return Err(Errors::IO(e));
+            }
+        }
+    }
+    Ok(n)
+}
+
+fn write_full(fd: &File, buf: &[u8], offset: u64) -> Result<()> {
+    if let Err(e) = write_all_at(fd, buf, offset) {
+        error!("write to data file err: {}", e);
+        return Err(Errors::IO(e));
+    }
+    Ok(())
+}
+
+/// 按`DIRECT_IO_ALIGN`对齐的缓冲区, 长度是`DIRECT_IO_ALIGN`的整数倍
+struct AlignedBuf {
+    data: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl AlignedBuf {
+    fn new(len: usize) -> Self {
+        let data = vec![0u8; len + DIRECT_IO_ALIGN];
+        let start = data.as_ptr().align_offset(DIRECT_IO_ALIGN);
+        Self { data, start, len }
+    }
+
+    fn as_ref(&self) -> &[u8] {
+        &self.data[self.start..self.start + self.len]
+    }
+
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.data[self.start..self.start + self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fio::file_io::FileIO;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/direct_io".into()
+    }
+
+    fn setup(name: &str) -> PathBuf {
+        std::fs::create_dir_all(basepath()).unwrap();
+        let path = basepath().join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_direct_io_write_and_read() {
+        let path = setup("write.data");
+
+        let dio = DirectIO::new(path.clone()).unwrap();
+        assert_eq!(5, dio.write(b"key-1").unwrap());
+        assert_eq!(11, dio.write(b"hello-lucas").unwrap());
+        assert_eq!(16, dio.size().unwrap());
+
+        // 跨越块边界的写入
+        let big: Vec<u8> = (0..DIRECT_IO_ALIGN * 2).map(|i| (i % 251) as u8).collect();
+        assert_eq!(big.len(), dio.write(&big).unwrap());
+        assert_eq!(16 + big.len() as u64, dio.size().unwrap());
+        dio.sync().unwrap();
+
+        let mut buf = [0u8; 11];
+        assert_eq!(11, dio.read(&mut buf, 5).unwrap());
+        assert_eq!(b"hello-lucas", &buf);
+
+        let mut read_big = vec![0u8; big.len() + 100];
+        assert_eq!(big.len(), dio.read(&mut read_big, 16).unwrap());
+        assert_eq!(big, read_big[..big.len()]);
+        assert!(matches!(
+            dio.read(&mut buf, 16 + big.len() as u64),
+            Err(Errors::ReadDataFileEOF)
+        ));
+
+        // 覆盖中间的一部分, 前后的数据不变
+        dio.write_at(b"KEY", 0).unwrap();
+        let mut buf = [0u8; 16];
+        dio.read(&mut buf, 0).unwrap();
+        assert_eq!(b"KEY-1hello-lucas", &buf);
+
+        // 文件按块填充, 其他IO也能读取
+        assert_eq!(
+            0,
+            std::fs::metadata(&path).unwrap().len() % DIRECT_IO_ALIGN as u64
+        );
+        let fio = FileIO::new(path.clone()).unwrap();
+        fio.read(&mut buf, 0).unwrap();
+        assert_eq!(b"KEY-1hello-lucas", &buf);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_direct_io_read_unaligned_file() {
+        let path = setup("unaligned.data");
+
+        let fio = FileIO::new(path.clone()).unwrap();
+        fio.write(b"written-by-file-io").unwrap();
+
+        let dio = DirectIO::new(path.clone()).unwrap();
+        assert_eq!(18, dio.size().unwrap());
+        let mut buf = [0u8; 32];
+        assert_eq!(18, dio.read(&mut buf, 0).unwrap());
+        assert_eq!(b"written-by-file-io", &buf[..18]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
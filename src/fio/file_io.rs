@@ -1,16 +1,16 @@
 use crate::prelude::*;
 use std::{
     fs::{File, OpenOptions},
-    io::Write,
+    io::{Seek, SeekFrom, Write},
     path::PathBuf,
     sync::Arc,
 };
 
-use log::error;
-#[cfg(windows)]
+use fs2::FileExt as _;
 use parking_lot::RwLock;
+use tracing::error;
 
-use super::IOManager;
+use super::{read_full_at, write_all_at, IOManager};
 
 /// standard file io
 pub struct FileIO {
@@ -20,12 +20,12 @@ pub struct FileIO {
 impl FileIO {
     /// `file_name`: 文件路径
     /// 如果 `file_name` 不存在, 会创建一个文件,赋予相应的读写权限
+    /// 不使用追加模式, 否则预分配空间之后 `write_at` 也会写到文件末尾
     pub fn new(file_name: PathBuf) -> Result<Self> {
         match OpenOptions::new()
             .create(true)
             .read(true)
             .write(true)
-            .append(true)
             .open(file_name)
         {
             Ok(file) => {
@@ -39,45 +39,69 @@ impl FileIO {
             }
         }
     }
+
+    /// 以只读方式打开已经存在的文件, 文件不存在时返回错误
+    pub fn open_read_only(file_name: PathBuf) -> Result<Self> {
+        match OpenOptions::new().read(true).open(file_name) {
+            Ok(file) => Ok(Self {
+                fd: Arc::new(RwLock::new(file)),
+            }),
+            Err(e) => {
+                error!("open data file error: {}", e);
+                Err(Errors::IO(e))
+            }
+        }
+    }
 }
 
 impl IOManager for FileIO {
+    /// 读取到`buf`填满或者文件末尾, 返回读取的字节数
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
         let read_guard = self.fd.read();
-        let mut read_result;
-
-        #[cfg(unix)]
-        {
-            use std::os::unix::prelude::FileExt;
-            read_result = read_guard.read_at(buf, offset);
-        }
-
-        #[cfg(windows)]
-        {
-            use std::os::windows::prelude::FileExt;
-            read_result = read_guard.seek_read(buf, offset);
-        }
-
-        match read_result {
-            Ok(n) => return Ok(n),
+        match read_full_at(&read_guard, buf, offset) {
+            Ok(n) => Ok(n),
             Err(e) => {
                 error!("read from data file err: {}", e);
-                return Err(Errors::IO(e));
+                Err(Errors::IO(e))
             }
-        };
+        }
     }
 
     fn write(&self, buf: &[u8]) -> Result<usize> {
         let mut write_guard = self.fd.write();
-        match write_guard.write(buf) {
-            Ok(n) => return Ok(n),
+        match write_guard
+            .seek(SeekFrom::End(0))
+            .and_then(|_| write_guard.write_all(buf))
+        {
+            Ok(_) => Ok(buf.len()),
             Err(e) => {
                 error!("write to data file err: {}", e);
-                return Err(Errors::IO(e));
+                Err(Errors::IO(e))
+            }
+        }
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let write_guard = self.fd.write();
+        match write_all_at(&write_guard, buf, offset) {
+            Ok(_) => Ok(buf.len()),
+            Err(e) => {
+                error!("write to data file err: {}", e);
+                Err(Errors::IO(e))
             }
         }
     }
 
+    fn allocate(&self, size: u64) -> Result<()> {
+        let write_guard = self.fd.write();
+        if let Err(e) = write_guard.allocate(size) {
+            error!("allocate data file err: {}", e);
+            return Err(Errors::IO(e));
+        }
+
+        Ok(())
+    }
+
     fn sync(&self) -> Result<()> {
         let read_guard = self.fd.read();
         if let Err(e) = read_guard.sync_all() {
@@ -214,4 +238,63 @@ mod tests {
 
         clean();
     }
+
+    #[test]
+    fn test_file_io_write_at_and_allocate() {
+        setup();
+
+        let path = get_path("allocate.data");
+
+        let fio = FileIO::new(path.clone()).unwrap();
+        assert!(fio.allocate(1024).is_ok());
+        assert_eq!(fio.size().unwrap(), 1024);
+
+        // 预分配之后按位置写入, 不会写到文件末尾
+        assert_eq!(fio.write_at("key-1".as_bytes(), 0).unwrap(), 5);
+        assert_eq!(fio.write_at("hello".as_bytes(), 5).unwrap(), 5);
+        assert_eq!(fio.size().unwrap(), 1024);
+
+        let mut buf = [0u8; 12];
+        assert_eq!(fio.read(&mut buf, 0).unwrap(), 12);
+        assert_eq!(&buf, b"key-1hello\0\0");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_file_io_read_parity() {
+        setup();
+
+        let path = get_path("parity.data");
+        let fio = std::sync::Arc::new(FileIO::new(path.clone()).unwrap());
+        let data: Vec<u8> = (0..=255u8).cycle().take(64 * 1024).collect();
+        assert_eq!(fio.write(&data).unwrap(), data.len());
+        assert_eq!(fio.size().unwrap(), data.len() as u64);
+
+        // 读到文件末尾时返回实际读取的长度, 超过文件末尾返回0
+        let mut buf = [0u8; 16];
+        assert_eq!(fio.read(&mut buf, data.len() as u64 - 4).unwrap(), 4);
+        assert_eq!(&buf[..4], &data[data.len() - 4..]);
+        assert_eq!(fio.read(&mut buf, data.len() as u64 + 10).unwrap(), 0);
+
+        // 并发按位置读取, 互相不影响
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let fio = fio.clone();
+                let data = data.clone();
+                std::thread::spawn(move || {
+                    for offset in (i * 100..data.len() - 4096).step_by(4000) {
+                        let mut buf = vec![0u8; 4096];
+                        assert_eq!(fio.read(&mut buf, offset as u64).unwrap(), 4096);
+                        assert_eq!(buf, data[offset..offset + 4096]);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let _ = std::fs::remove_file(path);
+    }
 }
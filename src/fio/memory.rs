@@ -0,0 +1,104 @@
+use crate::prelude::*;
+
+use parking_lot::RwLock;
+
+use super::IOManager;
+
+/// 数据保存在内存中的IO, 用于`EngineOptions::ephemeral`, 不会创建任何文件
+/// 数据随着`MemoryIO`一起释放, `sync`不做任何事
+#[derive(Default)]
+pub struct MemoryIO {
+    buf: RwLock<Vec<u8>>,
+}
+
+impl MemoryIO {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl IOManager for MemoryIO {
+    /// 和标准文件IO一样, 剩余的数据不够时只读取剩余的部分, 超过末尾返回0
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let data = self.buf.read();
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.buf.write().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let mut data = self.buf.write();
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn allocate(&self, size: u64) -> Result<()> {
+        let mut data = self.buf.write();
+        if (data.len() as u64) < size {
+            data.resize(size as usize, 0);
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.buf.read().len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_io_read_write() {
+        let mio = MemoryIO::new();
+        assert_eq!(mio.write("key-1".as_bytes()).unwrap(), 5);
+        assert_eq!(mio.write("hello-lucas".as_bytes()).unwrap(), 11);
+        assert_eq!(mio.size().unwrap(), 16);
+        assert!(mio.sync().is_ok());
+
+        let mut buf = [0u8; 5];
+        assert_eq!(mio.read(&mut buf, 0).unwrap(), 5);
+        assert_eq!(&buf, b"key-1");
+
+        // 读到末尾时返回实际读取的长度, 超过末尾返回0
+        let mut buf = [0u8; 16];
+        assert_eq!(mio.read(&mut buf, 10).unwrap(), 6);
+        assert_eq!(&buf[..6], b"-lucas");
+        assert_eq!(mio.read(&mut buf, 100).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_memory_io_write_at_and_allocate() {
+        let mio = MemoryIO::new();
+        assert!(mio.allocate(1024).is_ok());
+        assert_eq!(mio.size().unwrap(), 1024);
+
+        // 预分配之后按位置写入, 不会写到末尾
+        assert_eq!(mio.write_at("key-1".as_bytes(), 0).unwrap(), 5);
+        assert_eq!(mio.write_at("hello".as_bytes(), 5).unwrap(), 5);
+        assert_eq!(mio.size().unwrap(), 1024);
+
+        let mut buf = [0u8; 12];
+        assert_eq!(mio.read(&mut buf, 0).unwrap(), 12);
+        assert_eq!(&buf, b"key-1hello\0\0");
+
+        // 超过末尾时扩展
+        assert_eq!(mio.write_at("tail".as_bytes(), 1022).unwrap(), 4);
+        assert_eq!(mio.size().unwrap(), 1026);
+    }
+}
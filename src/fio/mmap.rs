@@ -1,13 +1,45 @@
 use crate::prelude::*;
-use std::{fs::OpenOptions, path::PathBuf, sync::Arc};
-
-use memmap2::Mmap;
+use std::{
+    fs::{File, OpenOptions},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use memmap2::MmapRaw;
 use parking_lot::Mutex;
 
 use super::IOManager;
 
+/// 内存映射IO
+/// 写入前需要先通过`allocate`把文件扩展到足够大, 写入超出映射范围时会扩展文件并重新映射
+/// 预分配之后文件的实际长度大于写入的数据, 所以单独记录数据的逻辑长度, 读取不会超过逻辑长度
+/// `read_bytes`返回的`Bytes`共享映射, 重新映射之后旧的映射在这些`Bytes`都释放之后才解除
 pub struct MMapIO {
-    map: Arc<Mutex<Mmap>>,
+    file: File,
+    inner: Arc<Mutex<MMapInner>>,
+}
+
+struct MMapInner {
+    map: Arc<MmapRaw>,
+    /// 数据的逻辑长度
+    len: u64,
+}
+
+impl MMapInner {
+    /// 映射中`[start, end)`的数据, 数据文件只追加写入, 写入之后的部分不会再被修改
+    fn slice(&self, start: usize, end: usize) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.map.as_ptr().add(start), end - start) }
+    }
+}
+
+/// 让`Bytes`持有整个映射
+struct SharedMap(Arc<MmapRaw>);
+
+impl AsRef<[u8]> for SharedMap {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.0.as_ptr(), self.0.len()) }
+    }
 }
 
 impl MMapIO {
@@ -19,39 +51,92 @@ impl MMapIO {
             .open(file_name)
         {
             Ok(file) => {
-                let map = unsafe { Arc::new(Mutex::new(Mmap::map(&file)?)) };
-                return Ok(Self { map });
+                let map = Arc::new(MmapRaw::map_raw(&file)?);
+                let len = map.len() as u64;
+                Ok(Self {
+                    file,
+                    inner: Arc::new(Mutex::new(MMapInner { map, len })),
+                })
             }
-            Err(e) => return Err(Errors::DataFileLoadError(e)),
+            Err(e) => Err(Errors::DataFileLoadError(e)),
         }
     }
+
+    /// 把文件扩展到`size`并重新映射
+    fn remap(&self, inner: &mut MMapInner, size: u64) -> Result<()> {
+        inner.map.flush()?;
+        self.file.set_len(size)?;
+        inner.map = Arc::new(MmapRaw::map_raw(&self.file)?);
+        Ok(())
+    }
 }
 
 impl IOManager for MMapIO {
     /// 从 offset 位置开始,读取 [offset, offset + buf.len())  -- 左闭右开
+    /// 和标准文件IO一样, 剩余的数据不够时只读取剩余的部分
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
-        let map_arr = self.map.lock();
-        let end = offset + buf.len() as u64;
-        if end > map_arr.len() as u64 {
+        let inner = self.inner.lock();
+        if offset >= inner.len {
             return Err(Errors::ReadDataFileEOF);
         }
+        let end = std::cmp::min(offset + buf.len() as u64, inner.len);
 
-        let val = &map_arr[offset as usize..end as usize];
-        buf.copy_from_slice(val);
+        let val = inner.slice(offset as usize, end as usize);
+        buf[..val.len()].copy_from_slice(val);
         Ok(val.len())
     }
 
+    /// 直接引用映射的内存, 不拷贝数据
+    fn read_bytes(&self, offset: u64, len: usize) -> Result<Option<Bytes>> {
+        let inner = self.inner.lock();
+        if offset >= inner.len {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        let end = std::cmp::min(offset + len as u64, inner.len);
+
+        let bytes = Bytes::from_owner(SharedMap(inner.map.clone()));
+        Ok(Some(bytes.slice(offset as usize..end as usize)))
+    }
+
     fn write(&self, buf: &[u8]) -> Result<usize> {
-        unimplemented!("mmap unsupport write()");
+        let offset = self.inner.lock().len;
+        self.write_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let mut inner = self.inner.lock();
+        let end = offset + buf.len() as u64;
+        if end > inner.map.len() as u64 {
+            self.remap(&mut inner, end)?;
+        }
+
+        // 写入的位置在逻辑长度之后, 不会修改已经被`read_bytes`引用的数据
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                buf.as_ptr(),
+                inner.map.as_mut_ptr().add(offset as usize),
+                buf.len(),
+            );
+        }
+        inner.len = inner.len.max(end);
+        Ok(buf.len())
+    }
+
+    fn allocate(&self, size: u64) -> Result<()> {
+        let mut inner = self.inner.lock();
+        if size > inner.map.len() as u64 {
+            self.remap(&mut inner, size)?;
+        }
+        Ok(())
     }
 
     fn sync(&self) -> Result<()> {
-        unimplemented!("mmap unsupport sync()");
+        self.inner.lock().map.flush()?;
+        Ok(())
     }
 
     fn size(&self) -> Result<u64> {
-        let map_arr = self.map.lock();
-        Ok(map_arr.len() as u64)
+        Ok(self.inner.lock().len)
     }
 }
 
@@ -90,27 +175,77 @@ mod tests {
         let _ = std::fs::remove_dir_all(basepath());
     }
 
-    // #[test]
-    // fn test_file_io_write() {
-    //     setup();
+    #[test]
+    fn test_mmap_write() {
+        setup();
 
-    //     let path = get_path("write.data");
+        let path = get_path("write.data");
+        let _ = std::fs::remove_file(&path);
 
-    //     let fio_res = MMapIO::new(path.clone());
-    //     assert!(fio_res.is_ok());
+        {
+            let mmap_io = MMapIO::new(path.clone()).unwrap();
+            mmap_io.allocate(16).unwrap();
+            assert_eq!(0, mmap_io.size().unwrap());
+
+            assert_eq!(5, mmap_io.write(b"key-1").unwrap());
+            // 超出预分配的部分, 会扩展文件
+            assert_eq!(11, mmap_io.write(b"hello-lucas").unwrap());
+            assert_eq!(16, mmap_io.write_at(b"0123456789abcdef", 16).unwrap());
+            assert_eq!(32, mmap_io.size().unwrap());
+            mmap_io.allocate(64).unwrap();
+            mmap_io.sync().unwrap();
+
+            let mut buf = [0u8; 16];
+            assert_eq!(11, mmap_io.read(&mut buf[..11], 5).unwrap());
+            assert_eq!(b"hello-lucas", &buf[..11]);
+            // 不会读到预分配的部分
+            assert_eq!(16, mmap_io.read(&mut buf, 16).unwrap());
+            assert!(matches!(
+                mmap_io.read(&mut buf, 32),
+                Err(Errors::ReadDataFileEOF)
+            ));
+            assert_eq!(64, std::fs::metadata(&path).unwrap().len());
+        }
 
-    //     let fio = fio_res.unwrap();
+        let fio = FileIO::new(path.clone()).unwrap();
+        let mut buf = [0u8; 5];
+        fio.read(&mut buf, 0).unwrap();
+        assert_eq!(b"key-1", &buf);
 
-    //     let res1 = fio.write("key-1".as_bytes());
-    //     assert!(res1.is_ok());
-    //     assert_eq!(5, res1.unwrap());
+        clean();
+    }
 
-    //     let res2 = fio.write("hello-lucas".as_bytes());
-    //     assert!(res2.is_ok());
-    //     assert_eq!(11, res2.unwrap());
+    #[test]
+    fn test_mmap_read_bytes() {
+        setup();
 
-    //     clean();
-    // }
+        let path = get_path("read_bytes.data");
+        let _ = std::fs::remove_file(&path);
+
+        let mmap_io = MMapIO::new(path.clone()).unwrap();
+        assert!(matches!(
+            mmap_io.read_bytes(0, 4),
+            Err(Errors::ReadDataFileEOF)
+        ));
+        mmap_io.allocate(8).unwrap();
+        mmap_io.write(b"key-1").unwrap();
+        let bytes = mmap_io.read_bytes(0, 8).unwrap().unwrap();
+        // 只返回逻辑长度以内的数据
+        assert_eq!(&bytes[..], b"key-1");
+        assert_eq!(mmap_io.read_bytes(4, 1).unwrap().unwrap(), Bytes::from("1"));
+
+        // 重新映射之后, 之前返回的数据依然可以读取
+        mmap_io.write(b"hello-lucas").unwrap();
+        assert_eq!(&bytes[..], b"key-1");
+        assert_eq!(
+            mmap_io.read_bytes(5, 11).unwrap().unwrap(),
+            Bytes::from("hello-lucas")
+        );
+        drop(mmap_io);
+        assert_eq!(&bytes[..], b"key-1");
+
+        clean();
+    }
 
     #[test]
     fn test_file_io_read() {
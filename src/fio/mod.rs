@@ -1,18 +1,35 @@
-use std::path::PathBuf;
+use std::{fs::File, io, path::PathBuf};
 
+use bytes::Bytes;
+
+use direct_io::DirectIO;
 use file_io::FileIO;
+use memory::MemoryIO;
 use mmap::MMapIO;
 
 use crate::prelude::*;
 
+pub mod direct_io;
 pub mod file_io;
+pub mod memory;
 pub mod mmap;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
 /// 抽象IO接口,接入不同IO类型,比如 标准文件io、mmap等
 pub trait IOManager: Sync + Send {
     /// 从文件的指定位置读取数据
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
+    /// 不拷贝地读取`[offset, offset + len)`, 剩余的数据不够时只返回剩余的部分
+    /// 只有内存映射IO支持, 其他IO类型返回 None, 调用者改用`read`读到自己的缓冲区中
+    fn read_bytes(&self, _offset: u64, _len: usize) -> Result<Option<Bytes>> {
+        Ok(None)
+    }
     /// 写入buf到字节数组中
     fn write(&self, buf: &[u8]) -> Result<usize>;
+    /// 在文件的指定位置写入buf
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize>;
+    /// 预先分配磁盘空间, 文件长度不足`size`时会扩展, 扩展的部分全是0
+    fn allocate(&self, size: u64) -> Result<()>;
     /// 持久化数据
     fn sync(&self) -> Result<()>;
 
@@ -23,12 +40,124 @@ pub trait IOManager: Sync + Send {
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum IOType {
     StandardFileIO, // 标准文件IO
-    MemoryMap,      // 内存映射,用于加快启动速度
+    MemoryMap,      // 内存映射,用于加快启动速度, 也可以用于写入活跃文件
+    DirectIO,       // 绕过页缓存, 用于使用者自己实现缓存的场景
+    InMemory,       // 数据只保存在内存中, 不创建文件, 只能用于`EngineOptions::ephemeral`
+    #[cfg(feature = "io-uring")]
+    IoUring, // io_uring 提交读写请求, 不支持时使用标准文件IO
 }
 
 pub fn new_io_manager(file_name: PathBuf, io_type: IOType) -> Result<Box<dyn IOManager>> {
-    match io_type {
-        IOType::StandardFileIO => Ok(Box::new(FileIO::new(file_name)?)),
-        IOType::MemoryMap => Ok(Box::new(MMapIO::new(file_name)?)),
+    let io_manager: Box<dyn IOManager> = match io_type {
+        IOType::StandardFileIO => Box::new(FileIO::new(file_name.clone())?),
+        IOType::MemoryMap => Box::new(MMapIO::new(file_name.clone())?),
+        IOType::DirectIO => Box::new(DirectIO::new(file_name.clone())?),
+        IOType::InMemory => Box::new(MemoryIO::new()),
+        #[cfg(feature = "io-uring")]
+        IOType::IoUring => new_uring_io_manager(file_name.clone())?,
+    };
+    // 测试崩溃恢复时, 注入了故障的数据目录中的文件写入会失败
+    #[cfg(any(test, feature = "test-util"))]
+    let io_manager = crate::test_util::wrap_io_manager(&file_name, io_manager);
+    Ok(io_manager)
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn new_uring_io_manager(file_name: PathBuf) -> Result<Box<dyn IOManager>> {
+    match uring::UringIO::new(file_name.clone()) {
+        Ok(uring_io) => Ok(Box::new(uring_io)),
+        Err(e) => {
+            tracing::warn!(
+                "io_uring is not supported, fallback to standard file io: {}",
+                e
+            );
+            Ok(Box::new(FileIO::new(file_name)?))
+        }
+    }
+}
+
+#[cfg(all(feature = "io-uring", not(target_os = "linux")))]
+fn new_uring_io_manager(file_name: PathBuf) -> Result<Box<dyn IOManager>> {
+    Ok(Box::new(FileIO::new(file_name)?))
+}
+
+/// 从`offset`开始读取, 直到`buf`填满或者到达文件末尾, 返回读取的字节数
+/// 一次系统调用可能读不满`buf`, 各个平台都循环读取, 行为保持一致
+pub(crate) fn read_full_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match read_at(file, &mut buf[n..], offset + n as u64) {
+            Ok(0) => break,
+            Ok(size) => n += size,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
     }
+    Ok(n)
 }
+
+/// 在`offset`处写入整个`buf`
+pub(crate) fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    let mut n = 0;
+    while n < buf.len() {
+        match write_at(file, &buf[n..], offset + n as u64) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(size) => n += size,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// 按位置读取一次, windows 上会移动文件的读写位置, 顺序写入之前都会重新 seek
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_at(buf, offset)
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        file.seek_read(buf, offset)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        use std::io::{Read, Seek, SeekFrom};
+        let _guard = SEEK_LOCK.lock();
+        let mut file = file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read(buf)
+    }
+}
+
+/// 按位置写入一次
+pub(crate) fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.write_at(buf, offset)
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        file.seek_write(buf, offset)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        use std::io::{Seek, SeekFrom, Write};
+        let _guard = SEEK_LOCK.lock();
+        let mut file = file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write(buf)
+    }
+}
+
+/// 没有按位置读写接口的平台上先 seek 再读写, 两步之间不能被其他线程打断
+#[cfg(not(any(unix, windows)))]
+static SEEK_LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
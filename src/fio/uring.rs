@@ -0,0 +1,147 @@
+use crate::prelude::*;
+use std::{
+    fs::{File, OpenOptions},
+    os::fd::AsRawFd,
+    path::PathBuf,
+};
+
+use fs2::FileExt as _;
+use io_uring::{opcode, squeue, types, IoUring};
+use tracing::error;
+use parking_lot::Mutex;
+
+use super::IOManager;
+
+/// 每个文件的提交队列大小, 读写都是提交一个请求后等待它完成
+const RING_ENTRIES: u32 = 8;
+
+/// 基于 io_uring 的文件IO
+pub struct UringIO {
+    file: File,
+    ring: Mutex<IoUring>,
+}
+
+impl UringIO {
+    /// 内核不支持 io_uring 时返回错误
+    pub fn new(file_name: PathBuf) -> Result<Self> {
+        let file = match OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(file_name)
+        {
+            Ok(file) => file,
+            Err(e) => {
+                error!("open data file error: {}", e);
+                return Err(Errors::IO(e));
+            }
+        };
+        let ring = IoUring::new(RING_ENTRIES)?;
+
+        Ok(Self {
+            file,
+            ring: Mutex::new(ring),
+        })
+    }
+
+    /// 提交一个请求并等待完成, 返回请求的结果
+    fn submit(&self, entry: squeue::Entry) -> Result<usize> {
+        let mut ring = self.ring.lock();
+        // 每次只有一个请求, 队列不会满
+        unsafe {
+            ring.submission()
+                .push(&entry)
+                .map_err(std::io::Error::other)?;
+        }
+        ring.submit_and_wait(1)?;
+
+        let cqe = ring
+            .completion()
+            .next()
+            .ok_or_else(|| std::io::Error::other("no completion"))?;
+        if cqe.result() < 0 {
+            let e = std::io::Error::from_raw_os_error(-cqe.result());
+            error!("io_uring request err: {}", e);
+            return Err(Errors::IO(e));
+        }
+        Ok(cqe.result() as usize)
+    }
+
+    fn fd(&self) -> types::Fd {
+        types::Fd(self.file.as_raw_fd())
+    }
+}
+
+impl IOManager for UringIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let entry = opcode::Read::new(self.fd(), buf.as_mut_ptr(), buf.len() as u32)
+            .offset(offset)
+            .build();
+        self.submit(entry)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let offset = self.size()?;
+        self.write_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            let rest = &buf[n..];
+            let entry = opcode::Write::new(self.fd(), rest.as_ptr(), rest.len() as u32)
+                .offset(offset + n as u64)
+                .build();
+            n += self.submit(entry)?;
+        }
+        Ok(n)
+    }
+
+    fn allocate(&self, size: u64) -> Result<()> {
+        if let Err(e) = self.file.allocate(size) {
+            error!("allocate data file err: {}", e);
+            return Err(Errors::IO(e));
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.submit(opcode::Fsync::new(self.fd()).build())?;
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fio::{new_io_manager, IOType};
+
+    use super::*;
+
+    #[test]
+    fn test_uring_io_write_and_read() {
+        let basepath = PathBuf::from("./tmp/uring");
+        std::fs::create_dir_all(&basepath).unwrap();
+        let path = basepath.join("write.data");
+        let _ = std::fs::remove_file(&path);
+
+        // 不支持 io_uring 时会使用标准文件IO, 读写的结果一样
+        let fio = new_io_manager(path.clone(), IOType::IoUring).unwrap();
+        assert_eq!(5, fio.write(b"key-1").unwrap());
+        assert_eq!(11, fio.write(b"hello-lucas").unwrap());
+        assert_eq!(3, fio.write_at(b"KEY", 0).unwrap());
+        fio.sync().unwrap();
+        assert_eq!(16, fio.size().unwrap());
+
+        let mut buf = [0u8; 16];
+        assert_eq!(16, fio.read(&mut buf, 0).unwrap());
+        assert_eq!(b"KEY-1hello-lucas", &buf);
+        assert_eq!(0, fio.read(&mut buf, 16).unwrap());
+
+        let _ = std::fs::remove_dir_all(&basepath);
+    }
+}
@@ -1,16 +1,57 @@
 use crate::prelude::*;
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
 
 use bytes::Bytes;
 use parking_lot::RwLock;
 
-use crate::data::log_record::LogRecordPos;
+use crate::{data::log_record::LogRecordPos, options::IndexType, stat::IndexStat};
 
-use super::{btree_iterator::BTreeIterator, IndexIterator, Indexer};
+use super::{btree_iterator::BTreeIterator, IndexIterator, IndexUpdate, Indexer};
+
+/// `keys_iter`每次持有读锁时最多拷贝多少个`key`
+const KEYS_BATCH_SIZE: usize = 1024;
 
 /// `BTree` 内存索引,封装了标准库的 `BTreeMap`
 pub struct BTree {
+    pub(crate) tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+}
+
+/// 分批拷贝`key`, 拷贝完一批就释放读锁, 不会长时间阻塞写入
+struct BTreeKeys {
     tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+    batch: std::vec::IntoIter<Bytes>,
+    /// 上一批的最后一个`key`, 下一批从它之后开始
+    last: Option<Bytes>,
+    finished: bool,
+}
+
+impl Iterator for BTreeKeys {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        if let Some(key) = self.batch.next() {
+            return Some(key);
+        }
+        if self.finished {
+            return None;
+        }
+
+        let lower = match &self.last {
+            Some(key) => Bound::Excluded(key.as_ref()),
+            None => Bound::Unbounded,
+        };
+        let batch: Vec<_> = self
+            .tree
+            .read()
+            .range::<[u8], _>((lower, Bound::Unbounded))
+            .take(KEYS_BATCH_SIZE)
+            .map(|(key, _)| Bytes::copy_from_slice(key))
+            .collect();
+        self.finished = batch.len() < KEYS_BATCH_SIZE;
+        self.last = batch.last().cloned();
+        self.batch = batch.into_iter();
+        self.batch.next()
+    }
 }
 
 impl BTree {
@@ -22,36 +63,34 @@ impl BTree {
 }
 
 impl Indexer for BTree {
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
         let mut write_guard = self.tree.write();
-        write_guard.insert(key, pos)
+        Ok(write_guard.insert(key, pos))
     }
 
-    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    fn get(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
         let read_guard = self.tree.read();
-        read_guard.get(&key).copied()
+        Ok(read_guard.get(&key).copied())
     }
     /// 删除key,key不存在返回false
-    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
         let mut write_guard = self.tree.write();
-        write_guard.remove(&key)
+        Ok(write_guard.remove(&key))
     }
 
-    fn iterator(&self, options: crate::options::IteratorOptions) -> Box<dyn IndexIterator> {
-        let read_guard = self.tree.read();
-        let mut items = Vec::with_capacity(read_guard.len());
-        for (key, value) in read_guard.iter() {
-            items.push((key.clone(), value.clone()));
-        }
+    fn extend(&self, updates: Vec<IndexUpdate>) -> Result<Vec<LogRecordPos>> {
+        let mut write_guard = self.tree.write();
+        Ok(updates
+            .into_iter()
+            .filter_map(|(key, pos)| match pos {
+                Some(pos) => write_guard.insert(key, pos),
+                None => write_guard.remove(&key),
+            })
+            .collect())
+    }
 
-        if options.reverse {
-            items.reverse();
-        }
-        Box::new(BTreeIterator {
-            items,
-            curr_index: 0,
-            options,
-        })
+    fn iterator(&self, options: crate::options::IteratorOptions) -> Box<dyn IndexIterator> {
+        Box::new(BTreeIterator::new(self.tree.clone(), options))
     }
 
     fn list_keys(&self) -> Result<Vec<bytes::Bytes>> {
@@ -63,6 +102,54 @@ impl Indexer for BTree {
 
         Ok(keys)
     }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        Box::new(BTreeKeys {
+            tree: self.tree.clone(),
+            batch: Vec::new().into_iter(),
+            last: None,
+            finished: false,
+        })
+    }
+
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        f: &mut dyn FnMut(&[u8], &LogRecordPos),
+    ) -> Result<()> {
+        let read_guard = self.tree.read();
+        for (key, pos) in read_guard
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+        {
+            f(key, pos);
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.tree.read().len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        // 每个节点最多存放 11 个元素, 按半满估算节点的额外开销
+        const ENTRY_OVERHEAD: usize = std::mem::size_of::<usize>() * 2;
+        let entry_size = std::mem::size_of::<(Vec<u8>, LogRecordPos)>() + ENTRY_OVERHEAD;
+
+        let read_guard = self.tree.read();
+        read_guard
+            .keys()
+            .map(|key| key.capacity() + entry_size)
+            .sum()
+    }
+
+    fn stat(&self) -> IndexStat {
+        IndexStat {
+            index_type: IndexType::BTree,
+            in_memory_keys: self.len(),
+            spilled_keys: 0,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -72,36 +159,42 @@ mod tests {
     #[test]
     fn test_btree_put() {
         let bt = BTree::new();
-        let ret1 = bt.put(
-            "ret1".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret1 = bt
+            .put(
+                "ret1".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
 
         assert_eq!(true, ret1.is_none());
 
-        let ret2 = bt.put(
-            "ret2".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret2 = bt
+            .put(
+                "ret2".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
 
         assert_eq!(true, ret2.is_none());
 
-        let ret1 = bt.put(
-            "ret1".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret1 = bt
+            .put(
+                "ret1".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
         assert_eq!(true, ret1.is_some());
         let pos = ret1.unwrap();
         assert_eq!(1, pos.file_id);
@@ -111,17 +204,19 @@ mod tests {
     #[test]
     fn test_btree_get_exist_key() {
         let bt = BTree::new();
-        let ret1 = bt.put(
-            "ret1".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret1 = bt
+            .put(
+                "ret1".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
         assert_eq!(ret1.is_none(), true);
 
-        let pos = bt.get("ret1".as_bytes().to_vec());
+        let pos = bt.get("ret1".as_bytes().to_vec()).unwrap();
         assert!(pos.is_some());
         let pos = pos.unwrap();
         assert_eq!(pos.file_id, 1);
@@ -131,33 +226,35 @@ mod tests {
     #[test]
     fn test_btree_get_non_exist_key() {
         let bt = BTree::new();
-        let pos1 = bt.get("ret1".as_bytes().to_vec());
+        let pos1 = bt.get("ret1".as_bytes().to_vec()).unwrap();
         assert!(pos1.is_none());
 
-        let pos2: Option<LogRecordPos> = bt.get("".as_bytes().to_vec());
+        let pos2: Option<LogRecordPos> = bt.get("".as_bytes().to_vec()).unwrap();
         assert!(pos2.is_none());
     }
 
     #[test]
     fn test_btree_delete_exist_key() {
         let bt = BTree::new();
-        let ret1 = bt.put(
-            "ret1".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret1 = bt
+            .put(
+                "ret1".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
         assert_eq!(ret1.is_none(), true);
 
-        let delete_ret = bt.delete("ret1".as_bytes().to_vec());
+        let delete_ret = bt.delete("ret1".as_bytes().to_vec()).unwrap();
         assert_eq!(delete_ret.is_some(), true);
         let delete_pos = delete_ret.unwrap();
         assert_eq!(delete_pos.file_id, 1);
         assert_eq!(delete_pos.offset, 32);
 
-        let pos1 = bt.get("ret1".as_bytes().to_vec());
+        let pos1 = bt.get("ret1".as_bytes().to_vec()).unwrap();
         assert!(pos1.is_none());
     }
 
@@ -165,10 +262,38 @@ mod tests {
     fn test_btree_delete_non_exist_key() {
         let bt = BTree::new();
 
-        let delete_ret = bt.delete("ret1".as_bytes().to_vec());
+        let delete_ret = bt.delete("ret1".as_bytes().to_vec()).unwrap();
         assert_eq!(delete_ret.is_none(), true);
 
-        let pos1 = bt.get("ret1".as_bytes().to_vec());
+        let pos1 = bt.get("ret1".as_bytes().to_vec()).unwrap();
         assert!(pos1.is_none());
     }
+
+    #[test]
+    fn test_btree_extend() {
+        let bt = BTree::new();
+        let pos = |offset: u64| LogRecordPos {
+            file_id: 1,
+            offset,
+            size: 100,
+        };
+        bt.put(b"old".to_vec(), pos(0)).unwrap();
+
+        // 按顺序应用, 同一批中后面的修改覆盖前面的
+        let old = bt
+            .extend(vec![
+                (b"a".to_vec(), Some(pos(1))),
+                (b"old".to_vec(), Some(pos(2))),
+                (b"a".to_vec(), Some(pos(3))),
+                (b"b".to_vec(), Some(pos(4))),
+                (b"b".to_vec(), None),
+                (b"missing".to_vec(), None),
+            ])
+            .unwrap();
+        assert_eq!(old, vec![pos(0), pos(1), pos(4)]);
+        assert_eq!(bt.get(b"a".to_vec()).unwrap(), Some(pos(3)));
+        assert_eq!(bt.get(b"old".to_vec()).unwrap(), Some(pos(2)));
+        assert!(bt.get(b"b".to_vec()).unwrap().is_none());
+        assert_eq!(bt.len(), 2);
+    }
 }
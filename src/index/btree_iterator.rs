@@ -1,46 +1,107 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Bound,
+    sync::Arc,
+};
+
+use parking_lot::RwLock;
+
 use crate::{data::log_record::LogRecordPos, options::IteratorOptions};
 
-use super::IndexIterator;
+use super::{bound_as_slice, start_bound, IndexIterator};
 
+/// 每次持有读锁时最多拷贝多少条数据
+const ITER_BATCH_SIZE: usize = 256;
+
+/// 按需从`BTreeMap`中分批拷贝数据, 拷贝完一批就释放读锁, 不会一次拷贝整个索引
+/// 不是快照, 遍历期间的修改可能看得到也可能看不到
 pub struct BTreeIterator {
-    pub(crate) items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key, 索引
-    pub(crate) curr_index: usize,                   // 当前遍历的位置
-    pub(crate) options: IteratorOptions,
+    tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+    /// 已经拷贝、还没有返回的数据
+    batch: VecDeque<(Vec<u8>, LogRecordPos)>,
+    /// 下一批从哪里开始拷贝, 正向时是下界, 反向时是上界
+    pub(crate) bound: Bound<Vec<u8>>,
+    /// 底层已经没有更多的数据, 或者已经遇到了不带前缀的`key`
+    finished: bool,
+    /// 上一次`next`返回的数据
+    curr: Option<(Vec<u8>, LogRecordPos)>,
+    options: IteratorOptions,
+}
+
+impl BTreeIterator {
+    /// 设置了前缀时从第一个带前缀的`key`开始
+    pub(crate) fn new(
+        tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+        options: IteratorOptions,
+    ) -> Self {
+        let mut iter = BTreeIterator {
+            tree,
+            batch: VecDeque::new(),
+            bound: Bound::Unbounded,
+            finished: false,
+            curr: None,
+            options,
+        };
+        iter.rewind();
+        iter
+    }
+
+    fn reset(&mut self, bound: Bound<Vec<u8>>) {
+        self.bound = bound;
+        self.batch.clear();
+        self.finished = false;
+        self.curr = None;
+    }
+
+    /// 从`bound`开始按遍历方向拷贝下一批数据
+    fn fill_batch(&mut self) {
+        let tree = self.tree.read();
+        let bound = bound_as_slice(&self.bound);
+        let batch: VecDeque<_> = match self.options.reverse {
+            false => tree
+                .range::<[u8], _>((bound, Bound::Unbounded))
+                .take(ITER_BATCH_SIZE)
+                .map(|(key, pos)| (key.clone(), *pos))
+                .collect(),
+            true => tree
+                .range::<[u8], _>((Bound::Unbounded, bound))
+                .rev()
+                .take(ITER_BATCH_SIZE)
+                .map(|(key, pos)| (key.clone(), *pos))
+                .collect(),
+        };
+        self.finished = batch.len() < ITER_BATCH_SIZE;
+        if let Some((key, _)) = batch.back() {
+            self.bound = Bound::Excluded(key.clone());
+        }
+        self.batch = batch;
+    }
 }
 
 impl IndexIterator for BTreeIterator {
     fn rewind(&mut self) {
-        self.curr_index = 0;
+        self.reset(start_bound(&self.options, None));
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        self.curr_index = match self.items.binary_search_by(|(x, _)| {
-            if self.options.reverse {
-                x.cmp(&key).reverse()
-            } else {
-                x.cmp(&key)
-            }
-        }) {
-            Ok(val) => val,
-            Err(insert_val) => insert_val,
-        }
+        self.reset(start_bound(&self.options, Some(&key)));
     }
 
+    /// 带前缀的`key`是连续的, 遇到第一个不带前缀的`key`时结束
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
-            return None;
+        if self.batch.is_empty() && !self.finished {
+            self.fill_batch();
         }
-
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
-            let prefix = &self.options.prefix;
-
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
-                return Some((&item.0, &item.1));
+        self.curr = self.batch.pop_front();
+        match &self.curr {
+            Some((key, _)) if !key.starts_with(&self.options.prefix) => {
+                self.batch.clear();
+                self.finished = true;
+                self.curr = None;
             }
+            _ => {}
         }
-
-        None
+        self.curr.as_ref().map(|(key, pos)| (key, pos))
     }
 }
 
@@ -72,7 +133,7 @@ mod tests {
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
 
             let mut iter = bt.iterator(IteratorOptions::default());
 
@@ -101,28 +162,28 @@ mod tests {
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
             let key = "aa-33-44".as_bytes().to_vec();
             let pos = LogRecordPos {
                 file_id: 0,
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
             let key = "bb-11-22".as_bytes().to_vec();
             let pos = LogRecordPos {
                 file_id: 0,
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
             let key: Vec<u8> = "bb-33-44".as_bytes().to_vec();
             let pos = LogRecordPos {
                 file_id: 0,
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
         }
 
         // 查找,应该只包含aa开头的key
@@ -140,4 +201,146 @@ mod tests {
 
     #[test]
     fn test_btree_iterator_next() {}
+
+    fn keys(iter: &mut Box<dyn IndexIterator>) -> Vec<String> {
+        let mut keys = vec![];
+        while let Some((key, _)) = iter.next() {
+            keys.push(String::from_utf8(key.clone()).unwrap());
+        }
+        keys
+    }
+
+    fn seek_keys(iter: &mut Box<dyn IndexIterator>, key: &str) -> Vec<String> {
+        iter.seek(key.as_bytes().to_vec());
+        keys(iter)
+    }
+
+    fn setup_index() -> BTree {
+        let index = BTree::new();
+        for key in ["a", "aa-1", "aa-2", "ab", "b-1", "b-2", "c"] {
+            index
+                .put(
+                    key.as_bytes().to_vec(),
+                    LogRecordPos {
+                        file_id: 0,
+                        offset: 0,
+                        size: 0,
+                    },
+                )
+                .unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn test_btree_iterator_prefix_range() {
+        let index = setup_index();
+
+        // 直接定位到前缀的起点
+        let opts = IteratorOptions::builder()
+            .prefix("aa".as_bytes().to_vec())
+            .reverse(false)
+            .build();
+        let iter = BTreeIterator::new(index.tree.clone(), opts);
+        assert_eq!(iter.bound, Bound::Included("aa".as_bytes().to_vec()));
+        let opts = IteratorOptions::builder()
+            .prefix("aa".as_bytes().to_vec())
+            .reverse(true)
+            .build();
+        let iter = BTreeIterator::new(index.tree.clone(), opts);
+        assert_eq!(iter.bound, Bound::Excluded("ab".as_bytes().to_vec()));
+
+        let opts = IteratorOptions::builder()
+            .prefix("aa".as_bytes().to_vec())
+            .reverse(false)
+            .build();
+        let mut iter = index.iterator(opts);
+        assert_eq!(keys(&mut iter), vec!["aa-1", "aa-2"]);
+        // 遇到不带前缀的`key`之后一直结束
+        assert!(iter.next().is_none());
+        iter.rewind();
+        assert_eq!(keys(&mut iter), vec!["aa-1", "aa-2"]);
+        assert_eq!(seek_keys(&mut iter, ""), vec!["aa-1", "aa-2"]);
+        assert_eq!(seek_keys(&mut iter, "a"), vec!["aa-1", "aa-2"]);
+        assert_eq!(seek_keys(&mut iter, "aa-15"), vec!["aa-2"]);
+        assert!(seek_keys(&mut iter, "ab").is_empty());
+
+        let opts = IteratorOptions::builder()
+            .prefix("aa".as_bytes().to_vec())
+            .reverse(true)
+            .build();
+        let mut iter = index.iterator(opts);
+        assert_eq!(keys(&mut iter), vec!["aa-2", "aa-1"]);
+        iter.rewind();
+        assert_eq!(keys(&mut iter), vec!["aa-2", "aa-1"]);
+        assert_eq!(seek_keys(&mut iter, "zzz"), vec!["aa-2", "aa-1"]);
+        assert_eq!(seek_keys(&mut iter, "aa-2"), vec!["aa-2", "aa-1"]);
+        assert_eq!(seek_keys(&mut iter, "aa-15"), vec!["aa-1"]);
+        assert!(seek_keys(&mut iter, "a").is_empty());
+    }
+
+    #[test]
+    fn test_btree_iterator_reverse_seek() {
+        let index = setup_index();
+
+        let mut iter = index.iterator(IteratorOptions::default());
+        assert_eq!(seek_keys(&mut iter, "b"), vec!["b-1", "b-2", "c"]);
+        assert_eq!(seek_keys(&mut iter, "b-2"), vec!["b-2", "c"]);
+        assert!(seek_keys(&mut iter, "d").is_empty());
+
+        let opts = IteratorOptions::builder()
+            .prefix(vec![])
+            .reverse(true)
+            .build();
+        let mut iter = index.iterator(opts);
+        // 反向时定位到第一个 小于/等于 的`key`
+        assert_eq!(seek_keys(&mut iter, "b"), vec!["ab", "aa-2", "aa-1", "a"]);
+        assert_eq!(
+            seek_keys(&mut iter, "b-1"),
+            vec!["b-1", "ab", "aa-2", "aa-1", "a"]
+        );
+        assert_eq!(seek_keys(&mut iter, "d").len(), 7);
+        assert!(seek_keys(&mut iter, "0").is_empty());
+    }
+
+    #[test]
+    fn test_btree_iterator_batches() {
+        let index = BTree::new();
+        let pos = LogRecordPos {
+            file_id: 0,
+            offset: 0,
+            size: 0,
+        };
+        let count = ITER_BATCH_SIZE * 3 + 10;
+        for i in 0..count {
+            index
+                .put(format!("key-{:05}", i).into_bytes(), pos)
+                .unwrap();
+        }
+
+        let mut iter = index.iterator(IteratorOptions::default());
+        let forward = keys(&mut iter);
+        assert_eq!(forward.len(), count);
+        assert!(forward.windows(2).all(|w| w[0] < w[1]));
+
+        let opts = IteratorOptions::builder()
+            .prefix(vec![])
+            .reverse(true)
+            .build();
+        let mut iter = index.iterator(opts);
+        let mut reverse = keys(&mut iter);
+        reverse.reverse();
+        assert_eq!(reverse, forward);
+
+        // 拷贝完一批就释放读锁, 遍历期间可以写入, 还没拷贝的部分能看到修改
+        let mut iter = index.iterator(IteratorOptions::default());
+        assert!(iter.next().is_some());
+        index
+            .delete(format!("key-{:05}", count - 1).into_bytes())
+            .unwrap();
+        index.put("key-99999".as_bytes().to_vec(), pos).unwrap();
+        let mut rest = keys(&mut iter);
+        assert_eq!(rest.len(), count - 1);
+        assert_eq!(rest.pop().unwrap(), "key-99999");
+    }
 }
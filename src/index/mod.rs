@@ -3,25 +3,58 @@ pub mod btree;
 pub mod btree_iterator;
 pub mod skiplist;
 pub mod skiplist_iterator;
+pub mod spill;
+
+use std::ops::Bound;
 
 use bytes::Bytes;
 
 use crate::{
     data::log_record::LogRecordPos,
-    options::{IndexType, IteratorOptions},
+    options::{EngineOptions, IndexType, IteratorOptions},
+    stat::IndexStat,
 };
 
+/// 批量更新索引时的一条修改, 位置为 None 表示删除`key`
+pub type IndexUpdate = (Vec<u8>, Option<LogRecordPos>);
+
 /// 内存索引抽象接口
 pub trait Indexer: Sync + Send {
     /// 写入`key`, 返回旧的`value`
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos>;
-    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos>;
+    /// 内存中的索引不会失败, 溢出到磁盘的索引读写磁盘失败时返回错误
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>>;
+    fn get(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>>;
     /// 删除`key`,返回被删除的`key`的`value`
-    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos>;
+    fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>>;
+    /// 按顺序应用一批修改, 返回被覆盖或删除的旧`value`, 启动时加载索引使用
+    /// 有锁的索引整批只获取一次写锁, 默认逐条调用`put`/`delete`
+    fn extend(&self, updates: Vec<IndexUpdate>) -> Result<Vec<LogRecordPos>> {
+        let mut old = Vec::new();
+        for (key, pos) in updates {
+            let old_pos = match pos {
+                Some(pos) => self.put(key, pos)?,
+                None => self.delete(key)?,
+            };
+            old.extend(old_pos);
+        }
+        Ok(old)
+    }
     /// 返回索引迭代器
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
     /// 获取所有 key
     fn list_keys(&self) -> Result<Vec<Bytes>>;
+    /// 按顺序逐个返回所有`key`, 不会一次拷贝所有`key`
+    /// 不是快照, 遍历期间的修改可能看得到也可能看不到
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_>;
+    /// 对以`prefix`开头的`key`执行`f`, 不拷贝`key`, `f`中不能再访问索引
+    fn for_each_prefix(&self, prefix: &[u8], f: &mut dyn FnMut(&[u8], &LogRecordPos))
+        -> Result<()>;
+    /// `key`的数量
+    fn len(&self) -> usize;
+    /// 估算索引占用的内存, 需要遍历所有`key`
+    fn memory_usage(&self) -> usize;
+    /// 索引的统计信息, 不需要遍历`key`
+    fn stat(&self) -> IndexStat;
 }
 
 pub trait IndexIterator: Sync + Send {
@@ -35,9 +68,51 @@ pub trait IndexIterator: Sync + Send {
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
 }
 
-pub fn new_indexer(index_type: IndexType) -> impl Indexer {
-    match index_type {
-        IndexType::BTree => btree::BTree::new(),
-        IndexType::SkipList => todo!(),
+/// 以`prefix`开头的`key`都小于返回值, 没有这样的上界时(`prefix`为空或者全是`0xff`)返回 None
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < u8::MAX {
+            upper.push(last + 1);
+            return Some(upper);
+        }
     }
+    None
+}
+
+/// 索引迭代器从哪里开始查找, 正向时是下界, 反向时是上界
+/// `seek_key`为空时从带前缀的第一个`key`开始, 不会移动到带前缀的`key`前面
+pub(crate) fn start_bound(options: &IteratorOptions, seek_key: Option<&[u8]>) -> Bound<Vec<u8>> {
+    let prefix = options.prefix.as_slice();
+    if !options.reverse {
+        let key = match seek_key {
+            Some(key) if key > prefix => key,
+            _ => prefix,
+        };
+        return Bound::Included(key.to_vec());
+    }
+
+    // 反向时跳过比所有带前缀的`key`都大的部分
+    match (seek_key, prefix_upper_bound(prefix)) {
+        (Some(key), Some(upper)) if key >= upper.as_slice() => Bound::Excluded(upper),
+        (Some(key), _) => Bound::Included(key.to_vec()),
+        (None, Some(upper)) => Bound::Excluded(upper),
+        (None, None) => Bound::Unbounded,
+    }
+}
+
+pub(crate) fn bound_as_slice(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.as_slice()),
+        Bound::Excluded(key) => Bound::Excluded(key.as_slice()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+pub fn new_indexer(options: &EngineOptions) -> Result<Box<dyn Indexer>> {
+    Ok(match options.index_type {
+        IndexType::BTree => Box::new(btree::BTree::new()),
+        IndexType::SkipList => todo!(),
+        IndexType::Spill => Box::new(spill::SpillIndex::open(options)?),
+    })
 }
@@ -1,15 +1,15 @@
 use crate::prelude::*;
-use std::sync::Arc;
+use std::{ops::Bound, sync::Arc};
 
 use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
 
-use crate::data::log_record::LogRecordPos;
+use crate::{data::log_record::LogRecordPos, options::IndexType, stat::IndexStat};
 
 use super::{skiplist_iterator::SkipListIterator, Indexer};
 
 pub struct SkipList {
-    skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
+    pub(crate) skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
 }
 
 impl SkipList {
@@ -21,47 +21,32 @@ impl SkipList {
 }
 
 impl Indexer for SkipList {
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
         let mut old_value = None;
 
         if let Some(entry) = self.skl.get(&key) {
             old_value = Some(*entry.value());
         }
         self.skl.insert(key, pos);
-        old_value
+        Ok(old_value)
     }
 
-    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    fn get(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
         if let Some(entry) = self.skl.get(&key) {
-            return Some(*entry.value());
+            return Ok(Some(*entry.value()));
         }
-        None
+        Ok(None)
     }
 
-    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
         if let Some(entry) = self.skl.remove(&key) {
-            return Some(*entry.value());
+            return Ok(Some(*entry.value()));
         }
-        None
+        Ok(None)
     }
 
     fn iterator(&self, options: crate::options::IteratorOptions) -> Box<dyn super::IndexIterator> {
-        let mut items = Vec::with_capacity(self.skl.len());
-
-        for entry in self.skl.iter() {
-            let (key, value) = (entry.key(), entry.value());
-            items.push((key.clone(), value.clone()));
-        }
-
-        if options.reverse {
-            items.reverse();
-        }
-
-        Box::new(SkipListIterator {
-            items,
-            curr_index: 0,
-            options,
-        })
+        Box::new(SkipListIterator::new(self.skl.clone(), options))
     }
 
     fn list_keys(&self) -> Result<Vec<bytes::Bytes>> {
@@ -72,6 +57,52 @@ impl Indexer for SkipList {
 
         Ok(keys)
     }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        Box::new(
+            self.skl
+                .iter()
+                .map(|entry| Bytes::copy_from_slice(entry.key())),
+        )
+    }
+
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        f: &mut dyn FnMut(&[u8], &LogRecordPos),
+    ) -> Result<()> {
+        for entry in self
+            .skl
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|entry| entry.key().starts_with(prefix))
+        {
+            f(entry.key(), entry.value());
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.skl.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        // 每个节点有引用计数、高度以及平均 2 层的指针
+        const NODE_OVERHEAD: usize = std::mem::size_of::<usize>() * 4;
+        let entry_size = std::mem::size_of::<(Vec<u8>, LogRecordPos)>() + NODE_OVERHEAD;
+
+        self.skl
+            .iter()
+            .map(|entry| entry.key().capacity() + entry_size)
+            .sum()
+    }
+
+    fn stat(&self) -> IndexStat {
+        IndexStat {
+            index_type: IndexType::SkipList,
+            in_memory_keys: self.len(),
+            spilled_keys: 0,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -81,36 +112,42 @@ mod tests {
     #[test]
     fn test_btree_put() {
         let bt = SkipList::new();
-        let ret1 = bt.put(
-            "ret1".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret1 = bt
+            .put(
+                "ret1".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
 
         assert_eq!(ret1.is_none(), true);
 
-        let ret2 = bt.put(
-            "ret2".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret2 = bt
+            .put(
+                "ret2".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
 
         assert_eq!(ret2.is_none(), true);
 
-        let ret1 = bt.put(
-            "ret1".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 3,
-                offset: 4,
-                size: 100,
-            },
-        );
+        let ret1 = bt
+            .put(
+                "ret1".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 3,
+                    offset: 4,
+                    size: 100,
+                },
+            )
+            .unwrap();
         assert_eq!(ret1.is_some(), true);
         let old_pos = ret1.unwrap();
         assert_eq!(old_pos.file_id, 1);
@@ -120,17 +157,19 @@ mod tests {
     #[test]
     fn test_btree_get_exist_key() {
         let bt = SkipList::new();
-        let ret1 = bt.put(
-            "ret1".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret1 = bt
+            .put(
+                "ret1".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
         assert_eq!(ret1.is_none(), true);
 
-        let pos = bt.get("ret1".as_bytes().to_vec());
+        let pos = bt.get("ret1".as_bytes().to_vec()).unwrap();
         assert!(pos.is_some());
         let pos = pos.unwrap();
         assert_eq!(pos.file_id, 1);
@@ -140,33 +179,35 @@ mod tests {
     #[test]
     fn test_btree_get_non_exist_key() {
         let bt = SkipList::new();
-        let pos1 = bt.get("ret1".as_bytes().to_vec());
+        let pos1 = bt.get("ret1".as_bytes().to_vec()).unwrap();
         assert!(pos1.is_none());
 
-        let pos2: Option<LogRecordPos> = bt.get("".as_bytes().to_vec());
+        let pos2: Option<LogRecordPos> = bt.get("".as_bytes().to_vec()).unwrap();
         assert!(pos2.is_none());
     }
 
     #[test]
     fn test_btree_delete_exist_key() {
         let bt = SkipList::new();
-        let ret1 = bt.put(
-            "ret1".as_bytes().to_vec(),
-            LogRecordPos {
-                file_id: 1,
-                offset: 32,
-                size: 100,
-            },
-        );
+        let ret1 = bt
+            .put(
+                "ret1".as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 32,
+                    size: 100,
+                },
+            )
+            .unwrap();
         assert_eq!(ret1.is_none(), true);
 
-        let delete_ret = bt.delete("ret1".as_bytes().to_vec());
+        let delete_ret = bt.delete("ret1".as_bytes().to_vec()).unwrap();
         assert_eq!(delete_ret.is_some(), true);
         let delete_pos = delete_ret.unwrap();
         assert_eq!(delete_pos.file_id, 1);
         assert_eq!(delete_pos.offset, 32);
 
-        let pos1 = bt.get("ret1".as_bytes().to_vec());
+        let pos1 = bt.get("ret1".as_bytes().to_vec()).unwrap();
         assert!(pos1.is_none());
     }
 
@@ -174,10 +215,10 @@ mod tests {
     fn test_btree_delete_non_exist_key() {
         let bt = SkipList::new();
 
-        let delete_ret = bt.delete("ret1".as_bytes().to_vec());
+        let delete_ret = bt.delete("ret1".as_bytes().to_vec()).unwrap();
         assert_eq!(delete_ret.is_none(), true);
 
-        let pos1 = bt.get("ret1".as_bytes().to_vec());
+        let pos1 = bt.get("ret1".as_bytes().to_vec()).unwrap();
         assert!(pos1.is_none());
     }
 }
@@ -1,46 +1,75 @@
+use std::{ops::Bound, sync::Arc};
+
+use crossbeam_skiplist::SkipMap;
+
 use crate::{data::log_record::LogRecordPos, options::IteratorOptions};
 
-use super::IndexIterator;
+use super::{bound_as_slice, start_bound, IndexIterator};
 
+/// 每次`next`从上一个`key`之后在跳表中查找, 跳表支持无锁的并发读写, 不需要拷贝整个索引
+/// 不是快照, 遍历期间的修改可能看得到也可能看不到
 pub struct SkipListIterator {
-    pub(crate) items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key, 索引
-    pub(crate) curr_index: usize,                   // 当前遍历的位置
-    pub(crate) options: IteratorOptions,
+    skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
+    /// 下一次从哪里开始查找, 正向时是下界, 反向时是上界
+    pub(crate) bound: Bound<Vec<u8>>,
+    /// 已经遍历完, 或者已经遇到了不带前缀的`key`
+    finished: bool,
+    /// 上一次`next`返回的数据
+    curr: Option<(Vec<u8>, LogRecordPos)>,
+    options: IteratorOptions,
+}
+
+impl SkipListIterator {
+    /// 设置了前缀时从第一个带前缀的`key`开始
+    pub(crate) fn new(skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>, options: IteratorOptions) -> Self {
+        let mut iter = SkipListIterator {
+            skl,
+            bound: Bound::Unbounded,
+            finished: false,
+            curr: None,
+            options,
+        };
+        iter.rewind();
+        iter
+    }
+
+    fn reset(&mut self, bound: Bound<Vec<u8>>) {
+        self.bound = bound;
+        self.finished = false;
+        self.curr = None;
+    }
 }
 
 impl IndexIterator for SkipListIterator {
     fn rewind(&mut self) {
-        self.curr_index = 0;
+        self.reset(start_bound(&self.options, None));
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        self.curr_index = match self.items.binary_search_by(|(x, _)| {
-            if self.options.reverse {
-                x.cmp(&key).reverse()
-            } else {
-                x.cmp(&key)
-            }
-        }) {
-            Ok(val) => val,
-            Err(insert_val) => insert_val,
-        }
+        self.reset(start_bound(&self.options, Some(&key)));
     }
 
+    /// 带前缀的`key`是连续的, 遇到第一个不带前缀的`key`时结束
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
+        if self.finished {
             return None;
         }
-
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
-            let prefix = &self.options.prefix;
-
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
-                return Some((&item.0, &item.1));
+        // 从上一次返回的`key`之后开始
+        if let Some((key, _)) = self.curr.take() {
+            self.bound = Bound::Excluded(key);
+        }
+        let bound = bound_as_slice(&self.bound);
+        let entry = match self.options.reverse {
+            false => self.skl.lower_bound(bound),
+            true => self.skl.upper_bound(bound),
+        };
+        match entry {
+            Some(entry) if entry.key().starts_with(&self.options.prefix) => {
+                self.curr = Some((entry.key().clone(), *entry.value()));
             }
+            _ => self.finished = true,
         }
-
-        None
+        self.curr.as_ref().map(|(key, pos)| (key, pos))
     }
 }
 
@@ -72,7 +101,7 @@ mod tests {
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
 
             let mut iter = bt.iterator(IteratorOptions::default());
 
@@ -101,28 +130,28 @@ mod tests {
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
             let key = "aa-33-44".as_bytes().to_vec();
             let pos = LogRecordPos {
                 file_id: 0,
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
             let key = "bb-11-22".as_bytes().to_vec();
             let pos = LogRecordPos {
                 file_id: 0,
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
             let key: Vec<u8> = "bb-33-44".as_bytes().to_vec();
             let pos = LogRecordPos {
                 file_id: 0,
                 offset: 10,
                 size: 100,
             };
-            bt.put(key.clone(), pos.clone());
+            bt.put(key.clone(), pos.clone()).unwrap();
         }
 
         // 查找,应该只包含aa开头的key
@@ -140,4 +169,123 @@ mod tests {
 
     #[test]
     fn test_skiplist_iterator_next() {}
+
+    fn keys(iter: &mut Box<dyn IndexIterator>) -> Vec<String> {
+        let mut keys = vec![];
+        while let Some((key, _)) = iter.next() {
+            keys.push(String::from_utf8(key.clone()).unwrap());
+        }
+        keys
+    }
+
+    fn seek_keys(iter: &mut Box<dyn IndexIterator>, key: &str) -> Vec<String> {
+        iter.seek(key.as_bytes().to_vec());
+        keys(iter)
+    }
+
+    fn setup_index() -> SkipList {
+        let index = SkipList::new();
+        for key in ["a", "aa-1", "aa-2", "ab", "b-1", "b-2", "c"] {
+            index
+                .put(
+                    key.as_bytes().to_vec(),
+                    LogRecordPos {
+                        file_id: 0,
+                        offset: 0,
+                        size: 0,
+                    },
+                )
+                .unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn test_skiplist_iterator_prefix_range() {
+        let index = setup_index();
+
+        // 直接定位到前缀的起点
+        let opts = IteratorOptions::builder()
+            .prefix("aa".as_bytes().to_vec())
+            .reverse(false)
+            .build();
+        let iter = SkipListIterator::new(index.skl.clone(), opts);
+        assert_eq!(iter.bound, Bound::Included("aa".as_bytes().to_vec()));
+        let opts = IteratorOptions::builder()
+            .prefix("aa".as_bytes().to_vec())
+            .reverse(true)
+            .build();
+        let iter = SkipListIterator::new(index.skl.clone(), opts);
+        assert_eq!(iter.bound, Bound::Excluded("ab".as_bytes().to_vec()));
+
+        let opts = IteratorOptions::builder()
+            .prefix("aa".as_bytes().to_vec())
+            .reverse(false)
+            .build();
+        let mut iter = index.iterator(opts);
+        assert_eq!(keys(&mut iter), vec!["aa-1", "aa-2"]);
+        // 遇到不带前缀的`key`之后一直结束
+        assert!(iter.next().is_none());
+        iter.rewind();
+        assert_eq!(keys(&mut iter), vec!["aa-1", "aa-2"]);
+        assert_eq!(seek_keys(&mut iter, ""), vec!["aa-1", "aa-2"]);
+        assert_eq!(seek_keys(&mut iter, "a"), vec!["aa-1", "aa-2"]);
+        assert_eq!(seek_keys(&mut iter, "aa-15"), vec!["aa-2"]);
+        assert!(seek_keys(&mut iter, "ab").is_empty());
+
+        let opts = IteratorOptions::builder()
+            .prefix("aa".as_bytes().to_vec())
+            .reverse(true)
+            .build();
+        let mut iter = index.iterator(opts);
+        assert_eq!(keys(&mut iter), vec!["aa-2", "aa-1"]);
+        iter.rewind();
+        assert_eq!(keys(&mut iter), vec!["aa-2", "aa-1"]);
+        assert_eq!(seek_keys(&mut iter, "zzz"), vec!["aa-2", "aa-1"]);
+        assert_eq!(seek_keys(&mut iter, "aa-2"), vec!["aa-2", "aa-1"]);
+        assert_eq!(seek_keys(&mut iter, "aa-15"), vec!["aa-1"]);
+        assert!(seek_keys(&mut iter, "a").is_empty());
+    }
+
+    #[test]
+    fn test_skiplist_iterator_reverse_seek() {
+        let index = setup_index();
+
+        let mut iter = index.iterator(IteratorOptions::default());
+        assert_eq!(seek_keys(&mut iter, "b"), vec!["b-1", "b-2", "c"]);
+        assert_eq!(seek_keys(&mut iter, "b-2"), vec!["b-2", "c"]);
+        assert!(seek_keys(&mut iter, "d").is_empty());
+
+        let opts = IteratorOptions::builder()
+            .prefix(vec![])
+            .reverse(true)
+            .build();
+        let mut iter = index.iterator(opts);
+        // 反向时定位到第一个 小于/等于 的`key`
+        assert_eq!(seek_keys(&mut iter, "b"), vec!["ab", "aa-2", "aa-1", "a"]);
+        assert_eq!(
+            seek_keys(&mut iter, "b-1"),
+            vec!["b-1", "ab", "aa-2", "aa-1", "a"]
+        );
+        assert_eq!(seek_keys(&mut iter, "d").len(), 7);
+        assert!(seek_keys(&mut iter, "0").is_empty());
+    }
+
+    #[test]
+    fn test_skiplist_iterator_concurrent_modification() {
+        let index = setup_index();
+        let pos = LogRecordPos {
+            file_id: 0,
+            offset: 0,
+            size: 0,
+        };
+
+        // 每次从上一个`key`之后查找, 遍历期间的修改在还没遍历的部分可以看到
+        let mut iter = index.iterator(IteratorOptions::default());
+        assert_eq!(iter.next().unwrap().0, &"a".as_bytes().to_vec());
+        index.delete("a".as_bytes().to_vec()).unwrap();
+        index.delete("aa-1".as_bytes().to_vec()).unwrap();
+        index.put("bb".as_bytes().to_vec(), pos).unwrap();
+        assert_eq!(keys(&mut iter), vec!["aa-2", "ab", "b-1", "b-2", "bb", "c"]);
+    }
 }
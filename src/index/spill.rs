@@ -0,0 +1,661 @@
+use crate::prelude::*;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    ops::Bound,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use parking_lot::RwLock;
+use tracing::error;
+
+use crate::{
+    data::log_record::LogRecordPos,
+    fio::{read_full_at, write_all_at},
+    options::{EngineOptions, IndexType, IteratorOptions},
+    stat::IndexStat,
+};
+
+use super::{btree_iterator::BTreeIterator, IndexIterator, IndexUpdate, Indexer};
+
+/// 数据目录中存放溢出文件的子目录, 索引重建后就没有用了, 打开时清空, 备份时跳过
+pub(crate) const INDEX_SPILL_DIR_NAME: &str = "index-spill";
+/// 没有设置`max_index_memory`时的默认值
+pub const DEFAULT_MAX_INDEX_MEMORY: usize = 256 * 1024 * 1024;
+
+/// 磁盘上一条数据的头部: next(8) + hash(8) + key 长度(4) + file_id(4) + offset(8) + size(8) + 删除标记(1)
+const ENTRY_HEADER_SIZE: usize = 8 + 8 + 4 + 4 + 8 + 8 + 1;
+/// 链表的结尾
+const NO_ENTRY: u64 = u64::MAX;
+/// 桶数组最多占用内存限制的 1/4, 剩下的给内存中的热数据
+const BUCKET_MEMORY_RATIO: usize = 4;
+const MIN_BUCKET_NUM: usize = 1024;
+/// 超过内存限制时一次淘汰到限制的 90%, 避免每次写入都写一次磁盘
+const EVICT_TARGET_PERCENT: usize = 90;
+
+/// 同一个进程中的每个索引使用不同的溢出文件
+static SPILL_FILE_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// 内存有限的索引: 最近访问过的`key`保存在内存的`BTreeMap`中,
+/// 超过`max_index_memory`后按 CLOCK 算法把不常访问的`key`写到磁盘上的哈希表
+///
+/// 磁盘上的哈希表只追加: 桶数组在内存中, 每个桶指向最新写入的一条数据, 数据之间用 next 串成链表,
+/// 更新和删除都在链表头部追加一条新数据. 读取磁盘上的`key`不会放回内存, 只读一次的遍历不会把热数据挤出去
+///
+/// 索引启动时会重新构建, 溢出文件只在进程运行期间有效
+/// 读写溢出文件失败时返回错误, 索引保持失败之前的状态; `iterator`和`keys_iter`无法返回错误, 读取失败时记录日志并返回空的结果
+pub struct SpillIndex {
+    inner: RwLock<Inner>,
+}
+
+struct Inner {
+    hot: BTreeMap<Vec<u8>, HotEntry>,
+    hot_bytes: usize,
+    max_hot_bytes: usize,
+    buckets: Vec<u64>,
+    /// 写到磁盘上的数据条数, 为0时不需要查找磁盘
+    spilled: usize,
+    /// `key`的数量, 包括内存和磁盘上的
+    len: usize,
+    /// CLOCK 算法的指针, 下次从这个`key`之后开始淘汰
+    clock_hand: Vec<u8>,
+    file: SpillFile,
+}
+
+struct HotEntry {
+    pos: LogRecordPos,
+    /// 最近是否被访问过, 淘汰时跳过并清除
+    referenced: AtomicBool,
+    /// 磁盘上是否有这个`key`的旧数据, 删除时需要追加删除标记
+    on_disk: bool,
+}
+
+/// 溢出文件, 索引释放时删除
+struct SpillFile {
+    file: File,
+    path: PathBuf,
+    size: AtomicU64,
+}
+
+struct SpillEntry {
+    next: u64,
+    hash: u64,
+    key: Vec<u8>,
+    pos: LogRecordPos,
+    deleted: bool,
+}
+
+impl SpillIndex {
+    pub fn open(options: &EngineOptions) -> Result<Self> {
+        // 只读模式不能修改数据目录, 溢出文件放到临时目录
+        let dir_path = if options.read_only {
+            std::env::temp_dir().join(INDEX_SPILL_DIR_NAME)
+        } else {
+            let dir_path = options.dir_path.join(INDEX_SPILL_DIR_NAME);
+            // 已经拿到了文件锁, 剩下的是上次没有正常关闭时留下的
+            if dir_path.is_dir() {
+                fs::remove_dir_all(&dir_path)?;
+            }
+            dir_path
+        };
+        fs::create_dir_all(&dir_path)?;
+
+        let path = dir_path.join(format!(
+            "{}-{}.spill",
+            std::process::id(),
+            SPILL_FILE_SEQ.fetch_add(1, Ordering::SeqCst)
+        ));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        let max_memory = options.max_index_memory.unwrap_or(DEFAULT_MAX_INDEX_MEMORY);
+        let bucket_num = bucket_num(max_memory);
+        let max_hot_bytes = max_memory.saturating_sub(bucket_num * std::mem::size_of::<u64>());
+
+        Ok(SpillIndex {
+            inner: RwLock::new(Inner {
+                hot: BTreeMap::new(),
+                hot_bytes: 0,
+                max_hot_bytes,
+                buckets: vec![NO_ENTRY; bucket_num],
+                spilled: 0,
+                len: 0,
+                clock_hand: Vec::new(),
+                file: SpillFile {
+                    file,
+                    path,
+                    size: AtomicU64::new(0),
+                },
+            }),
+        })
+    }
+
+    /// 内存和磁盘上所有以`prefix`开头的`key`, 按`key`排序
+    /// 需要读取磁盘上的所有数据, 结果全部放在内存中
+    fn collect(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+        let mut items = Vec::new();
+        self.for_each_prefix(prefix, &mut |key, pos| items.push((key.to_vec(), *pos)))?;
+        items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Ok(items)
+    }
+
+    /// 用于不能返回错误的遍历
+    fn collect_or_empty(&self, prefix: &[u8]) -> Vec<(Vec<u8>, LogRecordPos)> {
+        self.collect(prefix).unwrap_or_else(|e| {
+            error!("failed to read index spill file: {}", e);
+            Vec::new()
+        })
+    }
+}
+
+impl Indexer for SpillIndex {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+        self.inner.write().put(key, pos).map_err(put_failed)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+        let inner = self.inner.read();
+        if let Some(entry) = inner.hot.get(&key) {
+            entry.referenced.store(true, Ordering::Relaxed);
+            return Ok(Some(entry.pos));
+        }
+        Ok(inner.disk_get(&key)?)
+    }
+
+    fn delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+        Ok(self.inner.write().delete(key)?)
+    }
+
+    fn extend(&self, updates: Vec<IndexUpdate>) -> Result<Vec<LogRecordPos>> {
+        let mut inner = self.inner.write();
+        let mut old = Vec::new();
+        for (key, pos) in updates {
+            let old_pos = match pos {
+                Some(pos) => inner.put(key, pos).map_err(put_failed)?,
+                None => inner.delete(key)?,
+            };
+            old.extend(old_pos);
+        }
+        Ok(old)
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        // 磁盘上的`key`没有顺序, 先把带前缀的数据取出来
+        let items: BTreeMap<_, _> = self.collect_or_empty(&options.prefix).into_iter().collect();
+        Box::new(BTreeIterator::new(Arc::new(RwLock::new(items)), options))
+    }
+
+    fn list_keys(&self) -> Result<Vec<Bytes>> {
+        Ok(self
+            .collect(&[])?
+            .into_iter()
+            .map(|(key, _)| Bytes::from(key))
+            .collect())
+    }
+
+    /// 磁盘上的`key`没有顺序, 需要先全部取出来排序
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        Box::new(
+            self.collect_or_empty(&[])
+                .into_iter()
+                .map(|(key, _)| Bytes::from(key)),
+        )
+    }
+
+    /// 先遍历内存中的数据, 再遍历磁盘上的, 不按`key`排序
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        f: &mut dyn FnMut(&[u8], &LogRecordPos),
+    ) -> Result<()> {
+        let inner = self.inner.read();
+        for (key, entry) in inner
+            .hot
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+        {
+            f(key, &entry.pos);
+        }
+        if inner.spilled == 0 {
+            return Ok(());
+        }
+
+        for &head in inner.buckets.iter() {
+            // 链表中先出现的是最新的数据
+            let mut seen = HashSet::new();
+            let mut offset = head;
+            while offset != NO_ENTRY {
+                let entry = inner.file.read_entry(offset, None)?;
+                offset = entry.next;
+                if !seen.insert(entry.key.clone()) {
+                    continue;
+                }
+                if !entry.deleted
+                    && entry.key.starts_with(prefix)
+                    && !inner.hot.contains_key(&entry.key)
+                {
+                    f(&entry.key, &entry.pos);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.inner.read().len
+    }
+
+    fn memory_usage(&self) -> usize {
+        let inner = self.inner.read();
+        inner.hot_bytes + inner.buckets.len() * std::mem::size_of::<u64>()
+    }
+
+    fn stat(&self) -> IndexStat {
+        let inner = self.inner.read();
+        IndexStat {
+            index_type: IndexType::Spill,
+            in_memory_keys: inner.hot.len(),
+            spilled_keys: inner.len - inner.hot.len(),
+        }
+    }
+}
+
+impl Inner {
+    fn bucket(&self, hash: u64) -> usize {
+        (hash as usize) & (self.buckets.len() - 1)
+    }
+
+    fn put(&mut self, key: Vec<u8>, pos: LogRecordPos) -> std::io::Result<Option<LogRecordPos>> {
+        if let Some(entry) = self.hot.get_mut(&key) {
+            entry.referenced.store(true, Ordering::Relaxed);
+            return Ok(Some(std::mem::replace(&mut entry.pos, pos)));
+        }
+
+        let old = self.disk_get(&key)?;
+        if old.is_none() {
+            self.len += 1;
+        }
+        self.hot_bytes += hot_entry_size(&key);
+        self.hot.insert(
+            key,
+            HotEntry {
+                pos,
+                referenced: AtomicBool::new(false),
+                on_disk: old.is_some(),
+            },
+        );
+        if self.hot_bytes > self.max_hot_bytes {
+            self.evict();
+        }
+        Ok(old)
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> std::io::Result<Option<LogRecordPos>> {
+        let (old, on_disk) = match self.hot.get(&key) {
+            Some(entry) => (Some(entry.pos), entry.on_disk),
+            None => {
+                let old = self.disk_get(&key)?;
+                (old, old.is_some())
+            }
+        };
+
+        // 磁盘上的旧数据需要用删除标记覆盖, 否则会重新出现
+        // 先写删除标记再从内存中移除, 写入失败时索引保持不变
+        if on_disk {
+            let tombstone = LogRecordPos {
+                file_id: 0,
+                offset: 0,
+                size: 0,
+            };
+            self.spill(vec![(key.clone(), tombstone, true)])?;
+        }
+        if self.hot.remove(&key).is_some() {
+            self.hot_bytes -= hot_entry_size(&key);
+        }
+        if old.is_some() {
+            self.len -= 1;
+        }
+        Ok(old)
+    }
+
+    /// 在磁盘上的哈希表中查找, 遇到删除标记时返回 None
+    fn disk_get(&self, key: &[u8]) -> std::io::Result<Option<LogRecordPos>> {
+        if self.spilled == 0 {
+            return Ok(None);
+        }
+        let hash = hash_key(key);
+        let mut offset = self.buckets[self.bucket(hash)];
+        while offset != NO_ENTRY {
+            let entry = self.file.read_entry(offset, Some((hash, key.len())))?;
+            if entry.hash == hash && entry.key == key {
+                return Ok((!entry.deleted).then_some(entry.pos));
+            }
+            offset = entry.next;
+        }
+        Ok(None)
+    }
+
+    /// 把一批数据追加到溢出文件, 插入到对应的链表头部
+    fn spill(&mut self, entries: Vec<(Vec<u8>, LogRecordPos, bool)>) -> std::io::Result<()> {
+        let total: usize = entries
+            .iter()
+            .map(|(key, _, _)| ENTRY_HEADER_SIZE + key.len())
+            .sum();
+        let base = self.file.size.fetch_add(total as u64, Ordering::SeqCst);
+
+        let mut buf = BytesMut::with_capacity(total);
+        let mut heads = Vec::with_capacity(entries.len());
+        for (key, pos, deleted) in entries {
+            let hash = hash_key(&key);
+            let bucket = self.bucket(hash);
+            // 同一批中同一个桶的数据也要串起来
+            let next = heads
+                .iter()
+                .rev()
+                .find(|(b, _)| *b == bucket)
+                .map(|(_, offset)| *offset)
+                .unwrap_or(self.buckets[bucket]);
+            let offset = base + buf.len() as u64;
+
+            buf.put_u64(next);
+            buf.put_u64(hash);
+            buf.put_u32(key.len() as u32);
+            buf.put_u32(pos.file_id);
+            buf.put_u64(pos.offset);
+            buf.put_u64(pos.size as u64);
+            buf.put_u8(deleted as u8);
+            buf.put_slice(&key);
+            heads.push((bucket, offset));
+        }
+        write_all_at(&self.file.file, &buf, base)?;
+
+        self.spilled += heads.len();
+        for (bucket, offset) in heads {
+            self.buckets[bucket] = offset;
+        }
+        Ok(())
+    }
+
+    /// CLOCK 算法: 从上次的位置开始扫描, 最近访问过的清除标记后跳过, 最多扫描两圈
+    fn evict(&mut self) {
+        let target = self.max_hot_bytes / 100 * EVICT_TARGET_PERCENT;
+        let need = self.hot_bytes.saturating_sub(target);
+
+        let mut victims = Vec::new();
+        let mut freed = 0;
+        'sweep: for _ in 0..2 {
+            let after = self
+                .hot
+                .range::<Vec<u8>, _>((Bound::Excluded(&self.clock_hand), Bound::Unbounded));
+            let before = self
+                .hot
+                .range::<Vec<u8>, _>((Bound::Unbounded, Bound::Included(&self.clock_hand)));
+            for (key, entry) in after.chain(before) {
+                if entry.referenced.swap(false, Ordering::Relaxed) {
+                    continue;
+                }
+                // 选中的设置访问标记, 第二圈会跳过
+                entry.referenced.store(true, Ordering::Relaxed);
+                freed += hot_entry_size(key);
+                victims.push((key.clone(), entry.pos, false));
+                if freed >= need {
+                    break 'sweep;
+                }
+            }
+        }
+        if victims.is_empty() {
+            return;
+        }
+
+        let last = victims[victims.len() - 1].0.clone();
+        let keys: Vec<_> = victims.iter().map(|(key, _, _)| key.clone()).collect();
+        if let Err(e) = self.spill(victims) {
+            // 写磁盘失败时留在内存中, 下次写入时再试
+            error!("failed to spill index entries to disk: {}", e);
+            return;
+        }
+        for key in keys {
+            self.hot.remove(&key);
+        }
+        self.hot_bytes -= freed;
+        self.clock_hand = last;
+    }
+}
+
+impl SpillFile {
+    /// 读取`offset`处的数据, `expect`是要查找的 hash 和`key`的长度, 不匹配时不读取`key`
+    fn read_entry(&self, offset: u64, expect: Option<(u64, usize)>) -> std::io::Result<SpillEntry> {
+        let mut header = [0u8; ENTRY_HEADER_SIZE];
+        self.read_exact_at(&mut header, offset)?;
+        let mut buf = &header[..];
+        let next = buf.get_u64();
+        let hash = buf.get_u64();
+        let key_len = buf.get_u32() as usize;
+        let pos = LogRecordPos {
+            file_id: buf.get_u32(),
+            offset: buf.get_u64(),
+            size: buf.get_u64() as usize,
+        };
+        let deleted = buf.get_u8() == 1;
+
+        let mut key = Vec::new();
+        if expect.is_none_or(|(h, len)| h == hash && len == key_len) {
+            key.resize(key_len, 0);
+            self.read_exact_at(&mut key, offset + ENTRY_HEADER_SIZE as u64)?;
+        }
+        Ok(SpillEntry {
+            next,
+            hash,
+            key,
+            pos,
+            deleted,
+        })
+    }
+
+    /// 数据都是写入成功之后才能被找到, 读不满说明溢出文件已经损坏
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        if read_full_at(&self.file, buf, offset)? < buf.len() {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!("failed to remove index spill file: {}", e);
+        }
+    }
+}
+
+/// 写入失败统一返回`IndexUpdateFailed`, 具体原因记录到日志
+fn put_failed(e: std::io::Error) -> Errors {
+    error!("failed to update index spill file: {}", e);
+    Errors::IndexUpdateFailed
+}
+
+/// 桶的数量取不超过内存限制 1/4 的 2 的幂
+fn bucket_num(max_memory: usize) -> usize {
+    let max = max_memory / BUCKET_MEMORY_RATIO / std::mem::size_of::<u64>();
+    if max <= MIN_BUCKET_NUM {
+        return MIN_BUCKET_NUM;
+    }
+    1 << (usize::BITS - 1 - max.leading_zeros())
+}
+
+fn hash_key(key: &[u8]) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// 和`BTree::memory_usage`的估算方式相同
+fn hot_entry_size(key: &[u8]) -> usize {
+    const ENTRY_OVERHEAD: usize = std::mem::size_of::<usize>() * 2;
+    key.len() + std::mem::size_of::<(Vec<u8>, HotEntry)>() + ENTRY_OVERHEAD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(name: &str, max_index_memory: usize) -> SpillIndex {
+        let mut opts = EngineOptions::default();
+        opts.dir_path = PathBuf::from("./tmp/spill").join(name);
+        opts.max_index_memory = Some(max_index_memory);
+        SpillIndex::open(&opts).unwrap()
+    }
+
+    fn pos(i: u64) -> LogRecordPos {
+        LogRecordPos {
+            file_id: i as u32,
+            offset: i * 10,
+            size: 100,
+        }
+    }
+
+    #[test]
+    fn test_spill_index() {
+        // 只有桶数组的空间, 几乎所有 key 都写到磁盘
+        let index = open("basic", MIN_BUCKET_NUM * 8 * BUCKET_MEMORY_RATIO + 4096);
+        for i in 0..2000 {
+            assert!(index
+                .put(format!("key-{:05}", i).into_bytes(), pos(i))
+                .unwrap()
+                .is_none());
+        }
+        assert!(index.inner.read().spilled > 0);
+        assert!(index.memory_usage() <= MIN_BUCKET_NUM * 8 * BUCKET_MEMORY_RATIO + 4096);
+        assert_eq!(index.len(), 2000);
+
+        for i in 0..2000 {
+            assert_eq!(
+                index.get(format!("key-{:05}", i).into_bytes()).unwrap(),
+                Some(pos(i))
+            );
+        }
+        assert!(index.get(b"missing".to_vec()).unwrap().is_none());
+
+        // 更新和删除磁盘上的 key
+        assert_eq!(
+            index.put(b"key-00001".to_vec(), pos(9999)).unwrap(),
+            Some(pos(1))
+        );
+        assert_eq!(index.get(b"key-00001".to_vec()).unwrap(), Some(pos(9999)));
+        assert_eq!(index.delete(b"key-00002".to_vec()).unwrap(), Some(pos(2)));
+        assert!(index.get(b"key-00002".to_vec()).unwrap().is_none());
+        assert!(index.delete(b"key-00002".to_vec()).unwrap().is_none());
+        assert_eq!(index.len(), 1999);
+
+        for i in 0..1000 {
+            index.delete(format!("key-{:05}", i).into_bytes()).unwrap();
+        }
+        assert_eq!(index.len(), 1000);
+
+        // 迭代器按顺序返回, 不包括删除的 key
+        let keys = index.list_keys().unwrap();
+        assert_eq!(keys.len(), 1000);
+        assert_eq!(keys[0], Bytes::from("key-01000"));
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        let mut iter = index.iterator(IteratorOptions {
+            prefix: b"key-019".to_vec(),
+            reverse: true,
+            ..Default::default()
+        });
+        assert_eq!(iter.next().unwrap().0, b"key-01999");
+        iter.seek(b"key-01950".to_vec());
+        assert_eq!(iter.next(), Some((&b"key-01950".to_vec(), &pos(1950))));
+
+        let path = index.inner.read().file.path.clone();
+        assert!(path.is_file());
+        drop(index);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_spill_index_keeps_hot_keys() {
+        let index = open("hot", MIN_BUCKET_NUM * 8 * BUCKET_MEMORY_RATIO + 64 * 1024);
+        index.put(b"hot".to_vec(), pos(0)).unwrap();
+        for i in 1..5000 {
+            index
+                .put(format!("cold-{:05}", i).into_bytes(), pos(i))
+                .unwrap();
+            // 经常访问的 key 不会被淘汰
+            index.get(b"hot".to_vec()).unwrap();
+        }
+        let inner = index.inner.read();
+        assert!(inner.spilled > 0);
+        assert!(inner.hot.contains_key(&b"hot".to_vec()));
+        assert!(inner.hot_bytes <= inner.max_hot_bytes);
+    }
+
+    #[test]
+    fn test_spill_index_extend() {
+        let index = open("extend", MIN_BUCKET_NUM * 8 * BUCKET_MEMORY_RATIO + 4096);
+        let old = index
+            .extend(
+                (0..2000)
+                    .map(|i| (format!("key-{:05}", i).into_bytes(), Some(pos(i))))
+                    .collect(),
+            )
+            .unwrap();
+        assert!(old.is_empty());
+        assert!(index.inner.read().spilled > 0);
+        assert_eq!(index.len(), 2000);
+
+        // 磁盘上的 key 也能被覆盖和删除
+        let old = index
+            .extend(vec![
+                (b"key-00001".to_vec(), Some(pos(9999))),
+                (b"key-00002".to_vec(), None),
+                (b"key-00002".to_vec(), None),
+                (b"new".to_vec(), Some(pos(1))),
+            ])
+            .unwrap();
+        assert_eq!(old, vec![pos(1), pos(2)]);
+        assert_eq!(index.get(b"key-00001".to_vec()).unwrap(), Some(pos(9999)));
+        assert!(index.get(b"key-00002".to_vec()).unwrap().is_none());
+        assert_eq!(index.get(b"new".to_vec()).unwrap(), Some(pos(1)));
+        assert_eq!(index.len(), 2000);
+    }
+
+    #[test]
+    fn test_spill_index_io_error() {
+        let index = open("io-error", MIN_BUCKET_NUM * 8 * BUCKET_MEMORY_RATIO + 4096);
+        for i in 0..2000 {
+            index
+                .put(format!("key-{:05}", i).into_bytes(), pos(i))
+                .unwrap();
+        }
+        let (spilled_key, len) = {
+            let inner = index.inner.read();
+            let key = (0..2000)
+                .map(|i| format!("key-{:05}", i).into_bytes())
+                .find(|key| !inner.hot.contains_key(key))
+                .unwrap();
+            inner.file.file.set_len(0).unwrap();
+            (key, inner.len)
+        };
+
+        // 读取失败时返回错误, 不会 panic
+        assert!(matches!(index.get(spilled_key.clone()), Err(Errors::IO(_))));
+        assert!(matches!(
+            index.put(spilled_key.clone(), pos(9999)),
+            Err(Errors::IndexUpdateFailed)
+        ));
+        assert!(matches!(index.delete(spilled_key), Err(Errors::IO(_))));
+        assert!(index.list_keys().is_err());
+        assert_eq!(index.len(), len);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::prelude::*;
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+use bytes::Bytes;
+use tracing::{field::Empty, instrument, Span};
+
+use crate::{
+    batch::log_record_key_with_seq,
+    data::{
+        data_file::{get_data_file_name, DataFile},
+        log_record::{now_timestamp, LogRecord, LogRecordPos, LogRecordType},
+    },
+    db::{older_file_io_type, Engine},
+    options::CompressionType,
+    utils,
+    watch::Operation,
+};
+
+/// 导入时暂存数据文件的临时目录, 在数据目录下
+pub(crate) const INGEST_TMP_DIR_NAME: &str = "ingest-tmp";
+/// 写临时数据文件的缓冲区大小
+const INGEST_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// 写到临时目录中的数据文件, 文件名是从0开始的序号, 写满`data_file_size`后换下一个文件
+struct IngestFiles {
+    dir_path: PathBuf,
+    data_file_size: u64,
+    /// 已经创建的文件数量
+    file_num: u32,
+    writer: Option<BufWriter<File>>,
+    /// 当前文件的写偏移
+    write_off: u64,
+    /// 所有文件的总大小
+    size: u64,
+}
+
+impl IngestFiles {
+    fn new(dir_path: PathBuf, data_file_size: u64) -> Self {
+        IngestFiles {
+            dir_path,
+            data_file_size,
+            file_num: 0,
+            writer: None,
+            write_off: 0,
+            size: 0,
+        }
+    }
+
+    /// 追加一条编码之后的数据, 返回的位置中`file_id`是临时文件的序号
+    fn append(&mut self, encoded_record: &[u8]) -> Result<LogRecordPos> {
+        let len = encoded_record.len() as u64;
+        if len > self.data_file_size {
+            return Err(Errors::RecordTooLarge {
+                size: len,
+                data_file_size: self.data_file_size,
+            });
+        }
+
+        if self.writer.is_none() || self.write_off + len > self.data_file_size {
+            self.finish_file()?;
+            let file = File::create(get_data_file_name(&self.dir_path, self.file_num))?;
+            self.writer = Some(BufWriter::with_capacity(INGEST_BUFFER_SIZE, file));
+            self.file_num += 1;
+            self.write_off = 0;
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_all(encoded_record)?;
+        }
+
+        let pos = LogRecordPos {
+            file_id: self.file_num - 1,
+            offset: self.write_off,
+            size: encoded_record.len(),
+        };
+        self.write_off += len;
+        self.size += len;
+        Ok(pos)
+    }
+
+    /// 写完当前文件并持久化
+    fn finish_file(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+/// 写好的临时文件
+struct StagedFiles {
+    file_num: u32,
+    size: u64,
+    /// 导入的`key`和在临时文件中的位置
+    positions: Vec<(Vec<u8>, LogRecordPos)>,
+}
+
+impl Engine {
+    /// 批量导入按`key`升序排列、没有重复的数据, 返回导入的数量, 已经存在的`key`会被覆盖
+    /// 数据直接写到新的数据文件中, 不经过活跃文件, 适合初始化时导入大量数据
+    /// 所有数据作为一个事务, 全部写完之后才放到活跃文件的前面并更新索引,
+    /// 中途失败或者崩溃时不会导入任何数据
+    /// 导入之后根据内存索引重新生成hint文件, 需要遍历整个索引
+    #[instrument(skip_all, fields(keys = Empty, files = Empty, elapsed_us = Empty))]
+    pub fn ingest<I>(&self, sorted_kvs: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = (Bytes, Bytes)>,
+    {
+        let _timer = utils::trace::span_timer();
+        self.check_writable()?;
+        self.check_persistent("ingest")?;
+        // merge 也会切换活跃文件和生成hint文件, 导入期间不能 merge
+        let _merge_lock = self.merging_lock.lock();
+
+        let tmp_path = self.options.dir_path.join(INGEST_TMP_DIR_NAME);
+        if tmp_path.is_dir() {
+            fs::remove_dir_all(&tmp_path)?;
+        }
+        fs::create_dir_all(&tmp_path)?;
+
+        let seq_no = self.seq_no.fetch_add(1, Ordering::SeqCst);
+        let res = self
+            .stage_ingest_files(&tmp_path, sorted_kvs, seq_no)
+            .and_then(|staged| self.install_ingest_files(&tmp_path, staged, seq_no));
+        let _ = fs::remove_dir_all(&tmp_path);
+        res
+    }
+
+    /// 按顺序把数据写到临时目录, 最后一个文件的末尾是事务完成的标识
+    fn stage_ingest_files<I>(
+        &self,
+        tmp_path: &Path,
+        sorted_kvs: I,
+        seq_no: usize,
+    ) -> Result<StagedFiles>
+    where
+        I: IntoIterator<Item = (Bytes, Bytes)>,
+    {
+        let mut files = IngestFiles::new(tmp_path.to_path_buf(), self.options.data_file_size);
+        let mut positions = Vec::new();
+        let mut last_key: Option<Bytes> = None;
+        let timestamp = now_timestamp();
+        for (key, value) in sorted_kvs {
+            self.check_key_value_size(&key, &value)?;
+            if last_key.as_ref().is_some_and(|last| key <= *last) {
+                return Err(Errors::IngestKeysNotSorted);
+            }
+
+            let record = LogRecord {
+                key: log_record_key_with_seq(key.to_vec(), seq_no)?,
+                value: value.to_vec(),
+                rec_type: LogRecordType::Normal,
+                timestamp,
+            };
+            let encoded_record = record.encode_with(
+                self.options.compression,
+                self.options.checksum,
+                self.cipher.as_ref(),
+            )?;
+            positions.push((key.to_vec(), files.append(&encoded_record)?));
+            last_key = Some(key);
+        }
+
+        if !positions.is_empty() {
+            // 标识事务完成
+            let finish_log_record = LogRecord {
+                key: log_record_key_with_seq(TXN_FINISHED_KEY.to_vec(), seq_no)?,
+                value: Default::default(),
+                rec_type: LogRecordType::TxnFinished,
+                timestamp: 0,
+            };
+            files.append(&finish_log_record.encode_with(
+                CompressionType::None,
+                self.options.checksum,
+                None,
+            )?)?;
+        }
+        files.finish_file()?;
+
+        Ok(StagedFiles {
+            file_num: files.file_num,
+            size: files.size,
+            positions,
+        })
+    }
+
+    /// 把临时文件移动到活跃文件前面预留的id上, 然后更新索引、生成hint文件
+    fn install_ingest_files(
+        &self,
+        tmp_path: &Path,
+        staged: StagedFiles,
+        seq_no: usize,
+    ) -> Result<usize> {
+        let StagedFiles {
+            file_num,
+            size,
+            positions,
+        } = staged;
+        Span::current()
+            .record("keys", positions.len())
+            .record("files", file_num);
+        if positions.is_empty() {
+            return Ok(0);
+        }
+
+        if let Some(max_size) = self.options.max_db_size_bytes {
+            if self.disk_size.load(Ordering::SeqCst) + size > max_size {
+                self.db_full_count.fetch_add(1, Ordering::SeqCst);
+                return Err(Errors::DatabaseFull);
+            }
+        }
+
+        // 生成hint文件时不能有提交了一半的事务
+        let _batch_lock = self.batch_commit_lock.lock();
+        let base_fid = {
+            let mut older_files = self.older_files.write();
+            // 在活跃文件后面预留id, 按文件id加载时导入的数据排在已有数据的后面
+            let base_fid = self.rotate_active_file(&mut older_files, file_num)? + 1;
+            // 按顺序移动, 事务完成的标识在最后一个文件中, 中途崩溃时重启会丢弃整个事务
+            let res = (|| {
+                let tmp_path = tmp_path.to_path_buf();
+                for i in 0..file_num {
+                    let file_id = base_fid + i;
+                    fs::rename(
+                        get_data_file_name(&tmp_path, i),
+                        get_data_file_name(&self.options.dir_path, file_id),
+                    )?;
+                    let data_file = DataFile::new(
+                        self.options.dir_path.clone(),
+                        file_id,
+                        older_file_io_type(&self.options),
+                    )?
+                    .with_cipher(self.cipher.clone())
+                    .with_checksum(self.options.checksum);
+                    older_files.insert(file_id, data_file);
+                }
+                Ok(())
+            })();
+            if let Err(e) = res {
+                self.aborted_txn_seqs.lock().insert(seq_no);
+                return Err(e);
+            }
+            base_fid
+        };
+        self.disk_size.fetch_add(size, Ordering::SeqCst);
+
+        // 订阅者需要变更的内容, 有订阅者或者回调时从导入的文件中读回数据
+        let notify = !self.watchers.read().is_empty() || self.event_listener().is_some();
+        let count = positions.len();
+        for (key, mut pos) in positions {
+            pos.file_id += base_fid;
+            if notify {
+                let value = self.get_value_by_position(&pos)?;
+                self.notify_watchers(Operation::Put, &key, &value, &pos);
+            }
+            if let Some(old_pos) = self.update_index(key, Some(pos))? {
+                self.add_reclaim_size(&old_pos);
+            }
+        }
+
+        self.write_hint_file(base_fid + file_num)?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{data::MERGE_FINISHED_FILE_NAME, merge::load_merge_info, options::EngineOptions};
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/ingest".into()
+    }
+
+    fn setup(name: &str) -> EngineOptions {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 4 * 1024;
+        opts
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn kvs(range: std::ops::Range<usize>, value: &str) -> Vec<(Bytes, Bytes)> {
+        range
+            .map(|i| {
+                (
+                    Bytes::from(format!("key-{:05}", i)),
+                    Bytes::from(format!("{}-{}", value, i)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ingest() {
+        let name = "ingest";
+        let opts = setup(name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine
+            .put(Bytes::from("key-00001"), Bytes::from("old"))
+            .unwrap();
+        engine.put(Bytes::from("other"), Bytes::from("v")).unwrap();
+
+        assert_eq!(engine.ingest(kvs(0..1000, "value")).unwrap(), 1000);
+        assert_eq!(engine.ingest(Vec::new()).unwrap(), 0);
+        assert_eq!(engine.len().unwrap(), 1001);
+        assert_eq!(
+            engine.get(Bytes::from("key-00001")).unwrap(),
+            Bytes::from("value-1")
+        );
+        assert!(engine.reclaim_size.load(Ordering::SeqCst) > 0);
+        assert!(engine.older_files.read().len() > 2);
+        assert!(!opts.dir_path.join(MERGE_FINISHED_FILE_NAME).exists());
+        assert!(load_merge_info(&opts.dir_path).unwrap().is_some());
+        assert!(!opts.dir_path.join(INGEST_TMP_DIR_NAME).exists());
+
+        // 导入之后的写入在新的活跃文件中
+        engine
+            .put(Bytes::from("key-00002"), Bytes::from("new"))
+            .unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.len().unwrap(), 1001);
+        assert_eq!(
+            engine.get(Bytes::from("key-00999")).unwrap(),
+            Bytes::from("value-999")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("key-00002")).unwrap(),
+            Bytes::from("new")
+        );
+        assert_eq!(engine.get(Bytes::from("other")).unwrap(), Bytes::from("v"));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_ingest_rejects_unsorted_keys() {
+        let name = "unsorted";
+        let opts = setup(name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let mut data = kvs(0..100, "value");
+        data.swap(10, 20);
+        assert!(matches!(
+            engine.ingest(data),
+            Err(Errors::IngestKeysNotSorted)
+        ));
+        let mut data = kvs(0..100, "value");
+        data[50].0 = data[49].0.clone();
+        assert!(matches!(
+            engine.ingest(data),
+            Err(Errors::IngestKeysNotSorted)
+        ));
+        assert!(engine.is_empty().unwrap());
+        assert!(!opts.dir_path.join(INGEST_TMP_DIR_NAME).exists());
+        drop(engine);
+
+        let engine = Engine::open(opts).expect("failed to reopen engine");
+        assert!(engine.is_empty().unwrap());
+
+        clean(name);
+    }
+}
@@ -1,28 +1,60 @@
 use crate::prelude::*;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
 
 use bytes::Bytes;
 use parking_lot::RwLock;
 
-use crate::{db::Engine, index::IndexIterator, options::IteratorOptions};
+use crate::{
+    data::log_record::LogRecordPos, db::Engine, index::IndexIterator, options::IteratorOptions,
+};
+
+/// 满足过滤条件的`key`、位置和已经读取的 value
+type MatchedItem = (Vec<u8>, LogRecordPos, Option<Bytes>);
 
 pub struct Iterator<'a> {
-    index_iter: Arc<RwLock<Box<dyn IndexIterator>>>, // 索引迭代器
-    engine: &'a Engine,
+    pub(crate) index_iter: Arc<RwLock<Box<dyn IndexIterator>>>, // 索引迭代器
+    pub(crate) engine: &'a Engine,
+    offset: usize,
+    limit: Option<usize>,
+    keys_only: bool,
+    modified_since: Option<u64>,
+    /// 当前位置开始之后是否已经跳过了`offset`条数据
+    skipped: AtomicBool,
+    /// 当前位置开始之后返回了多少条数据
+    returned: AtomicUsize,
 }
 
 impl Engine {
+    /// 按需从内存索引中读取`key`, 不会在创建时拷贝整个索引
+    /// 不是快照, 遍历期间写入的数据可能看得到也可能看不到, 需要一致的结果时使用`Snapshot::iter`
     pub fn iter(&self, options: IteratorOptions) -> Iterator {
-        Iterator {
-            index_iter: Arc::new(RwLock::new(self.index.iterator(options))),
-            engine: self,
-        }
+        Iterator::new(self.index.iterator(options.clone()), self, &options)
     }
 
     pub fn list_keys(&self) -> Result<Vec<Bytes>> {
         self.index.list_keys()
     }
 
+    /// 按顺序逐个返回所有`key`, 和`list_keys`不同, 不需要一次为所有`key`分配内存
+    /// 不是快照, 遍历期间写入的数据可能看得到也可能看不到, 需要一致的结果时使用`Snapshot::keys_iter`
+    pub fn keys_iter(&self) -> impl std::iter::Iterator<Item = Bytes> + '_ {
+        self.index.keys_iter()
+    }
+
+    /// 以`prefix`开头的`key`的数量, 只遍历内存索引, 不拷贝`key`
+    pub fn count(&self, prefix: &[u8]) -> Result<usize> {
+        self.check_open()?;
+        if prefix.is_empty() {
+            return Ok(self.index.len());
+        }
+        let mut count = 0;
+        self.index.for_each_prefix(prefix, &mut |_, _| count += 1)?;
+        Ok(count)
+    }
+
     /// 对数据库中的所有数据执行某个参数,函数返回false时终止
     pub fn fold<F>(&self, f: F) -> Result<()>
     where
@@ -30,7 +62,7 @@ impl Engine {
         F: Fn(Bytes, Bytes) -> bool,
     {
         let iter = self.iter(IteratorOptions::default());
-        while let Some((key, value)) = iter.next() {
+        while let Some((key, value)) = iter.next()? {
             if !f(key, value) {
                 break;
             }
@@ -39,30 +71,97 @@ impl Engine {
     }
 }
 
-impl Iterator<'_> {
+impl<'a> Iterator<'a> {
+    pub(crate) fn new(
+        index_iter: Box<dyn IndexIterator>,
+        engine: &'a Engine,
+        options: &IteratorOptions,
+    ) -> Self {
+        Iterator {
+            index_iter: Arc::new(RwLock::new(index_iter)),
+            engine,
+            offset: options.offset,
+            limit: options.limit,
+            keys_only: options.keys_only,
+            modified_since: options.modified_since,
+            skipped: AtomicBool::new(false),
+            returned: AtomicUsize::new(0),
+        }
+    }
+
     /// 回到迭代器的起点,指向第一个数据
     pub fn rewind(&self) {
         let mut index_iter = self.index_iter.write();
         index_iter.rewind();
+        self.reset_progress();
     }
 
     /// 根据传入的key找到第一个 大于/等于 或 小于/等于 的目标key, 从这个key开始遍历
     pub fn seek(&self, key: Vec<u8>) {
         let mut index_iter = self.index_iter.write();
         index_iter.seek(key);
+        self.reset_progress();
+    }
+
+    fn reset_progress(&self) {
+        self.skipped.store(false, Ordering::SeqCst);
+        self.returned.store(0, Ordering::SeqCst);
     }
 
     /// 移动到下一个 key, 返回 None 说明迭代完毕
-    pub fn next(&self) -> Option<(Bytes, Bytes)> {
+    /// `keys_only`时不读取磁盘, 返回的 value 为空
+    /// 读取 value 失败时返回错误, 迭代器已经越过这个 key, 可以继续调用
+    pub fn next(&self) -> Result<Option<(Bytes, Bytes)>> {
+        self.engine.check_open()?;
         let mut index_iter = self.index_iter.write();
-        if let Some(item) = index_iter.next() {
-            let value = self
-                .engine
-                .get_value_by_position(item.1)
-                .expect("failed to get value from data file");
-            return Some((Bytes::from(item.0.to_vec()), value));
+
+        // 没有过滤条件时, 跳过的数据只需要移动索引迭代器
+        if !self.skipped.swap(true, Ordering::SeqCst) {
+            for _ in 0..self.offset {
+                let skipped = match self.modified_since {
+                    None => index_iter.next().is_some(),
+                    Some(_) => self.next_matched(&mut index_iter)?.is_some(),
+                };
+                if !skipped {
+                    return Ok(None);
+                }
+            }
+        }
+        if let Some(limit) = self.limit {
+            if self.returned.load(Ordering::SeqCst) >= limit {
+                return Ok(None);
+            }
+        }
+
+        let (key, pos, value) = match self.next_matched(&mut index_iter)? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        self.returned.fetch_add(1, Ordering::SeqCst);
+        let key = Bytes::from(key);
+        if self.keys_only {
+            return Ok(Some((key, Bytes::new())));
         }
-        None
+        let value = match value {
+            Some(value) => value,
+            None => self.engine.get_value_by_position(&pos)?,
+        };
+        Ok(Some((key, value)))
+    }
+
+    /// 移动索引迭代器到下一个满足`modified_since`的 key
+    /// 判断时间戳时已经读取了 value, 一起返回, 避免再读一次
+    fn next_matched(&self, index_iter: &mut Box<dyn IndexIterator>) -> Result<Option<MatchedItem>> {
+        while let Some((key, pos)) = index_iter.next() {
+            let Some(since) = self.modified_since else {
+                return Ok(Some((key.to_vec(), *pos, None)));
+            };
+            let meta = self.engine.get_meta_by_position(pos)?;
+            if meta.timestamp >= since {
+                return Ok(Some((key.to_vec(), *pos, Some(meta.value))));
+            }
+        }
+        Ok(None)
     }
 }
 
@@ -110,7 +209,7 @@ mod tests {
             let iter = engine.iter(IteratorOptions::default());
             iter.seek(key.clone());
 
-            assert!(iter.next().is_none());
+            assert!(iter.next().unwrap().is_none());
         }
 
         // 有1条数据
@@ -122,7 +221,7 @@ mod tests {
 
             let iter = engine.iter(IteratorOptions::default());
             iter.seek("a".as_bytes().to_vec());
-            let next_kv = iter.next();
+            let next_kv = iter.next().unwrap();
             assert!(next_kv.is_some());
             let next_kv = next_kv.unwrap();
 
@@ -168,7 +267,7 @@ mod tests {
         // 检查遍历的每个key都是以a开头的
         {
             let iter = engine.iter(iter_opts);
-            while let Some((key, _)) = iter.next() {
+            while let Some((key, _)) = iter.next().unwrap() {
                 let key = String::from_utf8(key.to_vec());
                 assert!(key.is_ok());
                 let key = key.unwrap();
@@ -207,6 +306,43 @@ mod tests {
         clean(&dir_name);
     }
 
+    #[test]
+    fn test_iterator_keys_iter_and_count() {
+        let dir_name = "keys_iter";
+        setup(&dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 超过一批的数量
+        for i in 0..3000 {
+            let prefix = if i % 3 == 0 { "a" } else { "b" };
+            engine
+                .put(
+                    Bytes::from(format!("{}-{:04}", prefix, i)),
+                    Bytes::from("v"),
+                )
+                .unwrap();
+        }
+        let keys: Vec<_> = engine.keys_iter().collect();
+        assert_eq!(keys, engine.list_keys().unwrap());
+
+        assert_eq!(engine.count(b"").unwrap(), 3000);
+        assert_eq!(engine.count(b"a-").unwrap(), 1000);
+        assert_eq!(engine.count(b"c-").unwrap(), 0);
+
+        // 遍历的同时删除
+        for key in engine.keys_iter() {
+            if key.starts_with(b"a-") {
+                engine.delete(key).unwrap();
+            }
+        }
+        assert_eq!(engine.count(b"a-").unwrap(), 0);
+        assert_eq!(engine.keys_iter().count(), 2000);
+
+        clean(&dir_name);
+    }
+
     #[test]
     fn test_iterator_fold() {
         let dir_name = "fold";
@@ -238,4 +374,154 @@ mod tests {
         assert_eq!(*count.borrow(), keys.len());
         clean(&dir_name);
     }
+
+    #[test]
+    fn test_iterator_limit_offset_keys_only() {
+        let dir_name = "limit_offset";
+        setup(&dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..10 {
+            let key = Bytes::from(format!("key-{}", i));
+            let value = Bytes::from(format!("value-{}", i));
+            engine.put(key, value).unwrap();
+        }
+
+        // 分页: 跳过3条, 最多返回4条
+        let iter = engine.iter(
+            IteratorOptions::builder()
+                .prefix(b"key-".to_vec())
+                .reverse(false)
+                .offset(3)
+                .limit(4)
+                .build(),
+        );
+        let mut keys = vec![];
+        while let Some((key, value)) = iter.next().unwrap() {
+            assert_eq!(value, Bytes::from(format!("value-{}", keys.len() + 3)));
+            keys.push(key);
+        }
+        assert_eq!(keys.len(), 4);
+        assert_eq!(keys[0], Bytes::from("key-3"));
+        assert_eq!(keys[3], Bytes::from("key-6"));
+
+        // rewind 之后重新计算 offset 和 limit
+        iter.rewind();
+        assert_eq!(iter.next().unwrap().unwrap().0, Bytes::from("key-3"));
+
+        // offset 超过数据量
+        let iter = engine.iter(IteratorOptions {
+            offset: 20,
+            ..Default::default()
+        });
+        assert!(iter.next().unwrap().is_none());
+
+        // 只返回 key
+        let iter = engine.iter(IteratorOptions {
+            keys_only: true,
+            reverse: true,
+            ..Default::default()
+        });
+        let (key, value) = iter.next().unwrap().unwrap();
+        assert_eq!(key, Bytes::from("key-9"));
+        assert!(value.is_empty());
+
+        clean(&dir_name);
+    }
+
+    #[test]
+    fn test_iterator_value_read_error() {
+        let dir_name = "read_error";
+        setup(&dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        engine.put(Bytes::from("a"), Bytes::from("v1")).unwrap();
+        engine.put(Bytes::from("b"), Bytes::from("v2")).unwrap();
+
+        // 模拟数据文件被截断
+        let file_name = crate::data::data_file::get_data_file_name(&opts.dir_path, 0);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(file_name)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+
+        // 返回错误而不是 panic, 只遍历 key 不受影响
+        let iter = engine.iter(IteratorOptions::default());
+        assert!(iter.next().is_err());
+        assert!(iter.next().is_err());
+        assert!(iter.next().unwrap().is_none());
+        assert!(engine.fold(|_, _| true).is_err());
+
+        let iter = engine.iter(IteratorOptions {
+            keys_only: true,
+            ..Default::default()
+        });
+        assert_eq!(iter.next().unwrap().unwrap().0, Bytes::from("a"));
+
+        clean(&dir_name);
+    }
+
+    #[test]
+    fn test_iterator_modified_since() {
+        let dir_name = "modified_since";
+        setup(dir_name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(dir_name);
+        let engine = Engine::open(opts).expect("failed to open engine");
+
+        for key in ["a", "b", "c"] {
+            engine.put(Bytes::from(key), Bytes::from("old")).unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let since = engine.get_with_meta(Bytes::from("c")).unwrap().timestamp + 1;
+        for key in ["b", "d", "e"] {
+            engine.put(Bytes::from(key), Bytes::from("new")).unwrap();
+        }
+
+        let collect = |options: IteratorOptions| {
+            let iter = engine.iter(options);
+            let mut items = vec![];
+            while let Some((key, value)) = iter.next().unwrap() {
+                items.push((key, value));
+            }
+            items
+        };
+        let options = IteratorOptions::builder()
+            .prefix(vec![])
+            .reverse(false)
+            .modified_since(since)
+            .build();
+        let items = collect(options.clone());
+        assert_eq!(
+            items,
+            vec![
+                (Bytes::from("b"), Bytes::from("new")),
+                (Bytes::from("d"), Bytes::from("new")),
+                (Bytes::from("e"), Bytes::from("new")),
+            ]
+        );
+
+        // offset 和 limit 只计算满足条件的数据
+        let mut paged = options.clone();
+        paged.offset = 1;
+        paged.limit = Some(1);
+        assert_eq!(collect(paged), vec![(Bytes::from("d"), Bytes::from("new"))]);
+
+        let mut keys_only = options;
+        keys_only.keys_only = true;
+        keys_only.reverse = true;
+        let keys: Vec<Bytes> = collect(keys_only).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(
+            keys,
+            vec![Bytes::from("e"), Bytes::from("d"), Bytes::from("b")]
+        );
+
+        clean(dir_name);
+    }
 }
@@ -1,13 +1,49 @@
+#[cfg(feature = "alloc-stats")]
+pub mod alloc;
 mod batch;
+mod blob;
 mod data;
 pub mod db;
 pub mod errors;
+pub mod event;
+pub mod export;
 mod fio;
 mod index;
+mod ingest;
 pub mod iterator;
+pub mod manifest;
 mod merge;
+pub mod namespace;
 pub mod options;
 mod prelude;
+mod rate_limit;
+pub mod raw;
+pub mod replica;
+pub mod replication;
+mod restore;
+pub mod snapshot;
 mod stat;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+#[cfg(feature = "serde")]
+pub mod typed;
 mod utils;
-pub use batch::batch::*;
+pub mod verify;
+pub mod watch;
+
+// 稳定的公开接口, 使用者直接从根模块引入这些类型, 内部模块的结构调整不影响它们
+pub use batch::batch::WriteBatch;
+pub use data::log_record::{LogRecordPos, LogRecordType, ValueMeta};
+pub use db::Engine;
+pub use errors::{Errors, Result};
+pub use event::{EventListener, OpenProgress};
+pub use iterator::Iterator;
+pub use namespace::{Namespace, NamespaceStat};
+pub use options::{
+    ChecksumType, CompressionType, EngineOptions, IOType, IndexType, IteratorOptions, MergeOptions,
+    RateLimit, RecoveryMode, SyncPolicy, WriteBatchOptions,
+};
+pub use raw::{RawRecord, RawScan};
+pub use snapshot::Snapshot;
+pub use stat::{IndexStat, MemoryUsage, Stat, ThrottleStat};
+pub use verify::{RecoveryReport, VerifyReport};
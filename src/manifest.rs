@@ -0,0 +1,491 @@
+use crate::prelude::*;
+use std::{fs, path::Path};
+
+use bytes::{Buf, BufMut, BytesMut};
+use tracing::{info, warn};
+
+use crate::{
+    data::{
+        encryption::{RecordCipher, NONCE_SIZE},
+        MANIFEST_FILE_NAME,
+    },
+    db::{lock_file_with_wait, Engine, FILE_LOCK_NAME},
+    options::{ChecksumType, CompressionType, EngineOptions, IndexType},
+    utils,
+};
+
+/// 当前的文件格式版本, 修改`LogRecord`等磁盘上的编码时加1, 并在`UPGRADES`中增加升级函数
+pub const FORMAT_VERSION: u32 = 3;
+/// 从这个版本开始清单中记录数据的校验方式
+const CHECKSUM_FORMAT_VERSION: u32 = 3;
+
+const MANIFEST_MAGIC: &[u8] = b"LDBM";
+/// magic + 版本号 + 索引类型 + 校验方式 + 配置指纹 + crc
+/// 加密的数据库在 crc 前面还有密钥标记, merge 过的数据库在 crc 前面还有 merge 的结果
+const MANIFEST_SIZE: usize = 4 + 4 + 1 + 1 + 4 + CRC_SIZE;
+/// 版本3之前的清单没有校验方式
+const LEGACY_MANIFEST_SIZE: usize = MANIFEST_SIZE - 1;
+/// 用密钥加密这段内容作为标记, 打开时能解密说明密钥正确
+const KEY_MARKER_PLAINTEXT: &[u8] = b"lucasdb-encryption-key";
+/// nonce + 密文 + tag
+const KEY_MARKER_SIZE: usize = NONCE_SIZE + KEY_MARKER_PLAINTEXT.len() + 16;
+/// 未参与merge的文件id + 最大的事务序列号
+const MERGE_INFO_SIZE: usize = 4 + 8;
+
+/// 把数据目录从版本`i + 1`升级到`i + 2`, 执行之前已经拿到了文件锁
+/// 升级函数需要能够重复执行, 中途失败时清单中还是旧的版本号
+type UpgradeFn = fn(&EngineOptions) -> Result<()>;
+
+const UPGRADES: &[UpgradeFn] = &[upgrade_record_timestamp, upgrade_checksum_type];
+
+/// 版本2在`LogRecord`的头部增加了时间戳, 旧数据没有设置`TIMESTAMP_FLAG`, 可以直接读取, 不需要改写
+/// 升级之后旧版本的程序不能再打开这个数据库
+fn upgrade_record_timestamp(_options: &EngineOptions) -> Result<()> {
+    Ok(())
+}
+
+/// 版本3在清单中记录数据的校验方式, 之前的数据都使用 crc32, 升级后保存的清单中就是 crc32
+fn upgrade_checksum_type(_options: &EngineOptions) -> Result<()> {
+    Ok(())
+}
+
+const _: () = assert!(UPGRADES.len() + 1 == FORMAT_VERSION as usize);
+
+/// 数据目录的清单, 第一次初始化时写入, 记录文件格式的版本和创建时的配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// 创建时使用的索引类型, 索引只在内存中, 之后可以修改
+    pub index_type: IndexType,
+    /// 数据文件中每条数据的校验方式, 创建之后不能修改
+    pub checksum: ChecksumType,
+    /// 影响磁盘数据的配置(数据文件大小、压缩方式)的指纹
+    pub options_fingerprint: u32,
+    /// 密钥标记, 没有加密时为空
+    pub key_marker: Option<Vec<u8>>,
+    /// 最近一次 merge 或生成 hint 文件的结果, 没有时为空
+    pub merge: Option<MergeInfo>,
+}
+
+/// merge 安装完成之后记录在清单中, 数据目录中不再保留 merge 完成的标识
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MergeInfo {
+    /// id 比它小的数据文件从 hint 文件加载索引, 为0时没有可用的 hint 文件
+    pub non_merge_fid: u32,
+    /// merge 时已经分配的最大事务序列号, 重启时从它之后分配
+    pub max_seq_no: usize,
+}
+
+impl Manifest {
+    fn new(format_version: u32, options: &EngineOptions) -> Result<Self> {
+        let key_marker = match RecordCipher::from_options(options) {
+            Some(cipher) => Some(new_key_marker(&cipher)?),
+            None => None,
+        };
+        // 之前的版本只支持 crc32
+        let checksum = match format_version {
+            v if v >= CHECKSUM_FORMAT_VERSION => options.checksum,
+            _ => ChecksumType::Crc32,
+        };
+        Ok(Manifest {
+            format_version,
+            index_type: options.index_type,
+            checksum,
+            options_fingerprint: options_fingerprint(options),
+            key_marker,
+            merge: None,
+        })
+    }
+
+    /// 读取数据目录中的清单, 文件不存在时返回 None
+    pub fn load(dir_path: &Path) -> Result<Option<Self>> {
+        let path = dir_path.join(MANIFEST_FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Self::decode(&fs::read(path)?).map(Some)
+    }
+
+    /// 先写临时文件并持久化再重命名, 不会留下写了一半的清单
+    pub(crate) fn save(&self, dir_path: &Path) -> Result<()> {
+        let path = dir_path.join(MANIFEST_FILE_NAME);
+        let tmp_path = path.with_extension("tmp");
+        utils::file::write_file_atomic(&path, &tmp_path, &self.encode())?;
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(MANIFEST_SIZE);
+        buf.put_slice(MANIFEST_MAGIC);
+        buf.put_u32(self.format_version);
+        buf.put_u8(match self.index_type {
+            IndexType::BTree => 0,
+            IndexType::SkipList => 1,
+            IndexType::Spill => 2,
+        });
+        if self.format_version >= CHECKSUM_FORMAT_VERSION {
+            buf.put_u8(match self.checksum {
+                ChecksumType::Crc32 => 0,
+                ChecksumType::XxHash64 => 1,
+                ChecksumType::None => 2,
+            });
+        }
+        buf.put_u32(self.options_fingerprint);
+        if let Some(key_marker) = &self.key_marker {
+            buf.put_slice(key_marker);
+        }
+        if let Some(merge) = &self.merge {
+            buf.put_u32(merge.non_merge_fid);
+            buf.put_u64(merge.max_seq_no as u64);
+        }
+        let crc = crc32fast::hash(&buf);
+        buf.put_u32(crc);
+        buf.to_vec()
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < LEGACY_MANIFEST_SIZE || !buf.starts_with(MANIFEST_MAGIC) {
+            return Err(Errors::InvalidManifest(
+                "unexpected size or magic".to_string(),
+            ));
+        }
+        let (content, mut crc) = buf.split_at(buf.len() - CRC_SIZE);
+        if crc32fast::hash(content) != crc.get_u32() {
+            return Err(Errors::InvalidManifest("crc mismatch".to_string()));
+        }
+
+        let mut content = &content[MANIFEST_MAGIC.len()..];
+        let format_version = content.get_u32();
+        let index_type = match content.get_u8() {
+            0 => IndexType::BTree,
+            1 => IndexType::SkipList,
+            2 => IndexType::Spill,
+            v => return Err(Errors::InvalidManifest(format!("unknown index type {}", v))),
+        };
+        let checksum = match format_version {
+            v if v < CHECKSUM_FORMAT_VERSION => ChecksumType::Crc32,
+            _ if buf.len() < MANIFEST_SIZE => {
+                return Err(Errors::InvalidManifest(format!(
+                    "unexpected size {}",
+                    buf.len()
+                )))
+            }
+            _ => match content.get_u8() {
+                0 => ChecksumType::Crc32,
+                1 => ChecksumType::XxHash64,
+                2 => ChecksumType::None,
+                v => {
+                    return Err(Errors::InvalidManifest(format!(
+                        "unknown checksum type {}",
+                        v
+                    )))
+                }
+            },
+        };
+        let options_fingerprint = content.get_u32();
+        // 密钥标记和 merge 的结果都是固定长度, 根据剩余的长度区分
+        let key_marker_size = match content.len() {
+            0 | MERGE_INFO_SIZE => 0,
+            _ => KEY_MARKER_SIZE,
+        };
+        if content.len() != key_marker_size && content.len() != key_marker_size + MERGE_INFO_SIZE {
+            return Err(Errors::InvalidManifest(format!(
+                "unexpected size {}",
+                buf.len()
+            )));
+        }
+        let key_marker = (key_marker_size > 0).then(|| content[..key_marker_size].to_vec());
+        content.advance(key_marker_size);
+        let merge = (!content.is_empty()).then(|| MergeInfo {
+            non_merge_fid: content.get_u32(),
+            max_seq_no: content.get_u64() as usize,
+        });
+        Ok(Manifest {
+            format_version,
+            index_type,
+            checksum,
+            options_fingerprint,
+            key_marker,
+            merge,
+        })
+    }
+}
+
+fn options_fingerprint(options: &EngineOptions) -> u32 {
+    let mut buf = BytesMut::new();
+    buf.put_u64(options.data_file_size);
+    buf.put_u8(match options.compression {
+        CompressionType::None => 0,
+        CompressionType::Lz4 => 1,
+        CompressionType::Zstd => 2,
+    });
+    crc32fast::hash(&buf)
+}
+
+fn new_key_marker(cipher: &RecordCipher) -> Result<Vec<u8>> {
+    cipher.encrypt(KEY_MARKER_PLAINTEXT, MANIFEST_MAGIC)
+}
+
+/// 打开数据库时检查文件格式和密钥, 持有文件锁时调用
+/// 没有清单的数据库是加入清单之前创建的, 格式和版本1相同, 补写一个
+pub(crate) fn check_manifest(options: &EngineOptions, is_initial: bool) -> Result<Manifest> {
+    let mut manifest = match Manifest::load(&options.dir_path)? {
+        Some(manifest) => manifest,
+        None => {
+            let version = if is_initial { FORMAT_VERSION } else { 1 };
+            let manifest = Manifest::new(version, options)?;
+            if !options.read_only {
+                manifest.save(&options.dir_path)?;
+            }
+            manifest
+        }
+    };
+
+    if manifest.format_version > FORMAT_VERSION {
+        return Err(Errors::UnsupportedFormatVersion {
+            version: manifest.format_version,
+            supported: FORMAT_VERSION,
+        });
+    }
+    if manifest.format_version < FORMAT_VERSION {
+        return Err(Errors::UpgradeRequired {
+            version: manifest.format_version,
+            current: FORMAT_VERSION,
+        });
+    }
+    if manifest.options_fingerprint != options_fingerprint(options) {
+        warn!("data_file_size or compression differs from the options used to create the database");
+    }
+    if manifest.checksum != options.checksum {
+        warn!(
+            "checksum differs from the one used to create the database, use {:?} in the manifest",
+            manifest.checksum
+        );
+    }
+
+    match (&manifest.key_marker, RecordCipher::from_options(options)) {
+        (Some(key_marker), Some(cipher)) => {
+            let plaintext = cipher.decrypt(key_marker, MANIFEST_MAGIC).ok();
+            if plaintext.as_deref() != Some(KEY_MARKER_PLAINTEXT) {
+                return Err(Errors::InvalidEncryptionKey);
+            }
+        }
+        (Some(_), None) => return Err(Errors::EncryptionKeyRequired),
+        // 给没有加密的数据库设置密钥, 之前的数据保持明文, 之后写入和 merge 的数据会加密
+        (None, Some(cipher)) => {
+            if !options.read_only {
+                manifest.key_marker = Some(new_key_marker(&cipher)?);
+                manifest.save(&options.dir_path)?;
+            }
+        }
+        (None, None) => {}
+    }
+    Ok(manifest)
+}
+
+impl Engine {
+    /// 把数据目录升级到当前的文件格式版本, 返回升级后的版本号
+    /// 需要在打开数据库之前调用, 数据库被其他进程打开时返回`DatabaseIsUsing`
+    pub fn upgrade(options: EngineOptions) -> Result<u32> {
+        let file_lock = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(options.dir_path.join(FILE_LOCK_NAME))?;
+        if !lock_file_with_wait(&file_lock, true, options.lock_wait) {
+            return Err(Errors::DatabaseIsUsing);
+        }
+
+        let mut manifest = match Manifest::load(&options.dir_path)? {
+            Some(manifest) => manifest,
+            None => Manifest::new(1, &options)?,
+        };
+        if manifest.format_version > FORMAT_VERSION {
+            return Err(Errors::UnsupportedFormatVersion {
+                version: manifest.format_version,
+                supported: FORMAT_VERSION,
+            });
+        }
+
+        // 每升级一个版本就保存一次, 中途失败时下次从失败的版本继续
+        while manifest.format_version < FORMAT_VERSION {
+            let from = manifest.format_version;
+            UPGRADES[from as usize - 1](&options)?;
+            manifest.format_version = from + 1;
+            manifest.save(&options.dir_path)?;
+            info!(
+                "upgraded database format from version {} to {}",
+                from,
+                from + 1
+            );
+        }
+        manifest.save(&options.dir_path)?;
+        Ok(manifest.format_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/manifest".into()
+    }
+
+    fn setup(name: &str) -> EngineOptions {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_manifest_encode_decode() {
+        let manifest = Manifest::new(FORMAT_VERSION, &EngineOptions::default()).unwrap();
+        let buf = manifest.encode();
+        assert_eq!(buf.len(), MANIFEST_SIZE);
+        assert_eq!(Manifest::decode(&buf).unwrap(), manifest);
+
+        let mut opts = EngineOptions::default();
+        opts.encryption_key = Some([3; 32]);
+        let encrypted = Manifest::new(FORMAT_VERSION, &opts).unwrap();
+        assert!(encrypted.key_marker.is_some());
+        assert_eq!(Manifest::decode(&encrypted.encode()).unwrap(), encrypted);
+
+        // merge 的结果跟在密钥标记后面, 有没有加密都能解码
+        let merge = Some(MergeInfo {
+            non_merge_fid: 7,
+            max_seq_no: 42,
+        });
+        for mut m in [manifest.clone(), encrypted] {
+            m.merge = merge;
+            assert_eq!(Manifest::decode(&m.encode()).unwrap(), m);
+        }
+
+        // 版本3之前没有校验方式, 都是 crc32
+        let mut opts = EngineOptions::default();
+        opts.checksum = ChecksumType::XxHash64;
+        let legacy = Manifest::new(2, &opts).unwrap();
+        assert_eq!(legacy.checksum, ChecksumType::Crc32);
+        assert_eq!(legacy.encode().len(), LEGACY_MANIFEST_SIZE);
+        assert_eq!(Manifest::decode(&legacy.encode()).unwrap(), legacy);
+        let xxhash = Manifest::new(FORMAT_VERSION, &opts).unwrap();
+        assert_eq!(xxhash.checksum, ChecksumType::XxHash64);
+        assert_eq!(Manifest::decode(&xxhash.encode()).unwrap(), xxhash);
+
+        let mut corrupted = buf.clone();
+        corrupted[5] ^= 0xff;
+        assert!(matches!(
+            Manifest::decode(&corrupted),
+            Err(Errors::InvalidManifest(_))
+        ));
+    }
+
+    #[test]
+    fn test_manifest_written_and_checked() {
+        let name = "check";
+        let opts = setup(name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("k"), Bytes::from("v")).unwrap();
+        drop(engine);
+
+        let manifest = Manifest::load(&opts.dir_path).unwrap().unwrap();
+        assert_eq!(manifest.format_version, FORMAT_VERSION);
+        assert_eq!(manifest.index_type, opts.index_type);
+
+        // 加入清单之前创建的数据库, 打开时补写版本1的清单, 需要先升级
+        fs::remove_file(opts.dir_path.join(MANIFEST_FILE_NAME)).unwrap();
+        assert!(matches!(
+            Engine::open(opts.clone()),
+            Err(Errors::UpgradeRequired {
+                version: 1,
+                current: FORMAT_VERSION
+            })
+        ));
+        assert_eq!(Engine::upgrade(opts.clone()).unwrap(), FORMAT_VERSION);
+        let engine = Engine::open(opts.clone()).expect("failed to open legacy engine");
+        assert_eq!(engine.get(Bytes::from("k")).unwrap(), Bytes::from("v"));
+        drop(engine);
+        assert!(Manifest::load(&opts.dir_path).unwrap().is_some());
+
+        // 更新的版本不能打开
+        let mut newer = manifest.clone();
+        newer.format_version = FORMAT_VERSION + 1;
+        newer.save(&opts.dir_path).unwrap();
+        assert!(matches!(
+            Engine::open(opts.clone()),
+            Err(Errors::UnsupportedFormatVersion { .. })
+        ));
+        assert!(matches!(
+            Engine::upgrade(opts.clone()),
+            Err(Errors::UnsupportedFormatVersion { .. })
+        ));
+
+        manifest.save(&opts.dir_path).unwrap();
+        assert_eq!(Engine::upgrade(opts.clone()).unwrap(), FORMAT_VERSION);
+        assert!(Engine::open(opts).is_ok());
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_manifest_checksum_type() {
+        let name = "checksum";
+        let mut opts = setup(name);
+        opts.checksum = ChecksumType::XxHash64;
+        opts.data_file_size = 64;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        drop(engine);
+        let manifest = Manifest::load(&opts.dir_path).unwrap().unwrap();
+        assert_eq!(manifest.checksum, ChecksumType::XxHash64);
+
+        // 之后打开时使用清单中的校验方式, 配置中的被忽略
+        for checksum in [ChecksumType::Crc32, ChecksumType::None] {
+            let mut reopen = opts.clone();
+            reopen.checksum = checksum;
+            let engine = Engine::open(reopen).expect("failed to reopen engine");
+            assert_eq!(engine.options.checksum, ChecksumType::XxHash64);
+            for i in 0..10 {
+                assert_eq!(
+                    engine.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                    Bytes::from("value")
+                );
+            }
+            engine
+                .put(Bytes::from("new"), Bytes::from("value"))
+                .unwrap();
+        }
+
+        // merge 之后的数据也使用同样的校验方式
+        let mut reopen = opts.clone();
+        reopen.data_file_merge_ratio = 0.0;
+        let engine = Engine::open(reopen.clone()).expect("failed to reopen engine");
+        engine.merge().unwrap();
+        drop(engine);
+        let engine = Engine::open(reopen).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("key-9")).unwrap(),
+            Bytes::from("value")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("new")).unwrap(),
+            Bytes::from("value")
+        );
+
+        clean(name);
+    }
+}
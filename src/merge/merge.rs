@@ -1,30 +1,53 @@
-use std::sync::atomic::Ordering;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::atomic::Ordering,
+};
 
 use crate::{
     batch::{log_record_key_with_seq, parse_log_record_key},
     data::{
         data_file::DataFile,
         log_record::{LogRecord, LogRecordPos, LogRecordType},
-        HINT_FILE_NAME,
+        HINT_FILE_NAME, MANIFEST_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
     },
-    db::Engine,
+    db::{new_active_file, older_file_io_type, sync_active_file, Engine},
     fio::IOType,
-    merge::{get_merge_path, MERGE_FIN_KEY},
-    options::EngineOptions,
+    manifest::MergeInfo,
+    merge::{
+        get_merge_path, load_merge_info, save_merge_info, MergeManifest, HINT_TMP_DIR_NAME,
+        PARTIAL_MERGE_FIN_KEY,
+    },
+    options::{EngineOptions, IteratorOptions, MergeOptions},
     prelude::*,
     utils,
 };
+use tracing::{field::Empty, instrument, Span};
+
+/// 加载 hint 文件时每次批量写入索引的数量, 整个 hint 文件可能很大, 分批写入
+const HINT_INDEX_BATCH_SIZE: usize = 4096;
 
 impl Engine {
+    #[instrument(skip_all, fields(reclaim_size = Empty, elapsed_us = Empty))]
     pub fn merge(&self) -> Result<()> {
+        let _timer = utils::trace::span_timer();
+        self.check_writable()?;
+        self.check_persistent("merge")?;
         let lock = self.merging_lock.try_lock();
         if lock.is_none() {
             return Err(Errors::MergeInProgress);
         }
+        self.run_merge(|| self.merge_all_files())
+    }
 
+    /// 合并所有旧的数据文件, 调用时需要持有`merging_lock`
+    fn merge_all_files(&self) -> Result<()> {
         // 判断是否达到阈值,达到了才需要merge
         let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
-        let total_size = utils::file::dir_disk_size(&self.options.dir_path);
+        Span::current().record("reclaim_size", reclaim_size);
+        // 大`value`在 blob 文件中, 只按数据文件计算无效数据的占比
+        let total_size = utils::file::dir_disk_size(&self.options.dir_path)
+            .saturating_sub(self.blob_files.read().size()?);
         let cur_ratio = reclaim_size as f32 / total_size as f32;
         if cur_ratio < self.options.data_file_merge_ratio {
             return Err(Errors::MergeRatioUnreached {
@@ -44,25 +67,44 @@ impl Engine {
         }
 
         // 获取merge的临时目录
-        let merge_path = get_merge_path(self.options.dir_path.clone());
-
-        // 删除原来的
-        if merge_path.is_dir() {
-            std::fs::remove_dir_all(&merge_path).unwrap();
+        let merge_path = self.reset_merge_path()?;
+        let max_db_size = self.options.max_db_size_bytes;
+
+        // merge 的临时目录也会占用空间, 判断是否会超过数据库大小的上限
+        if let Some(max_size) = max_db_size {
+            let merged_size = total_size.saturating_sub(reclaim_size as u64);
+            if self.disk_size.load(Ordering::SeqCst) + merged_size > max_size {
+                self.db_full_count.fetch_add(1, Ordering::SeqCst);
+                return Err(Errors::DatabaseFull);
+            }
         }
 
         std::fs::create_dir_all(&merge_path)?;
-        // 获取需要merge的文件
-        let merge_files = self.rotate_merge_files()?;
+        // 获取需要merge的文件, 以及这时已经分配的最大事务序列号
+        let (merge_files, max_seq_no) = self.rotate_merge_files()?;
+        // 比 non_merge_file_id 小的id都会完成merge
+        let non_merge_file_id = match merge_files.last() {
+            Some(data_file) => data_file.get_file_id() + 1,
+            None => {
+                std::fs::remove_dir_all(&merge_path)?;
+                return Ok(());
+            }
+        };
 
         // 在merge_path上新建一个数据库实例
         let mut merge_db_opts = EngineOptions::default();
         merge_db_opts.dir_path = merge_path.clone();
         merge_db_opts.data_file_size = self.options.data_file_size;
+        // merge 之后的数据使用相同的压缩方式
+        merge_db_opts.compression = self.options.compression;
+        merge_db_opts.checksum = self.options.checksum;
+        merge_db_opts.encryption_key = self.options.encryption_key;
         let merge_db = Engine::open(merge_db_opts)?;
 
         // 打开hint文件,存储索引
         let hint_file = DataFile::new_hint_file(merge_path.clone())?;
+        // 无效数据较多的 blob 文件, 把其中有效的 blob 复制到新文件
+        let mut blob_rewriter = self.new_blob_rewriter(&merge_files, &merge_path)?;
 
         // 处理每个数据文件,重写有效数据
         for data_file in merge_files.iter() {
@@ -75,15 +117,21 @@ impl Engine {
                         _ => return Err(e),
                     },
                 };
+                self.throttle_merge(size);
 
                 // 解码,拿到实际的key
                 let (real_key, _) = parse_log_record_key(log_record.key.clone())?;
-                if let Some(index_pos) = self.index.get(real_key.clone()) {
+                if let Some(index_pos) = self.index.get(real_key.clone())? {
                     // 有效数据,重写
                     if index_pos.file_id == data_file.get_file_id() && index_pos.offset == offset {
                         // 去除事务标识
                         log_record.key =
                             log_record_key_with_seq(real_key.clone(), NON_TRANSACTION_SEQ_NO)?;
+                        if log_record.rec_type == LogRecordType::BlobIndex {
+                            if let Some(value) = blob_rewriter.rewrite(&log_record.value)? {
+                                log_record.value = value;
+                            }
+                        }
                         let log_record_pos = merge_db.append_log_record(&mut log_record)?;
                         // 写hint索引
                         hint_file.write_hint_record(real_key.clone(), log_record_pos)?;
@@ -94,56 +142,277 @@ impl Engine {
         }
 
         // 持久化
+        blob_rewriter.finish()?;
+        blob_rewriter.write_gc_file()?;
         merge_db.sync()?;
         hint_file.sync()?;
 
         // 标识merge全部完成
-        // 拿到最近未参与merge的文件id
-        // todo: 这里用了unwrap,有风险
-        // 比 non_merge_file_id 小的id都已经完成了merge
-        let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
+        MergeManifest {
+            non_merge_fid: non_merge_file_id,
+            max_seq_no,
+            output_fids: merge_db.data_file_ids(),
+        }
+        .save(&merge_path)?;
+
+        // 重启之前, merge 的临时目录会一直占用空间
+        if max_db_size.is_some() {
+            let merge_size = utils::file::dir_disk_size(&merge_path);
+            self.disk_size.fetch_add(merge_size, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// 只merge部分数据文件, 避免只有少数文件有大量无效数据时重写整个数据库
+    /// 被选中的文件在下次启动时替换, 之后会从数据文件加载索引, 可以调用`build_hint_file`重新生成hint文件
+    #[instrument(skip_all, fields(files = Empty, elapsed_us = Empty))]
+    pub fn merge_with(&self, options: MergeOptions) -> Result<()> {
+        let _timer = utils::trace::span_timer();
+        self.check_writable()?;
+        self.check_persistent("merge")?;
+        if options.max_files.is_none() && options.min_garbage_ratio.is_none() {
+            return self.merge();
+        }
+
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+        self.run_merge(|| self.merge_selected_files(&options))
+    }
+
+    /// 合并按`options`选中的数据文件, 调用时需要持有`merging_lock`
+    fn merge_selected_files(&self, options: &MergeOptions) -> Result<()> {
+        let file_ids = self.select_merge_files(options)?;
+        Span::current().record("files", file_ids.len());
+        if file_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut selected_size = 0;
+        {
+            let older_files = self.older_files.read();
+            for file_id in file_ids.iter() {
+                selected_size += older_files[file_id].file_size()?;
+            }
+        }
+
+        // 判断磁盘容量剩余空间是否足够容纳merge之后的数据
+        let available_size = utils::file::available_disk_size();
+        if selected_size >= available_size {
+            return Err(Errors::MergeSpaceNotEnough {
+                actual: available_size,
+                expected: selected_size,
+            });
+        }
+
+        let merge_path = self.reset_merge_path()?;
+        let max_db_size = self.options.max_db_size_bytes;
+        if let Some(max_size) = max_db_size {
+            if self.disk_size.load(Ordering::SeqCst) + selected_size > max_size {
+                self.db_full_count.fetch_add(1, Ordering::SeqCst);
+                return Err(Errors::DatabaseFull);
+            }
+        }
+        std::fs::create_dir_all(&merge_path)?;
+
+        // 在活跃文件后面给merge的输出文件预留id, 按文件id加载时, merge之后的数据排在所有旧数据后面、新写入的数据前面
+        // 有效数据不会超过选中文件的大小, 按顺序装进数据文件最多需要 2N+1 个文件
+        // 开启加密或者修改压缩方式之后输出可能比输入大, 超过预留的数量时放弃这次merge
+        let reserved = file_ids.len() as u32 * 2 + 1;
+        let (merge_files, output_base_fid, unselected_file_ids) = {
+            // 保证轮转之前的事务都已经提交完成
+            let _batch_lock = self.batch_commit_lock.lock();
+            let mut older_files = self.older_files.write();
+            let output_base_fid = self.rotate_active_file(&mut older_files, reserved)? + 1;
+
+            let mut merge_files = vec![];
+            for file_id in file_ids.iter() {
+                merge_files.push(
+                    DataFile::new(
+                        self.options.dir_path.clone(),
+                        *file_id,
+                        IOType::StandardFileIO,
+                    )?
+                    .with_cipher(self.cipher.clone())
+                    .with_checksum(self.options.checksum),
+                );
+            }
+            let unselected_file_ids: Vec<u32> = older_files
+                .keys()
+                .copied()
+                .filter(|file_id| !file_ids.contains(file_id))
+                .collect();
+            (merge_files, output_base_fid, unselected_file_ids)
+        };
+        let aborted_txn_seqs = self.aborted_txn_seqs.lock().clone();
+
+        let mut merge_db_opts = EngineOptions::default();
+        merge_db_opts.dir_path = merge_path.clone();
+        merge_db_opts.data_file_size = self.options.data_file_size;
+        merge_db_opts.compression = self.options.compression;
+        merge_db_opts.checksum = self.options.checksum;
+        merge_db_opts.encryption_key = self.options.encryption_key;
+        let merge_db = Engine::open(merge_db_opts)?;
+
+        let res = self.rewrite_selected_files(
+            &merge_db,
+            &merge_files,
+            &unselected_file_ids,
+            &aborted_txn_seqs,
+            reserved,
+        );
+        if let Err(e) = res {
+            // 输出放不进预留的id时, 这次merge的结果不能使用
+            if matches!(e, Errors::MergeOutputTooLarge { .. }) {
+                drop(merge_db);
+                std::fs::remove_dir_all(&merge_path)?;
+            }
+            return Err(e);
+        }
+        merge_db.sync()?;
+
+        // 标识merge全部完成
+        let merged_fids: Vec<String> = file_ids.iter().map(|id| id.to_string()).collect();
         let merge_fin_file = DataFile::new_merge_fin_file(merge_path.clone())?;
         let merge_fin_record = LogRecord {
-            key: MERGE_FIN_KEY.to_vec(),
-            value: non_merge_file_id.to_string().into_bytes(),
+            key: PARTIAL_MERGE_FIN_KEY.to_vec(),
+            value: format!("{}:{}:{}", output_base_fid, reserved, merged_fids.join(","))
+                .into_bytes(),
             rec_type: LogRecordType::Normal,
+            timestamp: 0,
         };
-
-        let encode_record = merge_fin_record.encode()?;
-        merge_fin_file.write(&encode_record)?;
+        merge_fin_file.write(&merge_fin_record.encode()?)?;
         merge_fin_file.sync()?;
 
+        if max_db_size.is_some() {
+            let merge_size = utils::file::dir_disk_size(&merge_path);
+            self.disk_size.fetch_add(merge_size, Ordering::SeqCst);
+        }
+
         Ok(())
     }
 
-    /// 拿到需要merge的文件
-    fn rotate_merge_files(&self) -> Result<Vec<DataFile>> {
-        let mut merge_file_ids = vec![];
-        let mut older_files = self.older_files.write();
+    /// 把选中文件中的有效数据重写到`merge_db`, 输出的文件数量超过`reserved`时返回`Errors::MergeOutputTooLarge`
+    fn rewrite_selected_files(
+        &self,
+        merge_db: &Engine,
+        merge_files: &[DataFile],
+        unselected_file_ids: &[u32],
+        aborted_txn_seqs: &HashSet<usize>,
+        reserved: u32,
+    ) -> Result<()> {
+        // merge 目录中的数据文件从0开始编号, 加载时加上输出文件的起始id
+        let append = |log_record: &mut LogRecord| -> Result<()> {
+            merge_db.append_log_record(log_record)?;
+            if merge_db.active_file.read().get_file_id() >= reserved {
+                return Err(Errors::MergeOutputTooLarge { reserved });
+            }
+            Ok(())
+        };
+
+        for data_file in merge_files.iter() {
+            let file_id = data_file.get_file_id();
+            // 更早的文件没有参与merge时, 要保留删除标识, 否则那些文件中的旧数据会重新生效
+            let keep_deleted = unselected_file_ids.iter().any(|id| *id < file_id);
 
-        for fid in older_files.keys() {
-            merge_file_ids.push(*fid);
+            let mut offset = 0;
+            loop {
+                let (mut log_record, size) = match data_file.read_log_record(offset) {
+                    Ok(result) => (result.record, result.size),
+                    Err(e) => match e {
+                        Errors::ReadDataFileEOF => break,
+                        _ => return Err(e),
+                    },
+                };
+                self.throttle_merge(size);
+
+                let (real_key, seq_no) = parse_log_record_key(log_record.key.clone())?;
+                let rewrite = match log_record.rec_type {
+                    LogRecordType::Normal | LogRecordType::BlobIndex => self
+                        .index
+                        .get(real_key.clone())?
+                        .is_some_and(|pos| pos.file_id == file_id && pos.offset == offset),
+                    LogRecordType::Deleted => {
+                        keep_deleted
+                            && !aborted_txn_seqs.contains(&seq_no)
+                            && self.index.get(real_key.clone())?.is_none()
+                    }
+                    // 事务的数据可能在没有参与merge的文件中, 保留完成标识
+                    LogRecordType::TxnFinished => {
+                        append(&mut log_record)?;
+                        false
+                    }
+                };
+
+                if rewrite {
+                    // 去除事务标识
+                    log_record.key = log_record_key_with_seq(real_key, NON_TRANSACTION_SEQ_NO)?;
+                    append(&mut log_record)?;
+                }
+                offset += size as u64;
+            }
         }
+        Ok(())
+    }
 
-        // 设置一个新的活跃文件用于写入
-        let mut active_file = self.active_file.write();
-        active_file.sync()?;
-        let active_file_id = active_file.get_file_id();
-        let new_active_file = DataFile::new(
-            self.options.dir_path.clone(),
-            active_file_id + 1,
-            IOType::StandardFileIO,
-        )?;
-        *active_file = new_active_file;
+    /// merge 每读取一条数据按读取的大小限速, 重写的数据不会超过读取的数据
+    fn throttle_merge(&self, size: usize) {
+        if let Some(limiter) = &self.rate_limiters.merge {
+            limiter.request(size);
+        }
+    }
 
-        // 加到旧的数据文件中
-        let old_file = DataFile::new(
-            self.options.dir_path.clone(),
-            active_file_id,
-            IOType::StandardFileIO,
-        )?;
-        older_files.insert(active_file_id, old_file);
-        merge_file_ids.push(active_file_id);
+    /// 挑选部分merge的文件, 按文件id从小到大排列
+    fn select_merge_files(&self, options: &MergeOptions) -> Result<Vec<u32>> {
+        let older_files = self.older_files.read();
+        let mut file_ids: Vec<u32> = older_files.keys().copied().collect();
+        file_ids.sort();
+
+        if let Some(ratio) = options.min_garbage_ratio {
+            let file_reclaim_size = self.file_reclaim_size.lock();
+            let mut selected = vec![];
+            for file_id in file_ids {
+                let file_size = older_files[&file_id].file_size()?;
+                let reclaim_size = file_reclaim_size.get(&file_id).copied().unwrap_or(0);
+                if file_size > 0 && reclaim_size as f32 / file_size as f32 >= ratio {
+                    selected.push(file_id);
+                }
+            }
+            file_ids = selected;
+        }
+
+        if let Some(max_files) = options.max_files {
+            file_ids.truncate(max_files);
+        }
+
+        Ok(file_ids)
+    }
+
+    /// 删除上一次merge留下的临时目录
+    fn reset_merge_path(&self) -> Result<PathBuf> {
+        let merge_path = get_merge_path(self.options.dir_path.clone());
+        if merge_path.is_dir() {
+            if self.options.max_db_size_bytes.is_some() {
+                let old_merge_size = utils::file::dir_disk_size(&merge_path);
+                self.disk_size.fetch_sub(old_merge_size, Ordering::SeqCst);
+            }
+            std::fs::remove_dir_all(&merge_path)?;
+        }
+        Ok(merge_path)
+    }
+
+    /// 拿到需要merge的文件
+    /// 切换活跃文件, 返回需要merge的文件和已经分配的最大事务序列号
+    /// 持有事务提交的锁, 提交了一半的事务不会一部分在merge的文件中, 一部分在新的活跃文件中
+    fn rotate_merge_files(&self) -> Result<(Vec<DataFile>, usize)> {
+        let _batch_lock = self.batch_commit_lock.lock();
+        let mut older_files = self.older_files.write();
+        self.rotate_active_file(&mut older_files, 0)?;
+        let max_seq_no = self.seq_no.load(Ordering::SeqCst).saturating_sub(1);
+
+        let mut merge_file_ids: Vec<u32> = older_files.keys().copied().collect();
 
         // 从小到大排序，依次merge
         merge_file_ids.sort();
@@ -155,22 +424,157 @@ impl Engine {
                 self.options.dir_path.clone(),
                 *file_id,
                 IOType::StandardFileIO,
-            )?;
+            )?
+            .with_cipher(self.cipher.clone())
+            .with_checksum(self.options.checksum);
             merge_files.push(data_file);
         }
 
-        Ok(merge_files)
+        Ok((merge_files, max_seq_no))
+    }
+
+    /// 设置一个新的活跃文件用于写入, 原来的活跃文件加到旧的数据文件中
+    /// 新活跃文件的id前面空出`reserved`个id, 返回原来的活跃文件id
+    pub(crate) fn rotate_active_file(
+        &self,
+        older_files: &mut HashMap<u32, DataFile>,
+        reserved: u32,
+    ) -> Result<u32> {
+        let mut active_file = self.active_file.write();
+        // blob 文件一起切换, 切换之前的数据文件只引用之前的 blob 文件
+        self.blob_files.write().rotate()?;
+        sync_active_file(&active_file, &self.blob_files.read(), self.event_listener())?;
+        let active_file_id = active_file.get_file_id();
+        let new_active_file = new_active_file(&self.options, active_file_id + 1 + reserved)?;
+        *active_file = new_active_file;
+
+        let old_file = DataFile::new(
+            self.options.dir_path.clone(),
+            active_file_id,
+            older_file_io_type(&self.options),
+        )?
+        .with_cipher(self.cipher.clone())
+        .with_checksum(self.options.checksum);
+        older_files.insert(active_file_id, old_file);
+        if let Some(listener) = self.event_listener() {
+            listener.on_file_rotate(active_file_id);
+        }
+        Ok(active_file_id)
+    }
+
+    /// 不执行merge, 直接根据当前的内存索引生成hint文件, 加快下次启动
+    /// 会切换一个新的活跃文件, 之前的数据文件下次启动时都从hint文件加载
+    pub fn build_hint_file(&self) -> Result<()> {
+        self.check_writable()?;
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+        // 防止拿到提交了一半的事务
+        let _batch_lock = self.batch_commit_lock.lock();
+
+        let non_merge_file_id = {
+            let mut older_files = self.older_files.write();
+            self.rotate_active_file(&mut older_files, 0)? + 1
+        };
+        self.write_hint_file(non_merge_file_id)
+    }
+
+    /// 删除数据目录中不再使用的文件, 返回删除的路径
+    /// 包括打开之后不再使用的序列号文件、清单中没有 merge 结果时的 hint 文件、崩溃时留下的临时文件和没有完成的 merge 目录
+    /// 已经完成、等待下次打开时安装的 merge 目录会保留
+    pub fn cleanup(&self) -> Result<Vec<PathBuf>> {
+        self.check_writable()?;
+        self.check_persistent("cleanup")?;
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+
+        let dir_path = &self.options.dir_path;
+        // 关闭时会重新写入序列号文件
+        let mut orphans = vec![
+            dir_path.join(SEQ_NO_FILE_NAME),
+            dir_path.join(HINT_TMP_DIR_NAME),
+            dir_path.join(MANIFEST_FILE_NAME).with_extension("tmp"),
+        ];
+        if !matches!(load_merge_info(dir_path)?, Some(merge) if merge.non_merge_fid > 0) {
+            orphans.push(dir_path.join(HINT_FILE_NAME));
+        }
+        let merge_path = get_merge_path(dir_path.clone());
+        if !merge_path.join(MERGE_FINISHED_FILE_NAME).is_file() {
+            orphans.push(merge_path);
+        }
+
+        let mut removed = Vec::new();
+        for path in orphans {
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            } else if path.is_file() {
+                std::fs::remove_file(&path)?;
+            } else {
+                continue;
+            }
+            removed.push(path);
+        }
+        Ok(removed)
+    }
+
+    /// 根据当前的内存索引生成hint文件, 下次启动时id小于`non_merge_file_id`的数据文件都从hint文件加载
+    /// 调用时需要持有`merging_lock`和`batch_commit_lock`
+    pub(crate) fn write_hint_file(&self, non_merge_file_id: u32) -> Result<()> {
+        // 先写到临时目录, 完成之后再替换, 防止写到一半崩溃
+        let dir_path = &self.options.dir_path;
+        let tmp_path = dir_path.join(HINT_TMP_DIR_NAME);
+        if tmp_path.is_dir() {
+            std::fs::remove_dir_all(&tmp_path)?;
+        }
+        std::fs::create_dir_all(&tmp_path)?;
+
+        let hint_file = DataFile::new_hint_file(tmp_path.clone())?;
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            hint_file.write_hint_record(key.clone(), *pos)?;
+        }
+        hint_file.sync()?;
+
+        // 先替换hint文件, 再更新清单中的 merge 结果
+        // 只替换了hint文件时, 启动时会从旧的结果开始重新加载数据文件, 结果依然正确
+        std::fs::rename(tmp_path.join(HINT_FILE_NAME), dir_path.join(HINT_FILE_NAME))?;
+        save_merge_info(
+            dir_path,
+            MergeInfo {
+                non_merge_fid: non_merge_file_id,
+                max_seq_no: self.seq_no.load(Ordering::SeqCst).saturating_sub(1),
+            },
+        )?;
+        std::fs::remove_dir_all(&tmp_path)?;
+
+        if self.options.max_db_size_bytes.is_some() {
+            let merge_path = get_merge_path(dir_path.clone());
+            let disk_size =
+                utils::file::dir_disk_size(dir_path) + utils::file::dir_disk_size(&merge_path);
+            self.disk_size.store(disk_size, Ordering::SeqCst);
+        }
+
+        Ok(())
     }
 
+    /// 只有清单中记录了 merge 的结果时才使用 hint 文件, 其他情况下残留的 hint 文件已经过期
     pub(crate) fn load_index_from_hint_file(&self) -> Result<()> {
         let hint_file_name = self.options.dir_path.join(HINT_FILE_NAME);
         if !hint_file_name.is_file() {
             return Ok(());
         }
+        match load_merge_info(&self.options.dir_path)? {
+            Some(merge) if merge.non_merge_fid > 0 => {}
+            _ => return Ok(()),
+        }
 
         let hint_file = DataFile::new_hint_file(self.options.dir_path.clone())?;
 
         let mut offset = 0;
+        let mut updates = Vec::new();
         loop {
             let (log_record, size) = match hint_file.read_log_record(offset) {
                 Ok(result) => (result.record, result.size),
@@ -181,10 +585,14 @@ impl Engine {
             };
             // 解码value,拿到位置索引
             let log_record_pos = LogRecordPos::decode(log_record.value)?;
-            self.index.put(log_record.key, log_record_pos);
+            updates.push((log_record.key, Some(log_record_pos)));
+            if updates.len() >= HINT_INDEX_BATCH_SIZE {
+                self.index.extend(std::mem::take(&mut updates))?;
+            }
 
             offset += size as u64
         }
+        self.index.extend(updates)?;
 
         Ok(())
     }
@@ -200,6 +608,8 @@ mod tests {
 
     use bytes::Bytes;
 
+    use crate::data::data_file::get_data_file_name;
+
     use super::*;
     fn basepath() -> PathBuf {
         "./tmp/merge".into()
@@ -528,4 +938,380 @@ mod tests {
 
         clean(name);
     }
+
+    #[test]
+    fn test_build_hint_file() {
+        let name = "build_hint";
+        let (db, opts) = setup(name);
+
+        for i in 0..1000 {
+            let (key, value) = get_test_kv(i);
+            db.put(key, value).unwrap();
+        }
+        for i in 0..100 {
+            let (key, _) = get_test_kv(i);
+            db.delete(key).unwrap();
+        }
+        {
+            let wb = db
+                .new_write_batch(crate::options::WriteBatchOptions::default())
+                .unwrap();
+            for i in 1000..1100 {
+                let (key, value) = get_test_kv(i);
+                wb.put(key, value).unwrap();
+            }
+            wb.commit().unwrap();
+        }
+
+        db.build_hint_file().unwrap();
+        assert!(opts.dir_path.join(HINT_FILE_NAME).is_file());
+        assert!(!opts.dir_path.join(MERGE_FINISHED_FILE_NAME).exists());
+        assert!(load_merge_info(&opts.dir_path).unwrap().is_some());
+        assert!(!opts.dir_path.join(HINT_TMP_DIR_NAME).exists());
+
+        // 生成hint文件之后的写入
+        for i in 200..300 {
+            let (key, _) = get_test_kv(i);
+            db.delete(key).unwrap();
+        }
+        let (key, value) = get_test_kv(2000);
+        db.put(key, value).unwrap();
+
+        // 重复生成会覆盖原来的hint文件
+        db.build_hint_file().unwrap();
+        let (key, value) = get_test_kv(2001);
+        db.put(key, value).unwrap();
+        std::mem::drop(db);
+
+        let db = Engine::open(opts.clone()).unwrap();
+        assert_eq!(db.list_keys().unwrap().len(), 1100 - 200 + 2);
+        for i in (100..200).chain(300..1100).chain(2000..2002) {
+            let (key, value) = get_test_kv(i);
+            assert_eq!(db.get(key).unwrap(), value);
+        }
+        for i in (0..100).chain(200..300) {
+            let (key, _) = get_test_kv(i);
+            assert!(db.get(key).is_err());
+        }
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_partial_merge() {
+        let name = "partial";
+        let (_, mut opts) = setup(name);
+        opts.data_file_size = 8 * 1024;
+        let db = Engine::open(opts.clone()).unwrap();
+
+        for i in 0..1000 {
+            let (key, value) = get_test_kv(i);
+            db.put(key, value).unwrap();
+        }
+        for i in 0..100 {
+            let (key, _) = get_test_kv(i);
+            db.delete(key).unwrap();
+        }
+        for i in 100..200 {
+            let (key, _) = get_test_kv(i);
+            db.put(key, Bytes::from("new value")).unwrap();
+        }
+
+        // 没有满足条件的文件
+        let before = utils::file::dir_disk_size(&opts.dir_path);
+        db.merge_with(MergeOptions::builder().min_garbage_ratio(2.0).build())
+            .unwrap();
+        assert!(!get_merge_path(opts.dir_path.clone()).exists());
+
+        // 只合并最旧的两个文件
+        db.merge_with(MergeOptions::builder().max_files(2).build())
+            .unwrap();
+        // 重启之前数据不变
+        let (key, value) = get_test_kv(300);
+        assert_eq!(db.get(key).unwrap(), value);
+        std::mem::drop(db);
+
+        let db = Engine::open(opts.clone()).unwrap();
+        assert!(utils::file::dir_disk_size(&opts.dir_path) < before);
+        assert_eq!(db.list_keys().unwrap().len(), 900);
+        for i in 0..100 {
+            let (key, _) = get_test_kv(i);
+            assert!(db.get(key).is_err());
+        }
+        for i in 100..200 {
+            let (key, _) = get_test_kv(i);
+            assert_eq!(db.get(key).unwrap(), Bytes::from("new value"));
+        }
+        for i in 200..1000 {
+            let (key, value) = get_test_kv(i);
+            assert_eq!(db.get(key).unwrap(), value);
+        }
+
+        // 删除标识所在的文件参与merge, 而更早的文件没有参与时, 删除标识要保留
+        for i in 500..510 {
+            let (key, _) = get_test_kv(i);
+            db.delete(key).unwrap();
+        }
+        for _ in 0..200 {
+            let (key, value) = get_test_kv(1000);
+            db.put(key, value).unwrap();
+        }
+        for i in 1001..1300 {
+            let (key, value) = get_test_kv(i);
+            db.put(key, value).unwrap();
+        }
+        db.merge_with(MergeOptions::builder().min_garbage_ratio(0.5).build())
+            .unwrap();
+        std::mem::drop(db);
+
+        let db = Engine::open(opts.clone()).unwrap();
+        assert_eq!(db.list_keys().unwrap().len(), 1190);
+        for i in (0..100).chain(500..510) {
+            let (key, _) = get_test_kv(i);
+            assert!(db.get(key).is_err());
+        }
+        for i in (200..500).chain(510..1300) {
+            let (key, value) = get_test_kv(i);
+            assert_eq!(db.get(key).unwrap(), value);
+        }
+
+        std::mem::drop(db);
+        clean(name);
+    }
+
+    #[test]
+    fn test_partial_merge_output_too_large() {
+        let name = "partial-too-large";
+        let (db, mut opts) = setup(name);
+        drop(db);
+        opts.data_file_size = 8 * 1024;
+        opts.compression = crate::options::CompressionType::Lz4;
+        let db = Engine::open(opts.clone()).unwrap();
+        for i in 0..300 {
+            let (key, _) = get_test_kv(i);
+            db.put(key, Bytes::from(vec![b'v'; 4096])).unwrap();
+        }
+        drop(db);
+
+        // 关闭压缩之后重写的数据比原来大很多, 放不进预留的文件id
+        opts.compression = crate::options::CompressionType::None;
+        let db = Engine::open(opts.clone()).unwrap();
+        let res = db.merge_with(MergeOptions::builder().max_files(1).build());
+        assert!(matches!(
+            res,
+            Err(Errors::MergeOutputTooLarge { reserved: 3 })
+        ));
+        assert!(!get_merge_path(opts.dir_path.clone()).exists());
+        drop(db);
+
+        let db = Engine::open(opts.clone()).unwrap();
+        assert_eq!(db.list_keys().unwrap().len(), 300);
+        for i in 0..300 {
+            let (key, _) = get_test_kv(i);
+            assert_eq!(db.get(key).unwrap().len(), 4096);
+        }
+
+        drop(db);
+        clean(name);
+    }
+
+    #[test]
+    fn test_partial_merge_invalid_finished_file() {
+        let name = "partial-invalid";
+        let (db, opts) = setup(name);
+        let (key, value) = get_test_kv(0);
+        db.put(key.clone(), value.clone()).unwrap();
+        drop(db);
+
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        let write_merge = |value: &str| {
+            let _ = std::fs::remove_dir_all(&merge_path);
+            std::fs::create_dir_all(&merge_path).unwrap();
+            std::fs::write(get_data_file_name(&merge_path, 0), b"").unwrap();
+            let merge_fin_file = DataFile::new_merge_fin_file(merge_path.clone()).unwrap();
+            let record = LogRecord {
+                key: PARTIAL_MERGE_FIN_KEY.to_vec(),
+                value: value.as_bytes().to_vec(),
+                rec_type: LogRecordType::Normal,
+                timestamp: 0,
+            };
+            merge_fin_file.write(&record.encode().unwrap()).unwrap();
+            merge_fin_file.sync().unwrap();
+        };
+
+        // 损坏的标识返回错误, 不会 panic
+        for value in ["", "x:1", "1:x:2", "1:2:x", "1"] {
+            write_merge(value);
+            assert!(matches!(
+                Engine::open(opts.clone()),
+                Err(Errors::InvalidMergeFinishedFile(_))
+            ));
+        }
+        // 超出预留的范围或者目标文件已经存在时不能重命名
+        for value in ["5:0:", "0:3:"] {
+            write_merge(value);
+            assert!(matches!(
+                Engine::open(opts.clone()),
+                Err(Errors::InvalidMergeFinishedFile(_))
+            ));
+        }
+
+        // 原来的数据没有被覆盖
+        std::fs::remove_dir_all(&merge_path).unwrap();
+        let db = Engine::open(opts.clone()).unwrap();
+        assert_eq!(db.get(key).unwrap(), value);
+
+        drop(db);
+        clean(name);
+    }
+
+    #[test]
+    fn test_merge_install_after_crash() {
+        let name = "install-crash";
+        let (_, mut opts) = setup(name);
+        opts.data_file_size = 8 * 1024;
+        let db = Engine::open(opts.clone()).unwrap();
+
+        for i in 0..1000 {
+            let (key, value) = get_test_kv(i);
+            db.put(key, value).unwrap();
+        }
+        for i in 0..500 {
+            let (key, _) = get_test_kv(i);
+            db.delete(key).unwrap();
+        }
+        {
+            let wb = db
+                .new_write_batch(crate::options::WriteBatchOptions::default())
+                .unwrap();
+            wb.put(Bytes::from("txn-key"), Bytes::from("txn-value"))
+                .unwrap();
+            wb.commit().unwrap();
+        }
+        let last_seq_no = db.seq_no.load(Ordering::SeqCst) - 1;
+        db.merge().unwrap();
+
+        // 清单记录了输出的文件和最大的事务序列号
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        let manifest = MergeManifest::load(&merge_path).unwrap().unwrap();
+        assert!(manifest.non_merge_fid > 1);
+        assert!(!manifest.output_fids.is_empty());
+        assert!(manifest.output_fids.len() < manifest.non_merge_fid as usize);
+        assert_eq!(manifest.max_seq_no, last_seq_no);
+        std::mem::drop(db);
+
+        // 模拟安装到一半时崩溃: 只移动了第一个输出文件, 也没有保存序列号
+        let _ = std::fs::remove_file(opts.dir_path.join(crate::data::SEQ_NO_FILE_NAME));
+        std::fs::rename(
+            get_data_file_name(&merge_path, manifest.output_fids[0]),
+            get_data_file_name(&opts.dir_path, manifest.output_fids[0]),
+        )
+        .unwrap();
+
+        let db = Engine::open(opts.clone()).unwrap();
+        assert!(!merge_path.exists());
+        assert!(!opts.dir_path.join(MERGE_FINISHED_FILE_NAME).exists());
+        let merge = load_merge_info(&opts.dir_path).unwrap().unwrap();
+        assert_eq!(merge.non_merge_fid, manifest.non_merge_fid);
+        assert_eq!(db.list_keys().unwrap().len(), 501);
+        for i in 500..1000 {
+            let (key, value) = get_test_kv(i);
+            assert_eq!(db.get(key).unwrap(), value);
+        }
+        assert_eq!(
+            db.get(Bytes::from("txn-key")).unwrap(),
+            Bytes::from("txn-value")
+        );
+        // 序列号从清单中恢复, 不会复用merge之前的事务序列号
+        assert!(db.seq_no.load(Ordering::SeqCst) > last_seq_no);
+        std::mem::drop(db);
+
+        let _ = std::fs::remove_file(opts.dir_path.join(crate::data::SEQ_NO_FILE_NAME));
+        let db = Engine::open(opts.clone()).unwrap();
+        assert!(db.seq_no.load(Ordering::SeqCst) > last_seq_no);
+        assert_eq!(db.list_keys().unwrap().len(), 501);
+
+        std::mem::drop(db);
+        clean(name);
+    }
+
+    #[test]
+    fn test_fold_legacy_merge_finished_file() {
+        let name = "legacy-merge-fin";
+        let (db, opts) = setup(name);
+        for i in 0..1000 {
+            let (key, value) = get_test_kv(i);
+            db.put(key, value).unwrap();
+        }
+        db.build_hint_file().unwrap();
+        let merge = load_merge_info(&opts.dir_path).unwrap().unwrap();
+        std::mem::drop(db);
+
+        // 旧版本把 merge 完成的标识留在数据目录中, 清单中没有 merge 的结果
+        let mut manifest = crate::manifest::Manifest::load(&opts.dir_path)
+            .unwrap()
+            .unwrap();
+        manifest.merge = None;
+        manifest.save(&opts.dir_path).unwrap();
+        MergeManifest {
+            non_merge_fid: merge.non_merge_fid,
+            max_seq_no: merge.max_seq_no,
+            output_fids: Vec::new(),
+        }
+        .save(&opts.dir_path)
+        .unwrap();
+
+        let db = Engine::open(opts.clone()).unwrap();
+        assert!(!opts.dir_path.join(MERGE_FINISHED_FILE_NAME).exists());
+        assert_eq!(load_merge_info(&opts.dir_path).unwrap(), Some(merge));
+        assert_eq!(db.list_keys().unwrap().len(), 1000);
+
+        std::mem::drop(db);
+        clean(name);
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let name = "cleanup";
+        let (db, opts) = setup(name);
+        for i in 0..100 {
+            let (key, value) = get_test_kv(i);
+            db.put(key, value).unwrap();
+        }
+
+        // 崩溃留下的文件, 没有 merge 结果时的 hint 文件也不会被使用
+        let dir_path = &opts.dir_path;
+        let merge_path = get_merge_path(dir_path.clone());
+        std::fs::write(dir_path.join(SEQ_NO_FILE_NAME), b"").unwrap();
+        std::fs::write(dir_path.join(HINT_FILE_NAME), b"").unwrap();
+        std::fs::write(dir_path.join(MANIFEST_FILE_NAME).with_extension("tmp"), b"").unwrap();
+        std::fs::create_dir_all(dir_path.join(HINT_TMP_DIR_NAME)).unwrap();
+        std::fs::create_dir_all(&merge_path).unwrap();
+
+        let mut removed = db.cleanup().unwrap();
+        removed.sort();
+        let mut expected = vec![
+            dir_path.join(SEQ_NO_FILE_NAME),
+            dir_path.join(HINT_TMP_DIR_NAME),
+            dir_path.join(MANIFEST_FILE_NAME).with_extension("tmp"),
+            dir_path.join(HINT_FILE_NAME),
+            merge_path.clone(),
+        ];
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert!(db.cleanup().unwrap().is_empty());
+
+        // 正在使用的 hint 文件和等待安装的 merge 目录会保留
+        db.build_hint_file().unwrap();
+        db.merge().unwrap();
+        assert!(merge_path.join(MERGE_FINISHED_FILE_NAME).is_file());
+        assert!(db.cleanup().unwrap().is_empty());
+        assert!(dir_path.join(HINT_FILE_NAME).is_file());
+        std::mem::drop(db);
+
+        let db = Engine::open(opts.clone()).unwrap();
+        assert_eq!(db.list_keys().unwrap().len(), 100);
+        std::mem::drop(db);
+        clean(name);
+    }
 }
@@ -1,22 +1,158 @@
-use log::error;
+use tracing::error;
 
 use crate::{
+    blob::{remove_gc_blob_files, BLOB_GC_FILE_NAME},
     data::{
         data_file::{get_data_file_name, DataFile},
-        MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
+        log_record::{LogRecord, LogRecordType},
+        HINT_FILE_NAME, MANIFEST_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
     },
     db::FILE_LOCK_NAME,
+    manifest::{Manifest, MergeInfo},
     prelude::*,
+    utils,
+};
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
 };
-use std::{fs, path::PathBuf};
 
 pub mod merge;
 
 const MERGE_DIR_NAME: &'static str = "merge";
-const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
+pub(crate) const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
+/// 部分merge完成的标识, value 为 `输出文件的起始id:预留的文件数量:被合并的文件id,...`
+const PARTIAL_MERGE_FIN_KEY: &[u8] = "merge.partial.finished".as_bytes();
+/// 生成hint文件时使用的临时目录, 在数据目录下
+const HINT_TMP_DIR_NAME: &'static str = "hint-tmp";
+
+/// 写merge完成的标识时使用的临时文件后缀, 写完之后重命名
+const MERGE_FINISHED_TMP_SUFFIX: &str = ".tmp";
+
+/// merge完成的标识中的内容
+/// 格式为`未参与merge的文件id;最大的事务序列号;输出的文件id,...`, 旧版本只有未参与merge的文件id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct MergeManifest {
+    /// id 比它小的数据文件都已经合并, 从 hint 文件加载索引
+    pub(crate) non_merge_fid: u32,
+    /// merge 时已经分配的最大事务序列号
+    /// 重写之后的数据不再带有序列号, 重启时从它之后分配, 不会和合并之前的事务重复
+    pub(crate) max_seq_no: usize,
+    /// merge 输出的数据文件id, 安装时覆盖同名的旧文件, 其他合并过的旧文件被删除
+    pub(crate) output_fids: Vec<u32>,
+}
+
+impl MergeManifest {
+    fn encode(&self) -> Vec<u8> {
+        let output_fids: Vec<String> = self.output_fids.iter().map(|id| id.to_string()).collect();
+        format!(
+            "{};{};{}",
+            self.non_merge_fid,
+            self.max_seq_no,
+            output_fids.join(",")
+        )
+        .into_bytes()
+    }
+
+    pub(crate) fn from_record(record: &LogRecord) -> Result<Self> {
+        let invalid =
+            || Errors::InvalidMergeFinishedFile(String::from_utf8_lossy(&record.value).to_string());
+        if record.key != MERGE_FIN_KEY {
+            return Err(invalid());
+        }
+        let value = std::str::from_utf8(&record.value).map_err(|_| invalid())?;
+        let mut parts = value.split(';');
+        let non_merge_fid = parts
+            .next()
+            .and_then(|v| v.parse::<u32>().ok())
+            .ok_or_else(invalid)?;
+        let max_seq_no = match parts.next() {
+            Some(v) => v.parse::<usize>().map_err(|_| invalid())?,
+            None => NON_TRANSACTION_SEQ_NO,
+        };
+        let output_fids = match parts.next() {
+            Some(v) => v
+                .split(',')
+                .filter(|fid| !fid.is_empty())
+                .map(|fid| fid.parse::<u32>().map_err(|_| invalid()))
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        Ok(MergeManifest {
+            non_merge_fid,
+            max_seq_no,
+            output_fids,
+        })
+    }
+
+    fn merge_info(&self) -> MergeInfo {
+        MergeInfo {
+            non_merge_fid: self.non_merge_fid,
+            max_seq_no: self.max_seq_no,
+        }
+    }
+
+    /// 读取`dir_path`中merge完成的标识, 不存在时返回 None
+    pub(crate) fn load(dir_path: &Path) -> Result<Option<Self>> {
+        if !dir_path.join(MERGE_FINISHED_FILE_NAME).is_file() {
+            return Ok(None);
+        }
+        let merge_fin_file = DataFile::new_merge_fin_file(dir_path.to_path_buf())?;
+        let record = merge_fin_file.read_log_record(0)?.record;
+        Ok(Some(Self::from_record(&record)?))
+    }
+
+    /// 写到`dir_path`中, 先写临时文件再重命名, 文件要么不存在要么是完整的
+    pub(crate) fn save(&self, dir_path: &Path) -> Result<()> {
+        let record = LogRecord {
+            key: MERGE_FIN_KEY.to_vec(),
+            value: self.encode(),
+            rec_type: LogRecordType::Normal,
+            timestamp: 0,
+        };
+        let tmp_path = dir_path.join(format!(
+            "{}{}",
+            MERGE_FINISHED_FILE_NAME, MERGE_FINISHED_TMP_SUFFIX
+        ));
+        utils::file::write_file_atomic(
+            &dir_path.join(MERGE_FINISHED_FILE_NAME),
+            &tmp_path,
+            &record.encode()?,
+        )?;
+        Ok(())
+    }
+}
+
+/// 读取数据目录中最近一次 merge 的结果
+/// 旧版本会把 merge 完成的标识留在数据目录中, 清单中没有时从标识中读取
+pub(crate) fn load_merge_info(dir_path: &Path) -> Result<Option<MergeInfo>> {
+    if let Some(merge) = Manifest::load(dir_path)?.and_then(|manifest| manifest.merge) {
+        return Ok(Some(merge));
+    }
+    Ok(MergeManifest::load(dir_path)?.map(|manifest| manifest.merge_info()))
+}
+
+/// 把 merge 的结果写入数据目录的清单, 持有文件锁时调用
+pub(crate) fn save_merge_info(dir_path: &Path, merge: MergeInfo) -> Result<()> {
+    let mut manifest = Manifest::load(dir_path)?
+        .ok_or_else(|| Errors::InvalidManifest("manifest not found".to_string()))?;
+    manifest.merge = Some(merge);
+    manifest.save(dir_path)
+}
+
+/// 把旧版本留在数据目录中的 merge 完成的标识合并到清单中, 然后删除标识
+fn fold_merge_finished_file(dir_path: &Path) -> Result<()> {
+    let Some(merge_manifest) = MergeManifest::load(dir_path)? else {
+        return Ok(());
+    };
+    save_merge_info(dir_path, merge_manifest.merge_info())?;
+    fs::remove_file(dir_path.join(MERGE_FINISHED_FILE_NAME))?;
+    Ok(())
+}
 
 /// 用于merge的临时目录
-fn get_merge_path(dir_path: PathBuf) -> PathBuf {
+pub(crate) fn get_merge_path(dir_path: PathBuf) -> PathBuf {
     // todo: 删掉unwrap
     let file_name = dir_path.file_name().unwrap();
     let merge_name = format!("{}-{}", file_name.to_str().unwrap(), MERGE_DIR_NAME);
@@ -27,6 +163,8 @@ fn get_merge_path(dir_path: PathBuf) -> PathBuf {
 
 /// 加载merge数据目录
 pub(crate) fn load_merge_files(dir_path: PathBuf) -> Result<()> {
+    fold_merge_finished_file(&dir_path)?;
+
     let merge_path = get_merge_path(dir_path.clone());
     // 没有发生merge
     if !merge_path.is_dir() {
@@ -48,8 +186,13 @@ pub(crate) fn load_merge_files(dir_path: PathBuf) -> Result<()> {
         if let Ok(entry) = file {
             let file_os_str = entry.file_name();
             let file_name = file_os_str.to_str().unwrap();
+            // merge完成的标识最后单独移动, 写到一半的临时文件直接丢弃
             if file_name.ends_with(MERGE_FINISHED_FILE_NAME) {
                 merge_finished = true;
+                continue;
+            }
+            if file_name.ends_with(MERGE_FINISHED_TMP_SUFFIX) {
+                continue;
             }
 
             if file_name.ends_with(SEQ_NO_FILE_NAME) {
@@ -59,6 +202,15 @@ pub(crate) fn load_merge_files(dir_path: PathBuf) -> Result<()> {
             if file_name.ends_with(FILE_LOCK_NAME) {
                 continue;
             }
+
+            // merge 目录中的清单是临时引擎写入的, 保留数据目录中原来的
+            if file_name.ends_with(MANIFEST_FILE_NAME) {
+                continue;
+            }
+
+            if file_name.ends_with(BLOB_GC_FILE_NAME) {
+                continue;
+            }
             merge_file_names.push(entry.file_name());
         }
     }
@@ -71,27 +223,134 @@ pub(crate) fn load_merge_files(dir_path: PathBuf) -> Result<()> {
     // 打开标识merge完成的文件,取出未参与merge的文件id
     let merge_fin_file = DataFile::new_merge_fin_file(merge_path.clone())?;
     let merge_fin_record = merge_fin_file.read_log_record(0)?;
+    if merge_fin_record.record.key == PARTIAL_MERGE_FIN_KEY {
+        let v = String::from_utf8(merge_fin_record.record.value).map_err(|e| {
+            Errors::InvalidMergeFinishedFile(String::from_utf8_lossy(e.as_bytes()).to_string())
+        })?;
+        return load_partial_merge_files(dir_path, merge_path, merge_file_names, &v);
+    }
+    let mut manifest = MergeManifest::from_record(&merge_fin_record.record)?;
+    // 旧版本的清单中没有输出的文件id, merge 目录中的数据文件就是输出
+    if manifest.output_fids.is_empty() {
+        manifest.output_fids = merge_file_names
+            .iter()
+            .filter_map(parse_data_file_id)
+            .collect();
+    }
 
-    let v = String::from_utf8(merge_fin_record.record.value).unwrap();
-    let non_merge_fid = v.parse::<u32>().unwrap(); // 未参与merge的文件id
+    // 按下面的顺序安装, 每一步重复执行的结果都一样, 中途崩溃时重启会从头再执行一次:
+    // 1. 新的数据文件和 hint 文件通过重命名覆盖数据目录中同名的文件, 每个文件要么是旧的要么是新的
+    // 2. 删除已经merge、又没有被覆盖的旧数据文件
+    // 3. 最后把 merge 的结果写入清单并删除 merge 目录
+    for file_name in merge_file_names {
+        let src_path = merge_path.join(file_name.clone());
+        let dst_path = dir_path.join(file_name.clone());
+        fs::rename(src_path, dst_path)?;
+    }
 
-    // 已经merge的文件删除
-    for fid in 0..non_merge_fid {
+    for fid in 0..manifest.non_merge_fid {
+        if manifest.output_fids.contains(&fid) {
+            continue;
+        }
         let file = get_data_file_name(&dir_path, fid);
-        if !file.is_file() {
+        if file.is_file() {
+            fs::remove_file(file)?;
+        }
+    }
+    // 新的数据文件已经引用了重写之后的 blob
+    remove_gc_blob_files(&dir_path, &merge_path)?;
+
+    save_merge_info(&dir_path, manifest.merge_info())?;
+    fs::remove_dir_all(merge_path.clone())?;
+
+    Ok(())
+}
+
+/// 文件名为`00001.data`时返回文件id
+fn parse_data_file_id(file_name: &OsString) -> Option<u32> {
+    file_name
+        .to_str()
+        .and_then(|name| name.strip_suffix(DATA_FILE_NAME_SUFFIX))
+        .and_then(|fid| fid.parse::<u32>().ok())
+}
+
+/// 加载部分merge的数据, `value`为`输出文件的起始id:预留的文件数量:被合并的文件id,...`, 旧版本没有预留的文件数量
+/// merge目录中的数据文件从0开始编号, 重命名到预留的id上, 重复执行也不会出错
+fn load_partial_merge_files(
+    dir_path: PathBuf,
+    merge_path: PathBuf,
+    merge_file_names: Vec<OsString>,
+    value: &str,
+) -> Result<()> {
+    let invalid = || Errors::InvalidMergeFinishedFile(value.to_string());
+    let parts: Vec<&str> = value.splitn(3, ':').collect();
+    let (base, reserved, fids) = match parts[..] {
+        [base, fids] => (base, None, fids),
+        [base, reserved, fids] => (base, Some(reserved), fids),
+        _ => return Err(invalid()),
+    };
+    let output_base_fid = base.parse::<u32>().map_err(|_| invalid())?;
+    let reserved = match reserved {
+        Some(reserved) => Some(reserved.parse::<u32>().map_err(|_| invalid())?),
+        None => None,
+    };
+    let merged_fids = fids
+        .split(',')
+        .filter(|fid| !fid.is_empty())
+        .map(|fid| fid.parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>>>()?;
+
+    // 重命名之前检查所有的输出文件, 超出预留的范围或者目标文件已经存在时会覆盖活跃文件或者更新的数据
+    let mut renames = vec![];
+    for file_name in merge_file_names {
+        let Some(fid) = parse_data_file_id(&file_name) else {
             continue;
+        };
+        if reserved.is_some_and(|reserved| fid >= reserved) {
+            return Err(invalid());
         }
+        let target = output_base_fid
+            .checked_add(fid)
+            .map(|fid| get_data_file_name(&dir_path, fid))
+            .ok_or_else(invalid)?;
+        if target.exists() {
+            return Err(Errors::InvalidMergeFinishedFile(format!(
+                "{}: {} already exists",
+                value,
+                target.display()
+            )));
+        }
+        renames.push((merge_path.join(file_name), target));
+    }
 
-        fs::remove_file(file)?;
+    // hint文件中的位置可能指向被合并的文件, 之后从数据文件重新加载索引, 保留最大的事务序列号
+    if let Some(merge) = load_merge_info(&dir_path)? {
+        save_merge_info(
+            &dir_path,
+            MergeInfo {
+                non_merge_fid: 0,
+                ..merge
+            },
+        )?;
+    }
+    let hint_path = dir_path.join(HINT_FILE_NAME);
+    if hint_path.is_file() {
+        fs::remove_file(hint_path)?;
     }
 
-    // 新的数据文件移动到数据库目录
-    for file_name in merge_file_names {
-        let src_path = merge_path.join(file_name.clone());
-        let dst_path = dir_path.join(file_name.clone());
-        fs::rename(src_path, dst_path)?;
+    // 新的数据文件移动到预留的id上
+    for (from, to) in renames {
+        fs::rename(from, to)?;
     }
-    fs::remove_dir_all(merge_path.clone())?;
+
+    // 已经merge的文件删除
+    for fid in merged_fids {
+        let file = get_data_file_name(&dir_path, fid);
+        if file.is_file() {
+            fs::remove_file(file)?;
+        }
+    }
+    fs::remove_dir_all(merge_path)?;
 
     Ok(())
 }
@@ -0,0 +1,343 @@
+use crate::prelude::*;
+
+use bytes::{Buf, Bytes};
+
+use crate::{db::Engine, iterator::Iterator, options::IteratorOptions};
+
+/// 命名空间的注册信息: 前缀 + 名称 -> id
+const NAMESPACE_META_PREFIX: &[u8] = b"\x00ns-meta:";
+/// 下一个可以分配的命名空间 id
+const NAMESPACE_NEXT_ID_KEY: &[u8] = b"\x00ns-next-id";
+/// 命名空间中的数据: 前缀 + id(4字节, 大端) + key
+const NAMESPACE_DATA_PREFIX: &[u8] = b"\x00ns:";
+
+/// 一个命名空间的统计信息, 只遍历内存索引
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NamespaceStat {
+    /// `key`的数量
+    pub key_num: usize,
+    /// 数据在磁盘上占据的空间(编码之后的`LogRecord`)
+    pub data_size: usize,
+}
+
+/// 逻辑上独立的一组`key`, 读写时自动加上命名空间的前缀
+/// 命名空间的注册信息也保存在引擎中, 随数据一起 merge/备份/复制
+pub struct Namespace<'a> {
+    engine: &'a Engine,
+    name: String,
+    id: u32,
+    prefix: Vec<u8>,
+}
+
+/// 命名空间的迭代器, 返回的`key`不包括命名空间的前缀
+pub struct NamespaceIterator<'a> {
+    inner: Iterator<'a>,
+    prefix: Vec<u8>,
+}
+
+fn meta_key(name: &str) -> Bytes {
+    let mut key = NAMESPACE_META_PREFIX.to_vec();
+    key.extend_from_slice(name.as_bytes());
+    Bytes::from(key)
+}
+
+fn data_prefix(id: u32) -> Vec<u8> {
+    let mut prefix = NAMESPACE_DATA_PREFIX.to_vec();
+    prefix.extend_from_slice(&id.to_be_bytes());
+    prefix
+}
+
+fn decode_id(mut value: Bytes) -> Result<u32> {
+    if value.len() != 4 {
+        return Err(Errors::DeserializeFailed(format!(
+            "invalid namespace id length: {}",
+            value.len()
+        )));
+    }
+    Ok(value.get_u32())
+}
+
+impl Engine {
+    /// 打开名为`name`的命名空间, 不存在时注册一个新的
+    pub fn namespace(&self, name: &str) -> Result<Namespace<'_>> {
+        if name.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        let id = match self.namespace_id(name)? {
+            Some(id) => id,
+            None => self.register_namespace(name)?,
+        };
+        Ok(Namespace {
+            engine: self,
+            name: name.to_string(),
+            id,
+            prefix: data_prefix(id),
+        })
+    }
+
+    /// 所有已经注册的命名空间, 按名称排序
+    pub fn list_namespaces(&self) -> Result<Vec<String>> {
+        let iter = self.iter(IteratorOptions {
+            prefix: NAMESPACE_META_PREFIX.to_vec(),
+            keys_only: true,
+            ..Default::default()
+        });
+        let mut names = Vec::new();
+        while let Some((key, _)) = iter.next()? {
+            let name = &key[NAMESPACE_META_PREFIX.len()..];
+            names.push(String::from_utf8_lossy(name).into_owned());
+        }
+        Ok(names)
+    }
+
+    /// 删除命名空间中的所有数据并取消注册, 返回删除的`key`数量
+    /// 数据分批写入删除标记, 中途失败时命名空间仍然存在, 可以再次调用
+    pub fn drop_namespace(&self, name: &str) -> Result<usize> {
+        self.check_writable()?;
+        let id = self
+            .namespace_id(name)?
+            .ok_or_else(|| Errors::NamespaceNotFound(name.to_string()))?;
+
+        let count = self.delete_prefix(Bytes::from(data_prefix(id)))?;
+        self.delete(meta_key(name))?;
+        Ok(count)
+    }
+
+    fn namespace_id(&self, name: &str) -> Result<Option<u32>> {
+        match self.get(meta_key(name)) {
+            Ok(value) => Ok(Some(decode_id(value)?)),
+            Err(Errors::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// id 只增不减, 删除之后再注册同名的命名空间不会看到之前残留的数据
+    fn register_namespace(&self, name: &str) -> Result<u32> {
+        let _guard = self.lock_key(NAMESPACE_NEXT_ID_KEY);
+        // 等待锁的时候其他线程可能已经注册了
+        if let Some(id) = self.namespace_id(name)? {
+            return Ok(id);
+        }
+
+        let id = match self.get(Bytes::from_static(NAMESPACE_NEXT_ID_KEY)) {
+            Ok(value) => decode_id(value)?,
+            Err(Errors::KeyNotFound) => 0,
+            Err(e) => return Err(e),
+        };
+        let next_id = id.checked_add(1).ok_or(Errors::IntegerOverflow)?;
+
+        let wb = self.new_write_batch(Default::default())?;
+        wb.put(
+            Bytes::from_static(NAMESPACE_NEXT_ID_KEY),
+            Bytes::copy_from_slice(&next_id.to_be_bytes()),
+        )?;
+        wb.put(meta_key(name), Bytes::copy_from_slice(&id.to_be_bytes()))?;
+        wb.commit()?;
+        Ok(id)
+    }
+}
+
+impl<'a> Namespace<'a> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+        self.engine.put(self.encode_key(&key)?, value)
+    }
+
+    pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        self.engine.get(self.encode_key(&key)?)
+    }
+
+    pub fn delete(&self, key: Bytes) -> Result<()> {
+        self.engine.delete(self.encode_key(&key)?)
+    }
+
+    /// `options.prefix`是命名空间内的前缀
+    pub fn iter(&self, mut options: IteratorOptions) -> NamespaceIterator<'a> {
+        let mut prefix = self.prefix.clone();
+        prefix.extend_from_slice(&options.prefix);
+        options.prefix = prefix;
+        NamespaceIterator {
+            inner: self.engine.iter(options),
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    pub fn list_keys(&self) -> Result<Vec<Bytes>> {
+        let iter = self.iter(IteratorOptions {
+            keys_only: true,
+            ..Default::default()
+        });
+        let mut keys = Vec::new();
+        while let Some((key, _)) = iter.next()? {
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// 命名空间中`key`的数量
+    pub fn count(&self) -> Result<usize> {
+        self.engine.count(&self.prefix)
+    }
+
+    /// 需要遍历命名空间中的所有`key`, 不适合频繁调用
+    pub fn stat(&self) -> Result<NamespaceStat> {
+        let mut stat = NamespaceStat::default();
+        self.engine
+            .index
+            .for_each_prefix(&self.prefix, &mut |_, pos| {
+                stat.key_num += 1;
+                stat.data_size += pos.size;
+            })?;
+        Ok(stat)
+    }
+
+    fn encode_key(&self, key: &[u8]) -> Result<Bytes> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        let mut buf = self.prefix.clone();
+        buf.extend_from_slice(key);
+        Ok(Bytes::from(buf))
+    }
+}
+
+impl NamespaceIterator<'_> {
+    /// 回到迭代器的起点,指向第一个数据
+    pub fn rewind(&self) {
+        self.inner.rewind();
+    }
+
+    /// 见`Iterator::seek`, `key`不需要加命名空间的前缀
+    pub fn seek(&self, key: Vec<u8>) {
+        let mut buf = self.prefix.clone();
+        buf.extend_from_slice(&key);
+        self.inner.seek(buf);
+    }
+
+    /// 见`Iterator::next`
+    pub fn next(&self) -> Result<Option<(Bytes, Bytes)>> {
+        Ok(self
+            .inner
+            .next()?
+            .map(|(key, value)| (key.slice(self.prefix.len()..), value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::options::EngineOptions;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/namespace".into()
+    }
+
+    fn setup(name: &str) -> EngineOptions {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_namespace_isolation() {
+        let name = "isolation";
+        let opts = setup(name);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let users = engine.namespace("users").unwrap();
+        let orders = engine.namespace("orders").unwrap();
+        assert_ne!(users.id(), orders.id());
+
+        users.put(Bytes::from("1"), Bytes::from("alice")).unwrap();
+        users.put(Bytes::from("2"), Bytes::from("bob")).unwrap();
+        orders
+            .put(Bytes::from("1"), Bytes::from("order-1"))
+            .unwrap();
+        engine.put(Bytes::from("1"), Bytes::from("plain")).unwrap();
+
+        assert_eq!(users.get(Bytes::from("1")).unwrap(), Bytes::from("alice"));
+        assert_eq!(
+            orders.get(Bytes::from("1")).unwrap(),
+            Bytes::from("order-1")
+        );
+        assert_eq!(engine.get(Bytes::from("1")).unwrap(), Bytes::from("plain"));
+        assert!(matches!(
+            orders.get(Bytes::from("2")),
+            Err(Errors::KeyNotFound)
+        ));
+
+        // 迭代器返回的 key 不带前缀
+        let iter = users.iter(IteratorOptions::default());
+        assert_eq!(
+            iter.next().unwrap(),
+            Some((Bytes::from("1"), Bytes::from("alice")))
+        );
+        iter.seek(b"2".to_vec());
+        assert_eq!(iter.next().unwrap().unwrap().0, Bytes::from("2"));
+        assert!(iter.next().unwrap().is_none());
+
+        users.delete(Bytes::from("2")).unwrap();
+        assert_eq!(users.list_keys().unwrap(), vec![Bytes::from("1")]);
+        let stat = users.stat().unwrap();
+        assert_eq!(stat.key_num, 1);
+        assert_eq!(users.count().unwrap(), 1);
+        assert!(stat.data_size > 0);
+
+        // 重启之后 id 不变
+        let users_id = users.id();
+        drop(engine);
+        let engine = Engine::open(opts).expect("failed to reopen engine");
+        assert_eq!(engine.list_namespaces().unwrap(), vec!["orders", "users"]);
+        let users = engine.namespace("users").unwrap();
+        assert_eq!(users.id(), users_id);
+        assert_eq!(users.get(Bytes::from("1")).unwrap(), Bytes::from("alice"));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_drop_namespace() {
+        let name = "drop";
+        let opts = setup(name);
+        let engine = Engine::open(opts).expect("failed to open engine");
+
+        let logs = engine.namespace("logs").unwrap();
+        for i in 0..300 {
+            logs.put(Bytes::from(format!("{:04}", i)), Bytes::from("line"))
+                .unwrap();
+        }
+        let keep = engine.namespace("keep").unwrap();
+        keep.put(Bytes::from("k"), Bytes::from("v")).unwrap();
+        let old_id = logs.id();
+
+        assert_eq!(engine.drop_namespace("logs").unwrap(), 300);
+        assert!(matches!(
+            engine.drop_namespace("logs"),
+            Err(Errors::NamespaceNotFound(_))
+        ));
+        assert_eq!(engine.list_namespaces().unwrap(), vec!["keep"]);
+        assert_eq!(keep.get(Bytes::from("k")).unwrap(), Bytes::from("v"));
+
+        // 同名的命名空间使用新的 id, 是空的
+        let logs = engine.namespace("logs").unwrap();
+        assert_ne!(logs.id(), old_id);
+        assert_eq!(logs.stat().unwrap(), NamespaceStat::default());
+
+        clean(name);
+    }
+}
@@ -1,7 +1,11 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use bon::{builder, Builder};
 
+pub use crate::fio::IOType;
+
+use crate::event::EventListener;
+
 /// 数据库配置
 #[derive(Debug, Clone, Builder)]
 pub struct EngineOptions {
@@ -10,16 +14,19 @@ pub struct EngineOptions {
     /// 单个数据文件的大小,单位字节
     #[builder(default = 256 * 1024 * 1024)]
     pub data_file_size: u64,
-    /// 是否每次写入都持久化
-    #[builder(default = false)]
-    pub sync_writes: bool,
+    /// 写入后什么时候持久化
+    #[builder(default = SyncPolicy::Never)]
+    pub sync_policy: SyncPolicy,
+    /// 后台线程每隔多久持久化一次活跃文件, 等价于`SyncPolicy::EveryDuration`, 为空表示使用`sync_policy`
+    /// 同时设置时`sync_policy`只能是`Never`或者间隔相同的`EveryDuration`, 否则返回`Errors::InvalidSyncPolicy`
+    pub sync_interval: Option<Duration>,
+    /// `SyncPolicy::Always` 模式下的组提交: 负责持久化的线程最多等待多久,
+    /// 让更多并发写入合并到同一次 sync 中, 为0时不等待
+    #[builder(default = Duration::ZERO)]
+    pub group_commit_max_wait: Duration,
     /// 索引类型
     pub index_type: IndexType,
 
-    /// 累计写到多少字节后进行持久化
-    #[builder(default = 0)]
-    pub bytes_per_sync: usize,
-
     /// 是否使用Mmap加快启动数据库
     #[builder(default = true)]
     pub use_mmap_when_startup: bool,
@@ -27,18 +34,121 @@ pub struct EngineOptions {
     /// 达到阈值了就执行merge操作
     #[builder(default = 0.5)]
     pub data_file_merge_ratio: f32,
+
+    /// 数据目录最多占用多少字节(包括merge的临时目录), 超过后写入会失败, 为空表示不限制
+    pub max_db_size_bytes: Option<u64>,
+
+    /// 写入时`value`的压缩方式, 修改后旧数据依然可以读取
+    #[builder(default = CompressionType::None)]
+    pub compression: CompressionType,
+
+    /// 数据文件中每条数据的校验方式, 只在第一次初始化数据目录时生效并记录在清单中
+    /// 之后打开时总是使用清单中的校验方式, 和这里不同时忽略这个配置
+    #[builder(default = ChecksumType::Crc32)]
+    pub checksum: ChecksumType,
+
+    /// 读取时遇到损坏的数据(crc校验失败), 是否当作`key`不存在, 而不是返回错误
+    /// 无论是否跳过, 损坏的数据都会记录到 quarantine 文件中
+    #[builder(default = false)]
+    pub skip_corrupted_records: bool,
+
+    /// 是否给活跃文件预分配`data_file_size`大小的磁盘空间, 减少文件系统碎片和追加写时的元数据更新
+    #[builder(default = false)]
+    pub preallocate_data_file: bool,
+
+    /// 启动时并发加载旧数据文件的线程数, 为1时按顺序加载
+    #[builder(default = 1)]
+    pub startup_threads: usize,
+
+    /// 活跃文件写入时使用的IO类型
+    /// 使用`MemoryMap`时活跃文件总是预分配`data_file_size`大小, 和`preallocate_data_file`一样
+    /// 使用`DirectIO`/`IoUring`时旧数据文件的读取也使用同样的方式
+    #[builder(default = IOType::StandardFileIO)]
+    pub write_io_type: IOType,
+
+    /// 以只读方式打开, 用于备份/校验工具读取其他进程正在使用的数据库
+    /// 只获取共享的文件锁, 不会修改数据目录中的任何文件, 写入和merge返回`Errors::ReadOnly`
+    #[builder(default = false)]
+    pub read_only: bool,
+
+    /// 数据库正在被其他进程使用时, 最多等待多久文件锁, 为0时立即返回`Errors::DatabaseIsUsing`
+    #[builder(default = Duration::ZERO)]
+    pub lock_wait: Duration,
+
+    /// `key`的最大长度, 超过时写入返回`Errors::KeyTooLarge`, 为空表示不限制
+    pub max_key_size: Option<usize>,
+
+    /// `value`的最大长度, 超过时写入返回`Errors::ValueTooLarge`, 为空表示不限制
+    /// 无论是否设置, 编码后超过`data_file_size`的数据都会返回`Errors::RecordTooLarge`
+    pub max_value_size: Option<usize>,
+
+    /// 加密`value`使用的密钥(XChaCha20-Poly1305), 为空表示不加密, `key`不会被加密
+    /// 第一次使用时在清单中记录一个标记, 之后打开时校验密钥, 加密过的数据库不能去掉密钥
+    pub encryption_key: Option<[u8; 32]>,
+
+    /// `IndexType::Spill`最多使用多少内存, 超过后把不常访问的`key`写到磁盘, 为空时使用 256MB
+    pub max_index_memory: Option<usize>,
+
+    /// 超过这个长度的`value`单独写到 blob 文件, 数据文件中只保存位置, 为空表示不分离
+    /// merge 时只重写无效数据占比达到`data_file_merge_ratio`的 blob 文件, 减少大`value`的重复写入
+    /// 分离之后的数据不能被之前的版本读取
+    pub big_value_threshold: Option<usize>,
+
+    /// 引擎内部事件的回调, 为空表示不通知
+    pub event_listener: Option<Arc<dyn EventListener>>,
+
+    /// 所有数据只保存在内存中(`IOType::InMemory`), 不使用`dir_path`, 关闭之后数据全部丢失
+    /// 用于单元测试或者缓存, 不支持只读模式、`IndexType::Spill`、大`value`分离、merge、备份和导入
+    #[builder(default = false)]
+    pub ephemeral: bool,
+
+    /// 限制前台写入和 merge 每秒写入、读取的字节数, 避免后台 merge 占满磁盘带宽, 为空表示不限制
+    pub rate_limit: Option<RateLimit>,
+
+    /// 打开时是否检查内存索引指向的数据文件都存在, 结果通过`Engine::recovery_report`获取
+    #[builder(default = RecoveryMode::None)]
+    pub recovery_mode: RecoveryMode,
+
+    /// 打开时加载索引最多用多久, 超时后返回`Errors::OpenTimeout`, 为空表示不限制
+    /// 加载的进度通过`EventListener::on_open_progress`获取
+    pub open_timeout: Option<Duration>,
+}
+
+/// 部分merge的配置, 两项都为空时和`merge`一样合并所有数据文件
+#[derive(Debug, Clone, Default, Builder)]
+pub struct MergeOptions {
+    /// 最多合并多少个最旧的数据文件
+    pub max_files: Option<usize>,
+    /// 只合并无效数据占比不低于这个值的数据文件
+    pub min_garbage_ratio: Option<f32>,
 }
 
 #[derive(Debug, Clone, Builder)]
 pub struct IteratorOptions {
     pub prefix: Vec<u8>, // 前缀,过滤用
     pub reverse: bool,   // 是否反向便利
+    /// 跳过前面多少条数据, `rewind`/`seek`之后从新的位置重新计算
+    #[builder(default)]
+    pub offset: usize,
+    /// 最多返回多少条数据, 为空表示不限制, `rewind`/`seek`之后重新计数
+    pub limit: Option<usize>,
+    /// 只返回`key`, 不读取磁盘, 返回的`value`为空
+    #[builder(default)]
+    pub keys_only: bool,
+    /// 只返回最后一次写入时间不早于这个毫秒时间戳的数据, 为空表示不过滤
+    /// 需要读取每条数据的头部判断, 设置了`keys_only`也会读取磁盘; 没有时间戳的旧数据不会返回
+    pub modified_since: Option<u64>,
 }
 
 #[derive(Debug, Clone, Builder)]
 pub struct WriteBatchOptions {
     pub max_batch_num: u32, // 一个Batch最多写多少条数据
     pub sync_writes: bool,  // 提交的时候是否持久化
+    /// 一个Batch中`key`和`value`的总字节数上限, 为空表示不限制
+    pub max_batch_bytes: Option<usize>,
+    /// 超过`max_batch_num`/`max_batch_bytes`时不报错, 提交时按顺序拆成多个事务, 每个事务各自原子
+    #[builder(default)]
+    pub auto_commit_chunks: bool,
 }
 
 impl Default for EngineOptions {
@@ -46,11 +156,31 @@ impl Default for EngineOptions {
         Self {
             dir_path: std::env::temp_dir().join("lucasdb"),
             data_file_size: 256 * 1024 * 1024,
-            sync_writes: false,
+            sync_policy: SyncPolicy::Never,
+            sync_interval: None,
+            group_commit_max_wait: Duration::ZERO,
             index_type: IndexType::BTree,
-            bytes_per_sync: 0,
             use_mmap_when_startup: true,
             data_file_merge_ratio: 0.5,
+            max_db_size_bytes: None,
+            compression: CompressionType::None,
+            checksum: ChecksumType::Crc32,
+            skip_corrupted_records: false,
+            preallocate_data_file: false,
+            startup_threads: 1,
+            write_io_type: IOType::StandardFileIO,
+            read_only: false,
+            lock_wait: Duration::ZERO,
+            max_key_size: None,
+            max_value_size: None,
+            encryption_key: None,
+            max_index_memory: None,
+            big_value_threshold: None,
+            event_listener: None,
+            ephemeral: false,
+            rate_limit: None,
+            recovery_mode: RecoveryMode::None,
+            open_timeout: None,
         }
     }
 }
@@ -60,6 +190,10 @@ impl Default for IteratorOptions {
         Self {
             prefix: Default::default(),
             reverse: false,
+            offset: 0,
+            limit: None,
+            keys_only: false,
+            modified_since: None,
         }
     }
 }
@@ -68,13 +202,72 @@ impl Default for WriteBatchOptions {
         Self {
             max_batch_num: 10000,
             sync_writes: true,
+            max_batch_bytes: None,
+            auto_commit_chunks: false,
         }
     }
 }
 
+/// IO 限速的方式, 单位字节每秒
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimit {
+    /// 前台写入和 merge 共用同一个额度
+    Shared(u64),
+    /// 分别限制, 为空表示不限制
+    Separate {
+        write: Option<u64>,
+        merge: Option<u64>,
+    },
+}
+
+/// 打开时如何处理指向不存在的数据文件的索引, 比如数据文件被手动删除了
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecoveryMode {
+    /// 不检查, 读取这些`key`时返回`Errors::DataFileNotFound`
+    None,
+    /// 检查并记录到`RecoveryReport`, 索引保持不变
+    Report,
+    /// 检查并从内存索引中删除这些`key`, 同时记录到`RecoveryReport`
+    Repair,
+}
+
+/// 写入数据后的持久化策略
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncPolicy {
+    /// 每次写入都持久化, 并发的写入通过组提交合并成一次 sync
+    Always,
+    /// 不主动持久化, 由操作系统决定什么时候写回磁盘
+    Never,
+    /// 累计写入多少字节后持久化
+    EveryNBytes(usize),
+    /// 后台线程每隔一段时间持久化一次, 期间没有写入时不 sync
+    EveryDuration(Duration),
+}
+
 // 索引类型
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IndexType {
     BTree,
     SkipList,
+    /// 内存中只保留常用的`key`, 其余的写到数据目录中的溢出文件, 内存占用不超过`max_index_memory`
+    Spill,
+}
+
+// 压缩类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompressionType {
+    None,
+    Lz4,
+    Zstd,
+}
+
+// 校验类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChecksumType {
+    /// 4字节的 crc32
+    Crc32,
+    /// 8字节的 xxhash64, 计算更快, 冲突的概率更低
+    XxHash64,
+    /// 不校验, 读取时发现不了损坏的数据
+    None,
 }
@@ -0,0 +1,157 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{options::RateLimit, stat::ThrottleStat};
+
+/// 令牌桶限速, 每秒补充`bytes_per_sec`个字节, 最多攒下一秒的量
+/// 请求时先扣除, 不够时欠下的部分按速率睡眠等待, 超过一秒的大请求也不会一直等不到
+pub(crate) struct RateLimiter {
+    bytes_per_sec: u64,
+    bucket: Mutex<Bucket>,
+    /// 当前正在等待的请求数
+    waiting: AtomicUsize,
+    throttled_count: AtomicUsize,
+    throttled_nanos: AtomicU64,
+}
+
+struct Bucket {
+    /// 可以使用的字节数, 为负数时表示欠下的字节数
+    available: i64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec,
+            bucket: Mutex::new(Bucket {
+                available: bytes_per_sec as i64,
+                last_refill: Instant::now(),
+            }),
+            waiting: AtomicUsize::new(0),
+            throttled_count: AtomicUsize::new(0),
+            throttled_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// 申请`bytes`个字节的额度, 超过速率时阻塞当前线程
+    pub(crate) fn request(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock();
+            self.refill(&mut bucket);
+            bucket.available -= bytes as i64;
+            if bucket.available >= 0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.available as f64 / self.bytes_per_sec as f64)
+        };
+
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(wait);
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        self.throttled_count.fetch_add(1, Ordering::SeqCst);
+        self.throttled_nanos
+            .fetch_add(wait.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill);
+        let refill = (elapsed.as_secs_f64() * self.bytes_per_sec as f64) as i64;
+        if refill > 0 {
+            bucket.available = (bucket.available + refill).min(self.bytes_per_sec as i64);
+            bucket.last_refill = now;
+        }
+    }
+
+    pub(crate) fn stat(&self) -> ThrottleStat {
+        let available = {
+            let mut bucket = self.bucket.lock();
+            self.refill(&mut bucket);
+            bucket.available
+        };
+        ThrottleStat {
+            bytes_per_sec: self.bytes_per_sec,
+            available_bytes: available,
+            waiting: self.waiting.load(Ordering::SeqCst),
+            throttled_count: self.throttled_count.load(Ordering::SeqCst),
+            throttled_time: Duration::from_nanos(self.throttled_nanos.load(Ordering::SeqCst)),
+        }
+    }
+}
+
+/// 前台写入和 merge 使用的限速器, 共用额度时指向同一个
+#[derive(Default)]
+pub(crate) struct RateLimiters {
+    pub(crate) write: Option<Arc<RateLimiter>>,
+    pub(crate) merge: Option<Arc<RateLimiter>>,
+}
+
+impl RateLimiters {
+    pub(crate) fn new(rate_limit: Option<RateLimit>) -> Self {
+        let new_limiter =
+            |bytes_per_sec: Option<u64>| bytes_per_sec.map(RateLimiter::new).map(Arc::new);
+        match rate_limit {
+            None => Self::default(),
+            Some(RateLimit::Shared(bytes_per_sec)) => {
+                let limiter = Arc::new(RateLimiter::new(bytes_per_sec));
+                RateLimiters {
+                    write: Some(limiter.clone()),
+                    merge: Some(limiter),
+                }
+            }
+            Some(RateLimit::Separate { write, merge }) => RateLimiters {
+                write: new_limiter(write),
+                merge: new_limiter(merge),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(1000);
+        // 一开始有一秒的额度
+        let start = Instant::now();
+        limiter.request(1000);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(limiter.stat().throttled_count, 0);
+
+        // 额度用完之后按速率等待
+        let start = Instant::now();
+        limiter.request(200);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        let stat = limiter.stat();
+        assert_eq!(stat.bytes_per_sec, 1000);
+        assert_eq!(stat.throttled_count, 1);
+        assert!(stat.throttled_time >= Duration::from_millis(150));
+        assert_eq!(stat.waiting, 0);
+    }
+
+    #[test]
+    fn test_rate_limiters_shared() {
+        let limiters = RateLimiters::new(Some(RateLimit::Shared(1000)));
+        assert!(Arc::ptr_eq(
+            limiters.write.as_ref().unwrap(),
+            limiters.merge.as_ref().unwrap()
+        ));
+
+        let limiters = RateLimiters::new(Some(RateLimit::Separate {
+            write: None,
+            merge: Some(1000),
+        }));
+        assert!(limiters.write.is_none());
+        assert!(limiters.merge.is_some());
+    }
+}
@@ -0,0 +1,210 @@
+//! 直接读取数据文件的底层接口, 用于在引擎之外实现调试、复制、分析等工具
+use crate::prelude::*;
+
+use crate::{
+    batch::parse_log_record_key,
+    data::log_record::{LogRecordPos, LogRecordType},
+    db::Engine,
+};
+
+/// 数据文件中的一条数据
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawRecord {
+    /// 去掉事务序列号之后的`key`
+    pub key: Vec<u8>,
+    /// 解密、解压之后的`value`, `LogRecordType::BlobIndex`时是编码之后的 blob 位置
+    pub value: Vec<u8>,
+    pub rec_type: LogRecordType,
+    /// 事务序列号, 不在事务中写入的数据为0
+    pub seq_no: usize,
+    /// 在数据文件中的位置
+    pub pos: LogRecordPos,
+}
+
+/// 按顺序读取一个数据文件中的所有数据, 由`Engine::raw_scan`创建
+/// 遇到无法读取的数据时返回错误, 之后不再返回数据
+pub struct RawScan<'a> {
+    engine: &'a Engine,
+    file_id: u32,
+    offset: u64,
+    done: bool,
+}
+
+impl Engine {
+    /// 当前所有数据文件的id, 从小到大排列, 最后一个是活跃文件
+    pub fn data_file_ids(&self) -> Vec<u32> {
+        let active_file = self.active_file.read();
+        let mut file_ids: Vec<u32> = self.older_files.read().keys().copied().collect();
+        file_ids.sort();
+        file_ids.push(active_file.get_file_id());
+        file_ids
+    }
+
+    /// 按写入顺序读取数据文件`file_id`中的数据, 包括已经被覆盖、删除的数据和事务完成的标识
+    /// 活跃文件只读取到调用`next`时已经写入的位置, merge 之后旧的文件不存在时返回`Errors::DataFileNotFound`
+    pub fn raw_scan(&self, file_id: u32) -> Result<RawScan<'_>> {
+        self.check_open()?;
+        if !self.data_file_ids().contains(&file_id) {
+            return Err(Errors::DataFileNotFound);
+        }
+        Ok(RawScan {
+            engine: self,
+            file_id,
+            offset: 0,
+            done: false,
+        })
+    }
+}
+
+impl RawScan<'_> {
+    fn read_next(&mut self) -> Result<Option<RawRecord>> {
+        let active_file = self.engine.active_file.read();
+        let older_files = self.engine.older_files.read();
+        let data_file = match older_files.get(&self.file_id) {
+            Some(data_file) => data_file,
+            None if active_file.get_file_id() == self.file_id => {
+                // 活跃文件末尾可能有预分配的空间或者正在写入的数据
+                if self.offset >= active_file.get_write_off() {
+                    return Ok(None);
+                }
+                &active_file
+            }
+            None => return Err(Errors::DataFileNotFound),
+        };
+
+        let read_record = match data_file.read_log_record(self.offset) {
+            Ok(read_record) => read_record,
+            Err(Errors::ReadDataFileEOF) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let pos = LogRecordPos {
+            file_id: self.file_id,
+            offset: self.offset,
+            size: read_record.size,
+        };
+        self.offset += read_record.size as u64;
+
+        let record = read_record.record;
+        let (key, seq_no) = parse_log_record_key(record.key)?;
+        Ok(Some(RawRecord {
+            key,
+            value: record.value,
+            rec_type: record.rec_type,
+            seq_no,
+            pos,
+        }))
+    }
+}
+
+impl Iterator for RawScan<'_> {
+    type Item = Result<RawRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_next() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bytes::Bytes;
+
+    use crate::options::{EngineOptions, WriteBatchOptions};
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/raw".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_raw_scan() {
+        let name = "scan";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 128;
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("k1"), Bytes::from("v1")).unwrap();
+        engine.put(Bytes::from("k1"), Bytes::from("v2")).unwrap();
+        engine.delete(Bytes::from("k1")).unwrap();
+        {
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .unwrap();
+            wb.put(Bytes::from("k2"), Bytes::from("v3")).unwrap();
+            wb.commit().unwrap();
+        }
+
+        let records: Vec<RawRecord> = engine.raw_scan(0).unwrap().collect::<Result<_>>().unwrap();
+        let summary: Vec<(&[u8], &[u8], LogRecordType)> = records
+            .iter()
+            .map(|r| (r.key.as_slice(), r.value.as_slice(), r.rec_type))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (&b"k1"[..], &b"v1"[..], LogRecordType::Normal),
+                (b"k1", b"v2", LogRecordType::Normal),
+                (b"k1", b"", LogRecordType::Deleted),
+                (b"k2", b"v3", LogRecordType::Normal),
+                (TXN_FINISHED_KEY, b"", LogRecordType::TxnFinished),
+            ]
+        );
+        assert_eq!(records[0].seq_no, 0);
+        assert!(records[3].seq_no > 0);
+        assert_eq!(records[3].seq_no, records[4].seq_no);
+
+        // 位置是连续的, 和内存索引中的一样
+        assert_eq!(records[0].pos.offset(), 0);
+        for pair in records.windows(2) {
+            assert_eq!(
+                pair[1].pos.offset(),
+                pair[0].pos.offset() + pair[0].pos.size() as u64
+            );
+        }
+        assert_eq!(
+            engine.index.get(b"k2".to_vec()).unwrap(),
+            Some(records[3].pos)
+        );
+
+        // 写满之后切换活跃文件, 旧文件依然可以读取
+        for i in 0..20 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        let file_ids = engine.data_file_ids();
+        assert!(file_ids.len() > 1);
+        let total: usize = file_ids
+            .iter()
+            .map(|file_id| engine.raw_scan(*file_id).unwrap().count())
+            .sum();
+        assert_eq!(total, 25);
+        assert!(matches!(
+            engine.raw_scan(1000),
+            Err(Errors::DataFileNotFound)
+        ));
+
+        clean(name);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::prelude::*;
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::{
+    data::{
+        data_file::DataFile,
+        log_record::{LogRecord, LogRecordPos, LogRecordType},
+    },
+    db::Engine,
+    options::IteratorOptions,
+};
+
+/// 同一个进程内的只读副本
+/// 和主`Engine`共享旧数据文件以及内存索引, 不需要再次`open`, 也就不会和文件锁冲突
+/// 活跃文件使用副本自己打开的只读句柄, 读取时不会和写入线程竞争活跃文件的锁
+/// 每个线程可以各自创建一个副本, 用于并行读取
+pub struct ReadReplica<'a> {
+    engine: &'a Engine,
+    /// 副本私有的活跃文件句柄, 活跃文件切换之后重新打开
+    active_file: RwLock<Option<DataFile>>,
+}
+
+impl Engine {
+    /// 创建一个只读副本
+    pub fn read_replica(&self) -> ReadReplica<'_> {
+        ReadReplica {
+            engine: self,
+            active_file: RwLock::new(None),
+        }
+    }
+}
+
+impl ReadReplica<'_> {
+    pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let pos = self.engine.index.get(key.to_vec())?;
+        if pos.is_none() {
+            return Err(Errors::KeyNotFound);
+        }
+
+        self.get_value_by_position(&pos.unwrap())
+    }
+
+    pub fn list_keys(&self) -> Result<Vec<Bytes>> {
+        self.engine.index.list_keys()
+    }
+
+    /// 对数据库中的所有数据执行某个参数,函数返回false时终止
+    pub fn fold<F>(&self, f: F) -> Result<()>
+    where
+        F: Fn(Bytes, Bytes) -> bool,
+    {
+        let mut index_iter = self.engine.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            let value = self.get_value_by_position(pos)?;
+            if !f(Bytes::from(key.to_vec()), value) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
+        // 先从共享的旧数据文件中查找
+        let log_record = {
+            let older_files = self.engine.older_files.read();
+            match older_files.get(&log_record_pos.file_id) {
+                Some(data_file) => Some(self.read_log_record(data_file, log_record_pos)?),
+                None => None,
+            }
+        };
+
+        let log_record = match log_record {
+            Some(log_record) => log_record,
+            None => self.read_active_file(log_record_pos)?,
+        };
+
+        match log_record.rec_type {
+            LogRecordType::Deleted => Err(Errors::KeyNotFound),
+            _ => Ok(self
+                .engine
+                .resolve_blob(log_record.rec_type, log_record.value)?
+                .into()),
+        }
+    }
+
+    /// 从副本私有的活跃文件句柄中读取
+    fn read_active_file(&self, log_record_pos: &LogRecordPos) -> Result<LogRecord> {
+        {
+            let active_file = self.active_file.read();
+            if let Some(data_file) = active_file.as_ref() {
+                if data_file.get_file_id() == log_record_pos.file_id {
+                    return self.read_log_record(data_file, log_record_pos);
+                }
+            }
+        }
+
+        // 还没有打开, 或者主`Engine`已经切换了活跃文件
+        let mut active_file = self.active_file.write();
+        let is_opened =
+            matches!(active_file.as_ref(), Some(f) if f.get_file_id() == log_record_pos.file_id);
+        if !is_opened {
+            *active_file = Some(
+                DataFile::open_read_only(
+                    self.engine.options.dir_path.clone(),
+                    log_record_pos.file_id,
+                )?
+                .with_cipher(self.engine.cipher.clone())
+                .with_checksum(self.engine.options.checksum),
+            );
+        }
+
+        let data_file = active_file.as_ref().unwrap();
+        self.read_log_record(data_file, log_record_pos)
+    }
+
+    fn read_log_record(
+        &self,
+        data_file: &DataFile,
+        log_record_pos: &LogRecordPos,
+    ) -> Result<LogRecord> {
+        match data_file.read_log_record(log_record_pos.offset) {
+            Ok(read_log_record) => Ok(read_log_record.record),
+            Err(Errors::InvalidLogRecordCrc) => {
+                Err(self.engine.quarantine_record(data_file, log_record_pos))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::atomic::AtomicUsize, sync::atomic::Ordering};
+
+    use crate::options::EngineOptions;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/replica".into()
+    }
+
+    fn setup(name: &str) -> Engine {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 64 * 1024;
+        Engine::open(opts).expect("failed to open engine")
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_replica_get() {
+        let name = "get";
+        let engine = setup(name);
+
+        let replica = engine.read_replica();
+        assert!(matches!(
+            replica.get(Bytes::from("key")),
+            Err(Errors::KeyNotFound)
+        ));
+
+        engine
+            .put(Bytes::from("key"), Bytes::from("value"))
+            .unwrap();
+        assert_eq!(
+            replica.get(Bytes::from("key")).unwrap(),
+            Bytes::from("value")
+        );
+
+        // 写入的数据足够多, 活跃文件会切换, 副本依然可以读到
+        for i in 0..2000 {
+            let key = Bytes::from(format!("key-{:05}", i));
+            engine.put(key, Bytes::from(vec![b'v'; 128])).unwrap();
+        }
+        assert!(engine.older_files.read().len() > 0);
+        assert_eq!(
+            replica.get(Bytes::from("key")).unwrap(),
+            Bytes::from("value")
+        );
+        assert_eq!(
+            replica.get(Bytes::from("key-01999")).unwrap(),
+            Bytes::from(vec![b'v'; 128])
+        );
+
+        engine.delete(Bytes::from("key")).unwrap();
+        assert!(matches!(
+            replica.get(Bytes::from("key")),
+            Err(Errors::KeyNotFound)
+        ));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_replica_parallel_read() {
+        let name = "parallel";
+        let engine = setup(name);
+
+        for i in 0..1000 {
+            let key = Bytes::from(format!("key-{:05}", i));
+            engine
+                .put(key, Bytes::from(format!("value-{}", i)))
+                .unwrap();
+        }
+
+        let count = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            // 一个线程继续写入, 其他线程通过副本读取
+            s.spawn(|| {
+                for i in 1000..2000 {
+                    let key = Bytes::from(format!("key-{:05}", i));
+                    engine
+                        .put(key, Bytes::from(format!("value-{}", i)))
+                        .unwrap();
+                }
+            });
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let replica = engine.read_replica();
+                    for i in 0..1000 {
+                        let key = Bytes::from(format!("key-{:05}", i));
+                        let value = replica.get(key).unwrap();
+                        assert_eq!(value, Bytes::from(format!("value-{}", i)));
+                    }
+                    replica
+                        .fold(|_, _| {
+                            count.fetch_add(1, Ordering::SeqCst);
+                            true
+                        })
+                        .unwrap();
+                });
+            }
+        });
+        assert!(count.load(Ordering::SeqCst) >= 4 * 1000);
+
+        clean(name);
+    }
+}
@@ -0,0 +1,621 @@
+use crate::prelude::*;
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tracing::{info, warn};
+
+use crate::{
+    db::Engine,
+    options::IteratorOptions,
+    utils,
+    watch::{EventSeq, Operation, WatchFilter},
+};
+
+/// 副本保存同步位置的文件
+pub(crate) const REPLICATION_POSITION_FILE_NAME: &str = "replication-position";
+
+/// 没有数据时主节点发送心跳的间隔, 主节点和副本都在这个间隔内检查是否需要停止
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+/// 超过这个时间没有收到任何消息, 副本认为连接已经断开
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// 断开之后重新连接的间隔
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+/// 副本每应用多少个事件保存一次同步位置, 收到心跳和断开连接时也会保存
+const SAVE_POSITION_EVERY: usize = 1000;
+/// 单条消息内容的最大长度, 防止错误的长度字段导致一次分配过大的内存
+/// 超过这个长度的 key/value 无法复制
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+const MSG_HELLO: u8 = 1;
+const MSG_FULL_SYNC: u8 = 2;
+const MSG_SNAPSHOT_PUT: u8 = 3;
+const MSG_SNAPSHOT_END: u8 = 4;
+const MSG_RESUME: u8 = 5;
+const MSG_PUT: u8 = 6;
+const MSG_DELETE: u8 = 7;
+const MSG_HEARTBEAT: u8 = 8;
+
+/// 副本的同步位置
+/// 主节点重启后 merge 的结果生效, 数据在文件中的位置会发生变化,
+/// 所以用`run_id`标识主节点的一次启动, `run_id`不同时需要重新全量同步
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Position {
+    /// 为0表示还没有同步过
+    pub run_id: u64,
+    /// 下一个要应用的事件
+    pub seq: EventSeq,
+}
+
+/// 主节点和副本之间的消息, 编码为 类型(1字节) + 内容长度(4字节) + 内容
+#[derive(Debug, PartialEq)]
+enum Message {
+    /// 副本连接之后发送上次同步到的位置
+    Hello {
+        position: Position,
+    },
+    /// 主节点开始发送全量快照, 副本先清空自己的数据
+    FullSync,
+    SnapshotPut {
+        key: Bytes,
+        value: Bytes,
+    },
+    /// 快照发送完毕, 之后的事件从`position`开始
+    SnapshotEnd {
+        position: Position,
+    },
+    /// 主节点接受了副本的同步位置, 接下来直接发送之后的事件
+    Resume,
+    Put {
+        seq: EventSeq,
+        key: Bytes,
+        value: Bytes,
+    },
+    Delete {
+        seq: EventSeq,
+        key: Bytes,
+    },
+    Heartbeat,
+}
+
+impl Message {
+    fn encode(&self) -> Bytes {
+        let mut body = BytesMut::new();
+        let msg_type = match self {
+            Message::Hello { position } => {
+                put_position(&mut body, position);
+                MSG_HELLO
+            }
+            Message::FullSync => MSG_FULL_SYNC,
+            Message::SnapshotPut { key, value } => {
+                put_bytes(&mut body, key);
+                put_bytes(&mut body, value);
+                MSG_SNAPSHOT_PUT
+            }
+            Message::SnapshotEnd { position } => {
+                put_position(&mut body, position);
+                MSG_SNAPSHOT_END
+            }
+            Message::Resume => MSG_RESUME,
+            Message::Put { seq, key, value } => {
+                put_seq(&mut body, seq);
+                put_bytes(&mut body, key);
+                put_bytes(&mut body, value);
+                MSG_PUT
+            }
+            Message::Delete { seq, key } => {
+                put_seq(&mut body, seq);
+                put_bytes(&mut body, key);
+                MSG_DELETE
+            }
+            Message::Heartbeat => MSG_HEARTBEAT,
+        };
+
+        let mut buf = BytesMut::with_capacity(5 + body.len());
+        buf.put_u8(msg_type);
+        buf.put_u32(body.len() as u32);
+        buf.extend_from_slice(&body);
+        buf.freeze()
+    }
+
+    fn decode(msg_type: u8, mut body: Bytes) -> Result<Message> {
+        let msg = match msg_type {
+            MSG_HELLO => Message::Hello {
+                position: get_position(&mut body)?,
+            },
+            MSG_FULL_SYNC => Message::FullSync,
+            MSG_SNAPSHOT_PUT => Message::SnapshotPut {
+                key: get_bytes(&mut body)?,
+                value: get_bytes(&mut body)?,
+            },
+            MSG_SNAPSHOT_END => Message::SnapshotEnd {
+                position: get_position(&mut body)?,
+            },
+            MSG_RESUME => Message::Resume,
+            MSG_PUT => Message::Put {
+                seq: get_seq(&mut body)?,
+                key: get_bytes(&mut body)?,
+                value: get_bytes(&mut body)?,
+            },
+            MSG_DELETE => Message::Delete {
+                seq: get_seq(&mut body)?,
+                key: get_bytes(&mut body)?,
+            },
+            MSG_HEARTBEAT => Message::Heartbeat,
+            _ => {
+                return Err(Errors::InvalidReplicationMessage(format!(
+                    "unknown message type {}",
+                    msg_type
+                )))
+            }
+        };
+        if body.has_remaining() {
+            return Err(Errors::InvalidReplicationMessage(
+                "unexpected trailing bytes".to_string(),
+            ));
+        }
+        Ok(msg)
+    }
+}
+
+fn put_seq(buf: &mut BytesMut, seq: &EventSeq) {
+    buf.put_u32(seq.file_id);
+    buf.put_u64(seq.offset);
+}
+
+fn put_position(buf: &mut BytesMut, position: &Position) {
+    buf.put_u64(position.run_id);
+    put_seq(buf, &position.seq);
+}
+
+fn put_bytes(buf: &mut BytesMut, data: &[u8]) {
+    buf.put_u32(data.len() as u32);
+    buf.extend_from_slice(data);
+}
+
+fn check_remaining(buf: &Bytes, len: usize) -> Result<()> {
+    if buf.remaining() < len {
+        return Err(Errors::InvalidReplicationMessage(
+            "message is truncated".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn get_seq(buf: &mut Bytes) -> Result<EventSeq> {
+    check_remaining(buf, 12)?;
+    Ok(EventSeq {
+        file_id: buf.get_u32(),
+        offset: buf.get_u64(),
+    })
+}
+
+fn get_position(buf: &mut Bytes) -> Result<Position> {
+    check_remaining(buf, 8)?;
+    let run_id = buf.get_u64();
+    Ok(Position {
+        run_id,
+        seq: get_seq(buf)?,
+    })
+}
+
+fn get_bytes(buf: &mut Bytes) -> Result<Bytes> {
+    check_remaining(buf, 4)?;
+    let len = buf.get_u32() as usize;
+    check_remaining(buf, len)?;
+    Ok(buf.split_to(len))
+}
+
+fn check_message_size(len: usize) -> Result<()> {
+    if len > MAX_MESSAGE_SIZE {
+        return Err(Errors::InvalidReplicationMessage(format!(
+            "message size {} exceeds the limit {}",
+            len, MAX_MESSAGE_SIZE
+        )));
+    }
+    Ok(())
+}
+
+fn write_message(writer: &mut impl Write, msg: &Message) -> Result<()> {
+    let encoded = msg.encode();
+    check_message_size(encoded.len() - 5)?;
+    writer.write_all(&encoded)?;
+    Ok(())
+}
+
+fn read_message(reader: &mut impl Read) -> Result<Message> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    check_message_size(len)?;
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+    Message::decode(header[0], body.into())
+}
+
+/// 每次打开数据库时生成, 不为0
+pub(crate) fn new_run_id() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    std::cmp::max(nanos, 1)
+}
+
+impl Engine {
+    /// 作为主节点接受副本的连接, 按顺序把已经写入的数据变更发送给副本
+    /// 异步复制, 写入不等待副本确认; 每个副本使用一个线程, 阻塞直到`shutdown`被设置为 true
+    pub fn serve_replication(&self, listener: TcpListener, shutdown: &AtomicBool) -> Result<()> {
+        self.check_persistent("replication")?;
+        listener.set_nonblocking(true)?;
+        std::thread::scope(|s| {
+            while !shutdown.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, addr)) => {
+                        s.spawn(move || {
+                            info!(%addr, "replica connected");
+                            if let Err(e) = self.serve_replica(stream, shutdown) {
+                                warn!(%addr, "replica disconnected: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(HEARTBEAT_INTERVAL);
+                    }
+                    Err(e) => return Err(Errors::IO(e)),
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn serve_replica(&self, stream: TcpStream, shutdown: &AtomicBool) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        let position = match read_message(&mut reader)? {
+            Message::Hello { position } => position,
+            msg => {
+                return Err(Errors::InvalidReplicationMessage(format!(
+                    "expected hello, got {:?}",
+                    msg
+                )))
+            }
+        };
+
+        // 先注册订阅, 再发送快照, 订阅之后的写入通过事件发送
+        // 快照中可能已经包含了其中的部分事件, 按顺序重复应用的结果不变
+        let mut sub = if position.run_id == self.run_id {
+            write_message(&mut writer, &Message::Resume)?;
+            self.subscribe_from(position.seq, WatchFilter::default())
+        } else {
+            write_message(&mut writer, &Message::FullSync)?;
+            let sub = self.subscribe(WatchFilter::default());
+            self.send_snapshot(&mut writer)?;
+            let position = Position {
+                run_id: self.run_id,
+                seq: sub.live_seq(),
+            };
+            write_message(&mut writer, &Message::SnapshotEnd { position })?;
+            sub
+        };
+        writer.flush()?;
+
+        // 副本跟不上时订阅的队列会满, 返回错误断开连接, 副本重新连接后从保存的位置回放
+        while !shutdown.load(Ordering::SeqCst) {
+            let mut event = sub.recv_timeout(HEARTBEAT_INTERVAL)?;
+            if event.is_none() {
+                write_message(&mut writer, &Message::Heartbeat)?;
+            }
+            // 一次发送所有已经到达的事件, 然后再 flush
+            while let Some(e) = event {
+                let msg = match e.op {
+                    Operation::Put => Message::Put {
+                        seq: e.seq,
+                        key: e.key,
+                        value: e.value,
+                    },
+                    Operation::Delete => Message::Delete {
+                        seq: e.seq,
+                        key: e.key,
+                    },
+                };
+                write_message(&mut writer, &msg)?;
+                event = sub.try_recv()?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn send_snapshot(&self, writer: &mut impl Write) -> Result<()> {
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            let value = match self.get_value_by_position(pos) {
+                Ok(value) => value,
+                Err(Errors::KeyNotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            let msg = Message::SnapshotPut {
+                key: Bytes::copy_from_slice(key),
+                value,
+            };
+            write_message(writer, &msg)?;
+        }
+        Ok(())
+    }
+
+    /// 作为副本从主节点同步数据, 阻塞直到`shutdown`被设置为 true
+    /// 断开后自动重连, 从上次保存的位置继续; 第一次同步或者主节点重启之后, 先清空本地数据再接收全量快照
+    /// 事务中的数据逐条应用, 不保证原子性; 副本依然可以写入, 但是会被主节点的数据覆盖
+    pub fn replicate_from(&self, addr: impl ToSocketAddrs, shutdown: &AtomicBool) -> Result<()> {
+        self.check_writable()?;
+        self.check_persistent("replication")?;
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        while !shutdown.load(Ordering::SeqCst) {
+            match self.replicate_once(&addrs, shutdown) {
+                Ok(()) => {}
+                Err(Errors::IO(e)) => {
+                    warn!("replication connection lost: {}", e);
+                    std::thread::sleep(RECONNECT_INTERVAL);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn replicate_once(&self, addrs: &[SocketAddr], shutdown: &AtomicBool) -> Result<()> {
+        let stream = TcpStream::connect(addrs)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let mut position = self.replication_position()?;
+        write_message(&mut writer, &Message::Hello { position })?;
+
+        // 距离上次保存位置之后应用的事件数量
+        let mut applied = 0;
+        let res = (|| {
+            while !shutdown.load(Ordering::SeqCst) {
+                match read_message(&mut reader)? {
+                    Message::FullSync => {
+                        position = Position::default();
+                        self.save_replication_position(&position)?;
+                        self.delete_matching(IteratorOptions::default(), &[], |_| true)?;
+                    }
+                    Message::SnapshotPut { key, value } => self.put(key, value)?,
+                    Message::SnapshotEnd { position: end } => {
+                        position = end;
+                        self.save_replication_position(&position)?;
+                    }
+                    Message::Resume => {}
+                    Message::Put { seq, key, value } => {
+                        self.put(key, value)?;
+                        position.seq = seq.next();
+                        applied += 1;
+                    }
+                    Message::Delete { seq, key } => {
+                        self.delete(key)?;
+                        position.seq = seq.next();
+                        applied += 1;
+                    }
+                    Message::Heartbeat => {
+                        if applied > 0 {
+                            self.save_replication_position(&position)?;
+                            applied = 0;
+                        }
+                    }
+                    msg => {
+                        return Err(Errors::InvalidReplicationMessage(format!(
+                            "unexpected message {:?}",
+                            msg
+                        )))
+                    }
+                }
+
+                if applied >= SAVE_POSITION_EVERY {
+                    self.save_replication_position(&position)?;
+                    applied = 0;
+                }
+            }
+            Ok(())
+        })();
+
+        if applied > 0 {
+            self.save_replication_position(&position)?;
+        }
+        res
+    }
+
+    /// 副本当前的同步位置
+    pub fn replication_position(&self) -> Result<Position> {
+        let path = self.options.dir_path.join(REPLICATION_POSITION_FILE_NAME);
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Position::default()),
+            Err(e) => return Err(Errors::IO(e)),
+        };
+        get_position(&mut Bytes::from(data))
+    }
+
+    /// 先持久化已经应用的数据, 保证保存的位置不会超前于数据,
+    /// 再写入临时文件并重命名, 避免崩溃时留下不完整的内容
+    fn save_replication_position(&self, position: &Position) -> Result<()> {
+        self.sync()?;
+        let path = self.options.dir_path.join(REPLICATION_POSITION_FILE_NAME);
+        let tmp_path = path.with_extension("tmp");
+        let mut buf = BytesMut::new();
+        put_position(&mut buf, position);
+        utils::file::write_file_atomic(&path, &tmp_path, &buf)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    use crate::options::EngineOptions;
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/replication".into()
+    }
+
+    fn open(name: &str) -> Engine {
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        Engine::open(opts).unwrap()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    /// 等待副本中`key`的值变成`expected`, `None`表示不存在
+    fn wait_for(replica: &Engine, key: &str, expected: Option<&str>) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let value = replica.get(Bytes::from(key.to_string())).ok();
+            if value.as_deref() == expected.map(|v| v.as_bytes()) {
+                return;
+            }
+            assert!(Instant::now() < deadline, "timeout waiting for {}", key);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// 启动主节点和副本, 执行`f`之后停止
+    fn with_replication(primary: &Engine, replica: &Engine, f: impl FnOnce()) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            let primary_thread = s.spawn(|| primary.serve_replication(listener, &shutdown));
+            let replica_thread = s.spawn(|| replica.replicate_from(addr, &shutdown));
+            f();
+            shutdown.store(true, Ordering::SeqCst);
+            primary_thread.join().unwrap().unwrap();
+            replica_thread.join().unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_message_encode_decode() {
+        let messages = vec![
+            Message::Hello {
+                position: Position {
+                    run_id: 7,
+                    seq: EventSeq {
+                        file_id: 1,
+                        offset: 100,
+                    },
+                },
+            },
+            Message::FullSync,
+            Message::SnapshotPut {
+                key: Bytes::from("key"),
+                value: Bytes::new(),
+            },
+            Message::Put {
+                seq: EventSeq::default(),
+                key: Bytes::from("key"),
+                value: Bytes::from("value"),
+            },
+            Message::Delete {
+                seq: EventSeq::default(),
+                key: Bytes::from("key"),
+            },
+            Message::Heartbeat,
+        ];
+        let mut buf = vec![];
+        for msg in messages.iter() {
+            write_message(&mut buf, msg).unwrap();
+        }
+        let mut reader = buf.as_slice();
+        for msg in messages.iter() {
+            assert_eq!(&read_message(&mut reader).unwrap(), msg);
+        }
+
+        // 被截断的消息
+        let mut encoded = Message::Heartbeat.encode().to_vec();
+        encoded[4] = 3;
+        encoded.extend_from_slice(&[0, 0, 0]);
+        assert!(matches!(
+            read_message(&mut encoded.as_slice()),
+            Err(Errors::InvalidReplicationMessage(_))
+        ));
+
+        // 长度超过限制的消息, 不会按照长度分配内存
+        let mut encoded = Message::Heartbeat.encode().to_vec();
+        encoded[1..5].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            read_message(&mut encoded.as_slice()),
+            Err(Errors::InvalidReplicationMessage(_))
+        ));
+    }
+
+    #[test]
+    fn test_replication() {
+        clean("primary");
+        clean("replica");
+        let primary = open("primary");
+        let replica = open("replica");
+        primary
+            .put(Bytes::from("before"), Bytes::from("1"))
+            .unwrap();
+        // 全量同步时清空副本原来的数据
+        replica.put(Bytes::from("stale"), Bytes::from("1")).unwrap();
+
+        with_replication(&primary, &replica, || {
+            wait_for(&replica, "before", Some("1"));
+            wait_for(&replica, "stale", None);
+
+            primary.put(Bytes::from("live"), Bytes::from("2")).unwrap();
+            primary.delete(Bytes::from("before")).unwrap();
+            wait_for(&replica, "live", Some("2"));
+            wait_for(&replica, "before", None);
+        });
+        let position = replica.replication_position().unwrap();
+        assert_eq!(position.run_id, primary.run_id);
+
+        // 副本断开期间的写入, 重新连接后从上次的位置继续
+        primary
+            .put(Bytes::from("offline"), Bytes::from("3"))
+            .unwrap();
+        replica.put(Bytes::from("local"), Bytes::from("4")).unwrap();
+        with_replication(&primary, &replica, || {
+            wait_for(&replica, "offline", Some("3"));
+        });
+        // 没有重新全量同步
+        assert_eq!(replica.get(Bytes::from("local")).unwrap(), "4");
+        assert_eq!(replica.len().unwrap(), 3);
+
+        // 主节点重启之后重新全量同步
+        primary.close().unwrap();
+        drop(primary);
+        let primary = open("primary");
+        with_replication(&primary, &replica, || {
+            wait_for(&replica, "local", None);
+            wait_for(&replica, "offline", Some("3"));
+        });
+        assert_eq!(replica.len().unwrap(), 2);
+
+        clean("primary");
+        clean("replica");
+    }
+}
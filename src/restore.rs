@@ -0,0 +1,219 @@
+use crate::prelude::*;
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    data::HINT_FILE_NAME,
+    db::{Engine, FILE_LOCK_NAME},
+    index::spill::INDEX_SPILL_DIR_NAME,
+    ingest::INGEST_TMP_DIR_NAME,
+    manifest::{Manifest, FORMAT_VERSION},
+    merge::load_merge_info,
+    options::EngineOptions,
+    utils,
+};
+
+impl Engine {
+    /// 从`backup`复制的目录或者`backup_to_writer`生成的 tar 包恢复数据库, 校验通过后放到`dest_dir`并打开
+    /// 先解包到`dest_dir`旁边的临时目录, 校验失败时删除, 不会留下恢复了一半的目录
+    /// 需要每条数据都完整, 数据库写入时用`backup`复制的目录末尾可能有写了一半的数据, 这种情况使用`backup_to_writer`
+    /// `dest_dir`必须不存在或者为空, `options.dir_path`会被替换成`dest_dir`
+    pub fn restore(
+        backup_path: impl AsRef<Path>,
+        dest_dir: impl AsRef<Path>,
+        mut options: EngineOptions,
+    ) -> Result<Engine> {
+        let (backup_path, dest_dir) = (backup_path.as_ref(), dest_dir.as_ref());
+        if dest_dir.exists() && fs::read_dir(dest_dir)?.next().is_some() {
+            return Err(Errors::InvalidBackup(format!(
+                "destination {} is not empty",
+                dest_dir.display()
+            )));
+        }
+        let Some(dest_name) = dest_dir.file_name() else {
+            return Err(Errors::InvalidBackup(format!(
+                "invalid destination {}",
+                dest_dir.display()
+            )));
+        };
+        let staging_dir =
+            dest_dir.with_file_name(format!("{}-restoring", dest_name.to_string_lossy()));
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+
+        let res = unpack_backup(backup_path, &staging_dir)
+            .and_then(|_| validate_backup(&staging_dir, &options));
+        if let Err(e) = res {
+            let _ = fs::remove_dir_all(&staging_dir);
+            return Err(e);
+        }
+
+        if dest_dir.exists() {
+            fs::remove_dir(dest_dir)?;
+        }
+        fs::rename(&staging_dir, dest_dir)?;
+        options.dir_path = dest_dir.to_path_buf();
+        Engine::open(options)
+    }
+}
+
+/// 目录按`backup`的结果复制, 文件按 tar 包解包
+fn unpack_backup(backup_path: &Path, staging_dir: &PathBuf) -> Result<()> {
+    if backup_path.is_dir() {
+        let exclude = [FILE_LOCK_NAME, INDEX_SPILL_DIR_NAME, INGEST_TMP_DIR_NAME];
+        utils::file::copy_dir(backup_path.to_path_buf(), staging_dir.clone(), &exclude)?;
+    } else {
+        fs::create_dir_all(staging_dir)?;
+        tar::Archive::new(File::open(backup_path)?).unpack(staging_dir)?;
+    }
+    Ok(())
+}
+
+/// 校验清单、merge 完成的标识和所有数据的 crc
+fn validate_backup(dir_path: &Path, options: &EngineOptions) -> Result<()> {
+    if let Some(manifest) = Manifest::load(dir_path)? {
+        if manifest.format_version > FORMAT_VERSION {
+            return Err(Errors::UnsupportedFormatVersion {
+                version: manifest.format_version,
+                supported: FORMAT_VERSION,
+            });
+        }
+    }
+
+    // 有 merge 的结果时, 之前的数据文件已经被删除, 只能从 hint 文件加载索引
+    let merge = load_merge_info(dir_path)
+        .map_err(|_| Errors::InvalidBackup("invalid merge finished file".to_string()))?;
+    if let Some(merge) = merge {
+        if merge.non_merge_fid > 0 && !dir_path.join(HINT_FILE_NAME).is_file() {
+            return Err(Errors::InvalidBackup(
+                "merge finished file exists without hint file".to_string(),
+            ));
+        }
+    }
+
+    // 只读打开不会修改备份, 索引指向的位置也一起检查
+    let mut verify_options = options.clone();
+    verify_options.dir_path = dir_path.to_path_buf();
+    verify_options.read_only = true;
+    let report = Engine::open(verify_options)?.verify()?;
+    if !report.is_ok() {
+        return Err(Errors::InvalidBackup(format!(
+            "{} corrupt records, {} index mismatches",
+            report.corrupt_records.len(),
+            report.index_mismatches.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{
+        data::{data_file::DataFile, MERGE_FINISHED_FILE_NAME},
+        merge::MERGE_FIN_KEY,
+    };
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/restore".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn open_source(name: &str) -> (Engine, EngineOptions) {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name).join("source");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        (engine, opts)
+    }
+
+    #[test]
+    fn test_restore_from_dir_and_tar() {
+        let name = "dir_and_tar";
+        let (engine, opts) = open_source(name);
+        let base = basepath().join(name);
+
+        engine.backup(base.join("backup-dir")).unwrap();
+        let archive = engine.backup_to_writer(Vec::new()).unwrap();
+        fs::write(base.join("backup.tar"), archive).unwrap();
+
+        let restored =
+            Engine::restore(base.join("backup-dir"), base.join("from-dir"), opts.clone())
+                .expect("failed to restore from dir");
+        assert_eq!(restored.len().unwrap(), 100);
+        drop(restored);
+
+        // 目标目录存在但是为空
+        fs::create_dir_all(base.join("from-tar")).unwrap();
+        let restored =
+            Engine::restore(base.join("backup.tar"), base.join("from-tar"), opts.clone())
+                .expect("failed to restore from tar");
+        assert_eq!(
+            restored.get(Bytes::from("key-99")).unwrap(),
+            Bytes::from("value")
+        );
+        assert!(!base.join("from-tar-restoring").exists());
+
+        // 目标目录不为空
+        assert!(matches!(
+            Engine::restore(base.join("backup.tar"), base.join("from-dir"), opts),
+            Err(Errors::InvalidBackup(_))
+        ));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_restore_rejects_invalid_backup() {
+        let name = "invalid";
+        let (engine, opts) = open_source(name);
+        let base = basepath().join(name);
+        let backup_dir = base.join("backup");
+        engine.backup(backup_dir.clone()).unwrap();
+        drop(engine);
+
+        // merge 完成的标识没有对应的 hint 文件
+        let merge_fin_file = DataFile::new_merge_fin_file(backup_dir.clone()).unwrap();
+        let record = crate::data::log_record::LogRecord {
+            key: MERGE_FIN_KEY.to_vec(),
+            value: b"1".to_vec(),
+            rec_type: crate::data::log_record::LogRecordType::Normal,
+            timestamp: 0,
+        };
+        merge_fin_file.write(&record.encode().unwrap()).unwrap();
+        assert!(matches!(
+            Engine::restore(&backup_dir, base.join("dest"), opts.clone()),
+            Err(Errors::InvalidBackup(_))
+        ));
+        fs::remove_file(backup_dir.join(MERGE_FINISHED_FILE_NAME)).unwrap();
+
+        // 数据损坏
+        let data_file = crate::data::data_file::get_data_file_name(&backup_dir, 0);
+        let mut content = fs::read(&data_file).unwrap();
+        let last = content.len() - 1;
+        content[last] ^= 0xff;
+        fs::write(&data_file, content).unwrap();
+        assert!(matches!(
+            Engine::restore(&backup_dir, base.join("dest"), opts),
+            Err(Errors::InvalidBackup(_))
+        ));
+        assert!(!base.join("dest").exists());
+        assert!(!base.join("dest-restoring").exists());
+
+        clean(name);
+    }
+}
@@ -0,0 +1,417 @@
+use crate::prelude::*;
+
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::{
+    data::log_record::LogRecordPos,
+    db::Engine,
+    index::{bound_as_slice, start_bound, IndexIterator},
+    iterator::Iterator,
+    options::IteratorOptions,
+};
+
+static NEXT_SNAPSHOT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// 数据库某一时刻的快照
+/// 创建时不拷贝内存索引, 之后修改索引之前先把`key`原来的位置记到快照中, 读取时优先使用记下的位置,
+/// 占用的内存和快照存在期间修改的`key`的数量成正比
+/// 数据文件只会追加, merge 之后的文件要等到下次启动才会替换, 所以快照中的位置一直有效
+pub struct Snapshot<'a> {
+    engine: &'a Engine,
+    view: Arc<SnapshotView>,
+}
+
+/// 快照创建之后被修改的`key`在快照中的位置, 由引擎和快照共享
+pub(crate) struct SnapshotView {
+    id: u64,
+    /// 位置为 None 表示创建快照时`key`不存在
+    overwritten: RwLock<BTreeMap<Vec<u8>, Option<LogRecordPos>>>,
+}
+
+impl SnapshotView {
+    /// 只记录第一次修改之前的位置
+    fn preserve(&self, key: &[u8], pos: Option<LogRecordPos>) {
+        let mut overwritten = self.overwritten.write();
+        if !overwritten.contains_key(key) {
+            overwritten.insert(key.to_vec(), pos);
+        }
+    }
+
+    /// `live`是从内存索引中读到的位置, 必须在调用之前读取:
+    /// 修改索引之前已经记下了原来的位置, 这时读不到记录说明`live`是快照之前写入的
+    fn resolve(&self, key: &[u8], live: Option<LogRecordPos>) -> Option<LogRecordPos> {
+        match self.overwritten.read().get(key) {
+            Some(pos) => *pos,
+            None => live,
+        }
+    }
+}
+
+impl Engine {
+    /// 创建一个快照
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        // 防止看到提交了一半的事务
+        let _lock = self.batch_commit_lock.lock();
+        let view = Arc::new(SnapshotView {
+            id: NEXT_SNAPSHOT_ID.fetch_add(1, Ordering::SeqCst),
+            overwritten: RwLock::new(BTreeMap::new()),
+        });
+        self.snapshots.write().push(view.clone());
+        Snapshot { engine: self, view }
+    }
+
+    /// 修改内存索引, 位置为 None 表示删除, 返回旧的位置
+    /// 有快照时先把原来的位置记到快照中, 所有写入都要通过这里修改索引
+    pub(crate) fn update_index(
+        &self,
+        key: Vec<u8>,
+        pos: Option<LogRecordPos>,
+    ) -> Result<Option<LogRecordPos>> {
+        // 持有读锁直到索引修改完成, 创建快照时不会漏掉正在进行的修改
+        let snapshots = self.snapshots.read();
+        if !snapshots.is_empty() {
+            let old = self.index.get(key.clone())?;
+            for view in snapshots.iter() {
+                view.preserve(&key, old);
+            }
+        }
+        match pos {
+            Some(pos) => self.index.put(key, pos),
+            None => self.index.delete(key),
+        }
+    }
+}
+
+impl Snapshot<'_> {
+    pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let live = self.engine.index.get(key.to_vec())?;
+        match self.view.resolve(&key, live) {
+            Some(pos) => self.engine.get_value_by_position(&pos),
+            None => Err(Errors::KeyNotFound),
+        }
+    }
+
+    pub fn iter(&self, options: IteratorOptions) -> Iterator<'_> {
+        Iterator::new(
+            Box::new(SnapshotIterator::new(self, options.clone())),
+            self.engine,
+            &options,
+        )
+    }
+
+    pub fn list_keys(&self) -> Result<Vec<Bytes>> {
+        Ok(self.keys_iter().collect())
+    }
+
+    /// 见`Engine::keys_iter`, 返回的是创建快照时的`key`
+    pub fn keys_iter(&self) -> impl std::iter::Iterator<Item = Bytes> + '_ {
+        let mut iter = SnapshotIterator::new(self, IteratorOptions::default());
+        std::iter::from_fn(move || iter.next().map(|(key, _)| Bytes::copy_from_slice(key)))
+    }
+
+    /// 对快照中的所有数据执行某个参数,函数返回false时终止
+    pub fn fold<F>(&self, f: F) -> Result<()>
+    where
+        F: Fn(Bytes, Bytes) -> bool,
+    {
+        let iter = self.iter(IteratorOptions::default());
+        while let Some((key, value)) = iter.next()? {
+            if !f(key, value) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Snapshot<'_> {
+    fn drop(&mut self) {
+        let mut snapshots = self.engine.snapshots.write();
+        snapshots.retain(|view| view.id != self.view.id);
+    }
+}
+
+/// 合并内存索引和快照中记下的位置, 按顺序返回快照中的数据
+/// 快照之后写入的`key`记下的位置是 None, 会被跳过; 快照之后删除的`key`只在记下的位置中
+struct SnapshotIterator {
+    live: Box<dyn IndexIterator>,
+    view: Arc<SnapshotView>,
+    /// 从内存索引中读到、还没有返回的数据
+    live_next: Option<(Vec<u8>, LogRecordPos)>,
+    live_finished: bool,
+    /// 下一次在记下的位置中从哪里开始查找, 正向时是下界, 反向时是上界
+    bound: Bound<Vec<u8>>,
+    curr: Option<(Vec<u8>, LogRecordPos)>,
+    options: IteratorOptions,
+}
+
+impl SnapshotIterator {
+    fn new(snapshot: &Snapshot, options: IteratorOptions) -> Self {
+        SnapshotIterator {
+            live: snapshot.engine.index.iterator(options.clone()),
+            view: snapshot.view.clone(),
+            live_next: None,
+            live_finished: false,
+            bound: start_bound(&options, None),
+            curr: None,
+            options,
+        }
+    }
+
+    fn reset(&mut self, bound: Bound<Vec<u8>>) {
+        self.live_next = None;
+        self.live_finished = false;
+        self.bound = bound;
+        self.curr = None;
+    }
+
+    /// 按遍历方向在记下的位置中找下一个带前缀的`key`
+    fn next_overwritten(&self) -> Option<(Vec<u8>, Option<LogRecordPos>)> {
+        let overwritten = self.view.overwritten.read();
+        let bound = bound_as_slice(&self.bound);
+        let entry = match self.options.reverse {
+            false => overwritten
+                .range::<[u8], _>((bound, Bound::Unbounded))
+                .next(),
+            true => overwritten
+                .range::<[u8], _>((Bound::Unbounded, bound))
+                .next_back(),
+        };
+        entry
+            .filter(|(key, _)| key.starts_with(&self.options.prefix))
+            .map(|(key, pos)| (key.clone(), *pos))
+    }
+
+    /// 按遍历方向`a`是否在`b`前面
+    fn before(&self, a: &[u8], b: &[u8]) -> bool {
+        match self.options.reverse {
+            false => a < b,
+            true => a > b,
+        }
+    }
+}
+
+impl IndexIterator for SnapshotIterator {
+    fn rewind(&mut self) {
+        self.live.rewind();
+        self.reset(start_bound(&self.options, None));
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        self.live.seek(key.clone());
+        self.reset(start_bound(&self.options, Some(&key)));
+    }
+
+    fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+        loop {
+            // 先读内存索引再查找记下的位置, 和`Snapshot::get`的顺序相同
+            if self.live_next.is_none() && !self.live_finished {
+                self.live_next = self.live.next().map(|(key, pos)| (key.clone(), *pos));
+                self.live_finished = self.live_next.is_none();
+            }
+            let overwritten = self.next_overwritten();
+
+            let (key, pos) = match (self.live_next.take(), overwritten) {
+                (None, None) => {
+                    self.curr = None;
+                    return None;
+                }
+                (Some((key, live)), Some(entry)) if self.before(&key, &entry.0) => {
+                    let pos = self.view.resolve(&key, Some(live));
+                    (key, pos)
+                }
+                (Some((key, live)), None) => {
+                    let pos = self.view.resolve(&key, Some(live));
+                    (key, pos)
+                }
+                (live_next, Some((key, pos))) => {
+                    // 同一个`key`以记下的位置为准
+                    self.live_next = live_next.filter(|(live_key, _)| *live_key != key);
+                    (key, pos)
+                }
+            };
+            self.bound = Bound::Excluded(key.clone());
+            if let Some(pos) = pos {
+                self.curr = Some((key, pos));
+                return self.curr.as_ref().map(|(key, pos)| (key, pos));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::options::{EngineOptions, WriteBatchOptions};
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/snapshot".into()
+    }
+
+    fn setup(name: &str) -> Engine {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_merge_ratio = 0.0;
+        Engine::open(opts).expect("failed to open engine")
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_snapshot_get() {
+        let name = "get";
+        let engine = setup(name);
+
+        engine
+            .put(Bytes::from("key-1"), Bytes::from("value-1"))
+            .unwrap();
+        engine
+            .put(Bytes::from("key-2"), Bytes::from("value-2"))
+            .unwrap();
+
+        let snapshot = engine.snapshot();
+
+        // 快照之后的修改不可见
+        engine
+            .put(Bytes::from("key-1"), Bytes::from("new-value"))
+            .unwrap();
+        engine.delete(Bytes::from("key-2")).unwrap();
+        engine
+            .put(Bytes::from("key-3"), Bytes::from("value-3"))
+            .unwrap();
+
+        assert_eq!(
+            snapshot.get(Bytes::from("key-1")).unwrap(),
+            Bytes::from("value-1")
+        );
+        assert_eq!(
+            snapshot.get(Bytes::from("key-2")).unwrap(),
+            Bytes::from("value-2")
+        );
+        assert!(matches!(
+            snapshot.get(Bytes::from("key-3")),
+            Err(Errors::KeyNotFound)
+        ));
+        assert_eq!(snapshot.list_keys().unwrap().len(), 2);
+
+        // 引擎读到的是最新数据
+        assert_eq!(
+            engine.get(Bytes::from("key-1")).unwrap(),
+            Bytes::from("new-value")
+        );
+        assert!(engine.get(Bytes::from("key-2")).is_err());
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_snapshot_iter() {
+        let name = "iter";
+        let engine = setup(name);
+
+        for i in 0..10 {
+            let key = Bytes::from(format!("key-{:02}", i));
+            engine.put(key, Bytes::from("old")).unwrap();
+        }
+
+        let snapshot = engine.snapshot();
+        let iter = snapshot.iter(IteratorOptions::default());
+
+        // 迭代过程中继续写入/删除/提交事务
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        for i in 0..20 {
+            let key = Bytes::from(format!("key-{:02}", i));
+            wb.put(key, Bytes::from("new")).unwrap();
+        }
+        wb.commit().unwrap();
+        engine.delete(Bytes::from("key-05")).unwrap();
+        engine.merge().unwrap();
+
+        let mut count = 0;
+        while let Some((_, value)) = iter.next().unwrap() {
+            assert_eq!(value, Bytes::from("old"));
+            count += 1;
+        }
+        assert_eq!(count, 10);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_snapshot_copy_on_write() {
+        let name = "cow";
+        let engine = setup(name);
+
+        for i in 0..100 {
+            let key = Bytes::from(format!("key-{:03}", i));
+            engine.put(key, Bytes::from(format!("old-{}", i))).unwrap();
+        }
+
+        // 创建快照不拷贝索引, 只记下之后修改的 key
+        let snapshot = engine.snapshot();
+        assert!(snapshot.view.overwritten.read().is_empty());
+        engine
+            .put(Bytes::from("key-010"), Bytes::from("new"))
+            .unwrap();
+        engine
+            .put(Bytes::from("key-010"), Bytes::from("newer"))
+            .unwrap();
+        engine.delete(Bytes::from("key-020")).unwrap();
+        engine
+            .put(Bytes::from("key-050-new"), Bytes::from("new"))
+            .unwrap();
+        assert_eq!(snapshot.view.overwritten.read().len(), 3);
+
+        let keys = snapshot.list_keys().unwrap();
+        assert_eq!(keys.len(), 100);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        assert!(!keys.contains(&Bytes::from("key-050-new")));
+
+        // 反向遍历带前缀的 key, 包括快照之后删除的 key
+        let mut opts = IteratorOptions::default();
+        opts.prefix = b"key-02".to_vec();
+        opts.reverse = true;
+        let iter = snapshot.iter(opts);
+        let mut values = vec![];
+        while let Some((key, value)) = iter.next().unwrap() {
+            values.push((key, value));
+        }
+        assert_eq!(values.len(), 10);
+        assert_eq!(values[0].0, Bytes::from("key-029"));
+        assert_eq!(values[9], (Bytes::from("key-020"), Bytes::from("old-20")));
+
+        iter.seek(b"key-025".to_vec());
+        assert_eq!(iter.next().unwrap().unwrap().0, Bytes::from("key-025"));
+
+        // 释放快照之后不再记录修改
+        drop(snapshot);
+        assert!(engine.snapshots.read().is_empty());
+        engine
+            .put(Bytes::from("key-030"), Bytes::from("new"))
+            .unwrap();
+
+        clean(name);
+    }
+}
@@ -1,12 +1,73 @@
-/// 记录数据库的统计信息
+use std::time::Duration;
+
+use crate::options::IndexType;
+
+/// 记录数据库的统计信息, 不需要遍历索引
 #[derive(Debug)]
 pub struct Stat {
     /// `key`的总数量
     pub key_num: usize,
+    /// 内存索引的信息
+    pub index: IndexStat,
     /// 数据文件的数量
     pub data_file_num: usize,
     /// 可以回收的数据量
     pub reclaim_size: usize,
     /// 数据目录占据的磁盘空间大小
     pub disk_size: usize,
+    /// 因为超过数据库大小上限而被拒绝的写入次数
+    pub db_full_count: usize,
+    /// 启动时从活跃文件末尾截断的字节数(写入到一半的数据)
+    pub discarded_bytes: usize,
+    /// 前台写入的限速状态, 没有限速时为空
+    pub write_throttle: Option<ThrottleStat>,
+    /// merge 的限速状态, 和前台写入共用额度时两者相同
+    pub merge_throttle: Option<ThrottleStat>,
+}
+
+/// 限速器的状态
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrottleStat {
+    pub bytes_per_sec: u64,
+    /// 当前可以使用的字节数, 为负数时之后的请求需要等待
+    pub available_bytes: i64,
+    /// 正在等待的请求数
+    pub waiting: usize,
+    /// 累计等待过的请求数
+    pub throttled_count: usize,
+    /// 累计等待的时间
+    pub throttled_time: Duration,
+}
+
+/// 内存索引的统计信息
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexStat {
+    pub index_type: IndexType,
+    /// 保存在内存中的`key`数量
+    pub in_memory_keys: usize,
+    /// 写到磁盘上的`key`数量, 只有`IndexType::Spill`会写磁盘
+    pub spilled_keys: usize,
+}
+
+/// 内存占用的估算值, 单位字节
+#[derive(Debug, Default, Clone)]
+pub struct MemoryUsage {
+    /// 内存索引
+    pub index: usize,
+    /// 数据文件句柄
+    pub data_files: usize,
+    /// 还没提交的`WriteBatch`暂存的数据
+    pub pending_batches: usize,
+    /// 订阅者
+    pub watchers: usize,
+    /// 整个进程通过 `CountingAllocator` 分配的内存, 需要安装为全局分配器
+    #[cfg(feature = "alloc-stats")]
+    pub allocated: usize,
+}
+
+impl MemoryUsage {
+    /// 引擎自身占用的内存
+    pub fn total(&self) -> usize {
+        self.index + self.data_files + self.pending_batches + self.watchers
+    }
 }
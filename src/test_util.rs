@@ -0,0 +1,389 @@
+//! 测试用的工具, 需要开启`test-util`特性
+//! 在打开数据库之前用`FaultInjector::install`给数据目录注入故障, 之后这个目录中新打开的文件都会经过`FaultInjectionIO`,
+//! 累计写入到指定的字节时写入失败, 之后所有的写入和 sync 都失败, 相当于进程在这里崩溃了
+//! 释放`FaultInjector`之后重新打开数据库, 就可以检查恢复之后的数据是否满足预期
+//! `read_data_file`把任意字节当作数据文件读取, 是 fuzz 测试的入口, 见`fuzz/`目录
+use crate::prelude::*;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::{
+    data::{data_file::DataFile, encryption::RecordCipher, log_record::LogRecordPos},
+    fio::{IOManager, IOType},
+    options::ChecksumType,
+};
+
+/// 注入的故障, 字节数从`install`之后开始累计, 包括数据目录中所有文件的写入
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// 写入第 N 个字节的那次写入失败, 一个字节也不写入
+    FailWriteAt(u64),
+    /// 只写入第 N 个字节之前的部分然后失败, 模拟写到一半时崩溃
+    TruncateWriteAt(u64),
+}
+
+#[derive(Debug)]
+struct FaultState {
+    fault: Fault,
+    /// 已经写入的字节数
+    written: u64,
+    /// 故障已经发生, 之后的写入和 sync 都失败
+    crashed: bool,
+}
+
+/// 注入了故障的数据目录, 释放时移除故障, 之后打开的文件不再受影响
+static INJECTED_DIRS: Mutex<Vec<(PathBuf, Arc<Mutex<FaultState>>)>> = Mutex::new(Vec::new());
+
+/// 数据目录的故障注入, 释放时移除
+pub struct FaultInjector {
+    dir_path: PathBuf,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjector {
+    /// 给`dir_path`注入故障, 需要在打开数据库之前调用, `dir_path`和`EngineOptions::dir_path`要一样
+    /// 同一个目录只能有一个`FaultInjector`, 之前的会被替换
+    pub fn install<P: Into<PathBuf>>(dir_path: P, fault: Fault) -> Self {
+        let dir_path = dir_path.into();
+        let state = Arc::new(Mutex::new(FaultState {
+            fault,
+            written: 0,
+            crashed: false,
+        }));
+        let mut injected = INJECTED_DIRS.lock();
+        injected.retain(|(dir, _)| *dir != dir_path);
+        injected.push((dir_path.clone(), state.clone()));
+        FaultInjector { dir_path, state }
+    }
+
+    /// 已经写入的字节数, 不发生故障时跑一遍可以知道一共有哪些位置可以注入故障
+    pub fn written(&self) -> u64 {
+        self.state.lock().written
+    }
+
+    /// 故障是否已经发生
+    pub fn crashed(&self) -> bool {
+        self.state.lock().crashed
+    }
+}
+
+impl Drop for FaultInjector {
+    fn drop(&mut self) {
+        INJECTED_DIRS
+            .lock()
+            .retain(|(dir, state)| *dir != self.dir_path || !Arc::ptr_eq(state, &self.state));
+    }
+}
+
+/// 文件在注入了故障的目录中时包装成`FaultInjectionIO`
+pub(crate) fn wrap_io_manager(
+    file_name: &Path,
+    io_manager: Box<dyn IOManager>,
+) -> Box<dyn IOManager> {
+    let injected = INJECTED_DIRS.lock();
+    match injected
+        .iter()
+        .find(|(dir, _)| file_name.parent() == Some(dir.as_path()))
+    {
+        Some((_, state)) => Box::new(FaultInjectionIO {
+            inner: io_manager,
+            state: state.clone(),
+        }),
+        None => io_manager,
+    }
+}
+
+/// 按`Fault`让写入失败的`IOManager`, 读取不受影响
+pub(crate) struct FaultInjectionIO {
+    inner: Box<dyn IOManager>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+fn injected_error() -> Errors {
+    Errors::IO(io::Error::other("injected fault"))
+}
+
+impl FaultInjectionIO {
+    /// 检查这次写入会不会触发故障, 返回可以写入的字节数和之后是否要返回错误
+    fn check_write(&self, len: usize) -> Result<(usize, bool)> {
+        let mut state = self.state.lock();
+        if state.crashed {
+            return Err(injected_error());
+        }
+        let end = state.written + len as u64;
+        let (limit, truncate) = match state.fault {
+            Fault::FailWriteAt(n) => (n, false),
+            Fault::TruncateWriteAt(n) => (n, true),
+        };
+        if end <= limit {
+            state.written = end;
+            return Ok((len, false));
+        }
+
+        state.crashed = true;
+        let allowed = match truncate {
+            true => (limit.saturating_sub(state.written)) as usize,
+            false => 0,
+        };
+        state.written += allowed as u64;
+        Ok((allowed, true))
+    }
+}
+
+impl IOManager for FaultInjectionIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.inner.read(buf, offset)
+    }
+
+    fn read_bytes(&self, offset: u64, len: usize) -> Result<Option<Bytes>> {
+        self.inner.read_bytes(offset, len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let (allowed, fail) = self.check_write(buf.len())?;
+        if allowed > 0 {
+            self.inner.write(&buf[..allowed])?;
+        }
+        match fail {
+            true => Err(injected_error()),
+            false => Ok(buf.len()),
+        }
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let (allowed, fail) = self.check_write(buf.len())?;
+        if allowed > 0 {
+            self.inner.write_at(&buf[..allowed], offset)?;
+        }
+        match fail {
+            true => Err(injected_error()),
+            false => Ok(buf.len()),
+        }
+    }
+
+    fn allocate(&self, size: u64) -> Result<()> {
+        self.inner.allocate(size)
+    }
+
+    fn sync(&self) -> Result<()> {
+        if self.state.lock().crashed {
+            return Err(injected_error());
+        }
+        self.inner.sync()
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.inner.size()
+    }
+}
+
+/// 把`data`当作数据文件依次读取, 返回读出来的数据条数, 遇到第一个错误时停止
+/// 和 hint 文件一样把读出来的`value`解析成`LogRecordPos`, 损坏的文件只能返回错误, 不能 panic
+pub fn read_data_file(
+    data: &[u8],
+    checksum: ChecksumType,
+    encryption_key: Option<[u8; 32]>,
+) -> usize {
+    let data_file = DataFile::new(PathBuf::new(), 0, IOType::InMemory)
+        .expect("in-memory data file")
+        .with_checksum(checksum)
+        .with_cipher(encryption_key.as_ref().map(RecordCipher::new));
+    data_file.write(data).expect("in-memory write");
+
+    let mut offset = 0;
+    let mut count = 0;
+    while let Ok(read) = data_file.read_log_record(offset) {
+        let _ = data_file.read_log_record_size(offset);
+        let _ = LogRecordPos::decode(read.record.value);
+        offset += read.size as u64;
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{
+        db::Engine,
+        options::{EngineOptions, WriteBatchOptions},
+    };
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/test_util".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn options(name: &str) -> EngineOptions {
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 256;
+        opts
+    }
+
+    fn batch_key(b: usize, j: usize) -> Bytes {
+        Bytes::from(format!("batch-{}-{}", b, j))
+    }
+
+    /// 写入的结果, 用于检查恢复之后的数据
+    #[derive(Default)]
+    struct Outcome {
+        committed_batches: usize,
+        /// 提交失败的批次, 恢复之后要么全部存在, 要么全部不存在
+        failed_batch: Option<usize>,
+        deleted: Vec<usize>,
+    }
+
+    fn setup_base(opts: &EngineOptions) {
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(Bytes::from(format!("base-{}", i)), Bytes::from("base"))
+                .unwrap();
+        }
+    }
+
+    /// 每个批次写入4个`key`, 然后删除一个已有的`key`, 遇到第一个错误时停止
+    fn run_workload(engine: &Engine) -> Outcome {
+        let mut outcome = Outcome::default();
+        for b in 0..5 {
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .unwrap();
+            for j in 0..4 {
+                wb.put(batch_key(b, j), Bytes::from(format!("value-{}", b)))
+                    .unwrap();
+            }
+            if wb.commit().is_err() {
+                outcome.failed_batch = Some(b);
+                return outcome;
+            }
+            outcome.committed_batches += 1;
+
+            if engine.delete(Bytes::from(format!("base-{}", b))).is_err() {
+                return outcome;
+            }
+            outcome.deleted.push(b);
+        }
+        outcome
+    }
+
+    fn check_recovery(engine: &Engine, outcome: &Outcome, crash_point: String) {
+        for b in 0..outcome.committed_batches {
+            for j in 0..4 {
+                assert_eq!(
+                    engine.get(batch_key(b, j)).ok(),
+                    Some(Bytes::from(format!("value-{}", b))),
+                    "{}: lost committed batch {}",
+                    crash_point,
+                    b
+                );
+            }
+        }
+        if let Some(b) = outcome.failed_batch {
+            let found = (0..4)
+                .filter(|j| engine.get(batch_key(b, *j)).is_ok())
+                .count();
+            assert!(
+                found == 0 || found == 4,
+                "{}: partial batch {}",
+                crash_point,
+                b
+            );
+        }
+        for i in 0..10 {
+            let res = engine.get(Bytes::from(format!("base-{}", i)));
+            match outcome.deleted.contains(&i) {
+                true => assert!(
+                    matches!(res, Err(Errors::KeyNotFound)),
+                    "{}: deleted key base-{} resurrected",
+                    crash_point,
+                    i
+                ),
+                false if i >= outcome.deleted.len() + 1 => assert!(res.is_ok()),
+                false => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_crash_recovery() {
+        let name = "crash";
+        let opts = options(name);
+
+        // 不发生故障时一共写入多少字节
+        clean(name);
+        setup_base(&opts);
+        let total = {
+            let injector =
+                FaultInjector::install(opts.dir_path.clone(), Fault::FailWriteAt(u64::MAX));
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+            let outcome = run_workload(&engine);
+            assert_eq!(outcome.committed_batches, 5);
+            injector.written()
+        };
+        assert!(total > 0);
+
+        for crash_at in (0..total).step_by(13) {
+            for fault in [
+                Fault::FailWriteAt(crash_at),
+                Fault::TruncateWriteAt(crash_at),
+            ] {
+                clean(name);
+                setup_base(&opts);
+
+                let outcome = {
+                    let injector = FaultInjector::install(opts.dir_path.clone(), fault);
+                    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+                    let outcome = run_workload(&engine);
+                    assert!(injector.crashed());
+                    outcome
+                };
+
+                let engine = Engine::open(opts.clone())
+                    .unwrap_or_else(|e| panic!("{:?}: failed to recover: {}", fault, e));
+                check_recovery(&engine, &outcome, format!("{:?}", fault));
+            }
+        }
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_read_data_file() {
+        use crate::data::log_record::{LogRecord, LogRecordType};
+
+        let mut data = vec![];
+        for i in 0..3 {
+            let record = LogRecord {
+                key: format!("key-{}", i).into_bytes(),
+                value: b"value".to_vec(),
+                rec_type: LogRecordType::Normal,
+                timestamp: 0,
+            };
+            data.extend(record.encode().unwrap());
+        }
+        assert_eq!(read_data_file(&data, ChecksumType::Crc32, None), 3);
+        // 校验方式不对时读不出数据
+        assert_eq!(read_data_file(&data, ChecksumType::XxHash64, None), 0);
+
+        // 损坏的数据之后的内容都读不到
+        let len = data.len();
+        data[len / 2] ^= 0xff;
+        data.extend([0xff; 16]);
+        assert!(read_data_file(&data, ChecksumType::Crc32, None) < 3);
+        assert_eq!(read_data_file(&[0xff; 16], ChecksumType::None, None), 0);
+    }
+}
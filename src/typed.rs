@@ -0,0 +1,205 @@
+use crate::prelude::*;
+use std::marker::PhantomData;
+
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{db::Engine, options::IteratorOptions};
+
+/// `key`/`value`的序列化方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SerdeFormat {
+    /// 二进制格式, 体积小
+    #[default]
+    Bincode,
+    /// JSON 文本, 方便用其他工具查看
+    Json,
+}
+
+impl SerdeFormat {
+    fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            SerdeFormat::Bincode => {
+                bincode::serialize(value).map_err(|e| Errors::SerializeFailed(e.to_string()))
+            }
+            SerdeFormat::Json => {
+                serde_json::to_vec(value).map_err(|e| Errors::SerializeFailed(e.to_string()))
+            }
+        }
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, buf: &[u8]) -> Result<T> {
+        match self {
+            SerdeFormat::Bincode => {
+                bincode::deserialize(buf).map_err(|e| Errors::DeserializeFailed(e.to_string()))
+            }
+            SerdeFormat::Json => {
+                serde_json::from_slice(buf).map_err(|e| Errors::DeserializeFailed(e.to_string()))
+            }
+        }
+    }
+}
+
+/// 带类型的`key`/`value`, 读写时自动序列化
+/// 所有`key`都加上`namespace`前缀, 同一个`Engine`上可以有多个不同类型的`TypedEngine`
+/// 序列化之后的`key`按字节排序, 不一定和`K`本身的顺序一致
+pub struct TypedEngine<'a, K, V> {
+    engine: &'a Engine,
+    namespace: Vec<u8>,
+    format: SerdeFormat,
+    _marker: PhantomData<fn(K, V)>,
+}
+
+impl Engine {
+    /// 创建一个`namespace`下的`TypedEngine`, 默认使用 bincode 序列化
+    pub fn typed<K, V>(&self, namespace: impl Into<Vec<u8>>) -> TypedEngine<'_, K, V>
+    where
+        K: Serialize + DeserializeOwned,
+        V: Serialize + DeserializeOwned,
+    {
+        TypedEngine {
+            engine: self,
+            namespace: namespace.into(),
+            format: SerdeFormat::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V> TypedEngine<'_, K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// 修改序列化方式, 已经写入的数据不会转换
+    pub fn with_format(mut self, format: SerdeFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn put_typed(&self, key: &K, value: &V) -> Result<()> {
+        let key = self.encode_key(key)?;
+        let value = self.format.serialize(value)?;
+        self.engine.put(key, Bytes::from(value))
+    }
+
+    /// `key`不存在时返回None
+    pub fn get_typed(&self, key: &K) -> Result<Option<V>> {
+        match self.engine.get(self.encode_key(key)?) {
+            Ok(value) => Ok(Some(self.format.deserialize(&value)?)),
+            Err(Errors::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn delete_typed(&self, key: &K) -> Result<()> {
+        self.engine.delete(self.encode_key(key)?)
+    }
+
+    /// 按序列化之后的字节顺序返回`namespace`下的所有数据
+    pub fn scan_typed(&self) -> Result<Vec<(K, V)>> {
+        let iter = self.engine.iter(IteratorOptions {
+            prefix: self.namespace.clone(),
+            ..Default::default()
+        });
+
+        let mut entries = Vec::new();
+        while let Some((key, value)) = iter.next()? {
+            let key = self.format.deserialize(&key[self.namespace.len()..])?;
+            let value = self.format.deserialize(&value)?;
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+
+    fn encode_key(&self, key: &K) -> Result<Bytes> {
+        let mut buf = self.namespace.clone();
+        buf.extend(self.format.serialize(key)?);
+        Ok(Bytes::from(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde::Deserialize;
+
+    use crate::options::EngineOptions;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+        tags: Vec<String>,
+    }
+
+    fn basepath() -> PathBuf {
+        "./tmp/typed".into()
+    }
+
+    fn setup(name: &str) -> Engine {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        Engine::open(opts).expect("failed to open engine")
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_typed_put_get_scan() {
+        let name = "put_get_scan";
+        let engine = setup(name);
+
+        for format in [SerdeFormat::Bincode, SerdeFormat::Json] {
+            let namespace = format!("{:?}:", format);
+            let users = engine
+                .typed::<u64, User>(namespace.clone())
+                .with_format(format);
+            assert_eq!(users.get_typed(&1).unwrap(), None);
+
+            let alice = User {
+                name: "alice".to_string(),
+                age: 30,
+                tags: vec!["admin".to_string()],
+            };
+            let bob = User {
+                name: "bob".to_string(),
+                age: 25,
+                tags: vec![],
+            };
+            users.put_typed(&1, &alice).unwrap();
+            users.put_typed(&2, &bob).unwrap();
+            assert_eq!(users.get_typed(&1).unwrap(), Some(alice.clone()));
+
+            // 其他 namespace 的数据不会被扫描到
+            let counters = engine.typed::<String, i64>("counter:").with_format(format);
+            counters.put_typed(&"visits".to_string(), &-3).unwrap();
+
+            let mut entries = users.scan_typed().unwrap();
+            entries.sort_by_key(|(id, _)| *id);
+            assert_eq!(entries, vec![(1, alice), (2, bob.clone())]);
+
+            users.delete_typed(&1).unwrap();
+            assert_eq!(users.scan_typed().unwrap(), vec![(2, bob)]);
+            assert_eq!(counters.get_typed(&"visits".to_string()).unwrap(), Some(-3));
+        }
+
+        // 用错误的类型读取时返回错误
+        engine
+            .put(Bytes::from("raw:key"), Bytes::from("value"))
+            .unwrap();
+        let raw = engine.typed::<String, User>("raw:");
+        assert!(matches!(
+            raw.scan_typed(),
+            Err(Errors::DeserializeFailed(_))
+        ));
+
+        clean(name);
+    }
+}
@@ -1,4 +1,8 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 pub fn create_dir_if_not_exist(path: &PathBuf) -> Result<(), std::io::Error> {
     if !path.is_dir() {
@@ -24,6 +28,29 @@ pub fn dir_disk_size(dir_path: &PathBuf) -> u64 {
     0
 }
 
+/// 先写入并持久化临时文件`tmp_path`, 再重命名为`path`并持久化所在的目录
+/// 崩溃之后`path`要么是旧的内容, 要么是完整的新内容
+pub fn write_file_atomic(path: &Path, tmp_path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut file = fs::File::create(tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(tmp_path, path)?;
+    match path.parent() {
+        Some(dir_path) => sync_dir(dir_path),
+        None => Ok(()),
+    }
+}
+
+/// 持久化目录, 之前在目录中创建、重命名的文件在掉电之后不会丢失
+/// windows 上不能打开目录, 重命名是否持久化由文件系统保证
+pub fn sync_dir(dir_path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    fs::File::open(dir_path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir_path;
+    Ok(())
+}
+
 /// 将`src`目录的内容拷贝到`dest`
 /// 如果`src`中的路径以`exclue`结尾,就忽略
 /// 如果`dest`不存在,就会创建目录
@@ -61,4 +88,20 @@ mod tests {
         assert_ne!(0, size);
         println!("available_disk_size: {:?}", size);
     }
+
+    #[test]
+    fn test_write_file_atomic() {
+        let dir_path = PathBuf::from("./tmp/utils_file/atomic");
+        let _ = fs::remove_dir_all(&dir_path);
+        fs::create_dir_all(&dir_path).unwrap();
+        let path = dir_path.join("file");
+        let tmp_path = dir_path.join("file.tmp");
+
+        write_file_atomic(&path, &tmp_path, b"old").unwrap();
+        write_file_atomic(&path, &tmp_path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!tmp_path.exists());
+
+        let _ = fs::remove_dir_all(&dir_path);
+    }
 }
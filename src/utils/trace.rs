@@ -0,0 +1,24 @@
+use std::time::Instant;
+
+use tracing::Span;
+
+/// 离开作用域时把经过的时间(微秒)记录到当前 span 的`elapsed_us`字段
+/// span 需要声明`elapsed_us = Empty`, 提前返回错误时也会记录
+pub(crate) struct SpanTimer {
+    span: Span,
+    start: Instant,
+}
+
+pub(crate) fn span_timer() -> SpanTimer {
+    SpanTimer {
+        span: Span::current(),
+        start: Instant::now(),
+    }
+}
+
+impl Drop for SpanTimer {
+    fn drop(&mut self) {
+        self.span
+            .record("elapsed_us", self.start.elapsed().as_micros() as u64);
+    }
+}
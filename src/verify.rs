@@ -0,0 +1,394 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+
+use tracing::warn;
+
+use crate::{
+    batch::parse_log_record_key,
+    data::{
+        data_file::DataFile,
+        log_record::{LogRecordPos, LogRecordType},
+    },
+    db::Engine,
+    options::IteratorOptions,
+};
+
+/// 数据文件中一条无法读取的数据
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptRecord {
+    pub file_id: u32,
+    pub offset: u64,
+    /// 读取时的错误
+    pub reason: String,
+}
+
+/// 内存索引中位置不正确的`key`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexMismatch {
+    pub key: Vec<u8>,
+    pub file_id: u32,
+    pub offset: u64,
+    pub reason: String,
+}
+
+/// `Engine::verify`的结果
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// 检查过的数据文件数量
+    pub files_checked: usize,
+    /// 检查过的数据条数, 包括损坏的数据
+    pub records_checked: usize,
+    /// 校验失败的数据
+    pub corrupt_records: Vec<CorruptRecord>,
+    /// 内存索引指向的位置读不到这个`key`的有效数据
+    pub index_mismatches: Vec<IndexMismatch>,
+}
+
+impl VerifyReport {
+    /// 没有发现任何问题
+    pub fn is_ok(&self) -> bool {
+        self.corrupt_records.is_empty() && self.index_mismatches.is_empty()
+    }
+}
+
+/// 内存索引中指向不存在的数据文件的`key`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DanglingEntry {
+    pub key: Vec<u8>,
+    pub file_id: u32,
+    pub offset: u64,
+}
+
+/// 打开时检查内存索引的结果, `RecoveryMode::None`时为空
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    /// 索引中引用了但是不存在的数据文件, 从小到大排列
+    pub missing_file_ids: Vec<u32>,
+    /// 指向这些数据文件的索引
+    pub dangling_entries: Vec<DanglingEntry>,
+    /// `dangling_entries`是否已经从内存索引中删除
+    pub repaired: bool,
+}
+
+impl RecoveryReport {
+    /// 没有发现任何问题
+    pub fn is_ok(&self) -> bool {
+        self.dangling_entries.is_empty()
+    }
+}
+
+impl Engine {
+    /// 打开时检查内存索引指向的数据文件是否存在, `repair`为 true 时删除这些索引
+    /// 只修改内存索引, 数据文件中的内容不变
+    pub(crate) fn check_dangling_index(&self, repair: bool) -> Result<RecoveryReport> {
+        let mut report = RecoveryReport::default();
+        {
+            let active_file_id = self.active_file.read().get_file_id();
+            let older_files = self.older_files.read();
+            let mut index_iter = self.index.iterator(IteratorOptions::default());
+            while let Some((key, pos)) = index_iter.next() {
+                if pos.file_id == active_file_id || older_files.contains_key(&pos.file_id) {
+                    continue;
+                }
+                if !report.missing_file_ids.contains(&pos.file_id) {
+                    report.missing_file_ids.push(pos.file_id);
+                }
+                report.dangling_entries.push(DanglingEntry {
+                    key: key.clone(),
+                    file_id: pos.file_id,
+                    offset: pos.offset,
+                });
+            }
+        }
+        report.missing_file_ids.sort();
+
+        if !report.is_ok() {
+            warn!(
+                "{} index entries point to missing data files {:?}",
+                report.dangling_entries.len(),
+                report.missing_file_ids
+            );
+        }
+        if repair && !report.is_ok() {
+            for entry in report.dangling_entries.iter() {
+                self.index.delete(entry.key.clone())?;
+            }
+            report.repaired = true;
+        }
+        Ok(report)
+    }
+
+    /// 打开时检查内存索引的结果, 见`EngineOptions::recovery_mode`
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// 校验所有数据文件中每条数据的 crc 和`key`的编码, 并检查内存索引指向的位置是否正确
+    /// 遇到损坏的数据时记录下来, 根据头部中的长度跳过, 继续检查后面的数据
+    /// 检查期间持有数据文件的读锁, 写入会被阻塞
+    pub fn verify(&self) -> Result<VerifyReport> {
+        self.check_open()?;
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+
+        let mut files: HashMap<u32, &DataFile> = older_files
+            .iter()
+            .map(|(file_id, data_file)| (*file_id, data_file))
+            .collect();
+        files.insert(active_file.get_file_id(), &active_file);
+
+        let mut report = VerifyReport::default();
+        let mut file_ids: Vec<u32> = files.keys().copied().collect();
+        file_ids.sort();
+        for file_id in file_ids {
+            verify_data_file(files[&file_id], &mut report);
+            report.files_checked += 1;
+        }
+
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            let mismatch = |reason: String| IndexMismatch {
+                key: key.clone(),
+                file_id: pos.file_id,
+                offset: pos.offset,
+                reason,
+            };
+            let reason = match files.get(&pos.file_id) {
+                Some(data_file) => self.check_index_position(data_file, key, pos),
+                None => Some("data file not found".to_string()),
+            };
+            if let Some(reason) = reason {
+                report.index_mismatches.push(mismatch(reason));
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// 从头到尾读取一个数据文件
+fn verify_data_file(data_file: &DataFile, report: &mut VerifyReport) {
+    let file_id = data_file.get_file_id();
+    let mut offset = 0;
+    loop {
+        let reason = match data_file.read_log_record(offset) {
+            Ok(read_log_record) => {
+                report.records_checked += 1;
+                match parse_log_record_key(read_log_record.record.key) {
+                    Ok(_) => {
+                        offset += read_log_record.size as u64;
+                        continue;
+                    }
+                    Err(e) => e.to_string(),
+                }
+            }
+            Err(Errors::ReadDataFileEOF) => break,
+            Err(e) => {
+                report.records_checked += 1;
+                e.to_string()
+            }
+        };
+
+        report.corrupt_records.push(CorruptRecord {
+            file_id,
+            offset,
+            reason,
+        });
+
+        // 头部也损坏时无法知道数据的长度, 后面的数据都检查不了
+        match data_file.read_log_record_size(offset) {
+            Ok(size) => offset += size as u64,
+            Err(Errors::ReadDataFileEOF) => break,
+            Err(e) => {
+                report.corrupt_records.push(CorruptRecord {
+                    file_id,
+                    offset,
+                    reason: format!("skip the rest of the file: {}", e),
+                });
+                break;
+            }
+        }
+    }
+}
+
+impl Engine {
+    /// 检查内存索引中的位置, 正确时返回None, 指向 blob 时还要能读到 blob
+    fn check_index_position(
+        &self,
+        data_file: &DataFile,
+        key: &[u8],
+        pos: &LogRecordPos,
+    ) -> Option<String> {
+        let read_log_record = match data_file.read_log_record(pos.offset) {
+            Ok(read_log_record) => read_log_record,
+            Err(e) => return Some(e.to_string()),
+        };
+        if read_log_record.size != pos.size {
+            return Some(format!(
+                "record size mismatch, index:{}, actual:{}",
+                pos.size, read_log_record.size
+            ));
+        }
+        let record = read_log_record.record;
+        if !matches!(
+            record.rec_type,
+            LogRecordType::Normal | LogRecordType::BlobIndex
+        ) {
+            return Some("record is not a normal record".to_string());
+        }
+        match parse_log_record_key(record.key) {
+            Ok((real_key, _)) if real_key == key => {}
+            Ok(_) => return Some("key mismatch".to_string()),
+            Err(e) => return Some(e.to_string()),
+        }
+        match self.resolve_blob(record.rec_type, record.value) {
+            Ok(_) => None,
+            Err(e) => Some(format!("blob: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Seek, SeekFrom, Write},
+        path::PathBuf,
+    };
+
+    use bytes::Bytes;
+
+    use crate::{
+        data::data_file::get_data_file_name,
+        options::{EngineOptions, RecoveryMode},
+    };
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/verify".into()
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    #[test]
+    fn test_verify_corrupt_record() {
+        let name = "corrupt";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 4 * 1024;
+        let engine = Engine::open(opts.clone()).unwrap();
+
+        for i in 0..100 {
+            let key = Bytes::from(format!("key-{:03}", i));
+            engine.put(key, Bytes::from(vec![b'v'; 64])).unwrap();
+        }
+        engine.delete(Bytes::from("key-000")).unwrap();
+        engine.sync().unwrap();
+
+        let report = engine.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert!(report.files_checked > 1);
+        assert_eq!(report.records_checked, 101);
+
+        // 修改一条数据中`value`的一个字节
+        let pos = engine.index.get(b"key-050".to_vec()).unwrap().unwrap();
+        let file_name = get_data_file_name(&opts.dir_path, pos.file_id);
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(file_name)
+            .unwrap();
+        let corrupt_offset = pos.offset + pos.size as u64 - 8;
+        let mut byte = [0u8; 1];
+        file.seek(SeekFrom::Start(corrupt_offset)).unwrap();
+        file.read_exact(&mut byte).unwrap();
+        file.seek(SeekFrom::Start(corrupt_offset)).unwrap();
+        file.write_all(&[!byte[0]]).unwrap();
+        file.sync_all().unwrap();
+
+        let report = engine.verify().unwrap();
+        assert!(!report.is_ok());
+        // 跳过损坏的数据之后继续检查
+        assert_eq!(report.records_checked, 101);
+        assert_eq!(
+            report.corrupt_records,
+            vec![CorruptRecord {
+                file_id: pos.file_id,
+                offset: pos.offset,
+                reason: Errors::InvalidLogRecordCrc.to_string(),
+            }]
+        );
+        assert_eq!(report.index_mismatches.len(), 1);
+        assert_eq!(report.index_mismatches[0].key, b"key-050".to_vec());
+
+        std::mem::drop(engine);
+        clean(name);
+    }
+
+    #[test]
+    fn test_recovery_report_dangling_index() {
+        let name = "dangling";
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 4 * 1024;
+        opts.data_file_merge_ratio = 0f32;
+        {
+            let engine = Engine::open(opts.clone()).unwrap();
+            for i in 0..100 {
+                let key = Bytes::from(format!("key-{:03}", i));
+                engine.put(key, Bytes::from(vec![b'v'; 64])).unwrap();
+            }
+            engine.merge().unwrap();
+        }
+
+        // 重启之后 merge 生效, 索引从 hint 文件加载, 然后手动删除一个 merge 之后的数据文件
+        let (file_id, total) = {
+            let engine = Engine::open(opts.clone()).unwrap();
+            assert!(engine.recovery_report().is_ok());
+            let pos = engine.index.get(b"key-000".to_vec()).unwrap().unwrap();
+            (pos.file_id, engine.len().unwrap())
+        };
+        std::fs::remove_file(get_data_file_name(&opts.dir_path, file_id)).unwrap();
+
+        // 默认不检查
+        let engine = Engine::open(opts.clone()).unwrap();
+        assert!(engine.recovery_report().is_ok());
+        assert!(matches!(
+            engine.get(Bytes::from("key-000")),
+            Err(Errors::DataFileNotFound)
+        ));
+        drop(engine);
+
+        opts.recovery_mode = RecoveryMode::Report;
+        let engine = Engine::open(opts.clone()).unwrap();
+        let report = engine.recovery_report().clone();
+        assert!(!report.is_ok());
+        assert!(!report.repaired);
+        assert_eq!(report.missing_file_ids, vec![file_id]);
+        assert!(report.dangling_entries.iter().all(|e| e.file_id == file_id));
+        assert!(report
+            .dangling_entries
+            .iter()
+            .any(|e| e.key == b"key-000".to_vec()));
+        assert_eq!(engine.len().unwrap(), total);
+        drop(engine);
+
+        opts.recovery_mode = RecoveryMode::Repair;
+        let engine = Engine::open(opts.clone()).unwrap();
+        let repaired = engine.recovery_report();
+        assert!(repaired.repaired);
+        assert_eq!(repaired.dangling_entries, report.dangling_entries);
+        assert_eq!(engine.len().unwrap(), total - report.dangling_entries.len());
+        assert!(matches!(
+            engine.get(Bytes::from("key-000")),
+            Err(Errors::KeyNotFound)
+        ));
+        assert!(engine.verify().unwrap().index_mismatches.is_empty());
+
+        clean(name);
+    }
+}
@@ -0,0 +1,735 @@
+use crate::prelude::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError},
+    },
+    time::Duration,
+};
+
+use bon::Builder;
+use bytes::Bytes;
+
+use crate::{
+    batch::parse_log_record_key,
+    data::log_record::{LogRecordPos, LogRecordType, ReadLogRecord},
+    db::Engine,
+};
+
+/// 事件的类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Put,
+    Delete,
+}
+
+/// 事件的序号, 也就是数据在数据文件中的位置, 按 (file_id, offset) 排序
+/// 重启后依然有效, 可以用于断点续传; merge 并重启之后旧的序号会失效
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventSeq {
+    pub file_id: u32,
+    pub offset: u64,
+}
+
+impl EventSeq {
+    /// 紧跟在当前事件之后的序号, 续传时传入上次处理的最后一个事件的`next()`
+    pub fn next(&self) -> EventSeq {
+        EventSeq {
+            file_id: self.file_id,
+            offset: self.offset + 1,
+        }
+    }
+}
+
+/// 数据变更事件
+#[derive(Debug, Clone)]
+pub struct WatchEvent {
+    pub seq: EventSeq,
+    pub op: Operation,
+    pub key: Bytes,
+    /// `Delete` 事件的 value 为空
+    pub value: Bytes,
+}
+
+/// `watch_prefix`返回的事件, 只包含变更的内容
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Put { key: Bytes, value: Bytes },
+    Delete { key: Bytes },
+}
+
+/// `subscribe`和`watch_prefix`的队列长度
+pub const WATCH_CHANNEL_CAPACITY: usize = 1024;
+
+/// 订阅过滤条件, 在引擎内部分发事件之前判断, 条件都为空时接收所有事件
+#[derive(Debug, Clone, Default, Builder)]
+pub struct WatchFilter {
+    /// `key`的前缀
+    #[builder(default)]
+    pub prefix: Vec<u8>,
+    /// `key`中第一个`:`之前的部分, 比如 `user:1` 的 tag 是 `user`
+    pub tag: Option<Vec<u8>>,
+    /// 事件类型
+    pub op: Option<Operation>,
+}
+
+impl WatchFilter {
+    fn matches(&self, key: &[u8], op: Operation) -> bool {
+        if !key.starts_with(&self.prefix) {
+            return false;
+        }
+        if let Some(tag) = &self.tag {
+            match key.iter().position(|b| *b == b':') {
+                Some(i) if &key[..i] == tag.as_slice() => {}
+                _ => return false,
+            }
+        }
+        if let Some(expected) = self.op {
+            if expected != op {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+static NEXT_WATCHER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// 订阅者
+pub(crate) struct Watcher {
+    id: u64,
+    filter: WatchFilter,
+    sender: WatchSender,
+    /// 小于这个序号的事件已经在回放中处理了
+    min_seq: EventSeq,
+}
+
+/// 事件的发送端, 都是有界队列, 接收端被丢弃或者队列满了之后取消订阅
+enum WatchSender {
+    /// `subscribe`使用, 丢弃`Subscription`时也会取消订阅
+    Subscription(SyncSender<WatchEvent>),
+    /// `watch_prefix`使用
+    Prefix(SyncSender<Event>),
+}
+
+impl WatchSender {
+    /// 发送事件, 返回 false 表示需要取消订阅
+    fn send(&self, event: WatchEvent) -> bool {
+        match self {
+            WatchSender::Subscription(sender) => match sender.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+            },
+            WatchSender::Prefix(sender) => {
+                let event = match event.op {
+                    Operation::Put => Event::Put {
+                        key: event.key,
+                        value: event.value,
+                    },
+                    Operation::Delete => Event::Delete { key: event.key },
+                };
+                match sender.try_send(event) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+                }
+            }
+        }
+    }
+}
+
+/// 订阅句柄, 先返回回放的历史事件, 然后返回实时事件
+/// 实时事件最多缓存`WATCH_CHANNEL_CAPACITY`个, 写入不会等待订阅者, 队列满了之后取消订阅,
+/// 取完已有的事件后返回`SubscriptionOverflow`, 可以用最后一个事件的`seq.next()`调用`subscribe_from`继续
+/// 丢弃句柄即取消订阅
+pub struct Subscription<'a> {
+    id: u64,
+    engine: &'a Engine,
+    filter: WatchFilter,
+    replay: Option<Replay>,
+    receiver: Receiver<WatchEvent>,
+    /// 实时事件的起始位置, 在这之前的数据在订阅时已经写入
+    live_seq: EventSeq,
+}
+
+impl Engine {
+    /// 订阅之后的数据变更
+    pub fn subscribe(&self, filter: WatchFilter) -> Subscription<'_> {
+        let (sender, receiver) = mpsc::sync_channel(WATCH_CHANNEL_CAPACITY);
+        let (id, end) = self.register_watcher(filter.clone(), WatchSender::Subscription(sender));
+        Subscription {
+            id,
+            engine: self,
+            filter,
+            replay: None,
+            receiver,
+            live_seq: end,
+        }
+    }
+
+    /// 先从数据文件中回放序号不小于`seq`的历史事件, 然后切换到实时事件
+    /// 每个事件只会返回一次, 不会重复也不会遗漏
+    pub fn subscribe_from(&self, seq: EventSeq, filter: WatchFilter) -> Subscription<'_> {
+        let (sender, receiver) = mpsc::sync_channel(WATCH_CHANNEL_CAPACITY);
+        let (id, end) = self.register_watcher(filter.clone(), WatchSender::Subscription(sender));
+
+        Subscription {
+            id,
+            engine: self,
+            filter,
+            replay: Some(Replay::new(self, seq, end)),
+            receiver,
+            live_seq: end,
+        }
+    }
+
+    /// 返回序号不小于`seq`的历史变更, 截止到调用时已经写入的数据, 用于增量备份和副本追赶\
+    /// 只读取`seq`之后的数据文件, 不需要扫描全部数据; 事务数据在提交之后才返回\
+    /// merge 并重启之后数据的位置会发生变化, 之前保存的`seq`失效, 需要重新全量读取
+    pub fn changes_since(&self, seq: EventSeq) -> Changes<'_> {
+        let end = {
+            // 防止事务提交到一半
+            let _lock = self.batch_commit_lock.lock();
+            self.write_end()
+        };
+        Changes {
+            engine: self,
+            replay: Replay::new(self, seq, end),
+        }
+    }
+
+    /// 订阅`key`以`prefix`开头的数据变更, 事件在内存索引更新之后发送
+    /// 队列最多存放`WATCH_CHANNEL_CAPACITY`个事件, 写入不会等待接收端,
+    /// 队列满了之后取消订阅, 接收端取完已有的事件后返回断开连接, 需要重新订阅并重新读取数据
+    /// 丢弃接收端即取消订阅
+    pub fn watch_prefix(&self, prefix: impl AsRef<[u8]>) -> Receiver<Event> {
+        let filter = WatchFilter {
+            prefix: prefix.as_ref().to_vec(),
+            ..Default::default()
+        };
+        let (sender, receiver) = mpsc::sync_channel(WATCH_CHANNEL_CAPACITY);
+        self.register_watcher(filter, WatchSender::Prefix(sender));
+        receiver
+    }
+
+    /// 注册订阅者, 返回订阅者id和注册时数据文件的末尾位置
+    /// 末尾之前的数据通过回放获取, 之后的数据通过实时事件获取
+    fn register_watcher(&self, filter: WatchFilter, sender: WatchSender) -> (u64, EventSeq) {
+        // 防止事务提交到一半
+        let _lock = self.batch_commit_lock.lock();
+        let mut watchers = self.watchers.write();
+        let end = self.write_end();
+
+        let id = NEXT_WATCHER_ID.fetch_add(1, Ordering::SeqCst);
+        watchers.push(Watcher {
+            id,
+            filter,
+            sender,
+            min_seq: end,
+        });
+        (id, end)
+    }
+
+    /// 活跃文件当前的末尾位置
+    fn write_end(&self) -> EventSeq {
+        let active_file = self.active_file.read();
+        EventSeq {
+            file_id: active_file.get_file_id(),
+            offset: active_file.get_write_off(),
+        }
+    }
+
+    /// 数据写入并更新索引之后, 通知订阅者和`EventListener`
+    pub(crate) fn notify_watchers(
+        &self,
+        op: Operation,
+        key: &[u8],
+        value: &[u8],
+        pos: &LogRecordPos,
+    ) {
+        if let Some(listener) = self.event_listener() {
+            match op {
+                Operation::Put => listener.on_put(key, value),
+                Operation::Delete => listener.on_delete(key),
+            }
+        }
+
+        let seq = EventSeq {
+            file_id: pos.file_id,
+            offset: pos.offset,
+        };
+
+        let mut closed = vec![];
+        let watchers = self.watchers.read();
+        for watcher in watchers.iter() {
+            if seq < watcher.min_seq || !watcher.filter.matches(key, op) {
+                continue;
+            }
+            let event = WatchEvent {
+                seq,
+                op,
+                key: Bytes::copy_from_slice(key),
+                value: Bytes::copy_from_slice(value),
+            };
+            if !watcher.sender.send(event) {
+                closed.push(watcher.id);
+            }
+        }
+        drop(watchers);
+
+        if !closed.is_empty() {
+            self.watchers
+                .write()
+                .retain(|watcher| !closed.contains(&watcher.id));
+        }
+    }
+
+    fn read_log_record_at(&self, file_id: u32, offset: u64) -> Result<ReadLogRecord> {
+        let active_file = self.active_file.read();
+        if active_file.get_file_id() == file_id {
+            return active_file.read_log_record(offset);
+        }
+
+        let older_files = self.older_files.read();
+        match older_files.get(&file_id) {
+            Some(data_file) => data_file.read_log_record(offset),
+            None => Err(Errors::DataFileNotFound),
+        }
+    }
+}
+
+impl Subscription<'_> {
+    pub(crate) fn live_seq(&self) -> EventSeq {
+        self.live_seq
+    }
+
+    /// 获取下一个事件, 没有事件时立即返回`None`
+    pub fn try_recv(&mut self) -> Result<Option<WatchEvent>> {
+        if let Some(event) = self.next_replay_event()? {
+            return Ok(Some(event));
+        }
+
+        match self.receiver.try_recv() {
+            Ok(event) => Ok(Some(event)),
+            Err(TryRecvError::Empty) => Ok(None),
+            // 发送端只会在队列满了之后被丢弃
+            Err(TryRecvError::Disconnected) => Err(Errors::SubscriptionOverflow),
+        }
+    }
+
+    /// 获取下一个事件, 最多等待`timeout`
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<WatchEvent>> {
+        if let Some(event) = self.next_replay_event()? {
+            return Ok(Some(event));
+        }
+
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(Errors::SubscriptionOverflow),
+        }
+    }
+
+    fn next_replay_event(&mut self) -> Result<Option<WatchEvent>> {
+        let replay = match self.replay.as_mut() {
+            Some(replay) => replay,
+            None => return Ok(None),
+        };
+
+        let event = replay.next(self.engine, &self.filter)?;
+        if event.is_none() {
+            // 回放完毕, 切换到实时事件
+            self.replay = None;
+        }
+        Ok(event)
+    }
+}
+
+/// `changes_since`返回的历史变更, 按序号从小到大返回
+pub struct Changes<'a> {
+    engine: &'a Engine,
+    replay: Replay,
+}
+
+impl Changes<'_> {
+    /// 变更的截止位置, 下次从这里继续调用`changes_since`
+    pub fn end(&self) -> EventSeq {
+        self.replay.end
+    }
+
+    /// 获取下一个变更, 读完之后返回`None`
+    pub fn next_change(&mut self) -> Result<Option<WatchEvent>> {
+        self.replay.next(self.engine, &WatchFilter::default())
+    }
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        let mut watchers = self.engine.watchers.write();
+        watchers.retain(|watcher| watcher.id != self.id);
+    }
+}
+
+/// 按顺序读取数据文件, 回放 [start, end) 之间的事件
+/// 事务数据读到 TxnFinished 之后才返回
+struct Replay {
+    start: EventSeq,
+    end: EventSeq,
+    file_ids: VecDeque<u32>,
+    offset: u64,
+    txn_events: HashMap<usize, Vec<WatchEvent>>,
+    ready: VecDeque<WatchEvent>,
+}
+
+impl Replay {
+    fn new(engine: &Engine, start: EventSeq, end: EventSeq) -> Self {
+        let mut file_ids: Vec<u32> = engine
+            .older_files
+            .read()
+            .keys()
+            .copied()
+            .filter(|id| *id >= start.file_id)
+            .collect();
+        if end.file_id >= start.file_id {
+            file_ids.push(end.file_id);
+        }
+        file_ids.sort();
+
+        Replay {
+            start,
+            end,
+            file_ids: file_ids.into(),
+            offset: 0,
+            txn_events: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    fn next(&mut self, engine: &Engine, filter: &WatchFilter) -> Result<Option<WatchEvent>> {
+        loop {
+            if let Some(event) = self.ready.pop_front() {
+                return Ok(Some(event));
+            }
+
+            let file_id = match self.file_ids.front() {
+                Some(file_id) => *file_id,
+                None => return Ok(None),
+            };
+            let seq = EventSeq {
+                file_id,
+                offset: self.offset,
+            };
+            if seq >= self.end {
+                return Ok(None);
+            }
+
+            let read_log_record = match engine.read_log_record_at(file_id, self.offset) {
+                Ok(read_log_record) => read_log_record,
+                Err(Errors::ReadDataFileEOF) => {
+                    self.file_ids.pop_front();
+                    self.offset = 0;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            self.offset += read_log_record.size as u64;
+
+            let record = read_log_record.record;
+            let (key, seq_no) = parse_log_record_key(record.key)?;
+            let op = match record.rec_type {
+                LogRecordType::Normal | LogRecordType::BlobIndex => Operation::Put,
+                LogRecordType::Deleted => Operation::Delete,
+                LogRecordType::TxnFinished => {
+                    if let Some(events) = self.txn_events.remove(&seq_no) {
+                        self.ready.extend(events);
+                    }
+                    continue;
+                }
+            };
+
+            if seq < self.start || !filter.matches(&key, op) {
+                continue;
+            }
+
+            let event = WatchEvent {
+                seq,
+                op,
+                key: key.into(),
+                value: engine.resolve_blob(record.rec_type, record.value)?.into(),
+            };
+            match seq_no == NON_TRANSACTION_SEQ_NO {
+                true => self.ready.push_back(event),
+                false => self.txn_events.entry(seq_no).or_default().push(event),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::options::{EngineOptions, WriteBatchOptions};
+
+    use super::*;
+
+    fn basepath() -> PathBuf {
+        "./tmp/watch".into()
+    }
+
+    fn setup(name: &str) -> EngineOptions {
+        clean(name);
+        let mut opts = EngineOptions::default();
+        opts.dir_path = basepath().join(name);
+        opts.data_file_size = 1024;
+        opts
+    }
+
+    fn clean(name: &str) {
+        let _ = std::fs::remove_dir_all(basepath().join(name));
+    }
+
+    fn drain(sub: &mut Subscription) -> Vec<WatchEvent> {
+        let mut events = vec![];
+        while let Some(event) = sub.try_recv().unwrap() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn test_watch_filter() {
+        let name = "filter";
+        let engine = Engine::open(setup(name)).unwrap();
+
+        let mut all = engine.subscribe(WatchFilter::default());
+        let mut users = engine.subscribe(WatchFilter::builder().tag(b"user".to_vec()).build());
+        let mut deletes = engine.subscribe(
+            WatchFilter::builder()
+                .prefix(b"order".to_vec())
+                .op(Operation::Delete)
+                .build(),
+        );
+
+        engine.put(Bytes::from("user:1"), Bytes::from("a")).unwrap();
+        engine
+            .put(Bytes::from("users:2"), Bytes::from("b"))
+            .unwrap();
+        engine
+            .put(Bytes::from("order:1"), Bytes::from("c"))
+            .unwrap();
+        engine.delete(Bytes::from("order:1")).unwrap();
+
+        let events = drain(&mut all);
+        assert_eq!(events.len(), 4);
+        assert!(events.windows(2).all(|w| w[0].seq < w[1].seq));
+
+        let events = drain(&mut users);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].key, Bytes::from("user:1"));
+        assert_eq!(events[0].value, Bytes::from("a"));
+
+        let events = drain(&mut deletes);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].op, Operation::Delete);
+        assert_eq!(events[0].key, Bytes::from("order:1"));
+
+        // 丢弃订阅句柄即取消订阅
+        std::mem::drop(all);
+        std::mem::drop(users);
+        assert_eq!(engine.watchers.read().len(), 1);
+        std::mem::drop(deletes);
+        assert!(engine.watchers.read().is_empty());
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_watch_prefix() {
+        let name = "prefix";
+        let engine = Engine::open(setup(name)).unwrap();
+
+        let users = engine.watch_prefix("user:");
+        engine.put(Bytes::from("user:1"), Bytes::from("a")).unwrap();
+        engine
+            .put(Bytes::from("order:1"), Bytes::from("b"))
+            .unwrap();
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        wb.put(Bytes::from("user:2"), Bytes::from("c")).unwrap();
+        wb.delete(Bytes::from("user:1")).unwrap();
+        wb.commit().unwrap();
+
+        let events: Vec<_> = users.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            Event::Put {
+                key: Bytes::from("user:1"),
+                value: Bytes::from("a")
+            }
+        );
+        assert!(events.contains(&Event::Delete {
+            key: Bytes::from("user:1")
+        }));
+
+        // 丢弃接收端后, 下一次发送时取消订阅
+        drop(users);
+        assert_eq!(engine.watchers.read().len(), 1);
+        engine.put(Bytes::from("user:3"), Bytes::from("d")).unwrap();
+        assert!(engine.watchers.read().is_empty());
+
+        // 队列满了之后取消订阅, 取完已有的事件后断开连接
+        let slow = engine.watch_prefix("slow:");
+        for i in 0..=WATCH_CHANNEL_CAPACITY {
+            let key = Bytes::from(format!("slow:{}", i));
+            engine.put(key, Bytes::from("v")).unwrap();
+        }
+        assert!(engine.watchers.read().is_empty());
+        assert_eq!(slow.try_iter().count(), WATCH_CHANNEL_CAPACITY);
+        assert!(matches!(slow.try_recv(), Err(TryRecvError::Disconnected)));
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_subscribe_overflow() {
+        let name = "overflow";
+        let engine = Engine::open(setup(name)).unwrap();
+
+        // 队列满了之后取消订阅, 取完已有的事件后返回错误
+        let mut sub = engine.subscribe(WatchFilter::default());
+        for i in 0..=WATCH_CHANNEL_CAPACITY {
+            let key = Bytes::from(format!("key-{}", i));
+            engine.put(key, Bytes::from("v")).unwrap();
+        }
+        assert!(engine.watchers.read().is_empty());
+        let mut events = vec![];
+        for _ in 0..WATCH_CHANNEL_CAPACITY {
+            events.push(sub.try_recv().unwrap().unwrap());
+        }
+        assert!(matches!(sub.try_recv(), Err(Errors::SubscriptionOverflow)));
+
+        // 从最后一个收到的事件之后继续, 不会遗漏
+        let last = events.last().unwrap().seq;
+        let mut sub = engine.subscribe_from(last.next(), WatchFilter::default());
+        let rest = drain(&mut sub);
+        assert_eq!(rest.len(), 1);
+        assert_eq!(
+            rest[0].key,
+            Bytes::from(format!("key-{}", WATCH_CHANNEL_CAPACITY))
+        );
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_subscribe_from() {
+        let name = "replay";
+        let opts = setup(name);
+
+        // 历史数据跨越多个数据文件, 包含事务数据
+        {
+            let engine = Engine::open(opts.clone()).unwrap();
+            for i in 0..50 {
+                let key = Bytes::from(format!("key-{:03}", i));
+                engine.put(key, Bytes::from("value")).unwrap();
+            }
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .unwrap();
+            wb.put(Bytes::from("txn-1"), Bytes::from("value")).unwrap();
+            wb.delete(Bytes::from("key-000")).unwrap();
+            wb.commit().unwrap();
+            assert!(engine.older_files.read().len() > 0);
+        }
+
+        let engine = Engine::open(opts.clone()).unwrap();
+        let mut sub = engine.subscribe_from(EventSeq::default(), WatchFilter::default());
+
+        // 订阅之后的写入在回放之后返回
+        engine
+            .put(Bytes::from("live-1"), Bytes::from("value"))
+            .unwrap();
+
+        let events = drain(&mut sub);
+        assert_eq!(events.len(), 53);
+        assert!(events.windows(2).all(|w| w[0].seq < w[1].seq));
+        assert_eq!(events[0].key, Bytes::from("key-000"));
+        assert_eq!(events[52].key, Bytes::from("live-1"));
+        assert_eq!(
+            events.iter().filter(|e| e.op == Operation::Delete).count(),
+            1
+        );
+
+        // 从中间的某个事件之后续传
+        let last = events[20].seq;
+        let mut sub = engine.subscribe_from(last.next(), WatchFilter::default());
+        let resumed = drain(&mut sub);
+        assert_eq!(resumed.len(), 32);
+        assert_eq!(resumed[0].seq, events[21].seq);
+
+        clean(name);
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let name = "changes";
+        let opts = setup(name);
+        let engine = Engine::open(opts.clone()).unwrap();
+
+        let drain_changes = |changes: &mut Changes| {
+            let mut events = vec![];
+            while let Some(event) = changes.next_change().unwrap() {
+                events.push(event);
+            }
+            events
+        };
+
+        for i in 0..100 {
+            let key = Bytes::from(format!("key-{:03}", i));
+            engine.put(key, Bytes::from("value")).unwrap();
+        }
+        let mut changes = engine.changes_since(EventSeq::default());
+        // 调用之后的写入不会返回
+        engine.delete(Bytes::from("key-000")).unwrap();
+        let events = drain_changes(&mut changes);
+        assert_eq!(events.len(), 100);
+        assert!(events.windows(2).all(|w| w[0].seq < w[1].seq));
+        assert!(engine.older_files.read().len() > 0);
+
+        // 从上次的截止位置继续, 只返回之后的变更
+        let end = changes.end();
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        wb.put(Bytes::from("txn-1"), Bytes::from("value")).unwrap();
+        wb.put(Bytes::from("key-001"), Bytes::from("new")).unwrap();
+        wb.commit().unwrap();
+
+        let events = drain_changes(&mut engine.changes_since(end));
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].op, Operation::Delete);
+        assert_eq!(events[0].key, Bytes::from("key-000"));
+        // 同一个事务中的数据没有固定的顺序
+        let txn = &events[1..];
+        assert!(txn.iter().any(|e| e.key == Bytes::from("txn-1")));
+        assert!(txn
+            .iter()
+            .any(|e| e.key == Bytes::from("key-001") && e.value == Bytes::from("new")));
+
+        // 重启之后序号依然有效
+        drop(wb);
+        drop(engine);
+        let engine = Engine::open(opts).unwrap();
+        assert_eq!(drain_changes(&mut engine.changes_since(end)).len(), 3);
+        assert!(drain_changes(&mut engine.changes_since(EventSeq {
+            file_id: u32::MAX,
+            offset: 0
+        }))
+        .is_empty());
+
+        clean(name);
+    }
+}